@@ -161,6 +161,7 @@ pub enum TTHistory {
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq)]
 pub enum TTInputFormat {
+    PK = 1,
     TFM = 3,
     AFM = 4,
     BIB = 6,
@@ -83,6 +83,7 @@ pub struct cid_opt {
     pub embed: i32,
     pub stemv: i32,
     pub cff_charsets: *mut libc::c_void,
+    pub variations: *mut i8,
 }
 use super::dpx_fontmap::fontmap_opt;
 /*
@@ -904,6 +905,14 @@ pub unsafe extern "C" fn CIDFont_cache_find(
     (*opt).csi = get_cidsysinfo(map_name, fmap_opt);
     (*opt).stemv = (*fmap_opt).stemv;
     (*opt).cff_charsets = 0 as *mut libc::c_void;
+    (*opt).variations = if (*fmap_opt).variations.is_null() {
+        0 as *mut i8
+    } else {
+        let variations = new((strlen((*fmap_opt).variations).wrapping_add(1))
+            .wrapping_mul(::std::mem::size_of::<i8>()) as _) as *mut i8;
+        strcpy(variations, (*fmap_opt).variations);
+        variations
+    };
     if (*opt).csi.is_null() && !cmap_csi.is_null() {
         /*
          * No CIDSystemInfo supplied explicitly. Copy from CMap's one if available.
@@ -931,6 +940,8 @@ pub unsafe extern "C" fn CIDFont_cache_find(
         if streq_ptr((*font).name, map_name) as i32 != 0
             && (*(*font).options).style == (*opt).style
             && (*(*font).options).index == (*opt).index
+            && (streq_ptr((*(*font).options).variations, (*opt).variations)
+                || (*(*font).options).variations.is_null() && (*opt).variations.is_null())
         {
             if (*(*font).options).embed == (*opt).embed {
                 /*
@@ -1053,6 +1064,7 @@ unsafe fn release_opt(mut opt: *mut cid_opt) {
             cff_release_charsets((*opt).cff_charsets as *mut cff_charsets);
         }
     }
+    free((*opt).variations as *mut libc::c_void);
     free(opt as *mut libc::c_void);
 }
 unsafe fn get_cidsysinfo(
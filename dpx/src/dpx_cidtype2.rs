@@ -28,7 +28,7 @@
 )]
 
 use crate::DisplayExt;
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 
 use super::dpx_sfnt::{
     dfont_open, sfnt_close, sfnt_create_FontFile_stream, sfnt_find_table_pos, sfnt_open,
@@ -48,11 +48,13 @@ use super::dpx_tt_aux::ttc_read_offset;
 use super::dpx_tt_cmap::{tt_cmap_lookup, tt_cmap_read, tt_cmap_release};
 use super::dpx_tt_glyf::{
     tt_add_glyph, tt_build_finish, tt_build_init, tt_build_tables, tt_get_index, tt_get_metrics,
+    tt_set_variation,
 };
 use super::dpx_tt_gsub::{
     otl_gsub, otl_gsub_add_feat, otl_gsub_apply, otl_gsub_new, otl_gsub_release, otl_gsub_select,
 };
 use super::dpx_tt_table::tt_get_ps_fontname;
+use super::dpx_tt_var::{instance_name_suffix, parse_variations, VarInstance};
 use super::dpx_type0::{Type0Font_cache_get, Type0Font_get_usedchars};
 use crate::dpx_pdfobj::{
     pdf_add_array, pdf_add_dict, pdf_add_stream, pdf_copy_name, pdf_new_array, pdf_new_dict,
@@ -801,6 +803,14 @@ pub unsafe extern "C" fn CIDFont_type2_dofont(mut font: *mut CIDFont) {
         cmap = find_tocode_cmap((*(*font).csi).registry, (*(*font).csi).ordering, i)
     } /* .notdef */
     let glyphs = tt_build_init();
+    if !(*(*font).options).variations.is_null() {
+        if let Some(var) = VarInstance::new(
+            sfont,
+            CStr::from_ptr((*(*font).options).variations).to_bytes(),
+        ) {
+            tt_set_variation(glyphs, var);
+        }
+    }
     let mut last_cid = 0i32 as CID;
     let mut num_glyphs = 1_u16;
     let mut v_used_chars = 0 as *mut i8;
@@ -1138,15 +1148,26 @@ pub unsafe extern "C" fn CIDFont_type2_open(
         namelen = strlen(shortname) as i32
     }
     validate_name(shortname, namelen);
+    /*
+     * Instances of variable fonts are told apart by their axis settings.
+     */
+    let suffix = if (*opt).variations.is_null() {
+        CString::default()
+    } else {
+        let settings = parse_variations(CStr::from_ptr((*opt).variations).to_bytes());
+        CString::new(format!("-{}", instance_name_suffix(&settings))).unwrap()
+    };
     /*
      * Strlen works, after validate_named string.
      * Mangled name requires more 7 bytes.
      * Style requires more 11 bytes.
      */
-    let fontname =
-        new((strlen(shortname).wrapping_add(19)).wrapping_mul(::std::mem::size_of::<i8>()) as _)
-            as *mut i8;
+    let fontname = new((strlen(shortname)
+        .wrapping_add(suffix.as_bytes().len())
+        .wrapping_add(19))
+    .wrapping_mul(::std::mem::size_of::<i8>()) as _) as *mut i8;
     strcpy(fontname, shortname);
+    strcat(fontname, suffix.as_ptr());
     free(shortname as *mut libc::c_void);
     if (*opt).embed != 0 && (*opt).style != 0i32 {
         warn!(
@@ -29,7 +29,7 @@
 )]
 
 use super::dpx_mem::new;
-use libc::{memcpy, memset, rand};
+use libc::{free, memcpy, memset, rand};
 
 pub type size_t = u64;
 
@@ -461,6 +461,104 @@ pub unsafe extern "C" fn AES_cbc_encrypt_tectonic(
         );
     };
 }
+/* The counterpart of AES_cbc_encrypt_tectonic(): NULL iv means here "the IV
+ * is the first 16 bytes of the cipher text". With padding enabled, the
+ * padding bytes are checked and removed. Returns -1 (and no output) if the
+ * cipher text is malformed.
+ */
+#[no_mangle]
+pub unsafe extern "C" fn AES_cbc_decrypt_tectonic(
+    mut key: *const u8,
+    mut key_len: size_t,
+    mut iv: *const u8,
+    mut padding: i32,
+    mut cipher: *const u8,
+    mut cipher_len: size_t,
+    mut plain: *mut *mut u8,
+    mut plain_len: *mut size_t,
+) -> i32 {
+    let mut aes: AES_CONTEXT = AES_CONTEXT {
+        nrounds: 0,
+        rk: [0; 60],
+        iv: [0; 16],
+    };
+    let mut block: [u8; 16] = [0; 16];
+    let ctx = &mut aes;
+    *plain = 0 as *mut u8;
+    *plain_len = 0;
+    let mut inptr = cipher;
+    let mut len = cipher_len;
+    if !iv.is_null() {
+        memcpy(
+            (*ctx).iv.as_mut_ptr() as *mut libc::c_void,
+            iv as *const libc::c_void,
+            16,
+        );
+    } else {
+        if len < 16 {
+            return -1i32;
+        }
+        memcpy(
+            (*ctx).iv.as_mut_ptr() as *mut libc::c_void,
+            inptr as *const libc::c_void,
+            16,
+        );
+        inptr = inptr.offset(16);
+        len -= 16;
+    }
+    if len % 16 != 0 || (padding != 0 && len == 0) {
+        return -1i32;
+    }
+    let mut inv_sbox: [u8; 256] = [0; 256];
+    for i in 0..256 {
+        inv_sbox[(Te4[i] & 0xff_u32) as usize] = i as u8;
+    }
+    (*ctx).nrounds = rijndaelSetupEncrypt(
+        (*ctx).rk.as_mut_ptr(),
+        key,
+        key_len.wrapping_mul(8i32 as u64) as i32,
+    );
+    let out = new((len as u32).wrapping_add(1)) as *mut u8;
+    let mut out_len = len;
+    let mut outptr = out;
+    while len >= 16i32 as u64 {
+        rijndaelDecrypt(
+            (*ctx).rk.as_mut_ptr(),
+            (*ctx).nrounds,
+            &inv_sbox,
+            inptr,
+            block.as_mut_ptr(),
+        );
+        for i in 0..16 {
+            *outptr.offset(i as isize) = block[i] ^ (*ctx).iv[i];
+        }
+        memcpy(
+            (*ctx).iv.as_mut_ptr() as *mut libc::c_void,
+            inptr as *const libc::c_void,
+            16,
+        );
+        inptr = inptr.offset(16);
+        outptr = outptr.offset(16);
+        len -= 16;
+    }
+    if padding != 0 {
+        let padbytes = *outptr.offset(-1) as u64;
+        if padbytes == 0 || padbytes > 16 {
+            free(out as *mut libc::c_void);
+            return -1i32;
+        }
+        for i in 1..=padbytes {
+            if *outptr.offset(-(i as isize)) as u64 != padbytes {
+                free(out as *mut libc::c_void);
+                return -1i32;
+            }
+        }
+        out_len -= padbytes;
+    }
+    *plain = out;
+    *plain_len = out_len;
+    0i32
+}
 /* The following section contains a Rijndael encryption implementation
  * based on code from Philip J. Erdelsky's public domain one.
  * They can be obtained from
@@ -2232,3 +2330,71 @@ unsafe fn rijndaelEncrypt(
     *ciphertext.offset(12).offset(2) = (s3 >> 8i32) as u8;
     *ciphertext.offset(12).offset(3) = s3 as u8;
 }
+
+/* Decryption is only needed to read data back, so the inverse cipher is
+ * written in its plain, byte oriented form rather than with lookup tables.
+ * It uses the encryption key schedule, applying the round keys backwards.
+ */
+unsafe fn rijndaelDecrypt(
+    mut rk: *const u32,
+    mut nrounds: i32,
+    inv_sbox: &[u8; 256],
+    mut ciphertext: *const u8,
+    mut plaintext: *mut u8,
+) {
+    fn xtime(x: u8) -> u8 {
+        (x << 1) ^ if x & 0x80 != 0 { 0x1b } else { 0 }
+    }
+    fn gmul(mut a: u8, mut b: u8) -> u8 {
+        let mut p = 0;
+        while b != 0 {
+            if b & 1 != 0 {
+                p ^= a;
+            }
+            a = xtime(a);
+            b >>= 1;
+        }
+        p
+    }
+    let add_round_key = |s: &mut [u8; 16], round: i32| {
+        for c in 0..4 {
+            let w = *rk.offset((4 * round + c) as isize);
+            for r in 0..4 {
+                s[(4 * c + r) as usize] ^= (w >> (24 - 8 * r)) as u8;
+            }
+        }
+    };
+    let mut s: [u8; 16] = [0; 16];
+    for i in 0..16 {
+        s[i] = *ciphertext.offset(i as isize);
+    }
+    add_round_key(&mut s, nrounds);
+    let mut round = nrounds - 1;
+    loop {
+        /* InvShiftRows and InvSubBytes */
+        let t = s;
+        for c in 0..4 {
+            for r in 0..4 {
+                s[4 * ((c + r) % 4) + r] = inv_sbox[t[4 * c + r] as usize];
+            }
+        }
+        add_round_key(&mut s, round);
+        if round == 0 {
+            break;
+        }
+        /* InvMixColumns */
+        for c in 0..4 {
+            let a = [s[4 * c], s[4 * c + 1], s[4 * c + 2], s[4 * c + 3]];
+            for r in 0..4 {
+                s[4 * c + r] = gmul(a[r], 14)
+                    ^ gmul(a[(r + 1) % 4], 11)
+                    ^ gmul(a[(r + 2) % 4], 13)
+                    ^ gmul(a[(r + 3) % 4], 9);
+            }
+        }
+        round -= 1;
+    }
+    for i in 0..16 {
+        *plaintext.offset(i as isize) = s[i];
+    }
+}
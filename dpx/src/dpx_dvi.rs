@@ -32,7 +32,10 @@ use std::io::{Read, Seek, SeekFrom};
 
 use crate::FromBEByteSlice;
 use crate::DisplayExt;
+use std::collections::HashMap;
 use std::ffi::{CStr, CString};
+use std::ptr;
+use std::slice;
 
 use super::dpx_sfnt::{
     dfont_open, sfnt_close, sfnt_find_table_pos, sfnt_locate_table, sfnt_open,
@@ -49,7 +52,10 @@ use super::dpx_dpxfile::{
 };
 use super::dpx_dpxutil::{ParseCIdent, ParseFloatDecimal};
 use super::dpx_dvipdfmx::{is_xdv, landscape_mode, paper_height, paper_width};
-use super::dpx_fontmap::{pdf_insert_native_fontmap_record, pdf_lookup_fontmap_record};
+use super::dpx_fontmap::{
+    pdf_fontmap_diagnostics, pdf_fontmap_record_source, pdf_insert_native_fontmap_record,
+    pdf_lookup_fontmap_record,
+};
 use super::dpx_mem::{new, renew, xmalloc};
 use super::dpx_numbers::{
     sqxfw, tt_get_positive_quad, tt_get_signed_quad, tt_get_unsigned_byte, tt_get_unsigned_num,
@@ -58,23 +64,28 @@ use super::dpx_numbers::{
 use super::dpx_pdfcolor::{pdf_color_pop, pdf_color_push, PdfColor};
 use super::dpx_pdfdev::{
     graphics_mode, pdf_dev_begin_actualtext, pdf_dev_end_actualtext, pdf_dev_locate_font,
-    pdf_dev_set_dirmode, pdf_dev_set_rect, pdf_dev_set_rule, pdf_dev_set_string,
+    pdf_dev_put_image, pdf_dev_set_dirmode, pdf_dev_set_invisible_string, pdf_dev_set_rect,
+    pdf_dev_set_rule, pdf_dev_set_string, transform_info,
 };
 use super::dpx_pdfdoc::{
     pdf_doc_begin_page, pdf_doc_break_annot, pdf_doc_end_page, pdf_doc_expand_box,
 };
 use super::dpx_pdfparse::{dump_slice, SkipWhite, ParsePdfObj};
+use super::dpx_pdfstruct::pdf_struct_set_tagged;
 use super::dpx_subfont::{lookup_sfd_record, sfd_load_record, subfont_set_verbose};
+use super::dpx_svgimage::png_image;
 use super::dpx_t1_char::t1char_get_metrics;
 use super::dpx_t1_load::t1_load_font;
 use super::dpx_tfm::{
     tfm_close_all, tfm_get_fw_depth, tfm_get_fw_height, tfm_get_fw_width, tfm_open, tfm_set_verbose,
 };
 use super::dpx_tt_aux::ttc_read_offset;
+use super::dpx_tt_color::{ColorGlyph, ColorGlyphs};
 use super::dpx_tt_table::{
     tt_read_head_table, tt_read_hhea_table, tt_read_longMetrics, tt_read_maxp_table,
     tt_read_vhea_table,
 };
+use super::dpx_tt_var::{format_variations, VarInstance};
 use super::dpx_vf::{vf_close_all_fonts, vf_locate_font, vf_set_char, vf_set_verbose};
 use crate::dpx_pdfobj::{
     pdf_number_value, pdf_release_obj, pdf_string_value,
@@ -90,7 +101,7 @@ use crate::{
 };
 use crate::dpx_dvicodes::*;
 
-use libc::{atof, free, memset, strlen, strncpy, strtol};
+use libc::{atof, free, memset, strcat, strcpy, strlen, strncpy, strtol};
 
 use crate::TTInputFormat;
 
@@ -133,6 +144,7 @@ pub struct font_def {
     pub extend: i32,
     pub slant: i32,
     pub embolden: i32,
+    pub variations: *mut i8,
 }
 #[derive(Copy, Clone)]
 #[repr(C)]
@@ -154,6 +166,14 @@ pub struct loaded_font<'a> {
     pub extend: f32,
     pub slant: f32,
     pub embolden: f32,
+    pub color: *mut ColorFont,
+}
+/* Color glyph tables of a native font, and the images made so far from its
+ * bitmap glyphs (-1 for those that could not be embedded).
+ */
+pub struct ColorFont {
+    pub glyphs: ColorGlyphs,
+    pub images: HashMap<u16, i32>,
 }
 
 use super::dpx_cff::cff_font;
@@ -561,6 +581,7 @@ unsafe fn read_font_record(mut tex_id: u32) {
     (*def_fonts.offset(num_def_fonts as isize)).extend = 0x10000i32;
     (*def_fonts.offset(num_def_fonts as isize)).slant = 0i32;
     (*def_fonts.offset(num_def_fonts as isize)).embolden = 0i32;
+    (*def_fonts.offset(num_def_fonts as isize)).variations = 0 as *mut i8;
     num_def_fonts = num_def_fonts.wrapping_add(1);
 }
 unsafe fn read_native_font_record(mut tex_id: u32) {
@@ -604,12 +625,28 @@ unsafe fn read_native_font_record(mut tex_id: u32) {
     (*def_fonts.offset(num_def_fonts as isize)).extend = 0x10000i32;
     (*def_fonts.offset(num_def_fonts as isize)).slant = 0i32;
     (*def_fonts.offset(num_def_fonts as isize)).embolden = 0i32;
+    (*def_fonts.offset(num_def_fonts as isize)).variations = 0 as *mut i8;
     if flags & 0x100_u32 != 0 {
         (*def_fonts.offset(num_def_fonts as isize)).layout_dir = 1i32
     }
     if flags & 0x200_u32 != 0 {
         (*def_fonts.offset(num_def_fonts as isize)).rgba_color = tt_get_unsigned_quad(handle)
     }
+    if flags & 0x800_u32 != 0 {
+        /* Axis tags come first, then the values as Fixed. */
+        let num_axes = tt_get_unsigned_pair(handle) as usize;
+        let tags: Vec<[u8; 4]> = (0..num_axes)
+            .map(|_| tt_get_unsigned_quad(handle).to_be_bytes())
+            .collect();
+        let settings: Vec<([u8; 4], f64)> = tags
+            .into_iter()
+            .map(|tag| (tag, tt_get_signed_quad(handle) as f64 / 65536.0))
+            .collect();
+        let spec = CString::new(format_variations(&settings)).unwrap();
+        let variations = new(spec.as_bytes_with_nul().len() as u32) as *mut i8;
+        strcpy(variations, spec.as_ptr());
+        (*def_fonts.offset(num_def_fonts as isize)).variations = variations
+    }
     if flags & 0x1000_u32 != 0 {
         (*def_fonts.offset(num_def_fonts as isize)).extend = tt_get_signed_quad(handle)
     }
@@ -783,6 +820,45 @@ pub unsafe extern "C" fn dvi_do_special(buffer: &[u8]) {
 pub unsafe extern "C" fn dvi_unit_size() -> f64 {
     dvi2pts
 }
+/* Tectonic: with font map diagnostics enabled, tell how a TFM name was
+ * resolved to a font. */
+unsafe fn report_font_resolution(tfm_name: *const i8, mrec: *const fontmap_rec, how: &str) {
+    if !pdf_fontmap_diagnostics() {
+        return;
+    }
+    let tfm_name = CStr::from_ptr(tfm_name);
+    if mrec.is_null() {
+        info!(
+            "\nFont \"{}\": no font map entry; {}\n",
+            tfm_name.display(),
+            how
+        );
+        return;
+    }
+    let key = if (*mrec).map_name.is_null() {
+        tfm_name
+    } else {
+        CStr::from_ptr((*mrec).map_name)
+    };
+    let source = pdf_fontmap_record_source(key.to_bytes())
+        .map(|s| format!(" from {}", s))
+        .unwrap_or_default();
+    info!(
+        "\nFont \"{}\": font map entry \"{}\"{}; {}",
+        tfm_name.display(),
+        key.display(),
+        source,
+        how,
+    );
+    if !(*mrec).charmap.sfd_name.is_null() && !(*mrec).charmap.subfont_id.is_null() {
+        info!(
+            " (subfont {} of SFD {})",
+            CStr::from_ptr((*mrec).charmap.subfont_id).display(),
+            CStr::from_ptr((*mrec).charmap.sfd_name).display(),
+        );
+    }
+    info!("\n");
+}
 #[no_mangle]
 pub unsafe extern "C" fn dvi_locate_font(mut tfm_name: *const i8, mut ptsize: spt_t) -> u32 {
     let mut subfont_id: i32 = -1i32;
@@ -843,6 +919,7 @@ pub unsafe extern "C" fn dvi_locate_font(mut tfm_name: *const i8, mut ptsize: sp
             if verbose != 0 {
                 info!("(VF)>");
             }
+            report_font_resolution(tfm_name, mrec, "virtual font");
             return cur_id;
         }
     } else if subfont_id >= 0i32 && !(*mrec).map_name.is_null() {
@@ -874,6 +951,14 @@ pub unsafe extern "C" fn dvi_locate_font(mut tfm_name: *const i8, mut ptsize: sp
                 if verbose != 0 {
                     info!("(OVF)>");
                 }
+                report_font_resolution(
+                    tfm_name,
+                    mrec,
+                    &format!(
+                        "Omega virtual font \"{}\"",
+                        CStr::from_ptr((*mrec1).font_name).display()
+                    ),
+                );
                 return cur_id;
             }
         }
@@ -944,6 +1029,35 @@ pub unsafe extern "C" fn dvi_locate_font(mut tfm_name: *const i8, mut ptsize: sp
     if verbose != 0 {
         info!(">");
     }
+    if pdf_fontmap_diagnostics() {
+        let how = if mrec.is_null() {
+            "font looked up by the TFM name".to_owned()
+        } else {
+            let mrec1 = if (*mrec).map_name.is_null() {
+                mrec as *const fontmap_rec
+            } else {
+                pdf_lookup_fontmap_record(CStr::from_ptr((*mrec).map_name).to_bytes())
+                    as *const fontmap_rec
+            };
+            if mrec1.is_null() || (*mrec1).font_name.is_null() {
+                "physical font".to_owned()
+            } else {
+                format!(
+                    "font file \"{}\" with {}",
+                    CStr::from_ptr((*mrec1).font_name).display(),
+                    if (*mrec1).enc_name.is_null() {
+                        "its built-in encoding".to_owned()
+                    } else {
+                        format!(
+                            "encoding \"{}\"",
+                            CStr::from_ptr((*mrec1).enc_name).display()
+                        )
+                    },
+                )
+            }
+        };
+        report_font_resolution(tfm_name, mrec, &how);
+    }
     cur_id
 }
 unsafe fn dvi_locate_native_font(
@@ -954,6 +1068,7 @@ unsafe fn dvi_locate_native_font(
     mut extend: i32,
     mut slant: i32,
     mut embolden: i32,
+    mut variations: *const i8,
 ) -> i32 {
     let mut offset: u32 = 0_u32;
     let mut is_dfont: i32 = 0i32;
@@ -990,7 +1105,13 @@ unsafe fn dvi_locate_native_font(
     let fresh17 = num_loaded_fonts;
     num_loaded_fonts = num_loaded_fonts.wrapping_add(1);
     let cur_id = fresh17 as i32;
-    let fontmap_key = xmalloc(strlen(filename).wrapping_add(40) as _) as *mut i8;
+    let varlen = if variations.is_null() {
+        0
+    } else {
+        strlen(variations).wrapping_add(1)
+    };
+    let fontmap_key =
+        xmalloc(strlen(filename).wrapping_add(40).wrapping_add(varlen) as _) as *mut i8;
     sprintf(
         fontmap_key,
         b"%s/%u/%c/%d/%d/%d\x00" as *const u8 as *const i8,
@@ -1005,10 +1126,15 @@ unsafe fn dvi_locate_native_font(
         slant,
         embolden,
     );
+    if !variations.is_null() {
+        strcat(fontmap_key, b"/\x00" as *const u8 as *const i8);
+        strcat(fontmap_key, variations);
+    }
     let mut mrec = pdf_lookup_fontmap_record(CStr::from_ptr(fontmap_key).to_bytes());
     if mrec.is_null() {
-        mrec =
-            pdf_insert_native_fontmap_record(filename, index, layout_dir, extend, slant, embolden);
+        mrec = pdf_insert_native_fontmap_record(
+            filename, index, layout_dir, extend, slant, embolden, variations,
+        );
         if mrec.is_null() {
             panic!(
                 "Failed to insert font record for font: {}",
@@ -1100,7 +1226,28 @@ unsafe fn dvi_locate_native_font(
                 (*maxp).numGlyphs,
                 (*hhea).numOfLongHorMetrics,
                 (*hhea).numOfExSideBearings,
-            )
+            );
+            if !variations.is_null() {
+                if sfnt_find_table_pos(sfont, b"CFF2") > 0_u32 {
+                    warn!(
+                        "Variable fonts with CFF2 outlines are not supported: {}",
+                        CStr::from_ptr(filename).display()
+                    );
+                } else if let Some(var) =
+                    VarInstance::new(sfont, CStr::from_ptr(variations).to_bytes())
+                {
+                    var.apply_hmtx(
+                        sfont,
+                        slice::from_raw_parts_mut(*fresh20, (*maxp).numGlyphs as usize),
+                    );
+                }
+            }
+        }
+        if let Some(glyphs) = ColorGlyphs::new(sfont, (*maxp).numGlyphs) {
+            (*loaded_fonts.offset(cur_id as isize)).color = Box::into_raw(Box::new(ColorFont {
+                glyphs,
+                images: HashMap::new(),
+            }));
         }
         free(hhea as *mut libc::c_void);
         free(maxp as *mut libc::c_void);
@@ -1501,6 +1648,7 @@ unsafe fn do_fnt(mut tex_id: u32) {
                 (*def_fonts.offset(i as isize)).extend,
                 (*def_fonts.offset(i as isize)).slant,
                 (*def_fonts.offset(i as isize)).embolden,
+                (*def_fonts.offset(i as isize)).variations,
             ) as u32
         } else {
             dvi_locate_font(
@@ -1610,6 +1758,10 @@ unsafe fn skip_native_font_def() {
     if flags & 0x200_u32 != 0 {
         tt_skip_bytes(4, handle);
     }
+    if flags & 0x800_u32 != 0 {
+        let num_axes = tt_get_unsigned_pair(handle) as u32;
+        tt_skip_bytes(8 * num_axes, handle);
+    }
     if flags & 0x1000_u32 != 0 {
         tt_skip_bytes(4, handle);
     }
@@ -1744,10 +1896,18 @@ unsafe fn do_glyphs(mut do_actual_text: i32) {
                 pdf_doc_expand_box(&mut rect);
             }
         }
+        let xpos = dvi_state.h + *xloc.offset(i as isize);
+        let ypos = -dvi_state.v - *yloc.offset(i as isize);
+        if !(*font).color.is_null()
+            && (glyph_id as u32) < (*font).numGlyphs
+            && do_color_glyph(font, glyph_id, xpos, ypos, glyph_width)
+        {
+            continue;
+        }
         let wbuf = glyph_id.to_be_bytes();
         pdf_dev_set_string(
-            dvi_state.h + *xloc.offset(i as isize),
-            -dvi_state.v - *yloc.offset(i as isize),
+            xpos,
+            ypos,
             wbuf.as_ptr() as *const libc::c_void,
             2i32 as size_t,
             glyph_width,
@@ -1767,6 +1927,85 @@ unsafe fn do_glyphs(mut do_actual_text: i32) {
         dvi_right(width);
     };
 }
+/* Draws a glyph from the color tables of its font, on top of an invisible
+ * copy of the plain glyph that keeps the text searchable. Returns false if
+ * there is no color version to draw.
+ */
+unsafe fn do_color_glyph(
+    font: *mut loaded_font,
+    glyph_id: u16,
+    xpos: spt_t,
+    ypos: spt_t,
+    glyph_width: spt_t,
+) -> bool {
+    let color = &mut *(*font).color;
+    let wbuf = glyph_id.to_be_bytes();
+    match color.glyphs.lookup(glyph_id) {
+        Some(ColorGlyph::Layers(layers)) => {
+            pdf_dev_set_invisible_string(xpos, ypos, &wbuf, glyph_width, (*font).font_id);
+            /* Keep the layers out of the extracted text. */
+            pdf_dev_begin_actualtext(ptr::null_mut(), 0i32);
+            for layer in &layers {
+                /* Partially transparent layers are drawn opaque. */
+                if let Some([r, g, b, a]) = layer.color {
+                    if a == 0 {
+                        continue;
+                    }
+                    let mut layer_color = PdfColor::from_rgb(
+                        r as f64 / 255.0f64,
+                        g as f64 / 255.0f64,
+                        b as f64 / 255.0f64,
+                    )
+                    .unwrap();
+                    let color_clone = layer_color.clone();
+                    pdf_color_push(&mut layer_color, &color_clone);
+                }
+                let wbuf = layer.gid.to_be_bytes();
+                pdf_dev_set_string(
+                    xpos,
+                    ypos,
+                    wbuf.as_ptr() as *const libc::c_void,
+                    2i32 as size_t,
+                    glyph_width,
+                    (*font).font_id,
+                    -1i32,
+                );
+                if layer.color.is_some() {
+                    pdf_color_pop();
+                }
+            }
+            pdf_dev_end_actualtext();
+            true
+        }
+        Some(ColorGlyph::Bitmap(bitmap)) if (*font).layout_dir == 0i32 => {
+            let id = *color.images.entry(glyph_id).or_insert_with(|| {
+                png_image(bitmap.data).unwrap_or_else(|e| {
+                    warn!("Could not embed the bitmap of glyph {}: {}", glyph_id, e);
+                    -1i32
+                })
+            });
+            if id < 0i32 {
+                return false;
+            }
+            pdf_dev_set_invisible_string(xpos, ypos, &wbuf, glyph_width, (*font).font_id);
+            /* Bitmap pixels to bp */
+            let scale = (*font).size as f64 * dvi2pts / bitmap.ppem as f64;
+            let xscale = scale * (*font).extend as f64;
+            let mut info = transform_info::new();
+            info.width = bitmap.width * xscale;
+            info.height = bitmap.height * scale;
+            info.flags = 1i32 << 1i32 | 1i32 << 2i32;
+            pdf_dev_put_image(
+                id,
+                &mut info,
+                xpos as f64 * dvi2pts + bitmap.x * xscale,
+                ypos as f64 * dvi2pts + bitmap.y * scale,
+            );
+            true
+        }
+        _ => false,
+    }
+}
 unsafe fn check_postamble() {
     let handle = dvi_handle.as_mut().unwrap();
     tt_skip_bytes(28, handle);
@@ -2024,6 +2263,7 @@ pub unsafe extern "C" fn dvi_close() {
             let ref mut fresh23 = (*def_fonts.offset(i as isize)).font_name;
             *fresh23 =
                 mfree((*def_fonts.offset(i as isize)).font_name as *mut libc::c_void) as *mut i8;
+            free((*def_fonts.offset(i as isize)).variations as *mut libc::c_void);
         }
         free(def_fonts as *mut libc::c_void);
     }
@@ -2039,6 +2279,11 @@ pub unsafe extern "C" fn dvi_close() {
         }
         let ref mut fresh25 = (*loaded_fonts.offset(i as isize)).cffont;
         *fresh25 = 0 as *mut cff_font;
+        let color = (*loaded_fonts.offset(i as isize)).color;
+        if !color.is_null() {
+            drop(Box::from_raw(color));
+            (*loaded_fonts.offset(i as isize)).color = ptr::null_mut();
+        }
     }
     loaded_fonts = mfree(loaded_fonts as *mut libc::c_void) as *mut loaded_font;
     num_loaded_fonts = 0_u32;
@@ -2312,6 +2557,13 @@ unsafe fn scan_special(
             if let Some(kv_0) = buf.parse_float_decimal() {
                 *majorversion = strtol(kv_0.as_ptr(), 0 as *mut *mut i8, 10) as i32;
             }
+        } else if ns_pdf != 0
+            && (q.to_bytes() == b"tagged"
+                || q.to_bytes() == b"bstruct"
+                || q.to_bytes() == b"beginstruct")
+        {
+            /* Content must be marked from the very first page on. */
+            pdf_struct_set_tagged();
         } else if ns_pdf != 0
             && q.to_bytes() == b"encrypt"
             && !do_enc.is_null()
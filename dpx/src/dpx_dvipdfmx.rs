@@ -42,6 +42,7 @@ use super::dpx_pdfdoc::{
 use super::dpx_pdffont::{
     pdf_font_reset_unique_tag_state, pdf_font_set_deterministic_unique_tags, pdf_font_set_dpi,
 };
+use super::dpx_pkfont::PKFont_set_outline_fallback;
 use super::dpx_tt_aux::tt_aux_set_verbose;
 use crate::dpx_pdfparse::parse_unsigned;
 use crate::DisplayExt;
@@ -52,21 +53,29 @@ use super::dpx_cid::CIDFont_set_flags;
 use super::dpx_dpxconf::{paperinfo, defaultpapername, systempapername};
 use super::dpx_dpxfile::{dpx_delete_old_cache, dpx_file_set_verbose};
 use super::dpx_error::shut_up;
+use super::dpx_epdf::pdf_include_set_annotations;
+use super::dpx_imagecache;
 use super::dpx_fontmap::{
-    pdf_close_fontmaps, pdf_fontmap_set_verbose, pdf_init_fontmaps, pdf_load_fontmap_file,
+    pdf_close_fontmaps, pdf_fontmap_set_diagnostics, pdf_fontmap_set_verbose, pdf_init_fontmaps,
+    pdf_load_fontmap_requests,
 };
 use super::dpx_mem::{new, renew};
+use super::dpx_pdfa::{pdf_get_pdfa, pdf_set_pdfa, pdfa_violation, pdfa_violation_count};
 use super::dpx_pdfencrypt::{pdf_enc_compute_id_string, pdf_enc_set_passwd, pdf_enc_set_verbose};
 use super::dpx_pdfobj::{
     pdf_files_close, pdf_files_init, pdf_get_version, pdf_obj_reset_global_state,
-    pdf_obj_set_verbose, pdf_set_compression, pdf_set_use_predictor, pdf_set_version,
+    pdf_obj_set_verbose, pdf_set_compression, pdf_set_linearize, pdf_set_use_predictor,
+    pdf_set_version,
 };
+use super::dpx_pdfstruct::pdf_struct_reset_global_state;
+use super::dpx_pdfximage::pdf_ximage_set_downsampling;
 use super::dpx_tfm::tfm_reset_global_state;
 use super::dpx_vf::vf_reset_global_state;
 use crate::specials::{
     spc_exec_at_begin_document, spc_exec_at_end_document, tpic::tpic_set_fill_mode,
 };
-use libc::{atoi, free, strlen};
+use bridge::_tt_abort;
+use libc::{atoi, free, strlen, strncpy};
 use std::slice::from_raw_parts;
 
 pub type PageRange = page_range;
@@ -303,6 +312,43 @@ unsafe fn do_dvi_pages(mut page_ranges: *mut PageRange, mut num_page_ranges: u32
     }
     spc_exec_at_end_document();
 }
+/// The settings of a run that do not come from the DVI file itself. Strings
+/// are NUL-terminated; a null `image_cache_dir` disables the image cache, a
+/// null `paperspec` means letter paper, and null passwords are empty.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct DvipdfmxOptions {
+    pub compress: bool,
+    pub deterministic_tags: bool,
+    /// Downsample images painted at more than 1.5 times this resolution;
+    /// 0 disables downsampling.
+    pub image_dpi: u32,
+    pub jpeg_quality: u8,
+    pub png_to_jpeg: bool,
+    pub pk_outline_fallback: bool,
+    pub image_cache_dir: *const i8,
+    /// The PDF/A part to conform to, or 0.
+    pub pdfa_part: u32,
+    pub encrypt: bool,
+    pub enc_key_bits: u32,
+    pub enc_permission: u32,
+    pub enc_owner_pw: *const i8,
+    pub enc_user_pw: *const i8,
+    pub linearize: bool,
+    pub keep_annotations: bool,
+    pub font_map_diagnostics: bool,
+    pub paperspec: *const i8,
+    pub landscape: bool,
+    /// The origin, in big points from the top left corner of the paper.
+    pub origin_x: f64,
+    pub origin_y: f64,
+    pub pdf_minor_version: u32,
+    pub decimal_digits: i32,
+    pub bookmark_depth: i32,
+    /// How far link annotations extend beyond their text, in big points.
+    pub annot_margin: f64,
+    pub pk_dpi: i32,
+}
 #[no_mangle]
 pub unsafe extern "C" fn dvipdfmx_main(
     mut pdf_filename: *const i8,
@@ -310,8 +356,7 @@ pub unsafe extern "C" fn dvipdfmx_main(
     mut pagespec: *const i8,
     mut opt_flags: i32,
     mut translate: bool,
-    mut compress: bool,
-    mut deterministic_tags: bool,
+    options: &DvipdfmxOptions,
     mut quiet: bool,
     mut verbose: u32,
 ) -> i32 {
@@ -327,6 +372,8 @@ pub unsafe extern "C" fn dvipdfmx_main(
     pdf_dev_reset_global_state();
     pdf_obj_reset_global_state();
     pdf_font_reset_unique_tag_state();
+    pdf_set_pdfa(options.pdfa_part as i32);
+    pdf_struct_reset_global_state();
     if quiet {
         shut_up(2i32);
     } else {
@@ -339,29 +386,50 @@ pub unsafe extern "C" fn dvipdfmx_main(
         dpx_file_set_verbose(verbose as i32);
         tt_aux_set_verbose(verbose as i32);
     }
-    pdf_set_compression(if compress as i32 != 0 { 9i32 } else { 0i32 });
-    pdf_font_set_deterministic_unique_tags(if deterministic_tags as i32 != 0 {
+    pdf_set_compression(if options.compress { 9i32 } else { 0i32 });
+    pdf_font_set_deterministic_unique_tags(if options.deterministic_tags as i32 != 0 {
         1i32
     } else {
         0i32
     });
+    pdf_ximage_set_downsampling(options.image_dpi, options.jpeg_quality, options.png_to_jpeg);
+    let image_cache_dir = options.image_cache_dir;
+    dpx_imagecache::set_cache_dir(if image_cache_dir.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(image_cache_dir).to_string_lossy().into_owned().into())
+    });
     system_default();
     pdf_init_fontmaps();
     /* We used to read the config file here. It synthesized command-line
-     * arguments, so we emulate the default TeXLive config file by copying those
-     * code bits. */
-    pdf_set_version(5_u32); /* last page */
-    select_paper(b"letter");
-    annot_grow = 0i32 as f64;
-    bookmark_open = 0i32;
+     * arguments; these now come from the caller, whose defaults follow the
+     * TeXLive config file (letter paper, PDF 1.5, 600 dpi, 5 digits). */
+    /* A version of 0 means the default, which encryption may raise. */
+    let version_requested = options.pdf_minor_version != 0;
+    pdf_set_version(if version_requested {
+        options.pdf_minor_version
+    } else {
+        5_u32
+    }); /* last page */
+    select_paper(if options.paperspec.is_null() {
+        &b"letter"[..]
+    } else {
+        CStr::from_ptr(options.paperspec).to_bytes()
+    });
+    landscape_mode = options.landscape as i32;
+    x_offset = options.origin_x;
+    y_offset = options.origin_y;
+    annot_grow = options.annot_margin;
+    bookmark_open = options.bookmark_depth;
     key_bits = 40i32;
     permission = 0x3ci32;
-    font_dpi = 600i32;
-    pdfdecimaldigits = 5i32;
+    font_dpi = options.pk_dpi;
+    pdfdecimaldigits = options.decimal_digits;
     image_cache_life = -2i32;
-    pdf_load_fontmap_file(CStr::from_bytes_with_nul(b"pdftex.map\x00").unwrap(), '+' as i32);
-    pdf_load_fontmap_file(CStr::from_bytes_with_nul(b"kanjix.map\x00").unwrap(), '+' as i32);
-    pdf_load_fontmap_file(CStr::from_bytes_with_nul(b"ckx.map\x00").unwrap(), '+' as i32);
+    /* The map files (pdftex.map, kanjix.map and ckx.map by default) and lines
+     * are up to the caller. */
+    pdf_fontmap_set_diagnostics(options.font_map_diagnostics);
+    pdf_load_fontmap_requests();
     if !pagespec.is_null() {
         select_pages(pagespec, &mut page_ranges, &mut num_page_ranges);
     }
@@ -377,6 +445,7 @@ pub unsafe extern "C" fn dvipdfmx_main(
     /*kpse_init_prog("", font_dpi, NULL, NULL);
     kpse_set_program_enabled(kpse_pk_format, true, kpse_src_texmf_cnf);*/
     pdf_font_set_dpi(font_dpi);
+    PKFont_set_outline_fallback(options.pk_outline_fallback);
     dpx_delete_old_cache(image_cache_life);
     pdf_enc_compute_id_string(
         if dvi_filename.is_null() {
@@ -400,6 +469,25 @@ pub unsafe extern "C" fn dvipdfmx_main(
     let mut ver_minor: i32 = 0i32;
     let mut owner_pw: [i8; 127] = [0; 127];
     let mut user_pw: [i8; 127] = [0; 127];
+    if options.encrypt {
+        /* Settings from the caller; pdf:encrypt specials still override them. */
+        do_encryption = 1i32;
+        key_bits = options.enc_key_bits as i32;
+        permission = options.enc_permission as i32;
+        if !options.enc_user_pw.is_null() {
+            strncpy(user_pw.as_mut_ptr(), options.enc_user_pw, 126);
+        }
+        if !options.enc_owner_pw.is_null() && *options.enc_owner_pw != 0 {
+            strncpy(owner_pw.as_mut_ptr(), options.enc_owner_pw, 126);
+        } else {
+            /* Without an owner password, anyone could lift the permission
+             * restrictions; use one that nobody knows instead. */
+            let random_pw = format!("{:032x}", rand::random::<u128>());
+            for (d, s) in owner_pw.iter_mut().zip(random_pw.bytes()) {
+                *d = s as i8;
+            }
+        }
+    }
     /* Dependency between DVI and PDF side is rather complicated... */
     let dvi2pts = dvi_init(dvi_filename, mag);
     if dvi2pts == 0.0f64 {
@@ -421,18 +509,32 @@ pub unsafe extern "C" fn dvipdfmx_main(
         owner_pw.as_mut_ptr(),
         user_pw.as_mut_ptr(),
     );
-    if ver_minor >= 3i32 && ver_minor <= 7i32 {
+    let version_given = ver_minor >= 3i32 && ver_minor <= 7i32;
+    if version_given {
         pdf_set_version(ver_minor as u32);
     }
+    let version_given = version_given || version_requested;
+    if do_encryption != 0 && pdf_get_pdfa() != 0 {
+        pdfa_violation("encryption is not allowed; the document is written unencrypted");
+        do_encryption = 0i32;
+    }
     if do_encryption != 0 {
         if !(key_bits >= 40i32 && key_bits <= 128i32 && key_bits % 8i32 == 0i32)
             && key_bits != 256i32
         {
-            panic!("Invalid encryption key length specified: {}", key_bits);
-        } else {
-            if key_bits > 40i32 && pdf_get_version() < 4_u32 {
-                panic!("Chosen key length requires at least PDF 1.4. Use \"-V 4\" to change.");
-            }
+            _tt_abort(
+                b"invalid encryption key length: %d bits\x00" as *const u8 as *const i8,
+                key_bits,
+            );
+        } else if key_bits > 40i32 && pdf_get_version() < 4_u32 {
+            _tt_abort(
+                b"encryption keys longer than 40 bits require at least PDF 1.4\x00" as *const u8
+                    as *const i8,
+            );
+        }
+        if key_bits == 256i32 && !version_given && pdf_get_version() < 7_u32 {
+            /* AES-256 (revision 6) is not available before PDF 1.7. */
+            pdf_set_version(7_u32);
         }
         do_encryption = 1i32;
         pdf_enc_set_passwd(
@@ -451,6 +553,8 @@ pub unsafe extern "C" fn dvipdfmx_main(
     if opt_flags & 1i32 << 6i32 != 0 {
         enable_object_stream = false
     }
+    pdf_set_linearize(options.linearize);
+    pdf_include_set_annotations(options.keep_annotations);
     /* Set default paper size here so that all page's can inherite it.
      * annot_grow:    Margin of annotation.
      * bookmark_open: Miximal depth of open bookmarks.
@@ -489,5 +593,13 @@ pub unsafe extern "C" fn dvipdfmx_main(
     dvi_close();
     info!("\n");
     free(page_ranges as *mut libc::c_void);
+    if pdfa_violation_count() > 0 {
+        _tt_abort(
+            b"output does not conform to PDF/A-%db: %d violation(s) found\x00" as *const u8
+                as *const i8,
+            options.pdfa_part as i32,
+            pdfa_violation_count() as i32,
+        );
+    }
     0i32
 }
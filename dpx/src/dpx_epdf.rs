@@ -29,15 +29,16 @@
 
 use crate::warn;
 
+use super::dpx_pdfdev::{Coord, Rect};
+use super::dpx_pdfdoc::pdf_doc_get_dictionary;
 use super::dpx_pdfdoc::pdf_doc_get_page;
 use super::dpx_pdfximage::{pdf_ximage_init_form_info, pdf_ximage_set_form};
 use crate::dpx_pdfobj::{
-    pdf_add_array, pdf_add_dict, pdf_array_length, pdf_boolean_value, pdf_close, pdf_concat_stream,
-    pdf_deref_obj, pdf_file_get_catalog, pdf_file_get_version,
-    pdf_get_version, pdf_import_object, pdf_new_array,
-    pdf_new_name, pdf_new_number, pdf_new_stream, pdf_obj,
-    pdf_open, pdf_release_obj,
-    STREAM_COMPRESS,
+    pdf_add_array, pdf_add_dict, pdf_array_length, pdf_boolean_value, pdf_close,
+    pdf_compare_reference, pdf_concat_stream, pdf_deref_obj, pdf_dict, pdf_file_get_catalog,
+    pdf_file_get_version, pdf_get_version, pdf_import_object, pdf_link_obj, pdf_name_value,
+    pdf_new_array, pdf_new_dict, pdf_new_name, pdf_new_number, pdf_new_stream, pdf_number_value,
+    pdf_obj, pdf_open, pdf_release_obj, STREAM_COMPRESS,
 };
 pub type __off_t = i64;
 pub type __off64_t = i64;
@@ -71,6 +72,214 @@ pub const OP_UNKNOWN: C2RustUnnamed_0 = 16;
    Copyright 2016-2018 the Tectonic Project
    Licensed under the MIT License.
 */
+static mut keep_annotations: bool = false;
+
+/// Carries the link annotations of included PDF pages over onto the pages
+/// they are placed on, and merges the optional content (layers) of included
+/// files into the output.
+pub unsafe fn pdf_include_set_annotations(flag: bool) {
+    keep_annotations = flag;
+}
+
+unsafe fn is_name(obj: Option<&pdf_obj>, name: &[u8]) -> bool {
+    obj.map_or(false, |obj| {
+        obj.is_name() && pdf_name_value(obj).to_bytes() == name
+    })
+}
+
+/* The numbers of an array, which may be an indirect object */
+unsafe fn read_numbers(obj: Option<&mut pdf_obj>) -> Vec<f64> {
+    let array = pdf_deref_obj(obj);
+    let mut numbers = vec![];
+    if !array.is_null() && (*array).is_array() {
+        for &value in &(*array).as_array().values {
+            if !(*value).is_number() {
+                numbers.clear();
+                break;
+            }
+            numbers.push(pdf_number_value(&*value));
+        }
+    }
+    pdf_release_obj(array);
+    numbers
+}
+
+/* A copy of a link annotation of an included page, with its rectangle in the
+ * space of the form XObject. Links to places in the included document cannot
+ * work and are dropped, as are links outside of the included area. */
+unsafe fn import_link(annot: *mut pdf_obj, info: &xform_info) -> Option<*mut pdf_obj> {
+    if annot.is_null() || !(*annot).is_dict() {
+        return None;
+    }
+    let dict = (*annot).as_dict_mut();
+    if !is_name(dict.get("Subtype"), b"Link") || dict.has("Dest") {
+        return None;
+    }
+    let action = pdf_deref_obj(dict.get_mut("A"));
+    let external = !action.is_null()
+        && (*action).is_dict()
+        && [&b"URI"[..], b"GoToR", b"Launch"]
+            .iter()
+            .any(|&s| is_name((*action).as_dict().get("S"), s));
+    pdf_release_obj(action);
+    let rect = read_numbers(dict.get_mut("Rect"));
+    if !external || rect.len() != 4 {
+        return None;
+    }
+    let rect = Rect::new(
+        (rect[0].min(rect[2]), rect[1].min(rect[3])),
+        (rect[0].max(rect[2]), rect[1].max(rect[3])),
+    );
+    if rect.ur.x < info.bbox.ll.x
+        || rect.ll.x > info.bbox.ur.x
+        || rect.ur.y < info.bbox.ll.y
+        || rect.ll.y > info.bbox.ur.y
+    {
+        return None;
+    }
+    let link = pdf_new_dict();
+    let mut data = (*annot).data as *mut pdf_dict;
+    while !(*data).key.is_null() {
+        let key = pdf_name_value(&*(*data).key).to_bytes();
+        /* The page and the structure tree do not come along */
+        if key == b"Rect" {
+            let rect = rect.transform(&info.matrix);
+            let array = pdf_new_array();
+            for &v in &[rect.ll.x, rect.ll.y, rect.ur.x, rect.ur.y] {
+                pdf_add_array(&mut *array, pdf_new_number(v));
+            }
+            pdf_add_dict(&mut *link, key, array);
+        } else if key == b"QuadPoints" {
+            let array = pdf_new_array();
+            for xy in read_numbers((*data).value.as_mut()).chunks(2) {
+                if xy.len() == 2 {
+                    let p = info.matrix.transform_point(Coord::new(xy[0], xy[1]));
+                    pdf_add_array(&mut *array, pdf_new_number(p.x));
+                    pdf_add_array(&mut *array, pdf_new_number(p.y));
+                }
+            }
+            pdf_add_dict(&mut *link, key, array);
+        } else if key != b"P" && key != b"Parent" && key != b"Popup" && key != b"StructParent" {
+            pdf_add_dict(&mut *link, key, pdf_import_object((*data).value));
+        }
+        data = (*data).next
+    }
+    Some(link)
+}
+
+/* The links of an included page that are carried over, or NULL. */
+unsafe fn import_annotations(page: *mut pdf_obj, info: &xform_info) -> *mut pdf_obj {
+    let annots = pdf_deref_obj((*page).as_dict_mut().get_mut("Annots"));
+    let result = pdf_new_array();
+    if !annots.is_null() && (*annots).is_array() {
+        for i in 0..pdf_array_length(&*annots) {
+            let annot = pdf_deref_obj((*annots).as_array_mut().get_mut(i as i32));
+            if let Some(link) = import_link(annot, info) {
+                pdf_add_array(&mut *result, link);
+            }
+            pdf_release_obj(annot);
+        }
+    }
+    pdf_release_obj(annots);
+    if pdf_array_length(&*result) == 0 {
+        pdf_release_obj(result);
+        return 0 as *mut pdf_obj;
+    }
+    result
+}
+
+/* The array or dictionary under this key of an output dictionary, added if
+ * missing. */
+unsafe fn dict_entry(dict: *mut pdf_obj, key: &str, array: bool) -> *mut pdf_obj {
+    let value = pdf_deref_obj((*dict).as_dict_mut().get_mut(key));
+    pdf_release_obj(value);
+    if !value.is_null()
+        && (if array {
+            (*value).is_array()
+        } else {
+            (*value).is_dict()
+        })
+    {
+        return value;
+    }
+    let value = if array {
+        pdf_new_array()
+    } else {
+        pdf_new_dict()
+    };
+    pdf_add_dict(&mut *dict, key, value);
+    value
+}
+
+unsafe fn array_has_ref(array: *mut pdf_obj, obj: *mut pdf_obj) -> bool {
+    (*array)
+        .as_array()
+        .values
+        .iter()
+        .any(|&v| (*v).is_indirect() && (*obj).is_indirect() && pdf_compare_reference(v, obj) == 0)
+}
+
+/* Adds the optional content groups of an included file, and its default
+ * configuration, to the output. */
+unsafe fn merge_ocproperties(catalog: *mut pdf_obj) {
+    let props = pdf_deref_obj((*catalog).as_dict_mut().get_mut("OCProperties"));
+    let ocgs = if !props.is_null() && (*props).is_dict() {
+        pdf_deref_obj((*props).as_dict_mut().get_mut("OCGs"))
+    } else {
+        0 as *mut pdf_obj
+    };
+    if ocgs.is_null() || !(*ocgs).is_array() {
+        pdf_release_obj(ocgs);
+        pdf_release_obj(props);
+        return;
+    }
+    let groups = pdf_import_object(ocgs);
+    let out = dict_entry(pdf_doc_get_dictionary("Catalog"), "OCProperties", false);
+    let out_ocgs = dict_entry(out, "OCGs", true);
+    let mut added = false;
+    for &ocg in &(*groups).as_array().values {
+        if !array_has_ref(out_ocgs, ocg) {
+            pdf_add_array(&mut *out_ocgs, pdf_link_obj(ocg));
+            added = true;
+        }
+    }
+    /* The same file may be included more than once */
+    let config = pdf_deref_obj((*props).as_dict_mut().get_mut("D"));
+    if added && !config.is_null() && (*config).is_dict() {
+        let out_config = dict_entry(out, "D", false);
+        let mut on = 0 as *mut pdf_obj;
+        for &key in &["ON", "OFF", "Order", "RBGroups", "Locked"] {
+            let value = pdf_deref_obj((*config).as_dict_mut().get_mut(key));
+            if !value.is_null() && (*value).is_array() {
+                let imported = pdf_import_object(value);
+                let out_value = dict_entry(out_config, key, true);
+                for &v in &(*imported).as_array().values {
+                    pdf_add_array(&mut *out_value, pdf_link_obj(v));
+                }
+                if key == "ON" {
+                    on = pdf_link_obj(imported);
+                }
+                pdf_release_obj(imported);
+            }
+            pdf_release_obj(value);
+        }
+        /* The output starts with all groups on */
+        if is_name((*config).as_dict().get("BaseState"), b"OFF") {
+            let off = dict_entry(out_config, "OFF", true);
+            for &ocg in &(*groups).as_array().values {
+                if on.is_null() || !array_has_ref(on, ocg) {
+                    pdf_add_array(&mut *off, pdf_link_obj(ocg));
+                }
+            }
+        }
+        pdf_release_obj(on);
+    }
+    pdf_release_obj(config);
+    pdf_release_obj(groups);
+    pdf_release_obj(ocgs);
+    pdf_release_obj(props);
+}
+
 /* ximage here is the result. DONT USE IT FOR PASSING OPTIONS! */
 #[no_mangle]
 pub unsafe extern "C" fn pdf_include_page(
@@ -138,6 +347,11 @@ pub unsafe extern "C" fn pdf_include_page(
         pdf_release_obj(tmp);
     }
 
+    let annots = if keep_annotations {
+        import_annotations(page, &info)
+    } else {
+        0 as *mut pdf_obj
+    };
     contents = pdf_deref_obj((*page).as_dict_mut().get_mut("Contents"));
     pdf_release_obj(page);
     /*
@@ -171,12 +385,14 @@ pub unsafe extern "C" fn pdf_include_page(
             {
                 pdf_release_obj(content_seg);
                 pdf_release_obj(content_new);
+                pdf_release_obj(annots);
                 error();
                 return -1;
             }
             pdf_release_obj(content_seg);
         }
     } else {
+        pdf_release_obj(annots);
         error();
         return -1;
     }
@@ -208,9 +424,13 @@ pub unsafe extern "C" fn pdf_include_page(
     pdf_add_dict(contents_dict, "Resources", pdf_import_object(resources));
     pdf_release_obj(resources);
 
+    if keep_annotations {
+        merge_ocproperties(catalog);
+    }
     pdf_close(pf);
 
     pdf_ximage_set_form(ximage, &mut info, contents);
+    (*ximage).attr.annots = annots;
 
     0
 }
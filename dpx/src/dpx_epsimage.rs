@@ -0,0 +1,4240 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2002-2016 by Jin-Hwan Cho and Shunsaku Hirata,
+    the dvipdfmx project team.
+
+    Copyright (C) 1998, 1999 by Mark A. Wicks <mwicks@kettering.edu>
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! EPS figures are included by running them through a small, built-in
+//! PostScript interpreter while a Form XObject is being grabbed. Painting
+//! operators are mapped onto the `pdf_dev_*` drawing layer, so the figure
+//! ends up as ordinary PDF content in the native coordinates of its
+//! `%%BoundingBox`, just like an included PDF page.
+//!
+//! The interpreter covers the subset of PostScript that plotting tools
+//! (gnuplot, matplotlib, R, MetaPost, Asymptote, ...) actually emit: the
+//! stack, arithmetic, control and dictionary operators; paths, fills,
+//! clipping and colors; sampled images read from strings, procedures,
+//! `currentfile` and the usual decode filters; and text, either in fonts
+//! resolved through the fontmap or in Type 3 fonts defined by the figure
+//! itself. Anything else aborts the interpretation with a warning, keeping
+//! whatever had been drawn up to that point.
+
+#![allow(non_camel_case_types, non_snake_case)]
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use std::ptr;
+use std::rc::Rc;
+
+use crate::warn;
+
+use super::dpx_fontmap::pdf_resolve_font_key;
+use super::dpx_pdfcolor::PdfColor;
+use super::dpx_pdfdev::{
+    dev_unit_dviunit, graphics_mode, pdf_dev_get_font_wmode, pdf_dev_locate_font,
+    pdf_dev_set_string, Coord, Rect, TMatrix,
+};
+use super::dpx_pdfdoc::{
+    pdf_doc_add_page_content, pdf_doc_add_page_resource, pdf_doc_begin_grabbing,
+    pdf_doc_end_grabbing,
+};
+use super::dpx_pdfdraw::{
+    pdf_dev_arc, pdf_dev_arcn, pdf_dev_clip, pdf_dev_closepath, pdf_dev_concat,
+    pdf_dev_current_depth, pdf_dev_currentmatrix, pdf_dev_currentpoint, pdf_dev_curveto,
+    pdf_dev_eoclip, pdf_dev_flushpath, pdf_dev_grestore, pdf_dev_gsave, pdf_dev_lineto,
+    pdf_dev_moveto, pdf_dev_newpath, pdf_dev_rcurveto, pdf_dev_rlineto, pdf_dev_rmoveto,
+    pdf_dev_set_color, pdf_dev_setdash, pdf_dev_setlinecap, pdf_dev_setlinejoin,
+    pdf_dev_setlinewidth, pdf_dev_setmiterlimit,
+};
+use super::dpx_pdfximage::{
+    pdf_ximage_defineresource, pdf_ximage_get_reference, pdf_ximage_get_resname,
+    pdf_ximage_init_image_info, ximage_info, XInfo,
+};
+use super::dpx_tfm::{tfm_open, tfm_string_width};
+#[cfg(feature = "libz-sys")]
+use crate::dpx_pdfobj::pdf_inflate;
+use crate::dpx_pdfobj::{
+    pdf_add_array, pdf_add_dict, pdf_add_stream, pdf_new_array, pdf_new_boolean, pdf_new_name,
+    pdf_new_number, pdf_new_stream, pdf_new_string, pdf_obj, STREAM_COMPRESS,
+};
+
+use bridge::InputHandleWrapper;
+
+/// The magic number of the binary header of "DOS EPS" files, which wrap the
+/// PostScript section together with a TIFF or WMF preview.
+const DOS_EPS_MAGIC: [u8; 4] = [0xc5, 0xd0, 0xd3, 0xc6];
+
+const MAX_OSTACK: usize = 65535;
+const MAX_DEPTH: usize = 256;
+const MAX_STEPS: u64 = 50_000_000;
+
+type PsResult<T = ()> = Result<T, PsError>;
+
+#[derive(Clone, Debug, PartialEq)]
+enum PsError {
+    StackUnderflow,
+    TypeCheck,
+    RangeCheck,
+    UndefinedResult,
+    LimitCheck,
+    SyntaxError,
+    UnmatchedMark,
+    Undefined(String),
+    Unsupported(String),
+    /* Not errors: unwinding for `exit`, `stop`, `quit` and width-only
+     * rendering of Type 3 glyphs. */
+    Exit,
+    Stop,
+    Quit,
+    WidthDone,
+}
+
+impl fmt::Display for PsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PsError::StackUnderflow => write!(f, "stackunderflow"),
+            PsError::TypeCheck => write!(f, "typecheck"),
+            PsError::RangeCheck => write!(f, "rangecheck"),
+            PsError::UndefinedResult => write!(f, "undefinedresult"),
+            PsError::LimitCheck => write!(f, "limitcheck"),
+            PsError::SyntaxError => write!(f, "syntaxerror"),
+            PsError::UnmatchedMark => write!(f, "unmatchedmark"),
+            PsError::Undefined(name) => write!(f, "undefined name \"{}\"", name),
+            PsError::Unsupported(what) if what.is_empty() => write!(f, "unsupported operator"),
+            PsError::Unsupported(what) => write!(f, "unsupported feature \"{}\"", what),
+            PsError::Exit => write!(f, "invalidexit"),
+            PsError::Stop | PsError::WidthDone => write!(f, "invalidstop"),
+            PsError::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+type OpFn = fn(&mut Interp) -> PsResult;
+
+#[derive(Clone, Copy)]
+struct Operator {
+    name: &'static str,
+    func: OpFn,
+}
+
+#[derive(Clone)]
+struct PsStr {
+    buf: Rc<RefCell<Vec<u8>>>,
+    start: usize,
+    len: usize,
+}
+
+impl PsStr {
+    fn new(v: Vec<u8>) -> Self {
+        let len = v.len();
+        PsStr {
+            buf: Rc::new(RefCell::new(v)),
+            start: 0,
+            len,
+        }
+    }
+
+    fn bytes(&self) -> Vec<u8> {
+        self.buf.borrow()[self.start..self.start + self.len].to_vec()
+    }
+
+    fn sub(&self, start: usize, len: usize) -> PsResult<PsStr> {
+        if start + len > self.len {
+            return Err(PsError::RangeCheck);
+        }
+        Ok(PsStr {
+            buf: self.buf.clone(),
+            start: self.start + start,
+            len,
+        })
+    }
+}
+
+#[derive(Clone)]
+struct PsArr {
+    buf: Rc<RefCell<Vec<Obj>>>,
+    start: usize,
+    len: usize,
+}
+
+impl PsArr {
+    fn new(v: Vec<Obj>) -> Self {
+        let len = v.len();
+        PsArr {
+            buf: Rc::new(RefCell::new(v)),
+            start: 0,
+            len,
+        }
+    }
+
+    fn items(&self) -> Vec<Obj> {
+        self.buf.borrow()[self.start..self.start + self.len].to_vec()
+    }
+
+    fn get(&self, i: usize) -> PsResult<Obj> {
+        if i >= self.len {
+            return Err(PsError::RangeCheck);
+        }
+        Ok(self.buf.borrow()[self.start + i].clone())
+    }
+
+    fn put(&self, i: usize, o: Obj) -> PsResult {
+        if i >= self.len {
+            return Err(PsError::RangeCheck);
+        }
+        self.buf.borrow_mut()[self.start + i] = o;
+        Ok(())
+    }
+
+    fn sub(&self, start: usize, len: usize) -> PsResult<PsArr> {
+        if start + len > self.len {
+            return Err(PsError::RangeCheck);
+        }
+        Ok(PsArr {
+            buf: self.buf.clone(),
+            start: self.start + start,
+            len,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Int(i32),
+    Bool(bool),
+    Name(Vec<u8>),
+}
+
+type DictRef = Rc<RefCell<BTreeMap<Key, Obj>>>;
+
+fn new_dict() -> DictRef {
+    Rc::new(RefCell::new(BTreeMap::new()))
+}
+
+struct DataFile {
+    data: Vec<u8>,
+    pos: usize,
+}
+
+#[derive(Clone)]
+enum FileRef {
+    /// The program being interpreted, as returned by `currentfile`.
+    Current,
+    /// The decoded output of a filter over a string or procedure.
+    Data(Rc<RefCell<DataFile>>),
+    /// A decode filter reading from another file, decoding on demand.
+    Filter(Rc<RefCell<FilterFile>>),
+}
+
+#[derive(Clone, Copy)]
+enum FilterKind {
+    AsciiHex,
+    Ascii85,
+    RunLength,
+}
+
+struct FilterFile {
+    kind: FilterKind,
+    src: FileRef,
+    buf: Vec<u8>,
+    pos: usize,
+    eod: bool,
+}
+
+#[derive(Clone)]
+enum Obj {
+    Null,
+    Int(i32),
+    Real(f64),
+    Bool(bool),
+    Name(Rc<[u8]>),
+    ExecName(Rc<[u8]>),
+    Str(PsStr),
+    Array(PsArr),
+    Proc(PsArr),
+    Dict(DictRef),
+    Op(Operator),
+    Mark,
+    File(FileRef),
+    Save(usize),
+}
+
+impl Obj {
+    fn name(s: &[u8]) -> Obj {
+        Obj::Name(Rc::from(s))
+    }
+
+    fn string(s: &[u8]) -> Obj {
+        Obj::Str(PsStr::new(s.to_vec()))
+    }
+
+    fn real(v: f64) -> Obj {
+        Obj::Real(v)
+    }
+
+    fn num(&self) -> Option<f64> {
+        match *self {
+            Obj::Int(i) => Some(i as f64),
+            Obj::Real(r) => Some(r),
+            _ => None,
+        }
+    }
+
+    fn key(&self) -> PsResult<Key> {
+        match self {
+            Obj::Name(n) | Obj::ExecName(n) => Ok(Key::Name(n.to_vec())),
+            Obj::Str(s) => Ok(Key::Name(s.bytes())),
+            Obj::Int(i) => Ok(Key::Int(*i)),
+            Obj::Real(r) if r.fract() == 0. && r.abs() < i32::MAX as f64 => Ok(Key::Int(*r as i32)),
+            Obj::Bool(b) => Ok(Key::Bool(*b)),
+            _ => Err(PsError::TypeCheck),
+        }
+    }
+
+    fn from_key(k: &Key) -> Obj {
+        match k {
+            Key::Int(i) => Obj::Int(*i),
+            Key::Bool(b) => Obj::Bool(*b),
+            Key::Name(n) => Obj::name(n),
+        }
+    }
+
+    fn type_name(&self) -> &'static [u8] {
+        match self {
+            Obj::Null => b"nulltype",
+            Obj::Int(_) => b"integertype",
+            Obj::Real(_) => b"realtype",
+            Obj::Bool(_) => b"booleantype",
+            Obj::Name(_) | Obj::ExecName(_) => b"nametype",
+            Obj::Str(_) => b"stringtype",
+            Obj::Array(_) | Obj::Proc(_) => b"arraytype",
+            Obj::Dict(_) => b"dicttype",
+            Obj::Op(_) => b"operatortype",
+            Obj::Mark => b"marktype",
+            Obj::File(_) => b"filetype",
+            Obj::Save(_) => b"savetype",
+        }
+    }
+}
+
+fn ps_eq(a: &Obj, b: &Obj) -> bool {
+    if let (Some(x), Some(y)) = (a.num(), b.num()) {
+        return x == y;
+    }
+    match (a, b) {
+        (Obj::Bool(x), Obj::Bool(y)) => x == y,
+        (Obj::Null, Obj::Null) | (Obj::Mark, Obj::Mark) => true,
+        (Obj::Name(x), Obj::Name(y))
+        | (Obj::Name(x), Obj::ExecName(y))
+        | (Obj::ExecName(x), Obj::Name(y))
+        | (Obj::ExecName(x), Obj::ExecName(y)) => x == y,
+        (Obj::Str(x), Obj::Str(y)) => x.bytes() == y.bytes(),
+        (Obj::Str(x), Obj::Name(y))
+        | (Obj::Str(x), Obj::ExecName(y))
+        | (Obj::Name(y), Obj::Str(x))
+        | (Obj::ExecName(y), Obj::Str(x)) => x.bytes()[..] == y[..],
+        (Obj::Array(x), Obj::Array(y))
+        | (Obj::Proc(x), Obj::Proc(y))
+        | (Obj::Array(x), Obj::Proc(y))
+        | (Obj::Proc(x), Obj::Array(y)) => {
+            Rc::ptr_eq(&x.buf, &y.buf) && x.start == y.start && x.len == y.len
+        }
+        (Obj::Dict(x), Obj::Dict(y)) => Rc::ptr_eq(x, y),
+        (Obj::Op(x), Obj::Op(y)) => x.name == y.name,
+        _ => false,
+    }
+}
+
+fn format_real(v: f64) -> String {
+    let s = format!("{}", v);
+    if s.contains('.') || s.contains('e') || !v.is_finite() {
+        s
+    } else {
+        s + ".0"
+    }
+}
+
+#[derive(Clone)]
+enum ColorSpace {
+    Gray,
+    Rgb,
+    Cmyk,
+    Indexed {
+        base: Box<ColorSpace>,
+        hival: i32,
+        lookup: Vec<u8>,
+    },
+    Separation {
+        alt: Box<ColorSpace>,
+        tint: Obj,
+    },
+}
+
+impl ColorSpace {
+    fn ncomps(&self) -> usize {
+        match self {
+            ColorSpace::Gray => 1,
+            ColorSpace::Rgb => 3,
+            ColorSpace::Cmyk => 4,
+            ColorSpace::Indexed { .. } | ColorSpace::Separation { .. } => 1,
+        }
+    }
+
+    fn initial_color(&self) -> Vec<f64> {
+        match self {
+            ColorSpace::Cmyk => vec![0., 0., 0., 1.],
+            ColorSpace::Separation { .. } => vec![1.],
+            _ => vec![0.; self.ncomps()],
+        }
+    }
+}
+
+#[derive(Clone)]
+struct GState {
+    font: Option<DictRef>,
+    space: ColorSpace,
+    color: Vec<f64>,
+    linewidth: f64,
+}
+
+struct ResidentFont {
+    name: Vec<u8>,
+    size: f64,
+    font_id: i32,
+    tfm_id: i32,
+}
+
+enum GlyphSel {
+    Code(u8),
+    Name(Rc<[u8]>),
+}
+
+struct Interp {
+    src: Rc<[u8]>,
+    pos: usize,
+    ostack: Vec<Obj>,
+    dstack: Vec<DictRef>,
+    systemdict: DictRef,
+    userdict: DictRef,
+    font_directory: DictRef,
+    gstack: Vec<GState>,
+    saves: Vec<usize>,
+    /// Whether drawing operators are passed on to the PDF device. This is off
+    /// when the interpreter isn't attached to a form XObject and while Type 3
+    /// glyphs are run just to measure them.
+    paint: bool,
+    depth: usize,
+    steps: u64,
+    char_width: Option<(f64, f64)>,
+    resident_fonts: Vec<ResidentFont>,
+    missing_fonts: Vec<Vec<u8>>,
+    rand_state: u32,
+    failed_op: Option<&'static str>,
+}
+
+/*
+ * Scanner
+ */
+
+fn is_ps_white(c: u8) -> bool {
+    c == b' ' || c == b'\t' || c == b'\r' || c == b'\n' || c == b'\x0c' || c == 0
+}
+
+fn is_ps_delim(c: u8) -> bool {
+    b"()<>[]{}/%".contains(&c)
+}
+
+fn parse_number(tok: &[u8]) -> Option<Obj> {
+    let s = std::str::from_utf8(tok).ok()?;
+    if let Some(hash) = s.find('#') {
+        let radix: u32 = s[..hash].parse().ok()?;
+        if !(2..=36).contains(&radix) {
+            return None;
+        }
+        let v = u32::from_str_radix(&s[hash + 1..], radix).ok()?;
+        return Some(Obj::Int(v as i32));
+    }
+    let first = s.as_bytes()[0];
+    if !(first.is_ascii_digit() || first == b'+' || first == b'-' || first == b'.') {
+        return None;
+    }
+    if s.bytes()
+        .all(|c| c.is_ascii_digit() || c == b'+' || c == b'-')
+    {
+        if let Ok(i) = s.parse::<i32>() {
+            return Some(Obj::Int(i));
+        }
+    }
+    if !s.bytes().any(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    if s.bytes()
+        .any(|c| !(c.is_ascii_digit() || b"+-.eE".contains(&c)))
+    {
+        return None;
+    }
+    let fixed;
+    let s = if s.ends_with('.') {
+        fixed = format!("{}0", s);
+        &fixed[..]
+    } else {
+        s
+    };
+    s.parse::<f64>().ok().map(Obj::Real)
+}
+
+fn scan_string(src: &[u8], pos: &mut usize) -> PsResult<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut nest = 0;
+    loop {
+        let c = *src.get(*pos).ok_or(PsError::SyntaxError)?;
+        *pos += 1;
+        match c {
+            b'(' => {
+                nest += 1;
+                out.push(c);
+            }
+            b')' => {
+                if nest == 0 {
+                    return Ok(out);
+                }
+                nest -= 1;
+                out.push(c);
+            }
+            b'\\' => {
+                let e = *src.get(*pos).ok_or(PsError::SyntaxError)?;
+                *pos += 1;
+                match e {
+                    b'n' => out.push(b'\n'),
+                    b'r' => out.push(b'\r'),
+                    b't' => out.push(b'\t'),
+                    b'b' => out.push(8),
+                    b'f' => out.push(12),
+                    b'\r' => {
+                        if src.get(*pos) == Some(&b'\n') {
+                            *pos += 1;
+                        }
+                    }
+                    b'\n' => {}
+                    b'0'..=b'7' => {
+                        let mut v = (e - b'0') as u32;
+                        for _ in 0..2 {
+                            match src.get(*pos) {
+                                Some(&d) if (b'0'..=b'7').contains(&d) => {
+                                    v = v * 8 + (d - b'0') as u32;
+                                    *pos += 1;
+                                }
+                                _ => break,
+                            }
+                        }
+                        out.push(v as u8);
+                    }
+                    _ => out.push(e),
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/* The decoders work one chunk at a time, so that filters reading from
+ * `currentfile` stop exactly at their end-of-data marker. Each returns the
+ * decoded bytes and whether the end of the data was reached. */
+
+fn hex_chunk(getc: &mut impl FnMut() -> Option<u8>) -> (Vec<u8>, bool) {
+    let mut hi = None;
+    loop {
+        match getc() {
+            None | Some(b'>') => return (hi.map(|h| vec![h << 4]).unwrap_or_default(), true),
+            Some(c) => {
+                if let Some(v) = hex_value(c) {
+                    match hi {
+                        None => hi = Some(v),
+                        Some(h) => return (vec![h << 4 | v], false),
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn a85_chunk(getc: &mut impl FnMut() -> Option<u8>) -> PsResult<(Vec<u8>, bool)> {
+    let mut group = [0u8; 5];
+    let mut n = 0;
+    let eod = loop {
+        match getc() {
+            None => break true,
+            Some(b'~') => {
+                /* Swallow the `>` of the end-of-data marker. */
+                getc();
+                break true;
+            }
+            Some(b'z') if n == 0 => return Ok((vec![0; 4], false)),
+            Some(c @ b'!'..=b'u') => {
+                group[n] = c - b'!';
+                n += 1;
+                if n == 5 {
+                    break false;
+                }
+            }
+            Some(c) if is_ps_white(c) => {}
+            Some(_) => return Err(PsError::SyntaxError),
+        }
+    };
+    match n {
+        0 => Ok((Vec::new(), eod)),
+        1 => Err(PsError::SyntaxError),
+        _ => {
+            for d in group.iter_mut().skip(n) {
+                *d = 84;
+            }
+            let v = group.iter().fold(0u64, |acc, &d| acc * 85 + d as u64);
+            if v > u64::from(u32::max_value()) {
+                return Err(PsError::SyntaxError);
+            }
+            Ok(((v as u32).to_be_bytes()[..n - 1].to_vec(), eod))
+        }
+    }
+}
+
+fn runlength_chunk(getc: &mut impl FnMut() -> Option<u8>) -> (Vec<u8>, bool) {
+    match getc() {
+        None | Some(128) => (Vec::new(), true),
+        Some(len @ 0..=127) => {
+            let mut out = Vec::with_capacity(len as usize + 1);
+            for _ in 0..=len {
+                match getc() {
+                    Some(b) => out.push(b),
+                    None => return (out, true),
+                }
+            }
+            (out, false)
+        }
+        Some(len) => match getc() {
+            Some(b) => (vec![b; 257 - len as usize], false),
+            None => (Vec::new(), true),
+        },
+    }
+}
+
+/// Decodes ASCIIHex data up to the `>` end-of-data marker.
+fn decode_hex(mut getc: impl FnMut() -> Option<u8>) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let (chunk, eod) = hex_chunk(&mut getc);
+        out.extend(chunk);
+        if eod {
+            return out;
+        }
+    }
+}
+
+/// Decodes ASCII85 data up to the `~>` end-of-data marker.
+fn decode_a85(mut getc: impl FnMut() -> Option<u8>) -> PsResult<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let (chunk, eod) = a85_chunk(&mut getc)?;
+        out.extend(chunk);
+        if eod {
+            return Ok(out);
+        }
+    }
+}
+
+/// Decodes RunLength data up to the 128 end-of-data marker.
+fn decode_runlength(mut getc: impl FnMut() -> Option<u8>) -> Vec<u8> {
+    let mut out = Vec::new();
+    loop {
+        let (chunk, eod) = runlength_chunk(&mut getc);
+        out.extend(chunk);
+        if eod {
+            return out;
+        }
+    }
+}
+
+#[cfg(feature = "libz-sys")]
+fn decode_flate(data: &[u8]) -> PsResult<Vec<u8>> {
+    unsafe { pdf_inflate(data) }.ok_or_else(|| PsError::Unsupported("FlateDecode".to_owned()))
+}
+
+#[cfg(not(feature = "libz-sys"))]
+fn decode_flate(_data: &[u8]) -> PsResult<Vec<u8>> {
+    Err(PsError::Unsupported("FlateDecode".to_owned()))
+}
+
+/*
+ * Encodings: only the printable ASCII part of the standard encodings is
+ * provided, which is what figures reencoding their text fonts rely on.
+ */
+
+const ASCII_GLYPH_NAMES: [&str; 95] = [
+    "space",
+    "exclam",
+    "quotedbl",
+    "numbersign",
+    "dollar",
+    "percent",
+    "ampersand",
+    "quoteright",
+    "parenleft",
+    "parenright",
+    "asterisk",
+    "plus",
+    "comma",
+    "hyphen",
+    "period",
+    "slash",
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "colon",
+    "semicolon",
+    "less",
+    "equal",
+    "greater",
+    "question",
+    "at",
+    "A",
+    "B",
+    "C",
+    "D",
+    "E",
+    "F",
+    "G",
+    "H",
+    "I",
+    "J",
+    "K",
+    "L",
+    "M",
+    "N",
+    "O",
+    "P",
+    "Q",
+    "R",
+    "S",
+    "T",
+    "U",
+    "V",
+    "W",
+    "X",
+    "Y",
+    "Z",
+    "bracketleft",
+    "backslash",
+    "bracketright",
+    "asciicircum",
+    "underscore",
+    "quoteleft",
+    "a",
+    "b",
+    "c",
+    "d",
+    "e",
+    "f",
+    "g",
+    "h",
+    "i",
+    "j",
+    "k",
+    "l",
+    "m",
+    "n",
+    "o",
+    "p",
+    "q",
+    "r",
+    "s",
+    "t",
+    "u",
+    "v",
+    "w",
+    "x",
+    "y",
+    "z",
+    "braceleft",
+    "bar",
+    "braceright",
+    "asciitilde",
+];
+
+fn ascii_encoding() -> Obj {
+    let notdef = Obj::name(b".notdef");
+    let mut v = vec![notdef; 256];
+    for (i, name) in ASCII_GLYPH_NAMES.iter().enumerate() {
+        v[32 + i] = Obj::name(name.as_bytes());
+    }
+    Obj::Array(PsArr::new(v))
+}
+
+impl Interp {
+    fn new(src: Vec<u8>, paint: bool) -> Self {
+        let systemdict = new_dict();
+        let userdict = new_dict();
+        let font_directory = new_dict();
+        {
+            let mut sd = systemdict.borrow_mut();
+            for &(name, func) in OPERATORS {
+                sd.insert(
+                    Key::Name(name.as_bytes().to_vec()),
+                    Obj::Op(Operator { name, func }),
+                );
+            }
+            for &name in &[
+                b"errordict" as &[u8],
+                b"statusdict",
+                b"$error",
+                b"globaldict",
+            ] {
+                sd.insert(Key::Name(name.to_vec()), Obj::Dict(new_dict()));
+            }
+            sd.insert(
+                Key::Name(b"systemdict".to_vec()),
+                Obj::Dict(systemdict.clone()),
+            );
+            sd.insert(Key::Name(b"userdict".to_vec()), Obj::Dict(userdict.clone()));
+            sd.insert(
+                Key::Name(b"FontDirectory".to_vec()),
+                Obj::Dict(font_directory.clone()),
+            );
+            sd.insert(Key::Name(b"StandardEncoding".to_vec()), ascii_encoding());
+            sd.insert(Key::Name(b"ISOLatin1Encoding".to_vec()), ascii_encoding());
+            sd.insert(Key::Name(b"null".to_vec()), Obj::Null);
+            sd.insert(Key::Name(b"true".to_vec()), Obj::Bool(true));
+            sd.insert(Key::Name(b"false".to_vec()), Obj::Bool(false));
+            sd.insert(Key::Name(b"languagelevel".to_vec()), Obj::Int(3));
+            sd.insert(Key::Name(b"version".to_vec()), Obj::string(b"3010"));
+            sd.insert(Key::Name(b"product".to_vec()), Obj::string(b"Tectonic"));
+        }
+        Interp {
+            src: Rc::from(src),
+            pos: 0,
+            ostack: Vec::new(),
+            dstack: vec![systemdict.clone(), userdict.clone()],
+            systemdict,
+            userdict,
+            font_directory,
+            gstack: vec![GState {
+                font: None,
+                space: ColorSpace::Gray,
+                color: vec![0.],
+                linewidth: 1.,
+            }],
+            saves: Vec::new(),
+            paint,
+            depth: 0,
+            steps: 0,
+            char_width: None,
+            resident_fonts: Vec::new(),
+            missing_fonts: Vec::new(),
+            rand_state: 1,
+            failed_op: None,
+        }
+    }
+
+    /// Runs the whole program. `quit` is a normal way to end it.
+    fn run(&mut self) -> PsResult {
+        let src = self.src.clone();
+        loop {
+            let mut pos = self.pos;
+            let tok = self.scan(&src, &mut pos)?;
+            self.pos = pos;
+            let r = match tok {
+                None => return Ok(()),
+                Some(obj @ Obj::Proc(_)) => self.push(obj),
+                Some(obj) => self.exec(obj),
+            };
+            match r {
+                Err(PsError::Quit) => return Ok(()),
+                Err(e) => return Err(e),
+                Ok(()) => {}
+            }
+        }
+    }
+
+    fn gs(&mut self) -> &mut GState {
+        self.gstack.last_mut().unwrap()
+    }
+
+    /* Scanner */
+
+    fn scan(&self, src: &[u8], pos: &mut usize) -> PsResult<Option<Obj>> {
+        loop {
+            while *pos < src.len() && is_ps_white(src[*pos]) {
+                *pos += 1;
+            }
+            if *pos >= src.len() {
+                return Ok(None);
+            }
+            if src[*pos] == b'%' {
+                while *pos < src.len() && src[*pos] != b'\n' && src[*pos] != b'\r' {
+                    *pos += 1;
+                }
+                continue;
+            }
+            break;
+        }
+        let c = src[*pos];
+        *pos += 1;
+        match c {
+            b'(' => Ok(Some(Obj::Str(PsStr::new(scan_string(src, pos)?)))),
+            b'<' => {
+                if src.get(*pos) == Some(&b'<') {
+                    *pos += 1;
+                    return Ok(Some(Obj::ExecName(Rc::from(&b"<<"[..]))));
+                }
+                if src.get(*pos) == Some(&b'~') {
+                    *pos += 1;
+                    let mut it = src[*pos..].iter();
+                    let data = decode_a85(|| it.next().copied())?;
+                    *pos = src.len() - it.as_slice().len();
+                    return Ok(Some(Obj::Str(PsStr::new(data))));
+                }
+                let mut it = src[*pos..].iter();
+                let data = decode_hex(|| it.next().copied());
+                *pos = src.len() - it.as_slice().len();
+                Ok(Some(Obj::Str(PsStr::new(data))))
+            }
+            b'>' => {
+                if src.get(*pos) == Some(&b'>') {
+                    *pos += 1;
+                    return Ok(Some(Obj::ExecName(Rc::from(&b">>"[..]))));
+                }
+                Err(PsError::SyntaxError)
+            }
+            b'[' | b']' => Ok(Some(Obj::ExecName(Rc::from(&[c][..])))),
+            b'{' => {
+                let mut items = Vec::new();
+                loop {
+                    while *pos < src.len() && is_ps_white(src[*pos]) {
+                        *pos += 1;
+                    }
+                    if src.get(*pos) == Some(&b'}') {
+                        *pos += 1;
+                        break;
+                    }
+                    match self.scan(src, pos)? {
+                        Some(obj) => items.push(obj),
+                        None => return Err(PsError::SyntaxError),
+                    }
+                }
+                Ok(Some(Obj::Proc(PsArr::new(items))))
+            }
+            b'}' | b')' => Err(PsError::SyntaxError),
+            b'/' => {
+                let immediate = src.get(*pos) == Some(&b'/');
+                if immediate {
+                    *pos += 1;
+                }
+                let tok = self.scan_regular(src, pos);
+                if immediate {
+                    self.lookup(&tok).map(Some).ok_or_else(|| {
+                        PsError::Undefined(String::from_utf8_lossy(&tok).into_owned())
+                    })
+                } else {
+                    Ok(Some(Obj::name(&tok)))
+                }
+            }
+            _ => {
+                *pos -= 1;
+                let tok = self.scan_regular(src, pos);
+                Ok(Some(
+                    parse_number(&tok).unwrap_or_else(|| Obj::ExecName(Rc::from(&tok[..]))),
+                ))
+            }
+        }
+    }
+
+    /// Reads a regular token, consuming the single whitespace character that
+    /// terminates it, so that `currentfile` reads start right after it.
+    fn scan_regular(&self, src: &[u8], pos: &mut usize) -> Vec<u8> {
+        let start = *pos;
+        while *pos < src.len() && !is_ps_white(src[*pos]) && !is_ps_delim(src[*pos]) {
+            *pos += 1;
+        }
+        let tok = src[start..*pos].to_vec();
+        if *pos < src.len() && is_ps_white(src[*pos]) {
+            if src[*pos] == b'\r' && src.get(*pos + 1) == Some(&b'\n') {
+                *pos += 1;
+            }
+            *pos += 1;
+        }
+        tok
+    }
+
+    /* Execution */
+
+    fn lookup(&self, name: &[u8]) -> Option<Obj> {
+        let key = Key::Name(name.to_vec());
+        for d in self.dstack.iter().rev() {
+            if let Some(v) = d.borrow().get(&key) {
+                return Some(v.clone());
+            }
+        }
+        None
+    }
+
+    fn exec(&mut self, obj: Obj) -> PsResult {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(PsError::LimitCheck);
+        }
+        match obj {
+            Obj::ExecName(name) => {
+                let val = self.lookup(&name).ok_or_else(|| {
+                    PsError::Undefined(String::from_utf8_lossy(&name).into_owned())
+                })?;
+                match val {
+                    Obj::ExecName(_) | Obj::Op(_) | Obj::Proc(_) => self.exec(val),
+                    other => self.push(other),
+                }
+            }
+            Obj::Op(op) => {
+                let r = (op.func)(self);
+                if let Err(ref e) = r {
+                    match e {
+                        PsError::Exit | PsError::Stop | PsError::Quit | PsError::WidthDone => {}
+                        _ => {
+                            if self.failed_op.is_none() {
+                                self.failed_op = Some(op.name);
+                            }
+                        }
+                    }
+                }
+                r
+            }
+            Obj::Proc(arr) => self.run_proc(&arr),
+            other => self.push(other),
+        }
+    }
+
+    fn run_proc(&mut self, arr: &PsArr) -> PsResult {
+        if self.depth >= MAX_DEPTH {
+            return Err(PsError::LimitCheck);
+        }
+        self.depth += 1;
+        let mut r = Ok(());
+        for item in arr.items() {
+            r = match item {
+                Obj::Proc(_) => self.push(item),
+                _ => self.exec(item),
+            };
+            if r.is_err() {
+                break;
+            }
+        }
+        self.depth -= 1;
+        r
+    }
+
+    /// Runs a procedure operand, catching `exit`; for looping operators.
+    fn run_loop_body(&mut self, proc_: &PsArr) -> PsResult<bool> {
+        match self.run_proc(proc_) {
+            Ok(()) => Ok(true),
+            Err(PsError::Exit) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /* Operand stack helpers */
+
+    fn push(&mut self, o: Obj) -> PsResult {
+        if self.ostack.len() >= MAX_OSTACK {
+            return Err(PsError::LimitCheck);
+        }
+        self.ostack.push(o);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> PsResult<Obj> {
+        self.ostack.pop().ok_or(PsError::StackUnderflow)
+    }
+
+    fn peek(&self, i: usize) -> PsResult<&Obj> {
+        let len = self.ostack.len();
+        if i >= len {
+            return Err(PsError::StackUnderflow);
+        }
+        Ok(&self.ostack[len - 1 - i])
+    }
+
+    fn pop_num(&mut self) -> PsResult<f64> {
+        self.pop()?.num().ok_or(PsError::TypeCheck)
+    }
+
+    fn pop_nums(&mut self, n: usize) -> PsResult<Vec<f64>> {
+        let mut v = vec![0.; n];
+        for i in (0..n).rev() {
+            v[i] = self.pop_num()?;
+        }
+        Ok(v)
+    }
+
+    fn pop_int(&mut self) -> PsResult<i32> {
+        match self.pop()? {
+            Obj::Int(i) => Ok(i),
+            Obj::Real(r) if r.fract() == 0. && r.abs() <= i32::MAX as f64 => Ok(r as i32),
+            _ => Err(PsError::TypeCheck),
+        }
+    }
+
+    fn pop_bool(&mut self) -> PsResult<bool> {
+        match self.pop()? {
+            Obj::Bool(b) => Ok(b),
+            _ => Err(PsError::TypeCheck),
+        }
+    }
+
+    fn pop_proc(&mut self) -> PsResult<PsArr> {
+        match self.pop()? {
+            Obj::Proc(a) => Ok(a),
+            _ => Err(PsError::TypeCheck),
+        }
+    }
+
+    fn pop_array(&mut self) -> PsResult<PsArr> {
+        match self.pop()? {
+            Obj::Array(a) | Obj::Proc(a) => Ok(a),
+            _ => Err(PsError::TypeCheck),
+        }
+    }
+
+    fn pop_string(&mut self) -> PsResult<PsStr> {
+        match self.pop()? {
+            Obj::Str(s) => Ok(s),
+            _ => Err(PsError::TypeCheck),
+        }
+    }
+
+    fn pop_dict(&mut self) -> PsResult<DictRef> {
+        match self.pop()? {
+            Obj::Dict(d) => Ok(d),
+            _ => Err(PsError::TypeCheck),
+        }
+    }
+
+    fn pop_file(&mut self) -> PsResult<FileRef> {
+        match self.pop()? {
+            Obj::File(f) => Ok(f),
+            _ => Err(PsError::TypeCheck),
+        }
+    }
+
+    fn count_to_mark(&self) -> PsResult<usize> {
+        self.ostack
+            .iter()
+            .rev()
+            .position(|o| matches!(o, Obj::Mark))
+            .ok_or(PsError::UnmatchedMark)
+    }
+
+    /* Matrices */
+
+    fn array_to_matrix(a: &PsArr) -> PsResult<TMatrix> {
+        if a.len != 6 {
+            return Err(PsError::RangeCheck);
+        }
+        let mut v = [0.; 6];
+        for (i, x) in v.iter_mut().enumerate() {
+            *x = a.get(i)?.num().ok_or(PsError::TypeCheck)?;
+        }
+        Ok(TMatrix::row_major(v[0], v[1], v[2], v[3], v[4], v[5]))
+    }
+
+    fn store_matrix(a: &PsArr, m: &TMatrix) -> PsResult {
+        if a.len != 6 {
+            return Err(PsError::RangeCheck);
+        }
+        for (i, &v) in [m.m11, m.m12, m.m21, m.m22, m.m31, m.m32]
+            .iter()
+            .enumerate()
+        {
+            a.put(i, Obj::real(v))?;
+        }
+        Ok(())
+    }
+
+    fn matrix_obj(m: &TMatrix) -> Obj {
+        Obj::Array(PsArr::new(
+            [m.m11, m.m12, m.m21, m.m22, m.m31, m.m32]
+                .iter()
+                .map(|&v| Obj::real(v))
+                .collect(),
+        ))
+    }
+
+    fn pop_matrix(&mut self) -> PsResult<TMatrix> {
+        let a = self.pop_array()?;
+        Interp::array_to_matrix(&a)
+    }
+
+    fn ctm(&self) -> TMatrix {
+        if self.paint {
+            unsafe { pdf_dev_currentmatrix() }
+        } else {
+            TMatrix::identity()
+        }
+    }
+
+    fn concat(&mut self, m: &TMatrix) -> PsResult {
+        if self.paint && unsafe { pdf_dev_concat(m) } < 0 {
+            return Err(PsError::UndefinedResult);
+        }
+        Ok(())
+    }
+
+    /// Implements the optional trailing matrix operand of `translate`,
+    /// `scale` and `rotate`.
+    fn transform_op(&mut self, nargs: usize, make: fn(&[f64]) -> TMatrix) -> PsResult {
+        if let Obj::Array(_) = self.peek(0)? {
+            let a = self.pop_array()?;
+            let v = self.pop_nums(nargs)?;
+            Interp::store_matrix(&a, &make(&v))?;
+            return self.push(Obj::Array(a));
+        }
+        let v = self.pop_nums(nargs)?;
+        self.concat(&make(&v))
+    }
+
+    /// Pops the operands of `transform` and friends, returning the point and
+    /// the matrix to use.
+    fn pop_point_and_matrix(&mut self) -> PsResult<(f64, f64, TMatrix)> {
+        let m = if let Obj::Array(_) = self.peek(0)? {
+            self.pop_matrix()?
+        } else {
+            self.ctm()
+        };
+        let y = self.pop_num()?;
+        let x = self.pop_num()?;
+        Ok((x, y, m))
+    }
+
+    /* Graphics state */
+
+    fn gsave(&mut self) -> PsResult {
+        if self.gstack.len() > 256 {
+            return Err(PsError::LimitCheck);
+        }
+        let top = self.gs().clone();
+        self.gstack.push(top);
+        if self.paint {
+            unsafe {
+                pdf_dev_gsave();
+            }
+        }
+        Ok(())
+    }
+
+    fn grestore(&mut self) {
+        if self.gstack.len() > 1 {
+            self.gstack.pop();
+            if self.paint {
+                unsafe {
+                    pdf_dev_grestore();
+                }
+            }
+        }
+    }
+
+    fn current_point(&self) -> Coord {
+        let mut cp = Coord::zero();
+        if self.paint {
+            unsafe {
+                pdf_dev_currentpoint(&mut cp);
+            }
+        }
+        cp
+    }
+
+    fn set_color(&mut self, space: Option<ColorSpace>, color: Vec<f64>) -> PsResult {
+        if let Some(space) = space {
+            self.gs().space = space;
+        }
+        self.gs().color = color;
+        let space = self.gs().space.clone();
+        let color = self.gs().color.clone();
+        let pdf_color = self.device_color(&space, &color)?;
+        if self.paint {
+            unsafe {
+                pdf_dev_set_color(&pdf_color, 0, 0);
+                pdf_dev_set_color(&pdf_color, 0x20, 0);
+            }
+        }
+        Ok(())
+    }
+
+    fn device_color(&mut self, space: &ColorSpace, color: &[f64]) -> PsResult<PdfColor> {
+        let c = |i: usize| color.get(i).copied().unwrap_or(0.).max(0.).min(1.);
+        let r = match space {
+            ColorSpace::Gray => PdfColor::from_gray(c(0)),
+            ColorSpace::Rgb => PdfColor::from_rgb(c(0), c(1), c(2)),
+            ColorSpace::Cmyk => PdfColor::from_cmyk(c(0), c(1), c(2), c(3)),
+            ColorSpace::Indexed {
+                base,
+                hival,
+                lookup,
+            } => {
+                let n = base.ncomps();
+                let idx = (color.first().copied().unwrap_or(0.) as i32)
+                    .max(0)
+                    .min(*hival) as usize;
+                let comps = (0..n)
+                    .map(|i| lookup.get(idx * n + i).copied().unwrap_or(0) as f64 / 255.)
+                    .collect::<Vec<_>>();
+                return self.device_color(base, &comps);
+            }
+            ColorSpace::Separation { alt, tint } => {
+                self.push(Obj::real(c(0)))?;
+                self.exec(tint.clone())?;
+                let comps = self.pop_nums(alt.ncomps())?;
+                return self.device_color(alt, &comps);
+            }
+        };
+        r.map_err(|_| PsError::RangeCheck)
+    }
+
+    fn parse_color_space(&mut self, obj: &Obj) -> PsResult<ColorSpace> {
+        let (family, arr) = match obj {
+            Obj::Name(n) | Obj::ExecName(n) => (n.to_vec(), None),
+            Obj::Array(a) | Obj::Proc(a) => match a.get(0)? {
+                Obj::Name(n) | Obj::ExecName(n) => (n.to_vec(), Some(a.clone())),
+                _ => return Err(PsError::TypeCheck),
+            },
+            _ => return Err(PsError::TypeCheck),
+        };
+        match &family[..] {
+            b"DeviceGray" | b"CalGray" => Ok(ColorSpace::Gray),
+            b"DeviceRGB" | b"CalRGB" => Ok(ColorSpace::Rgb),
+            b"DeviceCMYK" => Ok(ColorSpace::Cmyk),
+            b"ICCBased" => {
+                let n = match arr.as_ref().map(|a| a.get(1)) {
+                    Some(Ok(Obj::Dict(d))) => d
+                        .borrow()
+                        .get(&Key::Name(b"N".to_vec()))
+                        .and_then(|n| n.num())
+                        .unwrap_or(0.),
+                    _ => 0.,
+                };
+                match n as i32 {
+                    1 => Ok(ColorSpace::Gray),
+                    3 => Ok(ColorSpace::Rgb),
+                    4 => Ok(ColorSpace::Cmyk),
+                    _ => Err(PsError::Unsupported("ICCBased".to_owned())),
+                }
+            }
+            b"Indexed" => {
+                let a = arr.ok_or(PsError::TypeCheck)?;
+                let base = self.parse_color_space(&a.get(1)?)?;
+                let hival = a.get(2)?.num().ok_or(PsError::TypeCheck)? as i32;
+                let lookup = match a.get(3)? {
+                    Obj::Str(s) => s.bytes(),
+                    Obj::Proc(p) => {
+                        /* Tabulate the lookup procedure once. */
+                        let n = base.ncomps();
+                        let mut table = Vec::with_capacity((hival as usize + 1) * n);
+                        for i in 0..=hival {
+                            self.push(Obj::Int(i))?;
+                            self.run_proc(&p)?;
+                            for v in self.pop_nums(n)? {
+                                table.push((v.max(0.).min(1.) * 255. + 0.5) as u8);
+                            }
+                        }
+                        table
+                    }
+                    _ => return Err(PsError::TypeCheck),
+                };
+                Ok(ColorSpace::Indexed {
+                    base: Box::new(base),
+                    hival,
+                    lookup,
+                })
+            }
+            b"Separation" => {
+                let a = arr.ok_or(PsError::TypeCheck)?;
+                let alt = self.parse_color_space(&a.get(2)?)?;
+                Ok(ColorSpace::Separation {
+                    alt: Box::new(alt),
+                    tint: a.get(3)?,
+                })
+            }
+            other => Err(PsError::Unsupported(
+                String::from_utf8_lossy(other).into_owned(),
+            )),
+        }
+    }
+
+    /* Files */
+
+    fn file_getc(&mut self, f: &FileRef) -> Option<u8> {
+        match f {
+            FileRef::Current => {
+                let c = self.src.get(self.pos).copied();
+                if c.is_some() {
+                    self.pos += 1;
+                }
+                c
+            }
+            FileRef::Data(d) => {
+                let mut d = d.borrow_mut();
+                let c = d.data.get(d.pos).copied();
+                if c.is_some() {
+                    d.pos += 1;
+                }
+                c
+            }
+            FileRef::Filter(ff) => loop {
+                let (kind, src) = {
+                    let mut ff = ff.borrow_mut();
+                    if ff.pos < ff.buf.len() {
+                        ff.pos += 1;
+                        return Some(ff.buf[ff.pos - 1]);
+                    }
+                    if ff.eod {
+                        return None;
+                    }
+                    (ff.kind, ff.src.clone())
+                };
+                let mut getc = || self.file_getc(&src);
+                let (chunk, eod) = match kind {
+                    FilterKind::AsciiHex => hex_chunk(&mut getc),
+                    FilterKind::Ascii85 => a85_chunk(&mut getc).unwrap_or((Vec::new(), true)),
+                    FilterKind::RunLength => runlength_chunk(&mut getc),
+                };
+                let mut ff = ff.borrow_mut();
+                ff.buf = chunk;
+                ff.pos = 0;
+                ff.eod = eod;
+            },
+        }
+    }
+
+    fn file_read(&mut self, f: &FileRef, n: usize) -> Vec<u8> {
+        if let FileRef::Current = f {
+            let end = self.pos.saturating_add(n).min(self.src.len());
+            let out = self.src[self.pos..end].to_vec();
+            self.pos = end;
+            return out;
+        }
+        let mut out = Vec::with_capacity(n.min(1 << 16));
+        while out.len() < n {
+            match self.file_getc(f) {
+                Some(c) => out.push(c),
+                None => break,
+            }
+        }
+        out
+    }
+
+    /// Collects all of the data available from a filter source.
+    fn read_all(&mut self, src: &Obj) -> PsResult<Vec<u8>> {
+        match src {
+            Obj::Str(s) => Ok(s.bytes()),
+            Obj::File(f) => Ok(self.file_read(f, usize::MAX)),
+            Obj::Proc(p) => {
+                let mut out = Vec::new();
+                loop {
+                    self.run_proc(p)?;
+                    let s = self.pop_string()?;
+                    if s.len == 0 {
+                        return Ok(out);
+                    }
+                    out.extend(s.bytes());
+                    if out.len() > 1 << 28 {
+                        return Err(PsError::LimitCheck);
+                    }
+                }
+            }
+            _ => Err(PsError::TypeCheck),
+        }
+    }
+
+    /// Reads `n` bytes of sample data from an image data source.
+    fn read_source(&mut self, src: &Obj, n: usize) -> PsResult<Vec<u8>> {
+        let mut out = Vec::with_capacity(n);
+        match src {
+            Obj::Str(s) => {
+                let b = s.bytes();
+                if b.is_empty() {
+                    return Err(PsError::RangeCheck);
+                }
+                while out.len() < n {
+                    let take = (n - out.len()).min(b.len());
+                    out.extend_from_slice(&b[..take]);
+                }
+            }
+            Obj::File(f) => out = self.file_read(f, n),
+            Obj::Proc(p) => {
+                while out.len() < n {
+                    self.run_proc(p)?;
+                    let s = self.pop_string()?;
+                    if s.len == 0 {
+                        break;
+                    }
+                    out.extend(s.bytes());
+                }
+                out.truncate(n);
+            }
+            _ => return Err(PsError::TypeCheck),
+        }
+        out.resize(n, 0);
+        Ok(out)
+    }
+
+    /* Fonts */
+
+    fn font_matrix(font: &DictRef) -> PsResult<TMatrix> {
+        match font.borrow().get(&Key::Name(b"FontMatrix".to_vec())) {
+            Some(Obj::Array(a)) | Some(Obj::Proc(a)) => Interp::array_to_matrix(a),
+            _ => Err(PsError::TypeCheck),
+        }
+    }
+
+    fn font_type(font: &DictRef) -> i32 {
+        font.borrow()
+            .get(&Key::Name(b"FontType".to_vec()))
+            .and_then(|t| t.num())
+            .unwrap_or(1.) as i32
+    }
+
+    fn current_font(&mut self) -> PsResult<DictRef> {
+        match self.gs().font.clone() {
+            Some(f) => Ok(f),
+            None => Err(PsError::Undefined("currentfont".to_owned())),
+        }
+    }
+
+    /// Copies a font dictionary with a transformed font matrix, for
+    /// `scalefont` and `makefont`.
+    fn transformed_font(font: &DictRef, m: &TMatrix) -> PsResult<DictRef> {
+        let fm = Interp::font_matrix(font)?;
+        let mut copy = font.borrow().clone();
+        copy.insert(
+            Key::Name(b"FontMatrix".to_vec()),
+            Interp::matrix_obj(&fm.post_transform(m)),
+        );
+        Ok(Rc::new(RefCell::new(copy)))
+    }
+
+    fn find_font(&mut self, key: &Obj) -> PsResult<DictRef> {
+        let k = key.key()?;
+        if let Some(Obj::Dict(d)) = self.font_directory.borrow().get(&k) {
+            return Ok(d.clone());
+        }
+        let name = match &k {
+            Key::Name(n) => n.clone(),
+            _ => return Err(PsError::TypeCheck),
+        };
+        /* Fonts that the figure doesn't define itself are resolved through
+         * the fontmap when they are first shown. */
+        let font = new_dict();
+        {
+            let mut f = font.borrow_mut();
+            f.insert(Key::Name(b"FontName".to_vec()), Obj::name(&name));
+            f.insert(Key::Name(b"FontType".to_vec()), Obj::Int(1));
+            f.insert(
+                Key::Name(b"FontMatrix".to_vec()),
+                Interp::matrix_obj(&TMatrix::row_major(0.001, 0., 0., 0.001, 0., 0.)),
+            );
+            f.insert(Key::Name(b"Encoding".to_vec()), ascii_encoding());
+        }
+        self.font_directory
+            .borrow_mut()
+            .insert(k, Obj::Dict(font.clone()));
+        Ok(font)
+    }
+
+    /// Locates a device font for a font dictionary that isn't a Type 3 font.
+    /// Returns the index into `resident_fonts`.
+    fn resident_font(&mut self, name: &[u8], size: f64) -> Option<usize> {
+        if let Some(i) = self
+            .resident_fonts
+            .iter()
+            .position(|f| f.name == name && f.size == size)
+        {
+            return Some(i);
+        }
+        if self.missing_fonts.iter().any(|n| n == name) {
+            return None;
+        }
+        unsafe {
+            let key = pdf_resolve_font_key(name);
+            let font_id = match key {
+                Some(ref key) => {
+                    let key = CString::new(key.clone()).ok()?;
+                    let font_id =
+                        pdf_dev_locate_font(key.as_ptr(), (size * dev_unit_dviunit()) as i32);
+                    if font_id >= 0 {
+                        Some((font_id, tfm_open(key.as_ptr(), 0)))
+                    } else {
+                        None
+                    }
+                }
+                None => None,
+            };
+            match font_id {
+                Some((font_id, tfm_id)) => {
+                    self.resident_fonts.push(ResidentFont {
+                        name: name.to_vec(),
+                        size,
+                        font_id,
+                        tfm_id,
+                    });
+                    Some(self.resident_fonts.len() - 1)
+                }
+                None => {
+                    warn!(
+                        "EPS: no font found for \"{}\"; text in it will be dropped.",
+                        String::from_utf8_lossy(name)
+                    );
+                    self.missing_fonts.push(name.to_vec());
+                    None
+                }
+            }
+        }
+    }
+
+    /// Splits a font matrix into a point size and the remaining
+    /// transformation, which is the identity for upright text.
+    fn split_font_matrix(fm: &TMatrix) -> (f64, TMatrix) {
+        let size = 1000. * (fm.m11 * fm.m22 - fm.m12 * fm.m21).abs().sqrt();
+        if size == 0. {
+            return (0., TMatrix::identity());
+        }
+        let s = 1000. / size;
+        (
+            size,
+            TMatrix::row_major(fm.m11 * s, fm.m12 * s, fm.m21 * s, fm.m22 * s, 0., 0.),
+        )
+    }
+
+    fn is_identity(m: &TMatrix) -> bool {
+        (m.m11 - 1.).abs() < 1e-9
+            && m.m12.abs() < 1e-9
+            && m.m21.abs() < 1e-9
+            && (m.m22 - 1.).abs() < 1e-9
+    }
+
+    /// Shows (or, if `paint` is off, measures) a string in a resident font,
+    /// returning the advance in user space.
+    fn show_resident(&mut self, font: &DictRef, s: &[u8]) -> PsResult<(f64, f64)> {
+        let fm = Interp::font_matrix(font)?;
+        let name = match font.borrow().get(&Key::Name(b"FontName".to_vec())) {
+            Some(Obj::Name(n)) | Some(Obj::ExecName(n)) => n.to_vec(),
+            Some(Obj::Str(n)) => n.bytes(),
+            _ => return Err(PsError::TypeCheck),
+        };
+        let (size, rest) = Interp::split_font_matrix(&fm);
+        if size == 0. || s.is_empty() {
+            return Ok((0., 0.));
+        }
+        let idx = match unsafe { self.resident_font_checked(&name, size) } {
+            Some(idx) => idx,
+            None => return Ok((0., 0.)),
+        };
+        let (font_id, tfm_id) = {
+            let f = &self.resident_fonts[idx];
+            (f.font_id, f.tfm_id)
+        };
+        let width = if tfm_id >= 0 {
+            let fw = unsafe { tfm_string_width(tfm_id, s.as_ptr(), s.len() as u32) };
+            fw as f64 / (1 << 20) as f64 * size
+        } else {
+            0.
+        };
+        let vertical = unsafe { pdf_dev_get_font_wmode(font_id) } != 0;
+        let adv = if vertical {
+            rest.transform_vector(euclid::vec2(0., -width))
+        } else {
+            rest.transform_vector(euclid::vec2(width, 0.))
+        };
+        if self.paint {
+            let cp = self.current_point();
+            let upright = Interp::is_identity(&rest);
+            unsafe {
+                if !upright {
+                    pdf_dev_gsave();
+                    pdf_dev_concat(&TMatrix::create_translation(cp.x, cp.y));
+                    pdf_dev_concat(&rest);
+                }
+                let (x, y) = if upright { (cp.x, cp.y) } else { (0., 0.) };
+                pdf_dev_set_string(
+                    (x * dev_unit_dviunit()) as i32,
+                    (y * dev_unit_dviunit()) as i32,
+                    s.as_ptr() as *const libc::c_void,
+                    s.len() as _,
+                    (width * dev_unit_dviunit()) as i32,
+                    font_id,
+                    0,
+                );
+                graphics_mode();
+                if !upright {
+                    pdf_dev_grestore();
+                }
+                pdf_dev_moveto(cp.x + adv.x, cp.y + adv.y);
+            }
+        }
+        Ok((adv.x, adv.y))
+    }
+
+    unsafe fn resident_font_checked(&mut self, name: &[u8], size: f64) -> Option<usize> {
+        if !self.paint {
+            /* Measuring only: look the font up without creating it. */
+            if let Some(i) = self
+                .resident_fonts
+                .iter()
+                .position(|f| f.name == name && f.size == size)
+            {
+                return Some(i);
+            }
+        }
+        self.resident_font(name, size)
+    }
+
+    /// Shows (or measures) one glyph of a Type 3 font by running its
+    /// `BuildGlyph` or `BuildChar` procedure, returning the advance in user
+    /// space.
+    fn show_type3_glyph(&mut self, font: &DictRef, glyph: GlyphSel) -> PsResult<(f64, f64)> {
+        let fm = Interp::font_matrix(font)?;
+        let (build_glyph, build_char, encoding) = {
+            let f = font.borrow();
+            (
+                f.get(&Key::Name(b"BuildGlyph".to_vec())).cloned(),
+                f.get(&Key::Name(b"BuildChar".to_vec())).cloned(),
+                f.get(&Key::Name(b"Encoding".to_vec())).cloned(),
+            )
+        };
+        let glyph_name = |code: u8| -> PsResult<Obj> {
+            match &encoding {
+                Some(Obj::Array(e)) | Some(Obj::Proc(e)) => e.get(code as usize),
+                _ => Err(PsError::TypeCheck),
+            }
+        };
+        let (arg, proc_) = match (glyph, build_glyph, build_char) {
+            (GlyphSel::Code(c), Some(bg), _) => (glyph_name(c)?, bg),
+            (GlyphSel::Name(n), Some(bg), _) => (Obj::Name(n), bg),
+            (GlyphSel::Code(c), None, Some(bc)) => (Obj::Int(c as i32), bc),
+            (GlyphSel::Name(n), None, Some(bc)) => {
+                let code = match &encoding {
+                    Some(Obj::Array(e)) | Some(Obj::Proc(e)) => e
+                        .items()
+                        .iter()
+                        .position(|o| ps_eq(o, &Obj::Name(n.clone()))),
+                    _ => None,
+                };
+                match code {
+                    Some(c) => (Obj::Int(c as i32), bc),
+                    None => return Ok((0., 0.)),
+                }
+            }
+            _ => return Err(PsError::TypeCheck),
+        };
+        let cp = self.current_point();
+        let ostack_len = self.ostack.len();
+        let dstack_len = self.dstack.len();
+        let gstack_len = self.gstack.len();
+        self.gsave()?;
+        self.char_width = None;
+        let mut r = self.concat(&TMatrix::create_translation(cp.x, cp.y));
+        if r.is_ok() {
+            r = self.concat(&fm);
+        }
+        if r.is_ok() {
+            if self.paint {
+                unsafe {
+                    pdf_dev_newpath();
+                }
+            }
+            r = self
+                .push(Obj::Dict(font.clone()))
+                .and_then(|_| self.push(arg))
+                .and_then(|_| self.exec(proc_));
+        }
+        let r = match r {
+            Err(PsError::WidthDone) => {
+                self.ostack.truncate(ostack_len);
+                self.dstack.truncate(dstack_len.max(2));
+                Ok(())
+            }
+            r => r,
+        };
+        while self.gstack.len() > gstack_len {
+            self.grestore();
+        }
+        r?;
+        let (wx, wy) = self.char_width.unwrap_or((0., 0.));
+        let adv = fm.transform_vector(euclid::vec2(wx, wy));
+        if self.paint {
+            unsafe {
+                pdf_dev_moveto(cp.x + adv.x, cp.y + adv.y);
+            }
+        }
+        Ok((adv.x, adv.y))
+    }
+
+    fn show_string(&mut self, s: &[u8]) -> PsResult<(f64, f64)> {
+        let font = self.current_font()?;
+        if Interp::font_type(&font) == 3 {
+            let mut total = (0., 0.);
+            for &c in s {
+                let (dx, dy) = self.show_type3_glyph(&font, GlyphSel::Code(c))?;
+                total.0 += dx;
+                total.1 += dy;
+            }
+            Ok(total)
+        } else {
+            self.show_resident(&font, s)
+        }
+    }
+
+    /* Images */
+
+    /// Emits sampled image data as an image XObject and paints it with the
+    /// given image matrix.
+    #[allow(clippy::too_many_arguments)]
+    fn paint_image(
+        &mut self,
+        width: i32,
+        height: i32,
+        bpc: i32,
+        space: Option<&ColorSpace>,
+        decode: Option<Vec<f64>>,
+        imat: &TMatrix,
+        data: &[u8],
+    ) -> PsResult {
+        if !self.paint || width <= 0 || height <= 0 {
+            return Ok(());
+        }
+        let inv = imat.inverse().ok_or(PsError::UndefinedResult)?;
+        unsafe {
+            let stream = pdf_new_stream(STREAM_COMPRESS);
+            let dict = (*stream).as_stream_mut().get_dict_mut();
+            match space {
+                Some(space) => {
+                    pdf_add_dict(dict, "ColorSpace", self.pdf_color_space(space)?);
+                }
+                None => {
+                    pdf_add_dict(dict, "ImageMask", pdf_new_boolean(1));
+                }
+            }
+            if let Some(decode) = decode {
+                let arr = pdf_new_array();
+                for v in decode {
+                    pdf_add_array(&mut *arr, pdf_new_number(v));
+                }
+                pdf_add_dict(dict, "Decode", arr);
+            }
+            pdf_add_stream(
+                &mut *stream,
+                data.as_ptr() as *const libc::c_void,
+                data.len() as i32,
+            );
+            let mut info = ximage_info::default();
+            pdf_ximage_init_image_info(&mut info);
+            info.width = width;
+            info.height = height;
+            info.bits_per_component = bpc;
+            info.num_components = space.map(ColorSpace::ncomps).unwrap_or(1) as i32;
+            let id = pdf_ximage_defineresource(ptr::null(), XInfo::Image(Box::new(info)), stream);
+            /* Map the unit square onto image space, then into user space. */
+            let unit =
+                TMatrix::row_major(width as f64, 0., 0., -(height as f64), 0., height as f64);
+            pdf_dev_gsave();
+            pdf_dev_concat(&unit.post_transform(&inv));
+            let resname = pdf_ximage_get_resname(id);
+            let mut content = b" /".to_vec();
+            content.extend_from_slice(std::ffi::CStr::from_ptr(resname).to_bytes());
+            content.extend_from_slice(b" Do");
+            pdf_doc_add_page_content(&content);
+            pdf_dev_grestore();
+            pdf_doc_add_page_resource("XObject", resname, pdf_ximage_get_reference(id));
+        }
+        Ok(())
+    }
+
+    unsafe fn pdf_color_space(&mut self, space: &ColorSpace) -> PsResult<*mut pdf_obj> {
+        Ok(match space {
+            ColorSpace::Gray => pdf_new_name("DeviceGray"),
+            ColorSpace::Rgb => pdf_new_name("DeviceRGB"),
+            ColorSpace::Cmyk => pdf_new_name("DeviceCMYK"),
+            ColorSpace::Indexed {
+                base,
+                hival,
+                lookup,
+            } => {
+                let arr = pdf_new_array();
+                pdf_add_array(&mut *arr, pdf_new_name("Indexed"));
+                pdf_add_array(&mut *arr, self.pdf_color_space(base)?);
+                pdf_add_array(&mut *arr, pdf_new_number(*hival as f64));
+                pdf_add_array(
+                    &mut *arr,
+                    pdf_new_string(lookup.as_ptr() as *const libc::c_void, lookup.len() as _),
+                );
+                arr
+            }
+            ColorSpace::Separation { .. } => {
+                return Err(PsError::Unsupported("Separation images".to_owned()))
+            }
+        })
+    }
+
+    fn image_common(&mut self, mask: bool) -> PsResult {
+        if let Obj::Dict(_) = self.peek(0)? {
+            let d = self.pop_dict()?;
+            let d = d.borrow();
+            let get = |k: &[u8]| d.get(&Key::Name(k.to_vec())).cloned();
+            let num = |k: &[u8]| -> PsResult<i32> {
+                get(k)
+                    .and_then(|o| o.num())
+                    .map(|v| v as i32)
+                    .ok_or(PsError::TypeCheck)
+            };
+            if get(b"ImageType").and_then(|o| o.num()).unwrap_or(1.) as i32 != 1 {
+                return Err(PsError::Unsupported("ImageType".to_owned()));
+            }
+            if let Some(Obj::Bool(true)) = get(b"MultipleDataSources") {
+                return Err(PsError::Unsupported("MultipleDataSources".to_owned()));
+            }
+            let width = num(b"Width")?;
+            let height = num(b"Height")?;
+            let bpc = if mask { 1 } else { num(b"BitsPerComponent")? };
+            let imat = match get(b"ImageMatrix") {
+                Some(Obj::Array(a)) | Some(Obj::Proc(a)) => Interp::array_to_matrix(&a)?,
+                _ => return Err(PsError::TypeCheck),
+            };
+            let decode = match get(b"Decode") {
+                Some(Obj::Array(a)) | Some(Obj::Proc(a)) => Some(
+                    a.items()
+                        .iter()
+                        .map(|o| o.num().ok_or(PsError::TypeCheck))
+                        .collect::<PsResult<Vec<f64>>>()?,
+                ),
+                _ => None,
+            };
+            let src = get(b"DataSource").ok_or(PsError::TypeCheck)?;
+            drop(d);
+            let space = self.gs().space.clone();
+            let ncomps = if mask { 1 } else { space.ncomps() };
+            let rowbytes = (width as usize * bpc as usize * ncomps + 7) / 8;
+            let data = self.read_source(&src, rowbytes * height as usize)?;
+            let space = if mask { None } else { Some(&space) };
+            return self.paint_image(width, height, bpc, space, decode, &imat, &data);
+        }
+        let src = self.pop()?;
+        let imat = self.pop_matrix()?;
+        let (bpc, decode) = if mask {
+            let polarity = self.pop_bool()?;
+            (1, if polarity { Some(vec![1., 0.]) } else { None })
+        } else {
+            (self.pop_int()?, None)
+        };
+        let height = self.pop_int()?;
+        let width = self.pop_int()?;
+        let rowbytes = (width.max(0) as usize * bpc.max(0) as usize + 7) / 8;
+        let data = self.read_source(&src, rowbytes * height.max(0) as usize)?;
+        let space = if mask { None } else { Some(&ColorSpace::Gray) };
+        self.paint_image(width, height, bpc, space, decode, &imat, &data)
+    }
+}
+
+/*
+ * Operators
+ */
+
+fn op_pop(it: &mut Interp) -> PsResult {
+    it.pop().map(|_| ())
+}
+
+fn op_exch(it: &mut Interp) -> PsResult {
+    let b = it.pop()?;
+    let a = it.pop()?;
+    it.push(b)?;
+    it.push(a)
+}
+
+fn op_dup(it: &mut Interp) -> PsResult {
+    let a = it.peek(0)?.clone();
+    it.push(a)
+}
+
+fn op_copy(it: &mut Interp) -> PsResult {
+    match it.pop()? {
+        Obj::Int(n) => {
+            if n < 0 {
+                return Err(PsError::RangeCheck);
+            }
+            let n = n as usize;
+            let len = it.ostack.len();
+            if n > len {
+                return Err(PsError::StackUnderflow);
+            }
+            for i in len - n..len {
+                let o = it.ostack[i].clone();
+                it.push(o)?;
+            }
+            Ok(())
+        }
+        Obj::Array(dst) | Obj::Proc(dst) => {
+            let src = it.pop_array()?;
+            if src.len > dst.len {
+                return Err(PsError::RangeCheck);
+            }
+            for (i, o) in src.items().into_iter().enumerate() {
+                dst.put(i, o)?;
+            }
+            it.push(Obj::Array(dst.sub(0, src.len)?))
+        }
+        Obj::Str(dst) => {
+            let src = it.pop_string()?;
+            if src.len > dst.len {
+                return Err(PsError::RangeCheck);
+            }
+            let bytes = src.bytes();
+            dst.buf.borrow_mut()[dst.start..dst.start + bytes.len()].copy_from_slice(&bytes);
+            it.push(Obj::Str(dst.sub(0, src.len)?))
+        }
+        Obj::Dict(dst) => {
+            let src = it.pop_dict()?;
+            let entries = src.borrow().clone();
+            dst.borrow_mut().extend(entries);
+            it.push(Obj::Dict(dst))
+        }
+        _ => Err(PsError::TypeCheck),
+    }
+}
+
+fn op_index(it: &mut Interp) -> PsResult {
+    let n = it.pop_int()?;
+    if n < 0 {
+        return Err(PsError::RangeCheck);
+    }
+    let o = it.peek(n as usize)?.clone();
+    it.push(o)
+}
+
+fn op_roll(it: &mut Interp) -> PsResult {
+    let j = it.pop_int()?;
+    let n = it.pop_int()?;
+    if n < 0 {
+        return Err(PsError::RangeCheck);
+    }
+    let n = n as usize;
+    let len = it.ostack.len();
+    if n > len {
+        return Err(PsError::StackUnderflow);
+    }
+    if n == 0 {
+        return Ok(());
+    }
+    let j = j.rem_euclid(n as i32) as usize;
+    it.ostack[len - n..].rotate_right(j);
+    Ok(())
+}
+
+fn op_clear(it: &mut Interp) -> PsResult {
+    it.ostack.clear();
+    Ok(())
+}
+
+fn op_count(it: &mut Interp) -> PsResult {
+    let n = it.ostack.len() as i32;
+    it.push(Obj::Int(n))
+}
+
+fn op_mark(it: &mut Interp) -> PsResult {
+    it.push(Obj::Mark)
+}
+
+fn op_cleartomark(it: &mut Interp) -> PsResult {
+    let n = it.count_to_mark()?;
+    let len = it.ostack.len();
+    it.ostack.truncate(len - n - 1);
+    Ok(())
+}
+
+fn op_counttomark(it: &mut Interp) -> PsResult {
+    let n = it.count_to_mark()? as i32;
+    it.push(Obj::Int(n))
+}
+
+fn arith(
+    it: &mut Interp,
+    int_op: fn(i32, i32) -> Option<i32>,
+    real_op: fn(f64, f64) -> f64,
+) -> PsResult {
+    let b = it.pop()?;
+    let a = it.pop()?;
+    match (&a, &b) {
+        (Obj::Int(x), Obj::Int(y)) => match int_op(*x, *y) {
+            Some(v) => it.push(Obj::Int(v)),
+            None => it.push(Obj::real(real_op(*x as f64, *y as f64))),
+        },
+        _ => {
+            let x = a.num().ok_or(PsError::TypeCheck)?;
+            let y = b.num().ok_or(PsError::TypeCheck)?;
+            it.push(Obj::real(real_op(x, y)))
+        }
+    }
+}
+
+fn op_add(it: &mut Interp) -> PsResult {
+    arith(it, i32::checked_add, |x, y| x + y)
+}
+
+fn op_sub(it: &mut Interp) -> PsResult {
+    arith(it, i32::checked_sub, |x, y| x - y)
+}
+
+fn op_mul(it: &mut Interp) -> PsResult {
+    arith(it, i32::checked_mul, |x, y| x * y)
+}
+
+fn op_div(it: &mut Interp) -> PsResult {
+    let b = it.pop_num()?;
+    let a = it.pop_num()?;
+    if b == 0. {
+        return Err(PsError::UndefinedResult);
+    }
+    it.push(Obj::real(a / b))
+}
+
+fn op_idiv(it: &mut Interp) -> PsResult {
+    let b = it.pop_int()?;
+    let a = it.pop_int()?;
+    it.push(Obj::Int(a.checked_div(b).ok_or(PsError::UndefinedResult)?))
+}
+
+fn op_mod(it: &mut Interp) -> PsResult {
+    let b = it.pop_int()?;
+    let a = it.pop_int()?;
+    it.push(Obj::Int(a.checked_rem(b).ok_or(PsError::UndefinedResult)?))
+}
+
+fn unary(it: &mut Interp, int_op: fn(i32) -> Option<i32>, real_op: fn(f64) -> f64) -> PsResult {
+    match it.pop()? {
+        Obj::Int(x) => match int_op(x) {
+            Some(v) => it.push(Obj::Int(v)),
+            None => it.push(Obj::real(real_op(x as f64))),
+        },
+        Obj::Real(x) => it.push(Obj::real(real_op(x))),
+        _ => Err(PsError::TypeCheck),
+    }
+}
+
+fn op_neg(it: &mut Interp) -> PsResult {
+    unary(it, i32::checked_neg, |x| -x)
+}
+
+fn op_abs(it: &mut Interp) -> PsResult {
+    unary(it, i32::checked_abs, f64::abs)
+}
+
+fn op_ceiling(it: &mut Interp) -> PsResult {
+    unary(it, Some, f64::ceil)
+}
+
+fn op_floor(it: &mut Interp) -> PsResult {
+    unary(it, Some, f64::floor)
+}
+
+fn op_round(it: &mut Interp) -> PsResult {
+    unary(it, Some, |x| (x + 0.5).floor())
+}
+
+fn op_truncate(it: &mut Interp) -> PsResult {
+    unary(it, Some, f64::trunc)
+}
+
+fn real_fn(it: &mut Interp, f: fn(f64) -> f64) -> PsResult {
+    let x = it.pop_num()?;
+    let v = f(x);
+    if !v.is_finite() {
+        return Err(PsError::UndefinedResult);
+    }
+    it.push(Obj::real(v))
+}
+
+fn op_sqrt(it: &mut Interp) -> PsResult {
+    real_fn(it, f64::sqrt)
+}
+
+fn op_sin(it: &mut Interp) -> PsResult {
+    real_fn(it, |x| x.to_radians().sin())
+}
+
+fn op_cos(it: &mut Interp) -> PsResult {
+    real_fn(it, |x| x.to_radians().cos())
+}
+
+fn op_ln(it: &mut Interp) -> PsResult {
+    real_fn(it, f64::ln)
+}
+
+fn op_log(it: &mut Interp) -> PsResult {
+    real_fn(it, f64::log10)
+}
+
+fn op_atan(it: &mut Interp) -> PsResult {
+    let den = it.pop_num()?;
+    let num = it.pop_num()?;
+    if num == 0. && den == 0. {
+        return Err(PsError::UndefinedResult);
+    }
+    let a = num.atan2(den).to_degrees();
+    it.push(Obj::real(if a < 0. { a + 360. } else { a }))
+}
+
+fn op_exp(it: &mut Interp) -> PsResult {
+    let e = it.pop_num()?;
+    let b = it.pop_num()?;
+    let v = b.powf(e);
+    if !v.is_finite() {
+        return Err(PsError::UndefinedResult);
+    }
+    it.push(Obj::real(v))
+}
+
+fn op_cvi(it: &mut Interp) -> PsResult {
+    let v = match it.pop()? {
+        Obj::Str(s) => parse_number(&s.bytes())
+            .and_then(|o| o.num())
+            .ok_or(PsError::TypeCheck)?,
+        o => o.num().ok_or(PsError::TypeCheck)?,
+    };
+    if v.abs() > i32::MAX as f64 {
+        return Err(PsError::RangeCheck);
+    }
+    it.push(Obj::Int(v as i32))
+}
+
+fn op_cvr(it: &mut Interp) -> PsResult {
+    let v = match it.pop()? {
+        Obj::Str(s) => parse_number(&s.bytes())
+            .and_then(|o| o.num())
+            .ok_or(PsError::TypeCheck)?,
+        o => o.num().ok_or(PsError::TypeCheck)?,
+    };
+    it.push(Obj::real(v))
+}
+
+fn op_rand(it: &mut Interp) -> PsResult {
+    it.rand_state = it
+        .rand_state
+        .wrapping_mul(1_103_515_245)
+        .wrapping_add(12345);
+    let v = (it.rand_state >> 1) as i32;
+    it.push(Obj::Int(v))
+}
+
+fn op_srand(it: &mut Interp) -> PsResult {
+    it.rand_state = it.pop_int()? as u32;
+    Ok(())
+}
+
+fn op_rrand(it: &mut Interp) -> PsResult {
+    let v = it.rand_state as i32;
+    it.push(Obj::Int(v))
+}
+
+fn op_eq(it: &mut Interp) -> PsResult {
+    let b = it.pop()?;
+    let a = it.pop()?;
+    it.push(Obj::Bool(ps_eq(&a, &b)))
+}
+
+fn op_ne(it: &mut Interp) -> PsResult {
+    let b = it.pop()?;
+    let a = it.pop()?;
+    it.push(Obj::Bool(!ps_eq(&a, &b)))
+}
+
+fn compare(it: &mut Interp, f: fn(std::cmp::Ordering) -> bool) -> PsResult {
+    let b = it.pop()?;
+    let a = it.pop()?;
+    let ord = match (&a, &b) {
+        (Obj::Str(x), Obj::Str(y)) => x.bytes().cmp(&y.bytes()),
+        _ => {
+            let x = a.num().ok_or(PsError::TypeCheck)?;
+            let y = b.num().ok_or(PsError::TypeCheck)?;
+            x.partial_cmp(&y).ok_or(PsError::UndefinedResult)?
+        }
+    };
+    it.push(Obj::Bool(f(ord)))
+}
+
+fn op_gt(it: &mut Interp) -> PsResult {
+    compare(it, |o| o == std::cmp::Ordering::Greater)
+}
+
+fn op_ge(it: &mut Interp) -> PsResult {
+    compare(it, |o| o != std::cmp::Ordering::Less)
+}
+
+fn op_lt(it: &mut Interp) -> PsResult {
+    compare(it, |o| o == std::cmp::Ordering::Less)
+}
+
+fn op_le(it: &mut Interp) -> PsResult {
+    compare(it, |o| o != std::cmp::Ordering::Greater)
+}
+
+fn logic(
+    it: &mut Interp,
+    bool_op: fn(bool, bool) -> bool,
+    int_op: fn(i32, i32) -> i32,
+) -> PsResult {
+    let b = it.pop()?;
+    let a = it.pop()?;
+    match (a, b) {
+        (Obj::Bool(x), Obj::Bool(y)) => it.push(Obj::Bool(bool_op(x, y))),
+        (Obj::Int(x), Obj::Int(y)) => it.push(Obj::Int(int_op(x, y))),
+        _ => Err(PsError::TypeCheck),
+    }
+}
+
+fn op_and(it: &mut Interp) -> PsResult {
+    logic(it, |x, y| x && y, |x, y| x & y)
+}
+
+fn op_or(it: &mut Interp) -> PsResult {
+    logic(it, |x, y| x || y, |x, y| x | y)
+}
+
+fn op_xor(it: &mut Interp) -> PsResult {
+    logic(it, |x, y| x ^ y, |x, y| x ^ y)
+}
+
+fn op_not(it: &mut Interp) -> PsResult {
+    match it.pop()? {
+        Obj::Bool(b) => it.push(Obj::Bool(!b)),
+        Obj::Int(i) => it.push(Obj::Int(!i)),
+        _ => Err(PsError::TypeCheck),
+    }
+}
+
+fn op_bitshift(it: &mut Interp) -> PsResult {
+    let shift = it.pop_int()?;
+    let v = it.pop_int()? as u32;
+    let r = if shift >= 32 || shift <= -32 {
+        0
+    } else if shift >= 0 {
+        v << shift
+    } else {
+        v >> -shift
+    };
+    it.push(Obj::Int(r as i32))
+}
+
+fn op_exec(it: &mut Interp) -> PsResult {
+    match it.pop()? {
+        Obj::Str(s) => {
+            let src = s.bytes();
+            let mut pos = 0;
+            let mut items = Vec::new();
+            while let Some(tok) = it.scan(&src, &mut pos)? {
+                items.push(tok);
+            }
+            it.run_proc(&PsArr::new(items))
+        }
+        o => it.exec(o),
+    }
+}
+
+fn op_if(it: &mut Interp) -> PsResult {
+    let p = it.pop_proc()?;
+    if it.pop_bool()? {
+        it.run_proc(&p)
+    } else {
+        Ok(())
+    }
+}
+
+fn op_ifelse(it: &mut Interp) -> PsResult {
+    let p2 = it.pop_proc()?;
+    let p1 = it.pop_proc()?;
+    if it.pop_bool()? {
+        it.run_proc(&p1)
+    } else {
+        it.run_proc(&p2)
+    }
+}
+
+fn op_for(it: &mut Interp) -> PsResult {
+    let p = it.pop_proc()?;
+    let limit = it.pop()?;
+    let inc = it.pop()?;
+    let init = it.pop()?;
+    if let (Obj::Int(i0), Obj::Int(di), Obj::Int(lim)) = (&init, &inc, &limit) {
+        let (mut i, di, lim) = (*i0 as i64, *di as i64, *lim as i64);
+        while (di >= 0 && i <= lim) || (di < 0 && i >= lim) {
+            it.push(Obj::Int(i as i32))?;
+            if !it.run_loop_body(&p)? {
+                break;
+            }
+            i += di;
+            if di == 0 {
+                it.steps += 1;
+                if it.steps > MAX_STEPS {
+                    return Err(PsError::LimitCheck);
+                }
+            }
+        }
+        return Ok(());
+    }
+    let mut x = init.num().ok_or(PsError::TypeCheck)?;
+    let dx = inc.num().ok_or(PsError::TypeCheck)?;
+    let lim = limit.num().ok_or(PsError::TypeCheck)?;
+    while (dx >= 0. && x <= lim) || (dx < 0. && x >= lim) {
+        it.push(Obj::real(x))?;
+        if !it.run_loop_body(&p)? {
+            break;
+        }
+        x += dx;
+        if dx == 0. {
+            it.steps += 1;
+            if it.steps > MAX_STEPS {
+                return Err(PsError::LimitCheck);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn op_repeat(it: &mut Interp) -> PsResult {
+    let p = it.pop_proc()?;
+    let n = it.pop_int()?;
+    if n < 0 {
+        return Err(PsError::RangeCheck);
+    }
+    for _ in 0..n {
+        if !it.run_loop_body(&p)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn op_loop(it: &mut Interp) -> PsResult {
+    let p = it.pop_proc()?;
+    loop {
+        it.steps += 1;
+        if it.steps > MAX_STEPS {
+            return Err(PsError::LimitCheck);
+        }
+        if !it.run_loop_body(&p)? {
+            return Ok(());
+        }
+    }
+}
+
+fn op_exit(_it: &mut Interp) -> PsResult {
+    Err(PsError::Exit)
+}
+
+fn op_stop(_it: &mut Interp) -> PsResult {
+    Err(PsError::Stop)
+}
+
+fn op_quit(_it: &mut Interp) -> PsResult {
+    Err(PsError::Quit)
+}
+
+fn op_stopped(it: &mut Interp) -> PsResult {
+    let o = it.pop()?;
+    let failed_op = it.failed_op;
+    match it.exec(o) {
+        Ok(()) => it.push(Obj::Bool(false)),
+        Err(e @ PsError::Exit) | Err(e @ PsError::Quit) | Err(e @ PsError::WidthDone) => Err(e),
+        Err(_) => {
+            /* An error caught by `stopped` isn't an error of the figure. */
+            it.failed_op = failed_op;
+            it.push(Obj::Bool(true))
+        }
+    }
+}
+
+fn op_forall(it: &mut Interp) -> PsResult {
+    let p = it.pop_proc()?;
+    match it.pop()? {
+        Obj::Array(a) | Obj::Proc(a) => {
+            for o in a.items() {
+                it.push(o)?;
+                if !it.run_loop_body(&p)? {
+                    break;
+                }
+            }
+        }
+        Obj::Str(s) => {
+            for b in s.bytes() {
+                it.push(Obj::Int(b as i32))?;
+                if !it.run_loop_body(&p)? {
+                    break;
+                }
+            }
+        }
+        Obj::Dict(d) => {
+            let entries: Vec<(Key, Obj)> = d
+                .borrow()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect();
+            for (k, v) in entries {
+                it.push(Obj::from_key(&k))?;
+                it.push(v)?;
+                if !it.run_loop_body(&p)? {
+                    break;
+                }
+            }
+        }
+        _ => return Err(PsError::TypeCheck),
+    }
+    Ok(())
+}
+
+fn op_dict(it: &mut Interp) -> PsResult {
+    if it.pop_int()? < 0 {
+        return Err(PsError::RangeCheck);
+    }
+    it.push(Obj::Dict(new_dict()))
+}
+
+fn op_begin(it: &mut Interp) -> PsResult {
+    let d = it.pop_dict()?;
+    if it.dstack.len() >= 256 {
+        return Err(PsError::LimitCheck);
+    }
+    it.dstack.push(d);
+    Ok(())
+}
+
+fn op_end(it: &mut Interp) -> PsResult {
+    if it.dstack.len() <= 2 {
+        return Err(PsError::Unsupported("dictstackunderflow".to_owned()));
+    }
+    it.dstack.pop();
+    Ok(())
+}
+
+fn op_def(it: &mut Interp) -> PsResult {
+    let v = it.pop()?;
+    let k = it.pop()?.key()?;
+    it.dstack.last().unwrap().borrow_mut().insert(k, v);
+    Ok(())
+}
+
+fn op_load(it: &mut Interp) -> PsResult {
+    let k = it.pop()?;
+    let key = k.key()?;
+    let found = it
+        .dstack
+        .iter()
+        .rev()
+        .find_map(|d| d.borrow().get(&key).cloned());
+    if let Some(v) = found {
+        return it.push(v);
+    }
+    Err(PsError::Undefined(match key {
+        Key::Name(n) => String::from_utf8_lossy(&n).into_owned(),
+        _ => "?".to_owned(),
+    }))
+}
+
+fn op_store(it: &mut Interp) -> PsResult {
+    let v = it.pop()?;
+    let key = it.pop()?.key()?;
+    for d in it.dstack.iter().rev() {
+        if d.borrow().contains_key(&key) {
+            d.borrow_mut().insert(key, v);
+            return Ok(());
+        }
+    }
+    it.dstack.last().unwrap().borrow_mut().insert(key, v);
+    Ok(())
+}
+
+fn op_known(it: &mut Interp) -> PsResult {
+    let key = it.pop()?.key()?;
+    let d = it.pop_dict()?;
+    let known = d.borrow().contains_key(&key);
+    it.push(Obj::Bool(known))
+}
+
+fn op_where(it: &mut Interp) -> PsResult {
+    let key = it.pop()?.key()?;
+    let found = it
+        .dstack
+        .iter()
+        .rev()
+        .find(|d| d.borrow().contains_key(&key))
+        .cloned();
+    match found {
+        Some(d) => {
+            it.push(Obj::Dict(d))?;
+            it.push(Obj::Bool(true))
+        }
+        None => it.push(Obj::Bool(false)),
+    }
+}
+
+fn op_undef(it: &mut Interp) -> PsResult {
+    let key = it.pop()?.key()?;
+    let d = it.pop_dict()?;
+    d.borrow_mut().remove(&key);
+    Ok(())
+}
+
+fn op_currentdict(it: &mut Interp) -> PsResult {
+    let d = it.dstack.last().unwrap().clone();
+    it.push(Obj::Dict(d))
+}
+
+fn op_countdictstack(it: &mut Interp) -> PsResult {
+    let n = it.dstack.len() as i32;
+    it.push(Obj::Int(n))
+}
+
+fn op_cleardictstack(it: &mut Interp) -> PsResult {
+    it.dstack.truncate(2);
+    Ok(())
+}
+
+fn op_maxlength(it: &mut Interp) -> PsResult {
+    let d = it.pop_dict()?;
+    let n = d.borrow().len() as i32 + 1;
+    it.push(Obj::Int(n.max(1000)))
+}
+
+fn op_dict_begin_mark(it: &mut Interp) -> PsResult {
+    it.push(Obj::Mark)
+}
+
+fn op_dict_end_mark(it: &mut Interp) -> PsResult {
+    let n = it.count_to_mark()?;
+    if n % 2 != 0 {
+        return Err(PsError::RangeCheck);
+    }
+    let len = it.ostack.len();
+    let items = it.ostack.split_off(len - n);
+    it.ostack.pop();
+    let d = new_dict();
+    {
+        let mut dm = d.borrow_mut();
+        for pair in items.chunks(2) {
+            dm.insert(pair[0].key()?, pair[1].clone());
+        }
+    }
+    it.push(Obj::Dict(d))
+}
+
+fn op_array_end_mark(it: &mut Interp) -> PsResult {
+    let n = it.count_to_mark()?;
+    let len = it.ostack.len();
+    let items = it.ostack.split_off(len - n);
+    it.ostack.pop();
+    it.push(Obj::Array(PsArr::new(items)))
+}
+
+fn op_bind(it: &mut Interp) -> PsResult {
+    fn bind(it: &Interp, a: &PsArr, depth: usize) {
+        if depth > 64 {
+            return;
+        }
+        for i in 0..a.len {
+            let item = match a.get(i) {
+                Ok(item) => item,
+                Err(_) => return,
+            };
+            match item {
+                Obj::ExecName(ref n) => {
+                    if let Some(Obj::Op(op)) = it.lookup(n) {
+                        let _ = a.put(i, Obj::Op(op));
+                    }
+                }
+                Obj::Proc(ref p) => bind(it, p, depth + 1),
+                _ => {}
+            }
+        }
+    }
+    let p = it.pop_proc()?;
+    bind(it, &p, 0);
+    it.push(Obj::Proc(p))
+}
+
+fn op_array(it: &mut Interp) -> PsResult {
+    let n = it.pop_int()?;
+    if !(0..=65535).contains(&n) {
+        return Err(PsError::RangeCheck);
+    }
+    it.push(Obj::Array(PsArr::new(vec![Obj::Null; n as usize])))
+}
+
+fn op_string(it: &mut Interp) -> PsResult {
+    let n = it.pop_int()?;
+    if !(0..=65535).contains(&n) {
+        return Err(PsError::RangeCheck);
+    }
+    it.push(Obj::Str(PsStr::new(vec![0; n as usize])))
+}
+
+fn op_length(it: &mut Interp) -> PsResult {
+    let n = match it.pop()? {
+        Obj::Array(a) | Obj::Proc(a) => a.len,
+        Obj::Str(s) => s.len,
+        Obj::Dict(d) => d.borrow().len(),
+        Obj::Name(n) | Obj::ExecName(n) => n.len(),
+        _ => return Err(PsError::TypeCheck),
+    };
+    it.push(Obj::Int(n as i32))
+}
+
+fn op_get(it: &mut Interp) -> PsResult {
+    let k = it.pop()?;
+    match it.pop()? {
+        Obj::Array(a) | Obj::Proc(a) => {
+            let i = k.num().ok_or(PsError::TypeCheck)?;
+            if i < 0. {
+                return Err(PsError::RangeCheck);
+            }
+            let o = a.get(i as usize)?;
+            it.push(o)
+        }
+        Obj::Str(s) => {
+            let i = k.num().ok_or(PsError::TypeCheck)?;
+            if i < 0. || i as usize >= s.len {
+                return Err(PsError::RangeCheck);
+            }
+            let b = s.buf.borrow()[s.start + i as usize];
+            it.push(Obj::Int(b as i32))
+        }
+        Obj::Dict(d) => {
+            let key = k.key()?;
+            let v = d.borrow().get(&key).cloned();
+            match v {
+                Some(v) => it.push(v),
+                None => Err(PsError::Undefined(match key {
+                    Key::Name(n) => String::from_utf8_lossy(&n).into_owned(),
+                    _ => "?".to_owned(),
+                })),
+            }
+        }
+        _ => Err(PsError::TypeCheck),
+    }
+}
+
+fn op_put(it: &mut Interp) -> PsResult {
+    let v = it.pop()?;
+    let k = it.pop()?;
+    match it.pop()? {
+        Obj::Array(a) | Obj::Proc(a) => {
+            let i = k.num().ok_or(PsError::TypeCheck)?;
+            if i < 0. {
+                return Err(PsError::RangeCheck);
+            }
+            a.put(i as usize, v)
+        }
+        Obj::Str(s) => {
+            let i = k.num().ok_or(PsError::TypeCheck)?;
+            let b = v.num().ok_or(PsError::TypeCheck)?;
+            if i < 0. || i as usize >= s.len {
+                return Err(PsError::RangeCheck);
+            }
+            s.buf.borrow_mut()[s.start + i as usize] = b as u8;
+            Ok(())
+        }
+        Obj::Dict(d) => {
+            d.borrow_mut().insert(k.key()?, v);
+            Ok(())
+        }
+        _ => Err(PsError::TypeCheck),
+    }
+}
+
+fn op_getinterval(it: &mut Interp) -> PsResult {
+    let count = it.pop_int()?;
+    let index = it.pop_int()?;
+    if count < 0 || index < 0 {
+        return Err(PsError::RangeCheck);
+    }
+    match it.pop()? {
+        Obj::Array(a) => it.push(Obj::Array(a.sub(index as usize, count as usize)?)),
+        Obj::Proc(a) => it.push(Obj::Proc(a.sub(index as usize, count as usize)?)),
+        Obj::Str(s) => it.push(Obj::Str(s.sub(index as usize, count as usize)?)),
+        _ => Err(PsError::TypeCheck),
+    }
+}
+
+fn op_putinterval(it: &mut Interp) -> PsResult {
+    let src = it.pop()?;
+    let index = it.pop_int()?;
+    if index < 0 {
+        return Err(PsError::RangeCheck);
+    }
+    let index = index as usize;
+    match (it.pop()?, src) {
+        (Obj::Array(dst), Obj::Array(src))
+        | (Obj::Array(dst), Obj::Proc(src))
+        | (Obj::Proc(dst), Obj::Array(src))
+        | (Obj::Proc(dst), Obj::Proc(src)) => {
+            if index + src.len > dst.len {
+                return Err(PsError::RangeCheck);
+            }
+            for (i, o) in src.items().into_iter().enumerate() {
+                dst.put(index + i, o)?;
+            }
+            Ok(())
+        }
+        (Obj::Str(dst), Obj::Str(src)) => {
+            if index + src.len > dst.len {
+                return Err(PsError::RangeCheck);
+            }
+            let bytes = src.bytes();
+            let start = dst.start + index;
+            dst.buf.borrow_mut()[start..start + bytes.len()].copy_from_slice(&bytes);
+            Ok(())
+        }
+        _ => Err(PsError::TypeCheck),
+    }
+}
+
+fn op_aload(it: &mut Interp) -> PsResult {
+    let a = it.pop_array()?;
+    for o in a.items() {
+        it.push(o)?;
+    }
+    it.push(Obj::Array(a))
+}
+
+fn op_astore(it: &mut Interp) -> PsResult {
+    let a = it.pop_array()?;
+    if it.ostack.len() < a.len {
+        return Err(PsError::StackUnderflow);
+    }
+    let len = it.ostack.len();
+    let items = it.ostack.split_off(len - a.len);
+    for (i, o) in items.into_iter().enumerate() {
+        a.put(i, o)?;
+    }
+    it.push(Obj::Array(a))
+}
+
+fn op_cvx(it: &mut Interp) -> PsResult {
+    let o = match it.pop()? {
+        Obj::Array(a) => Obj::Proc(a),
+        Obj::Name(n) => Obj::ExecName(n),
+        Obj::Str(s) => {
+            let src = s.bytes();
+            let mut pos = 0;
+            let mut items = Vec::new();
+            while let Some(tok) = it.scan(&src, &mut pos)? {
+                items.push(tok);
+            }
+            Obj::Proc(PsArr::new(items))
+        }
+        o => o,
+    };
+    it.push(o)
+}
+
+fn op_cvlit(it: &mut Interp) -> PsResult {
+    let o = match it.pop()? {
+        Obj::Proc(a) => Obj::Array(a),
+        Obj::ExecName(n) => Obj::Name(n),
+        o => o,
+    };
+    it.push(o)
+}
+
+fn op_xcheck(it: &mut Interp) -> PsResult {
+    let x = matches!(it.pop()?, Obj::Proc(_) | Obj::ExecName(_) | Obj::Op(_));
+    it.push(Obj::Bool(x))
+}
+
+fn op_type(it: &mut Interp) -> PsResult {
+    let t = it.pop()?.type_name();
+    it.push(Obj::ExecName(Rc::from(t)))
+}
+
+fn op_cvn(it: &mut Interp) -> PsResult {
+    let s = it.pop_string()?;
+    it.push(Obj::name(&s.bytes()))
+}
+
+fn op_cvs(it: &mut Interp) -> PsResult {
+    let dst = it.pop_string()?;
+    let text: Vec<u8> = match it.pop()? {
+        Obj::Int(i) => i.to_string().into_bytes(),
+        Obj::Real(r) => format_real(r).into_bytes(),
+        Obj::Bool(b) => {
+            if b {
+                b"true".to_vec()
+            } else {
+                b"false".to_vec()
+            }
+        }
+        Obj::Name(n) | Obj::ExecName(n) => n.to_vec(),
+        Obj::Str(s) => s.bytes(),
+        Obj::Op(op) => op.name.as_bytes().to_vec(),
+        _ => b"--nostringval--".to_vec(),
+    };
+    if text.len() > dst.len {
+        return Err(PsError::RangeCheck);
+    }
+    dst.buf.borrow_mut()[dst.start..dst.start + text.len()].copy_from_slice(&text);
+    it.push(Obj::Str(dst.sub(0, text.len())?))
+}
+
+fn op_search(it: &mut Interp) -> PsResult {
+    let seek = it.pop_string()?;
+    let s = it.pop_string()?;
+    let hay = s.bytes();
+    let needle = seek.bytes();
+    let found = if needle.is_empty() {
+        Some(0)
+    } else {
+        hay.windows(needle.len()).position(|w| w == &needle[..])
+    };
+    match found {
+        Some(i) => {
+            it.push(Obj::Str(s.sub(i + needle.len(), s.len - i - needle.len())?))?;
+            it.push(Obj::Str(s.sub(i, needle.len())?))?;
+            it.push(Obj::Str(s.sub(0, i)?))?;
+            it.push(Obj::Bool(true))
+        }
+        None => {
+            it.push(Obj::Str(s))?;
+            it.push(Obj::Bool(false))
+        }
+    }
+}
+
+fn op_noop(_it: &mut Interp) -> PsResult {
+    Ok(())
+}
+
+fn op_pop_noop(it: &mut Interp) -> PsResult {
+    it.pop().map(|_| ())
+}
+
+fn op_true_result(it: &mut Interp) -> PsResult {
+    it.pop()?;
+    it.push(Obj::Bool(true))
+}
+
+fn op_zero(it: &mut Interp) -> PsResult {
+    it.push(Obj::Int(0))
+}
+
+fn op_vmstatus(it: &mut Interp) -> PsResult {
+    it.push(Obj::Int(0))?;
+    it.push(Obj::Int(0))?;
+    it.push(Obj::Int(1 << 24))
+}
+
+fn op_save(it: &mut Interp) -> PsResult {
+    it.gsave()?;
+    it.saves.push(it.gstack.len() - 1);
+    let n = it.saves.len();
+    it.push(Obj::Save(n))
+}
+
+fn op_restore(it: &mut Interp) -> PsResult {
+    let n = match it.pop()? {
+        Obj::Save(n) => n,
+        _ => return Err(PsError::TypeCheck),
+    };
+    if n == 0 || n > it.saves.len() {
+        return Err(PsError::Unsupported("invalidrestore".to_owned()));
+    }
+    let depth = it.saves[n - 1];
+    it.saves.truncate(n - 1);
+    while it.gstack.len() > depth {
+        it.grestore();
+    }
+    Ok(())
+}
+
+fn op_gsave(it: &mut Interp) -> PsResult {
+    it.gsave()
+}
+
+fn op_grestore(it: &mut Interp) -> PsResult {
+    let floor = it.saves.last().map(|&d| d + 1).unwrap_or(1);
+    if it.gstack.len() > floor {
+        it.grestore();
+    }
+    Ok(())
+}
+
+fn op_grestoreall(it: &mut Interp) -> PsResult {
+    let floor = it.saves.last().map(|&d| d + 1).unwrap_or(1);
+    while it.gstack.len() > floor {
+        it.grestore();
+    }
+    Ok(())
+}
+
+/* Files and filters */
+
+fn op_currentfile(it: &mut Interp) -> PsResult {
+    it.push(Obj::File(FileRef::Current))
+}
+
+fn op_readhexstring(it: &mut Interp) -> PsResult {
+    let s = it.pop_string()?;
+    let f = it.pop_file()?;
+    let mut out = Vec::with_capacity(s.len);
+    let mut hi = None;
+    while out.len() < s.len {
+        match it.file_getc(&f) {
+            Some(c) => {
+                if let Some(v) = hex_value(c) {
+                    match hi.take() {
+                        None => hi = Some(v),
+                        Some(h) => out.push(h << 4 | v),
+                    }
+                }
+            }
+            None => break,
+        }
+    }
+    let full = out.len() == s.len;
+    let n = out.len();
+    s.buf.borrow_mut()[s.start..s.start + n].copy_from_slice(&out);
+    it.push(Obj::Str(s.sub(0, n)?))?;
+    it.push(Obj::Bool(full))
+}
+
+fn op_readstring(it: &mut Interp) -> PsResult {
+    let s = it.pop_string()?;
+    let f = it.pop_file()?;
+    let out = it.file_read(&f, s.len);
+    let full = out.len() == s.len;
+    let n = out.len();
+    s.buf.borrow_mut()[s.start..s.start + n].copy_from_slice(&out);
+    it.push(Obj::Str(s.sub(0, n)?))?;
+    it.push(Obj::Bool(full))
+}
+
+fn op_readline(it: &mut Interp) -> PsResult {
+    let s = it.pop_string()?;
+    let f = it.pop_file()?;
+    let mut out = Vec::new();
+    let mut eol = false;
+    while let Some(c) = it.file_getc(&f) {
+        if c == b'\n' {
+            eol = true;
+            break;
+        }
+        if c == b'\r' {
+            eol = true;
+            if let FileRef::Current = f {
+                if it.src.get(it.pos) == Some(&b'\n') {
+                    it.pos += 1;
+                }
+            }
+            break;
+        }
+        if out.len() == s.len {
+            return Err(PsError::RangeCheck);
+        }
+        out.push(c);
+    }
+    let n = out.len();
+    s.buf.borrow_mut()[s.start..s.start + n].copy_from_slice(&out);
+    it.push(Obj::Str(s.sub(0, n)?))?;
+    it.push(Obj::Bool(eol))
+}
+
+fn op_read(it: &mut Interp) -> PsResult {
+    let f = it.pop_file()?;
+    match it.file_getc(&f) {
+        Some(c) => {
+            it.push(Obj::Int(c as i32))?;
+            it.push(Obj::Bool(true))
+        }
+        None => it.push(Obj::Bool(false)),
+    }
+}
+
+fn op_filter(it: &mut Interp) -> PsResult {
+    let name = match it.pop()? {
+        Obj::Name(n) | Obj::ExecName(n) => n,
+        _ => return Err(PsError::TypeCheck),
+    };
+    /* Filter parameter dictionaries are accepted but ignored. */
+    if let Obj::Dict(_) = it.peek(0)? {
+        it.pop()?;
+    }
+    let data = match &name[..] {
+        b"ASCIIHexDecode" | b"AHx" | b"ASCII85Decode" | b"A85" | b"RunLengthDecode" | b"RL" => {
+            let kind = match &name[..] {
+                b"ASCIIHexDecode" | b"AHx" => FilterKind::AsciiHex,
+                b"ASCII85Decode" | b"A85" => FilterKind::Ascii85,
+                _ => FilterKind::RunLength,
+            };
+            match it.pop()? {
+                /* Filters over files decode on demand, since the encoded data
+                 * usually follows the operators consuming it in the program. */
+                Obj::File(src) => {
+                    return it.push(Obj::File(FileRef::Filter(Rc::new(RefCell::new(
+                        FilterFile {
+                            kind,
+                            src,
+                            buf: Vec::new(),
+                            pos: 0,
+                            eod: false,
+                        },
+                    )))))
+                }
+                src => {
+                    let raw = it.read_all(&src)?;
+                    let mut bytes = raw.into_iter();
+                    match kind {
+                        FilterKind::AsciiHex => decode_hex(|| bytes.next()),
+                        FilterKind::Ascii85 => decode_a85(|| bytes.next())?,
+                        FilterKind::RunLength => decode_runlength(|| bytes.next()),
+                    }
+                }
+            }
+        }
+        b"FlateDecode" | b"Fl" => {
+            let src = it.pop()?;
+            if let Obj::File(FileRef::Current) = src {
+                /* There is no telling where binary compressed data ends. */
+                return Err(PsError::Unsupported(
+                    "FlateDecode of currentfile".to_owned(),
+                ));
+            }
+            let raw = it.read_all(&src)?;
+            decode_flate(&raw)?
+        }
+        b"SubFileDecode" => {
+            let eod = it.pop_string()?.bytes();
+            let count = it.pop_int()?;
+            let src = it.pop()?;
+            if count != 0 || eod.is_empty() {
+                return Err(PsError::Unsupported("SubFileDecode".to_owned()));
+            }
+            let mut out = Vec::new();
+            match src {
+                Obj::File(f) => {
+                    while let Some(c) = it.file_getc(&f) {
+                        out.push(c);
+                        if out.ends_with(&eod) {
+                            out.truncate(out.len() - eod.len());
+                            break;
+                        }
+                    }
+                }
+                src => {
+                    out = it.read_all(&src)?;
+                    if let Some(i) = out.windows(eod.len()).position(|w| w == &eod[..]) {
+                        out.truncate(i);
+                    }
+                }
+            }
+            out
+        }
+        b"NullEncode" | b"ASCIIHexEncode" | b"ASCII85Encode" => {
+            /* Output filters are only used to write to %stdout and the like. */
+            it.pop()?;
+            Vec::new()
+        }
+        other => {
+            return Err(PsError::Unsupported(
+                String::from_utf8_lossy(other).into_owned(),
+            ))
+        }
+    };
+    it.push(Obj::File(FileRef::Data(Rc::new(RefCell::new(DataFile {
+        data,
+        pos: 0,
+    })))))
+}
+
+fn op_closefile(it: &mut Interp) -> PsResult {
+    it.pop_file().map(|_| ())
+}
+
+/* Coordinates and matrices */
+
+fn op_matrix(it: &mut Interp) -> PsResult {
+    it.push(Interp::matrix_obj(&TMatrix::identity()))
+}
+
+fn op_identmatrix(it: &mut Interp) -> PsResult {
+    let a = it.pop_array()?;
+    Interp::store_matrix(&a, &TMatrix::identity())?;
+    it.push(Obj::Array(a))
+}
+
+fn op_currentmatrix(it: &mut Interp) -> PsResult {
+    let a = it.pop_array()?;
+    Interp::store_matrix(&a, &it.ctm())?;
+    it.push(Obj::Array(a))
+}
+
+fn op_defaultmatrix(it: &mut Interp) -> PsResult {
+    op_identmatrix(it)
+}
+
+fn op_setmatrix(it: &mut Interp) -> PsResult {
+    let m = it.pop_matrix()?;
+    let inv = it.ctm().inverse().ok_or(PsError::UndefinedResult)?;
+    it.concat(&m.post_transform(&inv))
+}
+
+fn op_initmatrix(it: &mut Interp) -> PsResult {
+    let inv = it.ctm().inverse().ok_or(PsError::UndefinedResult)?;
+    it.concat(&inv)
+}
+
+fn op_concat(it: &mut Interp) -> PsResult {
+    let m = it.pop_matrix()?;
+    it.concat(&m)
+}
+
+fn op_concatmatrix(it: &mut Interp) -> PsResult {
+    let dst = it.pop_array()?;
+    let m2 = it.pop_matrix()?;
+    let m1 = it.pop_matrix()?;
+    Interp::store_matrix(&dst, &m1.post_transform(&m2))?;
+    it.push(Obj::Array(dst))
+}
+
+fn op_invertmatrix(it: &mut Interp) -> PsResult {
+    let dst = it.pop_array()?;
+    let m = it.pop_matrix()?;
+    Interp::store_matrix(&dst, &m.inverse().ok_or(PsError::UndefinedResult)?)?;
+    it.push(Obj::Array(dst))
+}
+
+fn op_translate(it: &mut Interp) -> PsResult {
+    it.transform_op(2, |v| TMatrix::create_translation(v[0], v[1]))
+}
+
+fn op_scale(it: &mut Interp) -> PsResult {
+    it.transform_op(2, |v| TMatrix::create_scale(v[0], v[1]))
+}
+
+fn op_rotate(it: &mut Interp) -> PsResult {
+    it.transform_op(1, |v| {
+        TMatrix::create_rotation(euclid::Angle::degrees(v[0]))
+    })
+}
+
+fn op_transform(it: &mut Interp) -> PsResult {
+    let (x, y, m) = it.pop_point_and_matrix()?;
+    let p = m.transform_point(Coord::new(x, y));
+    it.push(Obj::real(p.x))?;
+    it.push(Obj::real(p.y))
+}
+
+fn op_itransform(it: &mut Interp) -> PsResult {
+    let (x, y, m) = it.pop_point_and_matrix()?;
+    let p = m
+        .inverse()
+        .ok_or(PsError::UndefinedResult)?
+        .transform_point(Coord::new(x, y));
+    it.push(Obj::real(p.x))?;
+    it.push(Obj::real(p.y))
+}
+
+fn op_dtransform(it: &mut Interp) -> PsResult {
+    let (x, y, m) = it.pop_point_and_matrix()?;
+    let v = m.transform_vector(euclid::vec2(x, y));
+    it.push(Obj::real(v.x))?;
+    it.push(Obj::real(v.y))
+}
+
+fn op_idtransform(it: &mut Interp) -> PsResult {
+    let (x, y, m) = it.pop_point_and_matrix()?;
+    let v = m
+        .inverse()
+        .ok_or(PsError::UndefinedResult)?
+        .transform_vector(euclid::vec2(x, y));
+    it.push(Obj::real(v.x))?;
+    it.push(Obj::real(v.y))
+}
+
+/* Paths and painting */
+
+fn path_op(it: &mut Interp, n: usize, f: fn(&[f64]) -> i32) -> PsResult {
+    let v = it.pop_nums(n)?;
+    if it.paint && f(&v) < 0 {
+        return Err(PsError::Unsupported("nocurrentpoint".to_owned()));
+    }
+    Ok(())
+}
+
+fn op_newpath(it: &mut Interp) -> PsResult {
+    path_op(it, 0, |_| unsafe { pdf_dev_newpath() })
+}
+
+fn op_moveto(it: &mut Interp) -> PsResult {
+    path_op(it, 2, |v| unsafe { pdf_dev_moveto(v[0], v[1]) })
+}
+
+fn op_rmoveto(it: &mut Interp) -> PsResult {
+    path_op(it, 2, |v| unsafe { pdf_dev_rmoveto(v[0], v[1]) })
+}
+
+fn op_lineto(it: &mut Interp) -> PsResult {
+    path_op(it, 2, |v| unsafe { pdf_dev_lineto(v[0], v[1]) })
+}
+
+fn op_rlineto(it: &mut Interp) -> PsResult {
+    path_op(it, 2, |v| unsafe { pdf_dev_rlineto(v[0], v[1]) })
+}
+
+fn op_curveto(it: &mut Interp) -> PsResult {
+    path_op(it, 6, |v| unsafe {
+        pdf_dev_curveto(v[0], v[1], v[2], v[3], v[4], v[5])
+    })
+}
+
+fn op_rcurveto(it: &mut Interp) -> PsResult {
+    path_op(it, 6, |v| unsafe {
+        pdf_dev_rcurveto(v[0], v[1], v[2], v[3], v[4], v[5])
+    })
+}
+
+fn op_arc(it: &mut Interp) -> PsResult {
+    path_op(it, 5, |v| unsafe {
+        pdf_dev_arc(v[0], v[1], v[2], v[3], v[4])
+    })
+}
+
+fn op_arcn(it: &mut Interp) -> PsResult {
+    path_op(it, 5, |v| unsafe {
+        pdf_dev_arcn(v[0], v[1], v[2], v[3], v[4])
+    })
+}
+
+fn op_closepath(it: &mut Interp) -> PsResult {
+    path_op(it, 0, |_| unsafe { pdf_dev_closepath() })
+}
+
+fn op_currentpoint(it: &mut Interp) -> PsResult {
+    let cp = it.current_point();
+    it.push(Obj::real(cp.x))?;
+    it.push(Obj::real(cp.y))
+}
+
+fn op_stroke(it: &mut Interp) -> PsResult {
+    path_op(it, 0, |_| unsafe { pdf_dev_flushpath(b'S', 0) })
+}
+
+fn op_fill(it: &mut Interp) -> PsResult {
+    path_op(it, 0, |_| unsafe { pdf_dev_flushpath(b'f', 0) })
+}
+
+fn op_eofill(it: &mut Interp) -> PsResult {
+    path_op(it, 0, |_| unsafe { pdf_dev_flushpath(b'f', 1) })
+}
+
+fn op_clip(it: &mut Interp) -> PsResult {
+    path_op(it, 0, |_| unsafe { pdf_dev_clip() })
+}
+
+fn op_eoclip(it: &mut Interp) -> PsResult {
+    path_op(it, 0, |_| unsafe { pdf_dev_eoclip() })
+}
+
+/// Pops the operands of the rectangle operators: either `x y w h` or an
+/// array of such quadruples.
+fn pop_rects(it: &mut Interp) -> PsResult<Vec<f64>> {
+    match it.peek(0)? {
+        Obj::Array(_) | Obj::Proc(_) => {
+            let a = it.pop_array()?;
+            let v = a
+                .items()
+                .iter()
+                .map(|o| o.num().ok_or(PsError::TypeCheck))
+                .collect::<PsResult<Vec<f64>>>()?;
+            if v.len() % 4 != 0 {
+                return Err(PsError::RangeCheck);
+            }
+            Ok(v)
+        }
+        Obj::Str(_) => Err(PsError::Unsupported("encoded number strings".to_owned())),
+        _ => it.pop_nums(4),
+    }
+}
+
+unsafe fn append_rects(v: &[f64]) {
+    pdf_dev_newpath();
+    for r in v.chunks(4) {
+        pdf_dev_moveto(r[0], r[1]);
+        pdf_dev_lineto(r[0] + r[2], r[1]);
+        pdf_dev_lineto(r[0] + r[2], r[1] + r[3]);
+        pdf_dev_lineto(r[0], r[1] + r[3]);
+        pdf_dev_closepath();
+    }
+}
+
+fn op_rectfill(it: &mut Interp) -> PsResult {
+    let v = pop_rects(it)?;
+    if it.paint {
+        unsafe {
+            pdf_dev_gsave();
+            append_rects(&v);
+            pdf_dev_flushpath(b'f', 0);
+            pdf_dev_grestore();
+        }
+    }
+    Ok(())
+}
+
+fn op_rectstroke(it: &mut Interp) -> PsResult {
+    let m = if let Obj::Array(_) = it.peek(0)? {
+        if let Ok(Obj::Array(_)) | Ok(Obj::Proc(_)) | Ok(Obj::Int(_)) | Ok(Obj::Real(_)) =
+            it.peek(1).cloned()
+        {
+            Some(it.pop_matrix()?)
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+    let v = pop_rects(it)?;
+    if it.paint {
+        unsafe {
+            pdf_dev_gsave();
+            append_rects(&v);
+            if let Some(m) = m {
+                pdf_dev_concat(&m);
+            }
+            pdf_dev_flushpath(b'S', 0);
+            pdf_dev_grestore();
+        }
+    }
+    Ok(())
+}
+
+fn op_rectclip(it: &mut Interp) -> PsResult {
+    let v = pop_rects(it)?;
+    if it.paint {
+        unsafe {
+            append_rects(&v);
+            pdf_dev_clip();
+            pdf_dev_newpath();
+        }
+    }
+    Ok(())
+}
+
+fn op_setlinewidth(it: &mut Interp) -> PsResult {
+    let w = it.pop_num()?;
+    it.gs().linewidth = w;
+    if it.paint {
+        unsafe {
+            pdf_dev_setlinewidth(w);
+        }
+    }
+    Ok(())
+}
+
+fn op_currentlinewidth(it: &mut Interp) -> PsResult {
+    let w = it.gs().linewidth;
+    it.push(Obj::real(w))
+}
+
+fn op_setlinecap(it: &mut Interp) -> PsResult {
+    let v = it.pop_int()?;
+    if it.paint {
+        unsafe {
+            pdf_dev_setlinecap(v);
+        }
+    }
+    Ok(())
+}
+
+fn op_setlinejoin(it: &mut Interp) -> PsResult {
+    let v = it.pop_int()?;
+    if it.paint {
+        unsafe {
+            pdf_dev_setlinejoin(v);
+        }
+    }
+    Ok(())
+}
+
+fn op_setmiterlimit(it: &mut Interp) -> PsResult {
+    let v = it.pop_num()?;
+    if it.paint {
+        unsafe {
+            pdf_dev_setmiterlimit(v);
+        }
+    }
+    Ok(())
+}
+
+fn op_setdash(it: &mut Interp) -> PsResult {
+    let offset = it.pop_num()?;
+    let a = it.pop_array()?;
+    let pattern = a
+        .items()
+        .iter()
+        .map(|o| o.num().ok_or(PsError::TypeCheck))
+        .collect::<PsResult<Vec<f64>>>()?;
+    if it.paint {
+        unsafe {
+            pdf_dev_setdash(&pattern, offset);
+        }
+    }
+    Ok(())
+}
+
+fn op_setgray(it: &mut Interp) -> PsResult {
+    let v = it.pop_nums(1)?;
+    it.set_color(Some(ColorSpace::Gray), v)
+}
+
+fn op_setrgbcolor(it: &mut Interp) -> PsResult {
+    let v = it.pop_nums(3)?;
+    it.set_color(Some(ColorSpace::Rgb), v)
+}
+
+fn op_setcmykcolor(it: &mut Interp) -> PsResult {
+    let v = it.pop_nums(4)?;
+    it.set_color(Some(ColorSpace::Cmyk), v)
+}
+
+fn op_sethsbcolor(it: &mut Interp) -> PsResult {
+    let v = it.pop_nums(3)?;
+    let (h, s, b) = (v[0].max(0.).min(1.) * 6., v[1], v[2]);
+    let i = (h.floor() as i32).min(5);
+    let f = h - i as f64;
+    let (p, q, t) = (b * (1. - s), b * (1. - s * f), b * (1. - s * (1. - f)));
+    let rgb = match i {
+        0 => [b, t, p],
+        1 => [q, b, p],
+        2 => [p, b, t],
+        3 => [p, q, b],
+        4 => [t, p, b],
+        _ => [b, p, q],
+    };
+    it.set_color(Some(ColorSpace::Rgb), rgb.to_vec())
+}
+
+fn op_setcolorspace(it: &mut Interp) -> PsResult {
+    let o = it.pop()?;
+    let space = it.parse_color_space(&o)?;
+    let color = space.initial_color();
+    it.set_color(Some(space), color)
+}
+
+fn op_setcolor(it: &mut Interp) -> PsResult {
+    let n = it.gs().space.ncomps();
+    if let Obj::Dict(_) = it.peek(0)? {
+        return Err(PsError::Unsupported("Pattern".to_owned()));
+    }
+    let v = it.pop_nums(n)?;
+    it.set_color(None, v)
+}
+
+fn op_currentgray(it: &mut Interp) -> PsResult {
+    let gs = it.gs().clone();
+    let v = match gs.space {
+        ColorSpace::Gray => gs.color[0],
+        ColorSpace::Rgb => 0.3 * gs.color[0] + 0.59 * gs.color[1] + 0.11 * gs.color[2],
+        ColorSpace::Cmyk => {
+            1. - (0.3 * gs.color[0] + 0.59 * gs.color[1] + 0.11 * gs.color[2] + gs.color[3]).min(1.)
+        }
+        _ => 0.,
+    };
+    it.push(Obj::real(v))
+}
+
+fn op_currentrgbcolor(it: &mut Interp) -> PsResult {
+    let gs = it.gs().clone();
+    let v = match gs.space {
+        ColorSpace::Gray => [gs.color[0]; 3],
+        ColorSpace::Rgb => [gs.color[0], gs.color[1], gs.color[2]],
+        ColorSpace::Cmyk => {
+            let k = gs.color[3];
+            [
+                1. - (gs.color[0] + k).min(1.),
+                1. - (gs.color[1] + k).min(1.),
+                1. - (gs.color[2] + k).min(1.),
+            ]
+        }
+        _ => [0.; 3],
+    };
+    for &c in &v {
+        it.push(Obj::real(c))?;
+    }
+    Ok(())
+}
+
+fn op_image(it: &mut Interp) -> PsResult {
+    it.image_common(false)
+}
+
+fn op_imagemask(it: &mut Interp) -> PsResult {
+    it.image_common(true)
+}
+
+fn op_colorimage(it: &mut Interp) -> PsResult {
+    let ncomp = it.pop_int()?;
+    let multi = it.pop_bool()?;
+    let space = match ncomp {
+        1 => ColorSpace::Gray,
+        3 => ColorSpace::Rgb,
+        4 => ColorSpace::Cmyk,
+        _ => return Err(PsError::RangeCheck),
+    };
+    let nsrc = if multi { ncomp as usize } else { 1 };
+    let mut srcs = Vec::with_capacity(nsrc);
+    for _ in 0..nsrc {
+        srcs.push(it.pop()?);
+    }
+    srcs.reverse();
+    let imat = it.pop_matrix()?;
+    let bpc = it.pop_int()?;
+    let height = it.pop_int()?.max(0) as usize;
+    let width = it.pop_int()?.max(0) as usize;
+    let n = ncomp as usize;
+    let data = if multi {
+        if bpc != 8 {
+            return Err(PsError::Unsupported(
+                "multiple-source colorimage".to_owned(),
+            ));
+        }
+        let planes = srcs
+            .iter()
+            .map(|src| it.read_source(src, width * height))
+            .collect::<PsResult<Vec<_>>>()?;
+        let mut data = Vec::with_capacity(width * height * n);
+        for i in 0..width * height {
+            for plane in &planes {
+                data.push(plane[i]);
+            }
+        }
+        data
+    } else {
+        let rowbytes = (width * bpc.max(0) as usize * n + 7) / 8;
+        it.read_source(&srcs[0], rowbytes * height)?
+    };
+    it.paint_image(
+        width as i32,
+        height as i32,
+        bpc,
+        Some(&space),
+        None,
+        &imat,
+        &data,
+    )
+}
+
+/* Fonts and text */
+
+fn op_findfont(it: &mut Interp) -> PsResult {
+    let key = it.pop()?;
+    let font = it.find_font(&key)?;
+    it.push(Obj::Dict(font))
+}
+
+fn op_definefont(it: &mut Interp) -> PsResult {
+    let font = it.pop_dict()?;
+    let key = it.pop()?.key()?;
+    if !font
+        .borrow()
+        .contains_key(&Key::Name(b"FontMatrix".to_vec()))
+    {
+        return Err(PsError::Unsupported("invalidfont".to_owned()));
+    }
+    it.font_directory
+        .borrow_mut()
+        .insert(key, Obj::Dict(font.clone()));
+    it.push(Obj::Dict(font))
+}
+
+fn op_undefinefont(it: &mut Interp) -> PsResult {
+    let key = it.pop()?.key()?;
+    it.font_directory.borrow_mut().remove(&key);
+    Ok(())
+}
+
+fn op_scalefont(it: &mut Interp) -> PsResult {
+    let s = it.pop_num()?;
+    let font = it.pop_dict()?;
+    let scaled = Interp::transformed_font(&font, &TMatrix::create_scale(s, s))?;
+    it.push(Obj::Dict(scaled))
+}
+
+fn op_makefont(it: &mut Interp) -> PsResult {
+    let m = it.pop_matrix()?;
+    let font = it.pop_dict()?;
+    let made = Interp::transformed_font(&font, &m)?;
+    it.push(Obj::Dict(made))
+}
+
+fn op_setfont(it: &mut Interp) -> PsResult {
+    let font = it.pop_dict()?;
+    it.gs().font = Some(font);
+    Ok(())
+}
+
+fn op_selectfont(it: &mut Interp) -> PsResult {
+    let m = match it.pop()? {
+        Obj::Array(a) | Obj::Proc(a) => Interp::array_to_matrix(&a)?,
+        o => {
+            let s = o.num().ok_or(PsError::TypeCheck)?;
+            TMatrix::create_scale(s, s)
+        }
+    };
+    let key = it.pop()?;
+    let font = it.find_font(&key)?;
+    let font = Interp::transformed_font(&font, &m)?;
+    it.gs().font = Some(font);
+    Ok(())
+}
+
+fn op_currentfont(it: &mut Interp) -> PsResult {
+    match it.gs().font.clone() {
+        Some(f) => it.push(Obj::Dict(f)),
+        None => {
+            let font = it.find_font(&Obj::name(b"Courier"))?;
+            it.push(Obj::Dict(font))
+        }
+    }
+}
+
+fn op_show(it: &mut Interp) -> PsResult {
+    let s = it.pop_string()?;
+    it.show_string(&s.bytes()).map(|_| ())
+}
+
+fn op_ashow(it: &mut Interp) -> PsResult {
+    let s = it.pop_string()?;
+    let v = it.pop_nums(2)?;
+    for b in s.bytes() {
+        it.show_string(&[b])?;
+        if it.paint {
+            unsafe {
+                pdf_dev_rmoveto(v[0], v[1]);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn op_glyphshow(it: &mut Interp) -> PsResult {
+    let name = match it.pop()? {
+        Obj::Name(n) | Obj::ExecName(n) => n,
+        _ => return Err(PsError::TypeCheck),
+    };
+    let font = it.current_font()?;
+    if Interp::font_type(&font) == 3 {
+        return it.show_type3_glyph(&font, GlyphSel::Name(name)).map(|_| ());
+    }
+    /* Resident fonts are reached through the standard encoding. */
+    match ASCII_GLYPH_NAMES
+        .iter()
+        .position(|g| g.as_bytes() == &name[..])
+    {
+        Some(i) => it.show_resident(&font, &[32 + i as u8]).map(|_| ()),
+        None => Err(PsError::Unsupported(format!(
+            "glyphshow /{}",
+            String::from_utf8_lossy(&name)
+        ))),
+    }
+}
+
+fn op_stringwidth(it: &mut Interp) -> PsResult {
+    let s = it.pop_string()?;
+    let paint = it.paint;
+    it.paint = false;
+    let r = it.show_string(&s.bytes());
+    it.paint = paint;
+    let (wx, wy) = r?;
+    it.push(Obj::real(wx))?;
+    it.push(Obj::real(wy))
+}
+
+fn op_setcharwidth(it: &mut Interp) -> PsResult {
+    let v = it.pop_nums(2)?;
+    it.char_width = Some((v[0], v[1]));
+    if it.paint {
+        Ok(())
+    } else {
+        Err(PsError::WidthDone)
+    }
+}
+
+fn op_setcachedevice(it: &mut Interp) -> PsResult {
+    let v = it.pop_nums(6)?;
+    it.char_width = Some((v[0], v[1]));
+    if it.paint {
+        Ok(())
+    } else {
+        Err(PsError::WidthDone)
+    }
+}
+
+fn op_setcachedevice2(it: &mut Interp) -> PsResult {
+    let v = it.pop_nums(10)?;
+    it.char_width = Some((v[0], v[1]));
+    if it.paint {
+        Ok(())
+    } else {
+        Err(PsError::WidthDone)
+    }
+}
+
+fn op_unsupported(_it: &mut Interp) -> PsResult {
+    Err(PsError::Unsupported(String::new()))
+}
+
+const OPERATORS: &[(&str, OpFn)] = &[
+    /* Operand stack */
+    ("pop", op_pop),
+    ("exch", op_exch),
+    ("dup", op_dup),
+    ("copy", op_copy),
+    ("index", op_index),
+    ("roll", op_roll),
+    ("clear", op_clear),
+    ("count", op_count),
+    ("mark", op_mark),
+    ("cleartomark", op_cleartomark),
+    ("counttomark", op_counttomark),
+    /* Arithmetic */
+    ("add", op_add),
+    ("sub", op_sub),
+    ("mul", op_mul),
+    ("div", op_div),
+    ("idiv", op_idiv),
+    ("mod", op_mod),
+    ("neg", op_neg),
+    ("abs", op_abs),
+    ("ceiling", op_ceiling),
+    ("floor", op_floor),
+    ("round", op_round),
+    ("truncate", op_truncate),
+    ("sqrt", op_sqrt),
+    ("sin", op_sin),
+    ("cos", op_cos),
+    ("atan", op_atan),
+    ("exp", op_exp),
+    ("ln", op_ln),
+    ("log", op_log),
+    ("cvi", op_cvi),
+    ("cvr", op_cvr),
+    ("rand", op_rand),
+    ("srand", op_srand),
+    ("rrand", op_rrand),
+    /* Relational, boolean and bitwise */
+    ("eq", op_eq),
+    ("ne", op_ne),
+    ("gt", op_gt),
+    ("ge", op_ge),
+    ("lt", op_lt),
+    ("le", op_le),
+    ("and", op_and),
+    ("or", op_or),
+    ("xor", op_xor),
+    ("not", op_not),
+    ("bitshift", op_bitshift),
+    /* Control */
+    ("exec", op_exec),
+    ("if", op_if),
+    ("ifelse", op_ifelse),
+    ("for", op_for),
+    ("repeat", op_repeat),
+    ("loop", op_loop),
+    ("exit", op_exit),
+    ("stop", op_stop),
+    ("stopped", op_stopped),
+    ("quit", op_quit),
+    ("forall", op_forall),
+    /* Dictionaries */
+    ("dict", op_dict),
+    ("begin", op_begin),
+    ("end", op_end),
+    ("def", op_def),
+    ("load", op_load),
+    ("store", op_store),
+    ("known", op_known),
+    ("where", op_where),
+    ("undef", op_undef),
+    ("currentdict", op_currentdict),
+    ("countdictstack", op_countdictstack),
+    ("cleardictstack", op_cleardictstack),
+    ("maxlength", op_maxlength),
+    ("<<", op_dict_begin_mark),
+    (">>", op_dict_end_mark),
+    ("bind", op_bind),
+    /* Arrays and strings */
+    ("[", op_mark),
+    ("]", op_array_end_mark),
+    ("array", op_array),
+    ("string", op_string),
+    ("length", op_length),
+    ("get", op_get),
+    ("put", op_put),
+    ("getinterval", op_getinterval),
+    ("putinterval", op_putinterval),
+    ("aload", op_aload),
+    ("astore", op_astore),
+    ("search", op_search),
+    /* Types and conversions */
+    ("cvx", op_cvx),
+    ("cvlit", op_cvlit),
+    ("xcheck", op_xcheck),
+    ("type", op_type),
+    ("cvn", op_cvn),
+    ("cvs", op_cvs),
+    ("readonly", op_noop),
+    ("executeonly", op_noop),
+    ("noaccess", op_noop),
+    ("rcheck", op_true_result),
+    ("wcheck", op_true_result),
+    /* Miscellaneous */
+    ("save", op_save),
+    ("restore", op_restore),
+    ("vmstatus", op_vmstatus),
+    ("realtime", op_zero),
+    ("usertime", op_zero),
+    ("print", op_pop_noop),
+    ("=", op_pop_noop),
+    ("==", op_pop_noop),
+    ("flush", op_noop),
+    ("pstack", op_noop),
+    ("showpage", op_noop),
+    ("copypage", op_noop),
+    ("erasepage", op_noop),
+    ("initgraphics", op_noop),
+    ("initclip", op_noop),
+    ("setpagedevice", op_pop_noop),
+    ("setflat", op_pop_noop),
+    ("setstrokeadjust", op_pop_noop),
+    ("setoverprint", op_pop_noop),
+    ("setsmoothness", op_pop_noop),
+    ("setblackgeneration", op_pop_noop),
+    ("setundercolorremoval", op_pop_noop),
+    ("settransfer", op_pop_noop),
+    ("sethalftone", op_pop_noop),
+    ("setscreen", op_unsupported),
+    ("setpacking", op_pop_noop),
+    ("setglobal", op_pop_noop),
+    ("shfill", op_unsupported),
+    ("charpath", op_unsupported),
+    ("pathbbox", op_unsupported),
+    /* Files */
+    ("currentfile", op_currentfile),
+    ("readhexstring", op_readhexstring),
+    ("readstring", op_readstring),
+    ("readline", op_readline),
+    ("read", op_read),
+    ("filter", op_filter),
+    ("closefile", op_closefile),
+    /* Graphics state and coordinates */
+    ("gsave", op_gsave),
+    ("grestore", op_grestore),
+    ("grestoreall", op_grestoreall),
+    ("matrix", op_matrix),
+    ("identmatrix", op_identmatrix),
+    ("currentmatrix", op_currentmatrix),
+    ("defaultmatrix", op_defaultmatrix),
+    ("setmatrix", op_setmatrix),
+    ("initmatrix", op_initmatrix),
+    ("concat", op_concat),
+    ("concatmatrix", op_concatmatrix),
+    ("invertmatrix", op_invertmatrix),
+    ("translate", op_translate),
+    ("scale", op_scale),
+    ("rotate", op_rotate),
+    ("transform", op_transform),
+    ("itransform", op_itransform),
+    ("dtransform", op_dtransform),
+    ("idtransform", op_idtransform),
+    ("setlinewidth", op_setlinewidth),
+    ("currentlinewidth", op_currentlinewidth),
+    ("setlinecap", op_setlinecap),
+    ("setlinejoin", op_setlinejoin),
+    ("setmiterlimit", op_setmiterlimit),
+    ("setdash", op_setdash),
+    ("setgray", op_setgray),
+    ("setrgbcolor", op_setrgbcolor),
+    ("setcmykcolor", op_setcmykcolor),
+    ("sethsbcolor", op_sethsbcolor),
+    ("setcolorspace", op_setcolorspace),
+    ("setcolor", op_setcolor),
+    ("currentgray", op_currentgray),
+    ("currentrgbcolor", op_currentrgbcolor),
+    /* Paths and painting */
+    ("newpath", op_newpath),
+    ("moveto", op_moveto),
+    ("rmoveto", op_rmoveto),
+    ("lineto", op_lineto),
+    ("rlineto", op_rlineto),
+    ("curveto", op_curveto),
+    ("rcurveto", op_rcurveto),
+    ("arc", op_arc),
+    ("arcn", op_arcn),
+    ("closepath", op_closepath),
+    ("currentpoint", op_currentpoint),
+    ("stroke", op_stroke),
+    ("fill", op_fill),
+    ("eofill", op_eofill),
+    ("clip", op_clip),
+    ("eoclip", op_eoclip),
+    ("rectfill", op_rectfill),
+    ("rectstroke", op_rectstroke),
+    ("rectclip", op_rectclip),
+    ("image", op_image),
+    ("imagemask", op_imagemask),
+    ("colorimage", op_colorimage),
+    /* Fonts and text */
+    ("findfont", op_findfont),
+    ("definefont", op_definefont),
+    ("undefinefont", op_undefinefont),
+    ("scalefont", op_scalefont),
+    ("makefont", op_makefont),
+    ("setfont", op_setfont),
+    ("selectfont", op_selectfont),
+    ("currentfont", op_currentfont),
+    ("show", op_show),
+    ("ashow", op_ashow),
+    ("glyphshow", op_glyphshow),
+    ("stringwidth", op_stringwidth),
+    ("setcharwidth", op_setcharwidth),
+    ("setcachedevice", op_setcachedevice),
+    ("setcachedevice2", op_setcachedevice2),
+];
+
+/*
+ * File handling and entry points
+ */
+
+/// Returns the PostScript section of an EPS file, skipping the binary
+/// header of DOS EPS files.
+fn ps_section(data: &[u8]) -> Option<&[u8]> {
+    if data.starts_with(&DOS_EPS_MAGIC) {
+        if data.len() < 12 {
+            return None;
+        }
+        let offset = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        let length = u32::from_le_bytes([data[8], data[9], data[10], data[11]]) as usize;
+        return data.get(offset..offset.checked_add(length)?);
+    }
+    if data.starts_with(b"%!") {
+        Some(data)
+    } else {
+        None
+    }
+}
+
+/// Finds the `%%BoundingBox` of a PostScript program, following
+/// `(atend)` to the trailer.
+fn scan_bbox(ps: &[u8]) -> Option<Rect> {
+    for line in ps.split(|&c| c == b'\n' || c == b'\r') {
+        if let Some(rest) = line.strip_prefix(b"%%BoundingBox:") {
+            let rest = String::from_utf8_lossy(rest);
+            let v = rest
+                .split_whitespace()
+                .map(|t| t.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>();
+            match v {
+                Ok(ref v) if v.len() == 4 => {
+                    return Some(Rect::new((v[0], v[1]), (v[2], v[3])));
+                }
+                _ => continue,
+            }
+        }
+    }
+    None
+}
+
+fn read_ps(handle: &mut InputHandleWrapper) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    handle.seek(SeekFrom::Start(0)).ok()?;
+    handle.read_to_end(&mut data).ok()?;
+    handle.seek(SeekFrom::Start(0)).ok()?;
+    ps_section(&data).map(|ps| ps.to_vec())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn check_for_eps(handle: &mut InputHandleWrapper) -> i32 {
+    let mut head = [0u8; 4];
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    let n = handle.read(&mut head).unwrap_or(0);
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    (n >= 2 && (head.starts_with(b"%!") || (n == 4 && head == DOS_EPS_MAGIC))) as i32
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn eps_get_bbox(handle: &mut InputHandleWrapper, bbox: &mut Rect) -> i32 {
+    match read_ps(handle).as_deref().and_then(scan_bbox) {
+        Some(r) => {
+            *bbox = r;
+            0
+        }
+        None => {
+            warn!("EPS: could not find a %%BoundingBox in the figure.");
+            -1
+        }
+    }
+}
+
+/// Interprets an EPS figure into a new Form XObject, returning its id.
+#[no_mangle]
+pub unsafe extern "C" fn eps_include_page(
+    ident: *const i8,
+    handle: &mut InputHandleWrapper,
+) -> i32 {
+    let ps = match read_ps(handle) {
+        Some(ps) => ps,
+        None => {
+            warn!("EPS: not a PostScript file.");
+            return -1;
+        }
+    };
+    let bbox = match scan_bbox(&ps) {
+        Some(bbox) => bbox,
+        None => {
+            warn!("EPS: could not find a %%BoundingBox in the figure.");
+            return -1;
+        }
+    };
+    /* The figure goes into its own content stream: close any open text
+     * object in the page first. */
+    graphics_mode();
+    let xobj_id = pdf_doc_begin_grabbing(ident, 0., 0., &bbox);
+    let depth = pdf_dev_current_depth();
+    let mut interp = Interp::new(ps, true);
+    if let Err(e) = interp.run() {
+        match interp.failed_op {
+            Some(op) => warn!("EPS: {} in \"{}\"; the figure may be incomplete.", e, op),
+            None => warn!("EPS: {}; the figure may be incomplete.", e),
+        }
+    }
+    graphics_mode();
+    while pdf_dev_current_depth() > depth {
+        pdf_dev_grestore();
+    }
+    pdf_doc_end_grabbing(ptr::null_mut());
+    xobj_id
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(src: &str) -> Interp {
+        let mut it = Interp::new(src.as_bytes().to_vec(), false);
+        it.run().unwrap();
+        it
+    }
+
+    fn nums(it: &Interp) -> Vec<f64> {
+        it.ostack.iter().map(|o| o.num().unwrap()).collect()
+    }
+
+    #[test]
+    fn arithmetic_and_stack() {
+        let it = run("1 2 add 10 3 idiv 7 2.0 div 3 1 roll -5 abs 2147483647 1 add");
+        assert_eq!(nums(&it), vec![3.5, 3., 3., 5., 2147483648.]);
+    }
+
+    #[test]
+    fn procedures_and_dictionaries() {
+        let it = run("/sq { dup mul } bind def \
+             /d 3 dict def d begin /x 4 def end \
+             d /x get sq \
+             0 1 1 4 { add } for \
+             /n 0 def { n 3 ge { exit } if /n n 1 add def } loop n \
+             { 1 0 div } stopped");
+        let o = &it.ostack;
+        assert_eq!(o.len(), 4);
+        assert_eq!(o[0].num(), Some(16.));
+        assert_eq!(o[1].num(), Some(10.));
+        assert_eq!(o[2].num(), Some(3.));
+        assert!(matches!(o[3], Obj::Bool(true)));
+    }
+
+    #[test]
+    fn strings_and_currentfile() {
+        let it = run("/s 3 string def currentfile s readhexstring\n414243\npop \
+             (abc) (b) search pop pop pop length \
+             currentfile /ASCII85Decode filter 5 string readstring\n87cURDZ~>\npop");
+        let o = &it.ostack;
+        assert_eq!(o.len(), 3);
+        match (&o[0], &o[2]) {
+            (Obj::Str(a), Obj::Str(b)) => {
+                assert_eq!(a.bytes(), b"ABC");
+                assert_eq!(b.bytes(), b"Hello");
+            }
+            _ => panic!("expected strings"),
+        }
+        assert_eq!(o[1].num(), Some(1.));
+    }
+
+    #[test]
+    fn decoders() {
+        let mut it = b"616263>".iter();
+        assert_eq!(decode_hex(|| it.next().copied()), b"abc");
+        let mut it = [2u8, b'a', b'b', b'c', 253, b'x', 128].iter();
+        assert_eq!(decode_runlength(|| it.next().copied()), b"abcxxxx");
+        let mut it = b"z9jqo^~>".iter();
+        assert_eq!(decode_a85(|| it.next().copied()).unwrap(), b"\0\0\0\0Man ");
+    }
+
+    #[test]
+    fn bounding_boxes() {
+        let ps = b"%!PS-Adobe-3.0 EPSF-3.0\n%%BoundingBox: (atend)\n%%EndComments\n\
+                   showpage\n%%Trailer\n%%BoundingBox: 10 20 110 220\n";
+        let bbox = scan_bbox(ps).unwrap();
+        assert_eq!(
+            (bbox.ll.x, bbox.ll.y, bbox.ur.x, bbox.ur.y),
+            (10., 20., 110., 220.)
+        );
+
+        let mut dos = DOS_EPS_MAGIC.to_vec();
+        dos.extend_from_slice(&30u32.to_le_bytes());
+        dos.extend_from_slice(&(ps.len() as u32).to_le_bytes());
+        dos.resize(30, 0);
+        dos.extend_from_slice(ps);
+        dos.extend_from_slice(b"TIFF preview");
+        assert_eq!(ps_section(&dos), Some(&ps[..]));
+    }
+}
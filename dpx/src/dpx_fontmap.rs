@@ -28,7 +28,8 @@
 )]
 
 use crate::DisplayExt;
-use std::ffi::CStr;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
 
 use super::dpx_mfileio::work_buffer;
 use crate::mfree;
@@ -37,12 +38,14 @@ use crate::{streq_ptr, strstartswith};
 
 use super::dpx_dpxfile::dpx_tt_open;
 use super::dpx_dpxutil::{
-    ht_clear_table, ht_init_table, ht_insert_table, ht_lookup_table, ht_remove_table,
+    ht_clear_iter, ht_clear_table, ht_init_table, ht_insert_table, ht_iter, ht_iter_getkey,
+    ht_iter_getval, ht_iter_next, ht_lookup_table, ht_remove_table, ht_set_iter,
 };
 use super::dpx_dpxutil::{parse_c_string, parse_float_decimal};
 use super::dpx_mem::{new, xmalloc};
 use super::dpx_mfileio::tt_mfgets;
 use super::dpx_subfont::{release_sfd_record, sfd_get_subfont_ids};
+use super::dpx_tfm::tfm_exists;
 use crate::shims::sprintf;
 use crate::ttstub_input_close;
 use libc::{
@@ -69,6 +72,7 @@ pub struct fontmap_opt {
     pub index: i32,
     pub style: i32,
     pub stemv: i32,
+    pub variations: *mut i8,
 }
 #[derive(Copy, Clone)]
 #[repr(C)]
@@ -123,6 +127,7 @@ pub unsafe extern "C" fn pdf_init_fontmap_record(mut mrec: *mut fontmap_rec) {
     (*mrec).opt.style = 0i32;
     (*mrec).opt.stemv = -1i32;
     (*mrec).opt.cff_charsets = 0 as *mut libc::c_void;
+    (*mrec).opt.variations = 0 as *mut i8;
 }
 #[no_mangle]
 pub unsafe extern "C" fn pdf_clear_fontmap_record(mut mrec: *mut fontmap_rec) {
@@ -135,6 +140,7 @@ pub unsafe extern "C" fn pdf_clear_fontmap_record(mut mrec: *mut fontmap_rec) {
     free((*mrec).opt.tounicode as *mut libc::c_void);
     free((*mrec).opt.otl_tags as *mut libc::c_void);
     free((*mrec).opt.charcoll as *mut libc::c_void);
+    free((*mrec).opt.variations as *mut libc::c_void);
     pdf_init_fontmap_record(mrec);
 }
 /* strdup: just returns NULL for NULL */
@@ -165,6 +171,7 @@ unsafe fn pdf_copy_fontmap_record(mut dst: *mut fontmap_rec, mut src: *const fon
     (*dst).opt.style = (*src).opt.style;
     (*dst).opt.stemv = (*src).opt.stemv;
     (*dst).opt.cff_charsets = (*src).opt.cff_charsets;
+    (*dst).opt.variations = mstrdup((*src).opt.variations);
 }
 unsafe extern "C" fn hval_free(mut vp: *mut libc::c_void) {
     let mut mrec: *mut fontmap_rec = vp as *mut fontmap_rec;
@@ -1151,6 +1158,145 @@ pub unsafe extern "C" fn is_pdfm_mapline(mut mline: *const i8) -> i32
         1i32
     }
 }
+/* Tectonic: where the records of the font map came from, so that
+ * dvi_locate_font() can tell how TFM names were resolved. Only kept while font
+ * map diagnostics are enabled. */
+static mut fontmap_sources: Option<HashMap<Vec<u8>, String>> = None;
+/* Tectonic: the map files and lines that pdf_load_fontmap_requests() applies,
+ * each with a mode as for pdf_load_fontmap_file(). */
+static mut fontmap_requests: Vec<(CString, bool, i32)> = Vec::new();
+#[no_mangle]
+pub unsafe extern "C" fn pdf_fontmap_add_request(
+    mut name: *const i8,
+    mut is_line: i32,
+    mut mode: i32,
+) {
+    fontmap_requests.push((CStr::from_ptr(name).to_owned(), is_line != 0, mode));
+}
+#[no_mangle]
+pub unsafe extern "C" fn pdf_fontmap_clear_requests() {
+    fontmap_requests.clear();
+}
+pub unsafe fn pdf_fontmap_set_diagnostics(enable: bool) {
+    fontmap_sources = if enable { Some(HashMap::new()) } else { None };
+}
+pub unsafe fn pdf_fontmap_diagnostics() -> bool {
+    fontmap_sources.is_some()
+}
+/// Describes where the font map record with this key came from: a line of
+/// a map file, or a map line given by the caller. Records added by specials
+/// are not tracked.
+pub unsafe fn pdf_fontmap_record_source(key: &[u8]) -> Option<String> {
+    fontmap_sources.as_ref().and_then(|s| s.get(key).cloned())
+}
+/* Enters a record read from a map file (with its line number) or from a map
+ * line into the font map: mode 0 inserts it, replacing any record of the same
+ * TFM name, '+' only adds it if there is none, and '-' removes that record. */
+unsafe fn apply_fontmap_record(mrec: *mut fontmap_rec, mode: i32, from: Option<(&CStr, i32)>) {
+    let key = (*mrec).map_name;
+    let known = !pdf_lookup_fontmap_record(CStr::from_ptr(key).to_bytes()).is_null();
+    match mode {
+        0 => {
+            pdf_insert_fontmap_record(key, mrec);
+        }
+        43 => {
+            pdf_append_fontmap_record(key, mrec);
+        }
+        45 => {
+            pdf_remove_fontmap_record(key);
+        }
+        _ => {}
+    }
+    if let Some(ref mut sources) = fontmap_sources {
+        let source = match from {
+            Some((filename, lpos)) => format!("{} line {}", filename.display(), lpos),
+            None => "a map line".to_owned(),
+        };
+        let name = CStr::from_ptr(key);
+        let key = name.to_bytes().to_vec();
+        match mode {
+            0 => {
+                sources.insert(key, source);
+            }
+            43 if !known => {
+                sources.insert(key, source);
+            }
+            43 => {
+                let previous = sources
+                    .get(&key)
+                    .map(|s| format!(" from {}", s))
+                    .unwrap_or_default();
+                info!(
+                    "\nDuplicate font map entry \"{}\" in {} ignored; the entry{} is kept\n",
+                    name.display(),
+                    source,
+                    previous,
+                );
+            }
+            45 if !known => {
+                info!(
+                    "\nNo font map entry \"{}\" to remove, as {} asks\n",
+                    name.display(),
+                    source,
+                );
+            }
+            45 => {
+                sources.remove(&key);
+            }
+            _ => {}
+        }
+    }
+}
+/// Applies a single font map line, in the dvipdfm or the dvips/pdfTeX
+/// format, with a mode as for `pdf_load_fontmap_file()`. To remove a record,
+/// giving its TFM name is enough.
+pub unsafe fn pdf_load_fontmap_line(line: &CStr, mode: i32) -> i32 {
+    assert!(!fontmap.is_null());
+    let mut p = line.as_ptr();
+    let endptr = p.offset(line.to_bytes().len() as isize);
+    skip_blank(&mut p, endptr);
+    if p == endptr {
+        return 0i32;
+    }
+    let mrec = new((1_u64).wrapping_mul(::std::mem::size_of::<fontmap_rec>() as u64) as u32)
+        as *mut fontmap_rec;
+    pdf_init_fontmap_record(mrec);
+    let error = if mode == '-' as i32 {
+        (*mrec).map_name = parse_string_value(&mut p, endptr);
+        if (*mrec).map_name.is_null() {
+            -1i32
+        } else {
+            0i32
+        }
+    } else {
+        pdf_read_fontmap_line(
+            mrec,
+            p,
+            endptr.wrapping_offset_from(p) as i32,
+            is_pdfm_mapline(p),
+        )
+    };
+    if error != 0 {
+        warn!("Invalid font map line: {}", line.display());
+    } else {
+        apply_fontmap_record(mrec, mode, None);
+    }
+    pdf_clear_fontmap_record(mrec);
+    free(mrec as *mut libc::c_void);
+    error
+}
+/* Tectonic: load the map files and lines that the caller asked for, in order.
+ * They take the place of the pdftex.map, kanjix.map and ckx.map that
+ * dvipdfmx would load. */
+pub unsafe fn pdf_load_fontmap_requests() {
+    for &(ref name, is_line, mode) in fontmap_requests.iter() {
+        if is_line {
+            pdf_load_fontmap_line(name, mode);
+        } else {
+            pdf_load_fontmap_file(name, mode);
+        }
+    }
+}
 #[no_mangle]
 pub unsafe extern "C" fn pdf_load_fontmap_file(filename: &CStr, mut mode: i32) -> i32 {
     let mut p: *const i8 = 0 as *const i8;
@@ -1217,18 +1363,7 @@ pub unsafe extern "C" fn pdf_load_fontmap_file(filename: &CStr, mut mode: i32) -
                 pdf_clear_fontmap_record(mrec);
                 free(mrec as *mut libc::c_void);
             } else {
-                match mode {
-                    0 => {
-                        pdf_insert_fontmap_record((*mrec).map_name, mrec);
-                    }
-                    43 => {
-                        pdf_append_fontmap_record((*mrec).map_name, mrec);
-                    }
-                    45 => {
-                        pdf_remove_fontmap_record((*mrec).map_name);
-                    }
-                    _ => {}
-                }
+                apply_fontmap_record(mrec, mode, Some((filename, lpos)));
                 pdf_clear_fontmap_record(mrec);
                 free(mrec as *mut libc::c_void);
             }
@@ -1248,9 +1383,15 @@ pub unsafe extern "C" fn pdf_insert_native_fontmap_record(
     mut extend: i32,
     mut slant: i32,
     mut embolden: i32,
+    mut variations: *const i8,
 ) -> *mut fontmap_rec {
     assert!(!path.is_null());
-    let fontmap_key = xmalloc(strlen(path).wrapping_add(40) as _) as *mut i8;
+    let varlen = if variations.is_null() {
+        0
+    } else {
+        strlen(variations).wrapping_add(1)
+    };
+    let fontmap_key = xmalloc(strlen(path).wrapping_add(40).wrapping_add(varlen) as _) as *mut i8;
     sprintf(
         fontmap_key,
         b"%s/%d/%c/%d/%d/%d\x00" as *const u8 as *const i8,
@@ -1265,6 +1406,11 @@ pub unsafe extern "C" fn pdf_insert_native_fontmap_record(
         slant,
         embolden,
     );
+    /* Each instance of a variable font is a font of its own. */
+    if !variations.is_null() {
+        strcat(fontmap_key, b"/\x00" as *const u8 as *const i8);
+        strcat(fontmap_key, variations);
+    }
     if verbose != 0 {
         info!("<NATIVE-FONTMAP:{}", CStr::from_ptr(fontmap_key).display(),);
     }
@@ -1287,6 +1433,7 @@ pub unsafe extern "C" fn pdf_insert_native_fontmap_record(
     (*mrec).opt.extend = extend as f64 / 65536.0f64;
     (*mrec).opt.slant = slant as f64 / 65536.0f64;
     (*mrec).opt.bold = embolden as f64 / 65536.0f64;
+    (*mrec).opt.variations = mstrdup(variations);
     let ret = pdf_insert_fontmap_record((*mrec).map_name, mrec);
     pdf_clear_fontmap_record(mrec);
     free(mrec as *mut libc::c_void);
@@ -1307,6 +1454,54 @@ pub unsafe extern "C" fn pdf_lookup_fontmap_record(tfm_name: &[u8]) -> *mut font
     }
     mrec
 }
+/// Finds the map key of a record by the PostScript name of its font, as
+/// used by EPS figures that call `findfont` on names like "Helvetica".
+/// Records that keep the font's builtin encoding are preferred.
+pub unsafe fn pdf_lookup_fontmap_key_by_font_name(font_name: &[u8]) -> Option<Vec<u8>> {
+    if fontmap.is_null() || font_name.is_empty() {
+        return None;
+    }
+    let mut iter: ht_iter = ht_iter {
+        index: 0,
+        curr: 0 as *mut libc::c_void,
+        hash: 0 as *mut ht_table,
+    };
+    let mut found: Option<Vec<u8>> = None;
+    if ht_set_iter(fontmap, &mut iter) >= 0i32 {
+        loop {
+            let mrec = ht_iter_getval(&mut iter) as *const fontmap_rec;
+            if !mrec.is_null()
+                && !(*mrec).font_name.is_null()
+                && CStr::from_ptr((*mrec).font_name).to_bytes() == font_name
+            {
+                let mut keylen: i32 = 0;
+                let key = ht_iter_getkey(&mut iter, &mut keylen);
+                let key = std::slice::from_raw_parts(key as *const u8, keylen as usize);
+                let builtin = (*mrec).enc_name.is_null();
+                if found.is_none() || builtin {
+                    found = Some(key.to_vec());
+                }
+                if builtin {
+                    break;
+                }
+            }
+            if !(ht_iter_next(&mut iter) >= 0i32) {
+                break;
+            }
+        }
+        ht_clear_iter(&mut iter);
+    }
+    found
+}
+/// Finds the map key (TFM name) for a font that a figure refers to by
+/// name: either a map key or TFM name itself, or the PostScript name of a
+/// mapped font.
+pub unsafe fn pdf_resolve_font_key(name: &[u8]) -> Option<Vec<u8>> {
+    if !pdf_lookup_fontmap_record(name).is_null() || tfm_exists(name) {
+        return Some(name.to_vec());
+    }
+    pdf_lookup_fontmap_key_by_font_name(name)
+}
 #[no_mangle]
 pub unsafe extern "C" fn pdf_init_fontmaps() {
     fontmap =
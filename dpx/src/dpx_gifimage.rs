@@ -0,0 +1,423 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2002-2016 by Jin-Hwan Cho and Shunsaku Hirata,
+    the dvipdfmx project team.
+
+    Copyright (C) 1998, 1999 by Mark A. Wicks <mwicks@kettering.edu>
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! GIF images. Only the first frame of an animation is included; it is
+//! drawn on the logical screen as an Indexed image, with the transparent
+//! color (if any) masked out by a color-key mask.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::warn;
+
+use super::dpx_pdfximage::{
+    pdf_ximage_indexed_cspace, pdf_ximage_init_image_info, pdf_ximage_set_image,
+};
+use crate::dpx_pdfobj::{
+    pdf_add_array, pdf_add_dict, pdf_add_stream, pdf_get_version, pdf_new_array, pdf_new_name,
+    pdf_new_number, pdf_new_stream, STREAM_COMPRESS,
+};
+
+use bridge::InputHandleWrapper;
+
+use crate::dpx_pdfximage::{pdf_ximage, ximage_info};
+
+/// The first frame of a GIF, placed on its logical screen.
+struct GifImage {
+    width: usize,
+    height: usize,
+    /// Width of a pixel relative to its height.
+    aspect: f64,
+    palette: Vec<u8>,
+    transparent: Option<u8>,
+    indices: Vec<u8>,
+}
+
+fn le16(b: &[u8]) -> usize {
+    b[0] as usize | (b[1] as usize) << 8
+}
+
+/// Concatenates the data sub-blocks starting at `*pos`.
+fn read_sub_blocks(data: &[u8], pos: &mut usize) -> Result<Vec<u8>, &'static str> {
+    let mut out = Vec::new();
+    loop {
+        let len = *data.get(*pos).ok_or("Unexpected end of file")? as usize;
+        *pos += 1;
+        if len == 0 {
+            return Ok(out);
+        }
+        let block = data.get(*pos..*pos + len).ok_or("Unexpected end of file")?;
+        out.extend_from_slice(block);
+        *pos += len;
+    }
+}
+
+/// Decodes the variable-length-code LZW data of an image.
+fn lzw_decode(data: &[u8], min_code_size: u8, npixels: usize) -> Result<Vec<u8>, &'static str> {
+    if !(2..=11).contains(&min_code_size) {
+        return Err("Invalid LZW code size");
+    }
+    let clear = 1usize << min_code_size;
+    let end = clear + 1;
+    /* Each code is a (prefix code, last byte, length) triple. */
+    let mut prefix = vec![0u16; 4096];
+    let mut suffix = vec![0u8; 4096];
+    let mut length = vec![0u16; 4096];
+    for i in 0..clear {
+        suffix[i] = i as u8;
+        length[i] = 1;
+    }
+    let mut out = Vec::with_capacity(npixels);
+    let mut next = end + 1;
+    let mut code_size = min_code_size as u32 + 1;
+    let mut prev: Option<usize> = None;
+    let (mut bits, mut nbits, mut pos) = (0u32, 0u32, 0usize);
+    while out.len() < npixels {
+        while nbits < code_size {
+            match data.get(pos) {
+                Some(&b) => bits |= (b as u32) << nbits,
+                /* Some encoders omit the end code and any trailing bits. */
+                None => return Ok(out),
+            }
+            pos += 1;
+            nbits += 8;
+        }
+        let code = (bits & ((1 << code_size) - 1)) as usize;
+        bits >>= code_size;
+        nbits -= code_size;
+        if code == clear {
+            next = end + 1;
+            code_size = min_code_size as u32 + 1;
+            prev = None;
+            continue;
+        } else if code == end {
+            break;
+        }
+        let prev_code = match prev {
+            Some(p) => p,
+            None => {
+                if code >= clear {
+                    return Err("Invalid LZW code");
+                }
+                out.push(code as u8);
+                prev = Some(code);
+                continue;
+            }
+        };
+        let known = code < next;
+        if !known && code != next {
+            return Err("Invalid LZW code");
+        }
+        /* Output the string of the code, or of the previous code followed
+         * by its own first byte for a code not yet in the table. */
+        let emit = if known { code } else { prev_code };
+        let start = out.len();
+        let n = length[emit] as usize;
+        out.resize(start + n, 0);
+        let mut c = emit;
+        for i in (0..n).rev() {
+            out[start + i] = suffix[c];
+            c = prefix[c] as usize;
+        }
+        let first = out[start];
+        if !known {
+            out.push(first);
+        }
+        if next < 4096 {
+            prefix[next] = prev_code as u16;
+            suffix[next] = first;
+            length[next] = length[prev_code] + 1;
+            next += 1;
+            if next == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        }
+        prev = Some(code);
+    }
+    out.truncate(npixels);
+    Ok(out)
+}
+
+fn read_header(data: &[u8]) -> Result<(usize, usize, f64), &'static str> {
+    if data.len() < 13 || (&data[..6] != b"GIF87a" && &data[..6] != b"GIF89a") {
+        return Err("Not a GIF file");
+    }
+    let (width, height) = (le16(&data[6..]), le16(&data[8..]));
+    let aspect = match data[12] {
+        0 => 1.,
+        a => (a as f64 + 15.) / 64.,
+    };
+    Ok((width, height, aspect))
+}
+
+fn read_gif(data: &[u8]) -> Result<GifImage, &'static str> {
+    let (mut width, mut height, aspect) = read_header(data)?;
+    let flags = data[10];
+    let background = data[11] as usize;
+    let mut pos = 13;
+    let mut palette = Vec::new();
+    if flags & 0x80 != 0 {
+        let len = 3 << ((flags & 7) + 1);
+        palette = data
+            .get(pos..pos + len)
+            .ok_or("Unexpected end of file")?
+            .to_vec();
+        pos += len;
+    }
+    let mut transparent = None;
+    loop {
+        match data.get(pos) {
+            Some(0x21) => {
+                let label = *data.get(pos + 1).ok_or("Unexpected end of file")?;
+                pos += 2;
+                let block = read_sub_blocks(data, &mut pos)?;
+                /* Graphic Control Extension */
+                if label == 0xf9 && block.len() >= 4 {
+                    transparent = if block[0] & 1 != 0 {
+                        Some(block[3])
+                    } else {
+                        None
+                    };
+                }
+            }
+            Some(0x2c) => break,
+            Some(0x3b) | None => return Err("No image in file"),
+            Some(_) => return Err("Corrupt block structure"),
+        }
+    }
+    let desc = data
+        .get(pos + 1..pos + 10)
+        .ok_or("Unexpected end of file")?;
+    let (left, top) = (le16(desc), le16(&desc[2..]));
+    let (fw, fh) = (le16(&desc[4..]), le16(&desc[6..]));
+    let fflags = desc[8];
+    pos += 10;
+    if fflags & 0x80 != 0 {
+        let len = 3 << ((fflags & 7) + 1);
+        palette = data
+            .get(pos..pos + len)
+            .ok_or("Unexpected end of file")?
+            .to_vec();
+        pos += len;
+    }
+    if palette.is_empty() {
+        return Err("No color table");
+    }
+    /* Some files have a logical screen that is too small for their image. */
+    width = width.max(left + fw);
+    height = height.max(top + fh);
+    if width == 0 || height == 0 {
+        return Err("Empty image");
+    }
+    let min_code_size = *data.get(pos).ok_or("Unexpected end of file")?;
+    pos += 1;
+    let lzw = read_sub_blocks(data, &mut pos)?;
+    let mut frame = lzw_decode(&lzw, min_code_size, fw * fh)?;
+    if frame.len() < fw * fh {
+        warn!("GIF: Image data is truncated.");
+        frame.resize(fw * fh, 0);
+    }
+    if has_more_frames(data, pos) {
+        warn!("GIF: Animated image; only its first frame is included.");
+    }
+    /* The logical screen outside of the frame is left transparent if the
+     * palette has room for a color to mask out, else filled with the
+     * background color. */
+    let ncolors = palette.len() / 3;
+    let fill = if fw == width && fh == height {
+        0
+    } else if let Some(t) = transparent {
+        t
+    } else if ncolors < 256 {
+        palette.extend_from_slice(&[0xff, 0xff, 0xff]);
+        transparent = Some(ncolors as u8);
+        ncolors as u8
+    } else {
+        background.min(255) as u8
+    };
+    let mut indices = vec![fill; width * height];
+    let rows: Vec<usize> = if fflags & 0x40 != 0 {
+        /* Interlaced: rows 0, 8, 16, ..., then 4, 12, ..., 2, 6, ..., 1, 3, ... */
+        [(0, 8), (4, 8), (2, 4), (1, 2)]
+            .iter()
+            .flat_map(|&(start, step)| (start..fh).step_by(step))
+            .collect()
+    } else {
+        (0..fh).collect()
+    };
+    for (i, &row) in rows.iter().enumerate() {
+        let dst = (top + row) * width + left;
+        indices[dst..dst + fw].copy_from_slice(&frame[i * fw..(i + 1) * fw]);
+    }
+    Ok(GifImage {
+        width,
+        height,
+        aspect,
+        palette,
+        transparent,
+        indices,
+    })
+}
+
+/// Checks whether another image follows the one whose data ended at `pos`.
+fn has_more_frames(data: &[u8], mut pos: usize) -> bool {
+    loop {
+        match data.get(pos) {
+            Some(0x2c) => return true,
+            Some(0x21) => {
+                pos += 2;
+                if read_sub_blocks(data, &mut pos).is_err() {
+                    return false;
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn check_for_gif(handle: &mut InputHandleWrapper) -> i32 {
+    let mut sigbytes = [0u8; 6];
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    if handle.read_exact(&mut sigbytes).is_err()
+        || (&sigbytes != b"GIF87a" && &sigbytes != b"GIF89a")
+    {
+        return 0;
+    }
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gif_get_bbox(
+    handle: &mut InputHandleWrapper,
+    width: *mut u32,
+    height: *mut u32,
+    xdensity: *mut f64,
+    ydensity: *mut f64,
+) -> i32 {
+    let mut data = Vec::new();
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    if handle.read_to_end(&mut data).is_err() {
+        warn!("GIF: Reading file failed.");
+        return -1;
+    }
+    match read_gif(&data) {
+        Ok(image) => {
+            *width = image.width as u32;
+            *height = image.height as u32;
+            *xdensity = image.aspect;
+            *ydensity = 1.;
+            0
+        }
+        Err(e) => {
+            warn!("GIF: {}.", e);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn gif_include_image(
+    ximage: *mut pdf_ximage,
+    handle: &mut InputHandleWrapper,
+) -> i32 {
+    let mut info = ximage_info::default();
+    pdf_ximage_init_image_info(&mut info);
+    let mut data = Vec::new();
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    if handle.read_to_end(&mut data).is_err() {
+        warn!("GIF: Reading file failed.");
+        return -1;
+    }
+    let image = match read_gif(&data) {
+        Ok(image) => image,
+        Err(e) => {
+            warn!("GIF: {}.", e);
+            return -1;
+        }
+    };
+    info.width = image.width as i32;
+    info.height = image.height as i32;
+    info.bits_per_component = 8;
+    info.num_components = 1;
+    info.xdensity = image.aspect;
+    info.ydensity = 1.;
+    let stream = pdf_new_stream(STREAM_COMPRESS);
+    let stream_dict = (*stream).as_stream_mut().get_dict_mut();
+    let colorspace = pdf_ximage_indexed_cspace(pdf_new_name("DeviceRGB"), 3, &image.palette);
+    pdf_add_dict(stream_dict, "ColorSpace", colorspace);
+    if let Some(t) = image.transparent {
+        if pdf_get_version() < 3 {
+            warn!("GIF: Transparency not supported. You may want to use \"-V 3\" option.");
+        } else {
+            let colorkeys = pdf_new_array();
+            pdf_add_array(&mut *colorkeys, pdf_new_number(t as f64));
+            pdf_add_array(&mut *colorkeys, pdf_new_number(t as f64));
+            pdf_add_dict(stream_dict, "Mask", colorkeys);
+        }
+    }
+    pdf_add_stream(
+        &mut *stream,
+        image.indices.as_ptr() as *const libc::c_void,
+        image.indices.len() as i32,
+    );
+    pdf_ximage_set_image(ximage, &mut info, stream);
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A 3x2 interlaced frame at (1,1) on a 4x4 screen, with a transparent
+    /// color, followed by a second frame.
+    const SAMPLE: &[u8] = b"GIF89a\x04\x00\x04\x00\x80\x00\x00\
+        \x00\x00\x00\xff\xff\xff\
+        \x21\xf9\x04\x05\x00\x00\x01\x00\
+        \x2c\x01\x00\x01\x00\x03\x00\x02\x00\x40\x02\x03\x44\x62\x50\x00\
+        \x21\xf9\x04\x04\x00\x00\x00\x00\
+        \x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00\x02\x02\x4c\x01\x00\x3b";
+
+    #[test]
+    fn lzw_codes() {
+        let out = lzw_decode(&[0x44, 0x62, 0x50], 2, 6).unwrap();
+        assert_eq!(out, vec![0, 1, 1, 0, 1, 0]);
+        /* A code that is neither defined nor the next one to be defined */
+        assert!(lzw_decode(&[0x07], 2, 4).is_err());
+    }
+
+    #[test]
+    fn first_frame() {
+        let image = read_gif(SAMPLE).unwrap();
+        assert_eq!((image.width, image.height), (4, 4));
+        assert_eq!(image.transparent, Some(1));
+        assert_eq!(image.palette.len(), 6);
+        #[rustfmt::skip]
+        assert_eq!(image.indices, vec![
+            1, 1, 1, 1,
+            1, 0, 1, 1,
+            1, 0, 1, 0,
+            1, 1, 1, 1,
+        ]);
+        assert!(has_more_frames(SAMPLE, 43));
+        assert!(!has_more_frames(SAMPLE, SAMPLE.len() - 1));
+    }
+}
@@ -0,0 +1,839 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2002-2016 by Jin-Hwan Cho and Shunsaku Hirata,
+    the dvipdfmx project team.
+
+    Copyright (C) 1998, 1999 by Mark A. Wicks <mwicks@kettering.edu>
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+#![allow(non_upper_case_globals)]
+
+//! A content-addressed cache of finished raster image XObjects.
+//!
+//! An entry is keyed by the SHA-256 digest of the image file together with
+//! everything else that shapes the XObject: the load options, the PDF
+//! version and the compression settings. It holds the image stream and
+//! every object that was referred to for the first time while the image was
+//! being loaded (soft masks, XMP metadata, ...), with compressible streams
+//! already deflated. ICC based color spaces are shared between images, so
+//! they are kept as the profile they were loaded from and loaded again when
+//! an entry is used.
+
+use crate::warn;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use super::dpx_pdfcolor::{iccp_load_profile, pdf_colorspace_label, pdf_get_colorspace_reference};
+use super::dpx_pdfobj::{
+    pdf_add_array, pdf_add_dict, pdf_boolean_value, pdf_dict, pdf_get_compression, pdf_get_version,
+    pdf_indirect, pdf_link_obj, pdf_name, pdf_new_array, pdf_new_boolean, pdf_new_dict,
+    pdf_new_name, pdf_new_null, pdf_new_number, pdf_new_stream, pdf_new_string, pdf_number_value,
+    pdf_obj, pdf_ref_obj, pdf_release_obj, pdf_stream_precompress, pdf_string_length,
+    pdf_string_value, pdf_unwatch_labels, pdf_watch_labels, PdfObjType,
+};
+use super::dpx_pdfximage::{load_options, ximage_info};
+use bridge::InputHandleWrapper;
+
+const MAGIC: &[u8] = b"tectonic-ximage\x01";
+
+static mut cache_dir: Option<PathBuf> = None;
+static mut recording: Option<Recording> = None;
+
+struct Recording {
+    root: *mut pdf_obj,
+    info: ximage_info,
+    profiles: Vec<Profile>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Profile {
+    ident: Option<Vec<u8>>,
+    data: Vec<u8>,
+    cspc_id: i32,
+}
+
+/// An image XObject taken from the cache, ready for `pdf_ximage_set_image()`.
+pub struct CachedImage {
+    pub info: ximage_info,
+    pub page_count: i32,
+    pub resource: *mut pdf_obj,
+}
+
+/// A PDF object as it is stored in a cache entry. Indirect references are
+/// to earlier objects of the entry or to one of its ICC profiles.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(Vec<u8>),
+    Name(Vec<u8>),
+    Array(Vec<Value>),
+    Dict(Vec<(Vec<u8>, Value)>),
+    Stream {
+        flags: i32,
+        decodeparms: [i32; 4],
+        dict: Vec<(Vec<u8>, Value)>,
+        data: Vec<u8>,
+    },
+    Object(u32),
+    Profile(u32),
+}
+
+#[derive(Debug, PartialEq)]
+struct Entry {
+    info: ximage_info,
+    page_count: i32,
+    profiles: Vec<Profile>,
+    /* Object flags and value; the last one is the image itself */
+    objects: Vec<(i32, Value)>,
+}
+
+/// Sets the directory cache entries are kept in; `None` disables the cache.
+pub unsafe fn set_cache_dir(dir: Option<PathBuf>) {
+    cache_dir = dir;
+}
+
+/// Computes the cache key of an image, or `None` if it is not to be cached.
+/// With `keep_raw`, the image stream itself is stored uncompressed, for
+/// downsampling.
+pub unsafe fn image_key(
+    format: i32,
+    options: &load_options,
+    keep_raw: bool,
+    handle: &mut InputHandleWrapper,
+) -> Option<[u8; 32]> {
+    cache_dir.as_ref()?;
+    let mut sha = Sha256::new();
+    sha.input(MAGIC);
+    let (level, predictor) = pdf_get_compression();
+    for n in &[
+        format,
+        options.page_no,
+        options.bbox_type,
+        pdf_get_version() as i32,
+        level,
+        predictor as i32,
+        keep_raw as i32,
+    ] {
+        sha.input(&n.to_le_bytes());
+    }
+    if !options.dict.is_null() {
+        /* References cannot be told apart from one run to the next. */
+        let mut enc = Encoder::new(HashMap::new(), HashMap::new());
+        let mut out = Vec::new();
+        write_value(&enc.value(options.dict)?, &mut out);
+        sha.input(&out);
+    }
+    handle.seek(SeekFrom::Start(0)).ok()?;
+    let mut buf = vec![0; 65536];
+    loop {
+        let n = handle.read(&mut buf).ok()?;
+        if n == 0 {
+            break;
+        } else if n > buf.len() {
+            return None;
+        }
+        sha.input(&buf[..n]);
+    }
+    handle.seek(SeekFrom::Start(0)).ok()?;
+    let mut key = [0; 32];
+    key.copy_from_slice(&sha.result());
+    Some(key)
+}
+
+unsafe fn entry_path(key: &[u8; 32]) -> Option<PathBuf> {
+    let name: String = key.iter().map(|b| format!("{:02x}", b)).collect();
+    Some(cache_dir.as_ref()?.join(format!("{}.ximage", name)))
+}
+
+/// Looks an image up in the cache, creating its objects if it is found.
+pub unsafe fn fetch(key: &[u8; 32]) -> Option<CachedImage> {
+    let data = fs::read(entry_path(key)?).ok()?;
+    let entry = match read_entry(&data) {
+        Some(entry) => entry,
+        None => {
+            warn!("Ignoring damaged image cache entry.");
+            return None;
+        }
+    };
+    let mut cspc_ids = Vec::new();
+    for profile in &entry.profiles {
+        let ident = profile
+            .ident
+            .as_ref()
+            .map(|s| CString::new(s.clone()).unwrap());
+        let cspc_id = iccp_load_profile(
+            ident
+                .as_ref()
+                .map_or(b"\x00".as_ptr() as *const i8, |s| s.as_ptr()),
+            profile.data.as_ptr() as *const libc::c_void,
+            profile.data.len() as i32,
+        );
+        if cspc_id < 0 {
+            return None;
+        }
+        cspc_ids.push(cspc_id);
+    }
+    let mut objects: Vec<*mut pdf_obj> = Vec::new();
+    for (flags, value) in &entry.objects {
+        let obj = build_value(value, &objects, &cspc_ids);
+        (*obj).flags = *flags;
+        objects.push(obj);
+    }
+    let resource = objects.pop()?;
+    for obj in objects {
+        pdf_release_obj(obj);
+    }
+    Some(CachedImage {
+        info: entry.info,
+        page_count: entry.page_count,
+        resource,
+    })
+}
+
+/// Starts recording the objects of an image that is about to be loaded.
+pub unsafe fn start_recording() {
+    recording = Some(Recording {
+        root: 0 as *mut pdf_obj,
+        info: ximage_info::default(),
+        profiles: Vec::new(),
+    });
+    pdf_watch_labels();
+}
+
+/// Called by `pdf_ximage_set_image()` with the image being recorded.
+pub unsafe fn note_image(info: &ximage_info, resource: *mut pdf_obj) {
+    if let Some(r) = recording.as_mut() {
+        if r.root.is_null() {
+            r.root = pdf_link_obj(resource);
+            r.info = *info;
+        }
+    }
+}
+
+/// Called by `iccp_load_profile()` for every profile it loads.
+pub unsafe fn note_icc_profile(
+    ident: *const i8,
+    profile: *const libc::c_void,
+    proflen: i32,
+    cspc_id: i32,
+) {
+    if let Some(r) = recording.as_mut() {
+        if cspc_id >= 0 && proflen > 0 {
+            r.profiles.push(Profile {
+                ident: if ident.is_null() {
+                    None
+                } else {
+                    Some(CStr::from_ptr(ident).to_bytes().to_vec())
+                },
+                data: std::slice::from_raw_parts(profile as *const u8, proflen as usize).to_vec(),
+                cspc_id,
+            });
+        }
+    }
+}
+
+/// Stops recording and, given the key, stores what was recorded. The
+/// objects held on to meanwhile are released, so that they get written.
+pub unsafe fn finish_recording(key: Option<&[u8; 32]>, page_count: i32, keep_raw: bool) {
+    let watched = pdf_unwatch_labels();
+    let r = match recording.take() {
+        Some(r) => r,
+        None => return,
+    };
+    if let (Some(key), false) = (key, r.root.is_null()) {
+        for &obj in &watched {
+            if (*obj).is_stream() && !(keep_raw && obj == r.root) {
+                pdf_stream_precompress(&mut *obj);
+            }
+        }
+        if let Some(entry) = make_entry(&r, &watched, page_count) {
+            let mut data = Vec::new();
+            write_entry(&entry, &mut data);
+            if let Err(e) = store(key, &data) {
+                warn!("Could not write image cache entry: {}", e);
+            }
+        }
+    }
+    for obj in watched {
+        pdf_release_obj(obj);
+    }
+    pdf_release_obj(r.root);
+}
+
+unsafe fn store(key: &[u8; 32], data: &[u8]) -> std::io::Result<()> {
+    let path = entry_path(key).unwrap();
+    fs::create_dir_all(path.parent().unwrap())?;
+    /* Write under a temporary name so that readers never see half an entry */
+    let temp = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&temp, data)?;
+    fs::rename(&temp, &path)
+}
+
+unsafe fn make_entry(r: &Recording, watched: &[*mut pdf_obj], page_count: i32) -> Option<Entry> {
+    let labels = watched.iter().map(|&obj| ((*obj).label, obj)).collect();
+    let mut profiles = Vec::new();
+    let mut profile_labels = HashMap::new();
+    for profile in &r.profiles {
+        if let Some(label) = pdf_colorspace_label(profile.cspc_id) {
+            if !profile_labels.contains_key(&label) {
+                profile_labels.insert(label, profiles.len() as u32);
+                profiles.push(profile.clone());
+            }
+        }
+    }
+    let mut enc = Encoder::new(labels, profile_labels);
+    enc.object(r.root)?;
+    /* Profiles nothing refers to are left out. */
+    let used: HashSet<u32> = enc.profiles_used.iter().copied().collect();
+    let mut renumber = HashMap::new();
+    let mut kept = Vec::new();
+    for (i, profile) in profiles.into_iter().enumerate() {
+        if used.contains(&(i as u32)) {
+            renumber.insert(i as u32, kept.len() as u32);
+            kept.push(profile);
+        }
+    }
+    let objects = enc
+        .objects
+        .into_iter()
+        .map(|(flags, mut value)| {
+            renumber_profiles(&mut value, &renumber);
+            (flags, value)
+        })
+        .collect();
+    Some(Entry {
+        info: r.info,
+        page_count,
+        profiles: kept,
+        objects,
+    })
+}
+
+fn renumber_profiles(value: &mut Value, renumber: &HashMap<u32, u32>) {
+    match value {
+        Value::Profile(i) => *i = renumber[&*i],
+        Value::Array(values) => {
+            for v in values {
+                renumber_profiles(v, renumber);
+            }
+        }
+        Value::Dict(entries) | Value::Stream { dict: entries, .. } => {
+            for (_, v) in entries {
+                renumber_profiles(v, renumber);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Turns PDF objects into values, following references to the objects that
+/// were labeled while the image was loaded.
+struct Encoder {
+    labels: HashMap<u32, *mut pdf_obj>,
+    profile_labels: HashMap<u32, u32>,
+    numbers: HashMap<u32, u32>,
+    busy: HashSet<u32>,
+    objects: Vec<(i32, Value)>,
+    profiles_used: Vec<u32>,
+}
+
+impl Encoder {
+    fn new(labels: HashMap<u32, *mut pdf_obj>, profile_labels: HashMap<u32, u32>) -> Self {
+        Encoder {
+            labels,
+            profile_labels,
+            numbers: HashMap::new(),
+            busy: HashSet::new(),
+            objects: Vec::new(),
+            profiles_used: Vec::new(),
+        }
+    }
+
+    /// Adds an object (after those it refers to), returning its number.
+    unsafe fn object(&mut self, obj: *mut pdf_obj) -> Option<u32> {
+        let label = (*obj).label;
+        if let Some(&n) = self.numbers.get(&label) {
+            return Some(n);
+        }
+        if !self.busy.insert(label) {
+            return None;
+        }
+        let value = self.value(obj)?;
+        self.busy.remove(&label);
+        let n = self.objects.len() as u32;
+        self.objects.push(((*obj).flags, value));
+        self.numbers.insert(label, n);
+        Some(n)
+    }
+
+    unsafe fn entries(&mut self, dict: *mut pdf_obj) -> Option<Vec<(Vec<u8>, Value)>> {
+        let mut entries = Vec::new();
+        let mut data = (*dict).as_dict() as *const pdf_dict;
+        while !(*data).key.is_null() {
+            entries.push((name_bytes(&*(*data).key), self.value((*data).value)?));
+            data = (*data).next;
+        }
+        Some(entries)
+    }
+
+    unsafe fn value(&mut self, obj: *mut pdf_obj) -> Option<Value> {
+        if obj.is_null() {
+            return Some(Value::Null);
+        }
+        Some(match PdfObjType::from((*obj).typ) {
+            PdfObjType::NULL => Value::Null,
+            PdfObjType::BOOLEAN => Value::Boolean(pdf_boolean_value(&*obj) != 0),
+            PdfObjType::NUMBER => Value::Number(pdf_number_value(&*obj)),
+            PdfObjType::STRING => {
+                let len = pdf_string_length(&*obj) as usize;
+                let ptr = pdf_string_value(&*obj) as *const u8;
+                Value::String(if len == 0 {
+                    Vec::new()
+                } else {
+                    std::slice::from_raw_parts(ptr, len).to_vec()
+                })
+            }
+            PdfObjType::NAME => Value::Name(name_bytes(&*obj)),
+            PdfObjType::ARRAY => {
+                let mut values = Vec::new();
+                for &v in &(*obj).as_array().values {
+                    values.push(self.value(v)?);
+                }
+                Value::Array(values)
+            }
+            PdfObjType::DICT => Value::Dict(self.entries(obj)?),
+            PdfObjType::STREAM => {
+                let stream = (*obj).as_stream();
+                let p = stream.decodeparms;
+                Value::Stream {
+                    flags: stream._flags,
+                    decodeparms: [p.predictor, p.colors, p.bits_per_component, p.columns],
+                    dict: self.entries(stream.dict)?,
+                    data: stream.stream.clone(),
+                }
+            }
+            PdfObjType::INDIRECT => {
+                let ind = &*((*obj).data as *const pdf_indirect);
+                if !ind.pf.is_null() {
+                    return None;
+                }
+                if let Some(&i) = self.profile_labels.get(&ind.label) {
+                    self.profiles_used.push(i);
+                    Value::Profile(i)
+                } else {
+                    let target = *self.labels.get(&ind.label)?;
+                    Value::Object(self.object(target)?)
+                }
+            }
+            _ => return None,
+        })
+    }
+}
+
+unsafe fn name_bytes(obj: &pdf_obj) -> Vec<u8> {
+    let name = (*(obj.data as *const pdf_name)).name;
+    if name.is_null() {
+        Vec::new()
+    } else {
+        CStr::from_ptr(name).to_bytes().to_vec()
+    }
+}
+
+unsafe fn build_entries(
+    dict: &mut pdf_obj,
+    entries: &[(Vec<u8>, Value)],
+    objects: &[*mut pdf_obj],
+    cspc_ids: &[i32],
+) {
+    for (key, value) in entries {
+        pdf_add_dict(dict, key.clone(), build_value(value, objects, cspc_ids));
+    }
+}
+
+unsafe fn build_value(value: &Value, objects: &[*mut pdf_obj], cspc_ids: &[i32]) -> *mut pdf_obj {
+    match value {
+        Value::Null => pdf_new_null(),
+        Value::Boolean(b) => pdf_new_boolean(*b as i8),
+        Value::Number(n) => pdf_new_number(*n),
+        Value::String(s) => pdf_new_string(s.as_ptr() as *const libc::c_void, s.len() as _),
+        Value::Name(name) => pdf_new_name(name.clone()),
+        Value::Array(values) => {
+            let array = pdf_new_array();
+            for v in values {
+                pdf_add_array(&mut *array, build_value(v, objects, cspc_ids));
+            }
+            array
+        }
+        Value::Dict(entries) => {
+            let dict = pdf_new_dict();
+            build_entries(&mut *dict, entries, objects, cspc_ids);
+            dict
+        }
+        Value::Stream {
+            flags,
+            decodeparms,
+            dict,
+            data,
+        } => {
+            let obj = pdf_new_stream(*flags);
+            let stream = (*obj).as_stream_mut();
+            stream.decodeparms.predictor = decodeparms[0];
+            stream.decodeparms.colors = decodeparms[1];
+            stream.decodeparms.bits_per_component = decodeparms[2];
+            stream.decodeparms.columns = decodeparms[3];
+            build_entries(&mut *stream.dict, dict, objects, cspc_ids);
+            stream.stream = data.clone();
+            obj
+        }
+        Value::Object(n) => pdf_ref_obj(objects[*n as usize]),
+        Value::Profile(i) => pdf_get_colorspace_reference(cspc_ids[*i as usize]),
+    }
+}
+
+fn write_u32(n: u32, out: &mut Vec<u8>) {
+    out.extend_from_slice(&n.to_le_bytes());
+}
+
+fn write_bytes(s: &[u8], out: &mut Vec<u8>) {
+    write_u32(s.len() as u32, out);
+    out.extend_from_slice(s);
+}
+
+fn write_entries(entries: &[(Vec<u8>, Value)], out: &mut Vec<u8>) {
+    write_u32(entries.len() as u32, out);
+    for (key, value) in entries {
+        write_bytes(key, out);
+        write_value(value, out);
+    }
+}
+
+fn write_value(value: &Value, out: &mut Vec<u8>) {
+    match value {
+        Value::Null => out.push(0),
+        Value::Boolean(b) => out.extend_from_slice(&[1, *b as u8]),
+        Value::Number(n) => {
+            out.push(2);
+            out.extend_from_slice(&n.to_bits().to_le_bytes());
+        }
+        Value::String(s) => {
+            out.push(3);
+            write_bytes(s, out);
+        }
+        Value::Name(name) => {
+            out.push(4);
+            write_bytes(name, out);
+        }
+        Value::Array(values) => {
+            out.push(5);
+            write_u32(values.len() as u32, out);
+            for v in values {
+                write_value(v, out);
+            }
+        }
+        Value::Dict(entries) => {
+            out.push(6);
+            write_entries(entries, out);
+        }
+        Value::Stream {
+            flags,
+            decodeparms,
+            dict,
+            data,
+        } => {
+            out.push(7);
+            write_u32(*flags as u32, out);
+            for &p in decodeparms {
+                write_u32(p as u32, out);
+            }
+            write_entries(dict, out);
+            write_bytes(data, out);
+        }
+        Value::Object(n) => {
+            out.push(8);
+            write_u32(*n, out);
+        }
+        Value::Profile(i) => {
+            out.push(9);
+            write_u32(*i, out);
+        }
+    }
+}
+
+fn write_entry(entry: &Entry, out: &mut Vec<u8>) {
+    out.extend_from_slice(MAGIC);
+    let info = &entry.info;
+    for &n in &[
+        info.flags,
+        info.width,
+        info.height,
+        info.bits_per_component,
+        info.num_components,
+        info.min_dpi,
+        entry.page_count,
+    ] {
+        write_u32(n as u32, out);
+    }
+    out.extend_from_slice(&info.xdensity.to_bits().to_le_bytes());
+    out.extend_from_slice(&info.ydensity.to_bits().to_le_bytes());
+    write_u32(entry.profiles.len() as u32, out);
+    for profile in &entry.profiles {
+        match &profile.ident {
+            Some(ident) => {
+                out.push(1);
+                write_bytes(ident, out);
+            }
+            None => out.push(0),
+        }
+        write_bytes(&profile.data, out);
+    }
+    write_u32(entry.objects.len() as u32, out);
+    for (flags, value) in &entry.objects {
+        write_u32(*flags as u32, out);
+        write_value(value, out);
+    }
+}
+
+/// Reads back what the `write_*` functions wrote, checking that references
+/// only go to objects and profiles that come before.
+struct Reader<'a> {
+    data: &'a [u8],
+    num_objects: u32,
+    num_profiles: u32,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        if n > self.data.len() {
+            return None;
+        }
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.take(4)?);
+        Some(u32::from_le_bytes(b))
+    }
+
+    fn f64(&mut self) -> Option<f64> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Some(f64::from_bits(u64::from_le_bytes(b)))
+    }
+
+    fn bytes(&mut self) -> Option<Vec<u8>> {
+        let len = self.u32()? as usize;
+        Some(self.take(len)?.to_vec())
+    }
+
+    fn name(&mut self) -> Option<Vec<u8>> {
+        Some(self.bytes()?).filter(|name| !name.contains(&0))
+    }
+
+    fn entries(&mut self) -> Option<Vec<(Vec<u8>, Value)>> {
+        let n = self.u32()?;
+        let mut entries = Vec::new();
+        for _ in 0..n {
+            entries.push((self.name()?, self.value()?));
+        }
+        Some(entries)
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        Some(match self.u8()? {
+            0 => Value::Null,
+            1 => Value::Boolean(self.u8()? != 0),
+            2 => Value::Number(self.f64()?),
+            3 => Value::String(self.bytes()?),
+            4 => Value::Name(self.name()?),
+            5 => {
+                let n = self.u32()?;
+                let mut values = Vec::new();
+                for _ in 0..n {
+                    values.push(self.value()?);
+                }
+                Value::Array(values)
+            }
+            6 => Value::Dict(self.entries()?),
+            7 => {
+                let flags = self.u32()? as i32;
+                let mut decodeparms = [0; 4];
+                for p in &mut decodeparms {
+                    *p = self.u32()? as i32;
+                }
+                Value::Stream {
+                    flags,
+                    decodeparms,
+                    dict: self.entries()?,
+                    data: self.bytes()?,
+                }
+            }
+            8 => Value::Object(Some(self.u32()?).filter(|&n| n < self.num_objects)?),
+            9 => Value::Profile(Some(self.u32()?).filter(|&i| i < self.num_profiles)?),
+            _ => return None,
+        })
+    }
+}
+
+fn read_entry(data: &[u8]) -> Option<Entry> {
+    let mut r = Reader {
+        data,
+        num_objects: 0,
+        num_profiles: 0,
+    };
+    if r.take(MAGIC.len())? != MAGIC {
+        return None;
+    }
+    let mut n = [0; 7];
+    for v in &mut n {
+        *v = r.u32()? as i32;
+    }
+    let info = ximage_info {
+        flags: n[0],
+        width: n[1],
+        height: n[2],
+        bits_per_component: n[3],
+        num_components: n[4],
+        min_dpi: n[5],
+        xdensity: r.f64()?,
+        ydensity: r.f64()?,
+    };
+    let mut profiles = Vec::new();
+    for _ in 0..r.u32()? {
+        let ident = match r.u8()? {
+            0 => None,
+            _ => Some(r.name()?),
+        };
+        profiles.push(Profile {
+            ident,
+            data: r.bytes()?,
+            cspc_id: -1,
+        });
+    }
+    r.num_profiles = profiles.len() as u32;
+    let mut objects = Vec::new();
+    for _ in 0..r.u32()? {
+        let flags = r.u32()? as i32;
+        objects.push((flags, r.value()?));
+        r.num_objects += 1;
+    }
+    match objects.last() {
+        Some((_, Value::Stream { .. })) if r.data.is_empty() => Some(Entry {
+            info,
+            page_count: n[6],
+            profiles,
+            objects,
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> Entry {
+        Entry {
+            info: ximage_info {
+                flags: 0,
+                width: 3,
+                height: 2,
+                bits_per_component: 8,
+                num_components: 3,
+                min_dpi: 0,
+                xdensity: 1.,
+                ydensity: 0.5,
+            },
+            page_count: 1,
+            profiles: vec![Profile {
+                ident: Some(b"sRGB".to_vec()),
+                data: vec![1, 2, 3],
+                cspc_id: -1,
+            }],
+            objects: vec![
+                (
+                    1,
+                    Value::Stream {
+                        flags: 0,
+                        decodeparms: [2, 0, 0, 0],
+                        dict: vec![(b"Filter".to_vec(), Value::Name(b"FlateDecode".to_vec()))],
+                        data: vec![0x78, 0x9c],
+                    },
+                ),
+                (
+                    1,
+                    Value::Stream {
+                        flags: 3,
+                        decodeparms: [15, 3, 8, 3],
+                        dict: vec![
+                            (b"SMask".to_vec(), Value::Object(0)),
+                            (b"ColorSpace".to_vec(), Value::Profile(0)),
+                            (
+                                b"Decode".to_vec(),
+                                Value::Array(vec![Value::Number(1.), Value::Number(-0.25)]),
+                            ),
+                            (b"Interpolate".to_vec(), Value::Boolean(true)),
+                            (b"Note".to_vec(), Value::String(b"a\0b".to_vec())),
+                            (
+                                b"Extra".to_vec(),
+                                Value::Dict(vec![(b"X".to_vec(), Value::Null)]),
+                            ),
+                        ],
+                        data: vec![0; 18],
+                    },
+                ),
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let entry = entry();
+        let mut data = Vec::new();
+        write_entry(&entry, &mut data);
+        assert_eq!(read_entry(&data), Some(entry));
+    }
+
+    #[test]
+    fn damaged_entries() {
+        let mut data = Vec::new();
+        write_entry(&entry(), &mut data);
+        for n in 0..data.len() {
+            assert_eq!(read_entry(&data[..n]), None);
+        }
+        let mut long = data.clone();
+        long.push(0);
+        assert_eq!(read_entry(&long), None);
+        /* A reference to an object that comes later */
+        let mut bad = entry();
+        bad.objects.swap(0, 1);
+        let mut data = Vec::new();
+        write_entry(&bad, &mut data);
+        assert_eq!(read_entry(&data), None);
+    }
+}
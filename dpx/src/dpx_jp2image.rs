@@ -29,65 +29,101 @@
 
 use crate::warn;
 
-use super::dpx_mfileio::{file_size, seek_relative, work_buffer};
-use super::dpx_numbers::{get_unsigned_byte, get_unsigned_pair, get_unsigned_quad};
+use super::dpx_mfileio::work_buffer_u8;
+use super::dpx_numbers::{tt_get_unsigned_byte, tt_get_unsigned_pair, tt_get_unsigned_quad};
 use super::dpx_pdfximage::{pdf_ximage_init_image_info, pdf_ximage_set_image};
 use crate::dpx_pdfobj::{
     pdf_add_dict, pdf_add_stream, pdf_get_version, pdf_new_name, pdf_new_number, pdf_new_stream,
+    pdf_release_obj,
 };
-use libc::{fread, rewind, FILE};
+use crate::{ttstub_input_get_size, ttstub_input_read};
 
-pub type __off_t = i64;
-pub type __off64_t = i64;
+use std::io::{Read, Seek, SeekFrom};
+
+use bridge::InputHandleWrapper;
 
 use crate::dpx_pdfximage::{pdf_ximage, ximage_info};
 /* Label */
-unsafe fn read_box_hdr(mut fp: *mut FILE, mut lbox: *mut u32, mut tbox: *mut u32) -> u32 {
+/* Tectonic: `avail` is the number of bytes left in the enclosing box (or
+ * file), which the box must fit in. Returns the length of the box header,
+ * or 0 if the box is truncated or its length is invalid. An LBox value of
+ * 0, meaning "up to the end", is left for the caller to handle. */
+unsafe fn read_box_hdr(
+    handle: &mut InputHandleWrapper,
+    mut lbox: *mut u32,
+    mut tbox: *mut u32,
+    avail: u32,
+) -> u32 {
     let mut bytesread: u32 = 0_u32;
-    *lbox = get_unsigned_quad(fp);
-    *tbox = get_unsigned_quad(fp);
+    if avail < 8_u32 {
+        warn!("JPEG2000: Truncated box header in JP2 file.");
+        return 0_u32;
+    }
+    *lbox = tt_get_unsigned_quad(handle);
+    *tbox = tt_get_unsigned_quad(handle);
     bytesread = bytesread.wrapping_add(8_u32);
     if *lbox == 1_u32 {
-        if get_unsigned_quad(fp) != 0_u32 {
-            panic!("JPEG2000: LBox value in JP2 file >32 bits.\nI can\'t handle this!");
+        if avail < 16_u32 {
+            warn!("JPEG2000: Truncated box header in JP2 file.");
+            return 0_u32;
         }
-        *lbox = get_unsigned_quad(fp);
+        if tt_get_unsigned_quad(handle) != 0_u32 {
+            warn!("JPEG2000: LBox value in JP2 file >32 bits.");
+            return 0_u32;
+        }
+        *lbox = tt_get_unsigned_quad(handle);
         bytesread = bytesread.wrapping_add(8_u32)
-    } else if *lbox > 1_u32 && *lbox < 8_u32 {
-        warn!("JPEG2000: Unknown LBox value {} in JP2 file!", *lbox);
+    }
+    if *lbox != 0_u32 && (*lbox < bytesread || *lbox > avail) {
+        warn!("JPEG2000: Invalid LBox value {} in JP2 file!", *lbox);
+        return 0_u32;
     }
     bytesread
 }
-unsafe fn check_jp___box(mut fp: *mut FILE) -> i32 {
-    if get_unsigned_quad(fp) != 0xc_u32 {
+/* Skip the rest of a box, rather than read it. */
+unsafe fn skip_box_data(handle: &mut InputHandleWrapper, size: u32) -> i32 {
+    if handle.seek(SeekFrom::Current(size as i64)).is_err() {
+        warn!("JPEG2000: Seeking in JP2 file failed.");
+        return -1i32;
+    }
+    0i32
+}
+unsafe fn check_jp___box(handle: &mut InputHandleWrapper) -> i32 {
+    if tt_get_unsigned_quad(handle) != 0xc_u32 {
         return 0i32;
     }
-    if get_unsigned_quad(fp) != 0x6a502020_u32 {
+    if tt_get_unsigned_quad(handle) != 0x6a502020_u32 {
         return 0i32;
     }
     /* Next 4 bytes shall be 0D 0A 87 0A */
-    if get_unsigned_quad(fp) != 0xd0a870a_u32 {
+    if tt_get_unsigned_quad(handle) != 0xd0a870a_u32 {
         return 0i32;
     }
     1i32
 }
-unsafe fn check_ftyp_data(mut fp: *mut FILE, mut size: u32) -> i32 {
+unsafe fn check_ftyp_data(handle: &mut InputHandleWrapper, mut size: u32) -> i32 {
     let mut supported: i32 = 0i32;
-    let BR = get_unsigned_quad(fp);
+    /* Brand and MinV, followed by four bytes for each compatible brand */
+    if size < 8_u32 || size % 4_u32 != 0 {
+        warn!("JPEG2000: Invalid File Type box length.");
+        return 0i32;
+    }
+    let BR = tt_get_unsigned_quad(handle);
     size = size.wrapping_sub(4_u32);
     /* MinV = */
-    get_unsigned_quad(fp);
+    tt_get_unsigned_quad(handle);
     size = size.wrapping_sub(4_u32);
     match BR {
         1785737760 => {
             /* "jp2 " ... supported */
-            seek_relative(fp, size as i32);
-            supported = 1i32
+            if skip_box_data(handle, size) == 0 {
+                supported = 1i32
+            }
         }
         1785755680 => {
             /* "jpx " ... baseline subset supported */
             while size > 0_u32 {
-                let CLi = get_unsigned_quad(fp);
+                let CLi = tt_get_unsigned_quad(handle);
                 if CLi == 0x6a707862_u32 {
                     supported = 1i32
                 }
@@ -96,48 +132,61 @@ unsafe fn check_ftyp_data(mut fp: *mut FILE, mut size: u32) -> i32 {
         }
         _ => {
             warn!("JPEG2000: Unknown JPEG 2000 File Type box Brand field value.");
-            seek_relative(fp, size as i32);
             supported = 0i32
         }
     }
     supported
 }
-unsafe fn read_res__data(info: &mut ximage_info, mut fp: *mut FILE, mut _size: u32) {
-    let VR_N = get_unsigned_pair(fp) as u32;
-    let VR_D = get_unsigned_pair(fp) as u32;
-    let HR_N = get_unsigned_pair(fp) as u32;
-    let HR_D = get_unsigned_pair(fp) as u32;
-    let VR_E = get_unsigned_byte(fp);
-    let HR_E = get_unsigned_byte(fp);
+unsafe fn read_res__data(info: &mut ximage_info, handle: &mut InputHandleWrapper, mut _size: u32) {
+    let VR_N = tt_get_unsigned_pair(handle) as u32;
+    let VR_D = tt_get_unsigned_pair(handle) as u32;
+    let HR_N = tt_get_unsigned_pair(handle) as u32;
+    let HR_D = tt_get_unsigned_pair(handle) as u32;
+    let VR_E = tt_get_unsigned_byte(handle);
+    let HR_E = tt_get_unsigned_byte(handle);
     info.xdensity = 72. / (HR_N as f64 / HR_D as f64 * (10f64).powf(HR_E as f64) * 0.0254);
     info.ydensity = 72. / (VR_N as f64 / VR_D as f64 * (10f64).powf(VR_E as f64) * 0.0254);
 }
-unsafe fn scan_res_(info: &mut ximage_info, mut fp: *mut FILE, mut size: u32) -> i32 {
+unsafe fn scan_res_(info: &mut ximage_info, handle: &mut InputHandleWrapper, mut size: u32) -> i32 {
     let mut lbox: u32 = 0;
     let mut tbox: u32 = 0;
     let mut have_resd: i32 = 0i32;
     while size > 0_u32 {
-        let len = read_box_hdr(fp, &mut lbox, &mut tbox);
+        let len = read_box_hdr(handle, &mut lbox, &mut tbox, size);
+        if len == 0_u32 {
+            return -1i32;
+        }
         if lbox == 0_u32 {
             warn!("JPEG2000: Unexpected lbox value 0 in JP2 Resolution box.");
             break;
         } else {
-            match tbox {
+            /* Both resolution boxes hold 10 bytes. */
+            let is_res = tbox == 1919251299 || tbox == 1919251300;
+            if is_res && lbox.wrapping_sub(len) < 10_u32 {
+                warn!("JPEG2000: Invalid Resolution box length.");
+                return -1i32;
+            }
+            let skip = match tbox {
                 1919251299 => {
                     if have_resd == 0 {
-                        read_res__data(info, fp, lbox.wrapping_sub(len));
+                        read_res__data(info, handle, lbox.wrapping_sub(len));
+                        lbox.wrapping_sub(len).wrapping_sub(10_u32)
                     } else {
-                        seek_relative(fp, lbox.wrapping_sub(len) as i32);
+                        lbox.wrapping_sub(len)
                     }
                 }
                 1919251300 => {
-                    read_res__data(info, fp, lbox.wrapping_sub(len));
-                    have_resd = 1i32
+                    read_res__data(info, handle, lbox.wrapping_sub(len));
+                    have_resd = 1i32;
+                    lbox.wrapping_sub(len).wrapping_sub(10_u32)
                 }
                 _ => {
                     warn!("JPEG2000: Unknown JPEG 2000 box type in Resolution box.");
-                    seek_relative(fp, lbox.wrapping_sub(len) as i32);
+                    lbox.wrapping_sub(len)
                 }
+            };
+            if skip_box_data(handle, skip) < 0 {
+                return -1i32;
             }
             size = size.wrapping_sub(lbox)
         }
@@ -155,21 +204,25 @@ unsafe fn scan_res_(info: &mut ximage_info, mut fp: *mut FILE, mut size: u32) ->
 unsafe fn scan_cdef(
     _info: &mut ximage_info,
     mut smask: *mut i32,
-    mut fp: *mut FILE,
+    handle: &mut InputHandleWrapper,
     mut size: u32,
 ) -> i32 {
     let mut opacity_channels: i32 = 0i32; /* Cn */
     let mut have_type0: i32 = 0i32; /* must be 0 for SMask */
     *smask = 0i32;
-    let N = get_unsigned_pair(fp) as u32;
+    if size < 2_u32 {
+        warn!("JPEG2000: Invalid Channel Definition box length.");
+        return -1i32;
+    }
+    let N = tt_get_unsigned_pair(handle) as u32;
     if size < N.wrapping_mul(6_u32).wrapping_add(2_u32) {
         warn!("JPEG2000: Inconsistent N value in Channel Definition box.");
         return -1i32;
     }
     for _ in 0..N {
-        let Cn = get_unsigned_pair(fp) as u32;
-        let Typ = get_unsigned_pair(fp) as u32;
-        let Asoc = get_unsigned_pair(fp) as u32;
+        let Cn = tt_get_unsigned_pair(handle) as u32;
+        let Typ = tt_get_unsigned_pair(handle) as u32;
+        let Asoc = tt_get_unsigned_pair(handle) as u32;
         if Cn > N {
             warn!("JPEG2000: Invalid Cn value in Channel Definition box.");
         }
@@ -187,12 +240,12 @@ unsafe fn scan_cdef(
     } else if opacity_channels > 1i32 {
         warn!("JPEG2000: Unsupported transparency type. (ignored)");
     }
-    0i32
+    skip_box_data(handle, size - N * 6 - 2)
 }
 unsafe fn scan_jp2h(
     info: &mut ximage_info,
     mut smask: *mut i32,
-    mut fp: *mut FILE,
+    handle: &mut InputHandleWrapper,
     mut size: u32,
 ) -> i32 {
     let mut error: i32 = 0i32;
@@ -200,35 +253,42 @@ unsafe fn scan_jp2h(
     let mut lbox: u32 = 0;
     let mut tbox: u32 = 0;
     while size > 0_u32 && error == 0 {
-        let len = read_box_hdr(fp, &mut lbox, &mut tbox);
+        let len = read_box_hdr(handle, &mut lbox, &mut tbox, size);
+        if len == 0_u32 {
+            error = -1i32;
+            break;
+        }
         if lbox == 0_u32 {
             warn!("JPEG2000: Unexpected lbox value 0 in JP2 Header box...");
             error = -1i32;
             break;
         } else {
             match tbox {
+                1768449138 if lbox.wrapping_sub(len) != 14_u32 => {
+                    warn!("JPEG2000: Invalid Image Header box length.");
+                    error = -1i32
+                }
                 1768449138 => {
-                    info.height = get_unsigned_quad(fp) as i32;
-                    info.width = get_unsigned_quad(fp) as i32;
-                    info.num_components = get_unsigned_pair(fp) as i32;
+                    info.height = tt_get_unsigned_quad(handle) as i32;
+                    info.width = tt_get_unsigned_quad(handle) as i32;
+                    info.num_components = tt_get_unsigned_pair(handle) as i32;
                     /* c = */
-                    get_unsigned_byte(fp); /* BPC - 1 */
+                    tt_get_unsigned_byte(handle); /* BPC - 1 */
                     /* c = */
-                    get_unsigned_byte(fp); /* C: Compression type */
+                    tt_get_unsigned_byte(handle); /* C: Compression type */
                     /* c = */
-                    get_unsigned_byte(fp); /* UnkC */
+                    tt_get_unsigned_byte(handle); /* UnkC */
                     /* c = */
-                    get_unsigned_byte(fp); /* IPR */
+                    tt_get_unsigned_byte(handle); /* IPR */
                     have_ihdr = 1i32
                 }
-                1919251232 => error = scan_res_(info, fp, lbox.wrapping_sub(len)),
-                1667523942 => error = scan_cdef(info, smask, fp, lbox.wrapping_sub(len)),
+                1919251232 => error = scan_res_(info, handle, lbox.wrapping_sub(len)),
+                1667523942 => error = scan_cdef(info, smask, handle, lbox.wrapping_sub(len)),
                 1651532643 | 1668246642 | 1885564018 | 1668112752 | 1818389536 => {
-                    seek_relative(fp, lbox.wrapping_sub(len) as i32);
+                    error = skip_box_data(handle, lbox.wrapping_sub(len))
                 }
                 _ => {
                     warn!("JPEG2000: Unknown JPEG 2000 box in JP2 Header box.");
-                    seek_relative(fp, lbox.wrapping_sub(len) as i32);
                     error = -1i32
                 }
             }
@@ -244,36 +304,96 @@ unsafe fn scan_jp2h(
         -1i32
     };
 }
-unsafe fn scan_file(info: &mut ximage_info, mut smask: *mut i32, mut fp: *mut FILE) -> i32 {
+/* A raw JPEG 2000 codestream (.j2k, .j2c) starts with the SOC marker
+ * immediately followed by the SIZ marker segment. */
+unsafe fn scan_j2k(info: &mut ximage_info, handle: &mut InputHandleWrapper) -> i32 {
+    /* SOC, and the SIZ marker segment up to Csiz */
+    if ttstub_input_get_size(handle) < 42 {
+        warn!("JPEG2000: Truncated JPEG 2000 codestream.");
+        return -1i32;
+    }
+    if handle.seek(SeekFrom::Start(0)).is_err() {
+        return -1i32;
+    }
+    if tt_get_unsigned_pair(handle) != 0xff4f_u16 || tt_get_unsigned_pair(handle) != 0xff51_u16 {
+        return -1i32;
+    }
+    /* Lsiz = */
+    tt_get_unsigned_pair(handle);
+    /* Rsiz = */
+    tt_get_unsigned_pair(handle);
+    let Xsiz = tt_get_unsigned_quad(handle);
+    let Ysiz = tt_get_unsigned_quad(handle);
+    let XOsiz = tt_get_unsigned_quad(handle);
+    let YOsiz = tt_get_unsigned_quad(handle);
+    /* XTsiz, YTsiz, XTOsiz, YTOsiz */
+    if handle.seek(SeekFrom::Current(16)).is_err() {
+        return -1i32;
+    }
+    let Csiz = tt_get_unsigned_pair(handle);
+    if XOsiz >= Xsiz || YOsiz >= Ysiz || Csiz == 0 {
+        warn!("JPEG2000: Invalid image size in JPEG 2000 codestream.");
+        return -1i32;
+    }
+    info.width = Xsiz.wrapping_sub(XOsiz) as i32;
+    info.height = Ysiz.wrapping_sub(YOsiz) as i32;
+    info.num_components = Csiz as i32;
+    0i32
+}
+unsafe fn is_j2k(handle: &mut InputHandleWrapper) -> bool {
+    let mut sig: [u8; 4] = [0; 4];
+    if handle.seek(SeekFrom::Start(0)).is_err() {
+        return false;
+    }
+    let n = ttstub_input_read(handle.0.as_ptr(), sig.as_mut_ptr() as *mut i8, 4);
+    handle.seek(SeekFrom::Start(0)).is_ok() && n == 4 && sig == [0xff, 0x4f, 0xff, 0x51]
+}
+unsafe fn scan_file(
+    info: &mut ximage_info,
+    mut smask: *mut i32,
+    handle: &mut InputHandleWrapper,
+) -> i32 {
     let mut error: i32 = 0i32;
     let mut have_jp2h: i32 = 0i32;
     let mut lbox: u32 = 0;
     let mut tbox: u32 = 0;
-    let mut size = file_size(fp);
+    if is_j2k(handle) {
+        return scan_j2k(info, handle);
+    }
+    let file_size = ttstub_input_get_size(handle);
+    if file_size < 12 || file_size > u32::max_value() as u64 {
+        warn!("JPEG2000: Invalid JP2 file size.");
+        return -1i32;
+    }
+    let mut size = file_size as u32;
     /* Should have already been checked before. */
     /* JPEG 2000 Singature box */
-    if check_jp___box(fp) == 0 {
+    if check_jp___box(handle) == 0 {
         return -1i32;
     }
-    size -= 12i32;
+    size -= 12_u32;
     /* File Type box shall immediately follow */
-    let mut len = read_box_hdr(fp, &mut lbox, &mut tbox);
-    if tbox != 0x66747970_u32 {
+    let mut len = read_box_hdr(handle, &mut lbox, &mut tbox, size);
+    if len == 0_u32 || lbox == 0_u32 || tbox != 0x66747970_u32 {
         return -1i32;
     }
-    if check_ftyp_data(fp, lbox.wrapping_sub(len)) == 0 {
+    if check_ftyp_data(handle, lbox.wrapping_sub(len)) == 0 {
         return -1i32;
     }
-    size = (size as u32).wrapping_sub(lbox) as i32 as i32;
+    size -= lbox;
     /* Search for JP2 Header box */
-    while size > 0i32 && error == 0 {
-        len = read_box_hdr(fp, &mut lbox, &mut tbox);
+    while size > 0_u32 && error == 0 {
+        len = read_box_hdr(handle, &mut lbox, &mut tbox, size);
+        if len == 0_u32 {
+            error = -1i32;
+            break;
+        }
         if lbox == 0_u32 {
-            lbox = size as u32
+            lbox = size
         }
         match tbox {
             1785737832 => {
-                error = scan_jp2h(info, smask, fp, lbox.wrapping_sub(len));
+                error = scan_jp2h(info, smask, handle, lbox.wrapping_sub(len));
                 have_jp2h = 1i32
             }
             1785737827 => {
@@ -281,13 +401,11 @@ unsafe fn scan_file(info: &mut ximage_info, mut smask: *mut i32, mut fp: *mut FI
                 if have_jp2h == 0 {
                     warn!("JPEG2000: JPEG 2000 Codestream box found before JP2 Header box.");
                 }
-                seek_relative(fp, lbox.wrapping_sub(len) as i32);
-            }
-            _ => {
-                seek_relative(fp, lbox.wrapping_sub(len) as i32);
+                error = skip_box_data(handle, lbox.wrapping_sub(len))
             }
+            _ => error = skip_box_data(handle, lbox.wrapping_sub(len)),
         }
-        size = (size as u32).wrapping_sub(lbox) as i32 as i32
+        size -= lbox
     }
     /* From ISO/IEC 15444-2 M.9.2.7
      * The JP2 Header box shall be found in the file before the first
@@ -300,30 +418,47 @@ unsafe fn scan_file(info: &mut ximage_info, mut smask: *mut i32, mut fp: *mut FI
     }
     error
 }
+/* Tectonic: this also accepts raw codestreams, which PDF's JPXDecode filter
+ * handles just as well as JP2 files. */
 #[no_mangle]
-pub unsafe extern "C" fn check_for_jp2(mut fp: *mut FILE) -> i32 {
+pub unsafe extern "C" fn check_for_jp2(handle: &mut InputHandleWrapper) -> i32 {
     let mut lbox: u32 = 0;
     let mut tbox: u32 = 0;
-    if fp.is_null() {
+    let mut sig: [u8; 20] = [0; 20];
+    if is_j2k(handle) {
+        return 1i32;
+    }
+    /* Make sure that there's enough data for the signature and File Type box
+     * header, since the tt_get_* functions won't tolerate a short file. */
+    if handle.seek(SeekFrom::Start(0)).is_err() {
+        return 0i32;
+    }
+    if ttstub_input_read(handle.0.as_ptr(), sig.as_mut_ptr() as *mut i8, 20) != 20 {
+        return 0i32;
+    }
+    if handle.seek(SeekFrom::Start(0)).is_err() {
         return 0i32;
     }
-    rewind(fp);
     /* JPEG 2000 Singature box */
-    if check_jp___box(fp) == 0 {
+    if check_jp___box(handle) == 0 {
         return 0i32;
     }
     /* File Type box shall immediately follow */
-    let len = read_box_hdr(fp, &mut lbox, &mut tbox);
-    if tbox != 0x66747970_u32 {
+    let avail = (ttstub_input_get_size(handle) - 12).min(u32::max_value() as u64) as u32;
+    let len = read_box_hdr(handle, &mut lbox, &mut tbox, avail);
+    if len == 0_u32 || lbox == 0_u32 || tbox != 0x66747970_u32 {
         return 0i32;
     }
-    if check_ftyp_data(fp, lbox.wrapping_sub(len)) == 0 {
+    if check_ftyp_data(handle, lbox.wrapping_sub(len)) == 0 {
         return 0i32;
     }
     1i32
 }
 #[no_mangle]
-pub unsafe extern "C" fn jp2_include_image(mut ximage: *mut pdf_ximage, mut fp: *mut FILE) -> i32 {
+pub unsafe extern "C" fn jp2_include_image(
+    mut ximage: *mut pdf_ximage,
+    handle: &mut InputHandleWrapper,
+) -> i32 {
     let mut smask: i32 = 0i32;
     let mut info = ximage_info::default();
     let pdf_version = pdf_get_version();
@@ -335,8 +470,7 @@ pub unsafe extern "C" fn jp2_include_image(mut ximage: *mut pdf_ximage, mut fp:
         return -1i32;
     }
     pdf_ximage_init_image_info(&mut info);
-    rewind(fp);
-    if scan_file(&mut info, &mut smask, fp) < 0i32 {
+    if handle.seek(SeekFrom::Start(0)).is_err() || scan_file(&mut info, &mut smask, handle) < 0i32 {
         warn!("JPEG2000: Reading JPEG 2000 file failed.");
         return -1i32;
     }
@@ -347,20 +481,18 @@ pub unsafe extern "C" fn jp2_include_image(mut ximage: *mut pdf_ximage, mut fp:
         pdf_add_dict(stream_dict, "SMaskInData", pdf_new_number(1i32 as f64));
     }
     /* Read whole file */
-    rewind(fp);
+    if handle.seek(SeekFrom::Start(0)).is_err() {
+        pdf_release_obj(stream);
+        return -1i32;
+    }
     loop {
-        let nb_read = fread(
-            work_buffer.as_mut_ptr() as *mut libc::c_void,
-            ::std::mem::size_of::<i8>(),
-            1024,
-            fp,
-        ) as i32;
+        let nb_read = handle.read(&mut work_buffer_u8).unwrap_or(0) as i32;
         if !(nb_read > 0i32) {
             break;
         }
         pdf_add_stream(
             &mut *stream,
-            work_buffer.as_mut_ptr() as *const libc::c_void,
+            work_buffer_u8.as_ptr() as *const libc::c_void,
             nb_read,
         );
     }
@@ -369,19 +501,21 @@ pub unsafe extern "C" fn jp2_include_image(mut ximage: *mut pdf_ximage, mut fp:
 }
 #[no_mangle]
 pub unsafe extern "C" fn jp2_get_bbox(
-    mut fp: *mut FILE,
-    mut width: *mut i32,
-    mut height: *mut i32,
+    handle: &mut InputHandleWrapper,
+    mut width: *mut u32,
+    mut height: *mut u32,
     mut xdensity: *mut f64,
     mut ydensity: *mut f64,
 ) -> i32 {
     let mut smask: i32 = 0i32;
     let mut info = ximage_info::default();
     pdf_ximage_init_image_info(&mut info);
-    rewind(fp);
-    let r = scan_file(&mut info, &mut smask, fp);
-    *width = info.width;
-    *height = info.height;
+    if handle.seek(SeekFrom::Start(0)).is_err() {
+        return -1i32;
+    }
+    let r = scan_file(&mut info, &mut smask, handle);
+    *width = info.width as u32;
+    *height = info.height as u32;
     *xdensity = info.xdensity;
     *ydensity = info.ydensity;
     r
@@ -0,0 +1,1075 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2002-2016 by Jin-Hwan Cho and Shunsaku Hirata,
+    the dvipdfmx project team.
+
+    Copyright (C) 1998, 1999 by Mark A. Wicks <mwicks@kettering.edu>
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! A small JPEG codec for recompressing images: a decoder for baseline and
+//! progressive Huffman-coded JPEGs and a baseline encoder.
+//!
+//! Decoding stops at the component planes. No color conversion is done, so
+//! that an image can be re-encoded with the same color transform and keep
+//! whatever `/ColorSpace` and `/Decode` it was included with.
+
+/// Natural (row-major) position of the n-th coefficient in zig-zag order.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/* ITU T.81 Annex K tables */
+const STD_LUMINANCE_QT: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+const STD_CHROMINANCE_QT: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+const STD_DC_LUMINANCE_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const STD_DC_CHROMINANCE_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const STD_DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const STD_AC_LUMINANCE_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const STD_AC_LUMINANCE_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+const STD_AC_CHROMINANCE_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const STD_AC_CHROMINANCE_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+/// A decoded JPEG image.
+pub struct JpegPlanes {
+    pub width: usize,
+    pub height: usize,
+    /// One full-resolution plane per component, in frame order.
+    pub planes: Vec<Vec<u8>>,
+    /// The color transform in effect: 0 for none, 1 for YCbCr and 2 for
+    /// YCCK. This is what a PDF reader applies by default, i.e. the one in
+    /// the Adobe marker, or YCbCr for three components without one.
+    pub transform: u8,
+}
+
+/// `t[x][u] = C(u)/2 cos((2x + 1)uπ/16)`; both the forward and the inverse
+/// DCT are products with this matrix.
+fn dct_table() -> [[f32; 8]; 8] {
+    let mut t = [[0f32; 8]; 8];
+    for (x, row) in t.iter_mut().enumerate() {
+        for (u, c) in row.iter_mut().enumerate() {
+            let cu = if u == 0 {
+                std::f64::consts::FRAC_1_SQRT_2
+            } else {
+                1.
+            };
+            *c = (cu / 2. * ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / 16.).cos())
+                as f32;
+        }
+    }
+    t
+}
+
+/* Decoder */
+
+#[derive(Clone)]
+struct Huffman {
+    /// Codes of up to 9 bits: `(length << 8) | value`, or 0 if longer.
+    lookup: Vec<u16>,
+    maxcode: [i32; 17],
+    mincode: [i32; 17],
+    valptr: [i32; 17],
+    values: Vec<u8>,
+}
+
+impl Huffman {
+    fn new(counts: &[u8], values: &[u8]) -> Result<Huffman, &'static str> {
+        let mut h = Huffman {
+            lookup: vec![0; 512],
+            maxcode: [-1; 17],
+            mincode: [0; 17],
+            valptr: [0; 17],
+            values: values.to_vec(),
+        };
+        let mut code = 0i32;
+        let mut k = 0i32;
+        for len in 1..=16 {
+            let n = counts[len - 1] as i32;
+            h.valptr[len] = k;
+            h.mincode[len] = code;
+            if n > 0 {
+                if code + n > 1 << len || (k + n) as usize > values.len() {
+                    return Err("invalid Huffman table");
+                }
+                h.maxcode[len] = code + n - 1;
+                if len <= 9 {
+                    for c in code..code + n {
+                        let entry = ((len as u16) << 8) | values[(k + c - code) as usize] as u16;
+                        let first = (c as usize) << (9 - len);
+                        for e in &mut h.lookup[first..first + (1 << (9 - len))] {
+                            *e = entry;
+                        }
+                    }
+                }
+            }
+            code += n;
+            k += n;
+            code <<= 1;
+        }
+        Ok(h)
+    }
+}
+
+/// Entropy-coded data reader. Stops at the first marker and pads with zero
+/// bits from there on.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    acc: u32,
+    nbits: u32,
+    marker: bool,
+}
+
+impl<'a> BitReader<'a> {
+    fn fill(&mut self) {
+        while self.nbits <= 24 {
+            let mut b = 0;
+            if !self.marker && self.pos < self.data.len() {
+                b = self.data[self.pos];
+                if b == 0xff {
+                    if self.data.get(self.pos + 1) == Some(&0) {
+                        self.pos += 2;
+                    } else {
+                        self.marker = true;
+                        b = 0;
+                    }
+                } else {
+                    self.pos += 1;
+                }
+            }
+            self.acc |= (b as u32) << (24 - self.nbits);
+            self.nbits += 8;
+        }
+    }
+
+    fn bits(&mut self, n: u32) -> i32 {
+        if n == 0 {
+            return 0;
+        }
+        self.fill();
+        let v = self.acc >> (32 - n);
+        self.acc <<= n;
+        self.nbits -= n;
+        v as i32
+    }
+
+    fn bit(&mut self) -> bool {
+        self.bits(1) != 0
+    }
+
+    /// Reads an `n`-bit magnitude, as in `EXTEND` of T.81 F.2.2.1.
+    fn extend(&mut self, n: u32) -> i32 {
+        let v = self.bits(n);
+        if n > 0 && v < 1 << (n - 1) {
+            v - (1 << n) + 1
+        } else {
+            v
+        }
+    }
+
+    fn decode(&mut self, h: &Huffman) -> Result<u8, &'static str> {
+        self.fill();
+        let e = h.lookup[(self.acc >> 23) as usize];
+        if e != 0 {
+            let len = (e >> 8) as u32;
+            self.acc <<= len;
+            self.nbits -= len;
+            return Ok(e as u8);
+        }
+        for len in 10..=16 {
+            let code = (self.acc >> (32 - len)) as i32;
+            if code <= h.maxcode[len] {
+                self.acc <<= len;
+                self.nbits -= len as u32;
+                let i = h.valptr[len] + code - h.mincode[len];
+                return h
+                    .values
+                    .get(i as usize)
+                    .copied()
+                    .ok_or("invalid Huffman code");
+            }
+        }
+        Err("invalid Huffman code")
+    }
+
+    /// Skips to just after the next RSTn marker.
+    fn restart(&mut self) {
+        self.acc = 0;
+        self.nbits = 0;
+        self.marker = false;
+        while self.pos + 1 < self.data.len() {
+            let (b0, b1) = (self.data[self.pos], self.data[self.pos + 1]);
+            if b0 == 0xff && (0xd0..=0xd7).contains(&b1) {
+                self.pos += 2;
+                return;
+            }
+            if b0 == 0xff && b1 != 0 && b1 != 0xff {
+                return;
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// The position of the marker ending the entropy-coded segment.
+    fn end(&self) -> usize {
+        let mut pos = self.pos;
+        while pos + 1 < self.data.len() {
+            let b1 = self.data[pos + 1];
+            if self.data[pos] == 0xff && b1 != 0 && b1 != 0xff && !(0xd0..=0xd7).contains(&b1) {
+                return pos;
+            }
+            pos += 1;
+        }
+        self.data.len()
+    }
+}
+
+struct Component {
+    id: u8,
+    h: usize,
+    v: usize,
+    tq: usize,
+    /// Size of the coefficient grid in blocks, padded to whole MCUs.
+    bw: usize,
+    bh: usize,
+    coefs: Vec<[i16; 64]>,
+    dc_table: usize,
+    ac_table: usize,
+    pred: i32,
+}
+
+struct Decoder {
+    width: usize,
+    height: usize,
+    progressive: bool,
+    components: Vec<Component>,
+    hmax: usize,
+    vmax: usize,
+    qt: [[u16; 64]; 4],
+    dc: Vec<Option<Huffman>>,
+    ac: Vec<Option<Huffman>>,
+    restart_interval: usize,
+    eobrun: u32,
+    adobe: Option<u8>,
+}
+
+fn be16(data: &[u8], pos: usize) -> Result<usize, &'static str> {
+    if pos + 2 > data.len() {
+        return Err("unexpected end of data");
+    }
+    Ok((data[pos] as usize) << 8 | data[pos + 1] as usize)
+}
+
+impl Decoder {
+    fn read_frame(&mut self, seg: &[u8], progressive: bool) -> Result<(), &'static str> {
+        if !self.components.is_empty() {
+            return Err("multiple frames");
+        }
+        if seg.len() < 6 || seg[0] != 8 {
+            return Err("only 8-bit precision is supported");
+        }
+        self.progressive = progressive;
+        self.height = be16(seg, 1)?;
+        self.width = be16(seg, 3)?;
+        let n = seg[5] as usize;
+        if self.width == 0 || self.height == 0 {
+            return Err("invalid image size");
+        }
+        if n == 0 || n > 4 || seg.len() < 6 + 3 * n {
+            return Err("invalid number of components");
+        }
+        for c in seg[6..6 + 3 * n].chunks(3) {
+            let (h, v) = ((c[1] >> 4) as usize, (c[1] & 0xf) as usize);
+            if h == 0 || h > 4 || v == 0 || v > 4 || c[2] > 3 {
+                return Err("invalid component parameters");
+            }
+            self.components.push(Component {
+                id: c[0],
+                h,
+                v,
+                tq: c[2] as usize,
+                bw: 0,
+                bh: 0,
+                coefs: Vec::new(),
+                dc_table: 0,
+                ac_table: 0,
+                pred: 0,
+            });
+        }
+        self.hmax = self.components.iter().map(|c| c.h).max().unwrap();
+        self.vmax = self.components.iter().map(|c| c.v).max().unwrap();
+        let mcux = (self.width + 8 * self.hmax - 1) / (8 * self.hmax);
+        let mcuy = (self.height + 8 * self.vmax - 1) / (8 * self.vmax);
+        for c in &mut self.components {
+            c.bw = mcux * c.h;
+            c.bh = mcuy * c.v;
+            c.coefs = vec![[0; 64]; c.bw * c.bh];
+        }
+        Ok(())
+    }
+
+    fn read_dqt(&mut self, mut seg: &[u8]) -> Result<(), &'static str> {
+        while !seg.is_empty() {
+            let (pq, tq) = (seg[0] >> 4, (seg[0] & 0xf) as usize);
+            let size = if pq == 0 { 64 } else { 128 };
+            if tq > 3 || pq > 1 || seg.len() < 1 + size {
+                return Err("invalid quantization table");
+            }
+            for k in 0..64 {
+                self.qt[tq][ZIGZAG[k]] = if pq == 0 {
+                    seg[1 + k] as u16
+                } else {
+                    be16(seg, 1 + 2 * k)? as u16
+                };
+            }
+            seg = &seg[1 + size..];
+        }
+        Ok(())
+    }
+
+    fn read_dht(&mut self, mut seg: &[u8]) -> Result<(), &'static str> {
+        while !seg.is_empty() {
+            if seg.len() < 17 {
+                return Err("invalid Huffman table");
+            }
+            let (tc, th) = (seg[0] >> 4, (seg[0] & 0xf) as usize);
+            let total: usize = seg[1..17].iter().map(|&n| n as usize).sum();
+            if tc > 1 || th > 3 || seg.len() < 17 + total {
+                return Err("invalid Huffman table");
+            }
+            let h = Huffman::new(&seg[1..17], &seg[17..17 + total])?;
+            if tc == 0 {
+                self.dc[th] = Some(h);
+            } else {
+                self.ac[th] = Some(h);
+            }
+            seg = &seg[17 + total..];
+        }
+        Ok(())
+    }
+
+    fn decode_scan(&mut self, data: &[u8], pos: usize) -> Result<usize, &'static str> {
+        let len = be16(data, pos)?;
+        let seg = data
+            .get(pos + 2..pos + len)
+            .ok_or("unexpected end of data")?;
+        let n = *seg.first().ok_or("invalid scan header")? as usize;
+        if n == 0 || n > 4 || seg.len() < 4 + 2 * n {
+            return Err("invalid scan header");
+        }
+        let mut scomps = Vec::with_capacity(n);
+        for s in seg[1..1 + 2 * n].chunks(2) {
+            let ci = self
+                .components
+                .iter()
+                .position(|c| c.id == s[0])
+                .ok_or("scan refers to an unknown component")?;
+            let (td, ta) = ((s[1] >> 4) as usize, (s[1] & 0xf) as usize);
+            if td > 3 || ta > 3 {
+                return Err("invalid scan header");
+            }
+            self.components[ci].dc_table = td;
+            self.components[ci].ac_table = ta;
+            scomps.push(ci);
+        }
+        let (ss, se) = (seg[1 + 2 * n] as usize, seg[2 + 2 * n] as usize);
+        let (ah, al) = ((seg[3 + 2 * n] >> 4) as u32, (seg[3 + 2 * n] & 0xf) as u32);
+        if self.progressive {
+            if se > 63 || ss > se || (ss == 0 && se != 0) || (ss > 0 && n != 1) || al > 13 {
+                return Err("invalid progressive scan parameters");
+            }
+        } else if ss != 0 || se != 63 || ah != 0 || al != 0 {
+            return Err("invalid scan parameters");
+        }
+        for &ci in &scomps {
+            let c = &self.components[ci];
+            let needs_dc = ss == 0 && ah == 0;
+            if (needs_dc && self.dc[c.dc_table].is_none())
+                || (se > 0 && self.ac[c.ac_table].is_none())
+            {
+                return Err("scan uses an undefined Huffman table");
+            }
+        }
+
+        let (mcus_x, mcus_y) = if n == 1 {
+            let c = &self.components[scomps[0]];
+            let cw = (self.width * c.h + self.hmax - 1) / self.hmax;
+            let ch = (self.height * c.v + self.vmax - 1) / self.vmax;
+            ((cw + 7) / 8, (ch + 7) / 8)
+        } else {
+            (
+                (self.width + 8 * self.hmax - 1) / (8 * self.hmax),
+                (self.height + 8 * self.vmax - 1) / (8 * self.vmax),
+            )
+        };
+        let mut bits = BitReader {
+            data,
+            pos: pos + len,
+            acc: 0,
+            nbits: 0,
+            marker: false,
+        };
+        self.eobrun = 0;
+        for c in &mut self.components {
+            c.pred = 0;
+        }
+        for m in 0..mcus_x * mcus_y {
+            if self.restart_interval > 0 && m > 0 && m % self.restart_interval == 0 {
+                bits.restart();
+                self.eobrun = 0;
+                for c in &mut self.components {
+                    c.pred = 0;
+                }
+            }
+            let (mx, my) = (m % mcus_x, m / mcus_x);
+            if n == 1 {
+                self.decode_block(&mut bits, scomps[0], my, mx, (ss, se, ah, al))?;
+            } else {
+                for &ci in &scomps {
+                    let (h, v) = (self.components[ci].h, self.components[ci].v);
+                    for y in 0..v {
+                        for x in 0..h {
+                            self.decode_block(
+                                &mut bits,
+                                ci,
+                                my * v + y,
+                                mx * h + x,
+                                (ss, se, ah, al),
+                            )?;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(bits.end())
+    }
+
+    fn decode_block(
+        &mut self,
+        bits: &mut BitReader,
+        ci: usize,
+        by: usize,
+        bx: usize,
+        (ss, se, ah, al): (usize, usize, u32, u32),
+    ) -> Result<(), &'static str> {
+        let c = &mut self.components[ci];
+        let block = &mut c.coefs[by * c.bw + bx];
+        if !self.progressive {
+            let t = bits.decode(self.dc[c.dc_table].as_ref().unwrap())?;
+            if t > 11 {
+                return Err("corrupt DC coefficient");
+            }
+            c.pred += bits.extend(t as u32);
+            block[0] = c.pred as i16;
+            let ac = self.ac[c.ac_table].as_ref().unwrap();
+            let mut k = 1;
+            while k < 64 {
+                let rs = bits.decode(ac)?;
+                let (r, s) = ((rs >> 4) as usize, (rs & 0xf) as u32);
+                if s == 0 {
+                    if r != 15 {
+                        break;
+                    }
+                    k += 16;
+                    continue;
+                }
+                k += r;
+                if k > 63 {
+                    return Err("corrupt AC coefficients");
+                }
+                block[ZIGZAG[k]] = bits.extend(s) as i16;
+                k += 1;
+            }
+            return Ok(());
+        }
+        if ss == 0 {
+            /* DC scans */
+            if ah == 0 {
+                let t = bits.decode(self.dc[c.dc_table].as_ref().unwrap())?;
+                if t > 11 {
+                    return Err("corrupt DC coefficient");
+                }
+                c.pred += bits.extend(t as u32);
+                block[0] = (c.pred << al) as i16;
+            } else if bits.bit() {
+                block[0] |= 1 << al;
+            }
+            return Ok(());
+        }
+        let ac = self.ac[c.ac_table].as_ref().unwrap();
+        if ah == 0 {
+            /* AC first pass */
+            if self.eobrun > 0 {
+                self.eobrun -= 1;
+                return Ok(());
+            }
+            let mut k = ss;
+            while k <= se {
+                let rs = bits.decode(ac)?;
+                let (r, s) = ((rs >> 4) as u32, (rs & 0xf) as u32);
+                if s == 0 {
+                    if r < 15 {
+                        self.eobrun = (1 << r) - 1 + bits.bits(r) as u32;
+                        break;
+                    }
+                    k += 16;
+                    continue;
+                }
+                k += r as usize;
+                if k > 63 {
+                    return Err("corrupt AC coefficients");
+                }
+                block[ZIGZAG[k]] = (bits.extend(s) * (1 << al)) as i16;
+                k += 1;
+            }
+            return Ok(());
+        }
+        /* AC refinement, following T.81 G.1.2.3 */
+        let p1 = 1i16 << al;
+        let m1 = -1i16 << al;
+        let mut k = ss;
+        if self.eobrun == 0 {
+            while k <= se {
+                let rs = bits.decode(ac)?;
+                let (mut r, s) = ((rs >> 4) as i32, rs & 0xf);
+                let mut val = 0;
+                if s != 0 {
+                    val = if bits.bit() { p1 } else { m1 };
+                } else if r != 15 {
+                    self.eobrun = (1 << r) + bits.bits(r as u32) as u32;
+                    break;
+                }
+                while k <= se {
+                    let z = ZIGZAG[k];
+                    if block[z] != 0 {
+                        if bits.bit() && block[z] & p1 == 0 {
+                            block[z] += if block[z] >= 0 { p1 } else { m1 };
+                        }
+                    } else {
+                        r -= 1;
+                        if r < 0 {
+                            break;
+                        }
+                    }
+                    k += 1;
+                }
+                if val != 0 && k <= se {
+                    block[ZIGZAG[k]] = val;
+                }
+                k += 1;
+            }
+        }
+        if self.eobrun > 0 {
+            while k <= se {
+                let z = ZIGZAG[k];
+                if block[z] != 0 && bits.bit() && block[z] & p1 == 0 {
+                    block[z] += if block[z] >= 0 { p1 } else { m1 };
+                }
+                k += 1;
+            }
+            self.eobrun -= 1;
+        }
+        Ok(())
+    }
+
+    fn output(&self) -> JpegPlanes {
+        let t = dct_table();
+        let mut planes = Vec::with_capacity(self.components.len());
+        for c in &self.components {
+            let stride = c.bw * 8;
+            let mut samples = vec![0u8; stride * c.bh * 8];
+            let q = &self.qt[c.tq];
+            for by in 0..c.bh {
+                for bx in 0..c.bw {
+                    let block = &c.coefs[by * c.bw + bx];
+                    let mut tmp = [0f32; 64];
+                    for v in 0..8 {
+                        for x in 0..8 {
+                            let mut s = 0.;
+                            for u in 0..8 {
+                                s += t[x][u] * (block[v * 8 + u] as f32 * q[v * 8 + u] as f32);
+                            }
+                            tmp[v * 8 + x] = s;
+                        }
+                    }
+                    for y in 0..8 {
+                        let row = &mut samples[(by * 8 + y) * stride + bx * 8..][..8];
+                        for (x, out) in row.iter_mut().enumerate() {
+                            let mut s = 128.;
+                            for v in 0..8 {
+                                s += t[y][v] * tmp[v * 8 + x];
+                            }
+                            *out = s.round().max(0.).min(255.) as u8;
+                        }
+                    }
+                }
+            }
+            let mut plane = Vec::with_capacity(self.width * self.height);
+            for y in 0..self.height {
+                let row = &samples[(y * c.v / self.vmax) * stride..];
+                plane.extend((0..self.width).map(|x| row[x * c.h / self.hmax]));
+            }
+            planes.push(plane);
+        }
+        let transform = match self.adobe {
+            Some(t) => t,
+            None if self.components.len() == 3 => 1,
+            None => 0,
+        };
+        JpegPlanes {
+            width: self.width,
+            height: self.height,
+            planes,
+            transform,
+        }
+    }
+}
+
+/// Decodes a baseline or progressive Huffman-coded JPEG image with 8-bit
+/// samples.
+pub fn decode(data: &[u8]) -> Result<JpegPlanes, &'static str> {
+    if data.len() < 4 || data[0] != 0xff || data[1] != 0xd8 {
+        return Err("not a JPEG file");
+    }
+    let mut d = Decoder {
+        width: 0,
+        height: 0,
+        progressive: false,
+        components: Vec::new(),
+        hmax: 1,
+        vmax: 1,
+        qt: [[1; 64]; 4],
+        dc: vec![None, None, None, None],
+        ac: vec![None, None, None, None],
+        restart_interval: 0,
+        eobrun: 0,
+        adobe: None,
+    };
+    let mut pos = 2;
+    loop {
+        /* Skip fill bytes and anything that is not a marker. */
+        while pos < data.len() && data[pos] != 0xff {
+            pos += 1;
+        }
+        while pos < data.len() && data[pos] == 0xff {
+            pos += 1;
+        }
+        if pos >= data.len() {
+            break;
+        }
+        let marker = data[pos];
+        pos += 1;
+        match marker {
+            0xd9 => break,
+            0xd0..=0xd7 | 0x01 => continue,
+            _ => {}
+        }
+        let len = be16(data, pos)?;
+        if len < 2 || pos + len > data.len() {
+            return Err("invalid marker segment");
+        }
+        let seg = &data[pos + 2..pos + len];
+        match marker {
+            0xc0 | 0xc1 => d.read_frame(seg, false)?,
+            0xc2 => d.read_frame(seg, true)?,
+            0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => {
+                return Err("unsupported JPEG coding process")
+            }
+            0xc4 => d.read_dht(seg)?,
+            0xdb => d.read_dqt(seg)?,
+            0xdd => d.restart_interval = be16(seg, 0)?,
+            0xda => {
+                if d.components.is_empty() {
+                    return Err("scan before frame header");
+                }
+                pos = d.decode_scan(data, pos)?;
+                continue;
+            }
+            0xee if seg.len() >= 12 && &seg[..5] == b"Adobe" => d.adobe = Some(seg[11]),
+            _ => {}
+        }
+        pos += len;
+    }
+    if d.components.is_empty() {
+        return Err("no image data");
+    }
+    Ok(d.output())
+}
+
+/* Encoder */
+
+struct HuffmanCodes {
+    /// `(code, length)` for each symbol.
+    codes: [(u16, u8); 256],
+}
+
+impl HuffmanCodes {
+    fn new(counts: &[u8; 16], values: &[u8]) -> HuffmanCodes {
+        let mut codes = [(0, 0); 256];
+        let mut code = 0u16;
+        let mut k = 0;
+        for (i, &n) in counts.iter().enumerate() {
+            for _ in 0..n {
+                codes[values[k] as usize] = (code, (i + 1) as u8);
+                code += 1;
+                k += 1;
+            }
+            code <<= 1;
+        }
+        HuffmanCodes { codes }
+    }
+}
+
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u32,
+    nbits: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, bits: u32, n: u32) {
+        self.acc = (self.acc << n) | (bits & ((1 << n) - 1));
+        self.nbits += n;
+        while self.nbits >= 8 {
+            let b = (self.acc >> (self.nbits - 8)) as u8;
+            self.out.push(b);
+            if b == 0xff {
+                self.out.push(0);
+            }
+            self.nbits -= 8;
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.nbits > 0 {
+            self.put(0x7f, 8 - self.nbits);
+        }
+    }
+
+    fn put_code(&mut self, h: &HuffmanCodes, symbol: u8) {
+        let (code, len) = h.codes[symbol as usize];
+        self.put(code as u32, len as u32);
+    }
+
+    fn put_value(&mut self, v: i32) -> u32 {
+        let size = 32 - v.abs().leading_zeros();
+        if size > 0 {
+            self.put(if v < 0 { (v - 1) as u32 } else { v as u32 }, size);
+        }
+        size
+    }
+}
+
+/// The IJG scaling of the Annex K table for `quality` (1 to 100).
+fn scale_quant_table(base: &[u16; 64], quality: u8) -> [u16; 64] {
+    let quality = quality.max(1).min(100) as u32;
+    let scale = if quality < 50 {
+        5000 / quality
+    } else {
+        200 - 2 * quality
+    };
+    let mut q = [0; 64];
+    for (q, &b) in q.iter_mut().zip(base.iter()) {
+        *q = ((b as u32 * scale + 50) / 100).max(1).min(255) as u16;
+    }
+    q
+}
+
+fn put_segment(out: &mut Vec<u8>, marker: u8, data: &[u8]) {
+    out.extend_from_slice(&[0xff, marker]);
+    out.extend_from_slice(&((data.len() + 2) as u16).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+/// Halves a plane in both directions by averaging 2x2 pixels.
+fn subsample(plane: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let (w2, h2) = ((width + 1) / 2, (height + 1) / 2);
+    let mut out = Vec::with_capacity(w2 * h2);
+    for y in 0..h2 {
+        let (y0, y1) = (2 * y, (2 * y + 1).min(height - 1));
+        for x in 0..w2 {
+            let (x0, x1) = (2 * x, (2 * x + 1).min(width - 1));
+            let sum = plane[y0 * width + x0] as u32
+                + plane[y0 * width + x1] as u32
+                + plane[y1 * width + x0] as u32
+                + plane[y1 * width + x1] as u32;
+            out.push(((sum + 2) / 4) as u8);
+        }
+    }
+    out
+}
+
+/// Encodes component planes as a baseline JPEG image. `transform` is the
+/// color transform the planes are in, as in [`JpegPlanes`]; it is recorded
+/// in an Adobe marker for three or more components. The chroma planes of
+/// YCbCr images are subsampled 2:1 in both directions.
+pub fn encode(
+    width: usize,
+    height: usize,
+    planes: &[Vec<u8>],
+    transform: u8,
+    quality: u8,
+) -> Vec<u8> {
+    let n = planes.len();
+    assert!((1..=4).contains(&n) && width > 0 && height > 0);
+    let chroma =
+        |i: usize| (transform == 1 && n == 3 || transform == 2 && n == 4) && i > 0 && i < 3;
+    let subsampled = transform == 1 && n == 3;
+    let qt = [
+        scale_quant_table(&STD_LUMINANCE_QT, quality),
+        scale_quant_table(&STD_CHROMINANCE_QT, quality),
+    ];
+    let dc = [
+        HuffmanCodes::new(&STD_DC_LUMINANCE_BITS, &STD_DC_VALUES),
+        HuffmanCodes::new(&STD_DC_CHROMINANCE_BITS, &STD_DC_VALUES),
+    ];
+    let ac = [
+        HuffmanCodes::new(&STD_AC_LUMINANCE_BITS, &STD_AC_LUMINANCE_VALUES),
+        HuffmanCodes::new(&STD_AC_CHROMINANCE_BITS, &STD_AC_CHROMINANCE_VALUES),
+    ];
+
+    let mut out = vec![0xff, 0xd8];
+    if n >= 3 {
+        let mut app14 = b"Adobe".to_vec();
+        app14.extend_from_slice(&[0, 100, 0, 0, 0, 0, transform]);
+        put_segment(&mut out, 0xee, &app14);
+    }
+    let mut dqt = Vec::with_capacity(130);
+    for (i, q) in qt.iter().enumerate() {
+        dqt.push(i as u8);
+        dqt.extend(ZIGZAG.iter().map(|&z| q[z] as u8));
+    }
+    put_segment(&mut out, 0xdb, &dqt);
+    let mut sof = vec![8];
+    sof.extend_from_slice(&(height as u16).to_be_bytes());
+    sof.extend_from_slice(&(width as u16).to_be_bytes());
+    sof.push(n as u8);
+    for i in 0..n {
+        let hv = if subsampled && i == 0 { 0x22 } else { 0x11 };
+        sof.extend_from_slice(&[i as u8 + 1, hv, chroma(i) as u8]);
+    }
+    put_segment(&mut out, 0xc0, &sof);
+    let mut dht = Vec::new();
+    for &(class, bits, values) in &[
+        (0x00, &STD_DC_LUMINANCE_BITS, &STD_DC_VALUES[..]),
+        (0x01, &STD_DC_CHROMINANCE_BITS, &STD_DC_VALUES[..]),
+        (0x10, &STD_AC_LUMINANCE_BITS, &STD_AC_LUMINANCE_VALUES[..]),
+        (
+            0x11,
+            &STD_AC_CHROMINANCE_BITS,
+            &STD_AC_CHROMINANCE_VALUES[..],
+        ),
+    ] {
+        dht.push(class);
+        dht.extend_from_slice(bits);
+        dht.extend_from_slice(values);
+    }
+    put_segment(&mut out, 0xc4, &dht);
+    let mut sos = vec![n as u8];
+    for i in 0..n {
+        let t = chroma(i) as u8;
+        sos.extend_from_slice(&[i as u8 + 1, t << 4 | t]);
+    }
+    sos.extend_from_slice(&[0, 63, 0]);
+    put_segment(&mut out, 0xda, &sos);
+
+    /* Component planes at their own resolution */
+    let comps: Vec<(usize, usize, Vec<u8>)> = planes
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            if subsampled && i > 0 {
+                (
+                    (width + 1) / 2,
+                    (height + 1) / 2,
+                    subsample(p, width, height),
+                )
+            } else {
+                (width, height, p.clone())
+            }
+        })
+        .collect();
+    let mcu = if subsampled { 16 } else { 8 };
+    let t = dct_table();
+    let mut bits = BitWriter {
+        out,
+        acc: 0,
+        nbits: 0,
+    };
+    let mut preds = [0i32; 4];
+    for my in 0..(height + mcu - 1) / mcu {
+        for mx in 0..(width + mcu - 1) / mcu {
+            for (i, (cw, ch, plane)) in comps.iter().enumerate() {
+                let blocks = if subsampled && i == 0 { 2 } else { 1 };
+                let tbl = chroma(i) as usize;
+                for v in 0..blocks {
+                    for h in 0..blocks {
+                        let (x0, y0) = ((mx * blocks + h) * 8, (my * blocks + v) * 8);
+                        let mut tmp = [0f32; 64];
+                        for y in 0..8 {
+                            let row = &plane[(y0 + y).min(ch - 1) * cw..];
+                            for u in 0..8 {
+                                let mut s = 0.;
+                                for x in 0..8 {
+                                    s += t[x][u] * (row[(x0 + x).min(cw - 1)] as f32 - 128.);
+                                }
+                                tmp[y * 8 + u] = s;
+                            }
+                        }
+                        let mut coefs = [0i32; 64];
+                        for v in 0..8 {
+                            for u in 0..8 {
+                                let mut s = 0.;
+                                for y in 0..8 {
+                                    s += t[y][v] * tmp[y * 8 + u];
+                                }
+                                coefs[v * 8 + u] = (s / qt[tbl][v * 8 + u] as f32).round() as i32;
+                            }
+                        }
+                        let diff = coefs[0] - preds[i];
+                        preds[i] = coefs[0];
+                        let size = 32 - diff.abs().leading_zeros();
+                        bits.put_code(&dc[tbl], size as u8);
+                        bits.put_value(diff);
+                        let mut run = 0;
+                        for &z in &ZIGZAG[1..] {
+                            let c = coefs[z];
+                            if c == 0 {
+                                run += 1;
+                                continue;
+                            }
+                            while run > 15 {
+                                bits.put_code(&ac[tbl], 0xf0);
+                                run -= 16;
+                            }
+                            let size = 32 - c.abs().leading_zeros();
+                            bits.put_code(&ac[tbl], (run << 4 | size) as u8);
+                            bits.put_value(c);
+                            run = 0;
+                        }
+                        if run > 0 {
+                            bits.put_code(&ac[tbl], 0);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    bits.flush();
+    let mut out = bits.out;
+    out.extend_from_slice(&[0xff, 0xd9]);
+    out
+}
+
+/// Converts interleaved RGB samples to JFIF YCbCr planes.
+pub fn rgb_to_ycbcr(rgb: &[u8]) -> Vec<Vec<u8>> {
+    let n = rgb.len() / 3;
+    let mut planes = vec![
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+    ];
+    for p in rgb.chunks(3) {
+        let (r, g, b) = (p[0] as f32, p[1] as f32, p[2] as f32);
+        let y = 0.299 * r + 0.587 * g + 0.114 * b;
+        let cb = -0.168_736 * r - 0.331_264 * g + 0.5 * b + 128.;
+        let cr = 0.5 * r - 0.418_688 * g - 0.081_312 * b + 128.;
+        for (plane, v) in planes.iter_mut().zip(&[y, cb, cr]) {
+            plane.push(v.round().max(0.).min(255.) as u8);
+        }
+    }
+    planes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gradient(w: usize, h: usize, phase: usize) -> Vec<u8> {
+        (0..w * h)
+            .map(|i| ((i % w) * 3 + (i / w) * 2 + phase * 40) as u8)
+            .collect()
+    }
+
+    fn max_error(a: &[u8], b: &[u8]) -> i32 {
+        a.iter()
+            .zip(b)
+            .map(|(&x, &y)| (x as i32 - y as i32).abs())
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        for &(w, h, n, transform) in &[(1, 1, 1, 0), (19, 13, 1, 0), (33, 17, 3, 1), (9, 24, 4, 0)]
+        {
+            let planes: Vec<Vec<u8>> = (0..n).map(|c| gradient(w, h, c)).collect();
+            let data = encode(w, h, &planes, transform, 95);
+            let decoded = decode(&data).unwrap();
+            assert_eq!((decoded.width, decoded.height), (w, h));
+            assert_eq!(decoded.transform, transform);
+            for (a, b) in decoded.planes.iter().zip(&planes) {
+                assert!(max_error(a, b) <= 8);
+            }
+        }
+    }
+
+    #[test]
+    fn quality_scaling() {
+        assert_eq!(scale_quant_table(&STD_LUMINANCE_QT, 50), STD_LUMINANCE_QT);
+        assert!(scale_quant_table(&STD_LUMINANCE_QT, 100)
+            .iter()
+            .all(|&q| q == 1));
+        let plane = gradient(64, 64, 0);
+        let small = encode(64, 64, &[plane.clone()], 0, 10).len();
+        let large = encode(64, 64, &[plane], 0, 90).len();
+        assert!(small < large);
+    }
+}
@@ -0,0 +1,833 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2002-2016 by Jin-Hwan Cho and Shunsaku Hirata,
+    the dvipdfmx project team.
+
+    Copyright (C) 1998, 1999 by Mark A. Wicks <mwicks@kettering.edu>
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! Linearized ("fast web view") output, as described in annex F of
+//! ISO 32000-1.
+//!
+//! The layout of a linearized file depends on which objects each page uses,
+//! which is only known once the document is complete. So while linearizing,
+//! objects are not written as they are released but handed to a
+//! `Linearizer`, serialized, together with the places of the indirect
+//! references in them. When the document is closed, the objects are sorted
+//! into the parts of a linearized file, renumbered to match, and written out
+//! with the linearization parameter dictionary, the first-page
+//! cross-reference table and the primary hint stream.
+//!
+//! All hint tables go into the primary hint stream, so there is never an
+//! overflow hint stream. Every shared object forms a group of its own in the
+//! shared object hint table, and, as with Acrobat, the content stream of a
+//! page is taken to span the whole page. Object streams are not used.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+/// An indirect reference in a serialized object.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ObjRef {
+    /// Where the reference starts in the serialized data.
+    pub pos: usize,
+    /// The length of the reference (`12 0 R`).
+    pub len: usize,
+    /// The label of the object referred to.
+    pub label: u32,
+}
+
+/// An object as `pdf_write_obj()` wrote it, without `obj`/`endobj`.
+#[derive(Clone, Debug, Default)]
+pub struct Captured {
+    pub data: Vec<u8>,
+    pub refs: Vec<ObjRef>,
+}
+
+/// Encrypts the data of the hint stream, given its object number.
+pub type HintEncrypter<'a> = &'a mut dyn FnMut(u32, &[u8]) -> Vec<u8>;
+
+#[derive(Default)]
+pub struct Linearizer {
+    objects: BTreeMap<u32, Captured>,
+    pages: Vec<u32>,
+    page_nodes: HashSet<u32>,
+}
+
+/* The linearization parameter dictionary and the first-page trailer must
+ * be written before the offsets they contain are known, so the numbers in
+ * them are padded to this width. */
+const NUM_WIDTH: usize = 10;
+
+impl Linearizer {
+    pub fn new() -> Self {
+        Linearizer::default()
+    }
+
+    pub fn add_object(&mut self, label: u32, object: Captured) {
+        self.objects.insert(label, object);
+    }
+
+    /// Pages are to be added in order.
+    pub fn add_page(&mut self, label: u32) {
+        self.pages.push(label);
+    }
+
+    /// Nodes of the page tree are not part of any page.
+    pub fn add_page_node(&mut self, label: u32) {
+        self.page_nodes.insert(label);
+    }
+
+    /// Lays out the document, which has a file header of `header_len` bytes,
+    /// and returns everything that follows the header. `trailer` is the
+    /// trailer dictionary without `/Size`; `encrypt` is given the object
+    /// number and data of the hint stream, if the document is encrypted.
+    pub fn finish(
+        mut self,
+        header_len: usize,
+        trailer: &Captured,
+        root: u32,
+        encrypt_dict: Option<u32>,
+        encrypt: Option<HintEncrypter>,
+    ) -> Vec<u8> {
+        let objects = &self.objects;
+        self.pages.retain(|l| objects.contains_key(l));
+        if self.pages.is_empty() || !self.objects.contains_key(&root) {
+            return self.finish_plain(header_len, trailer);
+        }
+        let npages = self.pages.len();
+
+        /* The objects each page uses: everything that can be reached from
+         * it without passing through the page tree or another page. */
+        let mut stop: HashSet<u32> = self.page_nodes.clone();
+        stop.extend(self.pages.iter().cloned());
+        stop.insert(root);
+        let users: Vec<Vec<u32>> = self
+            .pages
+            .iter()
+            .map(|&page| {
+                let mut seen = HashSet::new();
+                let mut todo = vec![page];
+                seen.insert(page);
+                while let Some(label) = todo.pop() {
+                    for r in &self.objects[&label].refs {
+                        if self.objects.contains_key(&r.label)
+                            && !stop.contains(&r.label)
+                            && seen.insert(r.label)
+                        {
+                            todo.push(r.label);
+                        }
+                    }
+                }
+                seen.remove(&page);
+                let mut used: Vec<u32> = seen.into_iter().collect();
+                used.sort();
+                used.insert(0, page);
+                used
+            })
+            .collect();
+        let mut use_count: HashMap<u32, usize> = HashMap::new();
+        for used in &users[1..] {
+            for &label in used {
+                *use_count.entry(label).or_insert(0) += 1;
+            }
+        }
+
+        /* Part 4: the document catalog and the encryption dictionary.
+         * Part 6: everything the first page uses, its page object first.
+         * Part 7: each other page with the objects only it uses.
+         * Part 8: the objects that other pages share.
+         * Part 9: everything else. */
+        let mut part4 = vec![root];
+        if let Some(label) = encrypt_dict {
+            if self.objects.contains_key(&label) && !users[0].contains(&label) {
+                part4.push(label);
+            }
+        }
+        let part6 = users[0].clone();
+        let in6: HashSet<u32> = part6.iter().cloned().collect();
+        let mut part7: Vec<Vec<u32>> = vec![];
+        for used in &users[1..] {
+            part7.push(
+                used.iter()
+                    .cloned()
+                    .filter(|l| !in6.contains(l) && use_count[l] == 1)
+                    .collect(),
+            );
+        }
+        let part8: Vec<u32> = self
+            .objects
+            .keys()
+            .cloned()
+            .filter(|l| !in6.contains(l) && *use_count.get(l).unwrap_or(&0) > 1)
+            .collect();
+        let mut placed: HashSet<u32> = in6.clone();
+        placed.extend(part4.iter().cloned());
+        placed.extend(part7.iter().flatten().cloned());
+        placed.extend(part8.iter().cloned());
+        let part9: Vec<u32> = self
+            .objects
+            .keys()
+            .cloned()
+            .filter(|l| !placed.contains(l))
+            .collect();
+
+        /* Objects of the main cross-reference table are numbered from 1 in
+         * file order; those of the first-page section come after them. */
+        let mut renumber: HashMap<u32, u32> = HashMap::new();
+        let mut num = 1;
+        for &label in part7.iter().flatten().chain(&part8).chain(&part9) {
+            renumber.insert(label, num);
+            num += 1;
+        }
+        let main_size = num;
+        let lin_num = num;
+        num += 1;
+        for &label in part4.iter().chain(&part6) {
+            renumber.insert(label, num);
+            num += 1;
+        }
+        let hint_num = num;
+        let size = num + 1;
+
+        let render = |label: u32| render_object(renumber[&label], &self.objects[&label], &renumber);
+        let part4_data: Vec<Vec<u8>> = part4.iter().map(|&l| render(l)).collect();
+        let part6_data: Vec<Vec<u8>> = part6.iter().map(|&l| render(l)).collect();
+        let part7_data: Vec<Vec<Vec<u8>>> = part7
+            .iter()
+            .map(|page| page.iter().map(|&l| render(l)).collect())
+            .collect();
+        let part8_data: Vec<Vec<u8>> = part8.iter().map(|&l| render(l)).collect();
+        let part9_data: Vec<Vec<u8>> = part9.iter().map(|&l| render(l)).collect();
+        let trailer_data = render_body(trailer, &renumber);
+
+        /* Offsets up to the hint stream */
+        let lin_len = lin_dict(lin_num, [0; 6], npages).len();
+        let first_xref_head = format!("xref\n{} {}\n", lin_num, size - lin_num);
+        let first_trailer_len = first_trailer(size, 0, &trailer_data).len();
+        let lin_offset = header_len;
+        let first_xref_offset = lin_offset + lin_len;
+        let mut pos = first_xref_offset
+            + first_xref_head.len()
+            + 20 * (size - lin_num) as usize
+            + first_trailer_len;
+        let mut offsets: HashMap<u32, usize> = HashMap::new();
+        for (label, data) in part4.iter().zip(&part4_data) {
+            offsets.insert(renumber[label], pos);
+            pos += data.len();
+        }
+        let hint_offset = pos;
+
+        /* Offsets after the hint stream, first as if it were not there:
+         * this is how the hint tables give them. */
+        let mut page_lengths: Vec<usize> = vec![];
+        let mut group_lengths: Vec<usize> = vec![];
+        let first_page_offset = pos;
+        for (label, data) in part6.iter().zip(&part6_data) {
+            offsets.insert(renumber[label], pos);
+            pos += data.len();
+            group_lengths.push(data.len());
+        }
+        page_lengths.push(pos - first_page_offset);
+        let first_page_end = pos;
+        for (page, data) in part7.iter().zip(&part7_data) {
+            let start = pos;
+            for (label, data) in page.iter().zip(data) {
+                offsets.insert(renumber[label], pos);
+                pos += data.len();
+            }
+            page_lengths.push(pos - start);
+        }
+        let first_shared_offset = if part8.is_empty() { 0 } else { pos };
+        for (label, data) in part8.iter().zip(&part8_data) {
+            offsets.insert(renumber[label], pos);
+            pos += data.len();
+            group_lengths.push(data.len());
+        }
+        for (label, data) in part9.iter().zip(&part9_data) {
+            offsets.insert(renumber[label], pos);
+            pos += data.len();
+        }
+
+        /* The primary hint stream */
+        let index: HashMap<u32, usize> = part6
+            .iter()
+            .chain(&part8)
+            .enumerate()
+            .map(|(i, &l)| (l, i))
+            .collect();
+        let mut page_entries = vec![PageEntry {
+            nobjects: part6.len(),
+            length: page_lengths[0],
+            shared: vec![],
+        }];
+        for (i, page) in part7.iter().enumerate() {
+            page_entries.push(PageEntry {
+                nobjects: page.len(),
+                length: page_lengths[i + 1],
+                shared: users[i + 1]
+                    .iter()
+                    .filter_map(|l| {
+                        if in6.contains(l) || use_count[l] > 1 {
+                            Some(index[l])
+                        } else {
+                            None
+                        }
+                    })
+                    .collect(),
+            });
+        }
+        let (mut hints, shared_table) = hint_tables(
+            &page_entries,
+            first_page_offset,
+            part6.len(),
+            &group_lengths,
+            part8.first().map_or(0, |l| renumber[l]),
+            first_shared_offset,
+        );
+        if let Some(encrypt) = encrypt {
+            hints = encrypt(hint_num, &hints);
+        }
+        let mut hint_data = format!(
+            "{} 0 obj\n<< /Length {} /S {} >>\nstream\n",
+            hint_num,
+            hints.len(),
+            shared_table
+        )
+        .into_bytes();
+        hint_data.extend_from_slice(&hints);
+        hint_data.extend_from_slice(b"\nendstream\nendobj\n");
+        let hint_len = hint_data.len();
+        for (label, offset) in offsets.iter_mut() {
+            if *offset >= hint_offset && !part4.iter().any(|l| renumber[l] == *label) {
+                *offset += hint_len;
+            }
+        }
+        offsets.insert(lin_num, lin_offset);
+        offsets.insert(hint_num, hint_offset);
+        let main_xref_offset = pos + hint_len;
+        let main_xref_head = format!("xref\n0 {}\n", main_size);
+        let main_trailer = format!(
+            "trailer\n<< /Size {} >>\nstartxref\n{}\n%%EOF\n",
+            main_size, first_xref_offset
+        );
+        let file_len =
+            main_xref_offset + main_xref_head.len() + 20 * main_size as usize + main_trailer.len();
+
+        /* Write it all out */
+        let mut out = lin_dict(
+            lin_num,
+            [
+                file_len,
+                hint_offset,
+                hint_len,
+                renumber[&self.pages[0]] as usize,
+                first_page_end + hint_len,
+                main_xref_offset + main_xref_head.len() - 1,
+            ],
+            npages,
+        );
+        out.extend_from_slice(first_xref_head.as_bytes());
+        for num in lin_num..size {
+            out.extend_from_slice(xref_entry(offsets[&num]).as_bytes());
+        }
+        out.extend(first_trailer(size, main_xref_offset, &trailer_data));
+        part4_data.iter().for_each(|d| out.extend_from_slice(d));
+        out.extend(hint_data);
+        part6_data.iter().for_each(|d| out.extend_from_slice(d));
+        part7_data
+            .iter()
+            .flatten()
+            .for_each(|d| out.extend_from_slice(d));
+        part8_data.iter().for_each(|d| out.extend_from_slice(d));
+        part9_data.iter().for_each(|d| out.extend_from_slice(d));
+        out.extend_from_slice(main_xref_head.as_bytes());
+        out.extend_from_slice(b"0000000000 65535 f \n");
+        for num in 1..main_size {
+            out.extend_from_slice(xref_entry(offsets[&num]).as_bytes());
+        }
+        out.extend_from_slice(main_trailer.as_bytes());
+        assert_eq!(header_len + out.len(), file_len);
+        out
+    }
+
+    /* Without pages there is nothing to linearize; write the objects in
+     * the usual way. */
+    fn finish_plain(self, header_len: usize, trailer: &Captured) -> Vec<u8> {
+        let size = self.objects.keys().next_back().map_or(1, |l| l + 1);
+        let renumber: HashMap<u32, u32> = self.objects.keys().map(|&l| (l, l)).collect();
+        let mut out = vec![];
+        let mut offsets = HashMap::new();
+        for (&label, object) in &self.objects {
+            offsets.insert(label, header_len + out.len());
+            out.extend(render_object(label, object, &renumber));
+        }
+        let startxref = header_len + out.len();
+        out.extend_from_slice(format!("xref\n0 {}\n", size).as_bytes());
+        for num in 0..size {
+            match offsets.get(&num) {
+                Some(&offset) => out.extend_from_slice(xref_entry(offset).as_bytes()),
+                None => out.extend_from_slice(b"0000000000 65535 f \n"),
+            }
+        }
+        let trailer_data = render_body(trailer, &renumber);
+        out.extend_from_slice(format!("trailer\n<</Size {} ", size).as_bytes());
+        out.extend_from_slice(&trailer_data[2..]);
+        out.extend_from_slice(format!("\nstartxref\n{}\n%%EOF\n", startxref).as_bytes());
+        out
+    }
+}
+
+struct PageEntry {
+    nobjects: usize,
+    length: usize,
+    shared: Vec<usize>,
+}
+
+/* Replaces the references of a serialized object by the new object
+ * numbers; objects that were never written become null. */
+fn render_body(object: &Captured, renumber: &HashMap<u32, u32>) -> Vec<u8> {
+    let mut out = Vec::with_capacity(object.data.len());
+    let mut last = 0;
+    for r in &object.refs {
+        out.extend_from_slice(&object.data[last..r.pos]);
+        match renumber.get(&r.label) {
+            Some(num) => out.extend_from_slice(format!("{} 0 R", num).as_bytes()),
+            None => out.extend_from_slice(b"null"),
+        }
+        last = r.pos + r.len;
+    }
+    out.extend_from_slice(&object.data[last..]);
+    out
+}
+
+fn render_object(num: u32, object: &Captured, renumber: &HashMap<u32, u32>) -> Vec<u8> {
+    let mut out = format!("{} 0 obj\n", num).into_bytes();
+    out.extend(render_body(object, renumber));
+    out.extend_from_slice(b"\nendobj\n");
+    out
+}
+
+fn xref_entry(offset: usize) -> String {
+    format!("{:010} 00000 n \n", offset)
+}
+
+/* values: /L, the two numbers of /H, /O, /E and /T */
+fn lin_dict(num: u32, values: [usize; 6], npages: usize) -> Vec<u8> {
+    format!(
+        "{} 0 obj\n<< /Linearized 1 /L {:<w$} /H [ {:<w$} {:<w$} ] /O {:<w$} /E {:<w$} /N {} /T {:<w$} >>\nendobj\n",
+        num,
+        values[0],
+        values[1],
+        values[2],
+        values[3],
+        values[4],
+        npages,
+        values[5],
+        w = NUM_WIDTH
+    )
+    .into_bytes()
+}
+
+fn first_trailer(size: u32, prev: usize, trailer_data: &[u8]) -> Vec<u8> {
+    let mut out = format!(
+        "trailer\n<< /Size {} /Prev {:<w$} ",
+        size,
+        prev,
+        w = NUM_WIDTH
+    )
+    .into_bytes();
+    out.extend_from_slice(&trailer_data[2..]);
+    out.extend_from_slice(b"\nstartxref\n0\n%%EOF\n");
+    out
+}
+
+/* The number of bits needed for values up to `value` */
+fn nbits(value: usize) -> u32 {
+    (std::mem::size_of::<usize>() * 8) as u32 - value.leading_zeros()
+}
+
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    used: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: usize, nbits: u32) {
+        for i in (0..nbits).rev() {
+            if self.used == 0 {
+                self.data.push(0);
+            }
+            if (value >> i) & 1 != 0 {
+                *self.data.last_mut().unwrap() |= 0x80 >> self.used;
+            }
+            self.used = (self.used + 1) % 8;
+        }
+    }
+
+    /* Each table column starts on a byte boundary. */
+    fn align(&mut self) {
+        self.used = 0;
+    }
+}
+
+/* Returns the contents of the primary hint stream and the offset of the
+ * shared object hint table in it. */
+fn hint_tables(
+    pages: &[PageEntry],
+    first_page_offset: usize,
+    nshared_first_page: usize,
+    group_lengths: &[usize],
+    first_shared_obj: u32,
+    first_shared_offset: usize,
+) -> (Vec<u8>, usize) {
+    let mut w = BitWriter::default();
+
+    /* Page offset hint table (table F.3 and F.4) */
+    let min_nobjects = pages.iter().map(|p| p.nobjects).min().unwrap();
+    let max_nobjects = pages.iter().map(|p| p.nobjects).max().unwrap();
+    let min_length = pages.iter().map(|p| p.length).min().unwrap();
+    let max_length = pages.iter().map(|p| p.length).max().unwrap();
+    let max_shared = pages.iter().map(|p| p.shared.len()).max().unwrap();
+    let nbits_nobjects = nbits(max_nobjects - min_nobjects);
+    let nbits_length = nbits(max_length - min_length);
+    let nbits_nshared = nbits(max_shared);
+    let nbits_identifier = nbits(group_lengths.len());
+    w.write(min_nobjects, 32);
+    w.write(first_page_offset, 32);
+    w.write(nbits_nobjects as usize, 16);
+    w.write(min_length, 32);
+    w.write(nbits_length as usize, 16);
+    w.write(0, 32); /* content stream offset */
+    w.write(0, 16);
+    w.write(min_length, 32); /* content stream length */
+    w.write(nbits_length as usize, 16);
+    w.write(nbits_nshared as usize, 16);
+    w.write(nbits_identifier as usize, 16);
+    w.write(0, 16); /* no fractional positions */
+    w.write(4, 16);
+    for p in pages {
+        w.write(p.nobjects - min_nobjects, nbits_nobjects);
+    }
+    w.align();
+    for p in pages {
+        w.write(p.length - min_length, nbits_length);
+    }
+    w.align();
+    for p in pages {
+        w.write(p.shared.len(), nbits_nshared);
+    }
+    w.align();
+    for p in pages {
+        for &id in &p.shared {
+            w.write(id, nbits_identifier);
+        }
+    }
+    w.align();
+    /* The numerators take no bits and the content stream offsets are
+     * all 0. */
+    for p in pages {
+        w.write(p.length - min_length, nbits_length);
+    }
+    w.align();
+    let shared_table = w.data.len();
+
+    /* Shared object hint table (table F.5 and F.6) */
+    let min_group = group_lengths.iter().cloned().min().unwrap_or(0);
+    let max_group = group_lengths.iter().cloned().max().unwrap_or(0);
+    let nbits_group = nbits(max_group - min_group);
+    w.write(first_shared_obj as usize, 32);
+    w.write(first_shared_offset, 32);
+    w.write(nshared_first_page, 32);
+    w.write(group_lengths.len(), 32);
+    w.write(0, 16); /* one object per group */
+    w.write(min_group, 32);
+    w.write(nbits_group as usize, 16);
+    for &len in group_lengths {
+        w.write(len - min_group, nbits_group);
+    }
+    w.align();
+    for _ in group_lengths {
+        w.write(0, 1); /* no signatures */
+    }
+    w.align();
+    (w.data, shared_table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(text: &str) -> Captured {
+        /* "@n" stands for a reference to the object labeled n. */
+        let mut c = Captured::default();
+        let mut rest = text;
+        while let Some(at) = rest.find('@') {
+            c.data.extend_from_slice(&rest.as_bytes()[..at]);
+            let digits: String = rest[at + 1..]
+                .chars()
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            let r = format!("{} 0 R", digits);
+            c.refs.push(ObjRef {
+                pos: c.data.len(),
+                len: r.len(),
+                label: digits.parse().unwrap(),
+            });
+            c.data.extend_from_slice(r.as_bytes());
+            rest = &rest[at + 1 + digits.len()..];
+        }
+        c.data.extend_from_slice(rest.as_bytes());
+        c
+    }
+
+    /* Keeps byte offsets intact, unlike from_utf8_lossy() */
+    fn ascii(data: &[u8]) -> String {
+        data.iter()
+            .map(|&b| if b < 0x80 { b as char } else { '.' })
+            .collect()
+    }
+
+    fn number_after(pdf: &[u8], key: &str, from: usize) -> usize {
+        let text = ascii(&pdf[from..]);
+        let at = text.find(key).unwrap() + key.len();
+        text[at..]
+            .trim_start()
+            .split(|c: char| !c.is_ascii_digit())
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    fn xref_offsets(pdf: &[u8], at: usize) -> HashMap<u32, usize> {
+        let text = ascii(&pdf[at..]);
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("xref"));
+        let mut head = lines.next().unwrap().split(' ');
+        let first: u32 = head.next().unwrap().parse().unwrap();
+        let count: u32 = head.next().unwrap().parse().unwrap();
+        (first..first + count)
+            .zip(lines)
+            .filter(|(_, l)| l.ends_with(" n "))
+            .map(|(n, l)| (n, l[..10].parse().unwrap()))
+            .collect()
+    }
+
+    struct BitReader<'a> {
+        data: &'a [u8],
+        bit: usize,
+    }
+
+    impl<'a> BitReader<'a> {
+        fn read(&mut self, nbits: usize) -> usize {
+            let mut value = 0;
+            for _ in 0..nbits {
+                let byte = self.data[self.bit / 8];
+                value = value << 1 | ((byte >> (7 - self.bit % 8)) & 1) as usize;
+                self.bit += 1;
+            }
+            value
+        }
+
+        fn align(&mut self) {
+            self.bit += (8 - self.bit % 8) % 8;
+        }
+    }
+
+    #[test]
+    fn linearized_layout() {
+        let header = b"%PDF-1.5\n%\xe4\xf0\xed\xf8\n";
+        let mut lin = Linearizer::new();
+        lin.add_object(1, object("<</Type/Catalog/Pages @2/Outlines @13>>"));
+        lin.add_object(
+            2,
+            object("<</Type/Pages/Count 3/Kids[@3 @4 @5]/MediaBox[0 0 612 792]>>"),
+        );
+        for (page, content) in &[(3, 7), (4, 8), (5, 9)] {
+            let extra = match page {
+                3 => "/Annots[@12]",
+                4 => "/XObject<</Im1 @10>>",
+                _ => "",
+            };
+            let fonts = if *page == 3 { "" } else { "/F2 @14" };
+            lin.add_object(
+                *page,
+                object(&format!(
+                    "<</Type/Page/Parent @2/Contents @{}/Resources<</Font<</F1 @6{}>>{}>>>>",
+                    content, fonts, extra
+                )),
+            );
+        }
+        lin.add_object(
+            6,
+            object("<</Type/Font/Subtype/Type1/BaseFont/Times-Roman>>"),
+        );
+        for content in 7..10 {
+            lin.add_object(
+                content,
+                object(&format!(
+                    "<</Length 9>>\nstream\nBT ET {}\nendstream",
+                    content
+                )),
+            );
+        }
+        lin.add_object(
+            10,
+            object("<</Type/XObject/Subtype/Image/Length 1>>\nstream\nx\nendstream"),
+        );
+        lin.add_object(11, object("<</Producer(test)>>"));
+        lin.add_object(12, object("<</Type/Annot/Subtype/Link/P @3/Dest[@5/Fit]>>"));
+        lin.add_object(13, object("<</Type/Outlines/First @15>>"));
+        lin.add_object(14, object("<</Type/Font/Subtype/Type1/BaseFont/Courier>>"));
+        lin.add_object(
+            15,
+            object("<</Title(One)/Parent @13/Dest[@4/Fit]/Next @99>>"),
+        );
+        for page in 3..6 {
+            lin.add_page(page);
+        }
+        lin.add_page_node(2);
+        let trailer = object("<</Root @1/Info @11/ID[<00><00>]>>");
+
+        let mut pdf = header.to_vec();
+        pdf.extend(lin.finish(header.len(), &trailer, 1, None, None));
+        let text = ascii(&pdf);
+
+        /* The linearization parameter dictionary comes first */
+        let lin_at = header.len();
+        assert!(text[lin_at..].starts_with("11 0 obj\n<< /Linearized 1 "));
+        assert_eq!(number_after(&pdf, "/L ", lin_at), pdf.len());
+        assert_eq!(number_after(&pdf, "/N ", lin_at), 3);
+        let h_offset = number_after(&pdf, "/H [", lin_at);
+        let h_len = number_after(&pdf, &format!("/H [ {:<10}", h_offset), lin_at);
+        let o = number_after(&pdf, "/O ", lin_at);
+        let e = number_after(&pdf, "/E ", lin_at);
+        let t = number_after(&pdf, "/T ", lin_at);
+
+        /* Both cross-reference tables point at the right objects */
+        let first_xref = text.find("\nxref\n").unwrap() + 1;
+        let main_xref = text.rfind("\nxref\n").unwrap() + 1;
+        let startxref = number_after(&pdf, "startxref\n", main_xref);
+        assert_eq!(startxref, first_xref);
+        assert_eq!(number_after(&pdf, "/Prev", first_xref), main_xref);
+        assert_eq!(number_after(&pdf, "/Size", first_xref), 18);
+        assert_eq!(t, main_xref + "xref\n0 11\n".len() - 1);
+        assert!(text[t + 1..].starts_with("0000000000 65535 f \n"));
+        let mut offsets = xref_offsets(&pdf, first_xref);
+        assert_eq!(offsets.len(), 7);
+        offsets.extend(xref_offsets(&pdf, main_xref));
+        assert_eq!(offsets.len(), 17);
+        for (num, offset) in &offsets {
+            assert!(text[*offset..].starts_with(&format!("{} 0 obj\n", num)));
+        }
+        assert_eq!(offsets[&11], lin_at);
+        assert!(offsets
+            .values()
+            .all(|&off| off <= h_offset || off >= h_offset + h_len));
+        assert!(text[h_offset..h_offset + h_len].ends_with("endstream\nendobj\n"));
+
+        /* The first page: its page object, then what it uses */
+        let first_page = &text[offsets[&(o as u32)]..];
+        assert!(first_page.contains("/Annots[16 0 R]"));
+        let first_page_objects: Vec<usize> = (o..18)
+            .map(|n| offsets[&(n as u32)])
+            .filter(|&off| off > h_offset)
+            .collect();
+        assert_eq!(first_page_objects.len(), 4);
+        let last = *first_page_objects.iter().max().unwrap();
+        assert_eq!(e, last + text[last..].find("endobj\n").unwrap() + 7);
+        assert!(text[e..].starts_with("1 0 obj\n<</Type/Page/Parent"));
+
+        /* The trailer refers to the renumbered catalog, the dangling
+         * reference is gone */
+        assert!(text[first_xref..].contains("/Root 12 0 R"));
+        assert!(text.contains("/Next null"));
+
+        /* Page offset hint table: pages follow each other from the first
+         * page object, hint stream not counted */
+        let hint = &pdf[h_offset..h_offset + h_len];
+        let data_at = ascii(hint).find("stream\n").unwrap() + 7;
+        let mut r = BitReader {
+            data: &hint[data_at..],
+            bit: 0,
+        };
+        let min_nobjects = r.read(32);
+        let first_page_offset = r.read(32);
+        let nbits_nobjects = r.read(16);
+        let min_length = r.read(32);
+        let nbits_length = r.read(16);
+        r.read(32 + 16 + 32 + 16);
+        let nbits_nshared = r.read(16);
+        let nbits_identifier = r.read(16);
+        r.read(32);
+        assert_eq!(first_page_offset + h_len, offsets[&(o as u32)]);
+        let nobjects: Vec<usize> = (0..3)
+            .map(|_| min_nobjects + r.read(nbits_nobjects))
+            .collect();
+        r.align();
+        let lengths: Vec<usize> = (0..3).map(|_| min_length + r.read(nbits_length)).collect();
+        r.align();
+        let nshared: Vec<usize> = (0..3).map(|_| r.read(nbits_nshared)).collect();
+        r.align();
+        assert_eq!(nobjects, vec![4, 3, 2]);
+        assert_eq!(nshared, vec![0, 2, 2]);
+        let ids: Vec<usize> = (0..4).map(|_| r.read(nbits_identifier)).collect();
+        /* The fonts: Times-Roman is shared with the first page, Courier
+         * between the other pages */
+        assert_eq!(ids, vec![1, 4, 1, 4]);
+        let mut page_offset = first_page_offset + h_len;
+        for (i, len) in lengths.iter().enumerate() {
+            let num = if i == 0 {
+                o
+            } else if i == 1 {
+                1
+            } else {
+                4
+            };
+            assert_eq!(offsets[&(num as u32)], page_offset);
+            page_offset += len;
+        }
+        let shared_offset = number_after(hint, "/S ", 0);
+        let mut r = BitReader {
+            data: &hint[data_at + shared_offset..],
+            bit: 0,
+        };
+        let first_shared_obj = r.read(32);
+        let first_shared_offset = r.read(32);
+        assert_eq!((r.read(32), r.read(32)), (4, 5));
+        assert_eq!(first_shared_obj, 6);
+        assert_eq!(first_shared_offset + h_len, offsets[&6]);
+        assert!(
+            text[offsets[&6]..].starts_with("6 0 obj\n<</Type/Font/Subtype/Type1/BaseFont/Courier")
+        );
+    }
+
+    #[test]
+    fn bits() {
+        assert_eq!(nbits(0), 0);
+        assert_eq!(nbits(1), 1);
+        assert_eq!(nbits(255), 8);
+        assert_eq!(nbits(256), 9);
+        let mut w = BitWriter::default();
+        w.write(0b101, 3);
+        w.write(0x1ff, 9);
+        w.align();
+        w.write(1, 1);
+        assert_eq!(w.data, vec![0b1011_1111, 0b1111_0000, 0b1000_0000]);
+    }
+}
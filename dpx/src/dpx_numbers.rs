@@ -238,6 +238,14 @@ pub unsafe extern "C" fn tt_get_signed_pair(handle: &mut InputHandleWrapper) ->
     pair
 }
 #[no_mangle]
+pub unsafe extern "C" fn tt_get_unsigned_triple(handle: &mut InputHandleWrapper) -> u32 {
+    let mut triple: u32 = 0_u32;
+    for _ in 0..3 {
+        triple = triple << 8i32 | tt_get_unsigned_byte(handle) as u32;
+    }
+    triple
+}
+#[no_mangle]
 pub unsafe extern "C" fn tt_get_unsigned_quad(handle: &mut InputHandleWrapper) -> u32 {
     let mut quad: u32 = 0_u32;
     for _ in 0..4 {
@@ -0,0 +1,715 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2002-2016 by Jin-Hwan Cho and Shunsaku Hirata,
+    the dvipdfmx project team.
+
+    Copyright (C) 1998, 1999 by Mark A. Wicks <mwicks@kettering.edu>
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+#![allow(non_upper_case_globals)]
+
+//! PDF/A-2b and PDF/A-3b output.
+//!
+//! When enabled, the document gets an sRGB output intent and an XMP packet
+//! mirroring the document information dictionary. Every object is looked at
+//! as it is written out, and anything the conformance level does not allow
+//! (unembedded fonts, non-standard blend modes, forbidden actions, ...) is
+//! reported as an error. The caller fails the run if any were found.
+
+use std::collections::HashSet;
+use std::ffi::CString;
+
+use super::dpx_error::{_dpx_ensure_output_handle, _dpx_message_handle};
+use super::dpx_pdfcolor::{iccp_get_profile_reference, iccp_load_profile};
+use super::dpx_pdfobj::{
+    pdf_add_array, pdf_add_dict, pdf_add_stream, pdf_boolean_value, pdf_dict, pdf_name_value,
+    pdf_new_array, pdf_new_dict, pdf_new_name, pdf_new_number, pdf_new_stream, pdf_new_string,
+    pdf_number_value, pdf_obj, pdf_string_length, pdf_string_value, PdfObjType,
+};
+use bridge::ttstub_issue_error;
+
+static mut pdfa_part: i32 = 0;
+static mut violations: Option<HashSet<String>> = None;
+
+const SRGB_IDENT: &str = "sRGB IEC61966-2.1";
+
+const STANDARD_BLEND_MODES: [&str; 17] = [
+    "Normal",
+    "Compatible",
+    "Multiply",
+    "Screen",
+    "Overlay",
+    "Darken",
+    "Lighten",
+    "ColorDodge",
+    "ColorBurn",
+    "HardLight",
+    "SoftLight",
+    "Difference",
+    "Exclusion",
+    "Hue",
+    "Saturation",
+    "Color",
+    "Luminosity",
+];
+
+const FORBIDDEN_ACTIONS: [&str; 11] = [
+    "Launch",
+    "Sound",
+    "Movie",
+    "ResetForm",
+    "ImportData",
+    "JavaScript",
+    "Hide",
+    "SetOCGState",
+    "Rendition",
+    "Trans",
+    "GoTo3DView",
+];
+
+const FORBIDDEN_ANNOTATIONS: [&str; 5] = ["Sound", "Movie", "Screen", "3D", "RichMedia"];
+
+/// Selects the PDF/A part to conform to: 2 or 3 (level B), or 0 for none.
+pub unsafe fn pdf_set_pdfa(part: i32) {
+    pdfa_part = part;
+    violations = None;
+}
+
+pub unsafe fn pdf_get_pdfa() -> i32 {
+    pdfa_part
+}
+
+/// Reports a conformance violation. Each distinct message is only reported
+/// once.
+pub unsafe fn pdfa_violation(message: &str) {
+    if pdfa_part == 0 {
+        return;
+    }
+    let seen = violations.get_or_insert_with(HashSet::new);
+    if !seen.insert(message.to_owned()) {
+        return;
+    }
+    _dpx_ensure_output_handle();
+    if let Some(handle) = _dpx_message_handle.as_mut() {
+        use std::io::Write;
+        let _ = writeln!(handle, "\nerror: PDF/A: {}", message);
+    }
+    let message = CString::new(format!("PDF/A: {}", message).replace('\0', "")).unwrap();
+    ttstub_issue_error(b"%s\x00" as *const u8 as *const i8, message.as_ptr());
+}
+
+/// The number of distinct violations reported so far.
+pub unsafe fn pdfa_violation_count() -> usize {
+    violations.as_ref().map(|v| v.len()).unwrap_or(0)
+}
+
+/// Builds the `/OutputIntents` array of the document catalog: an sRGB
+/// profile that device RGB and gray colors are interpreted in. If the
+/// profile cannot be embedded, this is reported as a violation and there is
+/// no array.
+pub unsafe fn pdfa_output_intents() -> Option<*mut pdf_obj> {
+    let profile = srgb_profile();
+    let ident = CString::new(SRGB_IDENT).unwrap();
+    let cspc_id = iccp_load_profile(
+        ident.as_ptr(),
+        profile.as_ptr() as *const libc::c_void,
+        profile.len() as i32,
+    );
+    let profile_ref = match iccp_get_profile_reference(cspc_id) {
+        Some(profile_ref) => profile_ref,
+        None => {
+            pdfa_violation("could not embed the sRGB output intent profile");
+            return None;
+        }
+    };
+    let intent = pdf_new_dict();
+    pdf_add_dict(&mut *intent, "Type", pdf_new_name("OutputIntent"));
+    pdf_add_dict(&mut *intent, "S", pdf_new_name("GTS_PDFA1"));
+    for key in ["OutputConditionIdentifier", "Info"].iter() {
+        pdf_add_dict(
+            &mut *intent,
+            *key,
+            pdf_new_string(
+                SRGB_IDENT.as_ptr() as *const libc::c_void,
+                SRGB_IDENT.len() as _,
+            ),
+        );
+    }
+    let registry = "http://www.color.org";
+    pdf_add_dict(
+        &mut *intent,
+        "RegistryName",
+        pdf_new_string(
+            registry.as_ptr() as *const libc::c_void,
+            registry.len() as _,
+        ),
+    );
+    pdf_add_dict(&mut *intent, "DestOutputProfile", profile_ref);
+    let intents = pdf_new_array();
+    pdf_add_array(&mut *intents, intent);
+    Some(intents)
+}
+
+/// Builds the XMP metadata stream for the document catalog from the
+/// finished document information dictionary.
+pub unsafe fn pdfa_metadata(docinfo: &pdf_obj) -> *mut pdf_obj {
+    let info = docinfo.as_dict();
+    let text = |key: &str| {
+        info.get(key).filter(|v| v.is_string()).map(|v| {
+            let bytes = std::slice::from_raw_parts(
+                pdf_string_value(v) as *const u8,
+                pdf_string_length(v) as usize,
+            );
+            decode_text_string(bytes)
+        })
+    };
+    let fields = XmpFields {
+        title: text("Title"),
+        author: text("Author"),
+        subject: text("Subject"),
+        keywords: text("Keywords"),
+        creator: text("Creator"),
+        producer: text("Producer"),
+        creation_date: text("CreationDate").and_then(|d| xmp_date(&d)),
+        mod_date: text("ModDate").and_then(|d| xmp_date(&d)),
+    };
+    let packet = xmp_packet(pdfa_part, &fields);
+    /* The metadata stream must stay readable without decoding. */
+    let stream = pdf_new_stream(0);
+    let dict = (*stream).as_stream_mut().get_dict_mut();
+    pdf_add_dict(dict, "Type", pdf_new_name("Metadata"));
+    pdf_add_dict(dict, "Subtype", pdf_new_name("XML"));
+    pdf_add_stream(
+        &mut *stream,
+        packet.as_ptr() as *const libc::c_void,
+        packet.len() as i32,
+    );
+    stream
+}
+
+/// Looks at an object that is about to be written to the output file.
+pub unsafe fn pdfa_check_object(object: &pdf_obj) {
+    if pdfa_part == 0 {
+        return;
+    }
+    check_object(object);
+}
+
+unsafe fn check_object(object: &pdf_obj) {
+    match PdfObjType::from(object.typ) {
+        PdfObjType::ARRAY => {
+            for &value in &object.as_array().values {
+                if !value.is_null() {
+                    check_object(&*value);
+                }
+            }
+        }
+        PdfObjType::DICT => check_dict(object.as_dict(), false),
+        PdfObjType::STREAM => check_dict(object.as_stream().get_dict().as_dict(), true),
+        _ => {}
+    }
+}
+
+unsafe fn name_of(object: Option<&pdf_obj>) -> Option<String> {
+    object
+        .filter(|o| o.is_name())
+        .map(|o| pdf_name_value(o).to_string_lossy().into_owned())
+}
+
+unsafe fn is_name(object: Option<&pdf_obj>, name: &str) -> bool {
+    object.map_or(false, |o| {
+        o.is_name() && pdf_name_value(o).to_bytes() == name.as_bytes()
+    })
+}
+
+unsafe fn uses_device_cmyk(object: &pdf_obj) -> bool {
+    if object.is_name() {
+        is_name(Some(object), "DeviceCMYK")
+    } else if object.is_array() {
+        object
+            .as_array()
+            .values
+            .iter()
+            .any(|&v| !v.is_null() && (*v).is_name() && uses_device_cmyk(&*v))
+    } else {
+        false
+    }
+}
+
+unsafe fn check_dict(dict: &pdf_dict, is_stream: bool) {
+    let typ = name_of(dict.get("Type"));
+    let subtype = name_of(dict.get("Subtype"));
+    let typ = typ.as_ref().map(|s| s.as_str());
+    let subtype = subtype.as_ref().map(|s| s.as_str());
+
+    match typ {
+        Some("FontDescriptor") => {
+            if !dict.has("FontFile") && !dict.has("FontFile2") && !dict.has("FontFile3") {
+                let name = name_of(dict.get("FontName")).unwrap_or_default();
+                pdfa_violation(&format!("font \"{}\" is not embedded", name));
+            }
+        }
+        Some("Font") => match subtype {
+            Some("Type1") | Some("MMType1") | Some("TrueType") | Some("CIDFontType0")
+            | Some("CIDFontType2") => {
+                if !dict.has("FontDescriptor") {
+                    let name = name_of(dict.get("BaseFont")).unwrap_or_default();
+                    pdfa_violation(&format!("font \"{}\" is not embedded", name));
+                }
+            }
+            _ => {}
+        },
+        Some("EmbeddedFile") => {
+            if pdfa_part == 3 && subtype.is_none() {
+                pdfa_violation("embedded file without a MIME type (/Subtype)");
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(bm) = dict.get("BM") {
+        let modes: Vec<&pdf_obj> = if bm.is_array() {
+            bm.as_array().values.iter().map(|&v| &*v).collect()
+        } else {
+            vec![bm]
+        };
+        for mode in modes {
+            match name_of(Some(mode)) {
+                Some(ref m) if STANDARD_BLEND_MODES.contains(&m.as_str()) => {}
+                Some(m) => pdfa_violation(&format!("blend mode \"{}\" is not allowed", m)),
+                None => pdfa_violation("invalid blend mode"),
+            }
+        }
+    }
+    if dict.has("TR") {
+        pdfa_violation("transfer functions (/TR) are not allowed");
+    }
+    if let Some(tr2) = dict.get("TR2") {
+        if !is_name(Some(tr2), "Default") {
+            pdfa_violation("transfer functions (/TR2) are not allowed");
+        }
+    }
+    if let Some(cs) = dict.get("ColorSpace").or_else(|| dict.get("CS")) {
+        let cmyk = if cs.is_dict() {
+            /* A resource dictionary of named color spaces */
+            let mut entry = cs.as_dict();
+            let mut found = false;
+            while !entry.key.is_null() {
+                found |= !entry.value.is_null() && uses_device_cmyk(&*entry.value);
+                entry = &*entry.next;
+            }
+            found
+        } else {
+            uses_device_cmyk(cs)
+        };
+        if cmyk {
+            pdfa_violation("DeviceCMYK is used but the output intent is sRGB");
+        }
+    }
+
+    if let Some(s) = name_of(dict.get("S")) {
+        if FORBIDDEN_ACTIONS.contains(&s.as_str()) {
+            pdfa_violation(&format!("\"{}\" actions are not allowed", s));
+        }
+    }
+    if dict.has("JS") {
+        pdfa_violation("JavaScript is not allowed");
+    }
+    if dict.has("AA") {
+        pdfa_violation("additional actions (/AA) are not allowed");
+    }
+
+    if typ == Some("Annot") || (dict.has("Rect") && subtype.is_some() && !is_stream) {
+        let subtype = subtype.unwrap_or("");
+        if FORBIDDEN_ANNOTATIONS.contains(&subtype) {
+            pdfa_violation(&format!("\"{}\" annotations are not allowed", subtype));
+        }
+        match dict.get("F").filter(|f| f.is_number()) {
+            Some(f) => {
+                let flags = pdf_number_value(f) as i32;
+                if flags & 4 == 0 || flags & (1 | 2 | 32) != 0 {
+                    pdfa_violation("annotations must be printable and visible");
+                }
+            }
+            None => pdfa_violation("annotation without flags (/F)"),
+        }
+        if subtype != "Link" && subtype != "Popup" && !dict.has("AP") {
+            pdfa_violation(&format!(
+                "\"{}\" annotation without an appearance stream",
+                subtype
+            ));
+        }
+    }
+
+    if dict.has("EF") {
+        if pdfa_part == 2 {
+            pdfa_violation("embedded files are not allowed in PDF/A-2b; use PDF/A-3b");
+        } else if !dict.has("AFRelationship") {
+            pdfa_violation("embedded file specification without /AFRelationship");
+        }
+    }
+
+    if let Some(interpolate) = dict.get("Interpolate") {
+        if interpolate.is_boolean() && pdf_boolean_value(interpolate) != 0 {
+            pdfa_violation("image interpolation is not allowed");
+        }
+    }
+    if dict.has("Alternates") || dict.has("OPI") {
+        pdfa_violation("alternate images and OPI are not allowed");
+    }
+    if subtype == Some("PS") || is_name(dict.get("Subtype2"), "PS") {
+        pdfa_violation("PostScript XObjects are not allowed");
+    }
+
+    if is_stream {
+        if dict.has("F") || dict.has("FFilter") || dict.has("FDecodeParms") {
+            pdfa_violation("streams with external data are not allowed");
+        }
+        if let Some(filter) = dict.get("Filter") {
+            let lzw = if filter.is_array() {
+                filter
+                    .as_array()
+                    .values
+                    .iter()
+                    .any(|&v| is_name(Some(&*v), "LZWDecode"))
+            } else {
+                is_name(Some(filter), "LZWDecode")
+            };
+            if lzw {
+                pdfa_violation("LZW compressed streams are not allowed");
+            }
+        }
+    }
+
+    /* Direct children: indirect ones are checked when they are written. */
+    let mut entry = dict;
+    while !entry.key.is_null() {
+        if !entry.value.is_null() {
+            check_object(&*entry.value);
+        }
+        entry = &*entry.next;
+    }
+}
+
+#[derive(Default)]
+struct XmpFields {
+    title: Option<String>,
+    author: Option<String>,
+    subject: Option<String>,
+    keywords: Option<String>,
+    creator: Option<String>,
+    producer: Option<String>,
+    creation_date: Option<String>,
+    mod_date: Option<String>,
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if (c as u32) < 0x20 => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn xmp_packet(part: i32, fields: &XmpFields) -> String {
+    let mut body = String::new();
+    body.push_str("   <dc:format>application/pdf</dc:format>\n");
+    if let Some(ref title) = fields.title {
+        body.push_str(&format!(
+            "   <dc:title><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:title>\n",
+            xml_escape(title)
+        ));
+    }
+    if let Some(ref author) = fields.author {
+        body.push_str(&format!(
+            "   <dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n",
+            xml_escape(author)
+        ));
+    }
+    if let Some(ref subject) = fields.subject {
+        body.push_str(&format!(
+            "   <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>\n",
+            xml_escape(subject)
+        ));
+    }
+    let simple = [
+        ("pdf:Keywords", &fields.keywords),
+        ("pdf:Producer", &fields.producer),
+        ("xmp:CreatorTool", &fields.creator),
+        ("xmp:CreateDate", &fields.creation_date),
+        ("xmp:ModifyDate", &fields.mod_date),
+    ];
+    for (tag, value) in simple.iter() {
+        if let Some(value) = value {
+            body.push_str(&format!("   <{0}>{1}</{0}>\n", tag, xml_escape(value)));
+        }
+    }
+    body.push_str(&format!("   <pdfaid:part>{}</pdfaid:part>\n", part));
+    body.push_str("   <pdfaid:conformance>B</pdfaid:conformance>\n");
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n \
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n  \
+         <rdf:Description rdf:about=\"\"\n    \
+         xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n    \
+         xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n    \
+         xmlns:pdf=\"http://ns.adobe.com/pdf/1.3/\"\n    \
+         xmlns:pdfaid=\"http://www.aiim.org/pdfa/ns/id/\">\n\
+         {}  </rdf:Description>\n \
+         </rdf:RDF>\n\
+         </x:xmpmeta>\n\
+         <?xpacket end=\"w\"?>",
+        body
+    )
+}
+
+/// Converts a PDF date (`D:YYYYMMDDHHmmSSOHH'mm'`) to the ISO 8601 form
+/// used by XMP.
+fn xmp_date(date: &str) -> Option<String> {
+    let date = date.trim_start_matches("D:");
+    let digits: String = date.chars().take_while(|c| c.is_ascii_digit()).collect();
+    if digits.len() < 4 {
+        return None;
+    }
+    let rest = &date[digits.len()..];
+    let field = |start: usize, default: &str| -> String {
+        digits.get(start..start + 2).unwrap_or(default).to_owned()
+    };
+    let mut out = format!("{}-{}-{}", &digits[0..4], field(4, "01"), field(6, "01"));
+    if digits.len() < 10 {
+        return Some(out);
+    }
+    out.push_str(&format!(
+        "T{}:{}:{}",
+        field(8, "00"),
+        field(10, "00"),
+        field(12, "00")
+    ));
+    let mut tz = rest.chars();
+    match tz.next() {
+        Some('Z') => out.push('Z'),
+        Some(sign) if sign == '+' || sign == '-' => {
+            let offset: String = tz.filter(|c| c.is_ascii_digit()).collect();
+            let hours = offset.get(0..2).unwrap_or("00");
+            let minutes = offset.get(2..4).unwrap_or("00");
+            out.push_str(&format!("{}{}:{}", sign, hours, minutes));
+        }
+        _ => {}
+    }
+    Some(out)
+}
+
+/// PDFDocEncoding code points that differ from ISO Latin-1.
+fn pdfdoc_char(b: u8) -> Option<char> {
+    const HIGH: [u16; 33] = [
+        0x2022, 0x2020, 0x2021, 0x2026, 0x2014, 0x2013, 0x0192, 0x2044, 0x2039, 0x203a, 0x2212,
+        0x2030, 0x201e, 0x201c, 0x201d, 0x2018, 0x2019, 0x201a, 0x2122, 0xfb01, 0xfb02, 0x0141,
+        0x0152, 0x0160, 0x0178, 0x017d, 0x0131, 0x0142, 0x0153, 0x0161, 0x017e, 0, 0x20ac,
+    ];
+    const LOW: [u16; 8] = [
+        0x02d8, 0x02c7, 0x02c6, 0x02d9, 0x02dd, 0x02db, 0x02da, 0x02dc,
+    ];
+    let code = match b {
+        0x18..=0x1f => LOW[(b - 0x18) as usize],
+        0x80..=0xa0 => HIGH[(b - 0x80) as usize],
+        0xad => 0,
+        _ => b as u16,
+    };
+    if code == 0 {
+        None
+    } else {
+        std::char::from_u32(code as u32)
+    }
+}
+
+/// Decodes a PDF text string, which is either UTF-16BE with a byte order
+/// mark or PDFDocEncoding.
+fn decode_text_string(bytes: &[u8]) -> String {
+    if bytes.len() >= 2 && bytes[0] == 0xfe && bytes[1] == 0xff {
+        let units: Vec<u16> = bytes[2..]
+            .chunks(2)
+            .filter(|c| c.len() == 2)
+            .map(|c| (c[0] as u16) << 8 | c[1] as u16)
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        bytes.iter().filter_map(|&b| pdfdoc_char(b)).collect()
+    }
+}
+
+fn s15_fixed16(v: f64) -> [u8; 4] {
+    ((v * 65536.0).round() as i32).to_be_bytes()
+}
+
+/// An ICC version 2.1 display profile for the sRGB color space.
+fn srgb_profile() -> Vec<u8> {
+    fn tag_type(sig: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut tag = sig.to_vec();
+        tag.extend_from_slice(&[0; 4]);
+        tag.extend_from_slice(data);
+        while tag.len() % 4 != 0 {
+            tag.push(0);
+        }
+        tag
+    }
+    fn xyz(x: f64, y: f64, z: f64) -> Vec<u8> {
+        let mut data = Vec::new();
+        for v in [x, y, z].iter() {
+            data.extend_from_slice(&s15_fixed16(*v));
+        }
+        tag_type(b"XYZ ", &data)
+    }
+
+    let mut desc = Vec::new();
+    desc.extend_from_slice(&(SRGB_IDENT.len() as u32 + 1).to_be_bytes());
+    desc.extend_from_slice(SRGB_IDENT.as_bytes());
+    desc.push(0);
+    desc.extend_from_slice(&[0; 8]); /* no Unicode description */
+    desc.extend_from_slice(&[0; 3 + 67]); /* no ScriptCode description */
+    let mut cprt = b"No copyright, use freely".to_vec();
+    cprt.push(0);
+    let mut curve = Vec::new();
+    curve.extend_from_slice(&1024u32.to_be_bytes());
+    for i in 0..1024 {
+        let v = i as f64 / 1023.0;
+        let linear = if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        };
+        curve.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+    }
+
+    let trc = tag_type(b"curv", &curve);
+    let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+        (b"desc", tag_type(b"desc", &desc)),
+        (b"cprt", tag_type(b"text", &cprt)),
+        (b"wtpt", xyz(0.9642, 1.0, 0.8249)),
+        (b"rXYZ", xyz(0.4361, 0.2225, 0.0139)),
+        (b"gXYZ", xyz(0.3851, 0.7169, 0.0971)),
+        (b"bXYZ", xyz(0.1431, 0.0606, 0.7141)),
+        (b"rTRC", trc.clone()),
+        (b"gTRC", trc.clone()),
+        (b"bTRC", trc),
+    ];
+
+    let table_len = 4 + 12 * tags.len();
+    let mut table = (tags.len() as u32).to_be_bytes().to_vec();
+    let mut data: Vec<u8> = Vec::new();
+    let mut trc_offset = None;
+    for (sig, body) in &tags {
+        let is_trc = sig.ends_with(b"TRC");
+        let offset = match (is_trc, trc_offset) {
+            (true, Some(offset)) => offset,
+            _ => {
+                let offset = 128 + table_len + data.len();
+                data.extend_from_slice(body);
+                if is_trc {
+                    trc_offset = Some(offset);
+                }
+                offset
+            }
+        };
+        table.extend_from_slice(&sig[..]);
+        table.extend_from_slice(&(offset as u32).to_be_bytes());
+        table.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    }
+
+    let size = 128 + table.len() + data.len();
+    let mut profile = Vec::with_capacity(size);
+    profile.extend_from_slice(&(size as u32).to_be_bytes());
+    profile.extend_from_slice(&[0; 4]); /* preferred CMM */
+    profile.extend_from_slice(&0x0210_0000u32.to_be_bytes());
+    profile.extend_from_slice(b"mntrRGB XYZ ");
+    for v in [1998u16, 2, 9, 6, 49, 0].iter() {
+        profile.extend_from_slice(&v.to_be_bytes());
+    }
+    profile.extend_from_slice(b"acsp");
+    profile.extend_from_slice(&[0; 28]); /* platform, flags, device, attributes */
+    profile.extend_from_slice(&0u32.to_be_bytes()); /* perceptual intent */
+    for v in [0.9642, 1.0, 0.8249].iter() {
+        profile.extend_from_slice(&s15_fixed16(*v));
+    }
+    profile.resize(128, 0);
+    profile.extend_from_slice(&table);
+    profile.extend_from_slice(&data);
+    profile
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_profile_layout() {
+        let profile = srgb_profile();
+        assert_eq!(profile.len() % 4, 0);
+        assert_eq!(
+            u32::from_be_bytes([profile[0], profile[1], profile[2], profile[3]]) as usize,
+            profile.len()
+        );
+        assert_eq!(&profile[12..24], b"mntrRGB XYZ ");
+        assert_eq!(&profile[36..40], b"acsp");
+        let count = u32::from_be_bytes([profile[128], profile[129], profile[130], profile[131]]);
+        assert_eq!(count, 9);
+        for i in 0..count as usize {
+            let entry = &profile[132 + 12 * i..144 + 12 * i];
+            let offset = u32::from_be_bytes([entry[4], entry[5], entry[6], entry[7]]) as usize;
+            let len = u32::from_be_bytes([entry[8], entry[9], entry[10], entry[11]]) as usize;
+            assert_eq!(offset % 4, 0);
+            assert!(offset + len <= profile.len());
+        }
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(
+            xmp_date("D:20200131235960+01'30'").unwrap(),
+            "2020-01-31T23:59:60+01:30"
+        );
+        assert_eq!(
+            xmp_date("D:19700101000000Z").unwrap(),
+            "1970-01-01T00:00:00Z"
+        );
+        assert_eq!(xmp_date("D:2019").unwrap(), "2019-01-01");
+        assert_eq!(xmp_date("yesterday"), None);
+    }
+
+    #[test]
+    fn text_strings() {
+        assert_eq!(decode_text_string(b"\xfe\xff\x00A\x03\xb1"), "A\u{3b1}");
+        assert_eq!(
+            decode_text_string(b"caf\xe9 \x84 \x93"),
+            "caf\u{e9} \u{2014} \u{fb01}"
+        );
+        let fields = XmpFields {
+            title: Some("A & <B>".to_owned()),
+            ..Default::default()
+        };
+        let packet = xmp_packet(3, &fields);
+        assert!(packet.contains(">A &amp; &lt;B&gt;</rdf:li>"));
+        assert!(packet.contains("<pdfaid:part>3</pdfaid:part>"));
+        assert!(!packet.contains("xmp:CreateDate"));
+    }
+}
@@ -23,13 +23,14 @@
 
 use crate::DisplayExt;
 
+use super::dpx_imagecache;
 use super::dpx_mem::{new, renew};
 use super::dpx_numbers::sget_unsigned_pair;
 use super::dpx_pdfdev::{pdf_dev_get_param, pdf_dev_reset_color};
 use crate::dpx_pdfobj::{
     pdf_add_array, pdf_add_dict, pdf_add_stream, pdf_get_version, pdf_link_obj, pdf_new_array,
-    pdf_new_name, pdf_new_number, pdf_new_stream, pdf_obj, pdf_ref_obj, pdf_release_obj,
-    STREAM_COMPRESS,
+    pdf_new_name, pdf_new_number, pdf_new_stream, pdf_indirect, pdf_obj, pdf_ref_obj,
+    pdf_release_obj, STREAM_COMPRESS,
 };
 use crate::mfree;
 use crate::shims::sprintf;
@@ -1025,6 +1026,7 @@ pub unsafe extern "C" fn iccp_load_profile(
             info!("(ICCP:[id={}])", cspc_id);
         }
         release_iccbased_cdata(cdata);
+        dpx_imagecache::note_icc_profile(ident, profile, proflen, cspc_id);
         return cspc_id;
     }
     if VERBOSE > 1i32 {
@@ -1043,6 +1045,7 @@ pub unsafe extern "C" fn iccp_load_profile(
     pdf_add_stream(&mut *stream, profile, proflen);
     pdf_release_obj(stream);
     cspc_id = pdf_colorspace_defineresource(ident, 4i32, cdata, resource);
+    dpx_imagecache::note_icc_profile(ident, profile, proflen, cspc_id);
     cspc_id
 }
 static mut CSPC_CACHE: CspcCache = CspcCache {
@@ -1169,6 +1172,30 @@ pub unsafe extern "C" fn pdf_get_colorspace_reference(mut cspc_id: i32) -> *mut
     }
     pdf_link_obj((*colorspace).reference)
 }
+/// The object number of the color space's reference, if it has been
+/// referred to yet.
+pub unsafe fn pdf_colorspace_label(cspc_id: i32) -> Option<u32> {
+    let colorspace = &*CSPC_CACHE.colorspaces.offset(cspc_id as isize);
+    if colorspace.reference.is_null() {
+        None
+    } else {
+        Some((*((*colorspace.reference).data as *mut pdf_indirect)).label)
+    }
+}
+/// A reference to the profile stream of an ICC based color space, or `None`
+/// if `cspc_id` is not one. Only available until the color space itself has
+/// been referred to.
+pub unsafe fn iccp_get_profile_reference(cspc_id: i32) -> Option<*mut pdf_obj> {
+    if cspc_id < 0 || cspc_id as u32 >= CSPC_CACHE.count {
+        return None;
+    }
+    let colorspace = &*CSPC_CACHE.colorspaces.offset(cspc_id as isize);
+    if colorspace.subtype != 4 || colorspace.resource.is_null() {
+        return None;
+    }
+    let stream_ref = *(*colorspace.resource).as_array().values.get(1)?;
+    Some(pdf_link_obj(stream_ref))
+}
 #[no_mangle]
 pub unsafe extern "C" fn pdf_init_colors() {
     CSPC_CACHE.count = 0_u32;
@@ -44,13 +44,16 @@ use super::dpx_pdfdraw::{
     pdf_dev_clear_gstates, pdf_dev_current_depth, pdf_dev_grestore, pdf_dev_grestore_to,
     pdf_dev_gsave, pdf_dev_init_gstates,
 };
-use super::dpx_pdfdraw::{pdf_dev_concat, pdf_dev_set_color, pdf_dev_transform};
+use super::dpx_pdfdraw::{
+    pdf_dev_concat, pdf_dev_currentmatrix, pdf_dev_set_color, pdf_dev_transform,
+};
 use super::dpx_pdffont::{
     pdf_font_findresource, pdf_get_font_encoding, pdf_get_font_reference, pdf_get_font_subtype,
     pdf_get_font_usedchars, pdf_get_font_wmode,
 };
 use super::dpx_pdfximage::{
-    pdf_ximage_get_reference, pdf_ximage_get_resname, pdf_ximage_scale_image,
+    pdf_ximage_get_reference, pdf_ximage_get_resname, pdf_ximage_put_annots,
+    pdf_ximage_record_placement, pdf_ximage_scale_image,
 };
 use crate::dpx_pdfobj::{pdf_link_obj, pdf_obj, pdf_release_obj, pdfobj_escape_str};
 use crate::shims::sprintf;
@@ -179,6 +182,21 @@ impl Rect {
     pub fn upper_left(&self) -> Coord {
         Coord::new(self.ll.x, self.ur.y)
     }
+    /// The smallest rectangle containing this one transformed by `M`.
+    pub fn transform(&self, M: &TMatrix) -> Self {
+        let corners = [
+            M.transform_point(self.lower_left()),
+            M.transform_point(self.lower_right()),
+            M.transform_point(self.upper_right()),
+            M.transform_point(self.upper_left()),
+        ];
+        let mut r = Self::from((corners[0], corners[0]));
+        for c in &corners[1..] {
+            r.ll = Coord::new(r.ll.x.min(c.x), r.ll.y.min(c.y));
+            r.ur = Coord::new(r.ur.x.max(c.x), r.ur.y.max(c.y));
+        }
+        r
+    }
 }
 impl From<(Coord, Coord)> for Rect {
     fn from(c: (Coord, Coord)) -> Self {
@@ -1373,6 +1391,39 @@ pub unsafe extern "C" fn pdf_dev_set_string(
     pdf_doc_add_page_content(&format_buffer[..len as usize]); /* op: */
     text_state.offset += width;
 }
+/* Shows a string in text rendering mode 3 (neither fill nor stroke): the
+ * text can be searched and copied, but what is seen on the page is drawn
+ * by other means, as for color glyphs.
+ */
+pub unsafe fn pdf_dev_set_invisible_string(
+    xpos: spt_t,
+    ypos: spt_t,
+    instr: &[u8],
+    width: spt_t,
+    font_id: i32,
+) {
+    if font_id < 0i32 || font_id >= num_dev_fonts {
+        panic!("Invalid font: {} ({})", font_id, num_dev_fonts);
+    }
+    graphics_mode();
+    pdf_dev_gsave();
+    /* Q restores the rendering mode, but not our idea of it. */
+    let bold_param = text_state.bold_param;
+    dev_set_font(font_id);
+    pdf_doc_add_page_content(b" 3 Tr");
+    pdf_dev_set_string(
+        xpos,
+        ypos,
+        instr.as_ptr() as *const libc::c_void,
+        instr.len() as size_t,
+        width,
+        font_id,
+        -1i32,
+    );
+    graphics_mode();
+    pdf_dev_grestore();
+    text_state.bold_param = bold_param;
+}
 #[no_mangle]
 pub unsafe extern "C" fn pdf_init_device(
     mut dvi2pts: f64,
@@ -1925,6 +1976,8 @@ pub unsafe extern "C" fn pdf_dev_put_image(
     let M1 = pdf_ximage_scale_image(id, &mut r, p);
     M = M1.post_transform(&M);
     pdf_dev_concat(&mut M);
+    pdf_ximage_record_placement(id, &pdf_dev_currentmatrix());
+    pdf_ximage_put_annots(id, &pdf_dev_currentmatrix());
     /* Clip */
     if p.flags & 1i32 << 3i32 != 0 {
         r.clip(); /* op: Do */
@@ -2132,3 +2185,19 @@ pub unsafe extern "C" fn pdf_dev_reset_global_state() {
     max_dev_fonts = 0i32;
     num_phys_fonts = 0i32;
 }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rect_transform() {
+        let r = Rect::from((Coord::new(10., 20.), Coord::new(30., 60.)));
+        let t = r.transform(&TMatrix::identity());
+        assert_eq!((t.ll, t.ur), (r.ll, r.ur));
+        /* A quarter turn followed by a translation */
+        let m = TMatrix::row_major(0., 1., -1., 0., 100., 0.);
+        let t = r.transform(&m);
+        assert_eq!(t.ll, Coord::new(40., 10.));
+        assert_eq!(t.ur, Coord::new(80., 30.));
+    }
+}
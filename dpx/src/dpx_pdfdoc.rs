@@ -49,6 +49,7 @@ use super::dpx_pdfdraw::{
     pdf_dev_current_depth, pdf_dev_grestore, pdf_dev_grestore_to, pdf_dev_gsave,
     pdf_dev_pop_gstate, pdf_dev_push_gstate, pdf_dev_set_color,
 };
+use super::dpx_pdfa::{pdf_get_pdfa, pdfa_metadata, pdfa_output_intents};
 use super::dpx_pdfencrypt::{pdf_enc_id_array, pdf_encrypt_obj};
 use super::dpx_pdffont::{
     get_unique_time_if_given, pdf_close_fonts, pdf_font_set_verbose, pdf_init_fonts,
@@ -57,6 +58,10 @@ use super::dpx_pdfnames::{
     pdf_delete_name_tree, pdf_names_add_object, pdf_names_create_tree, pdf_new_name_tree,
 };
 use super::dpx_pdfresource::{pdf_close_resources, pdf_init_resources};
+use super::dpx_pdfstruct::{
+    pdf_struct_add_annot, pdf_struct_begin_page, pdf_struct_close, pdf_struct_content_marker,
+    pdf_struct_end_page, pdf_struct_is_tagged,
+};
 use super::dpx_pdfximage::{
     pdf_close_images, pdf_init_images, pdf_ximage_defineresource, pdf_ximage_findresource,
     pdf_ximage_get_reference, pdf_ximage_init_form_info, pdf_ximage_set_verbose, XInfo,
@@ -67,7 +72,8 @@ use crate::dpx_pdfobj::{
     pdf_deref_obj, pdf_file, pdf_file_get_catalog, pdf_link_obj,
     pdf_merge_dict, pdf_name_value, pdf_new_array, pdf_new_dict, pdf_new_name, pdf_new_number,
     pdf_new_stream, pdf_new_string, pdf_number_value, pdf_obj, pdf_obj_typeof, pdf_out_flush,
-    pdf_out_init, pdf_ref_obj, pdf_release_obj, pdf_remove_dict, pdf_set_encrypt, pdf_set_id,
+    pdf_out_init, pdf_out_mark_page, pdf_out_mark_page_node, pdf_ref_obj, pdf_release_obj,
+    pdf_remove_dict, pdf_set_encrypt, pdf_set_id,
     pdf_set_info, pdf_set_root, pdf_stream_length, pdf_string_length,
     pdf_string_value, PdfObjType, STREAM_COMPRESS,
 };
@@ -541,6 +547,11 @@ unsafe fn pdf_doc_close_docinfo(mut p: *mut pdf_doc) {
             pdf_new_string(now.as_ptr() as *const libc::c_void, l as _),
         );
     }
+    if pdf_get_pdfa() != 0 && !(*(*p).root.dict).as_dict().has("Metadata") {
+        let metadata = pdfa_metadata(&*docinfo);
+        pdf_add_dict(&mut *(*p).root.dict, "Metadata", pdf_ref_obj(metadata));
+        pdf_release_obj(metadata);
+    }
     pdf_release_obj(docinfo);
     (*p).info = 0 as *mut pdf_obj;
 }
@@ -679,6 +690,7 @@ unsafe fn doc_flush_page(
         pdf_add_dict(&mut *(*page).page_obj, "B", pdf_ref_obj((*page).beads));
         pdf_release_obj((*page).beads);
     }
+    pdf_out_mark_page(&*(*page).page_obj);
     pdf_release_obj((*page).page_obj);
     pdf_release_obj((*page).page_ref);
     (*page).page_obj = 0 as *mut pdf_obj;
@@ -704,6 +716,11 @@ unsafe fn build_page_tree(
     } else {
         pdf_ref_obj((*p).root.pages)
     };
+    pdf_out_mark_page_node(if !parent_ref.is_null() {
+        &*self_0
+    } else {
+        &*(*p).root.pages
+    });
     pdf_add_dict(&mut *self_0, "Type", pdf_new_name("Pages"));
     pdf_add_dict(&mut *self_0, "Count", pdf_new_number(num_pages as f64));
     if !parent_ref.is_null() {
@@ -1906,6 +1923,11 @@ pub unsafe extern "C" fn pdf_doc_add_annot(
         pdf_new_number(((annbox.ur.y + annot_grow) / 0.001 + 0.5).floor() * 0.001),
     );
     pdf_add_dict(&mut *annot_dict, "Rect", rect_array);
+    if pdf_get_pdfa() != 0 && !(*annot_dict).as_dict().has("F") {
+        /* PDF/A wants every annotation to be printed. */
+        pdf_add_dict(&mut *annot_dict, "F", pdf_new_number(4.0));
+    }
+    pdf_struct_add_annot(annot_dict);
     pdf_add_array(&mut *(*page).annots, pdf_ref_obj(annot_dict));
     if new_annot != 0 {
         pdf_doc_add_goto(annot_dict);
@@ -2413,10 +2435,16 @@ unsafe fn doc_fill_page_background(p: &mut pdf_doc) {
     }
     let saved_content = currentpage.contents;
     currentpage.contents = currentpage.background;
+    if pdf_struct_is_tagged() {
+        pdf_doc_add_page_marker(b" /Artifact BMC");
+    }
     pdf_dev_gsave();
     pdf_dev_set_color(&bgcolor, 0x20, 0);
     r.fill();
     pdf_dev_grestore();
+    if pdf_struct_is_tagged() {
+        pdf_doc_add_page_marker(b" EMC");
+    }
     currentpage.contents = saved_content;
 }
 #[no_mangle]
@@ -2432,12 +2460,14 @@ pub unsafe extern "C" fn pdf_doc_begin_page(mut scale: f64, mut x_origin: f64, m
     );
     /* pdf_doc_new_page() allocates page content stream. */
     pdf_doc_new_page(p);
+    pdf_struct_begin_page();
     pdf_dev_bop(&mut M);
 }
 #[no_mangle]
 pub unsafe extern "C" fn pdf_doc_end_page() {
     let p = &mut pdoc;
     pdf_dev_eop();
+    pdf_struct_end_page();
     doc_fill_page_background(p);
     pdf_doc_finish_page(p);
 }
@@ -2451,16 +2481,25 @@ pub unsafe fn pdf_doc_add_page_content(buffer: &[u8]) {
             buffer.len() as i32,
         );
     } else {
-        let currentpage =
-            &mut *p.pages.entries.offset(p.pages.num_entries as isize) as *mut pdf_page;
-        pdf_add_stream(
-            &mut *(*currentpage).contents,
-            buffer.as_ptr() as *const libc::c_void,
-            buffer.len() as i32,
-        );
+        if let Some(marker) = pdf_struct_content_marker() {
+            pdf_doc_add_page_marker(&marker);
+        }
+        pdf_doc_add_page_marker(buffer);
     };
 }
 
+/// Adds to the content stream of the current page itself, without starting
+/// a marked-content sequence and even while a form XObject is being built.
+pub unsafe fn pdf_doc_add_page_marker(buffer: &[u8]) {
+    let p = &mut pdoc;
+    let currentpage = &mut *p.pages.entries.offset(p.pages.num_entries as isize) as *mut pdf_page;
+    pdf_add_stream(
+        &mut *(*currentpage).contents,
+        buffer.as_ptr() as *const libc::c_void,
+        buffer.len() as i32,
+    );
+}
+
 static mut doccreator: *mut i8 = 0 as *const i8 as *mut i8;
 /* Ugh */
 #[no_mangle]
@@ -2498,6 +2537,11 @@ pub unsafe extern "C" fn pdf_open_document(
     pdf_doc_init_names(p, check_gotos);
     pdf_doc_init_page_tree(p, media_width, media_height);
     pdf_doc_set_bgcolor(None);
+    if pdf_get_pdfa() != 0 {
+        if let Some(intents) = pdfa_output_intents() {
+            pdf_add_dict(&mut *p.root.dict, "OutputIntents", intents);
+        }
+    }
     if enable_encrypt {
         let mut encrypt: *mut pdf_obj = pdf_encrypt_obj();
         pdf_set_encrypt(encrypt);
@@ -2550,6 +2594,7 @@ pub unsafe extern "C" fn pdf_close_document() {
     pdf_doc_close_names(p);
     pdf_doc_close_bookmarks(p);
     pdf_doc_close_page_tree(p);
+    pdf_struct_close(&mut *(*p).root.dict);
     pdf_doc_close_docinfo(p);
     pdf_doc_close_catalog(p);
     pdf_close_images();
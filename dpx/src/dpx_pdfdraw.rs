@@ -29,6 +29,7 @@
 
 use crate::warn;
 
+use super::dpx_pdfa::pdfa_violation;
 use super::dpx_pdfcolor::{PdfColor, BLACK};
 use super::dpx_pdfdev::{
     graphics_mode, pdf_dev_get_param, pdf_dev_reset_fonts, pdf_sprint_coord, pdf_sprint_length,
@@ -772,6 +773,7 @@ pub unsafe extern "C" fn pdf_dev_set_color(color: &PdfColor, mut mask: i8, mut f
             len += 1;
         }
         PdfColor::Cmyk(..) => {
+            pdfa_violation("DeviceCMYK is used but the output intent is sRGB");
             fmt_buf[len] = b'K' | mask as u8;
             len += 1;
         }
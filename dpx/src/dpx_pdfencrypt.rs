@@ -30,14 +30,17 @@
 use std::slice::from_raw_parts;
 
 use super::dpx_dpxcrypt::ARC4_CONTEXT;
-use super::dpx_dpxcrypt::{AES_cbc_encrypt_tectonic, AES_ecb_encrypt, ARC4_set_key, ARC4};
+use super::dpx_dpxcrypt::{
+    AES_cbc_decrypt_tectonic, AES_cbc_encrypt_tectonic, AES_ecb_encrypt, ARC4_set_key, ARC4,
+};
 use super::dpx_mem::new;
 use super::dpx_pdfdoc::pdf_doc_get_dictionary;
 use super::dpx_pdffont::get_unique_time_if_given;
 use super::dpx_unicode::{UC_UTF8_decode_char, UC_is_valid};
 use crate::dpx_pdfobj::{
-    pdf_add_array, pdf_add_dict, pdf_get_version, pdf_new_array, pdf_new_dict, pdf_new_name,
-    pdf_new_number, pdf_new_string, pdf_obj,
+    pdf_add_array, pdf_add_dict, pdf_boolean_value, pdf_get_version, pdf_name_value, pdf_new_array,
+    pdf_new_dict, pdf_new_name, pdf_new_number, pdf_new_string, pdf_number_value, pdf_obj,
+    pdf_string_length, pdf_string_value,
 };
 use crate::warn;
 use chrono::prelude::*;
@@ -229,7 +232,7 @@ unsafe fn compute_owner_password(p: &mut pdf_sec, mut opasswd: *const i8, mut up
     }
     memcpy(
         p.O.as_mut_ptr() as *mut libc::c_void,
-        hash.as_mut_ptr() as *const libc::c_void,
+        tmp1.as_mut_ptr() as *const libc::c_void,
         32,
     );
 }
@@ -247,6 +250,9 @@ unsafe fn compute_encryption_key(p: &mut pdf_sec, mut passwd: *const i8) {
     tmp[3] = ((p.P >> 24i32) as u8 as i32 & 0xffi32) as u8;
     md5.input(&tmp);
     md5.input(&p.ID);
+    if p.R >= 4 && p.setting.encrypt_metadata == 0 {
+        md5.input(&[0xff_u8; 4]);
+    }
     let mut hash = md5.result();
     if p.R >= 3i32 {
         for _ in 0..50 {
@@ -627,7 +633,10 @@ pub unsafe extern "C" fn pdf_enc_set_passwd(
     if p.key_size == 5i32 {
         /* 40bit */
         p.V = 1i32
-    } else if p.key_size <= 16i32 {
+    } else if p.key_size < 16i32 {
+        /* AESV2 only takes 128-bit keys. */
+        p.V = 2i32
+    } else if p.key_size == 16i32 {
         p.V = if p.setting.use_aes != 0 { 4i32 } else { 2i32 }
     } else if p.key_size == 32i32 {
         p.V = 5i32
@@ -900,4 +909,443 @@ pub unsafe extern "C" fn pdf_enc_set_generation(mut generation: u32) {
     let p = &mut sec_data;
     p.label.gennum = generation as u16;
 }
+/// Whether objects are encrypted with keys derived from their numbers, as
+/// they are by everything but AES-256.
+pub unsafe fn pdf_enc_key_depends_on_label() -> bool {
+    sec_data.V < 5
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum CryptMethod {
+    Identity,
+    RC4,
+    AES,
+}
+
+/// Decrypts the strings and streams of an encrypted PDF file that is read,
+/// which can be done without asking for a password as long as the user
+/// password is empty.
+pub struct PdfDecrypter {
+    sec: pdf_sec,
+    strings: CryptMethod,
+    streams: CryptMethod,
+}
+
+impl PdfDecrypter {
+    /// Opens the file with the encryption dictionary `encrypt` and the first
+    /// string of its `/ID` with the empty user password. Files that need a
+    /// password, or that use something other than the standard security
+    /// handler, are reported and give `None`.
+    pub unsafe fn new(encrypt: &pdf_obj, id: &[u8]) -> Option<Self> {
+        let dict = encrypt.as_dict();
+        let number = |key: &str, default: i32| {
+            dict.get(key)
+                .filter(|obj| obj.is_number())
+                .map_or(default, |obj| pdf_number_value(obj) as i32)
+        };
+        let string = |key: &str| {
+            dict.get(key).filter(|obj| obj.is_string()).map(|obj| {
+                from_raw_parts(
+                    pdf_string_value(obj) as *const u8,
+                    pdf_string_length(obj) as usize,
+                )
+            })
+        };
+        let is_name = |obj: Option<&pdf_obj>, name: &[u8]| {
+            obj.map_or(false, |obj| {
+                obj.is_name() && pdf_name_value(obj).to_bytes() == name
+            })
+        };
+        if !is_name(dict.get("Filter"), b"Standard") {
+            warn!("PDF document is encrypted with an unsupported security handler.");
+            return None;
+        }
+        /* Crypt filters name the method for strings and streams */
+        let method = |key: &str| -> Option<CryptMethod> {
+            let filter = match dict.get(key) {
+                Some(name) if name.is_name() => pdf_name_value(name).to_bytes(),
+                _ => return Some(CryptMethod::Identity),
+            };
+            if filter == b"Identity" {
+                return Some(CryptMethod::Identity);
+            }
+            let cfm = dict
+                .get("CF")
+                .filter(|cf| cf.is_dict())
+                .and_then(|cf| cf.as_dict().get(filter))
+                .filter(|cf| cf.is_dict())
+                .and_then(|cf| cf.as_dict().get("CFM"));
+            if cfm.is_none() || is_name(cfm, b"None") {
+                Some(CryptMethod::Identity)
+            } else if is_name(cfm, b"V2") {
+                Some(CryptMethod::RC4)
+            } else if is_name(cfm, b"AESV2") || is_name(cfm, b"AESV3") {
+                Some(CryptMethod::AES)
+            } else {
+                None
+            }
+        };
+
+        let mut p: pdf_sec = std::mem::zeroed();
+        p.V = number("V", 0);
+        p.R = number("R", 0);
+        p.P = number("P", 0);
+        p.setting.encrypt_metadata = dict
+            .get("EncryptMetadata")
+            .filter(|obj| obj.is_boolean())
+            .map_or(1, |obj| (pdf_boolean_value(obj) != 0) as i32);
+        let (strings, streams) = match p.V {
+            1 | 2 => {
+                p.key_size = if p.V == 1 {
+                    5
+                } else {
+                    number("Length", 40) / 8
+                };
+                (Some(CryptMethod::RC4), Some(CryptMethod::RC4))
+            }
+            4 | 5 => {
+                p.key_size = if p.V == 4 { 16 } else { 32 };
+                (method("StrF"), method("StmF"))
+            }
+            _ => (None, None),
+        };
+        let (strings, streams) = match (strings, streams) {
+            (Some(strings), Some(streams)) if (5..=16).contains(&p.key_size) || p.V == 5 => {
+                (strings, streams)
+            }
+            _ => {
+                warn!("Unsupported PDF encryption: V {}, R {}.", p.V, p.R);
+                return None;
+            }
+        };
+        let (O, U) = match (string("O"), string("U")) {
+            (Some(O), Some(U)) => (O, U),
+            _ => {
+                warn!("Broken PDF encryption dictionary.");
+                return None;
+            }
+        };
+
+        let empty = b"\x00".as_ptr() as *const i8;
+        let opened = if p.V < 5 {
+            if !(2..=4).contains(&p.R) || O.len() < 32 || U.len() < 32 || id.len() != 16 {
+                warn!("Broken PDF encryption dictionary.");
+                return None;
+            }
+            p.O[..32].copy_from_slice(&O[..32]);
+            p.ID.copy_from_slice(id);
+            /* Algorithm 6: the U entry of the empty password matches. */
+            let mut q = p;
+            compute_user_password(&mut q, empty);
+            p.key = q.key;
+            let checked = if p.R == 2 { 32 } else { 16 };
+            q.U[..checked] == U[..checked]
+        } else {
+            let UE = string("UE").unwrap_or(&[]);
+            if !(5..=6).contains(&p.R) || U.len() < 48 || UE.len() < 32 {
+                warn!("Broken PDF encryption dictionary.");
+                return None;
+            }
+            /* Algorithm 2.A */
+            let hash = compute_hash_V5(empty, U[32..].as_ptr(), 0 as *const u8, p.R);
+            hash[..] == U[..32] && {
+                let hash = compute_hash_V5(empty, U[40..].as_ptr(), 0 as *const u8, p.R);
+                let iv = [0_u8; 16];
+                let mut plain: *mut u8 = 0 as *mut u8;
+                let mut plain_len: size_t = 0;
+                let r = AES_cbc_decrypt_tectonic(
+                    hash.as_ptr(),
+                    32,
+                    iv.as_ptr(),
+                    0,
+                    UE.as_ptr(),
+                    32,
+                    &mut plain,
+                    &mut plain_len,
+                );
+                if r >= 0 {
+                    p.key.copy_from_slice(from_raw_parts(plain, 32));
+                    free(plain as *mut libc::c_void);
+                }
+                r >= 0
+            }
+        };
+        if !opened {
+            warn!("PDF document is protected by a password.");
+            return None;
+        }
+        Some(PdfDecrypter {
+            sec: p,
+            strings,
+            streams,
+        })
+    }
+
+    /// Whether the document metadata stream is encrypted as well.
+    pub fn encrypts_metadata(&self) -> bool {
+        self.sec.setting.encrypt_metadata != 0
+    }
+
+    /// Decrypts a string, or the data of a stream, of the object with this
+    /// number and generation.
+    pub unsafe fn decrypt(
+        &self,
+        stream: bool,
+        label: u32,
+        generation: u16,
+        data: &[u8],
+    ) -> Vec<u8> {
+        /* Some writers leave empty strings alone */
+        if data.is_empty() {
+            return vec![];
+        }
+        let mut p = self.sec;
+        p.label.objnum = label as u64;
+        p.label.gennum = generation;
+        match if stream { self.streams } else { self.strings } {
+            CryptMethod::Identity => data.to_vec(),
+            CryptMethod::RC4 => {
+                /* calculate_key() salts the key for AES from V 4 on. */
+                p.V = 2;
+                let key = calculate_key(&mut p);
+                let mut arc4: ARC4_CONTEXT = ARC4_CONTEXT {
+                    idx_i: 0,
+                    idx_j: 0,
+                    sbox: [0; 256],
+                };
+                let mut plain = vec![0_u8; data.len()];
+                ARC4_set_key(&mut arc4, (p.key_size + 5).min(16) as u32, key.as_ptr());
+                ARC4(
+                    &mut arc4,
+                    data.len() as u32,
+                    data.as_ptr(),
+                    plain.as_mut_ptr(),
+                );
+                plain
+            }
+            CryptMethod::AES => {
+                let key = if p.V == 5 {
+                    p.key
+                } else {
+                    p.V = 4;
+                    let mut key = [0_u8; 32];
+                    key[..16].copy_from_slice(&calculate_key(&mut p));
+                    key
+                };
+                let mut plain: *mut u8 = 0 as *mut u8;
+                let mut plain_len: size_t = 0;
+                if AES_cbc_decrypt_tectonic(
+                    key.as_ptr(),
+                    if p.V == 5 { 32 } else { 16 },
+                    0 as *const u8,
+                    1,
+                    data.as_ptr(),
+                    data.len() as size_t,
+                    &mut plain,
+                    &mut plain_len,
+                ) < 0
+                {
+                    warn!("Cannot decrypt data of object {} {}.", label, generation);
+                    return data.to_vec();
+                }
+                let result = from_raw_parts(plain, plain_len as usize).to_vec();
+                free(plain as *mut libc::c_void);
+                result
+            }
+        }
+    }
+}
 /* Order is important here */
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dpx_pdfobj::{pdf_release_obj, pdf_set_version};
+    use std::ffi::CString;
+
+    unsafe fn aes_decrypt(key: &[u8], iv: Option<&[u8; 16]>, data: &[u8]) -> Option<Vec<u8>> {
+        let mut plain: *mut u8 = 0 as *mut u8;
+        let mut plain_len: size_t = 0;
+        let padding = if iv.is_some() { 0 } else { 1 };
+        if AES_cbc_decrypt_tectonic(
+            key.as_ptr(),
+            key.len() as size_t,
+            iv.map_or(0 as *const u8, |iv| iv.as_ptr()),
+            padding,
+            data.as_ptr(),
+            data.len() as size_t,
+            &mut plain,
+            &mut plain_len,
+        ) < 0
+        {
+            return None;
+        }
+        let result = from_raw_parts(plain, plain_len as usize).to_vec();
+        free(plain as *mut libc::c_void);
+        Some(result)
+    }
+
+    unsafe fn encrypt(plain: &[u8]) -> Vec<u8> {
+        let mut cipher: *mut u8 = 0 as *mut u8;
+        let mut cipher_len: size_t = 0;
+        pdf_encrypt_data(
+            plain.as_ptr(),
+            plain.len() as size_t,
+            &mut cipher,
+            &mut cipher_len,
+        );
+        let result = from_raw_parts(cipher, cipher_len as usize).to_vec();
+        free(cipher as *mut libc::c_void);
+        result
+    }
+
+    /* Algorithm 2.A of ISO 32000-2: the file key, as a reader finds it. */
+    unsafe fn file_key_r6(p: &pdf_sec, passwd: &str) -> Option<Vec<u8>> {
+        let passwd = CString::new(passwd).unwrap();
+        let iv = [0u8; 16];
+        let hash = compute_hash_V5(passwd.as_ptr(), p.O[32..].as_ptr(), p.U.as_ptr(), 6);
+        if hash[..] == p.O[..32] {
+            let hash = compute_hash_V5(passwd.as_ptr(), p.O[40..].as_ptr(), p.U.as_ptr(), 6);
+            return aes_decrypt(&hash, Some(&iv), &p.OE);
+        }
+        let hash = compute_hash_V5(passwd.as_ptr(), p.U[32..].as_ptr(), 0 as *const u8, 6);
+        if hash[..] == p.U[..32] {
+            let hash = compute_hash_V5(passwd.as_ptr(), p.U[40..].as_ptr(), 0 as *const u8, 6);
+            return aes_decrypt(&hash, Some(&iv), &p.UE);
+        }
+        None
+    }
+
+    /* The encryption dictionary for the current settings. pdf_encrypt_obj()
+     * needs a document for AES-256. */
+    unsafe fn encryption_dict(p: &pdf_sec) -> *mut pdf_obj {
+        let dict = pdf_new_dict();
+        let string = |data: &[u8]| {
+            pdf_new_string(data.as_ptr() as *const libc::c_void, data.len() as size_t)
+        };
+        let len = if p.V == 5 { 48 } else { 32 };
+        pdf_add_dict(&mut *dict, "Filter", pdf_new_name("Standard"));
+        pdf_add_dict(&mut *dict, "V", pdf_new_number(p.V as f64));
+        pdf_add_dict(&mut *dict, "R", pdf_new_number(p.R as f64));
+        pdf_add_dict(&mut *dict, "P", pdf_new_number(p.P as f64));
+        pdf_add_dict(
+            &mut *dict,
+            "Length",
+            pdf_new_number((p.key_size * 8) as f64),
+        );
+        pdf_add_dict(&mut *dict, "O", string(&p.O[..len]));
+        pdf_add_dict(&mut *dict, "U", string(&p.U[..len]));
+        if p.V == 5 {
+            pdf_add_dict(&mut *dict, "OE", string(&p.OE));
+            pdf_add_dict(&mut *dict, "UE", string(&p.UE));
+        }
+        if p.V >= 4 {
+            let cf = pdf_new_dict();
+            let std_cf = pdf_new_dict();
+            let cfm = if p.V == 4 { "AESV2" } else { "AESV3" };
+            pdf_add_dict(&mut *std_cf, "CFM", pdf_new_name(cfm));
+            pdf_add_dict(&mut *cf, "StdCF", std_cf);
+            pdf_add_dict(&mut *dict, "CF", cf);
+            pdf_add_dict(&mut *dict, "StmF", pdf_new_name("StdCF"));
+            pdf_add_dict(&mut *dict, "StrF", pdf_new_name("StdCF"));
+        }
+        dict
+    }
+
+    #[test]
+    fn aes_known_answers() {
+        /* FIPS-197, appendix C */
+        let plain: Vec<u8> = (0..16).map(|i| i * 0x11).collect();
+        let key: Vec<u8> = (0..32).collect();
+        for &(key_len, expected) in &[
+            (16, "69c4e0d86a7b0430d8cdb78070b4c55a"),
+            (24, "dda97ca4864cdfe06eaf70a0ec0d7191"),
+            (32, "8ea2b7ca516745bfeafc49904b496089"),
+        ] {
+            unsafe {
+                let mut cipher: *mut u8 = 0 as *mut u8;
+                let mut cipher_len: size_t = 0;
+                AES_ecb_encrypt(
+                    key.as_ptr(),
+                    key_len as size_t,
+                    plain.as_ptr(),
+                    16,
+                    &mut cipher,
+                    &mut cipher_len,
+                );
+                let block = from_raw_parts(cipher, 16).to_vec();
+                free(cipher as *mut libc::c_void);
+                let hex: String = block.iter().map(|b| format!("{:02x}", b)).collect();
+                assert_eq!(hex, expected);
+                let decrypted = aes_decrypt(&key[..key_len], Some(&[0; 16]), &block);
+                assert_eq!(decrypted, Some(plain.clone()));
+            }
+        }
+    }
+
+    #[test]
+    fn encrypted_data_decrypts() {
+        let content = b"BT /F1 10 Tf 72 720 Td (Hello, world!) Tj ET".to_vec();
+        let owner = CString::new("owner secret").unwrap();
+        let user = CString::new("user").unwrap();
+        let empty = CString::new("").unwrap();
+        unsafe {
+            pdf_set_version(7);
+            pdf_enc_compute_id_string(None, None);
+
+            /* AES-256, revision 6 */
+            pdf_enc_set_passwd(256, 0x3c, owner.as_ptr(), user.as_ptr());
+            let p = sec_data;
+            assert_eq!((p.V, p.R, p.key_size), (5, 6, 32));
+            assert_eq!(p.P as u32, 0xfffff0fc);
+            assert_eq!(file_key_r6(&p, "user"), Some(p.key.to_vec()));
+            assert_eq!(file_key_r6(&p, "owner secret"), Some(p.key.to_vec()));
+            assert_eq!(file_key_r6(&p, "wrong"), None);
+            pdf_enc_set_label(12);
+            pdf_enc_set_generation(0);
+            let cipher = encrypt(&content);
+            assert_ne!(cipher[16..], content[..]);
+            assert_eq!(aes_decrypt(&p.key, None, &cipher), Some(content.clone()));
+
+            /* AES-128, revision 4: the key also depends on the object */
+            pdf_enc_set_passwd(128, 0x3c, owner.as_ptr(), user.as_ptr());
+            let mut p = sec_data;
+            assert_eq!((p.V, p.R), (4, 4));
+            let cipher = encrypt(&content);
+            let key = calculate_key(&mut p);
+            assert_eq!(aes_decrypt(&key, None, &cipher), Some(content.clone()));
+
+            /* Shorter keys stay with RC4 */
+            pdf_enc_set_passwd(64, 0x3c, owner.as_ptr(), user.as_ptr());
+            let p = sec_data;
+            assert_eq!((p.V, p.R, p.key_size), (2, 3, 8));
+
+            /* Reading it back with the empty user password: RC4 with 40 and
+             * 128 bits, AES-128 and AES-256 */
+            for &(bits, use_aes) in &[(40, 0), (128, 0), (128, 1), (256, 1)] {
+                sec_data.setting.use_aes = use_aes;
+                pdf_enc_set_passwd(bits, 0x3c, owner.as_ptr(), empty.as_ptr());
+                let p = sec_data;
+                let dict = encryption_dict(&p);
+                let dec = PdfDecrypter::new(&*dict, &p.ID).expect("cannot open");
+                assert!(dec.encrypts_metadata());
+                pdf_enc_set_label(12);
+                pdf_enc_set_generation(1);
+                let cipher = encrypt(&content);
+                assert_ne!(cipher, content);
+                assert_eq!(dec.decrypt(true, 12, 1, &cipher), content);
+                assert_eq!(dec.decrypt(false, 12, 1, &cipher), content);
+                assert_ne!(dec.decrypt(true, 13, 1, &cipher), content);
+                pdf_release_obj(dict);
+
+                /* A user password is not known */
+                pdf_enc_set_passwd(bits, 0x3c, owner.as_ptr(), user.as_ptr());
+                let p = sec_data;
+                let dict = encryption_dict(&p);
+                assert!(PdfDecrypter::new(&*dict, &p.ID).is_none());
+                pdf_release_obj(dict);
+            }
+            sec_data.setting.use_aes = 1;
+        }
+    }
+}
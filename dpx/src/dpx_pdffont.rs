@@ -31,7 +31,10 @@ use crate::DisplayExt;
 use std::ffi::CStr;
 
 use super::dpx_agl::{agl_close_map, agl_init_map, agl_set_verbose};
-use super::dpx_cid::CIDFont_set_verbose;
+use super::dpx_cid::{
+    CIDFont_get_embedding, CIDFont_get_fontname, CIDFont_get_ident, CIDFont_get_subtype,
+    CIDFont_set_verbose,
+};
 use super::dpx_cidtype0::t1_load_UnicodeCMap;
 use super::dpx_cmap::{
     CMap_cache_close, CMap_cache_find, CMap_cache_get, CMap_cache_init, CMap_get_name,
@@ -94,6 +97,38 @@ pub struct C2RustUnnamed_0 {
     pub fonts: *mut pdf_font,
 }
 
+/// The kind of font program behind a font resource of the PDF output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FontReportType {
+    Type1,
+    /// A CFF font program: a Type1C font, or a CIDFontType0 descendant.
+    Cff,
+    /// A TrueType font program, simple or as a CIDFontType2 descendant.
+    TrueType,
+    /// A Type3 font built from PK bitmaps.
+    Type3,
+}
+
+/// One font resource of the PDF output, as collected by `pdf_close_fonts`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FontReportEntry {
+    /// The font file, as it was opened.
+    pub file: String,
+    /// The PostScript name of the font, without any subset tag.
+    pub font_name: String,
+    pub font_type: FontReportType,
+    /// Whether this is a composite (Type0) font with a CID-keyed descendant.
+    pub cid_keyed: bool,
+    pub embedded: bool,
+    /// The six-letter tag that marks an embedded subset.
+    pub subset_tag: Option<String>,
+    /// The number of distinct glyphs (or character codes, for simple fonts)
+    /// that the document uses.
+    pub glyph_count: usize,
+    /// The encoding or CMap name; `None` for the font's built-in encoding.
+    pub encoding: Option<String>,
+}
+
 /* tectonic/core-strutils.h: miscellaneous C string utilities
    Copyright 2016-2018 the Tectonic Project
    Licensed under the MIT License.
@@ -293,14 +328,97 @@ unsafe fn pdf_clean_font_struct(mut font: *mut pdf_font) {
         (*font).usedchars = 0 as *mut i8
     };
 }
+unsafe fn report_string(s: *const i8) -> String {
+    if s.is_null() {
+        String::new()
+    } else {
+        CStr::from_ptr(s).to_string_lossy().into_owned()
+    }
+}
+unsafe fn pdf_font_report_entry(mut font: *mut pdf_font) -> FontReportEntry {
+    if (*font).subtype == 4i32 {
+        let t0font = Type0Font_cache_get((*font).font_id);
+        let cidfont = (*t0font).descendant;
+        let used_chars = Type0Font_get_usedchars(t0font);
+        let glyph_count = if used_chars.is_null() {
+            0
+        } else {
+            std::slice::from_raw_parts(used_chars as *const u8, 8192)
+                .iter()
+                .map(|b| b.count_ones() as usize)
+                .sum()
+        };
+        let embedded = CIDFont_get_embedding(cidfont) != 0;
+        let mut font_name = report_string(CIDFont_get_fontname(cidfont));
+        let mut subset_tag = None;
+        /* Embedded CID-keyed fonts got their "XXXXXX+" prefix when opened. */
+        if embedded && font_name.len() > 7 && font_name.as_bytes()[6] == b'+' {
+            subset_tag = Some(font_name[..6].to_owned());
+            font_name = font_name[7..].to_owned();
+        }
+        return FontReportEntry {
+            file: report_string(CIDFont_get_ident(cidfont)),
+            font_name,
+            font_type: if CIDFont_get_subtype(cidfont) == 2i32 {
+                FontReportType::TrueType
+            } else {
+                FontReportType::Cff
+            },
+            cid_keyed: true,
+            embedded,
+            subset_tag,
+            glyph_count,
+            encoding: Some(report_string((*t0font).encoding)),
+        };
+    }
+    let glyph_count = if (*font).usedchars.is_null() {
+        0
+    } else {
+        std::slice::from_raw_parts((*font).usedchars as *const u8, 256)
+            .iter()
+            .filter(|&&c| c != 0)
+            .count()
+    };
+    let embedded = pdf_font_get_flag(font, 1i32 << 0i32) == 0;
+    let subset_tag = if embedded && (*font).subtype != 2i32 && (*font).uniqueID[0] != 0 {
+        Some(report_string((*font).uniqueID.as_ptr()))
+    } else {
+        None
+    };
+    FontReportEntry {
+        file: report_string((*font).ident),
+        font_name: report_string((*font).fontname),
+        font_type: match (*font).subtype {
+            0 => FontReportType::Type1,
+            1 => FontReportType::Cff,
+            3 => FontReportType::TrueType,
+            _ => FontReportType::Type3,
+        },
+        cid_keyed: false,
+        embedded,
+        subset_tag,
+        glyph_count,
+        encoding: if (*font).encoding_id >= 0i32 {
+            Some(report_string(pdf_encoding_get_name((*font).encoding_id)))
+        } else {
+            None
+        },
+    }
+}
+/// Hand over the font resources written out by the most recent run.
+pub unsafe fn pdf_font_take_report() -> Vec<FontReportEntry> {
+    font_report.drain(..).collect()
+}
 static mut font_cache: C2RustUnnamed_0 = C2RustUnnamed_0 {
         count: 0i32,
         capacity: 0i32,
         fonts: 0 as *const pdf_font as *mut pdf_font,
     };
+static mut font_report: Vec<FontReportEntry> = Vec::new();
 #[no_mangle]
 pub unsafe extern "C" fn pdf_init_fonts() {
     assert!(font_cache.fonts.is_null());
+    font_report.clear();
     agl_init_map();
     CMap_cache_init();
     pdf_init_encodings();
@@ -543,6 +661,8 @@ pub unsafe extern "C" fn pdf_close_fonts() {
             ); /* After encoding */
         }
         pdf_flush_font(font_0);
+        /* After flushing, so that the subset tag has been chosen. */
+        font_report.push(pdf_font_report_entry(font_0));
         pdf_clean_font_struct(font_0);
         font_id += 1
     }
@@ -31,7 +31,9 @@ use crate::DisplayExt;
 use std::ffi::CString;
 use std::io::{Read, Seek, SeekFrom, Write};
 
-use crate::dpx_pdfparse::{parse_number, parse_pdf_object, parse_unsigned, SkipWhite, ParsePdfObj};
+use crate::dpx_pdfparse::{
+    is_space, istokensep, parse_number, parse_pdf_object, parse_unsigned, ParsePdfObj, SkipWhite,
+};
 use crate::mfree;
 use crate::strstartswith;
 use crate::{info, warn};
@@ -39,10 +41,15 @@ use std::ffi::CStr;
 use std::ptr;
 
 use super::dpx_dpxutil::{ht_append_table, ht_clear_table, ht_init_table, ht_lookup_table};
+use super::dpx_linearize::{Captured, HintEncrypter, Linearizer, ObjRef};
 use super::dpx_mem::{new, renew};
 use super::dpx_mfileio::{tt_mfgets, work_buffer, work_buffer_u8 as WORK_BUFFER};
+use super::dpx_pdfa::pdfa_check_object;
 use super::dpx_pdfdev::pdf_sprint_number;
-use super::dpx_pdfencrypt::{pdf_enc_set_generation, pdf_enc_set_label, pdf_encrypt_data};
+use super::dpx_pdfencrypt::{
+    pdf_enc_key_depends_on_label, pdf_enc_set_generation, pdf_enc_set_label, pdf_encrypt_data,
+    PdfDecrypter,
+};
 use super::dpx_pdfparse::skip_white;
 use crate::shims::{sprintf, sscanf};
 use crate::{
@@ -176,6 +183,7 @@ pub struct pdf_file {
     pub num_obj: i32,
     pub file_size: i32,
     pub version: u32,
+    pub decrypter: Option<PdfDecrypter>,
     /* External interface to pdf routines */
     /* Name does not include the / */
     /* pdf_add_dict requires key but pdf_add_array does not.
@@ -303,6 +311,13 @@ static mut xref_stream: *mut pdf_obj = 0 as *const pdf_obj as *mut pdf_obj;
 static mut verbose: i32 = 0i32;
 static mut compression_level: i8 = 9_i8;
 static mut compression_use_predictor: i8 = 1_i8;
+/* Objects labeled while this is set, linked so that they stay in memory */
+static mut label_watch: Option<Vec<*mut pdf_obj>> = None;
+static mut linearize: bool = false;
+/* Collects the objects instead of writing them while linearizing */
+static mut linearizer: Option<Linearizer> = None;
+/* Output to the PDF file goes here while it is set */
+static mut captured: Option<Captured> = None;
 #[no_mangle]
 pub unsafe extern "C" fn pdf_set_compression(mut level: i32) {
     if cfg!(not(feature = "libz-sys")) {
@@ -323,6 +338,36 @@ pub unsafe extern "C" fn pdf_set_compression(mut level: i32) {
 pub unsafe extern "C" fn pdf_set_use_predictor(mut bval: i32) {
     compression_use_predictor = (if bval != 0 { 1i32 } else { 0i32 }) as i8;
 }
+/// The compression level and whether predictors are used.
+pub unsafe fn pdf_get_compression() -> (i32, bool) {
+    (compression_level as i32, compression_use_predictor != 0)
+}
+/// Starts keeping every object that gets labeled (i.e. referenced) from
+/// now on in memory, instead of writing it out once it is released.
+pub unsafe fn pdf_watch_labels() {
+    label_watch = Some(Vec::new());
+}
+/// Stops watching labels and hands out the objects labeled since
+/// `pdf_watch_labels()`, each holding a link that is the caller's to release.
+pub unsafe fn pdf_unwatch_labels() -> Vec<*mut pdf_obj> {
+    label_watch.take().unwrap_or_default()
+}
+/// Makes the next output file linearized ("fast web view").
+pub unsafe fn pdf_set_linearize(flag: bool) {
+    linearize = flag;
+}
+/// Tells the linearizer that `page` is the next page of the document.
+pub unsafe fn pdf_out_mark_page(page: &pdf_obj) {
+    if let Some(lin) = linearizer.as_mut() {
+        lin.add_page(page.label);
+    }
+}
+/// Tells the linearizer that `node` is an intermediate node of the page tree.
+pub unsafe fn pdf_out_mark_page_node(node: &pdf_obj) {
+    if let Some(lin) = linearizer.as_mut() {
+        lin.add_page_node(node.label);
+    }
+}
 static mut pdf_version: u32 = 5_u32;
 #[no_mangle]
 pub unsafe extern "C" fn pdf_set_version(mut version: u32) {
@@ -368,8 +413,18 @@ pub unsafe extern "C" fn pdf_out_init(
     pdf_max_ind_objects = 0;
     add_xref_entry(0, 0_u8, 0_u32, 0xffff_u16);
     next_label = 1;
+    linearizer = None;
+    captured = None;
+    if linearize {
+        /* Linearizing renumbers the objects after they have been encrypted. */
+        if do_encryption && pdf_enc_key_depends_on_label() {
+            warn!("Linearized output requires AES-256 encryption; it will not be linearized.");
+        } else {
+            linearizer = Some(Linearizer::new());
+        }
+    }
     if pdf_version >= 5_u32 {
-        if enable_object_stream {
+        if enable_object_stream && linearizer.is_none() {
             xref_stream = pdf_new_stream(STREAM_COMPRESS);
             (*xref_stream).flags |= OBJ_NO_ENCRYPT;
             trailer_dict = (*xref_stream).as_stream_mut().get_dict_mut();
@@ -500,41 +555,94 @@ unsafe fn dump_xref_stream() {
     }
     pdf_release_obj(xref_stream);
 }
+unsafe fn dump_xref(handle: &mut OutputHandleWrapper) {
+    /* Flush current object stream */
+    if !current_objstm.is_null() {
+        release_objstm(current_objstm);
+        current_objstm = 0 as *mut pdf_obj
+    }
+    /*
+     * Label xref stream - we need the number of correct objects
+     * for the xref stream dictionary (= trailer).
+     * Labelling it in pdf_out_init (with 1)  does not work (why?).
+     */
+    if !xref_stream.is_null() {
+        pdf_label_obj(xref_stream);
+    }
+    /* Record where this xref is for trailer */
+    startxref = pdf_output_file_position as u32;
+    pdf_add_dict(&mut *trailer_dict, "Size", pdf_new_number(next_label as f64));
+    if !xref_stream.is_null() {
+        dump_xref_stream();
+    } else {
+        dump_xref_table();
+        dump_trailer_dict();
+    }
+    /* Done with xref table */
+    output_xref = vec![];
+    pdf_out(handle, b"startxref\n");
+    let length = sprintf(
+        format_buffer.as_mut_ptr() as *mut i8,
+        b"%u\n\x00" as *const u8 as *const i8,
+        startxref,
+    ) as usize;
+    pdf_out(handle, &format_buffer[..length]);
+    pdf_out(handle, b"%%EOF\n");
+}
+/* Writes out the objects collected for a linearized file, reordered and
+ * with the cross-reference tables and the hint stream in their places. */
+unsafe fn dump_linearized(lin: Linearizer, handle: &mut OutputHandleWrapper) {
+    let label_of = |key: &str| {
+        (*trailer_dict)
+            .as_dict()
+            .get(key)
+            .filter(|obj| obj.is_indirect())
+            .map(|obj| (*(obj.data as *mut pdf_indirect)).label)
+    };
+    let root = label_of("Root").unwrap_or(0);
+    let encrypt_dict = label_of("Encrypt");
+    enc_mode = false;
+    captured = Some(Captured::default());
+    write_dict((*trailer_dict).data as *mut pdf_dict, handle);
+    pdf_release_obj(trailer_dict);
+    let trailer = captured.take().unwrap();
+    let mut encrypt_hints = |label: u32, data: &[u8]| {
+        pdf_enc_set_label(label);
+        pdf_enc_set_generation(0);
+        let mut cipher: *mut u8 = ptr::null_mut();
+        let mut cipher_len: size_t = 0;
+        pdf_encrypt_data(
+            data.as_ptr(),
+            data.len() as size_t,
+            &mut cipher,
+            &mut cipher_len,
+        );
+        let encrypted = std::slice::from_raw_parts(cipher, cipher_len as usize).to_vec();
+        free(cipher as *mut libc::c_void);
+        encrypted
+    };
+    let data = lin.finish(
+        pdf_output_file_position,
+        &trailer,
+        root,
+        encrypt_dict,
+        if doc_enc_mode {
+            Some(&mut encrypt_hints as HintEncrypter)
+        } else {
+            None
+        },
+    );
+    output_xref = vec![];
+    pdf_out(handle, &data);
+}
 #[no_mangle]
 pub unsafe extern "C" fn pdf_out_flush() {
     if let Some(handle) = pdf_output_handle.as_mut() {
-        /* Flush current object stream */
-        if !current_objstm.is_null() {
-            release_objstm(current_objstm);
-            current_objstm = 0 as *mut pdf_obj
-        }
-        /*
-         * Label xref stream - we need the number of correct objects
-         * for the xref stream dictionary (= trailer).
-         * Labelling it in pdf_out_init (with 1)  does not work (why?).
-         */
-        if !xref_stream.is_null() {
-            pdf_label_obj(xref_stream);
-        }
-        /* Record where this xref is for trailer */
-        startxref = pdf_output_file_position as u32;
-        pdf_add_dict(&mut *trailer_dict, "Size", pdf_new_number(next_label as f64));
-        if !xref_stream.is_null() {
-            dump_xref_stream();
+        if let Some(lin) = linearizer.take() {
+            dump_linearized(lin, handle);
         } else {
-            dump_xref_table();
-            dump_trailer_dict();
+            dump_xref(handle);
         }
-        /* Done with xref table */
-        output_xref = vec![];
-        pdf_out(handle, b"startxref\n");
-        let length = sprintf(
-            format_buffer.as_mut_ptr() as *mut i8,
-            b"%u\n\x00" as *const u8 as *const i8,
-            startxref,
-        ) as usize;
-        pdf_out(handle, &format_buffer[..length]);
-        pdf_out(handle, b"%%EOF\n");
         if verbose != 0 {
             if compression_level as i32 > 0i32 {
                 info!(
@@ -601,6 +709,13 @@ unsafe fn pdf_out_char(handle: &mut OutputHandleWrapper, mut c: u8) {
             &mut c as *mut u8 as *mut i8 as *const libc::c_void,
             1i32,
         );
+    } else if capturing(handle) {
+        captured.as_mut().unwrap().data.push(c);
+        if c == b'\n' {
+            pdf_output_line_position = 0
+        } else {
+            pdf_output_line_position += 1
+        }
     } else {
         ttstub_output_putc(handle, c as i32);
         /* Keep tallys for xref table *only* if writing a pdf file. */
@@ -614,6 +729,10 @@ unsafe fn pdf_out_char(handle: &mut OutputHandleWrapper, mut c: u8) {
         }
     };
 }
+/* Whether output to `handle` goes to the linearizer */
+unsafe fn capturing(handle: &mut OutputHandleWrapper) -> bool {
+    captured.is_some() && handle == pdf_output_handle.as_mut().unwrap()
+}
 const xchar: &[u8; 17] = b"0123456789abcdef\x00";
 
 unsafe fn pdf_out(handle: &mut OutputHandleWrapper, buffer: &[u8]) {
@@ -624,6 +743,12 @@ unsafe fn pdf_out(handle: &mut OutputHandleWrapper, buffer: &[u8]) {
             buffer.as_ptr() as *const libc::c_void,
             length as i32,
         );
+    } else if capturing(handle) {
+        captured.as_mut().unwrap().data.extend_from_slice(buffer);
+        pdf_output_line_position += length;
+        if length > 0 && buffer[length - 1] == b'\n' {
+            pdf_output_line_position = 0
+        }
     } else {
         handle.write(buffer).unwrap();
         /* Keep tallys for xref table *only* if writing a pdf file */
@@ -738,6 +863,14 @@ unsafe fn write_indirect(mut indirect: *mut pdf_indirect, handle: &mut OutputHan
         (*indirect).label,
         (*indirect).generation as i32,
     ) as usize;
+    if capturing(handle) {
+        let c = captured.as_mut().unwrap();
+        c.refs.push(ObjRef {
+            pos: c.data.len(),
+            len: length,
+            label: (*indirect).label,
+        });
+    }
     pdf_out(handle, &format_buffer[..length]);
 }
 /* The undefined object is used as a placeholder in pdfnames.c
@@ -1778,6 +1911,163 @@ unsafe fn filter_create_predictor_dict(
     pdf_add_dict(&mut *parms, "Predictor", pdf_new_number(predictor as f64));
     return parms;
 }
+/* Applies the filters requested for the stream to data, which is consumed,
+ * and adds /Filter and /DecodeParms entries for them to its dictionary. */
+#[cfg(feature = "libz-sys")]
+unsafe fn filter_stream_data(
+    stream: *mut pdf_stream,
+    mut filtered: *mut u8,
+    mut filtered_length: u32,
+) -> (*mut u8, u32) {
+    if (*stream).stream.len() > 0
+        && (*stream)._flags & STREAM_COMPRESS != 0
+        && compression_level as libc::c_int > 0i32
+    {
+        /* First apply predictor filter if requested. */
+        if compression_use_predictor as libc::c_int != 0
+            && (*stream)._flags & STREAM_USE_PREDICTOR != 0
+            && !(*(*stream).dict).as_dict().has("DecodeParms")
+        {
+            let mut bits_per_pixel: libc::c_int =
+                (*stream).decodeparms.colors * (*stream).decodeparms.bits_per_component;
+            let mut len: i32 = ((*stream).decodeparms.columns * bits_per_pixel + 7i32) / 8i32;
+            let mut rows: i32 =
+                ((*stream).stream.len() as i32) / len;
+            let mut filtered2: *mut libc::c_uchar = 0 as *mut libc::c_uchar;
+            let mut length2: i32 = (*stream).stream.len() as i32;
+            let parms = filter_create_predictor_dict(
+                (*stream).decodeparms.predictor,
+                (*stream).decodeparms.columns,
+                (*stream).decodeparms.bits_per_component,
+                (*stream).decodeparms.colors,
+            );
+            match (*stream).decodeparms.predictor {
+                2 => {
+                    /* TIFF2 */
+                    filtered2 = filter_TIFF2_apply_filter(
+                        filtered,
+                        (*stream).decodeparms.columns,
+                        rows,
+                        (*stream).decodeparms.bits_per_component as i8,
+                        (*stream).decodeparms.colors as i8,
+                        &mut length2,
+                    )
+                }
+                15 => {
+                    /* PNG optimun */
+                    filtered2 = filter_PNG15_apply_filter(
+                        filtered,
+                        (*stream).decodeparms.columns,
+                        rows,
+                        (*stream).decodeparms.bits_per_component as i8,
+                        (*stream).decodeparms.colors as i8,
+                        &mut length2,
+                    )
+                }
+                _ => {
+                    warn!(
+                        "Unknown/unsupported Predictor function {}.",
+                        (*stream).decodeparms.predictor
+                    );
+                }
+            }
+            if !parms.is_null() && !filtered2.is_null() {
+                free(filtered as *mut libc::c_void);
+                filtered = filtered2;
+                filtered_length = length2 as libc::c_uint;
+                pdf_add_dict(&mut *(*stream).dict, "DecodeParms", parms);
+            }
+        }
+        let filters = (*(*stream).dict).as_dict_mut().get_mut("Filter");
+        let mut buffer_length: libz::uLong;
+        buffer_length = filtered_length
+            .wrapping_add(filtered_length.wrapping_div(1000i32 as libc::c_uint))
+            .wrapping_add(14i32 as libc::c_uint) as libz::uLong;
+        let buffer = new((buffer_length as u32 as u64)
+            .wrapping_mul(::std::mem::size_of::<libc::c_uchar>() as u64)
+            as u32) as *mut libc::c_uchar;
+        let mut filter_name: *mut pdf_obj = pdf_new_name("FlateDecode");
+        let has_filters = filters.is_some();
+        if let Some(filters) = filters {
+            /*
+             * FlateDecode is the first filter to be applied to the stream.
+             */
+            pdf_unshift_array(filters, filter_name);
+        } else {
+            /*
+             * Adding the filter as a name instead of a one-element array
+             * is crucial because otherwise Adobe Reader cannot read the
+             * cross-reference stream any more, cf. the PDF v1.5 Errata.
+             */
+            pdf_add_dict(&mut *(*stream).dict, "Filter", filter_name);
+        }
+
+        #[cfg(not(feature = "legacy-libz"))]
+        {
+            if libz::compress2(
+                buffer,
+                &mut buffer_length,
+                filtered,
+                filtered_length as libz::uLong,
+                compression_level as libc::c_int,
+            ) != 0
+            {
+                panic!("Zlib error");
+            }
+        }
+        #[cfg(feature = "legacy-libz")]
+        {
+            if libz::compress(
+                buffer,
+                &mut buffer_length,
+                filtered,
+                filtered_length as libz::uLong,
+            ) != 0
+            {
+                panic!("Zlib error");
+            }
+        }
+        free(filtered as *mut libc::c_void);
+        compression_saved = (compression_saved as u64).wrapping_add(
+            (filtered_length as u64)
+                .wrapping_sub(buffer_length as u64)
+                .wrapping_sub(if has_filters {
+                    strlen(b"/FlateDecode \x00" as *const u8 as *const i8)
+                } else {
+                    strlen(b"/Filter/FlateDecode\n\x00" as *const u8 as *const i8)
+                } as u64),
+        ) as libc::c_int as libc::c_int;
+        filtered = buffer;
+        filtered_length = buffer_length as libc::c_uint
+    }
+    (filtered, filtered_length)
+}
+/// Compresses the data of a stream right away, the way it would be when
+/// written out, so that the result can be kept around.
+pub unsafe fn pdf_stream_precompress(stream: &mut pdf_obj) {
+    let data = stream.as_stream_mut();
+    if data.stream.is_empty()
+        || data._flags & STREAM_COMPRESS == 0
+        || (*data.dict).as_dict().get("Type")
+            .filter(|typ| "Metadata" == pdf_name_value(&**typ).to_string_lossy())
+            .is_some()
+    {
+        return;
+    }
+    #[cfg(feature = "libz-sys")]
+    {
+        let filtered = new(data.stream.len() as u32) as *mut u8;
+        libc::memcpy(
+            filtered as *mut libc::c_void,
+            data.stream.as_ptr() as *const libc::c_void,
+            data.stream.len(),
+        );
+        let (filtered, length) = filter_stream_data(data, filtered, data.stream.len() as u32);
+        data.stream = std::slice::from_raw_parts(filtered, length as usize).to_vec();
+        free(filtered as *mut libc::c_void);
+        data._flags &= !(STREAM_COMPRESS | STREAM_USE_PREDICTOR);
+    }
+}
 unsafe fn write_stream(mut stream: *mut pdf_stream, handle: &mut OutputHandleWrapper) {
     /*
      * Always work from a copy of the stream. All filters read from
@@ -1800,127 +2090,9 @@ unsafe fn write_stream(mut stream: *mut pdf_stream, handle: &mut OutputHandleWra
     /* Apply compression filter if requested */
     #[cfg(feature = "libz-sys")]
     {
-        if (*stream).stream.len() > 0
-            && (*stream)._flags & STREAM_COMPRESS != 0
-            && compression_level as libc::c_int > 0i32
-        {
-            /* First apply predictor filter if requested. */
-            if compression_use_predictor as libc::c_int != 0
-                && (*stream)._flags & STREAM_USE_PREDICTOR != 0
-                && !(*(*stream).dict).as_dict().has("DecodeParms")
-            {
-                let mut bits_per_pixel: libc::c_int =
-                    (*stream).decodeparms.colors * (*stream).decodeparms.bits_per_component;
-                let mut len: i32 = ((*stream).decodeparms.columns * bits_per_pixel + 7i32) / 8i32;
-                let mut rows: i32 =
-                    ((*stream).stream.len() as i32) / len;
-                let mut filtered2: *mut libc::c_uchar = 0 as *mut libc::c_uchar;
-                let mut length2: i32 = (*stream).stream.len() as i32;
-                let parms = filter_create_predictor_dict(
-                    (*stream).decodeparms.predictor,
-                    (*stream).decodeparms.columns,
-                    (*stream).decodeparms.bits_per_component,
-                    (*stream).decodeparms.colors,
-                );
-                match (*stream).decodeparms.predictor {
-                    2 => {
-                        /* TIFF2 */
-                        filtered2 = filter_TIFF2_apply_filter(
-                            filtered,
-                            (*stream).decodeparms.columns,
-                            rows,
-                            (*stream).decodeparms.bits_per_component as i8,
-                            (*stream).decodeparms.colors as i8,
-                            &mut length2,
-                        )
-                    }
-                    15 => {
-                        /* PNG optimun */
-                        filtered2 = filter_PNG15_apply_filter(
-                            filtered,
-                            (*stream).decodeparms.columns,
-                            rows,
-                            (*stream).decodeparms.bits_per_component as i8,
-                            (*stream).decodeparms.colors as i8,
-                            &mut length2,
-                        )
-                    }
-                    _ => {
-                        warn!(
-                            "Unknown/unsupported Predictor function {}.",
-                            (*stream).decodeparms.predictor
-                        );
-                    }
-                }
-                if !parms.is_null() && !filtered2.is_null() {
-                    free(filtered as *mut libc::c_void);
-                    filtered = filtered2;
-                    filtered_length = length2 as libc::c_uint;
-                    pdf_add_dict(&mut *(*stream).dict, "DecodeParms", parms);
-                }
-            }
-            let filters = (*(*stream).dict).as_dict_mut().get_mut("Filter");
-            let mut buffer_length: libz::uLong;
-            buffer_length = filtered_length
-                .wrapping_add(filtered_length.wrapping_div(1000i32 as libc::c_uint))
-                .wrapping_add(14i32 as libc::c_uint) as libz::uLong;
-            let buffer = new((buffer_length as u32 as u64)
-                .wrapping_mul(::std::mem::size_of::<libc::c_uchar>() as u64)
-                as u32) as *mut libc::c_uchar;
-            let mut filter_name: *mut pdf_obj = pdf_new_name("FlateDecode");
-            let has_filters = filters.is_some();
-            if let Some(filters) = filters {
-                /*
-                 * FlateDecode is the first filter to be applied to the stream.
-                 */
-                pdf_unshift_array(filters, filter_name);
-            } else {
-                /*
-                 * Adding the filter as a name instead of a one-element array
-                 * is crucial because otherwise Adobe Reader cannot read the
-                 * cross-reference stream any more, cf. the PDF v1.5 Errata.
-                 */
-                pdf_add_dict(&mut *(*stream).dict, "Filter", filter_name);
-            }
-
-            #[cfg(not(feature = "legacy-libz"))]
-            {
-                if libz::compress2(
-                    buffer,
-                    &mut buffer_length,
-                    filtered,
-                    filtered_length as libz::uLong,
-                    compression_level as libc::c_int,
-                ) != 0
-                {
-                    panic!("Zlib error");
-                }
-            }
-            #[cfg(feature = "legacy-libz")]
-            {
-                if libz::compress(
-                    buffer,
-                    &mut buffer_length,
-                    filtered,
-                    filtered_length as libz::uLong,
-                ) != 0
-                {
-                    panic!("Zlib error");
-                }
-            }
-            free(filtered as *mut libc::c_void);
-            compression_saved = (compression_saved as u64).wrapping_add(
-                (filtered_length as u64)
-                    .wrapping_sub(buffer_length as u64)
-                    .wrapping_sub(if has_filters {
-                        strlen(b"/FlateDecode \x00" as *const u8 as *const i8)
-                    } else {
-                        strlen(b"/Filter/FlateDecode\n\x00" as *const u8 as *const i8)
-                    } as u64),
-            ) as libc::c_int as libc::c_int;
-            filtered = buffer;
-            filtered_length = buffer_length as libc::c_uint
-        }
+        let (data, length) = filter_stream_data(stream, filtered, filtered_length);
+        filtered = data;
+        filtered_length = length;
     }
     /* HAVE_ZLIB */
     /* AES will change the size of data! */
@@ -2084,6 +2256,25 @@ pub unsafe extern "C" fn pdf_add_stream_flate(
     };
 }
 
+/// Inflates zlib-compressed data into a new buffer.
+#[cfg(feature = "libz-sys")]
+pub unsafe fn pdf_inflate(data: &[u8]) -> Option<Vec<u8>> {
+    let tmp = pdf_new_stream(0i32);
+    let error = pdf_add_stream_flate(tmp, data.as_ptr() as *const libc::c_void, data.len() as _);
+    let len = pdf_stream_length(&*tmp) as usize;
+    let out = if len > 0 {
+        std::slice::from_raw_parts(pdf_stream_dataptr(&*tmp) as *const u8, len).to_vec()
+    } else {
+        Vec::new()
+    };
+    pdf_release_obj(tmp);
+    if error != 0 {
+        None
+    } else {
+        Some(out)
+    }
+}
+
 #[cfg(feature = "libz-sys")]
 unsafe fn get_decode_parms(parms: &mut decode_parms, dict: &mut pdf_obj) -> libc::c_int {
     assert!(dict.is_dict());
@@ -2618,6 +2809,16 @@ unsafe fn pdf_write_obj(mut object: *mut pdf_obj, handle: &mut OutputHandleWrapp
 }
 /* Write the object to the file */
 unsafe fn pdf_flush_obj(mut object: *mut pdf_obj, handle: &mut OutputHandleWrapper) {
+    if linearizer.is_some() {
+        enc_mode = doc_enc_mode && (*object).flags & OBJ_NO_ENCRYPT == 0;
+        pdf_enc_set_label((*object).label);
+        pdf_enc_set_generation((*object).generation as u32);
+        captured = Some(Captured::default());
+        pdf_write_obj(object, handle);
+        let lin = linearizer.as_mut().unwrap();
+        lin.add_object((*object).label, captured.take().unwrap());
+        return;
+    }
     /*
      * Record file position
      */
@@ -2723,6 +2924,7 @@ pub unsafe extern "C" fn pdf_release_obj(mut object: *mut pdf_obj) {
          * Nonzero "label" means object needs to be written before it's destroyed.
          */
         if (*object).label != 0 && pdf_output_handle.is_some() {
+            pdfa_check_object(&*object);
             if do_objstm == 0
                 || (*object).flags & OBJ_NO_OBJSTM != 0
                 || doc_enc_mode as i32 != 0 && (*object).flags & OBJ_NO_ENCRYPT != 0
@@ -3021,6 +3223,47 @@ unsafe fn pdf_read_object(
     free(buffer as *mut libc::c_void);
     result
 }
+/* Objects of an encrypted file have their strings and stream data decrypted
+ * as they are read. Objects inside object streams are not encrypted on their
+ * own. */
+unsafe fn decrypt_object(
+    decrypter: &PdfDecrypter,
+    obj: *mut pdf_obj,
+    label: u32,
+    generation: u16,
+) {
+    if (*obj).is_string() {
+        let data = std::slice::from_raw_parts(
+            pdf_string_value(&*obj) as *const u8,
+            pdf_string_length(&*obj) as usize,
+        );
+        let plain = decrypter.decrypt(false, label, generation, data);
+        pdf_set_string(&mut *obj, plain.as_ptr() as *mut u8, plain.len() as size_t);
+    } else if (*obj).is_array() {
+        for &value in &(*obj).as_array().values {
+            decrypt_object(decrypter, value, label, generation);
+        }
+    } else if (*obj).is_dict() {
+        let mut data = (*obj).data as *mut pdf_dict;
+        while !(*data).key.is_null() {
+            decrypt_object(decrypter, (*data).value, label, generation);
+            data = (*data).next
+        }
+    } else if (*obj).is_stream() {
+        let stream = (*obj).as_stream_mut();
+        let typ = (*stream.dict)
+            .as_dict()
+            .get("Type")
+            .filter(|typ| typ.is_name())
+            .map_or(&b""[..], |typ| pdf_name_value(typ).to_bytes());
+        /* Cross-reference streams are never encrypted, and the metadata
+         * only with /EncryptMetadata true */
+        if typ != b"XRef" && (typ != b"Metadata" || decrypter.encrypts_metadata()) {
+            stream.stream = decrypter.decrypt(true, label, generation, &stream.stream);
+        }
+        decrypt_object(decrypter, stream.dict, label, generation);
+    }
+}
 unsafe fn read_objstm(mut pf: *mut pdf_file, mut num: u32) -> *mut pdf_obj {
     let mut current_block: u64;
     let mut offset: u32 = (*(*pf).xref_table.offset(num as isize)).field2;
@@ -3029,6 +3272,9 @@ unsafe fn read_objstm(mut pf: *mut pdf_file, mut num: u32) -> *mut pdf_obj {
     let mut data: *mut i8 = 0 as *mut i8;
     let mut q: *mut i8 = 0 as *mut i8;
     let mut objstm = pdf_read_object(num, gen, pf, offset as i32, limit);
+    if let (false, Some(decrypter)) = (objstm.is_null(), (*pf).decrypter.as_ref()) {
+        decrypt_object(decrypter, objstm, num, gen);
+    }
     if !objstm.is_null() && (*objstm).is_stream() {
         let mut tmp: *mut pdf_obj = pdf_stream_uncompress(&mut *objstm);
         if !tmp.is_null() {
@@ -3142,7 +3388,11 @@ unsafe fn pdf_get_object(
         /* type == 1 */
         let offset = (*(*pf).xref_table.offset(obj_num as isize)).field2;
         let limit = next_object_offset(pf, obj_num);
-        result = Some(pdf_read_object(obj_num, obj_gen, pf, offset as i32, limit))
+        let obj = pdf_read_object(obj_num, obj_gen, pf, offset as i32, limit);
+        if let (false, Some(decrypter)) = (obj.is_null(), (*pf).decrypter.as_ref()) {
+            decrypt_object(decrypter, obj, obj_num, obj_gen);
+        }
+        result = Some(obj)
     } else {
         /* type == 2 */
         let mut objstm_num: u32 = (*(*pf).xref_table.offset(obj_num as isize)).field2;
@@ -3192,6 +3442,9 @@ unsafe fn pdf_get_object(
 unsafe fn pdf_new_ref(mut object: *mut pdf_obj) -> *mut pdf_obj {
     if (*object).label == 0 {
         pdf_label_obj(object);
+        if let Some(watched) = label_watch.as_mut() {
+            watched.push(pdf_link_obj(object));
+        }
     }
     let result = pdf_new_indirect(0 as *mut pdf_file, (*object).label, (*object).generation);
     let ref mut fresh28 = (*((*result).data as *mut pdf_indirect)).obj;
@@ -3724,20 +3977,29 @@ unsafe fn pdf_file_new(mut handle: InputHandleWrapper) -> *mut pdf_file {
     (*pf).num_obj = 0i32;
     (*pf).version = 0_u32;
     (*pf).file_size = file_size;
+    (*pf).decrypter = None;
     pf
 }
-unsafe extern "C" fn pdf_file_free(mut pf: *mut pdf_file) {
-    if pf.is_null() {
-        return;
-    }
-    //tectonic_bridge::ttstub_input_close((*pf).handle.clone()); // TODO: use drop
+/* Forgets everything read from the file. */
+unsafe fn pdf_file_reset(mut pf: *mut pdf_file) {
     for i in 0..(*pf).num_obj {
         pdf_release_obj((*(*pf).xref_table.offset(i as isize)).direct);
         pdf_release_obj((*(*pf).xref_table.offset(i as isize)).indirect);
     }
-    free((*pf).xref_table as *mut libc::c_void);
+    (*pf).xref_table = mfree((*pf).xref_table as *mut libc::c_void) as *mut xref_entry;
+    (*pf).num_obj = 0;
     pdf_release_obj((*pf).trailer);
+    (*pf).trailer = 0 as *mut pdf_obj;
     pdf_release_obj((*pf).catalog);
+    (*pf).catalog = 0 as *mut pdf_obj;
+    (*pf).decrypter = None;
+}
+unsafe extern "C" fn pdf_file_free(mut pf: *mut pdf_file) {
+    if pf.is_null() {
+        return;
+    }
+    //tectonic_bridge::ttstub_input_close((*pf).handle.clone()); // TODO: use drop
+    pdf_file_reset(pf);
     free(pf as *mut libc::c_void);
 }
 #[no_mangle]
@@ -3799,38 +4061,15 @@ pub unsafe extern "C" fn pdf_open(
         }
         pf = pdf_file_new(handle);
         (*pf).version = version;
-        (*pf).trailer = read_xref(pf);
-        if (*pf).trailer.is_null() {
-            return error(pf);
+        let mut status = open_objects(pf, false);
+        if status == 0 {
+            warn!("Rebuilding the cross-reference table. Broken PDF file?");
+            pdf_file_reset(pf);
+            status = open_objects(pf, true);
         }
-        if (*(*pf).trailer).as_dict().has("Encrypt") {
-            warn!("PDF document is encrypted.");
+        if status <= 0 {
             return error(pf);
         }
-        (*pf).catalog = pdf_deref_obj((*(*pf).trailer).as_dict_mut().get_mut("Root"));
-        if !(!(*pf).catalog.is_null() && (*(*pf).catalog).is_dict()) {
-            warn!("Cannot read PDF document catalog. Broken PDF file?");
-            return error(pf);
-        }
-        let new_version = pdf_deref_obj((*(*pf).catalog).as_dict_mut().get_mut("Version"));
-        if !new_version.is_null() {
-            let mut minor: u32 = 0;
-            if !(!new_version.is_null() && (*new_version).is_name())
-                || sscanf(
-                    pdf_name_value(&*new_version).as_ptr(),
-                    b"1.%u\x00" as *const u8 as *const i8,
-                    &mut minor as *mut u32,
-                ) != 1i32
-            {
-                pdf_release_obj(new_version);
-                warn!("Illegal Version entry in document catalog. Broken PDF file?");
-                return error(pf);
-            }
-            if (*pf).version < minor {
-                (*pf).version = minor
-            }
-            pdf_release_obj(new_version);
-        }
         if !ident.is_null() {
             ht_append_table(
                 pdf_files,
@@ -3846,6 +4085,270 @@ pub unsafe extern "C" fn pdf_open(
     }
     pf
 }
+/* Reads the trailer, the encryption dictionary and the catalog. Returns 1
+ * on success, 0 if the cross-reference table should be rebuilt, and -1 if
+ * the file cannot be used at all. */
+unsafe fn open_objects(pf: *mut pdf_file, rebuild: bool) -> i32 {
+    let mut objstms = vec![];
+    if rebuild {
+        match rebuild_xref(pf) {
+            Some((trailer, found)) => {
+                (*pf).trailer = trailer;
+                objstms = found;
+            }
+            None => return -1,
+        }
+    } else {
+        (*pf).trailer = read_xref(pf);
+        if (*pf).trailer.is_null() {
+            return 0;
+        }
+    }
+    let trailer = (*(*pf).trailer).as_dict_mut();
+    if trailer.has("Encrypt") {
+        /* The encryption dictionary itself is not encrypted */
+        let encrypt = pdf_deref_obj(trailer.get_mut("Encrypt"));
+        let id = trailer
+            .get("ID")
+            .filter(|id| id.is_array())
+            .and_then(|id| id.as_array().get(0))
+            .filter(|id| id.is_string())
+            .map_or(&[][..], |id| {
+                std::slice::from_raw_parts(
+                    pdf_string_value(id) as *const u8,
+                    pdf_string_length(id) as usize,
+                )
+            });
+        if !encrypt.is_null() && (*encrypt).is_dict() {
+            (*pf).decrypter = PdfDecrypter::new(&*encrypt, id);
+        } else {
+            warn!("Broken PDF encryption dictionary.");
+        }
+        pdf_release_obj(encrypt);
+        if (*pf).decrypter.is_none() {
+            warn!("Cannot decrypt PDF document.");
+            return -1;
+        }
+    }
+    add_objstm_entries(pf, &objstms);
+    (*pf).catalog = pdf_deref_obj((*(*pf).trailer).as_dict_mut().get_mut("Root"));
+    if !(!(*pf).catalog.is_null() && (*(*pf).catalog).is_dict()) {
+        warn!("Cannot read PDF document catalog. Broken PDF file?");
+        return if rebuild { -1 } else { 0 };
+    }
+    let new_version = pdf_deref_obj((*(*pf).catalog).as_dict_mut().get_mut("Version"));
+    if !new_version.is_null() {
+        let mut minor: u32 = 0;
+        if !(!new_version.is_null() && (*new_version).is_name())
+            || sscanf(
+                pdf_name_value(&*new_version).as_ptr(),
+                b"1.%u\x00" as *const u8 as *const i8,
+                &mut minor as *mut u32,
+            ) != 1i32
+        {
+            pdf_release_obj(new_version);
+            warn!("Illegal Version entry in document catalog. Broken PDF file?");
+            return -1;
+        }
+        if (*pf).version < minor {
+            (*pf).version = minor
+        }
+        pdf_release_obj(new_version);
+    }
+    1
+}
+/* The largest object number allowed by the PDF spec */
+const MAX_OBJ_NUM: u32 = 8_388_607;
+/* Offsets of the "N G obj" headers in a file, as (N, G, offset). Stream
+ * data is skipped, so that binary data does not give false hits. */
+fn find_object_headers(data: &[u8]) -> Vec<(u32, u16, usize)> {
+    let mut headers = vec![];
+    let mut i = 0;
+    while i < data.len() {
+        let rest = &data[i..];
+        let token = i == 0 || istokensep(&data[i - 1]);
+        let eol = |c: &u8| *c == b'\r' || *c == b'\n';
+        if token && rest.starts_with(b"stream") && rest.get(6).map_or(false, eol) {
+            match rest.windows(9).position(|w| w == b"endstream") {
+                Some(end) => i += end + 9,
+                None => break,
+            }
+            continue;
+        }
+        if rest.starts_with(b"obj") && rest.get(3).map_or(true, istokensep) {
+            if let Some((num, gen, offset)) = object_header_before(&data[..i]) {
+                headers.push((num, gen, offset));
+            }
+        }
+        i += 1;
+    }
+    headers
+}
+/* Object number and generation at the end of `data`, followed by white
+ * space, and where they start. */
+fn object_header_before(data: &[u8]) -> Option<(u32, u16, usize)> {
+    let spaces = |end: usize| {
+        data[..end]
+            .iter()
+            .rposition(|c| !is_space(c))
+            .map_or(0, |i| i + 1)
+    };
+    let digits = |end: usize| {
+        data[..end]
+            .iter()
+            .rposition(|c| !c.is_ascii_digit())
+            .map_or(0, |i| i + 1)
+    };
+    let gen_end = spaces(data.len());
+    let gen_start = digits(gen_end);
+    let num_end = spaces(gen_start);
+    let num_start = digits(num_end);
+    if gen_end == data.len()
+        || gen_start == gen_end
+        || gen_end - gen_start > 5
+        || num_end == gen_start
+        || num_start == num_end
+        || num_end - num_start > 10
+        || num_start > 0 && !istokensep(&data[num_start - 1])
+    {
+        return None;
+    }
+    let number = |start: usize, end: usize| {
+        std::str::from_utf8(&data[start..end])
+            .ok()
+            .and_then(|n| n.parse::<u64>().ok())
+    };
+    let num = number(num_start, num_end)?;
+    let gen = number(gen_start, gen_end)?;
+    if num > u32::max_value() as u64 || gen > u16::max_value() as u64 {
+        return None;
+    }
+    Some((num as u32, gen as u16, num_start))
+}
+/* Rebuilds the cross-reference table of a damaged file from the objects
+ * found in it. Returns the trailer and the object streams. */
+unsafe fn rebuild_xref(pf: *mut pdf_file) -> Option<(*mut pdf_obj, Vec<u32>)> {
+    let mut data = vec![0_u8; (*pf).file_size as usize];
+    if (*pf).handle.seek(SeekFrom::Start(0)).is_err() || (*pf).handle.read_exact(&mut data).is_err()
+    {
+        return None;
+    }
+    let headers = find_object_headers(&data);
+    let max = headers
+        .iter()
+        .map(|&(num, _, _)| num)
+        .filter(|&num| num <= MAX_OBJ_NUM)
+        .max()?;
+    extend_xref(pf, max as i32 + 1);
+    /* Later definitions replace earlier ones */
+    for &(num, gen, offset) in &headers {
+        if num > 0 && num <= max {
+            let entry = &mut *(*pf).xref_table.offset(num as isize);
+            entry.typ = 1;
+            entry.field2 = offset as u32;
+            entry.field3 = gen;
+        }
+    }
+    /* The last trailer that names the catalog */
+    let mut trailer = 0 as *mut pdf_obj;
+    let mut end = data.len();
+    while let Some(pos) = data[..end].windows(7).rposition(|w| w == b"trailer") {
+        let mut p = &data[pos + 7..];
+        p.skip_white();
+        if let Some(dict) = p.parse_pdf_dict(pf) {
+            if (*dict).as_dict().has("Root") {
+                trailer = dict;
+                break;
+            }
+            pdf_release_obj(dict);
+        }
+        end = pos;
+    }
+    /* Otherwise the last cross-reference stream, or the catalog */
+    let mut xref_trailer = 0 as *mut pdf_obj;
+    let mut catalog = None;
+    let mut objstms = vec![];
+    for num in 1..=max {
+        let entry = *(*pf).xref_table.offset(num as isize);
+        if entry.typ != 1 {
+            continue;
+        }
+        let limit = next_object_offset(pf, num);
+        let obj = pdf_read_object(num, entry.field3, pf, entry.field2 as i32, limit);
+        if obj.is_null() {
+            continue;
+        }
+        let dict = if (*obj).is_stream() {
+            (*obj).as_stream().get_dict()
+        } else {
+            &*obj
+        };
+        let typ = Some(dict)
+            .filter(|dict| dict.is_dict())
+            .and_then(|dict| dict.as_dict().get("Type"))
+            .filter(|typ| typ.is_name())
+            .map_or(&b""[..], |typ| pdf_name_value(typ).to_bytes());
+        if typ == b"ObjStm" && (*obj).is_stream() {
+            objstms.push(num);
+        } else if typ == b"XRef" && (*obj).is_stream() && dict.as_dict().has("Root") {
+            pdf_release_obj(xref_trailer);
+            xref_trailer = pdf_new_dict();
+            for &key in &["Root", "Encrypt", "ID", "Info"] {
+                if let Some(value) = dict.as_dict().get(key) {
+                    pdf_add_dict(
+                        &mut *xref_trailer,
+                        key,
+                        pdf_link_obj(value as *const pdf_obj as *mut pdf_obj),
+                    );
+                }
+            }
+        } else if typ == b"Catalog" {
+            catalog = Some((num, entry.field3));
+        }
+        pdf_release_obj(obj);
+    }
+    if trailer.is_null() {
+        trailer = xref_trailer;
+    } else {
+        pdf_release_obj(xref_trailer);
+    }
+    if trailer.is_null() {
+        if let Some((num, gen)) = catalog {
+            trailer = pdf_new_dict();
+            pdf_add_dict(&mut *trailer, "Root", pdf_new_indirect(pf, num, gen));
+        } else {
+            warn!("Cannot find PDF document catalog. Broken PDF file?");
+            return None;
+        }
+    }
+    Some((trailer, objstms))
+}
+/* Objects in the object streams of a rebuilt file, unless they are also
+ * defined outside of them. */
+unsafe fn add_objstm_entries(pf: *mut pdf_file, objstms: &[u32]) {
+    for &num in objstms {
+        let objstm = read_objstm(pf, num);
+        if objstm.is_null() {
+            continue;
+        }
+        let header = get_objstm_data(&*objstm);
+        for index in 0..*header {
+            let obj_num = *header.offset(2 + 2 * index as isize) as u32;
+            if obj_num == 0 || obj_num > MAX_OBJ_NUM {
+                continue;
+            }
+            if obj_num >= (*pf).num_obj as u32 {
+                extend_xref(pf, obj_num as i32 + 1);
+            }
+            let entry = &mut *(*pf).xref_table.offset(obj_num as isize);
+            if entry.typ == 0 {
+                entry.typ = 2;
+                entry.field2 = num;
+                entry.field3 = index as u16;
+            }
+        }
+    }
+}
 #[no_mangle]
 pub unsafe extern "C" fn pdf_close(mut pf: *mut pdf_file) {
     if !pf.is_null() {
@@ -4051,4 +4554,26 @@ pub unsafe extern "C" fn pdf_obj_reset_global_state() {
     pdf_output_file_position = 0;
     pdf_output_line_position = 0;
     compression_saved = 0i32;
+    label_watch = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_headers() {
+        let data = b"%PDF-1.4\n1 0 obj\n<< /Type /Catalog >>\nendobj\n\
+            12 3 obj<< /Length 12 >>stream\n7 0 obj junk\nendstream\nendobj\r\
+            x2 0 obj 5 0 objects 4 0 obj\n(a)\nendobj\n1 0 obj null endobj";
+        let headers = find_object_headers(data);
+        let labels: Vec<_> = headers.iter().map(|&(num, gen, _)| (num, gen)).collect();
+        assert_eq!(labels, vec![(1, 0), (12, 3), (4, 0), (1, 0)]);
+        for &(num, gen, offset) in &headers {
+            let header = format!("{} {} obj", num, gen);
+            assert!(data[offset..].starts_with(header.as_bytes()));
+        }
+        assert_eq!(object_header_before(b"0 obj"), None);
+        assert_eq!(object_header_before(b"%\n3 0 "), Some((3, 0, 2)));
+    }
 }
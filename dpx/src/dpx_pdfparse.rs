@@ -42,14 +42,14 @@ use crate::dpx_pdfobj::{
 use crate::specials::spc_lookup_reference;
 use libc::{memcpy};
 
-fn is_space(c: &u8) -> bool {
+pub(crate) fn is_space(c: &u8) -> bool {
     [b' ', b'\t', '\u{c}' as u8, b'\r', b'\n', 0].contains(c)
 }
 fn is_delim(c: &u8) -> bool {
     b"()/<>[]%".contains(c)
 }
 
-fn istokensep(c: &u8) -> bool {
+pub(crate) fn istokensep(c: &u8) -> bool {
     is_space(c) || is_delim(c)
 }
 
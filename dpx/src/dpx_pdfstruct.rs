@@ -0,0 +1,370 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2002-2016 by Jin-Hwan Cho and Shunsaku Hirata,
+    the dvipdfmx project team.
+
+    Copyright (C) 1998, 1999 by Mark A. Wicks <mwicks@kettering.edu>
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+#![allow(non_upper_case_globals)]
+
+//! Tagged PDF: the logical structure tree and marked content.
+//!
+//! Structure elements are opened and closed by the `pdf:bstruct` and
+//! `pdf:estruct` specials. Page content is put into marked-content
+//! sequences lazily, when it is written: content inside a structure element
+//! gets an `MCID` that is recorded among the element's kids, and everything
+//! else is marked as an artifact. An element that spans a page break simply
+//! gets another sequence on the next page.
+
+use crate::warn;
+
+use super::dpx_pdfdev::graphics_mode;
+use super::dpx_pdfdoc::{
+    pdf_doc_add_page_marker, pdf_doc_current_page_number, pdf_doc_get_dictionary,
+    pdf_doc_get_reference,
+};
+use super::dpx_pdfobj::{
+    pdf_add_array, pdf_add_dict, pdf_link_obj, pdf_merge_dict, pdf_name_value, pdf_new_array,
+    pdf_new_boolean, pdf_new_dict, pdf_new_name, pdf_new_number, pdf_obj, pdf_ref_obj,
+    pdf_release_obj,
+};
+
+struct Element {
+    dict: *mut pdf_obj,
+    role: Vec<u8>,
+    page_no: i32,
+}
+
+enum Open {
+    Element(usize),
+    /// An explicit artifact, with its property list as written.
+    Artifact(Option<Vec<u8>>),
+}
+
+struct StructTree {
+    root: *mut pdf_obj,
+    elements: Vec<Element>,
+    open: Vec<Open>,
+    role_map: *mut pdf_obj,
+    /* Values of the parent tree, by key. */
+    parent_tree: Vec<*mut pdf_obj>,
+    /* The elements that own the marked content of the current page, by MCID. */
+    page_mcids: Vec<*mut pdf_obj>,
+    page_open: bool,
+    in_sequence: bool,
+}
+
+static mut tagged: bool = false;
+static mut tree: Option<StructTree> = None;
+
+pub unsafe fn pdf_struct_reset_global_state() {
+    tagged = false;
+    tree = None;
+}
+
+/// Turns on tagging: from now on all page content is marked.
+pub unsafe fn pdf_struct_set_tagged() {
+    tagged = true;
+}
+
+pub unsafe fn pdf_struct_is_tagged() -> bool {
+    tagged
+}
+
+unsafe fn get_tree() -> &'static mut StructTree {
+    tagged = true;
+    tree.get_or_insert_with(|| {
+        let root = pdf_new_dict();
+        pdf_add_dict(&mut *root, "Type", pdf_new_name("StructTreeRoot"));
+        pdf_add_dict(&mut *root, "K", pdf_new_array());
+        StructTree {
+            root,
+            elements: Vec::new(),
+            open: Vec::new(),
+            role_map: pdf_new_dict(),
+            parent_tree: Vec::new(),
+            page_mcids: Vec::new(),
+            page_open: false,
+            in_sequence: false,
+        }
+    })
+}
+
+unsafe fn kids_of(dict: *mut pdf_obj) -> &'static mut pdf_obj {
+    (*dict).as_dict_mut().get_mut("K").unwrap()
+}
+
+/// Writes a name object, escaping the characters that need it.
+fn write_name(buf: &mut Vec<u8>, name: &[u8]) {
+    buf.push(b'/');
+    for &c in name {
+        if c <= b' ' || c > b'~' || b"#%()/<>[]{}".contains(&c) {
+            buf.extend_from_slice(format!("#{:02X}", c).as_bytes());
+        } else {
+            buf.push(c);
+        }
+    }
+}
+
+/// Ends the marked-content sequence that page content is currently going
+/// into, if any.
+unsafe fn close_sequence(t: &mut StructTree) {
+    if t.page_open && t.in_sequence {
+        graphics_mode();
+        pdf_doc_add_page_marker(b" EMC");
+        t.in_sequence = false;
+    }
+}
+
+/// Called before content is added to the current page; returns the operator
+/// that starts the marked-content sequence it belongs in, if a new one is
+/// needed.
+pub unsafe fn pdf_struct_content_marker() -> Option<Vec<u8>> {
+    if !tagged {
+        return None;
+    }
+    let t = get_tree();
+    if !t.page_open || t.in_sequence {
+        return None;
+    }
+    t.in_sequence = true;
+    let mut marker = vec![b' '];
+    match t.open.last() {
+        Some(&Open::Element(i)) => {
+            let element = &t.elements[i];
+            let mcid = t.page_mcids.len();
+            t.page_mcids.push(pdf_ref_obj(element.dict));
+            if element.page_no == pdf_doc_current_page_number() {
+                pdf_add_array(kids_of(element.dict), pdf_new_number(mcid as f64));
+            } else {
+                let mcr = pdf_new_dict();
+                pdf_add_dict(&mut *mcr, "Type", pdf_new_name("MCR"));
+                pdf_add_dict(&mut *mcr, "Pg", pdf_doc_get_reference("@THISPAGE"));
+                pdf_add_dict(&mut *mcr, "MCID", pdf_new_number(mcid as f64));
+                pdf_add_array(kids_of(element.dict), mcr);
+            }
+            write_name(&mut marker, &element.role);
+            marker.extend_from_slice(format!(" <</MCID {}>> BDC ", mcid).as_bytes());
+        }
+        Some(Open::Artifact(Some(props))) => {
+            marker.extend_from_slice(b"/Artifact ");
+            marker.extend_from_slice(props);
+            marker.extend_from_slice(b" BDC ");
+        }
+        _ => marker.extend_from_slice(b"/Artifact BMC "),
+    }
+    Some(marker)
+}
+
+pub unsafe fn pdf_struct_begin_page() {
+    if !tagged {
+        return;
+    }
+    let t = get_tree();
+    t.page_open = true;
+    t.in_sequence = false;
+}
+
+/// Closes the marked content of the current page and records it in the
+/// parent tree.
+pub unsafe fn pdf_struct_end_page() {
+    if !tagged {
+        return;
+    }
+    let t = get_tree();
+    close_sequence(t);
+    t.page_open = false;
+    let page = pdf_doc_get_dictionary("@THISPAGE");
+    pdf_add_dict(&mut *page, "Tabs", pdf_new_name("S"));
+    if !t.page_mcids.is_empty() {
+        let mcids = pdf_new_array();
+        for r in t.page_mcids.drain(..) {
+            pdf_add_array(&mut *mcids, r);
+        }
+        pdf_add_dict(
+            &mut *page,
+            "StructParents",
+            pdf_new_number(t.parent_tree.len() as f64),
+        );
+        t.parent_tree.push(mcids);
+    }
+}
+
+/// Opens a structure element of the given role. `attrs` holds additional
+/// entries for the element (`/Alt`, `/Lang`, `/ActualText`, `/T`, ...).
+pub unsafe fn pdf_struct_begin_element(
+    role: &pdf_obj,
+    attrs: Option<&pdf_obj>,
+) -> Result<(), &'static str> {
+    let t = get_tree();
+    if let Some(Open::Artifact(_)) = t.open.last() {
+        return Err("Structure elements cannot be nested in artifacts.");
+    }
+    close_sequence(t);
+    let dict = pdf_new_dict();
+    if let Some(attrs) = attrs {
+        pdf_merge_dict(&mut *dict, attrs);
+    }
+    pdf_add_dict(&mut *dict, "Type", pdf_new_name("StructElem"));
+    pdf_add_dict(
+        &mut *dict,
+        "S",
+        pdf_link_obj(role as *const pdf_obj as *mut pdf_obj),
+    );
+    pdf_add_dict(&mut *dict, "Pg", pdf_doc_get_reference("@THISPAGE"));
+    pdf_add_dict(&mut *dict, "K", pdf_new_array());
+    let parent = match t.open.last() {
+        Some(&Open::Element(i)) => t.elements[i].dict,
+        _ => t.root,
+    };
+    pdf_add_dict(&mut *dict, "P", pdf_ref_obj(parent));
+    pdf_add_array(kids_of(parent), pdf_ref_obj(dict));
+    t.open.push(Open::Element(t.elements.len()));
+    t.elements.push(Element {
+        dict,
+        role: pdf_name_value(role).to_bytes().to_owned(),
+        page_no: pdf_doc_current_page_number(),
+    });
+    Ok(())
+}
+
+/// Closes the innermost structure element. If `role` is given, it must be
+/// the role that the element was opened with.
+pub unsafe fn pdf_struct_end_element(role: Option<&[u8]>) -> Result<(), &'static str> {
+    let t = get_tree();
+    match t.open.last() {
+        Some(&Open::Element(i)) => {
+            if role.map_or(false, |r| r != &t.elements[i].role[..]) {
+                return Err("Structure element ended with a different role than it began with.");
+            }
+        }
+        Some(Open::Artifact(_)) => return Err("An artifact must be ended first."),
+        None => return Err("Tried to end a structure element without beginning one."),
+    }
+    close_sequence(t);
+    t.open.pop();
+    Ok(())
+}
+
+/// Starts marking content as an artifact explicitly, with an optional
+/// property list such as `<</Type /Pagination>>`.
+pub unsafe fn pdf_struct_begin_artifact(props: Option<&[u8]>) {
+    let t = get_tree();
+    close_sequence(t);
+    t.open.push(Open::Artifact(props.map(|p| p.to_owned())));
+}
+
+pub unsafe fn pdf_struct_end_artifact() -> Result<(), &'static str> {
+    let t = get_tree();
+    match t.open.last() {
+        Some(Open::Artifact(_)) => {}
+        _ => return Err("Tried to end an artifact without beginning one."),
+    }
+    close_sequence(t);
+    t.open.pop();
+    Ok(())
+}
+
+/// Adds mappings from custom roles to standard structure types.
+pub unsafe fn pdf_struct_add_role_map(map: &pdf_obj) {
+    let t = get_tree();
+    pdf_merge_dict(&mut *t.role_map, map);
+}
+
+/// Makes an annotation that is being placed on the current page a kid of
+/// the innermost open structure element.
+pub unsafe fn pdf_struct_add_annot(annot_dict: *mut pdf_obj) {
+    if !tagged {
+        return;
+    }
+    let t = get_tree();
+    let element = match t.open.last() {
+        Some(&Open::Element(i)) => t.elements[i].dict,
+        _ => return,
+    };
+    pdf_add_dict(
+        &mut *annot_dict,
+        "StructParent",
+        pdf_new_number(t.parent_tree.len() as f64),
+    );
+    t.parent_tree.push(pdf_ref_obj(element));
+    let objr = pdf_new_dict();
+    pdf_add_dict(&mut *objr, "Type", pdf_new_name("OBJR"));
+    pdf_add_dict(&mut *objr, "Obj", pdf_ref_obj(annot_dict));
+    pdf_add_dict(&mut *objr, "Pg", pdf_doc_get_reference("@THISPAGE"));
+    pdf_add_array(kids_of(element), objr);
+}
+
+/// Writes out the structure tree and hooks it into the document catalog.
+pub unsafe fn pdf_struct_close(catalog: &mut pdf_obj) {
+    let t = match tree.take() {
+        Some(t) => t,
+        None => {
+            tagged = false;
+            return;
+        }
+    };
+    tagged = false;
+    if !t.open.is_empty() {
+        warn!(
+            "{} structure element(s) or artifact(s) not closed at the end of the document.",
+            t.open.len()
+        );
+    }
+    let next_key = t.parent_tree.len();
+    let nums = pdf_new_array();
+    for (key, value) in t.parent_tree.into_iter().enumerate() {
+        pdf_add_array(&mut *nums, pdf_new_number(key as f64));
+        pdf_add_array(&mut *nums, value);
+    }
+    let parent_tree = pdf_new_dict();
+    pdf_add_dict(&mut *parent_tree, "Nums", nums);
+    pdf_add_dict(&mut *t.root, "ParentTree", pdf_ref_obj(parent_tree));
+    pdf_release_obj(parent_tree);
+    pdf_add_dict(
+        &mut *t.root,
+        "ParentTreeNextKey",
+        pdf_new_number(next_key as f64),
+    );
+    if !(*t.role_map).as_dict().key.is_null() {
+        pdf_add_dict(&mut *t.root, "RoleMap", pdf_link_obj(t.role_map));
+    }
+    pdf_release_obj(t.role_map);
+    pdf_add_dict(catalog, "StructTreeRoot", pdf_ref_obj(t.root));
+    if !catalog.as_dict().has("MarkInfo") {
+        let mark_info = pdf_new_dict();
+        pdf_add_dict(&mut *mark_info, "Marked", pdf_new_boolean(1));
+        pdf_add_dict(catalog, "MarkInfo", mark_info);
+    }
+    for element in t.elements {
+        pdf_release_obj(element.dict);
+    }
+    pdf_release_obj(t.root);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_name;
+
+    #[test]
+    fn escaped_names() {
+        let mut buf = Vec::new();
+        write_name(&mut buf, b"H1");
+        write_name(&mut buf, b"My Role#(1)");
+        assert_eq!(&buf[..], &b"/H1/My#20Role#23#281#29"[..]);
+    }
+}
@@ -30,20 +30,30 @@
 use crate::mfree;
 use crate::DisplayExt;
 use crate::{info, warn};
-use crate::{streq_ptr, strstartswith};
+use crate::streq_ptr;
 use std::ffi::CStr;
 
 use super::dpx_bmpimage::{bmp_include_image, check_for_bmp};
 use super::dpx_dpxfile::{dpx_delete_temp_file, keep_cache};
+use super::dpx_epsimage::{check_for_eps, eps_include_page};
+use super::dpx_gifimage::{check_for_gif, gif_include_image};
+use super::dpx_imagecache;
+use super::dpx_jp2image::{check_for_jp2, jp2_include_image};
+use super::dpx_jpegcodec;
 use super::dpx_jpegimage::{check_for_jpeg, jpeg_include_image};
 use super::dpx_mem::{new, renew};
-use super::dpx_mfileio::{tt_mfgets, work_buffer};
+use super::dpx_pdfdoc::{pdf_doc_add_annot, pdf_doc_current_page_number};
 use super::dpx_pdfdraw::pdf_dev_transform;
 use super::dpx_pngimage::{check_for_png, png_include_image};
+use super::dpx_svgimage::{check_for_svg, svg_include_page};
+use super::dpx_tiffimage::{check_for_tiff, tiff_include_image};
+use super::dpx_webpimage::{check_for_webp, webp_include_image};
 use crate::dpx_epdf::pdf_include_page;
 use crate::dpx_pdfobj::{
-    check_for_pdf, pdf_add_dict, pdf_link_obj, pdf_merge_dict, pdf_new_name, pdf_new_number,
-    pdf_obj, pdf_ref_obj, pdf_release_obj,
+    check_for_pdf, pdf_add_array, pdf_add_dict, pdf_add_stream, pdf_dict, pdf_link_obj,
+    pdf_merge_dict, pdf_name_value, pdf_new_array, pdf_new_dict, pdf_new_name, pdf_new_number,
+    pdf_new_stream, pdf_new_string, pdf_number_value, pdf_obj, pdf_ref_obj, pdf_release_obj,
+    pdf_remove_dict, pdf_stream_set_predictor, STREAM_COMPRESS, STREAM_USE_PREDICTOR,
 };
 use crate::shims::sprintf;
 use crate::{ttstub_input_close, ttstub_input_open};
@@ -58,7 +68,7 @@ use crate::TTInputFormat;
 use bridge::InputHandleWrapper;
 
 use super::dpx_pdfdev::{Coord, Rect, TMatrix, transform_info};
-#[derive(Copy, Clone, Default)]
+#[derive(Copy, Clone, Default, Debug, PartialEq)]
 #[repr(C)]
 pub struct ximage_info {
     pub flags: i32,
@@ -108,6 +118,13 @@ pub struct attr_ {
     pub bbox_type: i32,
     pub dict: *mut pdf_obj,
     pub tempfile: i8,
+    /* Image format, as found by source_image_type() */
+    pub source: i32,
+    /* Largest size the image is painted at, in bp; negative if unknown */
+    pub placed_width: f64,
+    pub placed_height: f64,
+    /* Annotations of an included PDF page, in the space of the form */
+    pub annots: *mut pdf_obj,
 }
 /* quasi-hack to get the primary input */
 /* verbose, verbose, verbose... */
@@ -116,6 +133,9 @@ pub struct attr_ {
 pub struct opt_ {
     pub verbose: i32,
     pub cmdtmpl: *mut i8,
+    pub image_dpi: u32,
+    pub jpeg_quality: u8,
+    pub png_to_jpeg: bool,
 }
 #[derive(Copy, Clone)]
 #[repr(C)]
@@ -134,11 +154,31 @@ pub struct ic_ {
 static mut _opts: opt_ = opt_ {
         verbose: 0i32,
         cmdtmpl: 0 as *const i8 as *mut i8,
+        image_dpi: 0,
+        jpeg_quality: 85,
+        png_to_jpeg: false,
     };
 #[no_mangle]
 pub unsafe extern "C" fn pdf_ximage_set_verbose(mut level: i32) {
     _opts.verbose = level;
 }
+/// Sets up image downsampling. Images painted at more than 1.5 times
+/// `image_dpi` (0 disables this) are downsampled to it; JPEG images are
+/// re-encoded at `jpeg_quality`. With `png_to_jpeg`, PNG images without
+/// transparency are also encoded as JPEG, whether downsampled or not.
+///
+/// Images are only written out at the end of the document when this is on,
+/// since the largest size an image is painted at is not known before.
+#[no_mangle]
+pub unsafe extern "C" fn pdf_ximage_set_downsampling(
+    image_dpi: u32,
+    jpeg_quality: u8,
+    png_to_jpeg: bool,
+) {
+    _opts.image_dpi = image_dpi;
+    _opts.jpeg_quality = jpeg_quality;
+    _opts.png_to_jpeg = png_to_jpeg;
+}
 static mut _ic: ic_ = ic_ {
         count: 0i32,
         capacity: 0i32,
@@ -161,6 +201,10 @@ unsafe fn pdf_init_ximage_struct(mut I: *mut pdf_ximage) {
     (*I).attr.bbox_type = 0i32;
     (*I).attr.dict = 0 as *mut pdf_obj;
     (*I).attr.tempfile = 0_i8;
+    (*I).attr.source = -1;
+    (*I).attr.placed_width = 0.;
+    (*I).attr.placed_height = 0.;
+    (*I).attr.annots = 0 as *mut pdf_obj;
 }
 unsafe fn pdf_clean_ximage_struct(mut I: *mut pdf_ximage) {
     free((*I).ident as *mut libc::c_void);
@@ -168,6 +212,7 @@ unsafe fn pdf_clean_ximage_struct(mut I: *mut pdf_ximage) {
     pdf_release_obj((*I).reference);
     pdf_release_obj((*I).resource);
     pdf_release_obj((*I).attr.dict);
+    pdf_release_obj((*I).attr.annots);
     pdf_init_ximage_struct(I);
 }
 #[no_mangle]
@@ -201,6 +246,9 @@ pub unsafe extern "C" fn pdf_close_images() {
                 dpx_delete_temp_file((*I).filename, 0i32);
                 (*I).filename = 0 as *mut i8
             }
+            if (*I).subtype == 1 && !(*I).resource.is_null() {
+                downsample_image(I);
+            }
             pdf_clean_ximage_struct(I);
         }
         (*ic).ximages = mfree((*ic).ximages as *mut libc::c_void) as *mut pdf_ximage;
@@ -211,17 +259,27 @@ pub unsafe extern "C" fn pdf_close_images() {
 }
 unsafe fn source_image_type(handle: &mut InputHandleWrapper) -> i32 {
     handle.seek(SeekFrom::Start(0)).unwrap();
-    /* Original check order: jpeg, jp2, png, bmp, pdf, ps */
+    /* Original check order: jpeg, jp2, png, bmp, pdf, ps; then webp, gif, tiff, svg */
     let format = if check_for_jpeg(handle) != 0 {
         1
+    } else if check_for_jp2(handle) != 0 {
+        7
     } else if check_for_png(handle) != 0 {
         2
     } else if check_for_bmp(handle) != 0 {
         6
     } else if check_for_pdf(handle) != 0 {
         0
-    } else if check_for_ps(handle) != 0 {
+    } else if check_for_eps(handle) != 0 {
         5
+    } else if check_for_webp(handle) != 0 {
+        9
+    } else if check_for_gif(handle) != 0 {
+        10
+    } else if check_for_tiff(handle) != 0 {
+        11
+    } else if check_for_svg(handle) != 0 {
+        8
     } else {
         warn!("Tectonic was unable to detect an image\'s format");
         -1
@@ -229,42 +287,14 @@ unsafe fn source_image_type(handle: &mut InputHandleWrapper) -> i32 {
     handle.seek(SeekFrom::Start(0)).unwrap();
     format
 }
-unsafe fn load_image(
-    mut ident: *const i8,
-    mut fullname: *const i8,
-    mut format: i32,
+/* Includes an image of the given format, cleaning up after failure. */
+unsafe fn include_image(
+    I: *mut pdf_ximage,
+    fullname: *const i8,
+    format: i32,
     mut handle: InputHandleWrapper,
-    mut options: load_options,
+    options: load_options,
 ) -> i32 {
-    let mut ic: *mut ic_ = &mut _ic;
-    let id = (*ic).count;
-    if (*ic).count >= (*ic).capacity {
-        (*ic).capacity += 16i32;
-        (*ic).ximages = renew(
-            (*ic).ximages as *mut libc::c_void,
-            ((*ic).capacity as u32 as u64).wrapping_mul(::std::mem::size_of::<pdf_ximage>() as u64)
-                as u32,
-        ) as *mut pdf_ximage
-    }
-    let I = &mut *(*ic).ximages.offset(id as isize) as *mut pdf_ximage;
-    pdf_init_ximage_struct(I);
-    if !ident.is_null() {
-        (*I).ident =
-            new((strlen(ident).wrapping_add(1)).wrapping_mul(::std::mem::size_of::<i8>()) as _)
-                as *mut i8;
-        strcpy((*I).ident, ident);
-    }
-    if !fullname.is_null() {
-        (*I).filename =
-            new((strlen(fullname).wrapping_add(1)).wrapping_mul(::std::mem::size_of::<i8>()) as _)
-                as *mut i8;
-        strcpy((*I).filename, fullname);
-    }
-    (*I).attr.page_no = options.page_no;
-    (*I).attr.bbox_type = options.bbox_type;
-    /* else if (check_for_jp2(fp))
-     *    format = IMAGE_TYPE_JP2; */
-    (*I).attr.dict = options.dict; /* unsafe? */
     match format {
         1 => {
             if _opts.verbose != 0 {
@@ -284,13 +314,13 @@ unsafe fn load_image(
             if _opts.verbose != 0 {
                 info!("[JP2]");
             }
-            /*if (jp2_include_image(I, fp) < 0)*/
-            warn!("Tectonic: JP2 not yet supported");
+            if jp2_include_image(I, &mut handle) < 0 {
+                ttstub_input_close(handle);
+                pdf_clean_ximage_struct(I);
+                return -1;
+            }
+            (*I).subtype = 1;
             ttstub_input_close(handle);
-            pdf_clean_ximage_struct(I);
-            return -1;
-            /*I->subtype = PDF_XOBJECT_TYPE_IMAGE;
-            break;*/
         }
         2 => {
             if _opts.verbose != 0 {
@@ -313,6 +343,45 @@ unsafe fn load_image(
             (*I).subtype = 1;
             ttstub_input_close(handle);
         }
+        9 => {
+            if _opts.verbose != 0 {
+                info!("[WebP]");
+            }
+            if webp_include_image(I, &mut handle) < 0 {
+                ttstub_input_close(handle);
+                pdf_clean_ximage_struct(I);
+                return -1;
+            }
+            (*I).subtype = 1;
+            ttstub_input_close(handle);
+        }
+        10 => {
+            if _opts.verbose != 0 {
+                info!("[GIF]");
+            }
+            if gif_include_image(I, &mut handle) < 0 {
+                ttstub_input_close(handle);
+                pdf_clean_ximage_struct(I);
+                return -1;
+            }
+            (*I).subtype = 1;
+            ttstub_input_close(handle);
+        }
+        11 => {
+            if _opts.verbose != 0 {
+                info!("[TIFF]");
+            }
+            if tiff_include_image(I, &mut handle) < 0 {
+                ttstub_input_close(handle);
+                pdf_clean_ximage_struct(I);
+                return -1;
+            }
+            if _opts.verbose != 0 {
+                info!(",Page:{}", (*I).attr.page_no);
+            }
+            (*I).subtype = 1;
+            ttstub_input_close(handle);
+        }
         0 => {
             if _opts.verbose != 0 {
                 info!("[PDF]");
@@ -329,16 +398,6 @@ unsafe fn load_image(
             ttstub_input_close(handle);
             (*I).subtype = 0;
         }
-        5 => {
-            if _opts.verbose != 0 {
-                info!("[EPS]");
-            }
-            warn!("sorry, PostScript images are not supported by Tectonic");
-            warn!("for details, please see https://github.com/tectonic-typesetting/tectonic/issues/27");
-            ttstub_input_close(handle);
-            pdf_clean_ximage_struct(I);
-            return -1;
-        }
         _ => {
             if _opts.verbose != 0 {
                 info!("[UNKNOWN]");
@@ -349,6 +408,75 @@ unsafe fn load_image(
             return -1;
         }
     }
+    0
+}
+unsafe fn load_image(
+    mut ident: *const i8,
+    mut fullname: *const i8,
+    mut format: i32,
+    mut handle: InputHandleWrapper,
+    mut options: load_options,
+) -> i32 {
+    let mut ic: *mut ic_ = &mut _ic;
+    let id = (*ic).count;
+    if (*ic).count >= (*ic).capacity {
+        (*ic).capacity += 16i32;
+        (*ic).ximages = renew(
+            (*ic).ximages as *mut libc::c_void,
+            ((*ic).capacity as u32 as u64).wrapping_mul(::std::mem::size_of::<pdf_ximage>() as u64)
+                as u32,
+        ) as *mut pdf_ximage
+    }
+    let I = &mut *(*ic).ximages.offset(id as isize) as *mut pdf_ximage;
+    pdf_init_ximage_struct(I);
+    if !ident.is_null() {
+        (*I).ident =
+            new((strlen(ident).wrapping_add(1)).wrapping_mul(::std::mem::size_of::<i8>()) as _)
+                as *mut i8;
+        strcpy((*I).ident, ident);
+    }
+    if !fullname.is_null() {
+        (*I).filename =
+            new((strlen(fullname).wrapping_add(1)).wrapping_mul(::std::mem::size_of::<i8>()) as _)
+                as *mut i8;
+        strcpy((*I).filename, fullname);
+    }
+    (*I).attr.page_no = options.page_no;
+    (*I).attr.bbox_type = options.bbox_type;
+    (*I).attr.dict = options.dict; /* unsafe? */
+    (*I).attr.source = format;
+    let keep_raw = _opts.image_dpi > 0 || _opts.png_to_jpeg;
+    /* PDF pages are not cached: objects imported from a file are shared
+     * between the pages included from it. */
+    let cache_key = match format {
+        1 | 2 | 6 | 7 | 9 | 10 | 11 => {
+            dpx_imagecache::image_key(format, &options, keep_raw, &mut handle)
+        }
+        _ => None,
+    };
+    if let Some(mut cached) = cache_key.as_ref().and_then(|key| dpx_imagecache::fetch(key)) {
+        if _opts.verbose != 0 {
+            info!("[cached]");
+        }
+        pdf_ximage_set_image(I, &mut cached.info, cached.resource);
+        (*I).attr.page_count = cached.page_count;
+        ttstub_input_close(handle);
+    } else {
+        if cache_key.is_some() {
+            dpx_imagecache::start_recording();
+        }
+        let result = include_image(I, fullname, format, handle, options);
+        if cache_key.is_some() {
+            dpx_imagecache::finish_recording(
+                cache_key.as_ref().filter(|_| result == 0),
+                (*I).attr.page_count,
+                keep_raw,
+            );
+        }
+        if result < 0 {
+            return -1;
+        }
+    }
 
     match (*I).subtype {
         1 => {
@@ -415,7 +543,35 @@ pub unsafe extern "C" fn pdf_ximage_findresource(
         info!("(Image:{}", CStr::from_ptr(ident).display());
     }
     let format = source_image_type(&mut handle);
-    let id = load_image(ident, ident, format, handle, options);
+    let id = if format == 5 || format == 8 {
+        /* EPS and SVG figures are rendered into a form XObject of their
+         * own, which is registered while it is being grabbed. */
+        let id = if format == 5 {
+            if _opts.verbose != 0 {
+                info!("[EPS]");
+            }
+            eps_include_page(ident, &mut handle)
+        } else {
+            if _opts.verbose != 0 {
+                info!("[SVG]");
+            }
+            svg_include_page(ident, &mut handle)
+        };
+        ttstub_input_close(handle);
+        if id >= 0 {
+            let I = &mut *(*ic).ximages.offset(id as isize) as *mut pdf_ximage;
+            (*I).filename =
+                new((strlen(ident).wrapping_add(1)).wrapping_mul(::std::mem::size_of::<i8>()) as _)
+                    as *mut i8;
+            strcpy((*I).filename, ident);
+            (*I).attr.page_no = options.page_no;
+            (*I).attr.bbox_type = options.bbox_type;
+            (*I).attr.dict = options.dict;
+        }
+        id
+    } else {
+        load_image(ident, ident, format, handle, options)
+    };
     if _opts.verbose != 0 {
         info!(")");
     }
@@ -498,6 +654,7 @@ pub unsafe extern "C" fn pdf_ximage_set_image(
     if !(!resource.is_null() && (*resource).is_stream()) {
         panic!("Image XObject must be of stream type.");
     }
+    dpx_imagecache::note_image(info, resource);
     (*I).subtype = 1i32;
     (*I).attr.width = info.width;
     (*I).attr.height = info.height;
@@ -520,8 +677,98 @@ pub unsafe extern "C" fn pdf_ximage_set_image(
     if !(*I).attr.dict.is_null() {
         pdf_merge_dict(dict, &*(*I).attr.dict);
     }
-    pdf_release_obj(resource);
-    (*I).resource = 0 as *mut pdf_obj;
+    if _opts.image_dpi > 0 || _opts.png_to_jpeg {
+        /* Kept for downsample_image() */
+        (*I).resource = resource;
+    } else {
+        pdf_release_obj(resource);
+        (*I).resource = 0 as *mut pdf_obj;
+    }
+}
+/* Helpers shared by the raster image readers that decode pixels themselves. */
+/// Builds `[/Indexed base hival lookup]` from a palette of packed samples
+/// in the base color space.
+pub unsafe fn pdf_ximage_indexed_cspace(
+    base: *mut pdf_obj,
+    ncomps: usize,
+    palette: &[u8],
+) -> *mut pdf_obj {
+    let num_entries = (palette.len() / ncomps).max(1).min(256);
+    let colorspace = pdf_new_array();
+    pdf_add_array(&mut *colorspace, pdf_new_name("Indexed"));
+    pdf_add_array(&mut *colorspace, base);
+    pdf_add_array(&mut *colorspace, pdf_new_number((num_entries - 1) as f64));
+    let mut lookup = palette[..palette.len().min(num_entries * ncomps)].to_vec();
+    lookup.resize(num_entries * ncomps, 0);
+    pdf_add_array(
+        &mut *colorspace,
+        pdf_new_string(lookup.as_ptr() as *const libc::c_void, lookup.len() as _),
+    );
+    colorspace
+}
+/// Builds a soft-mask image (see `create_soft_mask()` of the PNG reader)
+/// from rows of alpha samples. `matte` is given for premultiplied colors.
+pub unsafe fn pdf_ximage_soft_mask(
+    alpha: &[u8],
+    width: i32,
+    height: i32,
+    bpc: i32,
+    matte: Option<usize>,
+) -> *mut pdf_obj {
+    let smask = pdf_new_stream(STREAM_COMPRESS);
+    let dict = (*smask).as_stream_mut().get_dict_mut();
+    pdf_add_dict(dict, "Type", pdf_new_name("XObject"));
+    pdf_add_dict(dict, "Subtype", pdf_new_name("Image"));
+    pdf_add_dict(dict, "Width", pdf_new_number(width as f64));
+    pdf_add_dict(dict, "Height", pdf_new_number(height as f64));
+    pdf_add_dict(dict, "ColorSpace", pdf_new_name("DeviceGray"));
+    pdf_add_dict(dict, "BitsPerComponent", pdf_new_number(bpc as f64));
+    if let Some(ncomps) = matte {
+        let values = pdf_new_array();
+        for _ in 0..ncomps {
+            pdf_add_array(&mut *values, pdf_new_number(0.));
+        }
+        pdf_add_dict(dict, "Matte", values);
+    }
+    pdf_add_stream(
+        &mut *smask,
+        alpha.as_ptr() as *const libc::c_void,
+        alpha.len() as i32,
+    );
+    if bpc >= 8 && width > 64 {
+        pdf_stream_set_predictor(smask, 2, width, bpc, 1);
+    }
+    smask
+}
+/// Composites 8-bit colors onto a white background, for PDF versions
+/// without soft masks. `white` is the sample value of white in the color
+/// space, and `premultiplied` tells whether colors carry associated alpha.
+pub fn pdf_ximage_blend_white(
+    colors: &mut [u8],
+    alpha: &[u8],
+    ncomps: usize,
+    premultiplied: bool,
+    white: u8,
+) {
+    for (pixel, &a) in colors.chunks_exact_mut(ncomps).zip(alpha.iter()) {
+        let (a, white) = (a as u32, white as u32);
+        for c in pixel.iter_mut() {
+            let v = if premultiplied {
+                *c as u32 + (white * (255 - a) + 127) / 255
+            } else {
+                (*c as u32 * a + white * (255 - a) + 127) / 255
+            };
+            *c = v.min(255) as u8;
+        }
+    }
+}
+/// Returns true if every sample of an 8- or 16-bit alpha channel is opaque.
+pub fn pdf_ximage_is_opaque(alpha: &[u8], bpc: i32) -> bool {
+    if bpc == 16 {
+        alpha.chunks(2).all(|s| s == [0xff, 0xff])
+    } else {
+        alpha.iter().all(|&a| a == 0xff)
+    }
 }
 #[no_mangle]
 pub unsafe extern "C" fn pdf_ximage_set_form(
@@ -841,6 +1088,230 @@ pub unsafe extern "C" fn pdf_ximage_scale_image(
     }
     M
 }
+/// Records that an image is painted with the transformation `M` (from the
+/// unit square to page space), for downsampling.
+pub unsafe fn pdf_ximage_record_placement(id: i32, M: &TMatrix) {
+    let ic: *mut ic_ = &mut _ic;
+    if id < 0 || id >= (*ic).count {
+        return;
+    }
+    let I = (*ic).ximages.offset(id as isize);
+    if (*I).attr.placed_width < 0. {
+        return;
+    }
+    (*I).attr.placed_width = (*I).attr.placed_width.max(M.m11.hypot(M.m12));
+    (*I).attr.placed_height = (*I).attr.placed_height.max(M.m21.hypot(M.m22));
+}
+/// Adds the annotations carried over from an included PDF page to the
+/// current page, for the page painted with the transformation `M`.
+pub unsafe fn pdf_ximage_put_annots(id: i32, M: &TMatrix) {
+    let ic: *mut ic_ = &mut _ic;
+    if id < 0 || id >= (*ic).count {
+        return;
+    }
+    let annots = (*(*ic).ximages.offset(id as isize)).attr.annots;
+    if annots.is_null() {
+        return;
+    }
+    for src in &(*annots).as_array().values {
+        let annot = pdf_new_dict();
+        let mut rect = Rect::zero();
+        let mut data = (**src).data as *mut pdf_dict;
+        while !(*data).key.is_null() {
+            let key = pdf_name_value(&*(*data).key).to_bytes();
+            let value = &*(*data).value;
+            if key == b"Rect" {
+                let n = |i| pdf_number_value(value.as_array().get(i).unwrap());
+                rect = Rect::new((n(0), n(1)), (n(2), n(3))).transform(M);
+            } else if key == b"QuadPoints" {
+                let points = pdf_new_array();
+                for xy in value.as_array().values.chunks(2) {
+                    let p = M.transform_point(Coord::new(
+                        pdf_number_value(&*xy[0]),
+                        pdf_number_value(&*xy[1]),
+                    ));
+                    pdf_add_array(&mut *points, pdf_new_number(p.x));
+                    pdf_add_array(&mut *points, pdf_new_number(p.y));
+                }
+                pdf_add_dict(&mut *annot, key, points);
+            } else {
+                pdf_add_dict(&mut *annot, key, pdf_link_obj((*data).value));
+            }
+            data = (*data).next
+        }
+        pdf_doc_add_annot(pdf_doc_current_page_number() as u32, &rect, annot, 0);
+        pdf_release_obj(annot);
+    }
+}
+/// Marks an image as painted at a size that is not known here (e.g. inside
+/// a form XObject), so that it keeps its resolution.
+pub unsafe fn pdf_ximage_keep_resolution(id: i32) {
+    let ic: *mut ic_ = &mut _ic;
+    if id >= 0 && id < (*ic).count {
+        let I = (*ic).ximages.offset(id as isize);
+        (*I).attr.placed_width = -1.;
+        (*I).attr.placed_height = -1.;
+    }
+}
+/// Area-averages a plane of samples down to `nw` by `nh`.
+fn resample_plane(src: &[u8], w: usize, h: usize, nw: usize, nh: usize) -> Vec<u8> {
+    /* For each target sample: the first source sample and the weights. */
+    fn weights(n: usize, nn: usize) -> Vec<(usize, Vec<f32>)> {
+        let step = n as f64 / nn as f64;
+        (0..nn)
+            .map(|i| {
+                let (a, b) = (i as f64 * step, (i + 1) as f64 * step);
+                let first = a.floor() as usize;
+                let last = (b.ceil() as usize).min(n);
+                let w = (first..last)
+                    .map(|j| ((b.min(j as f64 + 1.) - a.max(j as f64)) / step) as f32)
+                    .collect();
+                (first, w)
+            })
+            .collect()
+    }
+    let (wx, wy) = (weights(w, nw), weights(h, nh));
+    let mut rows = vec![0f32; h * nw];
+    for y in 0..h {
+        let row = &src[y * w..(y + 1) * w];
+        for (x, (first, ws)) in wx.iter().enumerate() {
+            rows[y * nw + x] = ws
+                .iter()
+                .zip(&row[*first..])
+                .map(|(k, &v)| k * v as f32)
+                .sum();
+        }
+    }
+    let mut out = Vec::with_capacity(nw * nh);
+    for (first, ws) in &wy {
+        for x in 0..nw {
+            let v: f32 = ws
+                .iter()
+                .enumerate()
+                .map(|(j, k)| k * rows[(first + j) * nw + x])
+                .sum();
+            out.push(v.round().max(0.).min(255.) as u8);
+        }
+    }
+    out
+}
+/// Downsamples and re-encodes an image kept by `pdf_ximage_set_image()` as
+/// set up with `pdf_ximage_set_downsampling()`. Only images with 8-bit
+/// samples are handled: raw ones, as the readers decoding pixels produce
+/// them, and JPEG ones. Anything else is written out unchanged.
+unsafe fn downsample_image(I: *mut pdf_ximage) {
+    let stream = (*(*I).resource).as_stream_mut();
+    let dict = &mut *stream.dict;
+    let (width, height) = ((*I).attr.width as usize, (*I).attr.height as usize);
+    let d = dict.as_dict();
+    let bpc = d.get("BitsPerComponent").filter(|o| o.is_number());
+    if width == 0
+        || height == 0
+        || d.has("ImageMask")
+        || d.has("Mask")
+        || bpc.map(|o| pdf_number_value(o)) != Some(8.)
+    {
+        return;
+    }
+    let jpeg = match d.get("Filter") {
+        None => false,
+        Some(f) if f.is_name() && pdf_name_value(f).to_bytes() == b"DCTDecode" => true,
+        Some(_) => return,
+    };
+    if let Some(cs) = d.get("ColorSpace") {
+        if cs.is_array() {
+            if let Some(family) = cs.as_array().get(0) {
+                if family.is_name() && pdf_name_value(family).to_bytes() == b"Indexed" {
+                    return;
+                }
+            }
+        }
+    }
+    let (mut nw, mut nh) = (width, height);
+    let (pw, ph) = ((*I).attr.placed_width, (*I).attr.placed_height);
+    if _opts.image_dpi > 0 && pw > 0. && ph > 0. {
+        let dpi = _opts.image_dpi as f64;
+        let (tw, th) = ((pw / 72. * dpi).ceil(), (ph / 72. * dpi).ceil());
+        if width as f64 > 1.5 * tw || height as f64 > 1.5 * th {
+            nw = (tw as usize).max(1).min(width);
+            nh = (th as usize).max(1).min(height);
+        }
+    }
+    let to_jpeg = _opts.png_to_jpeg && (*I).attr.source == 2 && !jpeg && !d.has("SMask");
+    if (nw, nh) == (width, height) && !to_jpeg {
+        return;
+    }
+
+    let (planes, transform) = if jpeg {
+        match dpx_jpegcodec::decode(&stream.stream) {
+            Ok(img) if (img.width, img.height) == (width, height) => (img.planes, img.transform),
+            Ok(_) => return,
+            Err(e) => {
+                if _opts.verbose > 0 {
+                    info!("pdf_image>> not downsampling JPEG image: {}\n", e);
+                }
+                return;
+            }
+        }
+    } else {
+        let ncomps = stream.stream.len() / (width * height);
+        if ncomps == 0 || ncomps > 4 || stream.stream.len() != ncomps * width * height {
+            return;
+        }
+        let planes = (0..ncomps)
+            .map(|c| stream.stream.iter().skip(c).step_by(ncomps).copied().collect())
+            .collect();
+        (planes, 0)
+    };
+    let ncomps = planes.len();
+    let mut planes: Vec<Vec<u8>> = if (nw, nh) != (width, height) {
+        planes
+            .iter()
+            .map(|p| resample_plane(p, width, height, nw, nh))
+            .collect()
+    } else {
+        planes
+    };
+    if _opts.verbose > 0 {
+        let name = if (*I).filename.is_null() {
+            "".into()
+        } else {
+            CStr::from_ptr((*I).filename).to_string_lossy()
+        };
+        if (nw, nh) != (width, height) {
+            info!(
+                "pdf_image>> downsampling image \"{}\" from {}x{} to {}x{}\n",
+                name, width, height, nw, nh
+            );
+        } else {
+            info!("pdf_image>> encoding image \"{}\" as JPEG\n", name);
+        }
+    }
+    if jpeg || (to_jpeg && (ncomps == 1 || ncomps == 3)) {
+        let transform = if !jpeg && ncomps == 3 {
+            let rgb: Vec<u8> = (0..nw * nh)
+                .flat_map(|i| planes.iter().map(move |p| p[i]).collect::<Vec<_>>())
+                .collect();
+            planes = dpx_jpegcodec::rgb_to_ycbcr(&rgb);
+            1
+        } else {
+            transform
+        };
+        stream.stream = dpx_jpegcodec::encode(nw, nh, &planes, transform, _opts.jpeg_quality);
+        stream._flags &= !(STREAM_COMPRESS | STREAM_USE_PREDICTOR);
+        pdf_add_dict(dict, "Filter", pdf_new_name("DCTDecode"));
+        pdf_remove_dict(dict, "DecodeParms");
+    } else {
+        stream.stream = (0..nw * nh)
+            .flat_map(|i| planes.iter().map(move |p| p[i]).collect::<Vec<_>>())
+            .collect();
+        if stream._flags & STREAM_USE_PREDICTOR != 0 {
+            pdf_stream_set_predictor((*I).resource, 15, nw as i32, 8, ncomps as i32);
+        }
+    }
+    pdf_add_dict(dict, "Width", pdf_new_number(nw as f64));
+    pdf_add_dict(dict, "Height", pdf_new_number(nh as f64));
+}
 /* Migrated from psimage.c */
 #[no_mangle]
 pub unsafe extern "C" fn set_distiller_template(mut s: *mut i8) {
@@ -866,16 +1337,3 @@ pub unsafe extern "C" fn set_distiller_template(mut s: *mut i8) {
 pub unsafe extern "C" fn get_distiller_template() -> *mut i8 {
     _opts.cmdtmpl
 }
-unsafe fn check_for_ps(handle: &mut InputHandleWrapper) -> i32 {
-    handle.seek(SeekFrom::Start(0)).unwrap();
-    tt_mfgets(work_buffer.as_mut_ptr(), 1024i32, handle);
-    if !strstartswith(
-        work_buffer.as_mut_ptr(),
-        b"%!\x00" as *const u8 as *const i8,
-    )
-    .is_null()
-    {
-        return 1i32;
-    }
-    0i32
-}
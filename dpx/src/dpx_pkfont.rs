@@ -27,9 +27,9 @@
     unused_mut
 )]
 
-use crate::warn;
 use crate::DisplayExt;
-use std::ffi::CStr;
+use crate::{info, warn};
+use std::ffi::{CStr, CString};
 
 use super::dpx_mem::new;
 use super::dpx_mfileio::work_buffer_u8 as work_buffer;
@@ -38,27 +38,32 @@ use super::dpx_pdfencoding::{
     pdf_encoding_get_encoding, pdf_encoding_get_name, pdf_encoding_used_by_type3,
 };
 use super::dpx_pdffont::{
-    pdf_font, pdf_font_get_encoding, pdf_font_get_ident, pdf_font_get_param, pdf_font_get_resource,
-    pdf_font_get_usedchars, pdf_font_is_in_use, pdf_font_set_fontname,
+    pdf_font, pdf_font_get_encoding, pdf_font_get_ident, pdf_font_get_mapname,
+    pdf_font_get_param, pdf_font_get_resource, pdf_font_get_usedchars, pdf_font_get_verbose,
+    pdf_font_is_in_use, pdf_font_set_fontname,
 };
-use super::dpx_tfm::{tfm_get_design_size, tfm_open};
+use super::dpx_pkgen::OutlineFont;
+use super::dpx_tfm::{tfm_get_design_size, tfm_get_fw_width, tfm_open};
 use crate::dpx_pdfobj::{
     pdf_add_array, pdf_add_dict, pdf_add_stream, pdf_copy_name, pdf_new_array, pdf_new_dict,
     pdf_new_name, pdf_new_number, pdf_new_stream, pdf_obj, pdf_ref_obj, pdf_release_obj, STREAM_COMPRESS,
 };
 use crate::shims::sprintf;
-use libc::{fclose, fgetc, fopen, fread, free, memset};
+use crate::{ttstub_input_close, ttstub_input_getc, ttstub_input_open, ttstub_input_read};
+use libc::{free, memset};
 
 use crate::dpx_numbers::{
-    get_positive_quad, get_signed_byte, get_signed_pair, get_signed_quad, get_unsigned_byte,
-    get_unsigned_num, get_unsigned_pair, get_unsigned_triple, skip_bytes,
+    tt_get_positive_quad, tt_get_signed_byte, tt_get_signed_pair, tt_get_signed_quad,
+    tt_get_unsigned_byte, tt_get_unsigned_num, tt_get_unsigned_pair, tt_get_unsigned_triple,
+    tt_skip_bytes,
 };
 
 pub type __off_t = i64;
 pub type __off64_t = i64;
 pub type size_t = u64;
 use super::dpx_pdfdev::Rect;
-use libc::FILE;
+use crate::TTInputFormat;
+use bridge::InputHandleWrapper;
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct pk_header_ {
@@ -75,6 +80,8 @@ pub struct pk_header_ {
     pub run_color: i32,
 }
 static mut base_dpi: u32 = 600u32;
+/* Render glyphs from outline fonts when no PK font is found. */
+static mut outline_fallback: bool = false;
 #[no_mangle]
 pub unsafe extern "C" fn PKFont_set_dpi(mut dpi: i32) {
     if dpi <= 0i32 {
@@ -82,6 +89,10 @@ pub unsafe extern "C" fn PKFont_set_dpi(mut dpi: i32) {
     }
     base_dpi = dpi as u32;
 }
+#[no_mangle]
+pub unsafe extern "C" fn PKFont_set_outline_fallback(mut enable: bool) {
+    outline_fallback = enable;
+}
 /* (Only) This requires TFM to get design size... */
 unsafe fn truedpi(mut ident: *const i8, mut point_size: f64, mut bdpi: u32) -> u32 {
     let mut dpi: u32 = bdpi;
@@ -101,15 +112,43 @@ unsafe fn truedpi(mut ident: *const i8, mut point_size: f64, mut bdpi: u32) -> u
     }
     dpi
 }
-unsafe fn dpx_open_pk_font_at(_ident: *const i8, _dpi: u32) -> *mut FILE {
-    /*kpse_glyph_file_type kpse_file_info;*/
-    let fqpn = 0 as *mut i8; /*kpse_find_glyph(ident, dpi, kpse_pk_format, &kpse_file_info);*/
-    if fqpn.is_null() {
-        return 0 as *mut FILE;
+/* Looks for "<ident>.<dpi>pk", accepting resolutions within the same
+ * tolerance as kpathsea's kpse_bitmap_tolerance().
+ */
+unsafe fn dpx_open_pk_font_at(mut ident: *const i8, mut dpi: u32) -> Option<InputHandleWrapper> {
+    let tolerance = dpi / 500 + 1;
+    let mut resolutions = vec![dpi];
+    for delta in 1..=tolerance {
+        resolutions.push(dpi + delta);
+        if dpi > delta {
+            resolutions.push(dpi - delta);
+        }
+    }
+    let name = CStr::from_ptr(ident).to_string_lossy();
+    for res in resolutions {
+        let fqpn = CString::new(format!("{}.{}pk", name, res)).unwrap();
+        if let Some(handle) = ttstub_input_open(fqpn.as_ptr(), TTInputFormat::PK, 0i32) {
+            return Some(handle);
+        }
+    }
+    None
+}
+/* A Type 1 or OpenType font matching the PK font, for rendering glyphs
+ * when no PK font is available.
+ */
+unsafe fn dpx_open_outline_font(mut font: *mut pdf_font) -> Option<OutlineFont> {
+    if !outline_fallback {
+        return None;
     }
-    let fp = fopen(fqpn, b"rb\x00" as *const u8 as *const i8);
-    free(fqpn as *mut libc::c_void);
-    fp
+    let ident = pdf_font_get_ident(font);
+    let map_name = pdf_font_get_mapname(font);
+    OutlineFont::open(ident).or_else(|| {
+        if map_name.is_null() || CStr::from_ptr(map_name) == CStr::from_ptr(ident) {
+            None
+        } else {
+            OutlineFont::open(map_name)
+        }
+    })
 }
 #[no_mangle]
 pub unsafe extern "C" fn pdf_font_open_pkfont(mut font: *mut pdf_font) -> i32 {
@@ -120,11 +159,11 @@ pub unsafe extern "C" fn pdf_font_open_pkfont(mut font: *mut pdf_font) -> i32 {
         return -1i32;
     }
     let dpi = truedpi(ident, point_size, base_dpi);
-    let fp = dpx_open_pk_font_at(ident, dpi);
-    if fp.is_null() {
+    if let Some(handle) = dpx_open_pk_font_at(ident, dpi) {
+        ttstub_input_close(handle);
+    } else if dpx_open_outline_font(font).is_none() {
         return -1i32;
     }
-    fclose(fp);
     /* Type 3 fonts doesn't have FontName.
      * FontFamily is recommended for PDF 1.5.
      */
@@ -399,66 +438,70 @@ unsafe fn pk_decode_bitmap(
     }
     0i32
 }
-unsafe fn do_preamble(mut fp: *mut FILE) {
+unsafe fn do_preamble(handle: &mut InputHandleWrapper) {
     /* Check for id byte */
-    if fgetc(fp) == 89i32 {
+    if ttstub_input_getc(handle) == 89i32 {
         /* Skip comment */
-        skip_bytes(get_unsigned_byte(fp) as u32, fp);
+        tt_skip_bytes(tt_get_unsigned_byte(handle) as u32, handle);
         /* Skip other header info.  It's normally used for verifying this
         is the file wethink it is */
-        skip_bytes(16_u32, fp);
+        tt_skip_bytes(16_u32, handle);
     } else {
         panic!("embed_pk_font: PK ID byte is incorrect.  Are you sure this is a PK file?");
     };
 }
-unsafe fn read_pk_char_header(mut h: *mut pk_header_, mut opcode: u8, mut fp: *mut FILE) -> i32 {
+unsafe fn read_pk_char_header(
+    mut h: *mut pk_header_,
+    mut opcode: u8,
+    handle: &mut InputHandleWrapper,
+) -> i32 {
     assert!(!h.is_null());
     if opcode as i32 & 4i32 == 0i32 {
         /* short */
-        (*h).pkt_len = ((opcode as i32 & 3i32) << 8i32 | get_unsigned_byte(fp) as i32) as u32; /* TFM width */
-        (*h).chrcode = get_unsigned_byte(fp) as i32; /* horizontal escapement */
-        (*h).wd = get_unsigned_triple(fp) as i32; /* extended short */
-        (*h).dx = (get_unsigned_byte(fp) as i32) << 16i32;
+        (*h).pkt_len = ((opcode as i32 & 3i32) << 8i32 | tt_get_unsigned_byte(handle) as i32) as u32; /* TFM width */
+        (*h).chrcode = tt_get_unsigned_byte(handle) as i32; /* horizontal escapement */
+        (*h).wd = tt_get_unsigned_triple(handle) as i32; /* extended short */
+        (*h).dx = (tt_get_unsigned_byte(handle) as i32) << 16i32;
         (*h).dy = 0i32;
-        (*h).bm_wd = get_unsigned_byte(fp) as u32;
-        (*h).bm_ht = get_unsigned_byte(fp) as u32;
-        (*h).bm_hoff = get_signed_byte(fp) as i32;
-        (*h).bm_voff = get_signed_byte(fp) as i32;
+        (*h).bm_wd = tt_get_unsigned_byte(handle) as u32;
+        (*h).bm_ht = tt_get_unsigned_byte(handle) as u32;
+        (*h).bm_hoff = tt_get_signed_byte(handle) as i32;
+        (*h).bm_voff = tt_get_signed_byte(handle) as i32;
         (*h).pkt_len = ((*h).pkt_len as u32).wrapping_sub(8_u32) as u32 as u32
     } else if opcode as i32 & 7i32 == 7i32 {
         /* long */
-        (*h).pkt_len = get_positive_quad(
-            fp,
+        (*h).pkt_len = tt_get_positive_quad(
+            handle,
             b"PK\x00" as *const u8 as *const i8,
             b"pkt_len\x00" as *const u8 as *const i8,
         ); /* 16.16 fixed point number in pixels */
-        (*h).chrcode = get_signed_quad(fp);
-        (*h).wd = get_signed_quad(fp);
-        (*h).dx = get_signed_quad(fp);
-        (*h).dy = get_signed_quad(fp);
-        (*h).bm_wd = get_positive_quad(
-            fp,
+        (*h).chrcode = tt_get_signed_quad(handle);
+        (*h).wd = tt_get_signed_quad(handle);
+        (*h).dx = tt_get_signed_quad(handle);
+        (*h).dy = tt_get_signed_quad(handle);
+        (*h).bm_wd = tt_get_positive_quad(
+            handle,
             b"PK\x00" as *const u8 as *const i8,
             b"bm_wd\x00" as *const u8 as *const i8,
         );
-        (*h).bm_ht = get_positive_quad(
-            fp,
+        (*h).bm_ht = tt_get_positive_quad(
+            handle,
             b"PK\x00" as *const u8 as *const i8,
             b"bm_ht\x00" as *const u8 as *const i8,
         );
-        (*h).bm_hoff = get_signed_quad(fp);
-        (*h).bm_voff = get_signed_quad(fp);
+        (*h).bm_hoff = tt_get_signed_quad(handle);
+        (*h).bm_voff = tt_get_signed_quad(handle);
         (*h).pkt_len = ((*h).pkt_len as u32).wrapping_sub(28_u32) as u32
     } else {
-        (*h).pkt_len = ((opcode as i32 & 3i32) << 16i32 | get_unsigned_pair(fp) as i32) as u32;
-        (*h).chrcode = get_unsigned_byte(fp) as i32;
-        (*h).wd = get_unsigned_triple(fp) as i32;
-        (*h).dx = (get_unsigned_pair(fp) as i32) << 16i32;
+        (*h).pkt_len = ((opcode as i32 & 3i32) << 16i32 | tt_get_unsigned_pair(handle) as i32) as u32;
+        (*h).chrcode = tt_get_unsigned_byte(handle) as i32;
+        (*h).wd = tt_get_unsigned_triple(handle) as i32;
+        (*h).dx = (tt_get_unsigned_pair(handle) as i32) << 16i32;
         (*h).dy = 0i32;
-        (*h).bm_wd = get_unsigned_pair(fp) as u32;
-        (*h).bm_ht = get_unsigned_pair(fp) as u32;
-        (*h).bm_hoff = get_signed_pair(fp) as i32;
-        (*h).bm_voff = get_signed_pair(fp) as i32;
+        (*h).bm_wd = tt_get_unsigned_pair(handle) as u32;
+        (*h).bm_ht = tt_get_unsigned_pair(handle) as u32;
+        (*h).bm_hoff = tt_get_signed_pair(handle) as i32;
+        (*h).bm_voff = tt_get_signed_pair(handle) as i32;
         (*h).pkt_len = ((*h).pkt_len as u32).wrapping_sub(13_u32) as u32
     }
     (*h).dyn_f = opcode as i32 / 16i32;
@@ -582,6 +625,70 @@ unsafe fn create_pk_CharProc_stream(
     }
     stream
 }
+/* Adds the CharProc for a single glyph, and its width and extent. */
+unsafe fn add_pk_CharProc(
+    mut font: *mut pdf_font,
+    mut charprocs: *mut pdf_obj,
+    mut pkh: *mut pk_header_,
+    mut pkt_ptr: *mut u8,
+    mut pix2charu: f64,
+    widths: &mut [f64; 256],
+    bbox: &mut Rect,
+) {
+    let ident = pdf_font_get_ident(font);
+    let encoding_id = pdf_font_get_encoding(font);
+    let enc_vec = if encoding_id < 0i32 {
+        0 as *mut *mut i8
+    } else {
+        pdf_encoding_get_encoding(encoding_id)
+    };
+    let mut charname;
+    /* Charwidth in PDF units */
+    let charwidth =
+        (1000.0f64 * (*pkh).wd as f64 / ((1i32 << 20i32) as f64 * pix2charu) / 0.1f64 + 0.5f64)
+            .floor()
+            * 0.1f64;
+    widths[((*pkh).chrcode & 0xffi32) as usize] = charwidth;
+    /* Update font BBox info */
+    bbox.ll.x = bbox.ll.x.min(-(*pkh).bm_hoff as f64);
+    bbox.ll.y = bbox.ll.y.min((*pkh).bm_voff as f64 - (*pkh).bm_ht as f64);
+    bbox.ur.x = bbox.ur.x.max((*pkh).bm_wd as f64 - (*pkh).bm_hoff as f64);
+    bbox.ur.y = bbox.ur.y.max((*pkh).bm_voff as f64);
+    let charproc = create_pk_CharProc_stream(pkh, charwidth, pkt_ptr, (*pkh).pkt_len);
+    if charproc.is_null() {
+        panic!("Unpacking PK character data failed.");
+    }
+    if encoding_id >= 0i32 && !enc_vec.is_null() {
+        charname = *enc_vec.offset(((*pkh).chrcode & 0xffi32) as isize);
+        if charname.is_null() {
+            warn!(
+                "\".notdef\" glyph used in font (code=0x{:02x}): {}",
+                (*pkh).chrcode,
+                CStr::from_ptr(ident).display(),
+            );
+            charname = work_buffer.as_mut_ptr() as *mut i8;
+            sprintf(
+                charname,
+                b"x%02X\x00" as *const u8 as *const i8,
+                (*pkh).chrcode as u8 as i32,
+            );
+        }
+    } else {
+        /* ENABLE_GLYPHENC */
+        charname = work_buffer.as_mut_ptr() as *mut i8; /* _FIXME_ */
+        sprintf(
+            charname,
+            b"x%02X\x00" as *const u8 as *const i8,
+            (*pkh).chrcode as u8 as i32,
+        );
+    }
+    pdf_add_dict(
+        &mut *charprocs,
+        CStr::from_ptr(charname).to_bytes(),
+        pdf_ref_obj(charproc),
+    );
+    pdf_release_obj(charproc);
+}
 #[no_mangle]
 pub unsafe extern "C" fn pdf_font_load_pkfont(mut font: *mut pdf_font) -> i32 {
     let mut widths: [f64; 256] = [0.; 256];
@@ -602,14 +709,6 @@ pub unsafe extern "C" fn pdf_font_load_pkfont(mut font: *mut pdf_font) -> i32 {
     /* ENABLE_GLYPHENC */
     assert!(!ident.is_null() && !usedchars.is_null() && point_size > 0.0f64);
     let dpi = truedpi(ident, point_size, base_dpi);
-    let fp = dpx_open_pk_font_at(ident, dpi);
-    if fp.is_null() {
-        panic!(
-            "Could not find/open PK font file: {} (at {}dpi)",
-            CStr::from_ptr(ident).display(),
-            dpi,
-        );
-    }
     memset(charavail.as_mut_ptr() as *mut libc::c_void, 0i32, 256);
     let charprocs = pdf_new_dict();
     /* Include bitmap as 72dpi image:
@@ -621,123 +720,151 @@ pub unsafe extern "C" fn pdf_font_load_pkfont(mut font: *mut pdf_font) -> i32 {
         (core::f64::INFINITY, core::f64::INFINITY),
         (core::f64::NEG_INFINITY, core::f64::NEG_INFINITY)
     );
-    loop {
-        let opcode = fgetc(fp);
-        if !(opcode >= 0i32 && opcode != 245i32) {
-            break;
-        }
-        if opcode < 240i32 {
-            let mut pkh: pk_header_ = pk_header_ {
-                pkt_len: 0,
-                chrcode: 0,
-                wd: 0,
-                dx: 0,
-                dy: 0,
-                bm_wd: 0,
-                bm_ht: 0,
-                bm_hoff: 0,
-                bm_voff: 0,
-                dyn_f: 0,
-                run_color: 0,
-            };
-            let error = read_pk_char_header(&mut pkh, opcode as u8, fp);
-            if error != 0 {
-                panic!("Error in reading PK character header.");
-            } else {
-                if charavail[(pkh.chrcode & 0xffi32) as usize] != 0 {
-                    warn!(
-                        "More than two bitmap image for single glyph?: font=\"{}\" code=0x{:02x}",
-                        CStr::from_ptr(ident).display(),
-                        pkh.chrcode,
-                    );
-                }
+    if let Some(mut handle) = dpx_open_pk_font_at(ident, dpi) {
+        loop {
+            let opcode = ttstub_input_getc(&mut handle);
+            if !(opcode >= 0i32 && opcode != 245i32) {
+                break;
             }
-            if *usedchars.offset((pkh.chrcode & 0xffi32) as isize) == 0 {
-                skip_bytes(pkh.pkt_len, fp);
-            } else {
-                let mut charname;
-                /* Charwidth in PDF units */
-                let charwidth =
-                    (1000.0f64 * pkh.wd as f64 / ((1i32 << 20i32) as f64 * pix2charu) / 0.1f64
-                        + 0.5f64)
-                        .floor()
-                        * 0.1f64;
-                widths[(pkh.chrcode & 0xffi32) as usize] = charwidth;
-                /* Update font BBox info */
-                bbox.ll.x = bbox.ll.x.min(-pkh.bm_hoff as f64);
-                bbox.ll.y = bbox.ll.y.min(pkh.bm_voff as f64 - pkh.bm_ht as f64);
-                bbox.ur.x = bbox.ur.x.max(pkh.bm_wd as f64 - pkh.bm_hoff as f64);
-                bbox.ur.y = bbox.ur.y.max(pkh.bm_voff as f64);
-                let pkt_ptr = new((pkh.pkt_len as u64)
-                    .wrapping_mul(::std::mem::size_of::<u8>() as u64)
-                    as u32) as *mut u8;
-                let bytesread =
-                    fread(pkt_ptr as *mut libc::c_void, 1, pkh.pkt_len as _, fp) as size_t;
-                if bytesread != pkh.pkt_len as u64 {
-                    panic!(
-                        "Only {} bytes PK packet read. (expected {} bytes)",
-                        bytesread, pkh.pkt_len,
-                    );
-                }
-                let charproc =
-                    create_pk_CharProc_stream(&mut pkh, charwidth, pkt_ptr, bytesread as u32);
-                free(pkt_ptr as *mut libc::c_void);
-                if charproc.is_null() {
-                    panic!("Unpacking PK character data failed.");
-                }
-                if encoding_id >= 0i32 && !enc_vec.is_null() {
-                    charname = *enc_vec.offset((pkh.chrcode & 0xffi32) as isize);
-                    if charname.is_null() {
+            if opcode < 240i32 {
+                let mut pkh: pk_header_ = pk_header_ {
+                    pkt_len: 0,
+                    chrcode: 0,
+                    wd: 0,
+                    dx: 0,
+                    dy: 0,
+                    bm_wd: 0,
+                    bm_ht: 0,
+                    bm_hoff: 0,
+                    bm_voff: 0,
+                    dyn_f: 0,
+                    run_color: 0,
+                };
+                let error = read_pk_char_header(&mut pkh, opcode as u8, &mut handle);
+                if error != 0 {
+                    panic!("Error in reading PK character header.");
+                } else {
+                    if charavail[(pkh.chrcode & 0xffi32) as usize] != 0 {
                         warn!(
-                            "\".notdef\" glyph used in font (code=0x{:02x}): {}",
-                            pkh.chrcode,
+                            "More than two bitmap image for single glyph?: font=\"{}\" code=0x{:02x}",
                             CStr::from_ptr(ident).display(),
-                        );
-                        charname = work_buffer.as_mut_ptr() as *mut i8;
-                        sprintf(
-                            charname,
-                            b"x%02X\x00" as *const u8 as *const i8,
-                            pkh.chrcode as u8 as i32,
+                            pkh.chrcode,
                         );
                     }
+                }
+                if *usedchars.offset((pkh.chrcode & 0xffi32) as isize) == 0 {
+                    tt_skip_bytes(pkh.pkt_len, &mut handle);
                 } else {
-                    /* ENABLE_GLYPHENC */
-                    charname = work_buffer.as_mut_ptr() as *mut i8; /* _FIXME_ */
-                    sprintf(
-                        charname,
-                        b"x%02X\x00" as *const u8 as *const i8,
-                        pkh.chrcode as u8 as i32,
+                    let pkt_ptr = new((pkh.pkt_len as u64)
+                        .wrapping_mul(::std::mem::size_of::<u8>() as u64)
+                        as u32) as *mut u8;
+                    let bytesread = ttstub_input_read(
+                        handle.0.as_ptr(),
+                        pkt_ptr as *mut i8,
+                        pkh.pkt_len as size_t,
                     );
-                }
-                pdf_add_dict(
-                    &mut *charprocs,
-                    CStr::from_ptr(charname).to_bytes(),
-                    pdf_ref_obj(charproc),
-                );
-                pdf_release_obj(charproc);
-            }
-            charavail[(pkh.chrcode & 0xffi32) as usize] = 1_i8
-        } else {
-            match opcode {
-                240 | 241 | 242 | 243 => {
-                    let mut len: i32 = get_unsigned_num(fp, (opcode - 240i32) as u8) as i32;
-                    if len < 0i32 {
-                        warn!("PK: Special with {} bytes???", len);
-                    } else {
-                        skip_bytes(len as u32, fp);
+                    if bytesread != pkh.pkt_len as i64 {
+                        panic!(
+                            "Only {} bytes PK packet read. (expected {} bytes)",
+                            bytesread, pkh.pkt_len,
+                        );
                     }
+                    add_pk_CharProc(
+                        font,
+                        charprocs,
+                        &mut pkh,
+                        pkt_ptr,
+                        pix2charu,
+                        &mut widths,
+                        &mut bbox,
+                    );
+                    free(pkt_ptr as *mut libc::c_void);
                 }
-                244 => {
-                    skip_bytes(4_u32, fp);
-                }
-                247 => {
-                    do_preamble(fp);
+                charavail[(pkh.chrcode & 0xffi32) as usize] = 1_i8
+            } else {
+                match opcode {
+                    240 | 241 | 242 | 243 => {
+                        let mut len: i32 =
+                            tt_get_unsigned_num(&mut handle, (opcode - 240i32) as u8) as i32;
+                        if len < 0i32 {
+                            warn!("PK: Special with {} bytes???", len);
+                        } else {
+                            tt_skip_bytes(len as u32, &mut handle);
+                        }
+                    }
+                    244 => {
+                        tt_skip_bytes(4_u32, &mut handle);
+                    }
+                    247 => {
+                        do_preamble(&mut handle);
+                    }
+                    246 | _ => {}
                 }
-                246 | _ => {}
             }
         }
+        ttstub_input_close(handle);
+    } else if let Some(outline) = dpx_open_outline_font(font) {
+        if pdf_font_get_verbose() > 0 {
+            info!(
+                "(PK font \"{}\" rendered from outlines at {}dpi)",
+                CStr::from_ptr(ident).display(),
+                dpi,
+            );
+        }
+        /* One pixel is 72/base_dpi bp, as in the FontMatrix below. */
+        let ppem = point_size * base_dpi as f64 / 72.0f64;
+        let tfm_id = tfm_open(ident, 0i32);
+        for code in 0..256 {
+            if *usedchars.offset(code as isize) == 0 {
+                continue;
+            }
+            let glyph = if encoding_id >= 0i32
+                && !enc_vec.is_null()
+                && !(*enc_vec.offset(code as isize)).is_null()
+            {
+                Some(CStr::from_ptr(*enc_vec.offset(code as isize)).to_bytes())
+            } else {
+                outline.builtin_glyph_name(code as u8)
+            };
+            let mut bitmap = match glyph.and_then(|name| outline.render(name, ppem)) {
+                Some(bitmap) => bitmap,
+                None => continue,
+            };
+            let mut pkh = pk_header_ {
+                pkt_len: bitmap.data.len() as u32,
+                chrcode: code,
+                wd: if tfm_id >= 0i32 {
+                    tfm_get_fw_width(tfm_id, code)
+                } else {
+                    0i32
+                },
+                dx: 0,
+                dy: 0,
+                bm_wd: bitmap.width,
+                bm_ht: bitmap.height,
+                bm_hoff: bitmap.hoff,
+                bm_voff: bitmap.voff,
+                dyn_f: 14i32,
+                run_color: 0i32,
+            };
+            add_pk_CharProc(
+                font,
+                charprocs,
+                &mut pkh,
+                bitmap.data.as_mut_ptr(),
+                pix2charu,
+                &mut widths,
+                &mut bbox,
+            );
+            charavail[code as usize] = 1_i8
+        }
+    } else {
+        panic!(
+            "Could not find/open PK font file: {} (at {}dpi)",
+            CStr::from_ptr(ident).display(),
+            dpi,
+        );
     }
-    fclose(fp);
     /* Check if we really got all glyphs needed. */
     for code in 0..256 {
         if *usedchars.offset(code as isize) as i32 != 0 && charavail[code as usize] == 0 {
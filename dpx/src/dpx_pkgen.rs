@@ -0,0 +1,829 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2002-2016 by Jin-Hwan Cho and Shunsaku Hirata,
+    the dvipdfmx project team.
+
+    Copyright (C) 1998, 1999 by Mark A. Wicks <mwicks@kettering.edu>
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! Bitmap glyphs rendered from outline fonts, standing in for a PK font that
+//! Metafont would otherwise have had to generate.
+//!
+//! Type 1 charstrings are first converted to Type 2, so that Type 1 fonts and
+//! CFF-flavoured OpenType fonts share a single charstring interpreter. Glyphs
+//! are filled with the nonzero winding rule and 4x4 supersampling, and a pixel
+//! is black when at least half of it is covered.
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::io::{Seek, SeekFrom};
+
+use super::dpx_cff::{
+    cff_charsets_lookup_inverse, cff_close, cff_encoding_lookup, cff_font, cff_get_index,
+    cff_get_string, cff_index, cff_open, cff_read_charsets, cff_read_encoding, cff_read_private,
+    cff_read_subrs, cff_release_index,
+};
+use super::dpx_cff_dict::{cff_dict_get, cff_dict_known};
+use super::dpx_dpxfile::{dpx_open_opentype_file, dpx_open_type1_file};
+use super::dpx_mem::new;
+use super::dpx_sfnt::{sfnt_close, sfnt_find_table_pos, sfnt_open, sfnt_read_table_directory};
+use super::dpx_t1_char::{t1_ginfo, t1char_convert_charstring};
+use super::dpx_t1_load::{t1_get_standard_glyph, t1_load_font};
+use libc::free;
+
+/// Samples per pixel along each axis.
+const SUBSAMPLES: usize = 4;
+/// Nesting limit for subroutine calls, from the Type 2 charstring spec.
+const MAX_SUBR_NESTING: usize = 10;
+
+/// A glyph bitmap in the layout of a PK character with `dyn_f = 14`: rows
+/// are not padded to byte boundaries, and a set bit is a black pixel.
+pub struct GlyphBitmap {
+    pub width: u32,
+    pub height: u32,
+    /// Columns from the left edge of the bitmap to the reference point.
+    pub hoff: i32,
+    /// Rows from the top edge of the bitmap to the reference point.
+    pub voff: i32,
+    pub data: Vec<u8>,
+}
+
+pub struct OutlineFont {
+    charstrings: Vec<Vec<u8>>,
+    gsubrs: Vec<Vec<u8>>,
+    subrs: Vec<Vec<u8>>,
+    glyphs: HashMap<Vec<u8>, usize>,
+    encoding: Vec<Option<Vec<u8>>>,
+    units_per_em: f64,
+}
+
+unsafe fn index_items(idx: *const cff_index) -> Vec<Vec<u8>> {
+    if idx.is_null() || (*idx).count == 0 {
+        return Vec::new();
+    }
+    let idx = &*idx;
+    (0..idx.count as isize)
+        .map(|i| {
+            let start = *idx.offset.offset(i) as usize - 1;
+            let end = *idx.offset.offset(i + 1) as usize - 1;
+            std::slice::from_raw_parts(idx.data.add(start), end - start).to_vec()
+        })
+        .collect()
+}
+
+unsafe fn c_name(name: *const i8) -> Option<Vec<u8>> {
+    if name.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(name).to_bytes().to_vec())
+    }
+}
+
+unsafe fn glyph_names(cffont: &cff_font, num_glyphs: usize) -> HashMap<Vec<u8>, usize> {
+    let mut glyphs = HashMap::new();
+    for gid in 0..num_glyphs {
+        let name = cff_get_string(cffont, cff_charsets_lookup_inverse(cffont, gid as u16));
+        if let Some(name) = c_name(name) {
+            glyphs.entry(name).or_insert(gid);
+        }
+        free(name as *mut libc::c_void);
+    }
+    glyphs
+}
+
+unsafe fn units_per_em(cffont: &cff_font) -> f64 {
+    let key = b"FontMatrix\x00" as *const u8 as *const i8;
+    if cff_dict_known(cffont.topdict, key) != 0 {
+        let scale = cff_dict_get(cffont.topdict, key, 0);
+        if scale > 0. {
+            return 1. / scale;
+        }
+    }
+    1000.
+}
+
+impl OutlineFont {
+    /// Looks for a Type 1 or a CFF-flavoured OpenType font called `name`.
+    pub unsafe fn open(name: *const i8) -> Option<OutlineFont> {
+        OutlineFont::open_type1(name).or_else(|| OutlineFont::open_opentype(name))
+    }
+
+    unsafe fn open_type1(name: *const i8) -> Option<OutlineFont> {
+        let handle = dpx_open_type1_file(name)?;
+        let enc_vec = new((256 * std::mem::size_of::<*mut i8>()) as u32) as *mut *mut i8;
+        for code in 0..256 {
+            *enc_vec.offset(code) = 0 as *mut i8;
+        }
+        let cffont = t1_load_font(enc_vec, 0, handle);
+        let mut encoding = Vec::with_capacity(256);
+        for code in 0..256 {
+            let name = *enc_vec.offset(code);
+            encoding.push(c_name(name));
+            free(name as *mut libc::c_void);
+        }
+        free(enc_vec as *mut libc::c_void);
+        if cffont.is_null() {
+            return None;
+        }
+        let cffont = &mut *cffont;
+        let subrs = *cffont.subrs.offset(0);
+        let num_glyphs = (*cffont.cstrings).count as usize;
+        let mut buffer = vec![0u8; 65536];
+        let mut ginfo = t1_ginfo::new();
+        let charstrings = index_items(cffont.cstrings)
+            .iter_mut()
+            .map(|src| {
+                let len = t1char_convert_charstring(
+                    buffer.as_mut_ptr(),
+                    buffer.len() as i32,
+                    src.as_mut_ptr(),
+                    src.len() as i32,
+                    subrs,
+                    0.,
+                    0.,
+                    &mut ginfo,
+                );
+                buffer[..len.max(0) as usize].to_vec()
+            })
+            .collect();
+        let font = OutlineFont {
+            charstrings,
+            gsubrs: Vec::new(),
+            subrs: Vec::new(),
+            glyphs: glyph_names(cffont, num_glyphs),
+            encoding,
+            units_per_em: units_per_em(cffont),
+        };
+        cff_close(cffont);
+        Some(font)
+    }
+
+    unsafe fn open_opentype(name: *const i8) -> Option<OutlineFont> {
+        let handle = dpx_open_opentype_file(name)?;
+        let sfont = sfnt_open(handle);
+        if sfont.is_null() {
+            return None;
+        }
+        let offset = if (*sfont).type_0 == 1 << 2 && sfnt_read_table_directory(sfont, 0) >= 0 {
+            sfnt_find_table_pos(sfont, b"CFF ")
+        } else {
+            0
+        };
+        let cffont = if offset > 0 {
+            cff_open(&mut (*sfont).handle, offset as i32, 0)
+        } else {
+            0 as *mut cff_font
+        };
+        if cffont.is_null() {
+            sfnt_close(sfont);
+            return None;
+        }
+        let cffont = &mut *cffont;
+        if cffont.flag & 1 << 0 == 0 {
+            cff_read_charsets(cffont);
+        }
+        /* CIDFonts, and fonts using a predefined charset */
+        if cffont.flag & (1 << 0 | 1 << 5 | 1 << 6 | 1 << 7) != 0 {
+            cff_close(cffont);
+            sfnt_close(sfont);
+            return None;
+        }
+        cff_read_encoding(cffont);
+        cff_read_private(cffont);
+        cff_read_subrs(cffont);
+        let offset = cff_dict_get(
+            cffont.topdict,
+            b"CharStrings\x00" as *const u8 as *const i8,
+            0,
+        ) as u64;
+        let start = cffont.offset as u64 + offset;
+        cffont
+            .handle
+            .as_mut()
+            .unwrap()
+            .seek(SeekFrom::Start(start))
+            .unwrap();
+        let cs_idx = cff_get_index(cffont);
+        let charstrings = index_items(cs_idx);
+        cff_release_index(cs_idx);
+        let mut encoding = Vec::with_capacity(256);
+        for code in 0..256 {
+            let name = if cffont.flag & 1 << 3 != 0 {
+                /* Predefined StandardEncoding */
+                c_name(t1_get_standard_glyph(code))
+            } else if cffont.flag & 1 << 4 != 0 || cffont.encoding.is_null() {
+                None
+            } else {
+                match cff_encoding_lookup(cffont, code as u8) {
+                    0 => None,
+                    gid => {
+                        let name = cff_get_string(cffont, cff_charsets_lookup_inverse(cffont, gid));
+                        let copy = c_name(name);
+                        free(name as *mut libc::c_void);
+                        copy
+                    }
+                }
+            };
+            encoding.push(name);
+        }
+        let font = OutlineFont {
+            glyphs: glyph_names(cffont, charstrings.len()),
+            charstrings,
+            gsubrs: index_items(cffont.gsubr),
+            subrs: if cffont.subrs.is_null() {
+                Vec::new()
+            } else {
+                index_items(*cffont.subrs.offset(0))
+            },
+            encoding,
+            units_per_em: units_per_em(cffont),
+        };
+        cff_close(cffont);
+        sfnt_close(sfont);
+        Some(font)
+    }
+
+    /// The glyph that the font's built-in encoding assigns to `code`.
+    pub fn builtin_glyph_name(&self, code: u8) -> Option<&[u8]> {
+        self.encoding[code as usize].as_ref().map(|n| &n[..])
+    }
+
+    /// Renders the glyph called `name` with `ppem` pixels to the em, or
+    /// returns `None` if the font has no such glyph.
+    pub fn render(&self, name: &[u8], ppem: f64) -> Option<GlyphBitmap> {
+        let gid = *self.glyphs.get(name)?;
+        let mut path = Path::new(ppem / self.units_per_em);
+        self.outline(gid, 0., 0., &mut path, true);
+        path.close();
+        Some(rasterize(&path.contours))
+    }
+
+    fn outline(&self, gid: usize, dx: f64, dy: f64, path: &mut Path, allow_seac: bool) {
+        let mut cs = CharString::new(self, dx, dy);
+        if cs.run(&self.charstrings[gid], path, 0).is_err() {
+            return;
+        }
+        if let (Some([adx, ady, bchar, achar]), true) = (cs.seac, allow_seac) {
+            for &(code, x, y) in &[(bchar, dx, dy), (achar, dx + adx, dy + ady)] {
+                let name = unsafe { c_name(t1_get_standard_glyph(code as i32)) };
+                if let Some(&gid) = name.and_then(|n| self.glyphs.get(&n)) {
+                    path.close();
+                    self.outline(gid, x, y, path, false);
+                }
+            }
+        }
+    }
+}
+
+/// Flattened outline contours, in pixels.
+struct Path {
+    scale: f64,
+    contours: Vec<Vec<(f64, f64)>>,
+    current: Vec<(f64, f64)>,
+}
+
+impl Path {
+    fn new(scale: f64) -> Path {
+        Path {
+            scale,
+            contours: Vec::new(),
+            current: Vec::new(),
+        }
+    }
+
+    fn close(&mut self) {
+        if self.current.len() > 2 {
+            self.contours
+                .push(std::mem::replace(&mut self.current, Vec::new()));
+        } else {
+            self.current.clear();
+        }
+    }
+
+    fn move_to(&mut self, x: f64, y: f64) {
+        self.close();
+        self.current.push((x * self.scale, y * self.scale));
+    }
+
+    fn line_to(&mut self, x: f64, y: f64) {
+        self.current.push((x * self.scale, y * self.scale));
+    }
+
+    fn curve_to(&mut self, p0: (f64, f64), p1: (f64, f64), p2: (f64, f64), p3: (f64, f64)) {
+        let s = self.scale;
+        let (p0, p1, p2, p3) = (
+            (p0.0 * s, p0.1 * s),
+            (p1.0 * s, p1.1 * s),
+            (p2.0 * s, p2.1 * s),
+            (p3.0 * s, p3.1 * s),
+        );
+        let dist = |a: (f64, f64), b: (f64, f64)| (a.0 - b.0).hypot(a.1 - b.1);
+        let length = dist(p0, p1) + dist(p1, p2) + dist(p2, p3);
+        let steps = (length * 2.).ceil().max(1.).min(256.) as usize;
+        for i in 1..=steps {
+            let t = i as f64 / steps as f64;
+            let u = 1. - t;
+            let (a, b, c, d) = (u * u * u, 3. * u * u * t, 3. * u * t * t, t * t * t);
+            self.current.push((
+                a * p0.0 + b * p1.0 + c * p2.0 + d * p3.0,
+                a * p0.1 + b * p1.1 + c * p2.1 + d * p3.1,
+            ));
+        }
+    }
+}
+
+/// Type 2 charstring interpreter state for a single glyph.
+struct CharString<'a> {
+    font: &'a OutlineFont,
+    stack: Vec<f64>,
+    x: f64,
+    y: f64,
+    num_stems: usize,
+    seen_width: bool,
+    seac: Option<[f64; 4]>,
+}
+
+fn subr_bias(count: usize) -> i64 {
+    if count < 1240 {
+        107
+    } else if count < 33900 {
+        1131
+    } else {
+        32768
+    }
+}
+
+impl<'a> CharString<'a> {
+    fn new(font: &'a OutlineFont, x: f64, y: f64) -> CharString<'a> {
+        CharString {
+            font,
+            stack: Vec::new(),
+            x,
+            y,
+            num_stems: 0,
+            seen_width: false,
+            seac: None,
+        }
+    }
+
+    /// Drops the optional advance width in front of the first stack-clearing
+    /// operator, which is there when the argument count is off by one.
+    fn take_width(&mut self, has_extra: bool) {
+        if !self.seen_width && has_extra && !self.stack.is_empty() {
+            self.stack.remove(0);
+        }
+        self.seen_width = true;
+    }
+
+    fn line(&mut self, dx: f64, dy: f64, path: &mut Path) {
+        self.x += dx;
+        self.y += dy;
+        path.line_to(self.x, self.y);
+    }
+
+    fn curve(&mut self, d: [f64; 6], path: &mut Path) {
+        let p0 = (self.x, self.y);
+        let p1 = (p0.0 + d[0], p0.1 + d[1]);
+        let p2 = (p1.0 + d[2], p1.1 + d[3]);
+        let p3 = (p2.0 + d[4], p2.1 + d[5]);
+        path.curve_to(p0, p1, p2, p3);
+        self.x = p3.0;
+        self.y = p3.1;
+    }
+
+    /// Alternating horizontal/vertical curves of `vhcurveto`/`hvcurveto`.
+    fn hv_curves(&mut self, mut horizontal: bool, path: &mut Path) {
+        let args = std::mem::replace(&mut self.stack, Vec::new());
+        let mut i = 0;
+        while i + 4 <= args.len() {
+            let last = if args.len() - i == 5 { args[i + 4] } else { 0. };
+            let d = if horizontal {
+                [args[i], 0., args[i + 1], args[i + 2], last, args[i + 3]]
+            } else {
+                [0., args[i], args[i + 1], args[i + 2], args[i + 3], last]
+            };
+            self.curve(d, path);
+            horizontal = !horizontal;
+            i += 4;
+        }
+    }
+
+    /// Returns `Ok(true)` at `endchar` and `Ok(false)` at `return`.
+    fn run(&mut self, cs: &[u8], path: &mut Path, depth: usize) -> Result<bool, ()> {
+        if depth > MAX_SUBR_NESTING {
+            return Err(());
+        }
+        let mut i = 0;
+        while i < cs.len() {
+            let b0 = cs[i];
+            i += 1;
+            match b0 {
+                32..=246 => self.stack.push(b0 as f64 - 139.),
+                247..=250 => {
+                    let b1 = *cs.get(i).ok_or(())? as f64;
+                    i += 1;
+                    self.stack.push((b0 as f64 - 247.) * 256. + b1 + 108.);
+                }
+                251..=254 => {
+                    let b1 = *cs.get(i).ok_or(())? as f64;
+                    i += 1;
+                    self.stack.push(-(b0 as f64 - 251.) * 256. - b1 - 108.);
+                }
+                28 => {
+                    let b = cs.get(i..i + 2).ok_or(())?;
+                    i += 2;
+                    self.stack.push(i16::from_be_bytes([b[0], b[1]]) as f64);
+                }
+                255 => {
+                    let b = cs.get(i..i + 4).ok_or(())?;
+                    i += 4;
+                    let v = i32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+                    self.stack.push(v as f64 / 65536.);
+                }
+                /* hstem, vstem, hstemhm, vstemhm */
+                1 | 3 | 18 | 23 => {
+                    self.take_width(self.stack.len() % 2 == 1);
+                    self.num_stems += self.stack.len() / 2;
+                    self.stack.clear();
+                }
+                /* hintmask, cntrmask */
+                19 | 20 => {
+                    self.take_width(self.stack.len() % 2 == 1);
+                    self.num_stems += self.stack.len() / 2;
+                    self.stack.clear();
+                    i += (self.num_stems + 7) / 8;
+                }
+                /* rmoveto */
+                21 => {
+                    self.take_width(self.stack.len() > 2);
+                    if self.stack.len() < 2 {
+                        return Err(());
+                    }
+                    self.x += self.stack[0];
+                    self.y += self.stack[1];
+                    path.move_to(self.x, self.y);
+                    self.stack.clear();
+                }
+                /* hmoveto, vmoveto */
+                22 | 4 => {
+                    self.take_width(self.stack.len() > 1);
+                    let d = *self.stack.first().ok_or(())?;
+                    if b0 == 22 {
+                        self.x += d;
+                    } else {
+                        self.y += d;
+                    }
+                    path.move_to(self.x, self.y);
+                    self.stack.clear();
+                }
+                /* rlineto */
+                5 => {
+                    let args = std::mem::replace(&mut self.stack, Vec::new());
+                    for d in args.chunks(2).filter(|d| d.len() == 2) {
+                        self.line(d[0], d[1], path);
+                    }
+                }
+                /* hlineto, vlineto */
+                6 | 7 => {
+                    let args = std::mem::replace(&mut self.stack, Vec::new());
+                    for (n, &d) in args.iter().enumerate() {
+                        if (n % 2 == 0) == (b0 == 6) {
+                            self.line(d, 0., path);
+                        } else {
+                            self.line(0., d, path);
+                        }
+                    }
+                }
+                /* rrcurveto */
+                8 => {
+                    let args = std::mem::replace(&mut self.stack, Vec::new());
+                    for d in args.chunks(6).filter(|d| d.len() == 6) {
+                        self.curve([d[0], d[1], d[2], d[3], d[4], d[5]], path);
+                    }
+                }
+                /* rcurveline */
+                24 => {
+                    let args = std::mem::replace(&mut self.stack, Vec::new());
+                    let mut n = 0;
+                    while args.len() - n >= 8 {
+                        let d = &args[n..n + 6];
+                        self.curve([d[0], d[1], d[2], d[3], d[4], d[5]], path);
+                        n += 6;
+                    }
+                    if args.len() - n == 2 {
+                        self.line(args[n], args[n + 1], path);
+                    }
+                }
+                /* rlinecurve */
+                25 => {
+                    let args = std::mem::replace(&mut self.stack, Vec::new());
+                    let mut n = 0;
+                    while args.len() - n >= 8 {
+                        self.line(args[n], args[n + 1], path);
+                        n += 2;
+                    }
+                    if args.len() - n == 6 {
+                        let d = &args[n..];
+                        self.curve([d[0], d[1], d[2], d[3], d[4], d[5]], path);
+                    }
+                }
+                /* vvcurveto, hhcurveto */
+                26 | 27 => {
+                    let args = std::mem::replace(&mut self.stack, Vec::new());
+                    let (mut first, mut n) = if args.len() % 2 == 1 {
+                        (args[0], 1)
+                    } else {
+                        (0., 0)
+                    };
+                    while n + 4 <= args.len() {
+                        let d = &args[n..n + 4];
+                        if b0 == 26 {
+                            self.curve([first, d[0], d[1], d[2], 0., d[3]], path);
+                        } else {
+                            self.curve([d[0], first, d[1], d[2], d[3], 0.], path);
+                        }
+                        first = 0.;
+                        n += 4;
+                    }
+                }
+                30 => self.hv_curves(false, path),
+                31 => self.hv_curves(true, path),
+                /* callsubr, callgsubr */
+                10 | 29 => {
+                    let subrs = if b0 == 10 {
+                        &self.font.subrs
+                    } else {
+                        &self.font.gsubrs
+                    };
+                    let n = self.stack.pop().ok_or(())? as i64 + subr_bias(subrs.len());
+                    let subr = subrs.get(n as usize).filter(|_| n >= 0).ok_or(())?;
+                    if self.run(subr, path, depth + 1)? {
+                        return Ok(true);
+                    }
+                }
+                /* return */
+                11 => return Ok(false),
+                /* endchar */
+                14 => {
+                    let len = self.stack.len();
+                    self.take_width(len == 1 || len == 5);
+                    if self.stack.len() == 4 {
+                        let s = &self.stack;
+                        self.seac = Some([s[0], s[1], s[2], s[3]]);
+                    }
+                    self.stack.clear();
+                    return Ok(true);
+                }
+                12 => {
+                    let b1 = *cs.get(i).ok_or(())?;
+                    i += 1;
+                    self.escape(b1, path);
+                }
+                _ => self.stack.clear(),
+            }
+        }
+        Ok(false)
+    }
+
+    fn escape(&mut self, op: u8, path: &mut Path) {
+        let s = std::mem::replace(&mut self.stack, Vec::new());
+        match op {
+            /* flex */
+            35 if s.len() >= 12 => {
+                self.curve([s[0], s[1], s[2], s[3], s[4], s[5]], path);
+                self.curve([s[6], s[7], s[8], s[9], s[10], s[11]], path);
+            }
+            /* hflex */
+            34 if s.len() >= 7 => {
+                self.curve([s[0], 0., s[1], s[2], s[3], 0.], path);
+                self.curve([s[4], 0., s[5], -s[2], s[6], 0.], path);
+            }
+            /* hflex1 */
+            36 if s.len() >= 9 => {
+                self.curve([s[0], s[1], s[2], s[3], s[4], 0.], path);
+                let dy6 = -(s[1] + s[3] + s[7]);
+                self.curve([s[5], 0., s[6], s[7], s[8], dy6], path);
+            }
+            /* flex1 */
+            37 if s.len() >= 11 => {
+                let dx = s[0] + s[2] + s[4] + s[6] + s[8];
+                let dy = s[1] + s[3] + s[5] + s[7] + s[9];
+                let (dx6, dy6) = if dx.abs() > dy.abs() {
+                    (s[10], -dy)
+                } else {
+                    (-dx, s[10])
+                };
+                self.curve([s[0], s[1], s[2], s[3], s[4], s[5]], path);
+                self.curve([s[6], s[7], s[8], s[9], dx6, dy6], path);
+            }
+            /* abs, add, sub, div, neg, mul, sqrt, dup, exch */
+            9 | 14 | 26 | 27 if !s.is_empty() => {
+                let mut s = s;
+                let a = s.pop().unwrap();
+                match op {
+                    9 => s.push(a.abs()),
+                    14 => s.push(-a),
+                    26 => s.push(a.abs().sqrt()),
+                    _ => s.extend_from_slice(&[a, a]),
+                }
+                self.stack = s;
+            }
+            10 | 11 | 12 | 24 | 28 if s.len() >= 2 => {
+                let mut s = s;
+                let b = s.pop().unwrap();
+                let a = s.pop().unwrap();
+                match op {
+                    10 => s.push(a + b),
+                    11 => s.push(a - b),
+                    12 if b != 0. => s.push(a / b),
+                    24 => s.push(a * b),
+                    28 => s.extend_from_slice(&[b, a]),
+                    _ => s.push(0.),
+                }
+                self.stack = s;
+            }
+            /* drop */
+            18 if !s.is_empty() => {
+                self.stack = s;
+                self.stack.pop();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Fills `contours` (in pixels, y upwards, reference point at the origin)
+/// with the nonzero winding rule.
+fn rasterize(contours: &[Vec<(f64, f64)>]) -> GlyphBitmap {
+    let empty = GlyphBitmap {
+        width: 0,
+        height: 0,
+        hoff: 0,
+        voff: 0,
+        data: Vec::new(),
+    };
+    let points = contours.iter().flatten();
+    let (mut xmin, mut ymin) = (std::f64::INFINITY, std::f64::INFINITY);
+    let (mut xmax, mut ymax) = (std::f64::NEG_INFINITY, std::f64::NEG_INFINITY);
+    for &(x, y) in points {
+        xmin = xmin.min(x);
+        xmax = xmax.max(x);
+        ymin = ymin.min(y);
+        ymax = ymax.max(y);
+    }
+    if !(xmin < xmax && ymin < ymax) {
+        return empty;
+    }
+    let (x0, y1) = (xmin.floor() as i32, ymax.ceil() as i32);
+    let width = (xmax.ceil() as i32 - x0) as usize;
+    let height = (y1 - ymin.floor() as i32) as usize;
+    let n = SUBSAMPLES as f64;
+    let mut coverage = vec![0u8; width * height];
+    let mut crossings: Vec<(f64, i32)> = Vec::new();
+    for sub in 0..height * SUBSAMPLES {
+        let ys = y1 as f64 - (sub as f64 + 0.5) / n;
+        crossings.clear();
+        for contour in contours {
+            for (k, &(ax, ay)) in contour.iter().enumerate() {
+                let (bx, by) = contour[(k + 1) % contour.len()];
+                if (ay <= ys) != (by <= ys) {
+                    let x = ax + (ys - ay) * (bx - ax) / (by - ay);
+                    crossings.push((x, if by > ay { 1 } else { -1 }));
+                }
+            }
+        }
+        crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        let row = &mut coverage[(sub / SUBSAMPLES) * width..][..width];
+        let mut winding = 0;
+        for pair in crossings.windows(2) {
+            winding += pair[0].1;
+            if winding == 0 {
+                continue;
+            }
+            /* Samples at x0 + (k + 0.5) / n with xa <= x < xb */
+            let first = ((pair[0].0 - x0 as f64) * n - 0.5).ceil().max(0.) as usize;
+            let last = ((pair[1].0 - x0 as f64) * n - 0.5).ceil().max(0.) as usize;
+            for k in first..last.min(width * SUBSAMPLES) {
+                row[k / SUBSAMPLES] += 1;
+            }
+        }
+    }
+    let black =
+        |r: usize, c: usize| 2 * coverage[r * width + c] as usize >= SUBSAMPLES * SUBSAMPLES;
+    let rows: Vec<usize> = (0..height)
+        .filter(|&r| (0..width).any(|c| black(r, c)))
+        .collect();
+    let cols: Vec<usize> = (0..width)
+        .filter(|&c| (0..height).any(|r| black(r, c)))
+        .collect();
+    let (top, bottom) = match (rows.first(), rows.last()) {
+        (Some(&t), Some(&b)) => (t, b + 1),
+        _ => return empty,
+    };
+    let (left, right) = (cols[0], cols[cols.len() - 1] + 1);
+    let (w, h) = (right - left, bottom - top);
+    let mut data = vec![0u8; (w * h + 7) / 8];
+    for r in 0..h {
+        for c in 0..w {
+            if black(top + r, left + c) {
+                let bit = r * w + c;
+                data[bit / 8] |= 0x80 >> (bit % 8);
+            }
+        }
+    }
+    GlyphBitmap {
+        width: w as u32,
+        height: h as u32,
+        hoff: -(x0 + left as i32),
+        voff: y1 - top as i32,
+        data,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn font(charstrings: Vec<Vec<u8>>) -> OutlineFont {
+        let mut glyphs = HashMap::new();
+        for i in 0..charstrings.len() {
+            glyphs.insert(format!("g{}", i).into_bytes(), i);
+        }
+        OutlineFont {
+            charstrings,
+            gsubrs: Vec::new(),
+            subrs: Vec::new(),
+            glyphs,
+            encoding: vec![None; 256],
+            units_per_em: 1000.,
+        }
+    }
+
+    fn num(v: i32) -> Vec<u8> {
+        let b = (v as i16).to_be_bytes();
+        vec![28, b[0], b[1]]
+    }
+
+    #[test]
+    fn square() {
+        /* width 500, then a 400x600 unit box at (100, -100) */
+        let mut cs = Vec::new();
+        for v in &[500, 100, -100] {
+            cs.extend(num(*v));
+        }
+        cs.push(21);
+        for v in &[400, 600, -400] {
+            cs.extend(num(*v));
+        }
+        cs.push(6);
+        cs.push(14);
+        let bitmap = font(vec![cs]).render(b"g0", 100.).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (40, 60));
+        assert_eq!((bitmap.hoff, bitmap.voff), (-10, 50));
+        assert!(bitmap.data.iter().all(|&b| b == 0xff));
+    }
+
+    #[test]
+    fn nonzero_winding() {
+        /* Two overlapping boxes drawn in the same direction, and a counter
+         * drawn the other way round. */
+        let rect =
+            |x0: f64, y0: f64, x1: f64, y1: f64| vec![(x0, y0), (x1, y0), (x1, y1), (x0, y1)];
+        let mut counter = rect(2., 2., 5., 8.);
+        counter.reverse();
+        let bitmap = rasterize(&[rect(0., 0., 10., 10.), rect(5., 0., 15., 10.), counter]);
+        assert_eq!((bitmap.width, bitmap.height), (15, 10));
+        assert_eq!((bitmap.hoff, bitmap.voff), (0, 10));
+        let pixel = |r: usize, c: usize| {
+            let bit = r * 15 + c;
+            bitmap.data[bit / 8] & (0x80 >> (bit % 8)) != 0
+        };
+        assert!(pixel(0, 0) && pixel(5, 7) && pixel(9, 14));
+        assert!(!pixel(5, 3));
+    }
+
+    #[test]
+    fn subroutines_and_missing_glyphs() {
+        /* -107 callsubr endchar, calling subr 0 through the bias */
+        let mut f = font(vec![vec![32, 10, 14]]);
+        /* 0 0 rmoveto 10 0 0 10 -10 0 rlineto return */
+        f.subrs = vec![vec![139, 139, 21, 149, 139, 139, 149, 129, 139, 5, 11]];
+        let bitmap = f.render(b"g0", 1000.).unwrap();
+        assert_eq!((bitmap.width, bitmap.height), (10, 10));
+        assert!(f.render(b"missing", 1000.).is_none());
+    }
+}
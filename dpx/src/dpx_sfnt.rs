@@ -246,6 +246,12 @@ pub unsafe extern "C" fn sfnt_set_table(
                 .wrapping_mul(::std::mem::size_of::<sfnt_table>() as u64) as u32,
         ) as *mut sfnt_table;
         (*(*td).tables.offset(idx as isize)).tag = tag.clone();
+        (*td).flags = renew(
+            (*td).flags as *mut libc::c_void,
+            ((*td).num_tables as u32 as u64).wrapping_mul(::std::mem::size_of::<i8>() as u64)
+                as u32,
+        ) as *mut i8;
+        *(*td).flags.offset(idx as isize) = 0_i8;
     }
     (*(*td).tables.offset(idx as isize)).check_sum = sfnt_calc_checksum(data, length);
     (*(*td).tables.offset(idx as isize)).offset = 0i64 as u32;
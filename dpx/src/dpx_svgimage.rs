@@ -0,0 +1,3341 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2002-2016 by Jin-Hwan Cho and Shunsaku Hirata,
+    the dvipdfmx project team.
+
+    Copyright (C) 1998, 1999 by Mark A. Wicks <mwicks@kettering.edu>
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! SVG figures are included by rendering them into a Form XObject while it
+//! is being grabbed, the same way EPS figures are. The document is read by
+//! a small XML parser, styled from its presentation attributes, `style`
+//! attributes and `<style>` sheets, and painted through the `pdf_dev_*`
+//! drawing layer. The form has the size of the figure's viewport, with one
+//! SVG pixel being 0.75bp.
+//!
+//! Supported are paths and the basic shapes, solid colors, linear and
+//! radial gradients, opacity, clipping paths, `<use>` and `<symbol>`, nested
+//! viewports, raster images (external files, or PNG and JPEG `data:` URIs)
+//! and text in fonts resolved through the fontmap. Markers, patterns, masks
+//! and filters are skipped with a warning.
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::io::{Read, Seek, SeekFrom};
+use std::ptr;
+
+use crate::warn;
+
+use super::dpx_fontmap::pdf_resolve_font_key;
+use super::dpx_pdfcolor::PdfColor;
+use super::dpx_pdfdev::{
+    dev_unit_dviunit, graphics_mode, pdf_dev_locate_font, pdf_dev_set_string, transform_info,
+    Coord, Rect, TMatrix,
+};
+use super::dpx_pdfdoc::{
+    pdf_doc_add_page_content, pdf_doc_add_page_resource, pdf_doc_begin_grabbing,
+    pdf_doc_end_grabbing,
+};
+use super::dpx_pdfdraw::{
+    pdf_dev_clip, pdf_dev_closepath, pdf_dev_concat, pdf_dev_current_depth, pdf_dev_curveto,
+    pdf_dev_eoclip, pdf_dev_flushpath, pdf_dev_grestore, pdf_dev_gsave, pdf_dev_lineto,
+    pdf_dev_moveto, pdf_dev_newpath, pdf_dev_set_color, pdf_dev_setdash, pdf_dev_setlinecap,
+    pdf_dev_setlinejoin, pdf_dev_setlinewidth, pdf_dev_setmiterlimit,
+};
+use super::dpx_pdfximage::{
+    load_options, pdf_ximage_defineresource, pdf_ximage_findresource, pdf_ximage_get_reference,
+    pdf_ximage_get_resname, pdf_ximage_init_image_info, pdf_ximage_keep_resolution,
+    pdf_ximage_scale_image, ximage_info, XInfo,
+};
+use super::dpx_tfm::{tfm_open, tfm_string_width};
+#[cfg(feature = "libz-sys")]
+use crate::dpx_pdfobj::pdf_inflate;
+use crate::dpx_pdfobj::{
+    pdf_add_array, pdf_add_dict, pdf_add_stream, pdf_new_array, pdf_new_boolean, pdf_new_dict,
+    pdf_new_name, pdf_new_number, pdf_new_stream, pdf_new_string, pdf_obj, pdf_ref_obj,
+    pdf_release_obj, STREAM_COMPRESS,
+};
+
+use bridge::InputHandleWrapper;
+
+/// One SVG pixel (CSS "px") in PDF points.
+const PX_TO_BP: f64 = 0.75;
+/// Bezier control point distance for quarter circles.
+const KAPPA: f64 = 0.552_284_749_830_793_4;
+/// How deeply `<use>` references and nested elements may recurse.
+const MAX_DEPTH: usize = 64;
+
+/*
+ * XML
+ *
+ * Just enough of XML for SVG files: elements, attributes, character data
+ * and CDATA sections, the predefined and character entities, and entities
+ * declared in the internal subset of a DOCTYPE (which Illustrator uses for
+ * its namespace URIs). Namespace prefixes of element names are dropped.
+ */
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    attrs: Vec<(String, String)>,
+    children: Vec<Node>,
+}
+
+#[derive(Debug)]
+enum Node {
+    Element(Element),
+    Text(String),
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The `href` attribute, with or without the XLink namespace prefix.
+    fn href(&self) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|(k, _)| k == "href" || k.ends_with(":href"))
+            .map(|(_, v)| v.trim())
+    }
+
+    fn elements(&self) -> impl Iterator<Item = &Element> {
+        self.children.iter().filter_map(|n| match n {
+            Node::Element(e) => Some(e),
+            Node::Text(_) => None,
+        })
+    }
+}
+
+struct XmlParser<'a> {
+    s: &'a str,
+    pos: usize,
+    entities: HashMap<String, String>,
+}
+
+impl<'a> XmlParser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn starts_with(&self, p: &str) -> bool {
+        self.rest().starts_with(p)
+    }
+
+    fn skip_ws(&mut self) {
+        let r = self.rest();
+        self.pos += r.len() - r.trim_start().len();
+    }
+
+    /// Skips past the next occurrence of `end`, returning what came before.
+    fn skip_past(&mut self, end: &str) -> Result<&'a str, String> {
+        let r = self.rest();
+        match r.find(end) {
+            Some(i) => {
+                self.pos += i + end.len();
+                Ok(&r[..i])
+            }
+            None => Err(format!("unterminated markup, expected \"{}\"", end)),
+        }
+    }
+
+    fn name(&mut self) -> Result<&'a str, String> {
+        let r = self.rest();
+        let n = r
+            .find(|c: char| c.is_whitespace() || "/>=".contains(c))
+            .unwrap_or_else(|| r.len());
+        if n == 0 {
+            return Err("expected a name".to_owned());
+        }
+        self.pos += n;
+        Ok(&r[..n])
+    }
+
+    /// Skips the XML declaration, comments, processing instructions and
+    /// the document type declaration.
+    fn skip_misc(&mut self) -> Result<(), String> {
+        loop {
+            self.skip_ws();
+            if self.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.starts_with("<!DOCTYPE") {
+                self.pos += "<!DOCTYPE".len();
+                let r = self.rest();
+                let close = r.find('>').ok_or("unterminated DOCTYPE")?;
+                match r.find('[') {
+                    Some(open) if open < close => {
+                        self.pos += open + 1;
+                        let subset = self.skip_past("]")?;
+                        self.entity_decls(subset);
+                        self.skip_past(">")?;
+                    }
+                    _ => self.pos += close + 1,
+                }
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn entity_decls(&mut self, subset: &str) {
+        let mut rest = subset;
+        while let Some(i) = rest.find("<!ENTITY") {
+            rest = rest[i + "<!ENTITY".len()..].trim_start();
+            let n = rest.find(char::is_whitespace).unwrap_or_else(|| rest.len());
+            let name = &rest[..n];
+            let value = rest[n..].trim_start();
+            if let Some(q) = value.chars().next().filter(|&c| c == '"' || c == '\'') {
+                if let Some(end) = value[1..].find(q) {
+                    self.entities
+                        .insert(name.to_owned(), value[1..1 + end].to_owned());
+                }
+            }
+        }
+    }
+
+    fn unescape(&self, s: &str) -> String {
+        if !s.contains('&') {
+            return s.to_owned();
+        }
+        let mut out = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(i) = rest.find('&') {
+            out.push_str(&rest[..i]);
+            rest = &rest[i..];
+            let end = match rest.find(';') {
+                Some(end) => end,
+                None => break,
+            };
+            let ent = &rest[1..end];
+            let rep = match ent {
+                "lt" => Some("<".to_owned()),
+                "gt" => Some(">".to_owned()),
+                "amp" => Some("&".to_owned()),
+                "quot" => Some("\"".to_owned()),
+                "apos" => Some("'".to_owned()),
+                _ if ent.starts_with("#x") || ent.starts_with("#X") => {
+                    u32::from_str_radix(&ent[2..], 16)
+                        .ok()
+                        .and_then(std::char::from_u32)
+                        .map(String::from)
+                }
+                _ if ent.starts_with('#') => ent[1..]
+                    .parse()
+                    .ok()
+                    .and_then(std::char::from_u32)
+                    .map(String::from),
+                _ => self.entities.get(ent).cloned(),
+            };
+            match rep {
+                Some(rep) => {
+                    out.push_str(&rep);
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    out.push('&');
+                    rest = &rest[1..];
+                }
+            }
+        }
+        out.push_str(rest);
+        out
+    }
+
+    fn element(&mut self, depth: usize) -> Result<Element, String> {
+        if depth > 4 * MAX_DEPTH {
+            return Err("elements are nested too deeply".to_owned());
+        }
+        self.pos += 1; /* '<' */
+        let name = self.name()?;
+        let local = match name.rfind(':') {
+            Some(i) => &name[i + 1..],
+            None => name,
+        };
+        let mut el = Element {
+            name: local.to_owned(),
+            attrs: Vec::new(),
+            children: Vec::new(),
+        };
+        loop {
+            self.skip_ws();
+            if self.starts_with("/>") {
+                self.pos += 2;
+                return Ok(el);
+            } else if self.starts_with(">") {
+                self.pos += 1;
+                break;
+            } else if self.rest().is_empty() {
+                return Err(format!("unterminated start tag <{}>", name));
+            }
+            let key = self.name()?;
+            self.skip_ws();
+            if !self.starts_with("=") {
+                return Err(format!("attribute \"{}\" has no value", key));
+            }
+            self.pos += 1;
+            self.skip_ws();
+            let q = match self.rest().chars().next() {
+                Some(q) if q == '"' || q == '\'' => q,
+                _ => return Err(format!("attribute \"{}\" is not quoted", key)),
+            };
+            self.pos += 1;
+            let r = self.rest();
+            let end = r.find(q).ok_or("unterminated attribute value")?;
+            let value = self.unescape(&r[..end]);
+            self.pos += end + 1;
+            el.attrs.push((key.to_owned(), value));
+        }
+        loop {
+            let r = self.rest();
+            let i = r
+                .find('<')
+                .ok_or_else(|| format!("missing end tag for <{}>", name))?;
+            if i > 0 {
+                el.children.push(Node::Text(self.unescape(&r[..i])));
+                self.pos += i;
+            }
+            if self.starts_with("</") {
+                self.pos += 2;
+                let end = self.name()?;
+                if end != name {
+                    return Err(format!("mismatched end tag </{}> for <{}>", end, name));
+                }
+                self.skip_past(">")?;
+                return Ok(el);
+            } else if self.starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.starts_with("<![CDATA[") {
+                self.pos += "<![CDATA[".len();
+                let text = self.skip_past("]]>")?;
+                el.children.push(Node::Text(text.to_owned()));
+            } else if self.starts_with("<?") {
+                self.skip_past("?>")?;
+            } else {
+                el.children.push(Node::Element(self.element(depth + 1)?));
+            }
+        }
+    }
+}
+
+fn parse_xml(data: &[u8]) -> Result<Element, String> {
+    let data = data.strip_prefix(b"\xef\xbb\xbf").unwrap_or(data);
+    if data.starts_with(b"\xfe\xff") || data.starts_with(b"\xff\xfe") {
+        return Err("UTF-16 documents are not supported".to_owned());
+    }
+    let text = String::from_utf8_lossy(data);
+    let mut p = XmlParser {
+        s: &text,
+        pos: 0,
+        entities: HashMap::new(),
+    };
+    p.skip_misc()?;
+    if !p.starts_with("<") {
+        return Err("no root element".to_owned());
+    }
+    p.element(0)
+}
+
+/*
+ * CSS
+ *
+ * Style sheets are limited to rules whose selectors are a type, class or
+ * ID selector or a compound of them. Rules with combinators, attribute
+ * selectors or pseudo-classes are ignored.
+ */
+
+#[derive(Debug)]
+struct Selector {
+    tag: Option<String>,
+    id: Option<String>,
+    classes: Vec<String>,
+}
+
+impl Selector {
+    fn parse(s: &str) -> Option<Selector> {
+        let s = s.trim();
+        if s.is_empty() || s.contains(|c: char| c.is_whitespace() || ">+~[:".contains(c)) {
+            return None;
+        }
+        let mut sel = Selector {
+            tag: None,
+            id: None,
+            classes: Vec::new(),
+        };
+        let mut kind = ' ';
+        let mut start = 0;
+        for (i, c) in s.char_indices().chain(std::iter::once((s.len(), '\0'))) {
+            if c != '.' && c != '#' && c != '\0' {
+                continue;
+            }
+            let part = &s[start..i];
+            match kind {
+                ' ' => {
+                    if !part.is_empty() && part != "*" {
+                        sel.tag = Some(part.to_owned());
+                    }
+                }
+                _ if part.is_empty() => return None,
+                '.' => sel.classes.push(part.to_owned()),
+                _ => sel.id = Some(part.to_owned()),
+            }
+            kind = c;
+            start = i + 1;
+        }
+        Some(sel)
+    }
+
+    fn specificity(&self) -> u32 {
+        10000 * self.id.is_some() as u32
+            + 100 * self.classes.len() as u32
+            + self.tag.is_some() as u32
+    }
+
+    fn matches(&self, el: &Element) -> bool {
+        if let Some(ref tag) = self.tag {
+            if *tag != el.name {
+                return false;
+            }
+        }
+        if let Some(ref id) = self.id {
+            if el.attr("id") != Some(id.as_str()) {
+                return false;
+            }
+        }
+        let class = el.attr("class").unwrap_or("");
+        self.classes
+            .iter()
+            .all(|c| class.split_whitespace().any(|x| x == c))
+    }
+}
+
+struct CssRule {
+    selector: Selector,
+    decls: Vec<(String, String)>,
+}
+
+fn parse_declarations(s: &str) -> Vec<(String, String)> {
+    s.split(';')
+        .filter_map(|d| {
+            let colon = d.find(':')?;
+            let key = d[..colon].trim().to_ascii_lowercase();
+            let value = d[colon + 1..].trim();
+            let value = value.strip_suffix("!important").unwrap_or(value).trim();
+            if key.is_empty() || value.is_empty() {
+                None
+            } else {
+                Some((key, value.to_owned()))
+            }
+        })
+        .collect()
+}
+
+fn parse_stylesheet(css: &str, rules: &mut Vec<CssRule>) {
+    let mut text = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(i) = rest.find("/*") {
+        text.push_str(&rest[..i]);
+        rest = match rest[i + 2..].find("*/") {
+            Some(j) => &rest[i + 2 + j + 2..],
+            None => "",
+        };
+    }
+    text.push_str(rest);
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let brace = rest.find('{');
+        if rest.starts_with('@') {
+            /* At-rules: skip statements and blocks alike. */
+            match (rest.find(';'), brace) {
+                (Some(semi), Some(b)) if semi < b => rest = &rest[semi + 1..],
+                (Some(semi), None) => rest = &rest[semi + 1..],
+                (_, Some(b)) => {
+                    let mut depth = 0;
+                    let mut end = rest.len();
+                    for (i, c) in rest[b..].char_indices() {
+                        match c {
+                            '{' => depth += 1,
+                            '}' => {
+                                depth -= 1;
+                                if depth == 0 {
+                                    end = b + i + 1;
+                                    break;
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    rest = &rest[end..];
+                }
+                (None, None) => break,
+            }
+        } else {
+            let b = match brace {
+                Some(b) => b,
+                None => break,
+            };
+            let end = rest[b..]
+                .find('}')
+                .map(|e| b + e)
+                .unwrap_or_else(|| rest.len());
+            let decls = parse_declarations(&rest[b + 1..end]);
+            for sel in rest[..b].split(',').filter_map(Selector::parse) {
+                rules.push(CssRule {
+                    selector: sel,
+                    decls: decls.clone(),
+                });
+            }
+            rest = &rest[(end + 1).min(rest.len())..];
+        }
+        rest = rest.trim_start();
+    }
+}
+
+/*
+ * Property values
+ */
+
+type Rgb = (f64, f64, f64);
+
+#[derive(Clone, Debug, PartialEq)]
+enum Paint {
+    None,
+    Color(Rgb),
+    /// A paint server (gradient), with the fallback for when it can't be used.
+    Url(String, Box<Paint>),
+}
+
+/* The CSS color keywords, which SVG 1.1 adopts. */
+const NAMED_COLORS: &[(&str, u32)] = &[
+    ("aliceblue", 0xf0f8ff),
+    ("antiquewhite", 0xfaebd7),
+    ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4),
+    ("azure", 0xf0ffff),
+    ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4),
+    ("black", 0x000000),
+    ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff),
+    ("blueviolet", 0x8a2be2),
+    ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887),
+    ("cadetblue", 0x5f9ea0),
+    ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e),
+    ("coral", 0xff7f50),
+    ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc),
+    ("crimson", 0xdc143c),
+    ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b),
+    ("darkcyan", 0x008b8b),
+    ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9),
+    ("darkgreen", 0x006400),
+    ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b),
+    ("darkmagenta", 0x8b008b),
+    ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00),
+    ("darkorchid", 0x9932cc),
+    ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a),
+    ("darkseagreen", 0x8fbc8f),
+    ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f),
+    ("darkslategrey", 0x2f4f4f),
+    ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3),
+    ("deeppink", 0xff1493),
+    ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969),
+    ("dimgrey", 0x696969),
+    ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222),
+    ("floralwhite", 0xfffaf0),
+    ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff),
+    ("gainsboro", 0xdcdcdc),
+    ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700),
+    ("goldenrod", 0xdaa520),
+    ("gray", 0x808080),
+    ("grey", 0x808080),
+    ("green", 0x008000),
+    ("greenyellow", 0xadff2f),
+    ("honeydew", 0xf0fff0),
+    ("hotpink", 0xff69b4),
+    ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082),
+    ("ivory", 0xfffff0),
+    ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa),
+    ("lavenderblush", 0xfff0f5),
+    ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd),
+    ("lightblue", 0xadd8e6),
+    ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff),
+    ("lightgoldenrodyellow", 0xfafad2),
+    ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90),
+    ("lightgrey", 0xd3d3d3),
+    ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a),
+    ("lightseagreen", 0x20b2aa),
+    ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899),
+    ("lightslategrey", 0x778899),
+    ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0),
+    ("lime", 0x00ff00),
+    ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6),
+    ("magenta", 0xff00ff),
+    ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa),
+    ("mediumblue", 0x0000cd),
+    ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db),
+    ("mediumseagreen", 0x3cb371),
+    ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a),
+    ("mediumturquoise", 0x48d1cc),
+    ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970),
+    ("mintcream", 0xf5fffa),
+    ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5),
+    ("navajowhite", 0xffdead),
+    ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6),
+    ("olive", 0x808000),
+    ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500),
+    ("orangered", 0xff4500),
+    ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa),
+    ("palegreen", 0x98fb98),
+    ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093),
+    ("papayawhip", 0xffefd5),
+    ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f),
+    ("pink", 0xffc0cb),
+    ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6),
+    ("purple", 0x800080),
+    ("rebeccapurple", 0x663399),
+    ("red", 0xff0000),
+    ("rosybrown", 0xbc8f8f),
+    ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513),
+    ("salmon", 0xfa8072),
+    ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57),
+    ("seashell", 0xfff5ee),
+    ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0),
+    ("skyblue", 0x87ceeb),
+    ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090),
+    ("slategrey", 0x708090),
+    ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f),
+    ("steelblue", 0x4682b4),
+    ("tan", 0xd2b48c),
+    ("teal", 0x008080),
+    ("thistle", 0xd8bfd8),
+    ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0),
+    ("violet", 0xee82ee),
+    ("wheat", 0xf5deb3),
+    ("white", 0xffffff),
+    ("whitesmoke", 0xf5f5f5),
+    ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+fn rgb_from_u32(v: u32) -> Rgb {
+    (
+        ((v >> 16) & 0xff) as f64 / 255.,
+        ((v >> 8) & 0xff) as f64 / 255.,
+        (v & 0xff) as f64 / 255.,
+    )
+}
+
+fn parse_color(s: &str) -> Option<Rgb> {
+    let s = s.trim();
+    if let Some(hex) = s.strip_prefix('#') {
+        let v = u32::from_str_radix(hex, 16).ok()?;
+        return match hex.len() {
+            3 => Some(rgb_from_u32(
+                (v >> 8 & 0xf) * 0x110000 + (v >> 4 & 0xf) * 0x1100 + (v & 0xf) * 0x11,
+            )),
+            /* The alpha digits of #rgba and #rrggbbaa are ignored. */
+            4 => Some(rgb_from_u32(
+                (v >> 12 & 0xf) * 0x110000 + (v >> 8 & 0xf) * 0x1100 + (v >> 4 & 0xf) * 0x11,
+            )),
+            6 => Some(rgb_from_u32(v)),
+            8 => Some(rgb_from_u32(v >> 8)),
+            _ => None,
+        };
+    }
+    let lower = s.to_ascii_lowercase();
+    if lower.starts_with("rgb") {
+        let open = lower.find('(')?;
+        let close = lower.rfind(')')?;
+        let args: Vec<&str> = lower[open + 1..close]
+            .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+            .filter(|a| !a.is_empty())
+            .collect();
+        if args.len() < 3 {
+            return None;
+        }
+        let mut c = [0.; 3];
+        for (v, a) in c.iter_mut().zip(&args) {
+            let x = match a.strip_suffix('%') {
+                Some(p) => p.parse::<f64>().ok()? / 100.,
+                None => a.parse::<f64>().ok()? / 255.,
+            };
+            *v = x.max(0.).min(1.);
+        }
+        return Some((c[0], c[1], c[2]));
+    }
+    NAMED_COLORS
+        .iter()
+        .find(|(name, _)| *name == lower)
+        .map(|&(_, v)| rgb_from_u32(v))
+}
+
+/// Parses a `fill` or `stroke` value; `current` is the value of `color`.
+fn parse_paint(s: &str, current: Rgb) -> Option<Paint> {
+    let s = s.trim();
+    if let Some(rest) = s.strip_prefix("url(") {
+        let close = rest.find(')')?;
+        let id = rest[..close].trim().trim_matches(|c| c == '\'' || c == '"');
+        let id = id.strip_prefix('#').unwrap_or(id).to_owned();
+        let fallback = match rest[close + 1..].trim() {
+            "" => Paint::None,
+            f => parse_paint(f, current).unwrap_or(Paint::None),
+        };
+        return Some(Paint::Url(id, Box::new(fallback)));
+    }
+    match s {
+        "none" | "transparent" => Some(Paint::None),
+        "currentColor" | "currentcolor" => Some(Paint::Color(current)),
+        _ => parse_color(s).map(Paint::Color),
+    }
+}
+
+/// Parses a number at the start of `s`, returning it with the rest of `s`.
+fn number_prefix(s: &str) -> Option<(f64, &str)> {
+    let b = s.as_bytes();
+    let mut i = 0;
+    if i < b.len() && (b[i] == b'+' || b[i] == b'-') {
+        i += 1;
+    }
+    let int_start = i;
+    while i < b.len() && b[i].is_ascii_digit() {
+        i += 1;
+    }
+    let mut digits = i > int_start;
+    if i < b.len() && b[i] == b'.' {
+        let frac_start = i + 1;
+        let mut j = frac_start;
+        while j < b.len() && b[j].is_ascii_digit() {
+            j += 1;
+        }
+        if j > frac_start || digits {
+            digits = true;
+            i = j;
+        }
+    }
+    if !digits {
+        return None;
+    }
+    if i < b.len() && (b[i] == b'e' || b[i] == b'E') {
+        let mut j = i + 1;
+        if j < b.len() && (b[j] == b'+' || b[j] == b'-') {
+            j += 1;
+        }
+        if j < b.len() && b[j].is_ascii_digit() {
+            while j < b.len() && b[j].is_ascii_digit() {
+                j += 1;
+            }
+            i = j;
+        }
+    }
+    s[..i].parse().ok().map(|v| (v, &s[i..]))
+}
+
+/// Parses a list of numbers separated by whitespace and/or commas.
+fn parse_numbers(s: &str) -> Vec<f64> {
+    let mut out = Vec::new();
+    let mut rest = s;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+        match number_prefix(rest) {
+            Some((v, r)) => {
+                out.push(v);
+                rest = r;
+            }
+            None => break,
+        }
+    }
+    out
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Axis {
+    X,
+    Y,
+    Other,
+}
+
+/// Converts a length to user units. Percentages refer to the viewport
+/// `vp`; font-relative units to `font_size`.
+fn parse_length(s: &str, axis: Axis, font_size: f64, vp: (f64, f64)) -> Option<f64> {
+    let (v, unit) = number_prefix(s.trim())?;
+    Some(match unit.trim() {
+        "" | "px" => v,
+        "pt" => v * 4. / 3.,
+        "pc" => v * 16.,
+        "mm" => v * 96. / 25.4,
+        "cm" => v * 96. / 2.54,
+        "in" => v * 96.,
+        "em" => v * font_size,
+        "ex" => v * font_size / 2.,
+        "%" => {
+            v / 100.
+                * match axis {
+                    Axis::X => vp.0,
+                    Axis::Y => vp.1,
+                    Axis::Other => ((vp.0 * vp.0 + vp.1 * vp.1) / 2.).sqrt(),
+                }
+        }
+        _ => return None,
+    })
+}
+
+/// Parses a number or percentage, as gradient offsets and opacities are.
+fn parse_fraction(s: &str) -> Option<f64> {
+    let s = s.trim();
+    match s.strip_suffix('%') {
+        Some(p) => number_prefix(p).map(|(v, _)| v / 100.),
+        None => number_prefix(s).map(|(v, _)| v),
+    }
+}
+
+fn parse_transform(s: &str) -> Option<TMatrix> {
+    let mut m = TMatrix::identity();
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        let open = rest.find('(')?;
+        let close = rest.find(')')?;
+        if close < open {
+            return None;
+        }
+        let name = rest[..open].trim();
+        let a = parse_numbers(&rest[open + 1..close]);
+        let f = match (name, a.len()) {
+            ("matrix", 6) => TMatrix::row_major(a[0], a[1], a[2], a[3], a[4], a[5]),
+            ("translate", 1) => TMatrix::create_translation(a[0], 0.),
+            ("translate", 2) => TMatrix::create_translation(a[0], a[1]),
+            ("scale", 1) => TMatrix::create_scale(a[0], a[0]),
+            ("scale", 2) => TMatrix::create_scale(a[0], a[1]),
+            ("rotate", 1) | ("rotate", 3) => {
+                let (sin, cos) = a[0].to_radians().sin_cos();
+                let r = TMatrix::row_major(cos, sin, -sin, cos, 0., 0.);
+                if a.len() == 3 {
+                    TMatrix::create_translation(-a[1], -a[2])
+                        .post_transform(&r)
+                        .post_transform(&TMatrix::create_translation(a[1], a[2]))
+                } else {
+                    r
+                }
+            }
+            ("skewX", 1) => TMatrix::row_major(1., 0., a[0].to_radians().tan(), 1., 0., 0.),
+            ("skewY", 1) => TMatrix::row_major(1., a[0].to_radians().tan(), 0., 1., 0., 0.),
+            _ => return None,
+        };
+        /* The rightmost transformation applies first. */
+        m = f.post_transform(&m);
+        rest = rest[close + 1..].trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    }
+    Some(m)
+}
+
+/// Fits a box of size `w`x`h` into a viewport of size `vw`x`vh` according
+/// to a `preserveAspectRatio` value, returning the scale factors and the
+/// offset of the box in the viewport.
+fn fit_box(par: &str, w: f64, h: f64, vw: f64, vh: f64) -> (f64, f64, f64, f64) {
+    let mut words = par.split_whitespace().filter(|&w| w != "defer");
+    let align = words.next().unwrap_or("xMidYMid");
+    let slice = words.next() == Some("slice");
+    let (sx, sy) = (vw / w, vh / h);
+    if align == "none" {
+        return (sx, sy, 0., 0.);
+    }
+    let s = if slice { sx.max(sy) } else { sx.min(sy) };
+    let fx = if align.starts_with("xMid") {
+        0.5
+    } else if align.starts_with("xMax") {
+        1.
+    } else {
+        0.
+    };
+    let fy = if align.ends_with("YMid") {
+        0.5
+    } else if align.ends_with("YMax") {
+        1.
+    } else {
+        0.
+    };
+    (s, s, fx * (vw - w * s), fy * (vh - h * s))
+}
+
+/// The transformation from a `viewBox` into a viewport of size `vw`x`vh`.
+fn viewbox_transform(vb: &[f64], par: &str, vw: f64, vh: f64) -> TMatrix {
+    let (sx, sy, tx, ty) = fit_box(par, vb[2], vb[3], vw, vh);
+    TMatrix::row_major(sx, 0., 0., sy, tx - vb[0] * sx, ty - vb[1] * sy)
+}
+
+fn parse_viewbox(s: Option<&str>) -> Option<Vec<f64>> {
+    let vb = parse_numbers(s?);
+    if vb.len() == 4 && vb[2] > 0. && vb[3] > 0. {
+        Some(vb)
+    } else {
+        None
+    }
+}
+
+/*
+ * Path geometry
+ *
+ * Paths are reduced to absolute moveto, lineto, curveto and closepath
+ * segments; quadratic curves and elliptical arcs become cubic curves.
+ */
+
+type Point = (f64, f64);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Seg {
+    Move(Point),
+    Line(Point),
+    Curve(Point, Point, Point),
+    Close,
+}
+
+struct PathScanner<'a> {
+    s: &'a str,
+}
+
+impl<'a> PathScanner<'a> {
+    fn skip_sep(&mut self) {
+        self.s = self
+            .s
+            .trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    }
+
+    fn number(&mut self) -> Option<f64> {
+        self.skip_sep();
+        let (v, rest) = number_prefix(self.s)?;
+        self.s = rest;
+        Some(v)
+    }
+
+    fn point(&mut self) -> Option<Point> {
+        Some((self.number()?, self.number()?))
+    }
+
+    /* Arc flags may be written without separators, as in "a1 1 0 00 1 1". */
+    fn flag(&mut self) -> Option<bool> {
+        self.skip_sep();
+        let flag = match self.s.as_bytes().first()? {
+            b'0' => false,
+            b'1' => true,
+            _ => return None,
+        };
+        self.s = &self.s[1..];
+        Some(flag)
+    }
+
+    fn at_number(&mut self) -> bool {
+        self.skip_sep();
+        self.s
+            .as_bytes()
+            .first()
+            .map_or(false, |&c| c.is_ascii_digit() || b"+-.".contains(&c))
+    }
+}
+
+/// Parses path data. As SVG requires, a path is rendered up to the first
+/// error in its data.
+fn parse_path(d: &str) -> Vec<Seg> {
+    let mut segs = Vec::new();
+    let mut sc = PathScanner { s: d };
+    let mut cur = (0., 0.);
+    let mut start = (0., 0.);
+    /* The reflected control point for S and T, if the last segment has one. */
+    let mut last_cubic: Option<Point> = None;
+    let mut last_quad: Option<Point> = None;
+    let mut cmd = 0u8;
+    let mut closed = false;
+    loop {
+        sc.skip_sep();
+        let c = match sc.s.as_bytes().first() {
+            Some(&c) => c,
+            None => break,
+        };
+        if c.is_ascii_alphabetic() {
+            cmd = c;
+            sc.s = &sc.s[1..];
+        } else if cmd == 0 || cmd == b'z' || cmd == b'Z' {
+            break;
+        }
+        if segs.is_empty() && cmd != b'M' && cmd != b'm' {
+            break;
+        }
+        let rel = cmd.is_ascii_lowercase();
+        let base = cur;
+        let abs = move |p: Point| if rel { (base.0 + p.0, base.1 + p.1) } else { p };
+        let upper = cmd.to_ascii_uppercase();
+        if closed && upper != b'M' && upper != b'Z' {
+            segs.push(Seg::Move(cur));
+        }
+        closed = false;
+        let mut cubic = None;
+        let mut quad = None;
+        let ok = match upper {
+            b'M' => sc.point().map(|p| {
+                cur = abs(p);
+                start = cur;
+                segs.push(Seg::Move(cur));
+                /* Further coordinate pairs are implicit linetos. */
+                cmd = if rel { b'l' } else { b'L' };
+            }),
+            b'L' => sc.point().map(|p| {
+                cur = abs(p);
+                segs.push(Seg::Line(cur));
+            }),
+            b'H' => sc.number().map(|x| {
+                cur.0 = if rel { cur.0 + x } else { x };
+                segs.push(Seg::Line(cur));
+            }),
+            b'V' => sc.number().map(|y| {
+                cur.1 = if rel { cur.1 + y } else { y };
+                segs.push(Seg::Line(cur));
+            }),
+            b'C' => (|| Some((sc.point()?, sc.point()?, sc.point()?)))().map(|(c1, c2, p)| {
+                let (c1, c2, p) = (abs(c1), abs(c2), abs(p));
+                segs.push(Seg::Curve(c1, c2, p));
+                cubic = Some(c2);
+                cur = p;
+            }),
+            b'S' => (|| Some((sc.point()?, sc.point()?)))().map(|(c2, p)| {
+                let c1 = match last_cubic {
+                    Some(c) => (2. * cur.0 - c.0, 2. * cur.1 - c.1),
+                    None => cur,
+                };
+                let (c2, p) = (abs(c2), abs(p));
+                segs.push(Seg::Curve(c1, c2, p));
+                cubic = Some(c2);
+                cur = p;
+            }),
+            b'Q' => (|| Some((sc.point()?, sc.point()?)))().map(|(q, p)| {
+                let (q, p) = (abs(q), abs(p));
+                segs.push(quad_to_cubic(cur, q, p));
+                quad = Some(q);
+                cur = p;
+            }),
+            b'T' => sc.point().map(|p| {
+                let q = match last_quad {
+                    Some(q) => (2. * cur.0 - q.0, 2. * cur.1 - q.1),
+                    None => cur,
+                };
+                let p = abs(p);
+                segs.push(quad_to_cubic(cur, q, p));
+                quad = Some(q);
+                cur = p;
+            }),
+            b'A' => (|| {
+                let rx = sc.number()?;
+                let ry = sc.number()?;
+                let phi = sc.number()?;
+                let large = sc.flag()?;
+                let sweep = sc.flag()?;
+                Some((rx, ry, phi, large, sweep, sc.point()?))
+            })()
+            .map(|(rx, ry, phi, large, sweep, p)| {
+                let p = abs(p);
+                arc_to_curves(&mut segs, cur, rx, ry, phi, large, sweep, p);
+                cur = p;
+            }),
+            b'Z' => {
+                segs.push(Seg::Close);
+                cur = start;
+                closed = true;
+                Some(())
+            }
+            _ => None,
+        };
+        if ok.is_none() {
+            break;
+        }
+        last_cubic = cubic;
+        last_quad = quad;
+        if upper == b'Z' && sc.at_number() {
+            break;
+        }
+    }
+    segs
+}
+
+fn quad_to_cubic(p0: Point, q: Point, p: Point) -> Seg {
+    Seg::Curve(
+        (p0.0 + 2. / 3. * (q.0 - p0.0), p0.1 + 2. / 3. * (q.1 - p0.1)),
+        (p.0 + 2. / 3. * (q.0 - p.0), p.1 + 2. / 3. * (q.1 - p.1)),
+        p,
+    )
+}
+
+/// Approximates an elliptical arc by cubic curves, following the
+/// endpoint-to-center conversion in the SVG specification.
+#[allow(clippy::too_many_arguments)]
+fn arc_to_curves(
+    segs: &mut Vec<Seg>,
+    p0: Point,
+    rx: f64,
+    ry: f64,
+    phi: f64,
+    large: bool,
+    sweep: bool,
+    p1: Point,
+) {
+    if p0 == p1 {
+        return;
+    }
+    let (mut rx, mut ry) = (rx.abs(), ry.abs());
+    if rx == 0. || ry == 0. {
+        segs.push(Seg::Line(p1));
+        return;
+    }
+    let (sin, cos) = phi.to_radians().sin_cos();
+    let dx2 = (p0.0 - p1.0) / 2.;
+    let dy2 = (p0.1 - p1.1) / 2.;
+    let x1p = cos * dx2 + sin * dy2;
+    let y1p = -sin * dx2 + cos * dy2;
+    let lambda = (x1p * x1p) / (rx * rx) + (y1p * y1p) / (ry * ry);
+    if lambda > 1. {
+        rx *= lambda.sqrt();
+        ry *= lambda.sqrt();
+    }
+    let num = rx * rx * ry * ry - rx * rx * y1p * y1p - ry * ry * x1p * x1p;
+    let den = rx * rx * y1p * y1p + ry * ry * x1p * x1p;
+    let mut coef = (num / den).max(0.).sqrt();
+    if large == sweep {
+        coef = -coef;
+    }
+    let cxp = coef * rx * y1p / ry;
+    let cyp = -coef * ry * x1p / rx;
+    let cx = cos * cxp - sin * cyp + (p0.0 + p1.0) / 2.;
+    let cy = sin * cxp + cos * cyp + (p0.1 + p1.1) / 2.;
+    let angle = |ux: f64, uy: f64, vx: f64, vy: f64| (ux * vy - uy * vx).atan2(ux * vx + uy * vy);
+    let theta1 = angle(1., 0., (x1p - cxp) / rx, (y1p - cyp) / ry);
+    let mut dtheta = angle(
+        (x1p - cxp) / rx,
+        (y1p - cyp) / ry,
+        (-x1p - cxp) / rx,
+        (-y1p - cyp) / ry,
+    );
+    if !sweep && dtheta > 0. {
+        dtheta -= 2. * std::f64::consts::PI;
+    } else if sweep && dtheta < 0. {
+        dtheta += 2. * std::f64::consts::PI;
+    }
+    let n = (dtheta.abs() / std::f64::consts::FRAC_PI_2).ceil().max(1.) as usize;
+    let delta = dtheta / n as f64;
+    let t = 4. / 3. * (delta / 4.).tan();
+    let map = |ux: f64, uy: f64| {
+        (
+            cx + rx * ux * cos - ry * uy * sin,
+            cy + rx * ux * sin + ry * uy * cos,
+        )
+    };
+    for i in 0..n {
+        let a1 = theta1 + i as f64 * delta;
+        let a2 = a1 + delta;
+        let (s1, c1) = a1.sin_cos();
+        let (s2, c2) = a2.sin_cos();
+        let end = if i + 1 == n { p1 } else { map(c2, s2) };
+        segs.push(Seg::Curve(
+            map(c1 - t * s1, s1 + t * c1),
+            map(c2 + t * s2, s2 - t * c2),
+            end,
+        ));
+    }
+}
+
+fn ellipse_path(cx: f64, cy: f64, rx: f64, ry: f64) -> Vec<Seg> {
+    let (kx, ky) = (KAPPA * rx, KAPPA * ry);
+    vec![
+        Seg::Move((cx + rx, cy)),
+        Seg::Curve((cx + rx, cy + ky), (cx + kx, cy + ry), (cx, cy + ry)),
+        Seg::Curve((cx - kx, cy + ry), (cx - rx, cy + ky), (cx - rx, cy)),
+        Seg::Curve((cx - rx, cy - ky), (cx - kx, cy - ry), (cx, cy - ry)),
+        Seg::Curve((cx + kx, cy - ry), (cx + rx, cy - ky), (cx + rx, cy)),
+        Seg::Close,
+    ]
+}
+
+fn rect_path(x: f64, y: f64, w: f64, h: f64, rx: f64, ry: f64) -> Vec<Seg> {
+    if rx <= 0. || ry <= 0. {
+        return vec![
+            Seg::Move((x, y)),
+            Seg::Line((x + w, y)),
+            Seg::Line((x + w, y + h)),
+            Seg::Line((x, y + h)),
+            Seg::Close,
+        ];
+    }
+    let (kx, ky) = ((1. - KAPPA) * rx, (1. - KAPPA) * ry);
+    let (r, b) = (x + w, y + h);
+    vec![
+        Seg::Move((x + rx, y)),
+        Seg::Line((r - rx, y)),
+        Seg::Curve((r - kx, y), (r, y + ky), (r, y + ry)),
+        Seg::Line((r, b - ry)),
+        Seg::Curve((r, b - ky), (r - kx, b), (r - rx, b)),
+        Seg::Line((x + rx, b)),
+        Seg::Curve((x + kx, b), (x, b - ky), (x, b - ry)),
+        Seg::Line((x, y + ry)),
+        Seg::Curve((x, y + ky), (x + kx, y), (x + rx, y)),
+        Seg::Close,
+    ]
+}
+
+fn polyline_path(points: &str, close: bool) -> Vec<Seg> {
+    let v = parse_numbers(points);
+    let mut segs: Vec<Seg> = v
+        .chunks_exact(2)
+        .enumerate()
+        .map(|(i, p)| {
+            if i == 0 {
+                Seg::Move((p[0], p[1]))
+            } else {
+                Seg::Line((p[0], p[1]))
+            }
+        })
+        .collect();
+    if close && !segs.is_empty() {
+        segs.push(Seg::Close);
+    }
+    segs
+}
+
+/// The bounding box of a path's points (including control points), as
+/// `(x0, y0, x1, y1)`.
+fn path_bounds(segs: &[Seg]) -> Option<(f64, f64, f64, f64)> {
+    let mut bounds: Option<(f64, f64, f64, f64)> = None;
+    let mut add = |p: &Point| {
+        bounds = Some(match bounds {
+            Some((x0, y0, x1, y1)) => (x0.min(p.0), y0.min(p.1), x1.max(p.0), y1.max(p.1)),
+            None => (p.0, p.1, p.0, p.1),
+        })
+    };
+    for seg in segs {
+        match seg {
+            Seg::Move(p) | Seg::Line(p) => add(p),
+            Seg::Curve(c1, c2, p) => {
+                add(c1);
+                add(c2);
+                add(p);
+            }
+            Seg::Close => {}
+        }
+    }
+    bounds
+}
+
+/*
+ * Raster images in data: URIs
+ */
+
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for c in s.bytes() {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            _ if c.is_ascii_whitespace() => continue,
+            _ => return None,
+        };
+        acc = acc << 6 | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// Decodes a `data:` URI into its media type and contents.
+fn decode_data_uri(uri: &str) -> Option<(String, Vec<u8>)> {
+    let rest = uri.strip_prefix("data:")?;
+    let comma = rest.find(',')?;
+    let (meta, data) = (&rest[..comma], &rest[comma + 1..]);
+    let mime = meta
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    let bytes = if meta.ends_with(";base64") {
+        decode_base64(data)?
+    } else {
+        let b = data.as_bytes();
+        let mut out = Vec::with_capacity(b.len());
+        let mut i = 0;
+        while i < b.len() {
+            if b[i] == b'%' && i + 2 < b.len() {
+                let hex = |c: u8| (c as char).to_digit(16);
+                if let (Some(h), Some(l)) = (hex(b[i + 1]), hex(b[i + 2])) {
+                    out.push((h * 16 + l) as u8);
+                    i += 3;
+                    continue;
+                }
+            }
+            out.push(b[i]);
+            i += 1;
+        }
+        out
+    };
+    Some((mime, bytes))
+}
+
+fn be16(b: &[u8]) -> usize {
+    (b[0] as usize) << 8 | b[1] as usize
+}
+
+fn be32(b: &[u8]) -> usize {
+    (b[0] as usize) << 24 | (b[1] as usize) << 16 | (b[2] as usize) << 8 | b[3] as usize
+}
+
+/// Defines an image XObject, with the given samples or encoded data. An
+/// image pixel is made an SVG pixel.
+unsafe fn define_image(
+    width: usize,
+    height: usize,
+    bpc: i32,
+    ncomps: i32,
+    stream: *mut pdf_obj,
+) -> i32 {
+    let mut info = ximage_info::default();
+    pdf_ximage_init_image_info(&mut info);
+    info.width = width as i32;
+    info.height = height as i32;
+    info.bits_per_component = bpc;
+    info.num_components = ncomps;
+    info.xdensity = PX_TO_BP;
+    info.ydensity = PX_TO_BP;
+    pdf_ximage_defineresource(ptr::null(), XInfo::Image(Box::new(info)), stream)
+}
+
+fn device_space(ncomps: usize) -> Option<&'static str> {
+    match ncomps {
+        1 => Some("DeviceGray"),
+        3 => Some("DeviceRGB"),
+        4 => Some("DeviceCMYK"),
+        _ => None,
+    }
+}
+
+/// Embeds JPEG data as it is, with the DCTDecode filter.
+unsafe fn jpeg_image(data: &[u8]) -> Result<i32, &'static str> {
+    if !data.starts_with(b"\xff\xd8") {
+        return Err("not a JPEG file");
+    }
+    let mut pos = 2;
+    let mut adobe = false;
+    let mut frame = None;
+    while pos + 4 <= data.len() {
+        if data[pos] != 0xff {
+            return Err("broken JPEG file");
+        }
+        let marker = data[pos + 1];
+        if marker == 0xff {
+            pos += 1;
+            continue;
+        }
+        if marker == 0x01 || (0xd0..=0xd9).contains(&marker) {
+            pos += 2;
+            continue;
+        }
+        let len = be16(&data[pos + 2..]);
+        let seg = &data[pos + 4..(pos + 2 + len).min(data.len())];
+        match marker {
+            0xee if seg.starts_with(b"Adobe") => adobe = true,
+            0xc0..=0xcf if marker != 0xc4 && marker != 0xc8 && marker != 0xcc => {
+                if seg.len() < 6 {
+                    return Err("broken JPEG file");
+                }
+                frame = Some((seg[0], be16(&seg[1..]), be16(&seg[3..]), seg[5]));
+            }
+            0xda => break,
+            _ => {}
+        }
+        pos += 2 + len;
+    }
+    let (bpc, height, width, ncomps) = frame.ok_or("no JPEG frame found")?;
+    let space = device_space(ncomps as usize).ok_or("unsupported JPEG color space")?;
+    let stream = pdf_new_stream(0);
+    let dict = (*stream).as_stream_mut().get_dict_mut();
+    pdf_add_dict(dict, "Filter", pdf_new_name("DCTDecode"));
+    pdf_add_dict(dict, "ColorSpace", pdf_new_name(space));
+    if adobe && ncomps == 4 {
+        /* Adobe's CMYK JPEGs are inverted. */
+        let decode = pdf_new_array();
+        for _ in 0..4 {
+            pdf_add_array(&mut *decode, pdf_new_number(1.));
+            pdf_add_array(&mut *decode, pdf_new_number(0.));
+        }
+        pdf_add_dict(dict, "Decode", decode);
+    }
+    pdf_add_stream(
+        &mut *stream,
+        data.as_ptr() as *const libc::c_void,
+        data.len() as i32,
+    );
+    Ok(define_image(
+        width,
+        height,
+        bpc as i32,
+        ncomps as i32,
+        stream,
+    ))
+}
+
+/// Reverses the PNG row filters in place.
+fn png_unfilter(data: &[u8], rowbytes: usize, bpp: usize, height: usize) -> Option<Vec<u8>> {
+    if data.len() < (rowbytes + 1) * height {
+        return None;
+    }
+    let mut out = vec![0u8; rowbytes * height];
+    for y in 0..height {
+        let filter = data[y * (rowbytes + 1)];
+        let src = &data[y * (rowbytes + 1) + 1..(y + 1) * (rowbytes + 1)];
+        let (prev, row) = out.split_at_mut(y * rowbytes);
+        let prev = if y > 0 {
+            &prev[(y - 1) * rowbytes..]
+        } else {
+            &[][..]
+        };
+        let row = &mut row[..rowbytes];
+        for x in 0..rowbytes {
+            let a = if x >= bpp { row[x - bpp] as i32 } else { 0 };
+            let b = if y > 0 { prev[x] as i32 } else { 0 };
+            let c = if x >= bpp && y > 0 {
+                prev[x - bpp] as i32
+            } else {
+                0
+            };
+            let pred = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => (a + b) / 2,
+                4 => {
+                    let p = a + b - c;
+                    let (pa, pb, pc) = ((p - a).abs(), (p - b).abs(), (p - c).abs());
+                    if pa <= pb && pa <= pc {
+                        a
+                    } else if pb <= pc {
+                        b
+                    } else {
+                        c
+                    }
+                }
+                _ => return None,
+            };
+            row[x] = (src[x] as i32 + pred) as u8;
+        }
+    }
+    Some(out)
+}
+
+#[cfg(feature = "libz-sys")]
+unsafe fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    pdf_inflate(data)
+}
+
+#[cfg(not(feature = "libz-sys"))]
+unsafe fn inflate(_data: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+/// Embeds PNG data. Images without an alpha channel keep their compressed
+/// data, with PNG predictors; others are decoded to split off a soft mask.
+pub(crate) unsafe fn png_image(data: &[u8]) -> Result<i32, &'static str> {
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err("not a PNG file");
+    }
+    let mut pos = 8;
+    let mut ihdr = None;
+    let mut palette: &[u8] = &[];
+    let mut trns: &[u8] = &[];
+    let mut idat = Vec::new();
+    while pos + 8 <= data.len() {
+        let len = be32(&data[pos..]);
+        let kind = &data[pos + 4..pos + 8];
+        let body = data
+            .get(pos + 8..pos + 8 + len)
+            .ok_or("truncated PNG file")?;
+        match kind {
+            b"IHDR" if len >= 13 => ihdr = Some(body),
+            b"PLTE" => palette = body,
+            b"tRNS" => trns = body,
+            b"IDAT" => idat.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        pos += 12 + len;
+    }
+    let ihdr = ihdr.ok_or("no PNG header found")?;
+    let (width, height) = (be32(ihdr), be32(&ihdr[4..]));
+    let (depth, ctype, interlace) = (ihdr[8] as usize, ihdr[9], ihdr[12]);
+    if width == 0 || height == 0 {
+        return Err("empty PNG image");
+    }
+    if interlace != 0 {
+        return Err("interlaced PNG images are not supported");
+    }
+    let channels = match ctype {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err("unsupported PNG color type"),
+    };
+    let partial_alpha = ctype == 3 && trns.iter().any(|&a| a != 0 && a != 255);
+    if ctype == 4 || ctype == 6 || partial_alpha {
+        return png_image_with_alpha(&idat, width, height, depth, ctype, palette, trns);
+    }
+    let stream = pdf_new_stream(0);
+    let dict = (*stream).as_stream_mut().get_dict_mut();
+    pdf_add_dict(dict, "Filter", pdf_new_name("FlateDecode"));
+    let parms = pdf_new_dict();
+    pdf_add_dict(&mut *parms, "Predictor", pdf_new_number(15.));
+    pdf_add_dict(&mut *parms, "Colors", pdf_new_number(channels as f64));
+    pdf_add_dict(
+        &mut *parms,
+        "BitsPerComponent",
+        pdf_new_number(depth as f64),
+    );
+    pdf_add_dict(&mut *parms, "Columns", pdf_new_number(width as f64));
+    pdf_add_dict(dict, "DecodeParms", parms);
+    if ctype == 3 {
+        if palette.is_empty() {
+            pdf_release_obj(stream);
+            return Err("PNG palette missing");
+        }
+        let space = pdf_new_array();
+        pdf_add_array(&mut *space, pdf_new_name("Indexed"));
+        pdf_add_array(&mut *space, pdf_new_name("DeviceRGB"));
+        pdf_add_array(&mut *space, pdf_new_number((palette.len() / 3 - 1) as f64));
+        pdf_add_array(
+            &mut *space,
+            pdf_new_string(palette.as_ptr() as *const libc::c_void, palette.len() as _),
+        );
+        pdf_add_dict(dict, "ColorSpace", space);
+    } else {
+        pdf_add_dict(
+            dict,
+            "ColorSpace",
+            pdf_new_name(device_space(channels).unwrap()),
+        );
+    }
+    /* Fully transparent palette entries and transparent colors become a
+     * color key mask. */
+    let mut mask = Vec::new();
+    match ctype {
+        3 => {
+            for (i, _) in trns.iter().enumerate().filter(|(_, &a)| a == 0) {
+                mask.push(i as f64);
+                mask.push(i as f64);
+            }
+        }
+        0 | 2 if trns.len() >= 2 * channels => {
+            for c in 0..channels {
+                let v = be16(&trns[2 * c..]) as f64;
+                mask.push(v);
+                mask.push(v);
+            }
+        }
+        _ => {}
+    }
+    if !mask.is_empty() {
+        let arr = pdf_new_array();
+        for v in mask {
+            pdf_add_array(&mut *arr, pdf_new_number(v));
+        }
+        pdf_add_dict(dict, "Mask", arr);
+    }
+    pdf_add_stream(
+        &mut *stream,
+        idat.as_ptr() as *const libc::c_void,
+        idat.len() as i32,
+    );
+    Ok(define_image(
+        width,
+        height,
+        depth as i32,
+        channels as i32,
+        stream,
+    ))
+}
+
+unsafe fn png_image_with_alpha(
+    idat: &[u8],
+    width: usize,
+    height: usize,
+    depth: usize,
+    ctype: u8,
+    palette: &[u8],
+    trns: &[u8],
+) -> Result<i32, &'static str> {
+    let raw = inflate(idat).ok_or("PNG data could not be decompressed")?;
+    let channels = match ctype {
+        3 => 1,
+        4 => 2,
+        _ => 4,
+    };
+    if ctype != 3 && depth != 8 && depth != 16 {
+        return Err("unsupported PNG bit depth");
+    }
+    let rowbytes = (width * channels * depth + 7) / 8;
+    let bpp = ((channels * depth + 7) / 8).max(1);
+    let pixels = png_unfilter(&raw, rowbytes, bpp, height).ok_or("broken PNG data")?;
+    let ncolor = if ctype == 4 { 1 } else { 3 };
+    let mut color = Vec::with_capacity(width * height * ncolor);
+    let mut alpha = Vec::with_capacity(width * height);
+    for row in pixels.chunks_exact(rowbytes) {
+        for x in 0..width {
+            if ctype == 3 {
+                /* Palette indices of 1, 2, 4 or 8 bits. */
+                let bit = x * depth;
+                let index = (row[bit / 8] >> (8 - depth - bit % 8)) as usize & ((1 << depth) - 1);
+                let entry = palette.get(3 * index..3 * index + 3).unwrap_or(&[0, 0, 0]);
+                color.extend_from_slice(entry);
+                alpha.push(trns.get(index).copied().unwrap_or(255));
+            } else {
+                /* Sixteen-bit samples are reduced to their high byte. */
+                let step = depth / 8;
+                let px = &row[x * channels * step..(x + 1) * channels * step];
+                for c in 0..ncolor {
+                    color.push(px[c * step]);
+                }
+                alpha.push(px[ncolor * step]);
+            }
+        }
+    }
+    let smask = pdf_new_stream(STREAM_COMPRESS);
+    {
+        let dict = (*smask).as_stream_mut().get_dict_mut();
+        pdf_add_dict(dict, "Type", pdf_new_name("XObject"));
+        pdf_add_dict(dict, "Subtype", pdf_new_name("Image"));
+        pdf_add_dict(dict, "Width", pdf_new_number(width as f64));
+        pdf_add_dict(dict, "Height", pdf_new_number(height as f64));
+        pdf_add_dict(dict, "ColorSpace", pdf_new_name("DeviceGray"));
+        pdf_add_dict(dict, "BitsPerComponent", pdf_new_number(8.));
+    }
+    pdf_add_stream(
+        &mut *smask,
+        alpha.as_ptr() as *const libc::c_void,
+        alpha.len() as i32,
+    );
+    let stream = pdf_new_stream(STREAM_COMPRESS);
+    let dict = (*stream).as_stream_mut().get_dict_mut();
+    pdf_add_dict(
+        dict,
+        "ColorSpace",
+        pdf_new_name(device_space(ncolor).unwrap()),
+    );
+    pdf_add_dict(dict, "SMask", pdf_ref_obj(smask));
+    pdf_release_obj(smask);
+    pdf_add_stream(
+        &mut *stream,
+        color.as_ptr() as *const libc::c_void,
+        color.len() as i32,
+    );
+    Ok(define_image(width, height, 8, ncolor as i32, stream))
+}
+
+/*
+ * Rendering
+ */
+
+/// Computed values of the inherited properties.
+#[derive(Clone)]
+struct Style {
+    color: Rgb,
+    fill: Paint,
+    fill_opacity: f64,
+    fill_evenodd: bool,
+    stroke: Paint,
+    stroke_width: f64,
+    stroke_opacity: f64,
+    linecap: i32,
+    linejoin: i32,
+    miterlimit: f64,
+    dasharray: Vec<f64>,
+    dashoffset: f64,
+    visible: bool,
+    clip_evenodd: bool,
+    font_family: String,
+    font_size: f64,
+    bold: bool,
+    italic: bool,
+    /// 0 for `start`, 0.5 for `middle` and 1 for `end`.
+    anchor: f64,
+    preserve_space: bool,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Style {
+            color: (0., 0., 0.),
+            fill: Paint::Color((0., 0., 0.)),
+            fill_opacity: 1.,
+            fill_evenodd: false,
+            stroke: Paint::None,
+            stroke_width: 1.,
+            stroke_opacity: 1.,
+            linecap: 0,
+            linejoin: 0,
+            miterlimit: 4.,
+            dasharray: Vec::new(),
+            dashoffset: 0.,
+            visible: true,
+            clip_evenodd: false,
+            font_family: "serif".to_owned(),
+            font_size: 16.,
+            bold: false,
+            italic: false,
+            anchor: 0.,
+            preserve_space: false,
+        }
+    }
+}
+
+/// What an element inherits from its parent besides style.
+#[derive(Clone)]
+struct Context {
+    style: Style,
+    /// Maps user space to the space of the form.
+    ctm: TMatrix,
+    /// The size of the nearest viewport, in user units.
+    viewport: (f64, f64),
+    /// Group opacity, multiplied into everything painted.
+    alpha: f64,
+}
+
+/// A paint resolved for a particular shape.
+enum Ink {
+    None,
+    Color(Rgb),
+    Pattern(CString),
+}
+
+/// A piece of text in a single style, positioned by `x`/`y`/`dx`/`dy`.
+struct TextRun {
+    x: Option<f64>,
+    y: Option<f64>,
+    dx: f64,
+    dy: f64,
+    text: String,
+    style: Style,
+    alpha: f64,
+}
+
+struct Font {
+    key: Vec<u8>,
+    size: f64,
+    font_id: i32,
+    tfm_id: i32,
+}
+
+const PROPERTIES: &[&str] = &[
+    "clip-path",
+    "clip-rule",
+    "color",
+    "display",
+    "fill",
+    "fill-opacity",
+    "fill-rule",
+    "filter",
+    "font-family",
+    "font-size",
+    "font-style",
+    "font-weight",
+    "marker",
+    "marker-end",
+    "marker-mid",
+    "marker-start",
+    "mask",
+    "opacity",
+    "overflow",
+    "stop-color",
+    "stop-opacity",
+    "stroke",
+    "stroke-dasharray",
+    "stroke-dashoffset",
+    "stroke-linecap",
+    "stroke-linejoin",
+    "stroke-miterlimit",
+    "stroke-opacity",
+    "stroke-width",
+    "text-anchor",
+    "visibility",
+];
+
+fn font_candidates(families: &str, bold: bool, italic: bool) -> Vec<String> {
+    let standard = |base: &str, roman: &str, italic_name: &str| {
+        let variant = match (bold, italic) {
+            (false, false) => roman.to_owned(),
+            (true, false) => "Bold".to_owned(),
+            (false, true) => italic_name.to_owned(),
+            (true, true) => format!("Bold{}", italic_name),
+        };
+        if variant.is_empty() {
+            base.to_owned()
+        } else {
+            format!("{}-{}", base, variant)
+        }
+    };
+    let mut names = Vec::new();
+    for family in families.split(',').chain(std::iter::once("serif")) {
+        let family = family.trim().trim_matches(|c| c == '"' || c == '\'');
+        match family.to_ascii_lowercase().as_str() {
+            "serif" | "times" | "times new roman" | "times-roman" => {
+                names.push(standard("Times", "Roman", "Italic"))
+            }
+            "sans-serif" | "helvetica" | "arial" => {
+                names.push(standard("Helvetica", "", "Oblique"))
+            }
+            "monospace" | "courier" | "courier new" => {
+                names.push(standard("Courier", "", "Oblique"))
+            }
+            "" => {}
+            _ => {
+                let compact: String = family.split_whitespace().collect();
+                if bold || italic {
+                    names.push(standard(&compact, "", if italic { "Italic" } else { "" }));
+                }
+                names.push(compact);
+            }
+        }
+    }
+    names
+}
+
+struct Renderer<'a> {
+    ids: HashMap<&'a str, &'a Element>,
+    rules: Vec<CssRule>,
+    /// The file name of the figure itself.
+    ident: Vec<u8>,
+    use_stack: Vec<*const Element>,
+    gstates: Vec<(f64, f64, CString)>,
+    patterns: usize,
+    fonts: Vec<Font>,
+    missing_fonts: Vec<String>,
+    warned: Vec<&'static str>,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(root: &'a Element, ident: &[u8]) -> Self {
+        fn index<'a>(el: &'a Element, ids: &mut HashMap<&'a str, &'a Element>, css: &mut String) {
+            if let Some(id) = el.attr("id") {
+                ids.entry(id).or_insert(el);
+            }
+            for child in &el.children {
+                match child {
+                    Node::Element(e) => index(e, ids, css),
+                    Node::Text(t) if el.name == "style" => css.push_str(t),
+                    Node::Text(_) => {}
+                }
+            }
+            if el.name == "style" {
+                css.push('\n');
+            }
+        }
+        let mut ids = HashMap::new();
+        let mut css = String::new();
+        index(root, &mut ids, &mut css);
+        let mut rules = Vec::new();
+        parse_stylesheet(&css, &mut rules);
+        /* Later rules of equal specificity win: the sort below is stable. */
+        rules.sort_by_key(|r| r.selector.specificity());
+        Renderer {
+            ids,
+            rules,
+            ident: ident.to_vec(),
+            use_stack: Vec::new(),
+            gstates: Vec::new(),
+            patterns: 0,
+            fonts: Vec::new(),
+            missing_fonts: Vec::new(),
+            warned: Vec::new(),
+        }
+    }
+
+    fn warn_once(&mut self, what: &'static str) {
+        if !self.warned.contains(&what) {
+            warn!("SVG: {} not supported; ignored.", what);
+            self.warned.push(what);
+        }
+    }
+
+    fn lookup(&self, url: &str) -> Option<&'a Element> {
+        let id = url.trim();
+        let id = id
+            .strip_prefix("url(")
+            .map_or(id, |u| u.trim_end_matches(')'));
+        let id = id.trim().trim_matches(|c| c == '"' || c == '\'');
+        self.ids.get(id.strip_prefix('#')?).copied()
+    }
+
+    /// Collects the specified values of an element's properties, from
+    /// presentation attributes, style sheets and the `style` attribute.
+    fn specified(&self, el: &Element) -> HashMap<String, String> {
+        let mut props = HashMap::new();
+        for (k, v) in &el.attrs {
+            if PROPERTIES.contains(&k.as_str()) {
+                props.insert(k.clone(), v.trim().to_owned());
+            }
+        }
+        for rule in self.rules.iter().filter(|r| r.selector.matches(el)) {
+            for (k, v) in &rule.decls {
+                props.insert(k.clone(), v.clone());
+            }
+        }
+        if let Some(style) = el.attr("style") {
+            props.extend(parse_declarations(style));
+        }
+        if let Some(space) = el.attr("xml:space") {
+            props.insert("xml:space".to_owned(), space.to_owned());
+        }
+        props
+    }
+
+    /// Computes an element's style from its parent's context. The
+    /// specified properties are returned too, for those not inherited.
+    fn style(&self, el: &Element, ctx: &Context) -> (Style, HashMap<String, String>) {
+        let props = self.specified(el);
+        let parent = &ctx.style;
+        let mut s = parent.clone();
+        let get = |name: &str| {
+            props
+                .get(name)
+                .map(String::as_str)
+                .filter(|&v| v != "inherit")
+        };
+        let length =
+            |v: &str, font_size: f64| parse_length(v, Axis::Other, font_size, ctx.viewport);
+        if let Some(v) = get("font-size") {
+            let size = match v {
+                "xx-small" => Some(9.),
+                "x-small" => Some(10.),
+                "small" => Some(13.),
+                "medium" => Some(16.),
+                "large" => Some(18.),
+                "x-large" => Some(24.),
+                "xx-large" => Some(32.),
+                "larger" => Some(parent.font_size * 1.2),
+                "smaller" => Some(parent.font_size / 1.2),
+                _ if v.ends_with('%') => parse_fraction(v).map(|f| f * parent.font_size),
+                _ => length(v, parent.font_size),
+            };
+            if let Some(size) = size.filter(|&size| size > 0.) {
+                s.font_size = size;
+            }
+        }
+        if let Some(c) = get("color").and_then(parse_color) {
+            s.color = c;
+        }
+        if let Some(p) = get("fill").and_then(|v| parse_paint(v, s.color)) {
+            s.fill = p;
+        }
+        if let Some(p) = get("stroke").and_then(|v| parse_paint(v, s.color)) {
+            s.stroke = p;
+        }
+        if let Some(o) = get("fill-opacity").and_then(parse_fraction) {
+            s.fill_opacity = o.max(0.).min(1.);
+        }
+        if let Some(o) = get("stroke-opacity").and_then(parse_fraction) {
+            s.stroke_opacity = o.max(0.).min(1.);
+        }
+        match get("fill-rule") {
+            Some("evenodd") => s.fill_evenodd = true,
+            Some("nonzero") => s.fill_evenodd = false,
+            _ => {}
+        }
+        match get("clip-rule") {
+            Some("evenodd") => s.clip_evenodd = true,
+            Some("nonzero") => s.clip_evenodd = false,
+            _ => {}
+        }
+        if let Some(w) = get("stroke-width").and_then(|v| length(v, s.font_size)) {
+            if w >= 0. {
+                s.stroke_width = w;
+            }
+        }
+        match get("stroke-linecap") {
+            Some("butt") => s.linecap = 0,
+            Some("round") => s.linecap = 1,
+            Some("square") => s.linecap = 2,
+            _ => {}
+        }
+        match get("stroke-linejoin") {
+            Some("miter") | Some("miter-clip") | Some("arcs") => s.linejoin = 0,
+            Some("round") => s.linejoin = 1,
+            Some("bevel") => s.linejoin = 2,
+            _ => {}
+        }
+        if let Some(m) = get("stroke-miterlimit").and_then(|v| v.parse::<f64>().ok()) {
+            if m >= 1. {
+                s.miterlimit = m;
+            }
+        }
+        if let Some(v) = get("stroke-dasharray") {
+            let dashes: Option<Vec<f64>> = v
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|d| !d.is_empty() && *d != "none")
+                .map(|d| length(d, s.font_size).filter(|&d| d >= 0.))
+                .collect();
+            s.dasharray = match dashes {
+                Some(ref d) if d.iter().any(|&d| d > 0.) => {
+                    if d.len() % 2 == 1 {
+                        d.iter().chain(d.iter()).copied().collect()
+                    } else {
+                        d.clone()
+                    }
+                }
+                _ => Vec::new(),
+            };
+        }
+        if let Some(o) = get("stroke-dashoffset").and_then(|v| length(v, s.font_size)) {
+            s.dashoffset = o;
+        }
+        match get("visibility") {
+            Some("visible") => s.visible = true,
+            Some("hidden") | Some("collapse") => s.visible = false,
+            _ => {}
+        }
+        if let Some(f) = get("font-family") {
+            s.font_family = f.to_owned();
+        }
+        match get("font-weight") {
+            Some("bold") | Some("bolder") => s.bold = true,
+            Some("normal") | Some("lighter") => s.bold = false,
+            Some(w) => {
+                if let Ok(w) = w.parse::<u32>() {
+                    s.bold = w >= 600;
+                }
+            }
+            None => {}
+        }
+        match get("font-style") {
+            Some("italic") | Some("oblique") => s.italic = true,
+            Some("normal") => s.italic = false,
+            _ => {}
+        }
+        match get("text-anchor") {
+            Some("start") => s.anchor = 0.,
+            Some("middle") => s.anchor = 0.5,
+            Some("end") => s.anchor = 1.,
+            _ => {}
+        }
+        match get("xml:space") {
+            Some("preserve") => s.preserve_space = true,
+            Some("default") => s.preserve_space = false,
+            _ => {}
+        }
+        (s, props)
+    }
+
+    fn length(&self, el: &Element, name: &str, axis: Axis, ctx: &Context) -> Option<f64> {
+        el.attr(name)
+            .and_then(|v| parse_length(v, axis, ctx.style.font_size, ctx.viewport))
+    }
+
+    /// The geometry of a basic shape or path, in its user space.
+    fn shape(&self, el: &Element, ctx: &Context) -> Option<Vec<Seg>> {
+        let len = |name: &str, axis: Axis| self.length(el, name, axis, ctx);
+        let segs = match el.name.as_str() {
+            "path" => parse_path(el.attr("d")?),
+            "rect" => {
+                let (w, h) = (len("width", Axis::X)?, len("height", Axis::Y)?);
+                if w <= 0. || h <= 0. {
+                    return None;
+                }
+                let (rx, ry) = match (len("rx", Axis::X), len("ry", Axis::Y)) {
+                    (Some(rx), Some(ry)) => (rx, ry),
+                    (Some(r), None) | (None, Some(r)) => (r, r),
+                    (None, None) => (0., 0.),
+                };
+                rect_path(
+                    len("x", Axis::X).unwrap_or(0.),
+                    len("y", Axis::Y).unwrap_or(0.),
+                    w,
+                    h,
+                    rx.min(w / 2.),
+                    ry.min(h / 2.),
+                )
+            }
+            "circle" => {
+                let r = len("r", Axis::Other)?;
+                if r <= 0. {
+                    return None;
+                }
+                let (cx, cy) = (len("cx", Axis::X), len("cy", Axis::Y));
+                ellipse_path(cx.unwrap_or(0.), cy.unwrap_or(0.), r, r)
+            }
+            "ellipse" => {
+                let (rx, ry) = (len("rx", Axis::X)?, len("ry", Axis::Y)?);
+                if rx <= 0. || ry <= 0. {
+                    return None;
+                }
+                let (cx, cy) = (len("cx", Axis::X), len("cy", Axis::Y));
+                ellipse_path(cx.unwrap_or(0.), cy.unwrap_or(0.), rx, ry)
+            }
+            "line" => vec![
+                Seg::Move((
+                    len("x1", Axis::X).unwrap_or(0.),
+                    len("y1", Axis::Y).unwrap_or(0.),
+                )),
+                Seg::Line((
+                    len("x2", Axis::X).unwrap_or(0.),
+                    len("y2", Axis::Y).unwrap_or(0.),
+                )),
+            ],
+            "polyline" => polyline_path(el.attr("points")?, false),
+            "polygon" => polyline_path(el.attr("points")?, true),
+            _ => return None,
+        };
+        if segs.is_empty() {
+            None
+        } else {
+            Some(segs)
+        }
+    }
+
+    unsafe fn render(&mut self, el: &'a Element, parent: &Context, depth: usize) {
+        match el.name.as_str() {
+            "g" | "a" | "switch" | "svg" | "use" | "path" | "rect" | "circle" | "ellipse"
+            | "line" | "polyline" | "polygon" | "text" | "image" => {}
+            "foreignObject" => return self.warn_once("foreignObject"),
+            /* Everything else is either only rendered when referenced, or
+             * not rendered at all. */
+            _ => return,
+        }
+        if depth > MAX_DEPTH {
+            return self.warn_once("deeply nested content");
+        }
+        let (style, props) = self.style(el, parent);
+        let prop = |name: &str| props.get(name).map(String::as_str);
+        if prop("display") == Some("none") {
+            return;
+        }
+        for &(name, what) in &[
+            ("marker", "markers"),
+            ("marker-start", "markers"),
+            ("marker-mid", "markers"),
+            ("marker-end", "markers"),
+            ("mask", "masks"),
+            ("filter", "filters"),
+        ] {
+            if prop(name).map_or(false, |v| v != "none") {
+                self.warn_once(what);
+            }
+        }
+        let mut ctx = Context {
+            style,
+            ctm: parent.ctm,
+            viewport: parent.viewport,
+            alpha: parent.alpha,
+        };
+        if let Some(m) = el.attr("transform").and_then(parse_transform) {
+            ctx.ctm = m.post_transform(&parent.ctm);
+        }
+        if let Some(o) = prop("opacity").and_then(parse_fraction) {
+            /* Group opacity is approximated by applying it to each
+             * painted object separately. */
+            ctx.alpha *= o.max(0.).min(1.);
+        }
+        if ctx.alpha <= 0. {
+            return;
+        }
+        let clip = prop("clip-path")
+            .filter(|&v| v != "none")
+            .map(str::to_owned);
+        if let Some(ref clip) = clip {
+            pdf_dev_gsave();
+            if !self.clip(clip, el, &ctx) {
+                pdf_dev_grestore();
+                return;
+            }
+        }
+        match el.name.as_str() {
+            "g" | "a" => {
+                for child in el.elements() {
+                    self.render(child, &ctx, depth + 1);
+                }
+            }
+            "switch" => {
+                if let Some(child) = el
+                    .elements()
+                    .find(|c| c.attr("requiredExtensions").is_none() && c.name != "foreignObject")
+                {
+                    self.render(child, &ctx, depth + 1);
+                }
+            }
+            "svg" => {
+                let overflow = prop("overflow").unwrap_or("hidden");
+                self.render_viewport(el, &ctx, None, overflow, depth);
+            }
+            "use" => self.render_use(el, &ctx, depth),
+            "text" => self.render_text(el, &ctx),
+            "image" => self.render_image(el, &ctx),
+            _ => {
+                if let Some(segs) = self.shape(el, &ctx) {
+                    self.paint_path(&segs, &ctx);
+                }
+            }
+        }
+        if clip.is_some() {
+            pdf_dev_grestore();
+        }
+    }
+
+    /// Renders the contents of an `<svg>` or `<symbol>` element, which
+    /// establish a new viewport.
+    unsafe fn render_viewport(
+        &mut self,
+        el: &'a Element,
+        ctx: &Context,
+        size: Option<(f64, f64)>,
+        overflow: &str,
+        depth: usize,
+    ) {
+        let len = |name: &str, axis: Axis| self.length(el, name, axis, ctx);
+        let (x, y) = (
+            len("x", Axis::X).unwrap_or(0.),
+            len("y", Axis::Y).unwrap_or(0.),
+        );
+        let (w, h) = size.unwrap_or((
+            len("width", Axis::X).unwrap_or(ctx.viewport.0),
+            len("height", Axis::Y).unwrap_or(ctx.viewport.1),
+        ));
+        if w <= 0. || h <= 0. {
+            return;
+        }
+        let mut inner = ctx.clone();
+        inner.ctm = TMatrix::create_translation(x, y).post_transform(&ctx.ctm);
+        inner.viewport = (w, h);
+        pdf_dev_gsave();
+        if overflow != "visible" && overflow != "auto" {
+            emit_path(&rect_path(0., 0., w, h, 0., 0.), &inner.ctm);
+            pdf_dev_clip();
+            pdf_dev_newpath();
+        }
+        if let Some(vb) = parse_viewbox(el.attr("viewBox")) {
+            let par = el.attr("preserveAspectRatio").unwrap_or("");
+            inner.ctm = viewbox_transform(&vb, par, w, h).post_transform(&inner.ctm);
+            inner.viewport = (vb[2], vb[3]);
+        }
+        for child in el.elements() {
+            self.render(child, &inner, depth + 1);
+        }
+        pdf_dev_grestore();
+    }
+
+    unsafe fn render_use(&mut self, el: &'a Element, ctx: &Context, depth: usize) {
+        let target = match el.href() {
+            Some(href) if href.starts_with('#') => match self.lookup(href) {
+                Some(target) => target,
+                None => return,
+            },
+            Some(_) => return self.warn_once("references to other files"),
+            None => return,
+        };
+        if self.use_stack.contains(&(target as *const Element)) {
+            warn!(
+                "SVG: circular reference to \"{}\".",
+                el.href().unwrap_or("")
+            );
+            return;
+        }
+        let len = |name: &str, axis: Axis| self.length(el, name, axis, ctx);
+        let (x, y) = (
+            len("x", Axis::X).unwrap_or(0.),
+            len("y", Axis::Y).unwrap_or(0.),
+        );
+        let size = (
+            len("width", Axis::X).unwrap_or(ctx.viewport.0),
+            len("height", Axis::Y).unwrap_or(ctx.viewport.1),
+        );
+        let mut inner = ctx.clone();
+        inner.ctm = TMatrix::create_translation(x, y).post_transform(&ctx.ctm);
+        self.use_stack.push(target);
+        if target.name == "symbol" {
+            let (style, props) = self.style(target, &inner);
+            if props.get("display").map(String::as_str) != Some("none") {
+                let overflow = props
+                    .get("overflow")
+                    .map(String::as_str)
+                    .unwrap_or("hidden");
+                inner.style = style;
+                self.render_viewport(target, &inner, Some(size), overflow, depth + 1);
+            }
+        } else {
+            self.render(target, &inner, depth + 1);
+        }
+        self.use_stack.pop();
+    }
+
+    /// Intersects the clipping path with the `<clipPath>` referenced by a
+    /// `clip-path` property. Returns false if nothing remains visible.
+    unsafe fn clip(&mut self, url: &str, el: &'a Element, ctx: &Context) -> bool {
+        let cp = match self.lookup(url) {
+            Some(cp) if cp.name == "clipPath" => cp,
+            _ => return true,
+        };
+        let mut base = ctx.ctm;
+        if cp.attr("clipPathUnits") == Some("objectBoundingBox") {
+            match self.shape(el, ctx).as_deref().and_then(path_bounds) {
+                Some((x0, y0, x1, y1)) => {
+                    base =
+                        TMatrix::row_major(x1 - x0, 0., 0., y1 - y0, x0, y0).post_transform(&base)
+                }
+                None => {
+                    self.warn_once("bounding box clipping of groups");
+                    return true;
+                }
+            }
+        }
+        if let Some(m) = cp.attr("transform").and_then(parse_transform) {
+            base = m.post_transform(&base);
+        }
+        let (cp_style, _) = self.style(cp, ctx);
+        let cp_ctx = Context {
+            style: cp_style,
+            ..ctx.clone()
+        };
+        let mut any = false;
+        let mut evenodd = true;
+        for child in cp.elements() {
+            let (style, props) = self.style(child, &cp_ctx);
+            if props.get("display").map(String::as_str) == Some("none") || !style.visible {
+                continue;
+            }
+            let mut m = base;
+            if let Some(t) = child.attr("transform").and_then(parse_transform) {
+                m = t.post_transform(&m);
+            }
+            let mut shape = child;
+            if child.name == "use" {
+                match child.href().and_then(|h| self.lookup(h)) {
+                    Some(target) => {
+                        let x = self.length(child, "x", Axis::X, ctx).unwrap_or(0.);
+                        let y = self.length(child, "y", Axis::Y, ctx).unwrap_or(0.);
+                        m = TMatrix::create_translation(x, y).post_transform(&m);
+                        if let Some(t) = target.attr("transform").and_then(parse_transform) {
+                            m = t.post_transform(&m);
+                        }
+                        shape = target;
+                    }
+                    None => continue,
+                }
+            } else if child.name == "text" {
+                self.warn_once("text in clipping paths");
+                continue;
+            }
+            if let Some(segs) = self.shape(shape, &cp_ctx) {
+                emit_path(&segs, &m);
+                any = true;
+                evenodd &= style.clip_evenodd;
+            }
+        }
+        if !any {
+            return false;
+        }
+        if evenodd {
+            pdf_dev_eoclip();
+        } else {
+            pdf_dev_clip();
+        }
+        pdf_dev_newpath();
+        true
+    }
+
+    /// Resolves a paint for an object with the given bounding box.
+    unsafe fn ink(
+        &mut self,
+        paint: &Paint,
+        bbox: Option<(f64, f64, f64, f64)>,
+        ctx: &Context,
+    ) -> Ink {
+        match paint {
+            Paint::None => Ink::None,
+            Paint::Color(c) => Ink::Color(*c),
+            Paint::Url(id, fallback) => match self.ids.get(id.as_str()).copied() {
+                Some(el) if el.name == "linearGradient" || el.name == "radialGradient" => {
+                    self.gradient(el, bbox, ctx)
+                }
+                Some(el) if el.name == "pattern" => {
+                    self.warn_once("pattern fills");
+                    self.ink(fallback, bbox, ctx)
+                }
+                _ => self.ink(fallback, bbox, ctx),
+            },
+        }
+    }
+
+    /// Turns a gradient into a shading pattern.
+    unsafe fn gradient(
+        &mut self,
+        el: &'a Element,
+        bbox: Option<(f64, f64, f64, f64)>,
+        ctx: &Context,
+    ) -> Ink {
+        /* Attributes and stops may be inherited through xlink:href. */
+        let mut chain = vec![el];
+        while chain.len() < 16 {
+            match chain.last().unwrap().href().and_then(|h| self.lookup(h)) {
+                Some(e)
+                    if (e.name == "linearGradient" || e.name == "radialGradient")
+                        && !chain.iter().any(|&c| ptr::eq(c, e)) =>
+                {
+                    chain.push(e)
+                }
+                _ => break,
+            }
+        }
+        let attr = |name: &str| chain.iter().find_map(|e| e.attr(name));
+        let mut stops: Vec<(f64, Rgb)> = Vec::new();
+        if let Some(owner) = chain
+            .iter()
+            .find(|e| e.elements().any(|c| c.name == "stop"))
+        {
+            for stop in owner.elements().filter(|c| c.name == "stop") {
+                let props = self.specified(stop);
+                let last = stops.last().map_or(0., |s| s.0);
+                let offset = stop
+                    .attr("offset")
+                    .and_then(parse_fraction)
+                    .unwrap_or(0.)
+                    .max(last)
+                    .min(1.);
+                let color = match props.get("stop-color").map(String::as_str) {
+                    Some("currentColor") | Some("currentcolor") => ctx.style.color,
+                    Some(c) => parse_color(c).unwrap_or((0., 0., 0.)),
+                    None => (0., 0., 0.),
+                };
+                if props
+                    .get("stop-opacity")
+                    .and_then(|o| parse_fraction(o))
+                    .map_or(false, |o| o < 1.)
+                {
+                    self.warn_once("gradient stop opacity");
+                }
+                stops.push((offset, color));
+            }
+        }
+        match stops.len() {
+            0 => return Ink::None,
+            1 => return Ink::Color(stops[0].1),
+            _ => {}
+        }
+        if matches!(attr("spreadMethod"), Some("reflect") | Some("repeat")) {
+            self.warn_once("gradient spread methods other than \"pad\"");
+        }
+        let user_space = attr("gradientUnits") == Some("userSpaceOnUse");
+        let mut m = attr("gradientTransform")
+            .and_then(parse_transform)
+            .unwrap_or_else(TMatrix::identity);
+        if !user_space {
+            match bbox {
+                Some((x0, y0, x1, y1)) if x1 > x0 && y1 > y0 => {
+                    m = m.post_transform(&TMatrix::row_major(x1 - x0, 0., 0., y1 - y0, x0, y0))
+                }
+                _ => return Ink::None,
+            }
+        }
+        m = m.post_transform(&ctx.ctm);
+        let coord = |name: &str, axis: Axis, default: &str| {
+            let v = attr(name).unwrap_or(default);
+            if user_space {
+                parse_length(v, axis, ctx.style.font_size, ctx.viewport).unwrap_or(0.)
+            } else {
+                parse_fraction(v).unwrap_or(0.)
+            }
+        };
+        let radial = el.name == "radialGradient";
+        let coords = if radial {
+            let (cx, cy) = (coord("cx", Axis::X, "50%"), coord("cy", Axis::Y, "50%"));
+            let r = coord("r", Axis::Other, "50%");
+            if r <= 0. {
+                return Ink::Color(stops.last().unwrap().1);
+            }
+            let mut fx = attr("fx").map_or(cx, |_| coord("fx", Axis::X, "50%"));
+            let mut fy = attr("fy").map_or(cy, |_| coord("fy", Axis::Y, "50%"));
+            /* A focal point outside of the circle is moved onto it. */
+            let d = ((fx - cx).powi(2) + (fy - cy).powi(2)).sqrt();
+            if d > 0.99 * r {
+                fx = cx + (fx - cx) * 0.99 * r / d;
+                fy = cy + (fy - cy) * 0.99 * r / d;
+            }
+            vec![fx, fy, 0., cx, cy, r]
+        } else {
+            vec![
+                coord("x1", Axis::X, "0%"),
+                coord("y1", Axis::Y, "0%"),
+                coord("x2", Axis::X, "100%"),
+                coord("y2", Axis::Y, "0%"),
+            ]
+        };
+        let function = match stitching_function(&stops) {
+            Some(f) => f,
+            None => return Ink::Color(stops.last().unwrap().1),
+        };
+        let shading = pdf_new_dict();
+        pdf_add_dict(
+            &mut *shading,
+            "ShadingType",
+            pdf_new_number(if radial { 3. } else { 2. }),
+        );
+        pdf_add_dict(&mut *shading, "ColorSpace", pdf_new_name("DeviceRGB"));
+        pdf_add_dict(&mut *shading, "Coords", number_array(&coords));
+        pdf_add_dict(&mut *shading, "Function", function);
+        let extend = pdf_new_array();
+        pdf_add_array(&mut *extend, pdf_new_boolean(1));
+        pdf_add_array(&mut *extend, pdf_new_boolean(1));
+        pdf_add_dict(&mut *shading, "Extend", extend);
+        let pattern = pdf_new_dict();
+        pdf_add_dict(&mut *pattern, "Type", pdf_new_name("Pattern"));
+        pdf_add_dict(&mut *pattern, "PatternType", pdf_new_number(2.));
+        pdf_add_dict(&mut *pattern, "Shading", shading);
+        pdf_add_dict(
+            &mut *pattern,
+            "Matrix",
+            number_array(&[m.m11, m.m12, m.m21, m.m22, m.m31, m.m32]),
+        );
+        self.patterns += 1;
+        let name = CString::new(format!("SvgP{}", self.patterns)).unwrap();
+        pdf_doc_add_page_resource("Pattern", name.as_ptr(), pdf_ref_obj(pattern));
+        pdf_release_obj(pattern);
+        Ink::Pattern(name)
+    }
+
+    unsafe fn set_ink(&self, ink: &Ink, fill: bool) {
+        match ink {
+            Ink::None => {}
+            Ink::Color((r, g, b)) => {
+                pdf_dev_set_color(&PdfColor::Rgb(*r, *g, *b), if fill { 0x20 } else { 0 }, 0)
+            }
+            Ink::Pattern(name) => {
+                /* Only ever set within a saved graphics state, so that the
+                 * color tracked by pdfdraw is right again afterwards. */
+                let (cs, sc) = if fill { ("cs", "scn") } else { ("CS", "SCN") };
+                let content = format!(" /Pattern {} /{} {}", cs, name.to_string_lossy(), sc);
+                pdf_doc_add_page_content(content.as_bytes());
+            }
+        }
+    }
+
+    /// Sets the constant opacities through an ExtGState resource.
+    unsafe fn set_alpha(&mut self, fill: f64, stroke: f64) {
+        if fill >= 1. && stroke >= 1. {
+            return;
+        }
+        let i = match self
+            .gstates
+            .iter()
+            .position(|(f, s, _)| *f == fill && *s == stroke)
+        {
+            Some(i) => i,
+            None => {
+                let name = CString::new(format!("SvgGS{}", self.gstates.len() + 1)).unwrap();
+                let dict = pdf_new_dict();
+                pdf_add_dict(&mut *dict, "Type", pdf_new_name("ExtGState"));
+                pdf_add_dict(&mut *dict, "ca", pdf_new_number(fill));
+                pdf_add_dict(&mut *dict, "CA", pdf_new_number(stroke));
+                pdf_doc_add_page_resource("ExtGState", name.as_ptr(), pdf_ref_obj(dict));
+                pdf_release_obj(dict);
+                self.gstates.push((fill, stroke, name));
+                self.gstates.len() - 1
+            }
+        };
+        let content = format!(" /{} gs", self.gstates[i].2.to_string_lossy());
+        pdf_doc_add_page_content(content.as_bytes());
+    }
+
+    unsafe fn paint_path(&mut self, segs: &[Seg], ctx: &Context) {
+        let s = &ctx.style;
+        if !s.visible {
+            return;
+        }
+        let bbox = path_bounds(segs);
+        let fill = self.ink(&s.fill, bbox, ctx);
+        let stroke = if s.stroke_width > 0. {
+            self.ink(&s.stroke, bbox, ctx)
+        } else {
+            Ink::None
+        };
+        let op = match (&fill, &stroke) {
+            (Ink::None, Ink::None) => return,
+            (_, Ink::None) => b'f',
+            (Ink::None, _) => b'S',
+            _ => b'B',
+        };
+        /* Lines are drawn in the space of the form, so their widths are
+         * scaled here; skewed and anisotropic scalings are averaged. */
+        let m = &ctx.ctm;
+        let scale = (m.m11 * m.m22 - m.m12 * m.m21).abs().sqrt();
+        pdf_dev_gsave();
+        self.set_alpha(ctx.alpha * s.fill_opacity, ctx.alpha * s.stroke_opacity);
+        if op != b'f' {
+            pdf_dev_setlinewidth(s.stroke_width * scale);
+            pdf_dev_setlinecap(s.linecap);
+            pdf_dev_setlinejoin(s.linejoin);
+            pdf_dev_setmiterlimit(s.miterlimit);
+            if !s.dasharray.is_empty() {
+                let dashes: Vec<f64> = s.dasharray.iter().map(|d| d * scale).collect();
+                pdf_dev_setdash(&dashes, s.dashoffset * scale);
+            }
+        }
+        self.set_ink(&fill, true);
+        self.set_ink(&stroke, false);
+        emit_path(segs, m);
+        pdf_dev_flushpath(op, s.fill_evenodd as i32);
+        pdf_dev_grestore();
+    }
+
+    /// Collects the text of a `<text>` element and its `<tspan>`s.
+    fn collect_text(&mut self, el: &'a Element, ctx: &Context, runs: &mut Vec<TextRun>) {
+        let coord = |name: &str, axis: Axis| {
+            el.attr(name)
+                .and_then(|v| {
+                    v.split(|c: char| c == ',' || c.is_whitespace())
+                        .find(|v| !v.is_empty())
+                })
+                .and_then(|v| parse_length(v, axis, ctx.style.font_size, ctx.viewport))
+        };
+        let mut pos = (
+            coord("x", Axis::X),
+            coord("y", Axis::Y),
+            coord("dx", Axis::X).unwrap_or(0.),
+            coord("dy", Axis::Y).unwrap_or(0.),
+        );
+        if el.attrs.iter().any(|(k, v)| {
+            (k == "x" || k == "y" || k == "dx" || k == "dy" || k == "rotate")
+                && parse_numbers(v).len() > 1
+        }) {
+            self.warn_once("per-glyph text positioning");
+        }
+        for child in &el.children {
+            match child {
+                Node::Text(t) => {
+                    let mut text: String = t
+                        .chars()
+                        .filter(|&c| ctx.style.preserve_space || c != '\n')
+                        .map(|c| if c.is_whitespace() { ' ' } else { c })
+                        .collect();
+                    if !ctx.style.preserve_space {
+                        let after_space = runs.last().map_or(true, |r| r.text.ends_with(' '));
+                        let mut collapsed = String::with_capacity(text.len());
+                        let mut space = after_space;
+                        for c in text.chars() {
+                            if c == ' ' {
+                                if !space {
+                                    collapsed.push(c);
+                                }
+                                space = true;
+                            } else {
+                                collapsed.push(c);
+                                space = false;
+                            }
+                        }
+                        text = collapsed;
+                    }
+                    if text.is_empty() {
+                        continue;
+                    }
+                    runs.push(TextRun {
+                        x: pos.0.take(),
+                        y: pos.1.take(),
+                        dx: std::mem::replace(&mut pos.2, 0.),
+                        dy: std::mem::replace(&mut pos.3, 0.),
+                        text,
+                        style: ctx.style.clone(),
+                        alpha: ctx.alpha,
+                    });
+                }
+                Node::Element(e) if e.name == "tspan" || e.name == "a" => {
+                    let (style, props) = self.style(e, ctx);
+                    if props.get("display").map(String::as_str) == Some("none") {
+                        continue;
+                    }
+                    let mut inner = ctx.clone();
+                    inner.style = style;
+                    if let Some(o) = props.get("opacity").and_then(|o| parse_fraction(o)) {
+                        inner.alpha *= o.max(0.).min(1.);
+                    }
+                    let first = runs.len();
+                    self.collect_text(e, &inner, runs);
+                    /* Pending positions of the parent apply to the first run. */
+                    if let Some(run) = runs.get_mut(first) {
+                        if run.x.is_none() {
+                            run.x = pos.0.take();
+                        }
+                        if run.y.is_none() {
+                            run.y = pos.1.take();
+                        }
+                        run.dx += std::mem::replace(&mut pos.2, 0.);
+                        run.dy += std::mem::replace(&mut pos.3, 0.);
+                    }
+                }
+                Node::Element(e) if e.name == "textPath" => self.warn_once("text on a path"),
+                Node::Element(_) => {}
+            }
+        }
+    }
+
+    unsafe fn font(&mut self, style: &Style) -> Option<(i32, i32)> {
+        let candidates = font_candidates(&style.font_family, style.bold, style.italic);
+        let key = candidates
+            .iter()
+            .find_map(|name| pdf_resolve_font_key(name.as_bytes()));
+        let key = match key {
+            Some(key) => key,
+            None => {
+                if !self.missing_fonts.contains(&style.font_family) {
+                    warn!(
+                        "SVG: no font found for font-family \"{}\"; text in it will be dropped.",
+                        style.font_family
+                    );
+                    self.missing_fonts.push(style.font_family.clone());
+                }
+                return None;
+            }
+        };
+        if let Some(f) = self
+            .fonts
+            .iter()
+            .find(|f| f.key == key && f.size == style.font_size)
+        {
+            return Some((f.font_id, f.tfm_id));
+        }
+        let name = CString::new(key.clone()).ok()?;
+        let size = ((style.font_size * dev_unit_dviunit()) as i32).max(1);
+        let font_id = pdf_dev_locate_font(name.as_ptr(), size);
+        if font_id < 0 {
+            return None;
+        }
+        let tfm_id = tfm_open(name.as_ptr(), 0);
+        self.fonts.push(Font {
+            key,
+            size: style.font_size,
+            font_id,
+            tfm_id,
+        });
+        Some((font_id, tfm_id))
+    }
+
+    unsafe fn render_text(&mut self, el: &'a Element, ctx: &Context) {
+        let mut runs = Vec::new();
+        self.collect_text(el, ctx, &mut runs);
+        if let Some(last) = runs.last_mut() {
+            if !last.style.preserve_space {
+                let len = last.text.trim_end().len();
+                last.text.truncate(len);
+            }
+        }
+        /* Each absolutely positioned run starts a chunk, which is aligned
+         * as a whole according to its text-anchor. */
+        let mut pos = (0., 0.);
+        let mut i = 0;
+        while i < runs.len() {
+            let end = runs[i + 1..]
+                .iter()
+                .position(|r| r.x.is_some() || r.y.is_some())
+                .map_or(runs.len(), |n| i + 1 + n);
+            let mut shown = Vec::new();
+            let mut total = 0.;
+            for run in &runs[i..end] {
+                let bytes: Vec<u8> = run
+                    .text
+                    .chars()
+                    .map(|c| match c as u32 {
+                        0x20..=0x7e | 0xa0..=0xff => c as u8,
+                        _ => {
+                            self.warn_once("characters outside of Latin-1");
+                            b'?'
+                        }
+                    })
+                    .collect();
+                let font = self.font(&run.style);
+                let width = match font {
+                    Some((_, tfm_id)) if tfm_id >= 0 => {
+                        let fw = tfm_string_width(tfm_id, bytes.as_ptr(), bytes.len() as u32);
+                        fw as f64 / (1 << 20) as f64 * run.style.font_size
+                    }
+                    _ => 0.,
+                };
+                total += width;
+                shown.push((bytes, font, width));
+            }
+            let shift = -runs[i].style.anchor * total;
+            for (run, (bytes, font, width)) in runs[i..end].iter().zip(shown) {
+                if let Some(x) = run.x {
+                    pos.0 = x;
+                }
+                if let Some(y) = run.y {
+                    pos.1 = y;
+                }
+                pos.0 += run.dx;
+                pos.1 += run.dy;
+                if let Some((font_id, _)) = font {
+                    self.show_text(run, &bytes, font_id, width, (pos.0 + shift, pos.1), ctx);
+                }
+                pos.0 += width;
+            }
+            i = end;
+        }
+    }
+
+    unsafe fn show_text(
+        &mut self,
+        run: &TextRun,
+        bytes: &[u8],
+        font_id: i32,
+        width: f64,
+        at: Point,
+        ctx: &Context,
+    ) {
+        let s = &run.style;
+        if !s.visible || bytes.is_empty() {
+            return;
+        }
+        let color = match s.fill {
+            Paint::None => return,
+            Paint::Color(c) => c,
+            Paint::Url(_, ref fallback) => {
+                self.warn_once("gradient fills of text");
+                match **fallback {
+                    Paint::Color(c) => c,
+                    _ => (0., 0., 0.),
+                }
+            }
+        };
+        if s.stroke != Paint::None {
+            self.warn_once("stroked text");
+        }
+        pdf_dev_gsave();
+        self.set_alpha(run.alpha * s.fill_opacity, 1.);
+        pdf_dev_set_color(&PdfColor::Rgb(color.0, color.1, color.2), 0x20, 0);
+        /* Text is set upright, at the origin of a space that undoes the
+         * flip of the y axis. */
+        pdf_dev_concat(&TMatrix::row_major(1., 0., 0., -1., at.0, at.1).post_transform(&ctx.ctm));
+        pdf_dev_set_string(
+            0,
+            0,
+            bytes.as_ptr() as *const libc::c_void,
+            bytes.len() as _,
+            (width * dev_unit_dviunit()) as i32,
+            font_id,
+            0,
+        );
+        graphics_mode();
+        pdf_dev_grestore();
+    }
+
+    unsafe fn render_image(&mut self, el: &'a Element, ctx: &Context) {
+        if !ctx.style.visible {
+            return;
+        }
+        let href = match el.href() {
+            Some(href) if !href.is_empty() => href,
+            _ => return,
+        };
+        let id = if href.starts_with("data:") {
+            let image = decode_data_uri(href)
+                .ok_or("could not decode the data URI")
+                .and_then(|(mime, data)| match mime.as_str() {
+                    "image/png" => png_image(&data),
+                    "image/jpeg" | "image/jpg" => jpeg_image(&data),
+                    _ if data.starts_with(b"\x89PNG") => png_image(&data),
+                    _ if data.starts_with(b"\xff\xd8") => jpeg_image(&data),
+                    _ => Err("unsupported image type"),
+                });
+            match image {
+                Ok(id) => id,
+                Err(e) => {
+                    warn!("SVG: embedded image skipped: {}.", e);
+                    return;
+                }
+            }
+        } else {
+            let path = href.strip_prefix("file://").unwrap_or(href);
+            if path.as_bytes() == &self.ident[..] {
+                warn!("SVG: the figure includes itself.");
+                return;
+            }
+            let name = match CString::new(path) {
+                Ok(name) => name,
+                Err(_) => return,
+            };
+            let options = load_options {
+                page_no: 1,
+                bbox_type: 0,
+                dict: ptr::null_mut(),
+            };
+            let id = pdf_ximage_findresource(name.as_ptr(), options);
+            if id < 0 {
+                return;
+            }
+            id
+        };
+        /* Painted inside the figure's form XObject, at a size not known yet */
+        pdf_ximage_keep_resolution(id);
+        /* The natural size of the image, in bp. */
+        let mut r = Rect::zero();
+        let mut info = transform_info::new();
+        let m = pdf_ximage_scale_image(id, &mut r, &mut info);
+        let ll = m.transform_point(r.ll);
+        let ur = m.transform_point(r.ur);
+        let (x0, y0) = (ll.x.min(ur.x), ll.y.min(ur.y));
+        let (nw, nh) = (
+            (ll.x - ur.x).abs() / PX_TO_BP,
+            (ll.y - ur.y).abs() / PX_TO_BP,
+        );
+        if nw <= 0. || nh <= 0. {
+            return;
+        }
+        let len = |name: &str, axis: Axis| self.length(el, name, axis, ctx);
+        let (x, y) = (
+            len("x", Axis::X).unwrap_or(0.),
+            len("y", Axis::Y).unwrap_or(0.),
+        );
+        let (w, h) = (
+            len("width", Axis::X).unwrap_or(nw),
+            len("height", Axis::Y).unwrap_or(nh),
+        );
+        if w <= 0. || h <= 0. {
+            return;
+        }
+        let par = el.attr("preserveAspectRatio").unwrap_or("");
+        let (sx, sy, tx, ty) = fit_box(par, nw, nh, w, h);
+        let (sx, sy) = (sx / PX_TO_BP, sy / PX_TO_BP);
+        let place = TMatrix::row_major(
+            sx,
+            0.,
+            0.,
+            -sy,
+            x + tx - x0 * sx,
+            y + ty + (y0 + nh * PX_TO_BP) * sy,
+        );
+        pdf_dev_gsave();
+        if par.contains("slice") {
+            emit_path(&rect_path(x, y, w, h, 0., 0.), &ctx.ctm);
+            pdf_dev_clip();
+            pdf_dev_newpath();
+        }
+        self.set_alpha(ctx.alpha, ctx.alpha);
+        pdf_dev_concat(&m.post_transform(&place).post_transform(&ctx.ctm));
+        let resname = pdf_ximage_get_resname(id);
+        let mut content = b" /".to_vec();
+        content.extend_from_slice(CStr::from_ptr(resname).to_bytes());
+        content.extend_from_slice(b" Do");
+        pdf_doc_add_page_content(&content);
+        pdf_dev_grestore();
+        pdf_doc_add_page_resource("XObject", resname, pdf_ximage_get_reference(id));
+    }
+}
+
+/// Builds the color function of a gradient from its stops: a single
+/// exponential interpolation function, or a stitching function of them.
+unsafe fn stitching_function(stops: &[(f64, Rgb)]) -> Option<*mut pdf_obj> {
+    let mut stops = stops.to_vec();
+    if stops[0].0 > 0. {
+        stops.insert(0, (0., stops[0].1));
+    }
+    if stops.last().unwrap().0 < 1. {
+        stops.push((1., stops.last().unwrap().1));
+    }
+    /* Stops at the same offset make a sharp transition. */
+    let pieces: Vec<(f64, Rgb, Rgb)> = stops
+        .windows(2)
+        .filter(|w| w[1].0 > w[0].0)
+        .map(|w| (w[1].0, w[0].1, w[1].1))
+        .collect();
+    let interpolation = |c0: Rgb, c1: Rgb| {
+        let f = pdf_new_dict();
+        pdf_add_dict(&mut *f, "FunctionType", pdf_new_number(2.));
+        pdf_add_dict(&mut *f, "Domain", number_array(&[0., 1.]));
+        pdf_add_dict(&mut *f, "C0", number_array(&[c0.0, c0.1, c0.2]));
+        pdf_add_dict(&mut *f, "C1", number_array(&[c1.0, c1.1, c1.2]));
+        pdf_add_dict(&mut *f, "N", pdf_new_number(1.));
+        f
+    };
+    match pieces.len() {
+        0 => None,
+        1 => Some(interpolation(pieces[0].1, pieces[0].2)),
+        n => {
+            let functions = pdf_new_array();
+            let mut bounds = Vec::new();
+            let mut encode = Vec::new();
+            for (i, &(end, c0, c1)) in pieces.iter().enumerate() {
+                pdf_add_array(&mut *functions, interpolation(c0, c1));
+                if i + 1 < n {
+                    bounds.push(end);
+                }
+                encode.extend_from_slice(&[0., 1.]);
+            }
+            let f = pdf_new_dict();
+            pdf_add_dict(&mut *f, "FunctionType", pdf_new_number(3.));
+            pdf_add_dict(&mut *f, "Domain", number_array(&[0., 1.]));
+            pdf_add_dict(&mut *f, "Functions", functions);
+            pdf_add_dict(&mut *f, "Bounds", number_array(&bounds));
+            pdf_add_dict(&mut *f, "Encode", number_array(&encode));
+            Some(f)
+        }
+    }
+}
+
+unsafe fn number_array(values: &[f64]) -> *mut pdf_obj {
+    let arr = pdf_new_array();
+    for &v in values {
+        pdf_add_array(&mut *arr, pdf_new_number(v));
+    }
+    arr
+}
+
+/// Appends a path, mapped into the space of the form, to the current path.
+unsafe fn emit_path(segs: &[Seg], m: &TMatrix) {
+    let tp = |p: &Point| m.transform_point(Coord::new(p.0, p.1));
+    for seg in segs {
+        match seg {
+            Seg::Move(p) => {
+                let p = tp(p);
+                pdf_dev_moveto(p.x, p.y);
+            }
+            Seg::Line(p) => {
+                let p = tp(p);
+                pdf_dev_lineto(p.x, p.y);
+            }
+            Seg::Curve(c1, c2, p) => {
+                let (c1, c2, p) = (tp(c1), tp(c2), tp(p));
+                pdf_dev_curveto(c1.x, c1.y, c2.x, c2.y, p.x, p.y);
+            }
+            Seg::Close => {
+                pdf_dev_closepath();
+            }
+        }
+    }
+}
+
+/*
+ * Entry points
+ */
+
+fn looks_like_svg(head: &[u8]) -> bool {
+    let head = head.strip_prefix(b"\xef\xbb\xbf").unwrap_or(head);
+    let text = String::from_utf8_lossy(head);
+    let mut rest = text.trim_start();
+    loop {
+        let end = if rest.starts_with("<?") {
+            rest.find("?>").map(|i| i + 2)
+        } else if rest.starts_with("<!--") {
+            rest.find("-->").map(|i| i + 3)
+        } else if rest.starts_with("<!DOCTYPE") {
+            match (rest.find('['), rest.find('>')) {
+                (Some(open), Some(close)) if open < close => rest[open..]
+                    .find(']')
+                    .and_then(|i| rest[open + i..].find('>').map(|j| open + i + j + 1)),
+                (_, close) => close.map(|i| i + 1),
+            }
+        } else {
+            break;
+        };
+        match end {
+            Some(end) => rest = rest[end..].trim_start(),
+            None => return false,
+        }
+    }
+    let tag = match rest.strip_prefix('<') {
+        Some(tag) => tag,
+        None => return false,
+    };
+    let name = &tag[..tag
+        .find(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .unwrap_or_else(|| tag.len())];
+    name == "svg" || name.ends_with(":svg")
+}
+
+/// Returns the size of the figure in SVG pixels, and its viewBox.
+fn figure_size(root: &Element) -> Option<(f64, f64, Option<Vec<f64>>)> {
+    let vb = parse_viewbox(root.attr("viewBox"));
+    let dim = |name: &str, axis: Axis| {
+        root.attr(name)
+            .filter(|v| !v.trim().ends_with('%') && v.trim() != "auto")
+            .and_then(|v| parse_length(v, axis, 16., (0., 0.)))
+    };
+    let (w, h) = match (dim("width", Axis::X), dim("height", Axis::Y), &vb) {
+        (Some(w), Some(h), _) => (w, h),
+        (Some(w), None, Some(vb)) => (w, w * vb[3] / vb[2]),
+        (None, Some(h), Some(vb)) => (h * vb[2] / vb[3], h),
+        (None, None, Some(vb)) => (vb[2], vb[3]),
+        _ => return None,
+    };
+    if w > 0. && h > 0. {
+        Some((w, h, vb))
+    } else {
+        None
+    }
+}
+
+fn read_svg(handle: &mut InputHandleWrapper) -> Result<Element, String> {
+    let mut data = Vec::new();
+    handle.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+    handle.read_to_end(&mut data).map_err(|e| e.to_string())?;
+    handle.seek(SeekFrom::Start(0)).map_err(|e| e.to_string())?;
+    let root = parse_xml(&data)?;
+    if root.name != "svg" {
+        return Err(format!("the root element is <{}>, not <svg>", root.name));
+    }
+    Ok(root)
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn check_for_svg(handle: &mut InputHandleWrapper) -> i32 {
+    let mut head = Vec::new();
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    let ok = handle.take(4096).read_to_end(&mut head).is_ok();
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    (ok && looks_like_svg(&head)) as i32
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn svg_get_bbox(handle: &mut InputHandleWrapper, bbox: &mut Rect) -> i32 {
+    let size = match read_svg(handle) {
+        Ok(root) => figure_size(&root),
+        Err(e) => {
+            warn!("SVG: {}.", e);
+            return -1;
+        }
+    };
+    match size {
+        Some((w, h, _)) => {
+            *bbox = Rect::new((0., 0.), (w * PX_TO_BP, h * PX_TO_BP));
+            0
+        }
+        None => {
+            warn!("SVG: the figure has neither a size nor a viewBox.");
+            -1
+        }
+    }
+}
+
+/// Renders an SVG figure into a new Form XObject, returning its id.
+#[no_mangle]
+pub unsafe extern "C" fn svg_include_page(
+    ident: *const i8,
+    handle: &mut InputHandleWrapper,
+) -> i32 {
+    let root = match read_svg(handle) {
+        Ok(root) => root,
+        Err(e) => {
+            warn!("SVG: {}.", e);
+            return -1;
+        }
+    };
+    let (w, h, vb) = match figure_size(&root) {
+        Some(size) => size,
+        None => {
+            warn!("SVG: the figure has neither a size nor a viewBox.");
+            return -1;
+        }
+    };
+    let bbox = Rect::new((0., 0.), (w * PX_TO_BP, h * PX_TO_BP));
+    graphics_mode();
+    let xobj_id = pdf_doc_begin_grabbing(ident, 0., 0., &bbox);
+    let depth = pdf_dev_current_depth();
+    let ident = if ident.is_null() {
+        &[][..]
+    } else {
+        CStr::from_ptr(ident).to_bytes()
+    };
+    let mut renderer = Renderer::new(&root, ident);
+    /* SVG's y axis points down. */
+    let mut ctx = Context {
+        style: Style::default(),
+        ctm: TMatrix::row_major(PX_TO_BP, 0., 0., -PX_TO_BP, 0., h * PX_TO_BP),
+        viewport: (w, h),
+        alpha: 1.,
+    };
+    if let Some(vb) = vb {
+        let par = root.attr("preserveAspectRatio").unwrap_or("");
+        ctx.ctm = viewbox_transform(&vb, par, w, h).post_transform(&ctx.ctm);
+        ctx.viewport = (vb[2], vb[3]);
+    }
+    let (style, props) = renderer.style(&root, &ctx);
+    if props.get("display").map(String::as_str) != Some("none") {
+        ctx.style = style;
+        if let Some(o) = props.get("opacity").and_then(|o| parse_fraction(o)) {
+            ctx.alpha = o.max(0.).min(1.);
+        }
+        for child in root.elements() {
+            renderer.render(child, &ctx, 1);
+        }
+    }
+    graphics_mode();
+    while pdf_dev_current_depth() > depth {
+        pdf_dev_grestore();
+    }
+    pdf_doc_end_grabbing(ptr::null_mut());
+    xobj_id
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn xml_documents() {
+        let doc = br#"<?xml version="1.0"?>
+<!DOCTYPE svg [ <!ENTITY ns "http://www.w3.org/2000/svg"> ]>
+<!-- a comment -->
+<svg xmlns="&ns;" width="10pt" height='20'>
+  <style><![CDATA[ .a { fill: red } ]]></style>
+  <svg:g id="g&amp;1"><rect/>text &lt;&#65;&#x42;&gt;</svg:g>
+</svg>"#;
+        let root = parse_xml(doc).unwrap();
+        assert_eq!(root.name, "svg");
+        assert_eq!(root.attr("xmlns"), Some("http://www.w3.org/2000/svg"));
+        let g = root.elements().nth(1).unwrap();
+        assert_eq!(g.name, "g");
+        assert_eq!(g.attr("id"), Some("g&1"));
+        match &g.children[1] {
+            Node::Text(t) => assert_eq!(t, "text <AB>"),
+            _ => panic!("expected text"),
+        }
+        assert!(parse_xml(b"<svg><g></svg>").is_err());
+        assert!(looks_like_svg(doc));
+        assert!(!looks_like_svg(b"<?xml version=\"1.0\"?><html></html>"));
+        let (w, h, vb) = figure_size(&root).unwrap();
+        assert!((w - 40. / 3.).abs() < 1e-9 && h == 20. && vb.is_none());
+    }
+
+    #[test]
+    fn style_sheets_and_colors() {
+        let mut rules = Vec::new();
+        parse_stylesheet(
+            "/* c */ @import url(x.css); rect.a, #b { fill: #f00 !important; stroke:none } \
+             @media print { g { fill: blue } } g > rect { fill: green } .a.c { opacity: .5 }",
+            &mut rules,
+        );
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[0].selector.tag.as_deref(), Some("rect"));
+        assert_eq!(rules[1].selector.id.as_deref(), Some("b"));
+        assert_eq!(rules[2].selector.classes, vec!["a", "c"]);
+        assert_eq!(rules[0].decls[0], ("fill".to_owned(), "#f00".to_owned()));
+        assert_eq!(parse_color("#f80"), Some((1., 0x88 as f64 / 255., 0.)));
+        assert_eq!(parse_color("rgb(100%, 0%, 0%)"), Some((1., 0., 0.)));
+        assert_eq!(parse_color("Navy"), Some((0., 0., 128. / 255.)));
+        assert_eq!(
+            parse_paint("url(#g) none", (0., 0., 0.)),
+            Some(Paint::Url("g".to_owned(), Box::new(Paint::None)))
+        );
+        assert_eq!(
+            parse_paint("currentColor", (1., 1., 1.)),
+            Some(Paint::Color((1., 1., 1.)))
+        );
+    }
+
+    #[test]
+    fn path_data() {
+        let segs = parse_path("M10-20l5.5.5h1v-1zQ0 0 3 3t3 0a1 1 0 00 2 0 L");
+        assert_eq!(segs[0], Seg::Move((10., -20.)));
+        assert_eq!(segs[1], Seg::Line((15.5, -19.5)));
+        assert_eq!(segs[2], Seg::Line((16.5, -19.5)));
+        assert_eq!(segs[3], Seg::Line((16.5, -20.5)));
+        assert_eq!(segs[4], Seg::Close);
+        /* A drawing command after closepath starts at the subpath's start. */
+        assert_eq!(segs[5], Seg::Move((10., -20.)));
+        match segs[6] {
+            Seg::Curve(c1, c2, p) => {
+                assert!((c1.0 - 10. / 3.).abs() < 1e-9 && (c1.1 + 20. / 3.).abs() < 1e-9);
+                assert_eq!((c2, p), ((1., 1.), (3., 3.)));
+            }
+            _ => panic!("expected a curve"),
+        }
+        match segs[7] {
+            Seg::Curve(_, _, p) => assert_eq!(p, (6., 3.)),
+            _ => panic!("expected a curve"),
+        }
+        /* A half circle becomes two quarter curves; the error at the end
+         * stops the parsing. */
+        assert_eq!(segs.len(), 10);
+        match segs[9] {
+            Seg::Curve(_, c2, p) => {
+                assert_eq!(p, (8., 3.));
+                assert!((c2.0 - 8.).abs() < 1e-9);
+            }
+            _ => panic!("expected a curve"),
+        }
+    }
+
+    #[test]
+    fn transforms_and_viewports() {
+        let m = parse_transform("translate(10,20) scale(2) rotate(90)").unwrap();
+        let p = m.transform_point(Coord::new(1., 0.));
+        assert!((p.x - 10.).abs() < 1e-9 && (p.y - 22.).abs() < 1e-9);
+        assert!(parse_transform("scale(1,2,3)").is_none());
+        let m = viewbox_transform(&[0., 0., 10., 20.], "", 100., 100.);
+        let p = m.transform_point(Coord::new(10., 20.));
+        assert!((p.x - 75.).abs() < 1e-9 && (p.y - 100.).abs() < 1e-9);
+        assert_eq!(parse_length("1in", Axis::X, 16., (0., 0.)), Some(96.));
+        assert_eq!(parse_length("50%", Axis::Y, 16., (10., 30.)), Some(15.));
+        assert_eq!(parse_length("2em", Axis::X, 12., (0., 0.)), Some(24.));
+        assert_eq!(decode_base64("aGVsbG8=").unwrap(), b"hello");
+    }
+}
@@ -0,0 +1,967 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2002-2016 by Jin-Hwan Cho and Shunsaku Hirata,
+    the dvipdfmx project team.
+
+    Copyright (C) 1998, 1999 by Mark A. Wicks <mwicks@kettering.edu>
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! Baseline TIFF images. Each IFD of the file is a page, selected by the
+//! `page` option. Uncompressed, LZW, Deflate and PackBits data is decoded;
+//! CCITT Group 3/4 bilevel data is passed through as `CCITTFaxDecode`.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::warn;
+
+use super::dpx_pdfcolor::{iccp_check_colorspace, iccp_load_profile, pdf_get_colorspace_reference};
+use super::dpx_pdfximage::{
+    pdf_ximage_blend_white, pdf_ximage_indexed_cspace, pdf_ximage_init_image_info,
+    pdf_ximage_is_opaque, pdf_ximage_set_image, pdf_ximage_soft_mask,
+};
+#[cfg(feature = "libz-sys")]
+use crate::dpx_pdfobj::pdf_inflate;
+use crate::dpx_pdfobj::{
+    pdf_add_array, pdf_add_dict, pdf_add_stream, pdf_get_version, pdf_new_array, pdf_new_boolean,
+    pdf_new_dict, pdf_new_name, pdf_new_number, pdf_new_stream, pdf_obj, pdf_ref_obj,
+    pdf_release_obj, pdf_stream_set_predictor, STREAM_COMPRESS,
+};
+
+use bridge::InputHandleWrapper;
+
+use crate::dpx_pdfximage::{pdf_ximage, ximage_info};
+
+const TAG_IMAGE_WIDTH: u16 = 256;
+const TAG_IMAGE_LENGTH: u16 = 257;
+const TAG_BITS_PER_SAMPLE: u16 = 258;
+const TAG_COMPRESSION: u16 = 259;
+const TAG_PHOTOMETRIC: u16 = 262;
+const TAG_FILL_ORDER: u16 = 266;
+const TAG_STRIP_OFFSETS: u16 = 273;
+const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+const TAG_ROWS_PER_STRIP: u16 = 278;
+const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+const TAG_X_RESOLUTION: u16 = 282;
+const TAG_Y_RESOLUTION: u16 = 283;
+const TAG_PLANAR_CONFIG: u16 = 284;
+const TAG_T4_OPTIONS: u16 = 292;
+const TAG_RESOLUTION_UNIT: u16 = 296;
+const TAG_PREDICTOR: u16 = 317;
+const TAG_COLOR_MAP: u16 = 320;
+const TAG_TILE_WIDTH: u16 = 322;
+const TAG_TILE_LENGTH: u16 = 323;
+const TAG_TILE_OFFSETS: u16 = 324;
+const TAG_TILE_BYTE_COUNTS: u16 = 325;
+const TAG_INK_SET: u16 = 332;
+const TAG_EXTRA_SAMPLES: u16 = 338;
+const TAG_SAMPLE_FORMAT: u16 = 339;
+const TAG_ICC_PROFILE: u16 = 34675;
+
+const COMPRESSION_NONE: u32 = 1;
+const COMPRESSION_CCITT_RLE: u32 = 2;
+const COMPRESSION_CCITT_T4: u32 = 3;
+const COMPRESSION_CCITT_T6: u32 = 4;
+const COMPRESSION_LZW: u32 = 5;
+const COMPRESSION_OJPEG: u32 = 6;
+const COMPRESSION_JPEG: u32 = 7;
+const COMPRESSION_DEFLATE: u32 = 8;
+const COMPRESSION_PACKBITS: u32 = 32773;
+const COMPRESSION_DEFLATE_OLD: u32 = 32946;
+
+const PHOTOMETRIC_MINISWHITE: u32 = 0;
+const PHOTOMETRIC_MINISBLACK: u32 = 1;
+const PHOTOMETRIC_RGB: u32 = 2;
+const PHOTOMETRIC_PALETTE: u32 = 3;
+const PHOTOMETRIC_SEPARATED: u32 = 5;
+
+/// A directory entry: tag, field type, value count and the position of the
+/// values in the file.
+#[derive(Clone, Copy)]
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: usize,
+    pos: usize,
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Result<Self, &'static str> {
+        let big_endian = match data.get(0..4) {
+            Some(b"II*\x00") => false,
+            Some(b"MM\x00*") => true,
+            Some(b"II+\x00") | Some(b"MM\x00+") => return Err("BigTIFF files are not supported"),
+            _ => return Err("Not a TIFF file"),
+        };
+        Ok(Tiff { data, big_endian })
+    }
+
+    fn u16_at(&self, pos: usize) -> Option<u16> {
+        let b = self.data.get(pos..pos + 2)?;
+        Some(if self.big_endian {
+            u16::from_be_bytes([b[0], b[1]])
+        } else {
+            u16::from_le_bytes([b[0], b[1]])
+        })
+    }
+
+    fn u32_at(&self, pos: usize) -> Option<u32> {
+        let b = self.data.get(pos..pos + 4)?;
+        Some(if self.big_endian {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        })
+    }
+
+    /// Offsets of all image file directories, i.e. of the pages.
+    fn directories(&self) -> Result<Vec<usize>, &'static str> {
+        let mut offsets = Vec::new();
+        let mut next = self.u32_at(4).ok_or("Unexpected end of file")? as usize;
+        while next != 0 {
+            if offsets.contains(&next) {
+                warn!("TIFF: Loop in the chain of directories.");
+                break;
+            }
+            let count = self.u16_at(next).ok_or("Invalid directory offset")? as usize;
+            offsets.push(next);
+            next = self
+                .u32_at(next + 2 + 12 * count)
+                .ok_or("Unexpected end of file")? as usize;
+        }
+        if offsets.is_empty() {
+            return Err("No image in file");
+        }
+        Ok(offsets)
+    }
+
+    fn entries(&self, offset: usize) -> Result<Vec<Entry>, &'static str> {
+        let count = self.u16_at(offset).ok_or("Invalid directory offset")? as usize;
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let p = offset + 2 + 12 * i;
+            let tag = self.u16_at(p).ok_or("Unexpected end of file")?;
+            let kind = self.u16_at(p + 2).ok_or("Unexpected end of file")?;
+            let count = self.u32_at(p + 4).ok_or("Unexpected end of file")? as usize;
+            let size = match kind {
+                1 | 2 | 6 | 7 => 1,
+                3 | 8 => 2,
+                4 | 9 | 11 => 4,
+                5 | 10 | 12 => 8,
+                _ => continue,
+            };
+            let pos = if count.saturating_mul(size) <= 4 {
+                p + 8
+            } else {
+                self.u32_at(p + 8).ok_or("Unexpected end of file")? as usize
+            };
+            if pos.saturating_add(count.saturating_mul(size)) > self.data.len() {
+                return Err("Tag values out of range");
+            }
+            entries.push(Entry {
+                tag,
+                kind,
+                count,
+                pos,
+            });
+        }
+        Ok(entries)
+    }
+
+    /// Integer values of an entry.
+    fn values(&self, entry: &Entry) -> Vec<u32> {
+        (0..entry.count)
+            .filter_map(|i| match entry.kind {
+                1 | 6 | 7 => self.data.get(entry.pos + i).map(|&b| b as u32),
+                3 | 8 => self.u16_at(entry.pos + 2 * i).map(|v| v as u32),
+                4 | 9 => self.u32_at(entry.pos + 4 * i),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn rational(&self, entry: &Entry) -> Option<f64> {
+        match entry.kind {
+            5 => {
+                let num = self.u32_at(entry.pos)? as f64;
+                let den = self.u32_at(entry.pos + 4)? as f64;
+                if den > 0. {
+                    Some(num / den)
+                } else {
+                    None
+                }
+            }
+            3 | 4 => self.values(entry).first().map(|&v| v as f64),
+            _ => None,
+        }
+    }
+}
+
+/// The tags of a page that matter to us.
+struct Page<'a> {
+    width: usize,
+    height: usize,
+    bits_per_sample: u32,
+    samples_per_pixel: usize,
+    compression: u32,
+    photometric: u32,
+    fill_order: u32,
+    planar: u32,
+    predictor: u32,
+    t4_options: u32,
+    ink_set: u32,
+    extra_samples: Vec<u32>,
+    /// Rows per strip, or the tile size.
+    rows_per_strip: usize,
+    tile: Option<(usize, usize)>,
+    /// Compressed strips or tiles.
+    segments: Vec<&'a [u8]>,
+    color_map: Vec<u32>,
+    icc_profile: Option<&'a [u8]>,
+    resolution: Option<(f64, f64, u32)>,
+}
+
+fn read_page<'a>(tiff: &Tiff<'a>, offset: usize) -> Result<Page<'a>, &'static str> {
+    let entries = tiff.entries(offset)?;
+    let find = |tag: u16| entries.iter().find(|e| e.tag == tag);
+    let values = |tag: u16| find(tag).map(|e| tiff.values(e)).unwrap_or_default();
+    let value = |tag: u16, default: u32| values(tag).first().cloned().unwrap_or(default);
+
+    let width = value(TAG_IMAGE_WIDTH, 0) as usize;
+    let height = value(TAG_IMAGE_LENGTH, 0) as usize;
+    if width == 0 || height == 0 || width > 0x7fff_ffff || height > 0x7fff_ffff {
+        return Err("Invalid image size");
+    }
+    let samples_per_pixel = value(TAG_SAMPLES_PER_PIXEL, 1) as usize;
+    let bits = values(TAG_BITS_PER_SAMPLE);
+    let bits_per_sample = bits.first().cloned().unwrap_or(1);
+    if bits.iter().any(|&b| b != bits_per_sample) {
+        return Err("Samples of different sizes are not supported");
+    }
+    if ![1, 2, 4, 8, 16].contains(&bits_per_sample) {
+        return Err("Unsupported number of bits per sample");
+    }
+    if values(TAG_SAMPLE_FORMAT).iter().any(|&f| f != 1) {
+        return Err("Only unsigned integer samples are supported");
+    }
+    let photometric = value(TAG_PHOTOMETRIC, PHOTOMETRIC_MINISBLACK);
+
+    let (tile, offsets, counts) = if find(TAG_TILE_OFFSETS).is_some() {
+        let tw = value(TAG_TILE_WIDTH, 0) as usize;
+        let th = value(TAG_TILE_LENGTH, 0) as usize;
+        if tw == 0 || th == 0 {
+            return Err("Invalid tile size");
+        }
+        (
+            Some((tw, th)),
+            values(TAG_TILE_OFFSETS),
+            values(TAG_TILE_BYTE_COUNTS),
+        )
+    } else {
+        (
+            None,
+            values(TAG_STRIP_OFFSETS),
+            values(TAG_STRIP_BYTE_COUNTS),
+        )
+    };
+    if offsets.is_empty() || offsets.len() != counts.len() {
+        return Err("Invalid strip or tile offsets");
+    }
+    let mut segments = Vec::with_capacity(offsets.len());
+    for (&offset, &count) in offsets.iter().zip(counts.iter()) {
+        let start = offset as usize;
+        let end = start.saturating_add(count as usize).min(tiff.data.len());
+        segments.push(tiff.data.get(start..end).ok_or("Strip out of range")?);
+    }
+
+    let resolution = match (find(TAG_X_RESOLUTION), find(TAG_Y_RESOLUTION)) {
+        (Some(x), Some(y)) => match (tiff.rational(x), tiff.rational(y)) {
+            (Some(x), Some(y)) if x > 0. && y > 0. => Some((x, y, value(TAG_RESOLUTION_UNIT, 2))),
+            _ => None,
+        },
+        _ => None,
+    };
+    let icc_profile = find(TAG_ICC_PROFILE)
+        .filter(|e| e.kind == 7 || e.kind == 1)
+        .map(|e| &tiff.data[e.pos..e.pos + e.count]);
+
+    Ok(Page {
+        width,
+        height,
+        bits_per_sample,
+        samples_per_pixel,
+        compression: value(TAG_COMPRESSION, COMPRESSION_NONE),
+        photometric,
+        fill_order: value(TAG_FILL_ORDER, 1),
+        planar: value(TAG_PLANAR_CONFIG, 1),
+        predictor: value(TAG_PREDICTOR, 1),
+        t4_options: value(TAG_T4_OPTIONS, 0),
+        ink_set: value(TAG_INK_SET, 1),
+        extra_samples: values(TAG_EXTRA_SAMPLES),
+        rows_per_strip: (value(TAG_ROWS_PER_STRIP, u32::max_value()) as usize)
+            .max(1)
+            .min(height),
+        tile,
+        segments,
+        color_map: values(TAG_COLOR_MAP),
+        icc_profile,
+        resolution,
+    })
+}
+
+/// Decodes TIFF's flavour of LZW: MSB-first codes with "early change".
+fn lzw_decode(data: &[u8], expected: usize) -> Result<Vec<u8>, &'static str> {
+    const CLEAR: usize = 256;
+    const EOI: usize = 257;
+    if data.len() >= 2 && data[0] == 0 && data[1] & 1 != 0 {
+        return Err("Old-style LZW compression is not supported");
+    }
+    let mut prefix = vec![0u16; 4096];
+    let mut suffix = vec![0u8; 4096];
+    let mut first = vec![0u8; 4096];
+    let mut length = vec![0usize; 4096];
+    for i in 0..256 {
+        suffix[i] = i as u8;
+        first[i] = i as u8;
+        length[i] = 1;
+    }
+    let mut out = Vec::with_capacity(expected);
+    let mut width = 9;
+    let mut next = 258;
+    let mut prev: Option<usize> = None;
+    let mut buf = 0u32;
+    let mut nbits = 0;
+    let mut pos = 0;
+    while out.len() < expected {
+        while nbits < width {
+            match data.get(pos) {
+                Some(&b) => buf = buf << 8 | b as u32,
+                None => return Ok(out),
+            }
+            pos += 1;
+            nbits += 8;
+        }
+        let code = (buf >> (nbits - width)) as usize & ((1 << width) - 1);
+        nbits -= width;
+        buf &= (1 << nbits) - 1;
+        if code == CLEAR {
+            width = 9;
+            next = 258;
+            prev = None;
+            continue;
+        } else if code == EOI {
+            break;
+        }
+        let p = match prev {
+            None => {
+                if code > 255 {
+                    return Err("Invalid LZW code");
+                }
+                out.push(code as u8);
+                prev = Some(code);
+                continue;
+            }
+            Some(p) => p,
+        };
+        let (emit, last) = if code < next {
+            (code, first[code])
+        } else if code == next {
+            (p, first[p])
+        } else {
+            return Err("Invalid LZW code");
+        };
+        let start = out.len();
+        out.resize(start + length[emit], 0);
+        let mut c = emit;
+        for i in (start..out.len()).rev() {
+            out[i] = suffix[c];
+            c = prefix[c] as usize;
+        }
+        if code == next {
+            out.push(last);
+        }
+        if next < 4096 {
+            prefix[next] = p as u16;
+            suffix[next] = last;
+            first[next] = first[p];
+            length[next] = length[p] + 1;
+            next += 1;
+        }
+        if next + 1 >= 1 << width && width < 12 {
+            width += 1;
+        }
+        prev = Some(code);
+    }
+    Ok(out)
+}
+
+fn packbits_decode(data: &[u8], expected: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(expected);
+    let mut pos = 0;
+    while pos < data.len() && out.len() < expected {
+        let n = data[pos] as i8;
+        pos += 1;
+        if n >= 0 {
+            let end = (pos + n as usize + 1).min(data.len());
+            out.extend_from_slice(&data[pos..end]);
+            pos = end;
+        } else if n != -128 {
+            if let Some(&b) = data.get(pos) {
+                out.extend(std::iter::repeat(b).take((1 - n as isize) as usize));
+            }
+            pos += 1;
+        }
+    }
+    out
+}
+
+#[cfg(feature = "libz-sys")]
+unsafe fn inflate(data: &[u8]) -> Option<Vec<u8>> {
+    pdf_inflate(data)
+}
+
+#[cfg(not(feature = "libz-sys"))]
+unsafe fn inflate(_data: &[u8]) -> Option<Vec<u8>> {
+    None
+}
+
+fn reverse_bits(data: &[u8]) -> Vec<u8> {
+    data.iter().map(|b| b.reverse_bits()).collect()
+}
+
+/// Undoes horizontal differencing in rows of 8- or 16-bit (big-endian)
+/// samples.
+fn undo_predictor(data: &mut [u8], row_bytes: usize, samples: usize, bits: u32) {
+    for row in data.chunks_mut(row_bytes) {
+        if bits == 8 {
+            for i in samples..row.len() {
+                row[i] = row[i].wrapping_add(row[i - samples]);
+            }
+        } else {
+            for i in (2 * samples..row.len() - row.len() % 2).step_by(2) {
+                let v =
+                    u16::from_be_bytes([row[i], row[i + 1]]).wrapping_add(u16::from_be_bytes([
+                        row[i - 2 * samples],
+                        row[i - 2 * samples + 1],
+                    ]));
+                row[i..i + 2].copy_from_slice(&v.to_be_bytes());
+            }
+        }
+    }
+}
+
+/// Decodes the page into rows of interleaved samples; 16-bit samples are
+/// made big-endian.
+unsafe fn decode_samples(tiff: &Tiff, page: &Page) -> Result<Vec<u8>, &'static str> {
+    let bits = page.bits_per_sample as usize;
+    let (planes, spp) = if page.planar == 2 && page.samples_per_pixel > 1 {
+        if bits < 8 {
+            return Err("Planar images with less than 8 bits per sample are not supported");
+        }
+        (page.samples_per_pixel, 1)
+    } else {
+        (1, page.samples_per_pixel)
+    };
+    if page.predictor == 2 && bits < 8 {
+        return Err("Horizontal differencing needs 8 or 16 bits per sample");
+    } else if page.predictor != 1 && page.predictor != 2 {
+        return Err("Unsupported predictor");
+    }
+    let (seg_width, seg_height) = page.tile.unwrap_or((page.width, page.rows_per_strip));
+    let across = (page.width + seg_width - 1) / seg_width;
+    let down = (page.height + seg_height - 1) / seg_height;
+    let per_plane = across * down;
+    if page.segments.len() < per_plane * planes {
+        return Err("Missing strips or tiles");
+    }
+    let seg_row_bytes = (seg_width * spp * bits + 7) / 8;
+    let row_bytes = (page.width * spp * bits + 7) / 8;
+    let mut plane_data = Vec::with_capacity(planes);
+    for plane in 0..planes {
+        let mut out = vec![0u8; row_bytes * page.height];
+        for i in 0..per_plane {
+            let (col, row) = (i % across, i / across);
+            let rows = seg_height.min(page.height - row * seg_height);
+            /* The last strip may be short, tiles are always complete. */
+            let expected = seg_row_bytes
+                * if page.tile.is_some() {
+                    seg_height
+                } else {
+                    rows
+                };
+            let raw = page.segments[plane * per_plane + i];
+            let raw = if page.fill_order == 2 {
+                std::borrow::Cow::Owned(reverse_bits(raw))
+            } else {
+                std::borrow::Cow::Borrowed(raw)
+            };
+            let mut seg = match page.compression {
+                COMPRESSION_NONE => raw.to_vec(),
+                COMPRESSION_LZW => lzw_decode(&raw, expected)?,
+                COMPRESSION_PACKBITS => packbits_decode(&raw, expected),
+                COMPRESSION_DEFLATE | COMPRESSION_DEFLATE_OLD => {
+                    inflate(&raw).ok_or("Decompression of Deflate data failed")?
+                }
+                _ => return Err("Unsupported compression"),
+            };
+            if seg.len() < expected {
+                warn!("TIFF: Image data is truncated.");
+            }
+            seg.resize(expected, 0);
+            if bits == 16 && !tiff.big_endian {
+                for s in seg.chunks_mut(2) {
+                    s.swap(0, 1);
+                }
+            }
+            if page.predictor == 2 {
+                undo_predictor(&mut seg, seg_row_bytes, spp, page.bits_per_sample);
+            }
+            let x0 = col * seg_width * spp * bits / 8;
+            let copy = seg_row_bytes.min(row_bytes - x0);
+            for y in 0..rows {
+                let dst = (row * seg_height + y) * row_bytes + x0;
+                out[dst..dst + copy]
+                    .copy_from_slice(&seg[y * seg_row_bytes..y * seg_row_bytes + copy]);
+            }
+        }
+        plane_data.push(out);
+    }
+    if planes == 1 {
+        return Ok(plane_data.pop().unwrap());
+    }
+    let bytes = bits / 8;
+    let npixels = page.width * page.height;
+    let mut out = Vec::with_capacity(npixels * planes * bytes);
+    for i in 0..npixels {
+        for plane in &plane_data {
+            out.extend_from_slice(&plane[i * bytes..(i + 1) * bytes]);
+        }
+    }
+    Ok(out)
+}
+
+/// Splits interleaved 8- or 16-bit samples into color samples and the alpha
+/// channel (if `alpha` is given), dropping any other extra samples.
+fn split_samples(
+    samples: &[u8],
+    spp: usize,
+    ncolors: usize,
+    bytes: usize,
+    alpha: bool,
+) -> (Vec<u8>, Vec<u8>) {
+    let mut colors = Vec::with_capacity(samples.len() / spp * ncolors);
+    let mut alphas = Vec::new();
+    for pixel in samples.chunks_exact(spp * bytes) {
+        colors.extend_from_slice(&pixel[..ncolors * bytes]);
+        if alpha {
+            alphas.extend_from_slice(&pixel[ncolors * bytes..(ncolors + 1) * bytes]);
+        }
+    }
+    (colors, alphas)
+}
+
+fn strip_16(samples: &[u8]) -> Vec<u8> {
+    samples.iter().step_by(2).cloned().collect()
+}
+
+/// Density (see `pdf_ximage`) from the resolution tags.
+fn page_density(page: &Page) -> (f64, f64) {
+    match page.resolution {
+        Some((x, y, 1)) => (y / x, 1.),
+        Some((x, y, 3)) => (72. / 2.54 / x, 72. / 2.54 / y),
+        Some((x, y, _)) => (72. / x, 72. / y),
+        None => (1., 1.),
+    }
+}
+
+/// Selects the page of a file, counting from one; zero is the first page
+/// and negative numbers count from the end, as for PDF files.
+fn select_page<'a>(
+    data: &'a [u8],
+    page_no: i32,
+    page_count: Option<&mut i32>,
+) -> Result<(Tiff<'a>, usize), &'static str> {
+    let tiff = Tiff::new(data)?;
+    let directories = tiff.directories()?;
+    let count = directories.len() as i32;
+    if let Some(page_count) = page_count {
+        *page_count = count;
+    }
+    let page_no = match page_no {
+        0 => 1,
+        n if n < 0 => count + 1 + n,
+        n => n,
+    };
+    if page_no < 1 || page_no > count {
+        warn!(
+            "TIFF: Page {} does not exist (the file has {} pages).",
+            page_no, count
+        );
+        return Err("Invalid page number");
+    }
+    let offset = directories[page_no as usize - 1];
+    Ok((tiff, offset))
+}
+
+/// The color space of the color samples of a page, and their number.
+unsafe fn page_colorspace(page: &Page) -> Result<(*mut pdf_obj, usize), &'static str> {
+    let (colortype, ncolors) = match page.photometric {
+        PHOTOMETRIC_MINISWHITE | PHOTOMETRIC_MINISBLACK => (-1, 1),
+        PHOTOMETRIC_RGB => (-3, 3),
+        PHOTOMETRIC_SEPARATED if page.ink_set == 1 => (-4, 4),
+        PHOTOMETRIC_SEPARATED => return Err("Only CMYK separated images are supported"),
+        PHOTOMETRIC_PALETTE => (-3, 1),
+        _ => return Err("Unsupported photometric interpretation"),
+    };
+    if page.samples_per_pixel < ncolors {
+        return Err("Too few samples per pixel");
+    }
+    let mut colorspace = 0 as *mut pdf_obj;
+    if let Some(profile) = page.icc_profile {
+        if iccp_check_colorspace(
+            colortype,
+            profile.as_ptr() as *const libc::c_void,
+            profile.len() as i32,
+        ) >= 0
+        {
+            let cspc_id = iccp_load_profile(
+                0 as *const i8,
+                profile.as_ptr() as *const libc::c_void,
+                profile.len() as i32,
+            );
+            if cspc_id >= 0 {
+                colorspace = pdf_get_colorspace_reference(cspc_id);
+            }
+        }
+    }
+    if colorspace.is_null() {
+        colorspace = pdf_new_name(match colortype {
+            -1 => "DeviceGray",
+            -3 => "DeviceRGB",
+            _ => "DeviceCMYK",
+        });
+    }
+    if page.photometric == PHOTOMETRIC_PALETTE {
+        if page.bits_per_sample > 8 {
+            pdf_release_obj(colorspace);
+            return Err("Palette images with more than 8 bits per sample are not supported");
+        }
+        let n = 1 << page.bits_per_sample;
+        if page.color_map.len() < 3 * n {
+            pdf_release_obj(colorspace);
+            return Err("Missing or invalid color map");
+        }
+        /* Some writers store 8-bit values in the 16-bit color map. */
+        let shift = if page.color_map.iter().all(|&v| v < 256) {
+            0
+        } else {
+            8
+        };
+        let mut palette = Vec::with_capacity(3 * n);
+        for i in 0..n {
+            for c in 0..3 {
+                palette.push((page.color_map[c * n + i] >> shift) as u8);
+            }
+        }
+        colorspace = pdf_ximage_indexed_cspace(colorspace, 3, &palette);
+    }
+    Ok((colorspace, ncolors))
+}
+
+/// Passes CCITT-compressed bilevel data through.
+unsafe fn include_ccitt(page: &Page, stream: *mut pdf_obj) -> Result<(), &'static str> {
+    if page.bits_per_sample != 1 || page.samples_per_pixel != 1 {
+        return Err("CCITT compression needs one bit per pixel");
+    }
+    if page.tile.is_some() || (page.segments.len() > 1 && page.compression != COMPRESSION_CCITT_RLE)
+    {
+        return Err("Multi-strip or tiled CCITT-compressed images are not supported");
+    }
+    let (k, byte_align) = match page.compression {
+        COMPRESSION_CCITT_RLE => (0, true),
+        COMPRESSION_CCITT_T4 => ((page.t4_options & 1) as i32, page.t4_options & 4 != 0),
+        _ => (-1, false),
+    };
+    let parms = pdf_new_dict();
+    pdf_add_dict(&mut *parms, "K", pdf_new_number(k as f64));
+    pdf_add_dict(&mut *parms, "Columns", pdf_new_number(page.width as f64));
+    pdf_add_dict(&mut *parms, "Rows", pdf_new_number(page.height as f64));
+    if byte_align {
+        pdf_add_dict(&mut *parms, "EncodedByteAlign", pdf_new_boolean(1));
+    }
+    if page.photometric == PHOTOMETRIC_MINISBLACK {
+        pdf_add_dict(&mut *parms, "BlackIs1", pdf_new_boolean(1));
+    }
+    let stream_dict = (*stream).as_stream_mut().get_dict_mut();
+    pdf_add_dict(stream_dict, "Filter", pdf_new_name("CCITTFaxDecode"));
+    pdf_add_dict(stream_dict, "DecodeParms", parms);
+    pdf_add_dict(stream_dict, "ColorSpace", pdf_new_name("DeviceGray"));
+    for segment in &page.segments {
+        let data = if page.fill_order == 2 {
+            reverse_bits(segment)
+        } else {
+            segment.to_vec()
+        };
+        pdf_add_stream(
+            &mut *stream,
+            data.as_ptr() as *const libc::c_void,
+            data.len() as i32,
+        );
+    }
+    Ok(())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn check_for_tiff(handle: &mut InputHandleWrapper) -> i32 {
+    let mut sigbytes = [0u8; 4];
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    if handle.read_exact(&mut sigbytes).is_err()
+        || (&sigbytes != b"II*\x00" && &sigbytes != b"MM\x00*")
+    {
+        return 0;
+    }
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tiff_get_bbox(
+    handle: &mut InputHandleWrapper,
+    page_no: i32,
+    width: *mut u32,
+    height: *mut u32,
+    xdensity: *mut f64,
+    ydensity: *mut f64,
+) -> i32 {
+    let mut data = Vec::new();
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    if handle.read_to_end(&mut data).is_err() {
+        warn!("TIFF: Reading file failed.");
+        return -1;
+    }
+    let page =
+        select_page(&data, page_no, None).and_then(|(tiff, offset)| read_page(&tiff, offset));
+    match page {
+        Ok(page) => {
+            *width = page.width as u32;
+            *height = page.height as u32;
+            let (xd, yd) = page_density(&page);
+            *xdensity = xd;
+            *ydensity = yd;
+            0
+        }
+        Err(e) => {
+            warn!("TIFF: {}.", e);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tiff_include_image(
+    ximage: *mut pdf_ximage,
+    handle: &mut InputHandleWrapper,
+) -> i32 {
+    let mut info = ximage_info::default();
+    pdf_ximage_init_image_info(&mut info);
+    let mut data = Vec::new();
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    if handle.read_to_end(&mut data).is_err() {
+        warn!("TIFF: Reading file failed.");
+        return -1;
+    }
+    let page = select_page(
+        &data,
+        (*ximage).attr.page_no,
+        Some(&mut (*ximage).attr.page_count),
+    )
+    .and_then(|(tiff, offset)| Ok((read_page(&tiff, offset)?, tiff)));
+    let (page, tiff) = match page {
+        Ok(page) => page,
+        Err(e) => {
+            warn!("TIFF: {}.", e);
+            return -1;
+        }
+    };
+    match page.compression {
+        COMPRESSION_OJPEG | COMPRESSION_JPEG => {
+            warn!("TIFF: JPEG-compressed images are not supported.");
+            return -1;
+        }
+        _ => {}
+    }
+    info.width = page.width as i32;
+    info.height = page.height as i32;
+    let (xd, yd) = page_density(&page);
+    info.xdensity = xd;
+    info.ydensity = yd;
+
+    if let COMPRESSION_CCITT_RLE | COMPRESSION_CCITT_T4 | COMPRESSION_CCITT_T6 = page.compression {
+        info.bits_per_component = 1;
+        info.num_components = 1;
+        let stream = pdf_new_stream(0);
+        if let Err(e) = include_ccitt(&page, stream) {
+            warn!("TIFF: {}.", e);
+            pdf_release_obj(stream);
+            return -1;
+        }
+        pdf_ximage_set_image(ximage, &mut info, stream);
+        return 0;
+    }
+
+    let (colorspace, ncolors) = match page_colorspace(&page) {
+        Ok(cs) => cs,
+        Err(e) => {
+            warn!("TIFF: {}.", e);
+            return -1;
+        }
+    };
+    let samples = match decode_samples(&tiff, &page) {
+        Ok(samples) => samples,
+        Err(e) => {
+            warn!("TIFF: {}.", e);
+            pdf_release_obj(colorspace);
+            return -1;
+        }
+    };
+    let spp = page.samples_per_pixel;
+    let mut bpc = page.bits_per_sample as i32;
+    /* The first extra sample is alpha if it is marked as such. Like libtiff,
+     * we take an unmarked fourth sample of RGB images as associated alpha. */
+    let alpha_kind = match page.extra_samples.first() {
+        Some(&kind) if spp > ncolors && (kind == 1 || kind == 2) => kind,
+        None if page.photometric == PHOTOMETRIC_RGB && spp == 4 => 1,
+        _ => 0,
+    };
+    let (mut colors, mut alpha) = if spp > ncolors {
+        if bpc < 8 {
+            warn!("TIFF: Extra samples with less than 8 bits are not supported.");
+            pdf_release_obj(colorspace);
+            return -1;
+        }
+        split_samples(&samples, spp, ncolors, bpc as usize / 8, alpha_kind != 0)
+    } else {
+        (samples, Vec::new())
+    };
+    if bpc == 16 && pdf_get_version() < 5 {
+        warn!("TIFF: 16-bpc images require PDF version 1.5.");
+        colors = strip_16(&colors);
+        alpha = strip_16(&alpha);
+        bpc = 8;
+    }
+    if !alpha.is_empty() && pdf_ximage_is_opaque(&alpha, bpc) {
+        alpha.clear();
+    }
+    if !alpha.is_empty() && pdf_get_version() < 4 {
+        warn!("TIFF: Transparency will be ignored. (no support in PDF ver. < 1.4)");
+        warn!("TIFF: Please use -V 4 option to enable full alpha channel support.");
+        /* Palette indices cannot be blended; the mask is simply dropped. */
+        if page.photometric != PHOTOMETRIC_PALETTE {
+            let white = match page.photometric {
+                PHOTOMETRIC_MINISWHITE | PHOTOMETRIC_SEPARATED => 0,
+                _ => 255,
+            };
+            pdf_ximage_blend_white(&mut colors, &alpha, ncolors, alpha_kind == 1, white);
+        }
+        alpha.clear();
+    }
+
+    info.bits_per_component = bpc;
+    info.num_components = ncolors as i32;
+    let stream = pdf_new_stream(STREAM_COMPRESS);
+    let stream_dict = (*stream).as_stream_mut().get_dict_mut();
+    pdf_add_dict(stream_dict, "ColorSpace", colorspace);
+    if page.photometric == PHOTOMETRIC_MINISWHITE {
+        let decode = pdf_new_array();
+        pdf_add_array(&mut *decode, pdf_new_number(1.));
+        pdf_add_array(&mut *decode, pdf_new_number(0.));
+        pdf_add_dict(stream_dict, "Decode", decode);
+    }
+    if !alpha.is_empty() {
+        let matte = if alpha_kind == 1 { Some(ncolors) } else { None };
+        let smask = pdf_ximage_soft_mask(&alpha, info.width, info.height, bpc, matte);
+        pdf_add_dict(stream_dict, "SMask", pdf_ref_obj(smask));
+        pdf_release_obj(smask);
+    }
+    pdf_add_stream(
+        &mut *stream,
+        colors.as_ptr() as *const libc::c_void,
+        colors.len() as i32,
+    );
+    if page.photometric != PHOTOMETRIC_PALETTE && bpc >= 8 && info.height > 64 {
+        pdf_stream_set_predictor(stream, 15, info.width, bpc, ncolors as i32);
+    }
+    pdf_ximage_set_image(ximage, &mut info, stream);
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lzw_codes() {
+        /* Clear, 'a', 'b', 258 ("ab"), EOI in 9-bit codes. */
+        let data = [0x80, 0x18, 0x4c, 0x50, 0x28, 0x08];
+        assert_eq!(lzw_decode(&data, 4).unwrap(), b"abab".to_vec());
+        assert!(lzw_decode(&[0x00, 0x01], 1).is_err());
+    }
+
+    #[test]
+    fn packbits() {
+        let data = [0x02, 1, 2, 3, 0xfe, 9, 0x80, 0x00, 7];
+        assert_eq!(packbits_decode(&data, 7), vec![1, 2, 3, 9, 9, 9, 7]);
+    }
+
+    #[test]
+    fn pages() {
+        /* Two 1x1 8-bit gray pages in a little-endian file. */
+        let mut data = b"II*\x00\x08\x00\x00\x00".to_vec();
+        for (i, next) in [(0u8, 80u32), (1, 0)].iter() {
+            let base = data.len() as u32;
+            data.extend_from_slice(&5u16.to_le_bytes());
+            for &(tag, value) in [
+                (256u16, 1u32),
+                (257, 1),
+                (273, base + 66),
+                (279, 1),
+                (262, 1),
+            ]
+            .iter()
+            {
+                data.extend_from_slice(&tag.to_le_bytes());
+                data.extend_from_slice(&4u16.to_le_bytes());
+                data.extend_from_slice(&1u32.to_le_bytes());
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            data.extend_from_slice(&next.to_le_bytes());
+            data.push(0x40 + i);
+            data.resize(base as usize + 72, 0);
+        }
+        let (tiff, offset) = select_page(&data, -1, None).unwrap();
+        let page = read_page(&tiff, offset).unwrap();
+        assert_eq!(page.segments, vec![&[0x41u8][..]]);
+        assert_eq!(unsafe { decode_samples(&tiff, &page) }.unwrap(), vec![0x41]);
+        let mut count = 0;
+        assert!(select_page(&data, 0, Some(&mut count)).is_ok());
+        assert_eq!(count, 2);
+        assert!(select_page(&data, 3, None).is_err());
+    }
+}
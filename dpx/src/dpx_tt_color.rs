@@ -0,0 +1,465 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2002-2016 by Jin-Hwan Cho and Shunsaku Hirata,
+    the dvipdfmx project team.
+
+    Copyright (C) 1998, 1999 by Mark A. Wicks <mwicks@kettering.edu>
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! Color glyphs of OpenType fonts.
+//!
+//! Three kinds are supported: layered glyphs from `COLR` (version 0) with
+//! colors from `CPAL`, and bitmap glyphs from either `sbix` or
+//! `CBLC`/`CBDT`. Layers are glyphs of the font itself, which are drawn on
+//! top of each other, each in its own color; bitmaps are placed as images.
+
+use std::io::Read;
+
+use super::dpx_sfnt::{sfnt, sfnt_find_table_len, sfnt_locate_table};
+
+fn get_u8(data: &[u8], pos: usize) -> Option<u8> {
+    data.get(pos).cloned()
+}
+
+fn get_u16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]))
+}
+
+fn get_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes([
+        *data.get(pos)?,
+        *data.get(pos + 1)?,
+        *data.get(pos + 2)?,
+        *data.get(pos + 3)?,
+    ]))
+}
+
+/// One layer of a `COLR` glyph.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layer {
+    pub gid: u16,
+    /// RGBA color, or `None` for the current text color.
+    pub color: Option<[u8; 4]>,
+}
+
+/// A bitmap glyph. Positions and sizes are in pixels of a font of `ppem`
+/// pixels per em; (`x`, `y`) is the lower left corner of the image relative
+/// to the glyph origin.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bitmap<'a> {
+    pub data: &'a [u8],
+    pub ppem: u16,
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+pub enum ColorGlyph<'a> {
+    Layers(Vec<Layer>),
+    Bitmap(Bitmap<'a>),
+}
+
+/// The color tables of a font.
+pub struct ColorGlyphs {
+    colr: Option<Vec<u8>>,
+    cpal: Option<Vec<u8>>,
+    sbix: Option<Vec<u8>>,
+    cblc: Option<Vec<u8>>,
+    cbdt: Option<Vec<u8>>,
+    num_glyphs: u16,
+}
+
+unsafe fn read_table(sfont: *mut sfnt, tag: &[u8; 4]) -> Option<Vec<u8>> {
+    let len = sfnt_find_table_len(sfont, tag) as usize;
+    if len == 0 {
+        return None;
+    }
+    sfnt_locate_table(sfont, tag);
+    let mut data = vec![0u8; len];
+    (*sfont).handle.read_exact(&mut data).ok()?;
+    Some(data)
+}
+
+/// Width and height of PNG data, from its header.
+fn png_size(data: &[u8]) -> Option<(u32, u32)> {
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") || data.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    Some((get_u32(data, 16)?, get_u32(data, 20)?))
+}
+
+impl ColorGlyphs {
+    /// Reads the color tables of a font, if it has any.
+    pub unsafe fn new(sfont: *mut sfnt, num_glyphs: u16) -> Option<Self> {
+        let mut colr = read_table(sfont, b"COLR");
+        let cpal = read_table(sfont, b"CPAL");
+        if cpal.is_none() {
+            colr = None;
+        }
+        let sbix = read_table(sfont, b"sbix");
+        let (mut cblc, cbdt) = (read_table(sfont, b"CBLC"), read_table(sfont, b"CBDT"));
+        if cbdt.is_none() {
+            cblc = None;
+        }
+        if colr.is_none() && sbix.is_none() && cblc.is_none() {
+            return None;
+        }
+        Some(ColorGlyphs {
+            colr,
+            cpal,
+            sbix,
+            cblc,
+            cbdt,
+            num_glyphs,
+        })
+    }
+
+    /// Looks up the color version of a glyph. `COLR` layers are preferred
+    /// over bitmaps.
+    pub fn lookup(&self, gid: u16) -> Option<ColorGlyph<'_>> {
+        if let Some(layers) = self.layers(gid) {
+            return Some(ColorGlyph::Layers(layers));
+        }
+        self.sbix_bitmap(gid, 0)
+            .or_else(|| self.cbdt_bitmap(gid))
+            .map(ColorGlyph::Bitmap)
+    }
+
+    /// The layers of a glyph in `COLR`, with colors of the first palette.
+    fn layers(&self, gid: u16) -> Option<Vec<Layer>> {
+        let colr = self.colr.as_ref()?;
+        let cpal = self.cpal.as_ref()?;
+        let num_base = get_u16(colr, 2)? as usize;
+        let base_offset = get_u32(colr, 4)? as usize;
+        let layer_offset = get_u32(colr, 8)? as usize;
+        let num_layers = get_u16(colr, 12)? as usize;
+        /* Base glyph records are sorted by glyph ID. */
+        let (mut lo, mut hi) = (0, num_base);
+        let record = loop {
+            if lo >= hi {
+                return None;
+            }
+            let mid = (lo + hi) / 2;
+            let p = base_offset + 6 * mid;
+            let g = get_u16(colr, p)?;
+            if g == gid {
+                break p;
+            } else if g < gid {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        };
+        let first = get_u16(colr, record + 2)? as usize;
+        let count = get_u16(colr, record + 4)? as usize;
+        if first + count > num_layers {
+            return None;
+        }
+        let num_entries = get_u16(cpal, 2)? as usize;
+        let records = get_u32(cpal, 8)? as usize;
+        let palette = get_u16(cpal, 12)? as usize;
+        let mut layers = Vec::with_capacity(count);
+        for i in first..first + count {
+            let p = layer_offset + 4 * i;
+            let layer_gid = get_u16(colr, p)?;
+            let index = get_u16(colr, p + 2)?;
+            let color = if index == 0xffff || index as usize >= num_entries {
+                None
+            } else {
+                /* Color records are BGRA. */
+                let c = records + 4 * (palette + index as usize);
+                Some([
+                    get_u8(cpal, c + 2)?,
+                    get_u8(cpal, c + 1)?,
+                    get_u8(cpal, c)?,
+                    get_u8(cpal, c + 3)?,
+                ])
+            };
+            layers.push(Layer {
+                gid: layer_gid,
+                color,
+            });
+        }
+        Some(layers)
+    }
+
+    /// The bitmap of a glyph in the `sbix` strike with the most pixels per
+    /// em. `depth` limits how many "dupe" references are followed.
+    fn sbix_bitmap(&self, gid: u16, depth: u32) -> Option<Bitmap<'_>> {
+        let sbix = self.sbix.as_ref()?;
+        if gid >= self.num_glyphs {
+            return None;
+        }
+        let num_strikes = get_u32(sbix, 4)? as usize;
+        let strike = (0..num_strikes)
+            .filter_map(|i| get_u32(sbix, 8 + 4 * i).map(|o| o as usize))
+            .filter(|&o| get_u16(sbix, o).is_some())
+            .max_by_key(|&o| get_u16(sbix, o).unwrap_or(0))?;
+        let ppem = get_u16(sbix, strike)?;
+        let start = strike + get_u32(sbix, strike + 4 + 4 * gid as usize)? as usize;
+        let end = strike + get_u32(sbix, strike + 8 + 4 * gid as usize)? as usize;
+        if end <= start + 8 {
+            return None;
+        }
+        let x = get_u16(sbix, start)? as i16 as f64;
+        let y = get_u16(sbix, start + 2)? as i16 as f64;
+        let kind = sbix.get(start + 4..start + 8)?;
+        let data = sbix.get(start + 8..end)?;
+        match kind {
+            b"png " => {
+                let (width, height) = png_size(data)?;
+                Some(Bitmap {
+                    data,
+                    ppem,
+                    x,
+                    y,
+                    width: width as f64,
+                    height: height as f64,
+                })
+            }
+            b"dupe" if depth < 4 => self.sbix_bitmap(get_u16(data, 0)?, depth + 1),
+            _ => None,
+        }
+    }
+
+    /// The bitmap of a glyph in the `CBLC` strike with the most pixels per
+    /// em that has it.
+    fn cbdt_bitmap(&self, gid: u16) -> Option<Bitmap<'_>> {
+        let cblc = self.cblc.as_ref()?;
+        let cbdt = self.cbdt.as_ref()?;
+        let num_sizes = get_u32(cblc, 4)? as usize;
+        let mut best: Option<(u8, usize)> = None;
+        for i in 0..num_sizes {
+            let p = 8 + 48 * i;
+            let (start, end) = (get_u16(cblc, p + 40)?, get_u16(cblc, p + 42)?);
+            let ppem = get_u8(cblc, p + 45)?;
+            if start <= gid && gid <= end && best.iter().all(|b| ppem > b.0) {
+                best = Some((ppem, p));
+            }
+        }
+        let (ppem, size) = best?;
+        let array = get_u32(cblc, size)? as usize;
+        let num_subtables = get_u32(cblc, size + 8)? as usize;
+        for i in 0..num_subtables {
+            let p = array + 8 * i;
+            let (first, last) = (get_u16(cblc, p)?, get_u16(cblc, p + 2)?);
+            if gid < first || gid > last {
+                continue;
+            }
+            let sub = array + get_u32(cblc, p + 4)? as usize;
+            let index_format = get_u16(cblc, sub)?;
+            let image_format = get_u16(cblc, sub + 2)?;
+            let image_offset = get_u32(cblc, sub + 4)? as usize;
+            let k = (gid - first) as usize;
+            let mut metrics = None;
+            let (offset, len) = match index_format {
+                1 => {
+                    let a = get_u32(cblc, sub + 8 + 4 * k)? as usize;
+                    let b = get_u32(cblc, sub + 12 + 4 * k)? as usize;
+                    (a, b.checked_sub(a)?)
+                }
+                2 => {
+                    let size = get_u32(cblc, sub + 8)? as usize;
+                    metrics = Some(sub + 12);
+                    (size * k, size)
+                }
+                3 => {
+                    let a = get_u16(cblc, sub + 8 + 2 * k)? as usize;
+                    let b = get_u16(cblc, sub + 10 + 2 * k)? as usize;
+                    (a, b.checked_sub(a)?)
+                }
+                4 => {
+                    let n = get_u32(cblc, sub + 8)? as usize;
+                    let j = (0..n).find(|&j| get_u16(cblc, sub + 12 + 4 * j) == Some(gid))?;
+                    let a = get_u16(cblc, sub + 14 + 4 * j)? as usize;
+                    let b = get_u16(cblc, sub + 18 + 4 * j)? as usize;
+                    (a, b.checked_sub(a)?)
+                }
+                5 => {
+                    let size = get_u32(cblc, sub + 8)? as usize;
+                    metrics = Some(sub + 12);
+                    let n = get_u32(cblc, sub + 20)? as usize;
+                    let j = (0..n).find(|&j| get_u16(cblc, sub + 24 + 2 * j) == Some(gid))?;
+                    (size * j, size)
+                }
+                _ => return None,
+            };
+            if len == 0 {
+                return None;
+            }
+            let glyph = cbdt.get(image_offset + offset..image_offset + offset + len)?;
+            /* Height, width, bearingX and bearingY come first in both the
+             * small and the big glyph metrics. */
+            let (m, data) = match image_format {
+                17 => (glyph, glyph.get(9..9 + get_u32(glyph, 5)? as usize)?),
+                18 => (glyph, glyph.get(12..12 + get_u32(glyph, 8)? as usize)?),
+                19 => (
+                    cblc.get(metrics?..)?,
+                    glyph.get(4..4 + get_u32(glyph, 0)? as usize)?,
+                ),
+                _ => return None,
+            };
+            let height = get_u8(m, 0)? as f64;
+            let width = get_u8(m, 1)? as f64;
+            let x = get_u8(m, 2)? as i8 as f64;
+            let y = get_u8(m, 3)? as i8 as f64 - height;
+            return Some(Bitmap {
+                data,
+                ppem: ppem as u16,
+                x,
+                y,
+                width,
+                height,
+            });
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn glyphs() -> ColorGlyphs {
+        ColorGlyphs {
+            colr: None,
+            cpal: None,
+            sbix: None,
+            cblc: None,
+            cbdt: None,
+            num_glyphs: 4,
+        }
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        data.extend_from_slice(&width.to_be_bytes());
+        data.extend_from_slice(&height.to_be_bytes());
+        data.extend_from_slice(&[8, 6, 0, 0, 0]);
+        data
+    }
+
+    #[test]
+    fn colr_layers() {
+        /* Glyph 2 has layers 3 (red, half transparent) and 1 (text color). */
+        let mut colr = vec![0, 0, 0, 1, 0, 0, 0, 14, 0, 0, 0, 20, 0, 2];
+        colr.extend_from_slice(&[0, 2, 0, 0, 0, 2]);
+        colr.extend_from_slice(&[0, 3, 0, 0, 0, 1, 0xff, 0xff]);
+        let mut cpal = vec![0, 0, 0, 1, 0, 1, 0, 1, 0, 0, 0, 14, 0, 0];
+        cpal.extend_from_slice(&[0, 0, 255, 128]);
+        let mut g = glyphs();
+        g.colr = Some(colr);
+        g.cpal = Some(cpal);
+        match g.lookup(2) {
+            Some(ColorGlyph::Layers(layers)) => assert_eq!(
+                layers,
+                vec![
+                    Layer {
+                        gid: 3,
+                        color: Some([255, 0, 0, 128])
+                    },
+                    Layer {
+                        gid: 1,
+                        color: None
+                    },
+                ]
+            ),
+            _ => panic!("no layers for glyph 2"),
+        }
+        assert!(g.lookup(1).is_none());
+    }
+
+    #[test]
+    fn sbix_bitmaps() {
+        /* One strike at 40 ppem; glyph 1 is a PNG, glyph 2 a dupe of it. */
+        let image = png(36, 32);
+        let mut strike = vec![0, 40, 0, 72];
+        let offsets = [24, 24, 32 + image.len() as u32, 42 + image.len() as u32];
+        for &o in offsets.iter().chain(&[42 + image.len() as u32]) {
+            strike.extend_from_slice(&o.to_be_bytes());
+        }
+        strike.extend_from_slice(&[0, 2, 0xff, 0xfe]);
+        strike.extend_from_slice(b"png ");
+        strike.extend_from_slice(&image);
+        strike.extend_from_slice(&[0, 0, 0, 0]);
+        strike.extend_from_slice(b"dupe");
+        strike.extend_from_slice(&[0, 1]);
+        let mut sbix = vec![0, 1, 0, 1, 0, 0, 0, 1, 0, 0, 0, 12];
+        sbix.extend_from_slice(&strike);
+        let mut g = glyphs();
+        g.sbix = Some(sbix);
+        let expected = Bitmap {
+            data: &image,
+            ppem: 40,
+            x: 2.,
+            y: -2.,
+            width: 36.,
+            height: 32.,
+        };
+        match (g.lookup(1), g.lookup(2)) {
+            (Some(ColorGlyph::Bitmap(a)), Some(ColorGlyph::Bitmap(b))) => {
+                assert_eq!(a, expected);
+                assert_eq!(b, expected);
+            }
+            _ => panic!("no bitmaps"),
+        }
+        assert!(g.lookup(0).is_none());
+    }
+
+    #[test]
+    fn cbdt_bitmaps() {
+        /* One size at 109 ppem for glyphs 1-3, index format 1 and image
+         * format 17; glyph 2 has no bitmap. */
+        let image = png(136, 128);
+        let mut cbdt = vec![0, 3, 0, 0];
+        cbdt.extend_from_slice(&[128, 136, 0xfe, 100, 136]);
+        cbdt.extend_from_slice(&(image.len() as u32).to_be_bytes());
+        cbdt.extend_from_slice(&image);
+        let glyph_len = 9 + image.len() as u32;
+        let mut cblc = vec![0, 3, 0, 0, 0, 0, 0, 1];
+        cblc.extend_from_slice(&56u32.to_be_bytes());
+        cblc.extend_from_slice(&36u32.to_be_bytes());
+        cblc.extend_from_slice(&1u32.to_be_bytes());
+        cblc.extend_from_slice(&[0; 28]);
+        cblc.extend_from_slice(&[0, 1, 0, 3, 109, 109, 32, 1]);
+        cblc.extend_from_slice(&[0, 1, 0, 3, 0, 0, 0, 8]);
+        cblc.extend_from_slice(&[0, 1, 0, 17, 0, 0, 0, 4]);
+        for &o in &[0, glyph_len, glyph_len, glyph_len] {
+            cblc.extend_from_slice(&o.to_be_bytes());
+        }
+        let mut g = glyphs();
+        g.cblc = Some(cblc);
+        g.cbdt = Some(cbdt);
+        match g.lookup(1) {
+            Some(ColorGlyph::Bitmap(b)) => assert_eq!(
+                b,
+                Bitmap {
+                    data: &image,
+                    ppem: 109,
+                    x: -2.,
+                    y: -28.,
+                    width: 136.,
+                    height: 128.,
+                }
+            ),
+            _ => panic!("no bitmap for glyph 1"),
+        }
+        assert!(g.lookup(2).is_none());
+        assert!(g.lookup(0).is_none());
+    }
+}
@@ -37,11 +37,12 @@ use super::dpx_tt_table::{
     tt_read_hhea_table, tt_read_longMetrics, tt_read_maxp_table, tt_read_os2__table,
     tt_read_vhea_table,
 };
+use super::dpx_tt_var::VarInstance;
 use crate::dpx_truetype::sfnt_table_info;
 use crate::{ttstub_input_read};
 use libc::{free, memcpy, memset};
 
-use std::io::{Seek, SeekFrom};
+use std::io::{Read, Seek, SeekFrom};
 use std::slice;
 
 pub type __ssize_t = i64;
@@ -77,6 +78,7 @@ pub struct tt_glyphs {
     pub default_tsb: i16,
     pub gd: *mut tt_glyph_desc,
     pub used_slot: *mut u8,
+    pub var: *mut VarInstance,
 }
 
 use super::dpx_tt_table::tt_longMetrics;
@@ -181,9 +183,43 @@ pub unsafe extern "C" fn tt_build_init() -> *mut tt_glyphs {
     (*g).used_slot =
         new((8192_u64).wrapping_mul(::std::mem::size_of::<u8>() as u64) as u32) as *mut u8;
     memset((*g).used_slot as *mut libc::c_void, 0i32, 8192);
+    (*g).var = 0 as *mut VarInstance;
     tt_add_glyph(g, 0_u16, 0_u16);
     g
 }
+/* Glyphs are taken from the given instance of a variable font. */
+pub unsafe fn tt_set_variation(mut g: *mut tt_glyphs, var: VarInstance) {
+    assert!(!g.is_null());
+    if !(*g).var.is_null() {
+        drop(Box::from_raw((*g).var));
+    }
+    (*g).var = Box::into_raw(Box::new(var));
+}
+/* Outlines and metrics of the glyph at idx for the variable font instance. */
+unsafe fn tt_apply_variation(mut g: *mut tt_glyphs, idx: i32) {
+    let gd = &mut *(*g).gd.offset(idx as isize);
+    if gd.data.is_null() {
+        gd.advw = (*(*g).var).advance_width(gd.ogid, &[], gd.advw, gd.lsb);
+        return;
+    }
+    let mut bbox = [gd.llx, gd.lly, gd.urx, gd.ury];
+    let data = slice::from_raw_parts(gd.data, gd.length as usize);
+    if let Some(data) = (*(*g).var).apply_glyph(gd.ogid, data, &mut gd.advw, &mut gd.lsb, &mut bbox)
+    {
+        free(gd.data as *mut libc::c_void);
+        gd.data = new(data.len() as u32) as *mut u8;
+        memcpy(
+            gd.data as *mut libc::c_void,
+            data.as_ptr() as *const libc::c_void,
+            data.len(),
+        );
+        gd.length = data.len() as u32;
+        gd.llx = bbox[0];
+        gd.lly = bbox[1];
+        gd.urx = bbox[2];
+        gd.ury = bbox[3];
+    }
+}
 #[no_mangle]
 pub unsafe extern "C" fn tt_build_finish(mut g: *mut tt_glyphs) {
     if !g.is_null() {
@@ -194,6 +230,9 @@ pub unsafe extern "C" fn tt_build_finish(mut g: *mut tt_glyphs) {
             free((*g).gd as *mut libc::c_void);
         }
         free((*g).used_slot as *mut libc::c_void);
+        if !(*g).var.is_null() {
+            drop(Box::from_raw((*g).var));
+        }
         free(g as *mut libc::c_void);
     };
 }
@@ -256,15 +295,23 @@ pub unsafe extern "C" fn tt_build_tables(mut sfont: *mut sfnt, mut g: *mut tt_gl
     } else {
         vmtx = 0 as *mut tt_longMetrics
     }
-    sfnt_locate_table(sfont, sfnt_table_info::LOCA);
+    /* Fonts with bitmap glyphs only (CBDT) have no outlines at all. */
+    let has_outlines = sfnt_find_table_pos(sfont, sfnt_table_info::LOCA) > 0_u32
+        && sfnt_find_table_pos(sfont, sfnt_table_info::GLYF) > 0_u32;
     let location = new((((*maxp).numGlyphs as i32 + 1i32) as u32 as u64)
         .wrapping_mul(::std::mem::size_of::<u32>() as u64) as u32) as *mut u32; /* Estimate most frequently appeared width */
-    if (*head).indexToLocFormat as i32 == 0i32 {
+    if !has_outlines {
+        for i in 0..=(*maxp).numGlyphs as usize {
+            *location.offset(i as isize) = 0_u32;
+        }
+    } else if (*head).indexToLocFormat as i32 == 0i32 {
+        sfnt_locate_table(sfont, sfnt_table_info::LOCA);
         for i in 0..=(*maxp).numGlyphs as i32 {
             *location.offset(i as isize) =
                 (2_u32).wrapping_mul(tt_get_unsigned_pair(&mut (*sfont).handle) as u32);
         }
     } else if (*head).indexToLocFormat as i32 == 1i32 {
+        sfnt_locate_table(sfont, sfnt_table_info::LOCA);
         for i in 0..=(*maxp).numGlyphs as i32 {
             *location.offset(i as isize) = tt_get_unsigned_quad(&mut (*sfont).handle);
         }
@@ -281,7 +328,11 @@ pub unsafe extern "C" fn tt_build_tables(mut sfont: *mut sfnt, mut g: *mut tt_gl
     /*
      * Read glyf table.
      */
-    let offset = sfnt_locate_table(sfont, sfnt_table_info::GLYF);
+    let offset = if has_outlines {
+        sfnt_locate_table(sfont, sfnt_table_info::GLYF)
+    } else {
+        0_u32
+    };
     /*
      * The num_glyphs may grow when composite glyph is found.
      * A component of glyph refered by a composite glyph is appended
@@ -312,14 +363,6 @@ pub unsafe extern "C" fn tt_build_tables(mut sfont: *mut sfnt, mut g: *mut tt_gl
         (*(*g).gd.offset(i as isize)).length = len;
         let ref mut fresh2 = (*(*g).gd.offset(i as isize)).data;
         *fresh2 = 0 as *mut u8;
-        if (*(*g).gd.offset(i as isize)).advw as i32 <= (*g).emsize as i32 {
-            let ref mut fresh3 = *w_stat.offset((*(*g).gd.offset(i as isize)).advw as isize);
-            *fresh3 = (*fresh3 as i32 + 1i32) as u16
-        } else {
-            let ref mut fresh4 = *w_stat.offset(((*g).emsize as i32 + 1i32) as isize);
-            *fresh4 = (*fresh4 as i32 + 1i32) as u16
-            /* larger than em */
-        }
         if !(len == 0_u32) {
             if len < 10_u32 {
                 panic!("Invalid TrueType glyph data (gid {}).", gid);
@@ -430,6 +473,23 @@ pub unsafe extern "C" fn tt_build_tables(mut sfont: *mut sfnt, mut g: *mut tt_gl
             }
         }
         /* Does not contains any data. */
+        if !(*g).var.is_null() {
+            tt_apply_variation(g, i);
+            if vmtx.is_null() && len != 0 {
+                (*(*g).gd.offset(i as isize)).tsb = ((*g).default_advh as i32
+                    - (*g).default_tsb as i32
+                    - (*(*g).gd.offset(i as isize)).ury as i32)
+                    as i16
+            }
+        }
+        if (*(*g).gd.offset(i as isize)).advw as i32 <= (*g).emsize as i32 {
+            let ref mut fresh3 = *w_stat.offset((*(*g).gd.offset(i as isize)).advw as isize);
+            *fresh3 = (*fresh3 as i32 + 1i32) as u16
+        } else {
+            let ref mut fresh4 = *w_stat.offset(((*g).emsize as i32 + 1i32) as isize);
+            *fresh4 = (*fresh4 as i32 + 1i32) as u16
+            /* larger than em */
+        }
     }
     free(location as *mut libc::c_void);
     free(hmtx as *mut libc::c_void);
@@ -679,15 +739,23 @@ pub unsafe extern "C" fn tt_get_metrics(mut sfont: *mut sfnt, mut g: *mut tt_gly
     } else {
         vmtx = 0 as *mut tt_longMetrics
     }
-    sfnt_locate_table(sfont, sfnt_table_info::LOCA);
+    /* Fonts with bitmap glyphs only (CBDT) have no outlines at all. */
+    let has_outlines = sfnt_find_table_pos(sfont, sfnt_table_info::LOCA) > 0_u32
+        && sfnt_find_table_pos(sfont, sfnt_table_info::GLYF) > 0_u32;
     let location = new((((*maxp).numGlyphs as i32 + 1i32) as u32 as u64)
         .wrapping_mul(::std::mem::size_of::<u32>() as u64) as u32) as *mut u32;
-    if (*head).indexToLocFormat as i32 == 0i32 {
+    if !has_outlines {
+        for i in 0..=(*maxp).numGlyphs as usize {
+            *location.offset(i as isize) = 0_u32;
+        }
+    } else if (*head).indexToLocFormat as i32 == 0i32 {
+        sfnt_locate_table(sfont, sfnt_table_info::LOCA);
         for i in 0..=(*maxp).numGlyphs as u32 {
             *location.offset(i as isize) =
                 (2_u32).wrapping_mul(tt_get_unsigned_pair(&mut (*sfont).handle) as u32);
         }
     } else if (*head).indexToLocFormat as i32 == 1i32 {
+        sfnt_locate_table(sfont, sfnt_table_info::LOCA);
         for i in 0..=(*maxp).numGlyphs as u32 {
             *location.offset(i as isize) = tt_get_unsigned_quad(&mut (*sfont).handle);
         }
@@ -704,7 +772,11 @@ pub unsafe extern "C" fn tt_get_metrics(mut sfont: *mut sfnt, mut g: *mut tt_gly
     /*
      * Read glyf table.
      */
-    let offset = sfnt_locate_table(sfont, sfnt_table_info::GLYF); /* old gid */
+    let offset = if has_outlines {
+        sfnt_locate_table(sfont, sfnt_table_info::GLYF)
+    } else {
+        0_u32
+    }; /* old gid */
     for i in 0..(*g).num_glyphs as u32 {
         let gid = (*(*g).gd.offset(i as isize)).ogid;
         if gid as i32 >= (*maxp).numGlyphs as i32 {
@@ -724,14 +796,6 @@ pub unsafe extern "C" fn tt_get_metrics(mut sfont: *mut sfnt, mut g: *mut tt_gly
         (*(*g).gd.offset(i as isize)).length = len;
         let ref mut fresh7 = (*(*g).gd.offset(i as isize)).data;
         *fresh7 = 0 as *mut u8;
-        if (*(*g).gd.offset(i as isize)).advw as i32 <= (*g).emsize as i32 {
-            let ref mut fresh8 = *w_stat.offset((*(*g).gd.offset(i as isize)).advw as isize);
-            *fresh8 = (*fresh8 as i32 + 1i32) as u16
-        } else {
-            let ref mut fresh9 = *w_stat.offset(((*g).emsize as i32 + 1i32) as isize);
-            *fresh9 = (*fresh9 as i32 + 1i32) as u16
-            /* larger than em */
-        }
         if !(len == 0_u32) {
             if len < 10_u32 {
                 panic!("Invalid TrueType glyph data (gid {}).", gid);
@@ -754,6 +818,27 @@ pub unsafe extern "C" fn tt_get_metrics(mut sfont: *mut sfnt, mut g: *mut tt_gly
             }
         }
         /* Does not contains any data. */
+        if !(*g).var.is_null() {
+            /* Only the advance width is needed here, which may depend on the outline. */
+            let mut glyph = vec![0u8; len as usize];
+            if len != 0 {
+                let handle = &mut (*sfont).handle;
+                handle
+                    .seek(SeekFrom::Start(offset as u64 + loc as u64))
+                    .unwrap();
+                handle.read_exact(&mut glyph).unwrap();
+            }
+            let gd = &mut *(*g).gd.offset(i as isize);
+            gd.advw = (*(*g).var).advance_width(gid, &glyph, gd.advw, gd.lsb);
+        }
+        if (*(*g).gd.offset(i as isize)).advw as i32 <= (*g).emsize as i32 {
+            let ref mut fresh8 = *w_stat.offset((*(*g).gd.offset(i as isize)).advw as isize);
+            *fresh8 = (*fresh8 as i32 + 1i32) as u16
+        } else {
+            let ref mut fresh9 = *w_stat.offset(((*g).emsize as i32 + 1i32) as isize);
+            *fresh9 = (*fresh9 as i32 + 1i32) as u16
+            /* larger than em */
+        }
     }
     free(location as *mut libc::c_void);
     free(hmtx as *mut libc::c_void);
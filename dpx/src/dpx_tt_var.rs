@@ -0,0 +1,1092 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2002-2016 by Jin-Hwan Cho and Shunsaku Hirata,
+    the dvipdfmx project team.
+
+    Copyright (C) 1998, 1999 by Mark A. Wicks <mwicks@kettering.edu>
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! Static instances of TrueType variable fonts.
+//!
+//! XeTeX records the axis settings of a native font as a specification like
+//! `wght=650,wdth=90`. The settings are normalized through the `fvar` and
+//! `avar` tables, and each embedded glyph gets the `gvar` deltas of the
+//! instance applied to its outline. Advance widths come from `HVAR` if the
+//! font has one, and from the phantom points of `gvar` otherwise, so that
+//! the widths in the PDF match the ones used for positioning the glyphs.
+
+use std::io::Read;
+
+use super::dpx_sfnt::{sfnt, sfnt_find_table_len, sfnt_locate_table};
+use super::dpx_tt_table::tt_longMetrics;
+
+const ON_CURVE: u8 = 0x01;
+const X_SHORT: u8 = 0x02;
+const Y_SHORT: u8 = 0x04;
+const REPEAT: u8 = 0x08;
+const X_SAME: u8 = 0x10;
+const Y_SAME: u8 = 0x20;
+const OVERLAP_SIMPLE: u8 = 0x40;
+
+const ARGS_ARE_WORDS: u16 = 0x0001;
+const ARGS_ARE_XY_VALUES: u16 = 0x0002;
+const HAVE_SCALE: u16 = 0x0008;
+const MORE_COMPONENTS: u16 = 0x0020;
+const HAVE_XY_SCALE: u16 = 0x0040;
+const HAVE_2X2: u16 = 0x0080;
+
+fn get_u16(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]))
+}
+
+fn get_i16(data: &[u8], pos: usize) -> Option<i16> {
+    get_u16(data, pos).map(|v| v as i16)
+}
+
+fn get_u32(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes([
+        *data.get(pos)?,
+        *data.get(pos + 1)?,
+        *data.get(pos + 2)?,
+        *data.get(pos + 3)?,
+    ]))
+}
+
+fn get_f2dot14(data: &[u8], pos: usize) -> Option<f64> {
+    get_i16(data, pos).map(|v| v as f64 / 16384.0)
+}
+
+fn get_fixed(data: &[u8], pos: usize) -> Option<f64> {
+    get_u32(data, pos).map(|v| v as i32 as f64 / 65536.0)
+}
+
+fn round_i16(v: f64) -> i16 {
+    v.round()
+        .max(i16::min_value() as f64)
+        .min(i16::max_value() as f64) as i16
+}
+
+/// Parses an instance specification like `wght=650,wdth=90`. Tags shorter
+/// than four characters are padded with spaces.
+pub fn parse_variations(spec: &[u8]) -> Vec<([u8; 4], f64)> {
+    let mut settings = Vec::new();
+    for item in spec.split(|&c| c == b',') {
+        let eq = match item.iter().position(|&c| c == b'=') {
+            Some(eq) if (1..=4).contains(&eq) => eq,
+            _ => continue,
+        };
+        let mut tag = [b' '; 4];
+        tag[..eq].copy_from_slice(&item[..eq]);
+        if let Ok(value) = String::from_utf8_lossy(&item[eq + 1..])
+            .trim()
+            .parse::<f64>()
+        {
+            settings.push((tag, value));
+        }
+    }
+    settings
+}
+
+/// The inverse of `parse_variations()`.
+pub fn format_variations(settings: &[([u8; 4], f64)]) -> String {
+    settings
+        .iter()
+        .map(|(tag, value)| {
+            let tag = String::from_utf8_lossy(tag);
+            format!("{}={}", tag.trim_end(), value)
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Suffix for the PostScript name of an instance, like `wght650wdth90`, so
+/// that different instances of a font get different names in the PDF.
+pub fn instance_name_suffix(settings: &[([u8; 4], f64)]) -> String {
+    settings
+        .iter()
+        .map(|(tag, value)| {
+            let tag: String = tag
+                .iter()
+                .filter(|c| c.is_ascii_alphanumeric())
+                .map(|&c| c as char)
+                .collect();
+            format!("{}{}", tag, (value * 100000.0).round() / 100000.0)
+        })
+        .collect()
+}
+
+/// Maps the user value of an `fvar` axis to the normalized range -1..1.
+fn normalize(value: f64, min: f64, default: f64, max: f64) -> f64 {
+    let value = value.max(min).min(max);
+    if value < default && default > min {
+        (value - default) / (default - min)
+    } else if value > default && max > default {
+        (value - default) / (max - default)
+    } else {
+        0.0
+    }
+}
+
+/// Applies the piecewise linear `avar` segment map of an axis.
+fn apply_segment_map(value: f64, map: &[(f64, f64)]) -> f64 {
+    if map.is_empty() {
+        return value;
+    }
+    if value <= map[0].0 {
+        return map[0].1;
+    }
+    for w in map.windows(2) {
+        let (a, b) = (w[0], w[1]);
+        if value <= b.0 {
+            if b.0 == a.0 {
+                return b.1;
+            }
+            return a.1 + (b.1 - a.1) * (value - a.0) / (b.0 - a.0);
+        }
+    }
+    map[map.len() - 1].1
+}
+
+/// Normalizes the settings against the `fvar` (and optional `avar`) table,
+/// giving one coordinate per axis of the font.
+fn normalized_coords(fvar: &[u8], avar: Option<&[u8]>, settings: &[([u8; 4], f64)]) -> Vec<f64> {
+    let mut coords = Vec::new();
+    let offset = get_u16(fvar, 4).unwrap_or(0) as usize;
+    let count = get_u16(fvar, 8).unwrap_or(0) as usize;
+    let size = get_u16(fvar, 10).unwrap_or(0) as usize;
+    for i in 0..count {
+        let p = offset + i * size;
+        let (tag, min, default, max) = match (
+            get_u32(fvar, p),
+            get_fixed(fvar, p + 4),
+            get_fixed(fvar, p + 8),
+            get_fixed(fvar, p + 12),
+        ) {
+            (Some(tag), Some(min), Some(default), Some(max)) => (tag, min, default, max),
+            _ => break,
+        };
+        let value = settings
+            .iter()
+            .rev()
+            .find(|s| u32::from_be_bytes(s.0) == tag)
+            .map_or(default, |s| s.1);
+        coords.push(normalize(value, min, default, max));
+    }
+    if let Some(avar) = avar {
+        let mut p = 8;
+        for coord in coords.iter_mut() {
+            let n = match get_u16(avar, p) {
+                Some(n) => n as usize,
+                None => break,
+            };
+            let map: Vec<(f64, f64)> = (0..n)
+                .filter_map(|j| {
+                    let q = p + 2 + 4 * j;
+                    Some((get_f2dot14(avar, q)?, get_f2dot14(avar, q + 2)?))
+                })
+                .collect();
+            *coord = apply_segment_map(*coord, &map);
+            p += 2 + 4 * n;
+        }
+    }
+    /* Coordinates are F2Dot14 values. */
+    coords
+        .into_iter()
+        .map(|c| (c * 16384.0).round() / 16384.0)
+        .collect()
+}
+
+/// The scalar of a region or tuple for the instance: each axis contributes
+/// a factor that is 1 at the peak and falls off linearly to 0 at the
+/// region's start and end.
+fn region_scalar(coords: &[f64], start: &[f64], peak: &[f64], end: &[f64]) -> f64 {
+    let mut scalar = 1.0;
+    for (i, &p) in peak.iter().enumerate() {
+        let c = coords.get(i).cloned().unwrap_or(0.0);
+        let (s, e) = (start[i], end[i]);
+        if p == 0.0 || s > p || p > e || (s < 0.0 && e > 0.0) || c == p {
+            continue;
+        }
+        if c <= s || c >= e {
+            return 0.0;
+        }
+        scalar *= if c < p {
+            (c - s) / (p - s)
+        } else {
+            (e - c) / (e - p)
+        };
+    }
+    scalar
+}
+
+/// Reads packed point numbers; `None` means all points of the glyph.
+fn read_packed_points(data: &[u8], pos: &mut usize) -> Option<Option<Vec<usize>>> {
+    let first = *data.get(*pos)? as usize;
+    *pos += 1;
+    if first == 0 {
+        return Some(None);
+    }
+    let count = if first & 0x80 != 0 {
+        let second = *data.get(*pos)? as usize;
+        *pos += 1;
+        (first & 0x7f) << 8 | second
+    } else {
+        first
+    };
+    let mut points = Vec::with_capacity(count);
+    let mut last = 0;
+    while points.len() < count {
+        let control = *data.get(*pos)?;
+        *pos += 1;
+        let run = (control & 0x7f) as usize + 1;
+        for _ in 0..run {
+            let delta = if control & 0x80 != 0 {
+                let d = get_u16(data, *pos)? as usize;
+                *pos += 2;
+                d
+            } else {
+                let d = *data.get(*pos)? as usize;
+                *pos += 1;
+                d
+            };
+            last += delta;
+            points.push(last);
+        }
+    }
+    points.truncate(count);
+    Some(Some(points))
+}
+
+/// Reads `count` packed deltas.
+fn read_packed_deltas(data: &[u8], pos: &mut usize, count: usize) -> Option<Vec<f64>> {
+    let mut deltas = Vec::with_capacity(count);
+    while deltas.len() < count {
+        let control = *data.get(*pos)?;
+        *pos += 1;
+        let run = (control & 0x3f) as usize + 1;
+        for _ in 0..run {
+            let delta = if control & 0x80 != 0 {
+                0
+            } else if control & 0x40 != 0 {
+                let d = get_i16(data, *pos)? as i32;
+                *pos += 2;
+                d
+            } else {
+                let d = *data.get(*pos)? as i8 as i32;
+                *pos += 1;
+                d
+            };
+            deltas.push(delta as f64);
+        }
+    }
+    deltas.truncate(count);
+    Some(deltas)
+}
+
+/// Infers the deltas of the untouched points of one contour, one
+/// coordinate at a time ("interpolate untouched points").
+fn iup_contour(coords: &[f64], deltas: &mut [f64], touched: &[bool]) {
+    let n = coords.len();
+    let marks: Vec<usize> = (0..n).filter(|&i| touched[i]).collect();
+    if marks.is_empty() {
+        return;
+    }
+    if marks.len() == 1 {
+        let d = deltas[marks[0]];
+        for delta in deltas.iter_mut() {
+            *delta = d;
+        }
+        return;
+    }
+    for (k, &i1) in marks.iter().enumerate() {
+        let i2 = marks[(k + 1) % marks.len()];
+        let (c1, c2, d1, d2) = (coords[i1], coords[i2], deltas[i1], deltas[i2]);
+        let mut j = (i1 + 1) % n;
+        while j != i2 {
+            let c = coords[j];
+            deltas[j] = if c1 == c2 {
+                if d1 == d2 {
+                    d1
+                } else {
+                    0.0
+                }
+            } else {
+                let (lo, hi, dlo, dhi) = if c1 < c2 {
+                    (c1, c2, d1, d2)
+                } else {
+                    (c2, c1, d2, d1)
+                };
+                if c <= lo {
+                    dlo
+                } else if c >= hi {
+                    dhi
+                } else {
+                    dlo + (c - lo) * (dhi - dlo) / (hi - lo)
+                }
+            };
+            j = (j + 1) % n;
+        }
+    }
+}
+
+/// The outline of a simple glyph, as far as variations are concerned.
+struct SimpleGlyph {
+    end_pts: Vec<usize>,
+    instructions: Vec<u8>,
+    flags: Vec<u8>,
+    points: Vec<(f64, f64)>,
+}
+
+impl SimpleGlyph {
+    fn parse(data: &[u8]) -> Option<Self> {
+        let num_contours = get_i16(data, 0)? as usize;
+        let mut pos = 10;
+        let mut end_pts = Vec::with_capacity(num_contours);
+        for _ in 0..num_contours {
+            end_pts.push(get_u16(data, pos)? as usize);
+            pos += 2;
+        }
+        let num_points = end_pts.last().map_or(0, |&e| e + 1);
+        let ins_len = get_u16(data, pos)? as usize;
+        pos += 2;
+        let instructions = data.get(pos..pos + ins_len)?.to_vec();
+        pos += ins_len;
+        let mut flags = Vec::with_capacity(num_points);
+        while flags.len() < num_points {
+            let flag = *data.get(pos)?;
+            pos += 1;
+            flags.push(flag);
+            if flag & REPEAT != 0 {
+                let n = *data.get(pos)?;
+                pos += 1;
+                for _ in 0..n {
+                    flags.push(flag);
+                }
+            }
+        }
+        flags.truncate(num_points);
+        let mut xs = Vec::with_capacity(num_points);
+        let mut v = 0i32;
+        for &flag in &flags {
+            if flag & X_SHORT != 0 {
+                let d = *data.get(pos)? as i32;
+                pos += 1;
+                v += if flag & X_SAME != 0 { d } else { -d };
+            } else if flag & X_SAME == 0 {
+                v += get_i16(data, pos)? as i32;
+                pos += 2;
+            }
+            xs.push(v);
+        }
+        let mut points = Vec::with_capacity(num_points);
+        v = 0;
+        for (i, &flag) in flags.iter().enumerate() {
+            if flag & Y_SHORT != 0 {
+                let d = *data.get(pos)? as i32;
+                pos += 1;
+                v += if flag & Y_SAME != 0 { d } else { -d };
+            } else if flag & Y_SAME == 0 {
+                v += get_i16(data, pos)? as i32;
+                pos += 2;
+            }
+            points.push((xs[i] as f64, v as f64));
+        }
+        let flags = flags
+            .into_iter()
+            .map(|f| f & (ON_CURVE | OVERLAP_SIMPLE))
+            .collect();
+        Some(SimpleGlyph {
+            end_pts,
+            instructions,
+            flags,
+            points,
+        })
+    }
+
+    /// Writes the glyph out with rounded coordinates, returning the data
+    /// and its bounding box.
+    fn serialize(&self) -> (Vec<u8>, [i16; 4]) {
+        let points: Vec<(i16, i16)> = self
+            .points
+            .iter()
+            .map(|&(x, y)| (round_i16(x), round_i16(y)))
+            .collect();
+        let mut bbox = [0i16; 4];
+        if !points.is_empty() {
+            bbox = [
+                i16::max_value(),
+                i16::max_value(),
+                i16::min_value(),
+                i16::min_value(),
+            ];
+            for &(x, y) in &points {
+                bbox = [
+                    bbox[0].min(x),
+                    bbox[1].min(y),
+                    bbox[2].max(x),
+                    bbox[3].max(y),
+                ];
+            }
+        }
+        let mut out = Vec::new();
+        out.extend_from_slice(&(self.end_pts.len() as i16).to_be_bytes());
+        for v in &bbox {
+            out.extend_from_slice(&v.to_be_bytes());
+        }
+        for &e in &self.end_pts {
+            out.extend_from_slice(&(e as u16).to_be_bytes());
+        }
+        out.extend_from_slice(&(self.instructions.len() as u16).to_be_bytes());
+        out.extend_from_slice(&self.instructions);
+        let mut flags = Vec::with_capacity(points.len());
+        let mut xdata = Vec::new();
+        let mut ydata = Vec::new();
+        let (mut px, mut py) = (0i32, 0i32);
+        for (i, &(x, y)) in points.iter().enumerate() {
+            let mut flag = self.flags[i];
+            let (dx, dy) = (x as i32 - px, y as i32 - py);
+            if dx == 0 {
+                flag |= X_SAME;
+            } else if dx.abs() < 256 {
+                flag |= X_SHORT | if dx > 0 { X_SAME } else { 0 };
+                xdata.push(dx.abs() as u8);
+            } else {
+                xdata.extend_from_slice(&(dx as i16).to_be_bytes());
+            }
+            if dy == 0 {
+                flag |= Y_SAME;
+            } else if dy.abs() < 256 {
+                flag |= Y_SHORT | if dy > 0 { Y_SAME } else { 0 };
+                ydata.push(dy.abs() as u8);
+            } else {
+                ydata.extend_from_slice(&(dy as i16).to_be_bytes());
+            }
+            flags.push(flag);
+            px = x as i32;
+            py = y as i32;
+        }
+        out.extend_from_slice(&flags);
+        out.extend_from_slice(&xdata);
+        out.extend_from_slice(&ydata);
+        (out, bbox)
+    }
+}
+
+/// A component of a composite glyph; only the offset can vary.
+struct Component {
+    flags: u16,
+    gid: u16,
+    dx: f64,
+    dy: f64,
+    arg_bytes: [u8; 4],
+    transform: Vec<u8>,
+}
+
+fn parse_composite(data: &[u8]) -> Option<(Vec<Component>, Vec<u8>)> {
+    let mut components = Vec::new();
+    let mut pos = 10;
+    loop {
+        let flags = get_u16(data, pos)?;
+        let gid = get_u16(data, pos + 2)?;
+        pos += 4;
+        let mut arg_bytes = [0u8; 4];
+        let (dx, dy) = if flags & ARGS_ARE_WORDS != 0 {
+            arg_bytes.copy_from_slice(data.get(pos..pos + 4)?);
+            pos += 4;
+            (
+                get_i16(data, pos - 4)? as f64,
+                get_i16(data, pos - 2)? as f64,
+            )
+        } else {
+            arg_bytes[..2].copy_from_slice(data.get(pos..pos + 2)?);
+            pos += 2;
+            (arg_bytes[0] as i8 as f64, arg_bytes[1] as i8 as f64)
+        };
+        let tlen = if flags & HAVE_SCALE != 0 {
+            2
+        } else if flags & HAVE_XY_SCALE != 0 {
+            4
+        } else if flags & HAVE_2X2 != 0 {
+            8
+        } else {
+            0
+        };
+        let transform = data.get(pos..pos + tlen)?.to_vec();
+        pos += tlen;
+        components.push(Component {
+            flags,
+            gid,
+            dx,
+            dy,
+            arg_bytes,
+            transform,
+        });
+        if flags & MORE_COMPONENTS == 0 {
+            break;
+        }
+    }
+    Some((components, data[pos..].to_vec()))
+}
+
+fn serialize_composite(header: &[u8], components: &[Component], rest: &[u8]) -> Vec<u8> {
+    let mut out = header[..10].to_vec();
+    for c in components {
+        let mut flags = c.flags;
+        if flags & ARGS_ARE_XY_VALUES != 0 {
+            flags |= ARGS_ARE_WORDS;
+        }
+        out.extend_from_slice(&flags.to_be_bytes());
+        out.extend_from_slice(&c.gid.to_be_bytes());
+        if c.flags & ARGS_ARE_XY_VALUES != 0 {
+            out.extend_from_slice(&round_i16(c.dx).to_be_bytes());
+            out.extend_from_slice(&round_i16(c.dy).to_be_bytes());
+        } else if c.flags & ARGS_ARE_WORDS != 0 {
+            out.extend_from_slice(&c.arg_bytes);
+        } else {
+            out.extend_from_slice(&c.arg_bytes[..2]);
+        }
+        out.extend_from_slice(&c.transform);
+    }
+    out.extend_from_slice(rest);
+    out
+}
+
+/// Number of points `gvar` expects for a glyph, without phantom points.
+fn glyph_point_count(data: &[u8]) -> usize {
+    match get_i16(data, 0) {
+        None => 0,
+        Some(n) if n >= 0 => {
+            let n = n as usize;
+            if n == 0 {
+                0
+            } else {
+                get_u16(data, 10 + 2 * (n - 1)).map_or(0, |e| e as usize + 1)
+            }
+        }
+        Some(_) => parse_composite(data).map_or(0, |(c, _)| c.len()),
+    }
+}
+
+/// A normalized instance of a variable font, with the tables needed to
+/// compute its outlines and metrics.
+pub struct VarInstance {
+    coords: Vec<f64>,
+    gvar: Option<Vec<u8>>,
+    hvar: Option<Vec<u8>>,
+}
+
+unsafe fn read_table(sfont: *mut sfnt, tag: &[u8; 4]) -> Option<Vec<u8>> {
+    let len = sfnt_find_table_len(sfont, tag) as usize;
+    if len == 0 {
+        return None;
+    }
+    sfnt_locate_table(sfont, tag);
+    let mut data = vec![0u8; len];
+    (*sfont).handle.read_exact(&mut data).ok()?;
+    Some(data)
+}
+
+impl VarInstance {
+    /// Sets up the instance given by `spec` (see `parse_variations()`).
+    /// Returns `None` for fonts without variations, or for the default
+    /// instance, which needs no changes.
+    pub unsafe fn new(sfont: *mut sfnt, spec: &[u8]) -> Option<Self> {
+        let fvar = read_table(sfont, b"fvar")?;
+        let avar = read_table(sfont, b"avar");
+        let coords = normalized_coords(
+            &fvar,
+            avar.as_ref().map(|a| &a[..]),
+            &parse_variations(spec),
+        );
+        if coords.iter().all(|&c| c == 0.0) {
+            return None;
+        }
+        Some(VarInstance {
+            coords,
+            gvar: read_table(sfont, b"gvar"),
+            hvar: read_table(sfont, b"HVAR"),
+        })
+    }
+
+    fn from_coords(coords: Vec<f64>, gvar: Option<Vec<u8>>, hvar: Option<Vec<u8>>) -> Self {
+        VarInstance { coords, gvar, hvar }
+    }
+
+    /// The `GlyphVariationData` of a glyph in the `gvar` table.
+    fn glyph_variation_data(&self, gid: u16) -> Option<(&[u8], &[u8])> {
+        let gvar = self.gvar.as_ref()?;
+        let glyph_count = get_u16(gvar, 12)?;
+        if gid >= glyph_count {
+            return None;
+        }
+        let long = get_u16(gvar, 14)? & 1 != 0;
+        let data_offset = get_u32(gvar, 16)? as usize;
+        let (start, end) = if long {
+            let p = 20 + 4 * gid as usize;
+            (get_u32(gvar, p)? as usize, get_u32(gvar, p + 4)? as usize)
+        } else {
+            let p = 20 + 2 * gid as usize;
+            (
+                2 * get_u16(gvar, p)? as usize,
+                2 * get_u16(gvar, p + 2)? as usize,
+            )
+        };
+        if start >= end {
+            return None;
+        }
+        Some((gvar, gvar.get(data_offset + start..data_offset + end)?))
+    }
+
+    /// Computes the deltas of all points of a glyph, phantom points
+    /// included. For simple glyphs, `contours` gives the end point of each
+    /// contour, which is needed to infer the deltas of untouched points.
+    fn glyph_deltas(&self, gid: u16, points: &[(f64, f64)], contours: &[usize]) -> Vec<(f64, f64)> {
+        let mut total = vec![(0.0, 0.0); points.len()];
+        self.add_glyph_deltas(gid, points, contours, &mut total);
+        total
+    }
+
+    fn add_glyph_deltas(
+        &self,
+        gid: u16,
+        points: &[(f64, f64)],
+        contours: &[usize],
+        total: &mut [(f64, f64)],
+    ) -> Option<()> {
+        let (gvar, data) = self.glyph_variation_data(gid)?;
+        let axis_count = get_u16(gvar, 4)? as usize;
+        let shared_count = get_u16(gvar, 6)? as usize;
+        let shared_offset = get_u32(gvar, 8)? as usize;
+        let read_tuple = |buf: &[u8], pos: usize| -> Option<Vec<f64>> {
+            (0..axis_count)
+                .map(|a| get_f2dot14(buf, pos + 2 * a))
+                .collect()
+        };
+        let n = points.len();
+        let count_word = get_u16(data, 0)?;
+        let mut serialized = get_u16(data, 2)? as usize;
+        let mut header = 4;
+        let shared_points = if count_word & 0x8000 != 0 {
+            read_packed_points(data, &mut serialized)?
+        } else {
+            None
+        };
+        for _ in 0..(count_word & 0x0fff) {
+            let size = get_u16(data, header)? as usize;
+            let index = get_u16(data, header + 2)?;
+            header += 4;
+            let peak = if index & 0x8000 != 0 {
+                let t = read_tuple(data, header)?;
+                header += 2 * axis_count;
+                t
+            } else {
+                let i = (index & 0x0fff) as usize;
+                if i >= shared_count {
+                    return None;
+                }
+                read_tuple(gvar, shared_offset + 2 * axis_count * i)?
+            };
+            let (start, end) = if index & 0x4000 != 0 {
+                let s = read_tuple(data, header)?;
+                let e = read_tuple(data, header + 2 * axis_count)?;
+                header += 4 * axis_count;
+                (s, e)
+            } else {
+                (
+                    peak.iter().map(|&p| p.min(0.0)).collect(),
+                    peak.iter().map(|&p| p.max(0.0)).collect(),
+                )
+            };
+            let tuple = data.get(serialized..serialized + size)?;
+            serialized += size;
+            let scalar = region_scalar(&self.coords, &start, &peak, &end);
+            if scalar == 0.0 {
+                continue;
+            }
+            let mut pos = 0;
+            let point_numbers = if index & 0x2000 != 0 {
+                read_packed_points(tuple, &mut pos)?
+            } else {
+                shared_points.clone()
+            };
+            let count = point_numbers.as_ref().map_or(n, |p| p.len());
+            let xs = read_packed_deltas(tuple, &mut pos, count)?;
+            let ys = read_packed_deltas(tuple, &mut pos, count)?;
+            match point_numbers {
+                None => {
+                    for i in 0..n.min(count) {
+                        total[i].0 += scalar * xs[i];
+                        total[i].1 += scalar * ys[i];
+                    }
+                }
+                Some(numbers) => {
+                    let mut dx = vec![0.0; n];
+                    let mut dy = vec![0.0; n];
+                    let mut touched = vec![false; n];
+                    for (k, &p) in numbers.iter().enumerate() {
+                        if p < n {
+                            dx[p] += xs[k];
+                            dy[p] += ys[k];
+                            touched[p] = true;
+                        }
+                    }
+                    let mut first = 0;
+                    for &last in contours {
+                        if last >= n || last < first {
+                            break;
+                        }
+                        let xs: Vec<f64> = points[first..=last].iter().map(|p| p.0).collect();
+                        let ys: Vec<f64> = points[first..=last].iter().map(|p| p.1).collect();
+                        iup_contour(&xs, &mut dx[first..=last], &touched[first..=last]);
+                        iup_contour(&ys, &mut dy[first..=last], &touched[first..=last]);
+                        first = last + 1;
+                    }
+                    for i in 0..n {
+                        total[i].0 += scalar * dx[i];
+                        total[i].1 += scalar * dy[i];
+                    }
+                }
+            }
+        }
+        Some(())
+    }
+
+    /// The `HVAR` advance width delta of a glyph, if the font has `HVAR`.
+    fn hvar_advance_delta(&self, gid: u16) -> Option<f64> {
+        let hvar = self.hvar.as_ref()?;
+        let store = get_u32(hvar, 4)? as usize;
+        let mapping = get_u32(hvar, 8)? as usize;
+        let (outer, inner) = if mapping == 0 {
+            (0, gid as usize)
+        } else {
+            let format = *hvar.get(mapping)?;
+            let entry_format = *hvar.get(mapping + 1)? as usize;
+            let (count, data) = if format == 0 {
+                (get_u16(hvar, mapping + 2)? as usize, mapping + 4)
+            } else {
+                (get_u32(hvar, mapping + 2)? as usize, mapping + 6)
+            };
+            if count == 0 {
+                return Some(0.0);
+            }
+            let size = ((entry_format >> 4) & 3) + 1;
+            let inner_bits = (entry_format & 0x0f) + 1;
+            let i = (gid as usize).min(count - 1);
+            let mut entry = 0usize;
+            for b in 0..size {
+                entry = entry << 8 | *hvar.get(data + i * size + b)? as usize;
+            }
+            (entry >> inner_bits, entry & ((1 << inner_bits) - 1))
+        };
+        self.item_variation_delta(hvar, store, outer, inner)
+    }
+
+    /// Evaluates an item of an `ItemVariationStore` for the instance.
+    fn item_variation_delta(
+        &self,
+        table: &[u8],
+        store: usize,
+        outer: usize,
+        inner: usize,
+    ) -> Option<f64> {
+        let regions = store + get_u32(table, store + 2)? as usize;
+        if outer >= get_u16(table, store + 6)? as usize {
+            return None;
+        }
+        let ivd = store + get_u32(table, store + 8 + 4 * outer)? as usize;
+        let item_count = get_u16(table, ivd)? as usize;
+        let word_count_field = get_u16(table, ivd + 2)?;
+        let long_words = word_count_field & 0x8000 != 0;
+        let word_count = (word_count_field & 0x7fff) as usize;
+        let region_index_count = get_u16(table, ivd + 4)? as usize;
+        if inner >= item_count {
+            return None;
+        }
+        let (word_size, short_size) = if long_words { (4, 2) } else { (2, 1) };
+        let row_size = word_count * word_size + (region_index_count - word_count) * short_size;
+        let mut pos = ivd + 6 + 2 * region_index_count + inner * row_size;
+        let axis_count = get_u16(table, regions)? as usize;
+        let region_count = get_u16(table, regions + 2)? as usize;
+        let mut delta = 0.0;
+        for r in 0..region_index_count {
+            let value = if r < word_count {
+                let v = if long_words {
+                    get_u32(table, pos)? as i32
+                } else {
+                    get_i16(table, pos)? as i32
+                };
+                pos += word_size;
+                v
+            } else {
+                let v = if long_words {
+                    get_i16(table, pos)? as i32
+                } else {
+                    *table.get(pos)? as i8 as i32
+                };
+                pos += short_size;
+                v
+            };
+            let region = get_u16(table, ivd + 6 + 2 * r)? as usize;
+            if region >= region_count || value == 0 {
+                continue;
+            }
+            let base = regions + 4 + region * axis_count * 6;
+            let mut start = Vec::with_capacity(axis_count);
+            let mut peak = Vec::with_capacity(axis_count);
+            let mut end = Vec::with_capacity(axis_count);
+            for a in 0..axis_count {
+                start.push(get_f2dot14(table, base + 6 * a)?);
+                peak.push(get_f2dot14(table, base + 6 * a + 2)?);
+                end.push(get_f2dot14(table, base + 6 * a + 4)?);
+            }
+            delta += region_scalar(&self.coords, &start, &peak, &end) * value as f64;
+        }
+        Some(delta)
+    }
+
+    /// The advance width of a glyph in this instance. `glyph` is the glyph
+    /// data from `glyf`, which is only needed for fonts without `HVAR`.
+    pub fn advance_width(&self, gid: u16, glyph: &[u8], advw: u16, lsb: i16) -> u16 {
+        if let Some(delta) = self.hvar_advance_delta(gid) {
+            return (advw as f64 + delta).round().max(0.0) as u16;
+        }
+        let n = glyph_point_count(glyph);
+        let xmin = get_i16(glyph, 2).unwrap_or(0) as f64;
+        let mut points = vec![(0.0, 0.0); n];
+        let pp1 = xmin - lsb as f64;
+        points.push((pp1, 0.0));
+        points.push((pp1 + advw as f64, 0.0));
+        points.push((0.0, 0.0));
+        points.push((0.0, 0.0));
+        let deltas = self.glyph_deltas(gid, &points, &[]);
+        let left = pp1 + deltas[n].0;
+        let right = pp1 + advw as f64 + deltas[n + 1].0;
+        (right - left).round().max(0.0) as u16
+    }
+
+    /// Applies the instance to a glyph from `glyf`, updating its outline,
+    /// bounding box and horizontal metrics. `bbox` is (xMin, yMin, xMax,
+    /// yMax). Returns the new glyph data, or `None` if it is unchanged.
+    pub fn apply_glyph(
+        &self,
+        gid: u16,
+        glyph: &[u8],
+        advw: &mut u16,
+        lsb: &mut i16,
+        bbox: &mut [i16; 4],
+    ) -> Option<Vec<u8>> {
+        let new_advw = self.advance_width(gid, glyph, *advw, *lsb);
+        let pp1 = bbox[0] as f64 - *lsb as f64;
+        let phantom = [
+            (pp1, 0.0),
+            (pp1 + *advw as f64, 0.0),
+            (0.0, 0.0),
+            (0.0, 0.0),
+        ];
+        *advw = new_advw;
+        if glyph.len() < 10 {
+            return None;
+        }
+        let num_contours = get_i16(glyph, 0)?;
+        if num_contours >= 0 {
+            let mut simple = SimpleGlyph::parse(glyph)?;
+            let n = simple.points.len();
+            let mut points = simple.points.clone();
+            points.extend_from_slice(&phantom);
+            let deltas = self.glyph_deltas(gid, &points, &simple.end_pts);
+            for (p, d) in simple.points.iter_mut().zip(deltas.iter()) {
+                p.0 += d.0;
+                p.1 += d.1;
+            }
+            let (data, new_bbox) = simple.serialize();
+            *bbox = new_bbox;
+            *lsb = round_i16(new_bbox[0] as f64 - (pp1 + deltas[n].0));
+            Some(data)
+        } else {
+            let (mut components, rest) = parse_composite(glyph)?;
+            let n = components.len();
+            let mut points: Vec<(f64, f64)> = components.iter().map(|c| (c.dx, c.dy)).collect();
+            points.extend_from_slice(&phantom);
+            let deltas = self.glyph_deltas(gid, &points, &[]);
+            for (c, d) in components.iter_mut().zip(deltas.iter()) {
+                if c.flags & ARGS_ARE_XY_VALUES != 0 {
+                    c.dx += d.0;
+                    c.dy += d.1;
+                }
+            }
+            *lsb = round_i16(bbox[0] as f64 - (pp1 + deltas[n].0));
+            Some(serialize_composite(glyph, &components, &rest))
+        }
+    }
+
+    /// Updates the horizontal metrics of all glyphs for the instance; the
+    /// metrics are indexed by glyph ID.
+    pub unsafe fn apply_hmtx(&self, sfont: *mut sfnt, metrics: &mut [tt_longMetrics]) {
+        if self.hvar.is_some() {
+            for (gid, m) in metrics.iter_mut().enumerate() {
+                m.advance = self.advance_width(gid as u16, &[], m.advance, m.sideBearing);
+            }
+            return;
+        }
+        let (head, loca, glyf) = match (
+            read_table(sfont, b"head"),
+            read_table(sfont, b"loca"),
+            read_table(sfont, b"glyf"),
+        ) {
+            (Some(head), Some(loca), Some(glyf)) => (head, loca, glyf),
+            _ => return,
+        };
+        let long = get_i16(&head, 50) == Some(1);
+        let location = |gid: usize| -> Option<usize> {
+            if long {
+                get_u32(&loca, 4 * gid).map(|v| v as usize)
+            } else {
+                get_u16(&loca, 2 * gid).map(|v| 2 * v as usize)
+            }
+        };
+        for (gid, m) in metrics.iter_mut().enumerate() {
+            let glyph = match (location(gid), location(gid + 1)) {
+                (Some(start), Some(end)) if start < end => glyf.get(start..end).unwrap_or(&[]),
+                _ => &[],
+            };
+            m.advance = self.advance_width(gid as u16, glyph, m.advance, m.sideBearing);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variation_specs() {
+        let settings = parse_variations(b"wght=650,wdth=87.5,ital=-1,bogus");
+        assert_eq!(
+            settings,
+            vec![(*b"wght", 650.0), (*b"wdth", 87.5), (*b"ital", -1.0)]
+        );
+        assert_eq!(format_variations(&settings), "wght=650,wdth=87.5,ital=-1");
+        assert_eq!(parse_variations(b"opsz=12")[0].0, *b"opsz");
+        assert_eq!(parse_variations(b"XY=2")[0].0, *b"XY  ");
+        assert_eq!(instance_name_suffix(&settings), "wght650wdth87.5ital-1");
+    }
+
+    #[test]
+    fn normalization() {
+        assert_eq!(normalize(650.0, 100.0, 400.0, 900.0), 0.5);
+        assert_eq!(normalize(250.0, 100.0, 400.0, 900.0), -0.5);
+        assert_eq!(normalize(2000.0, 100.0, 400.0, 900.0), 1.0);
+        let map = [(-1.0, -1.0), (0.0, 0.0), (0.5, 0.8), (1.0, 1.0)];
+        assert_eq!(apply_segment_map(0.25, &map), 0.4);
+        assert_eq!(apply_segment_map(0.75, &map), 0.9);
+        assert_eq!(apply_segment_map(-0.5, &map), -0.5);
+    }
+
+    #[test]
+    fn scalars() {
+        assert_eq!(region_scalar(&[0.5], &[0.0], &[1.0], &[1.0]), 0.5);
+        assert_eq!(region_scalar(&[-0.5], &[0.0], &[1.0], &[1.0]), 0.0);
+        assert_eq!(region_scalar(&[0.75], &[0.5], &[1.0], &[1.0]), 0.5);
+        assert_eq!(
+            region_scalar(&[0.5, 0.3], &[0.0, 0.0], &[1.0, 0.0], &[1.0, 0.0]),
+            0.5
+        );
+    }
+
+    #[test]
+    fn packed_data() {
+        /* Three points: 1, 3 (bytes) and 259 (word). */
+        let data = [3u8, 0x01, 1, 2, 0x80, 0x01, 0x00];
+        let mut pos = 0;
+        let points = read_packed_points(&data, &mut pos).unwrap();
+        assert_eq!(points, Some(vec![1, 3, 259]));
+        assert_eq!(pos, data.len());
+        /* Two byte deltas, a word delta and three zeros. */
+        let data = [0x01u8, 5, 0xfb, 0x40, 0x01, 0x00, 0x82];
+        let mut pos = 0;
+        let deltas = read_packed_deltas(&data, &mut pos, 6).unwrap();
+        assert_eq!(deltas, vec![5.0, -5.0, 256.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn interpolate_untouched() {
+        let coords = [0.0, 50.0, 100.0, 150.0];
+        let mut deltas = [10.0, 0.0, 20.0, 0.0];
+        iup_contour(&coords, &mut deltas, &[true, false, true, false]);
+        assert_eq!(deltas, [10.0, 15.0, 20.0, 20.0]);
+        let mut deltas = [0.0, 7.0, 0.0, 0.0];
+        iup_contour(&coords, &mut deltas, &[false, true, false, false]);
+        assert_eq!(deltas, [7.0; 4]);
+    }
+
+    /// A triangle with one contour.
+    fn triangle() -> Vec<u8> {
+        let mut g = vec![0, 1, 0, 0, 0, 0, 0, 100, 0, 100];
+        g.extend_from_slice(&[0, 2, 0, 0]);
+        g.extend_from_slice(&[
+            ON_CURVE | X_SAME | Y_SAME,
+            ON_CURVE | X_SHORT | X_SAME | Y_SAME,
+        ]);
+        g.push(ON_CURVE | X_SHORT | Y_SHORT | Y_SAME);
+        g.extend_from_slice(&[100, 50, 100]);
+        g
+    }
+
+    #[test]
+    fn simple_glyph_round_trip() {
+        let data = triangle();
+        let glyph = SimpleGlyph::parse(&data).unwrap();
+        assert_eq!(glyph.points, vec![(0.0, 0.0), (100.0, 0.0), (50.0, 100.0)]);
+        let (out, bbox) = glyph.serialize();
+        assert_eq!(bbox, [0, 0, 100, 100]);
+        assert_eq!(SimpleGlyph::parse(&out).unwrap().points, glyph.points);
+        assert_eq!(glyph_point_count(&data), 3);
+    }
+
+    /// A `gvar` table for one glyph on one axis: at wght=1 the second point
+    /// moves right by 20 and the advance grows by 20; the first point stays
+    /// and the third point is inferred.
+    fn gvar_table() -> Vec<u8> {
+        let mut tuple = vec![3u8, 0x02, 0, 1, 3];
+        tuple.extend_from_slice(&[0x02, 0, 20, 20]);
+        tuple.extend_from_slice(&[0x82]);
+        let mut gvd = vec![0x00, 0x01, 0x00, 0x0a];
+        gvd.extend_from_slice(&(tuple.len() as u16).to_be_bytes());
+        gvd.extend_from_slice(&[0xa0, 0x00, 0x40, 0x00]);
+        gvd.extend_from_slice(&tuple);
+        let mut gvar = vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 1];
+        gvar.extend_from_slice(&28u32.to_be_bytes());
+        gvar.extend_from_slice(&0u32.to_be_bytes());
+        gvar.extend_from_slice(&(gvd.len() as u32).to_be_bytes());
+        gvar.extend_from_slice(&gvd);
+        gvar
+    }
+
+    #[test]
+    fn glyph_instances() {
+        let instance = VarInstance::from_coords(vec![0.5], Some(gvar_table()), None);
+        let (mut advw, mut lsb, mut bbox) = (120u16, 0i16, [0i16, 0, 100, 100]);
+        let out = instance
+            .apply_glyph(0, &triangle(), &mut advw, &mut lsb, &mut bbox)
+            .unwrap();
+        let glyph = SimpleGlyph::parse(&out).unwrap();
+        assert_eq!(glyph.points, vec![(0.0, 0.0), (110.0, 0.0), (55.0, 100.0)]);
+        assert_eq!((advw, lsb, bbox), (130, 0, [0, 0, 110, 100]));
+        let default = VarInstance::from_coords(vec![-0.5], Some(gvar_table()), None);
+        assert_eq!(default.advance_width(0, &triangle(), 120, 0), 120);
+    }
+}
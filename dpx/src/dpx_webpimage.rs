@@ -0,0 +1,2520 @@
+/* This is dvipdfmx, an eXtended version of dvipdfm by Mark A. Wicks.
+
+    Copyright (C) 2002-2016 by Jin-Hwan Cho and Shunsaku Hirata,
+    the dvipdfmx project team.
+
+    Copyright (C) 1998, 1999 by Mark A. Wicks <mwicks@kettering.edu>
+
+    This program is free software; you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation; either version 2 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program; if not, write to the Free Software
+    Foundation, Inc., 59 Temple Place, Suite 330, Boston, MA 02111-1307 USA.
+*/
+
+//! WebP images, lossy (VP8, RFC 6386) and lossless (VP8L), with alpha.
+//! Pixels are decoded to RGB, the way libwebp does by default, and the
+//! alpha channel becomes a soft mask. Of an animation, only the first frame
+//! is included.
+
+use std::io::{Read, Seek, SeekFrom};
+
+use crate::warn;
+
+use super::dpx_pdfcolor::{iccp_check_colorspace, iccp_load_profile, pdf_get_colorspace_reference};
+use super::dpx_pdfximage::{
+    pdf_ximage_blend_white, pdf_ximage_init_image_info, pdf_ximage_is_opaque, pdf_ximage_set_image,
+    pdf_ximage_soft_mask,
+};
+use crate::dpx_pdfobj::{
+    pdf_add_dict, pdf_add_stream, pdf_get_version, pdf_new_name, pdf_new_stream, pdf_obj,
+    pdf_ref_obj, pdf_release_obj, pdf_stream_set_predictor, STREAM_COMPRESS,
+};
+
+use bridge::InputHandleWrapper;
+
+use crate::dpx_pdfximage::{pdf_ximage, ximage_info};
+
+/// A decoded image: RGB samples and, if the image has one, an alpha channel.
+struct Picture {
+    width: usize,
+    height: usize,
+    rgb: Vec<u8>,
+    alpha: Option<Vec<u8>>,
+}
+
+fn le24(b: &[u8]) -> usize {
+    b[0] as usize | (b[1] as usize) << 8 | (b[2] as usize) << 16
+}
+
+fn le32(b: &[u8]) -> usize {
+    le24(b) | (b[3] as usize) << 24
+}
+
+/* The RIFF container */
+
+/// A chunk: its FourCC and its payload.
+type Chunk<'a> = (&'a [u8], &'a [u8]);
+
+fn read_chunks(mut data: &[u8]) -> Result<Vec<Chunk<'_>>, &'static str> {
+    let mut chunks = Vec::new();
+    while data.len() >= 8 {
+        let size = le32(&data[4..8]);
+        let payload = data.get(8..8 + size).ok_or("Truncated chunk")?;
+        chunks.push((&data[0..4], payload));
+        data = &data[(8 + size + (size & 1)).min(data.len())..];
+    }
+    Ok(chunks)
+}
+
+fn riff_chunks(data: &[u8]) -> Result<Vec<Chunk<'_>>, &'static str> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return Err("Not a WebP file");
+    }
+    let end = (8 + le32(&data[4..8])).min(data.len());
+    let chunks = read_chunks(&data[12..end])?;
+    if chunks.is_empty() {
+        return Err("No image data");
+    }
+    Ok(chunks)
+}
+
+fn find_chunk<'a>(chunks: &[Chunk<'a>], fourcc: &[u8]) -> Option<&'a [u8]> {
+    chunks.iter().find(|c| c.0 == fourcc).map(|c| c.1)
+}
+
+/// Image size, without decoding the image.
+fn read_size(data: &[u8]) -> Result<(usize, usize), &'static str> {
+    let chunks = riff_chunks(data)?;
+    let (fourcc, payload) = chunks[0];
+    match fourcc {
+        b"VP8X" if payload.len() >= 10 => Ok((le24(&payload[4..]) + 1, le24(&payload[7..]) + 1)),
+        b"VP8 " if payload.len() >= 10 => {
+            Ok((le24(&payload[6..]) & 0x3fff, le24(&payload[8..]) & 0x3fff))
+        }
+        b"VP8L" if payload.len() >= 5 => {
+            let bits = le32(&payload[1..]);
+            Ok(((bits & 0x3fff) + 1, (bits >> 14 & 0x3fff) + 1))
+        }
+        _ => Err("Invalid image header"),
+    }
+}
+
+/// Decodes an image, and returns its ICC profile, if any.
+fn read_webp(data: &[u8]) -> Result<(Picture, Option<&[u8]>), &'static str> {
+    let chunks = riff_chunks(data)?;
+    let (fourcc, payload) = chunks[0];
+    if fourcc != b"VP8X" {
+        return Ok((decode_frame(&chunks)?, None));
+    }
+    if payload.len() < 10 {
+        return Err("Invalid extended header");
+    }
+    let flags = payload[0];
+    let icc_profile = if flags & 0x20 != 0 {
+        find_chunk(&chunks, b"ICCP")
+    } else {
+        None
+    };
+    if flags & 0x02 == 0 {
+        return Ok((decode_frame(&chunks[1..])?, icc_profile));
+    }
+    /* Animation: draw the first frame on a transparent canvas. */
+    let mut frames = chunks.iter().filter(|c| c.0 == b"ANMF");
+    let frame = frames.next().ok_or("No frame in animation")?.1;
+    if frames.next().is_some() {
+        warn!("WebP: Animated image; only its first frame is included.");
+    }
+    if frame.len() < 16 {
+        return Err("Invalid animation frame");
+    }
+    let (x0, y0) = (2 * le24(&frame[0..]), 2 * le24(&frame[3..]));
+    let picture = decode_frame(&read_chunks(&frame[16..])?)?;
+    let (width, height) = (le24(&payload[4..]) + 1, le24(&payload[7..]) + 1);
+    let mut canvas = Picture {
+        width,
+        height,
+        rgb: vec![255; 3 * width * height],
+        alpha: Some(vec![0; width * height]),
+    };
+    let alpha = canvas.alpha.as_mut().unwrap();
+    for y in 0..picture.height.min(height.saturating_sub(y0)) {
+        for x in 0..picture.width.min(width.saturating_sub(x0)) {
+            let (src, dst) = (y * picture.width + x, (y0 + y) * width + x0 + x);
+            canvas.rgb[3 * dst..3 * dst + 3].copy_from_slice(&picture.rgb[3 * src..3 * src + 3]);
+            alpha[dst] = picture.alpha.as_ref().map_or(255, |a| a[src]);
+        }
+    }
+    Ok((canvas, icc_profile))
+}
+
+/// Decodes the image (and alpha) chunks of a still image or a frame.
+fn decode_frame(chunks: &[Chunk]) -> Result<Picture, &'static str> {
+    for &(fourcc, payload) in chunks {
+        match fourcc {
+            b"VP8L" => return decode_vp8l(payload),
+            b"VP8 " => {
+                let mut picture = decode_vp8(payload)?;
+                if let Some(alph) = find_chunk(chunks, b"ALPH") {
+                    picture.alpha = Some(decode_alpha(alph, picture.width, picture.height)?);
+                }
+                return Ok(picture);
+            }
+            _ => {}
+        }
+    }
+    Err("No image data")
+}
+
+/// Decodes an ALPH chunk: raw or VP8L-compressed, then unfiltered.
+fn decode_alpha(data: &[u8], width: usize, height: usize) -> Result<Vec<u8>, &'static str> {
+    let header = *data.first().ok_or("Empty alpha chunk")?;
+    let mut alpha = match header & 0x03 {
+        0 => data[1..].to_vec(),
+        1 => {
+            let mut br = BitReader::new(&data[1..]);
+            let argb = decode_vp8l_image(&mut br, width, height)?;
+            argb.iter().map(|&p| (p >> 8) as u8).collect()
+        }
+        _ => return Err("Unknown alpha compression"),
+    };
+    if alpha.len() < width * height {
+        return Err("Truncated alpha data");
+    }
+    alpha.truncate(width * height);
+    let filter = header >> 2 & 0x03;
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+            let pred = match (filter, x, y) {
+                (0, _, _) | (_, 0, 0) => 0,
+                (_, _, 0) => alpha[i - 1],
+                (_, 0, _) => alpha[i - width],
+                (1, _, _) => alpha[i - 1],
+                (2, _, _) => alpha[i - width],
+                _ => {
+                    let g =
+                        alpha[i - 1] as i32 + alpha[i - width] as i32 - alpha[i - width - 1] as i32;
+                    g.max(0).min(255) as u8
+                }
+            };
+            alpha[i] = alpha[i].wrapping_add(pred);
+        }
+    }
+    Ok(alpha)
+}
+
+/* Lossless images (VP8L) */
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    buf: u64,
+    nbits: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            pos: 0,
+            buf: 0,
+            nbits: 0,
+        }
+    }
+
+    fn peek(&mut self, n: u32) -> u32 {
+        while self.nbits <= 56 {
+            let byte = self.data.get(self.pos).cloned().unwrap_or(0);
+            self.buf |= (byte as u64) << self.nbits;
+            self.nbits += 8;
+            self.pos += 1;
+        }
+        (self.buf & ((1u64 << n) - 1)) as u32
+    }
+
+    fn consume(&mut self, n: u32) {
+        self.buf >>= n;
+        self.nbits -= n;
+    }
+
+    fn read(&mut self, n: u32) -> u32 {
+        let v = self.peek(n);
+        self.consume(n);
+        v
+    }
+
+    /// True once more bits were consumed than there are.
+    fn is_exhausted(&self) -> bool {
+        self.pos * 8 > self.data.len() * 8 + self.nbits as usize
+    }
+}
+
+/// A canonical prefix code.
+struct PrefixCode {
+    /// Symbol and code length by the next 8 bits; longer codes have length 0.
+    table: Vec<(u16, u8)>,
+    /// Number of codes of each length, and symbols in code order.
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+    /// The symbol of a code with a single symbol, which takes no bits.
+    single: Option<u16>,
+}
+
+impl PrefixCode {
+    fn from_lengths(lengths: &[u8]) -> Result<Self, &'static str> {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+        let used: Vec<usize> = (0..lengths.len()).filter(|&s| lengths[s] > 0).collect();
+        if used.is_empty() {
+            return Err("Empty prefix code");
+        } else if used.len() == 1 {
+            return Ok(PrefixCode {
+                table: Vec::new(),
+                counts,
+                symbols: Vec::new(),
+                single: Some(used[0] as u16),
+            });
+        }
+        /* The code must be complete. */
+        let mut left = 1i32;
+        for &count in &counts[1..] {
+            left = 2 * left - count as i32;
+            if left < 0 {
+                return Err("Over-subscribed prefix code");
+            }
+        }
+        if left != 0 {
+            return Err("Incomplete prefix code");
+        }
+        let mut symbols = Vec::with_capacity(used.len());
+        for len in 1..16 {
+            symbols.extend(
+                used.iter()
+                    .filter(|&&s| lengths[s] == len)
+                    .map(|&s| s as u16),
+            );
+        }
+        let mut table = vec![(0u16, 0u8); 256];
+        let mut code = 0u32;
+        let mut i = 0;
+        for len in 1..16u32 {
+            for _ in 0..counts[len as usize] {
+                if len <= 8 {
+                    let rev = code.reverse_bits() >> (32 - len);
+                    for k in (rev as usize..256).step_by(1 << len) {
+                        table[k] = (symbols[i], len as u8);
+                    }
+                }
+                code += 1;
+                i += 1;
+            }
+            code <<= 1;
+        }
+        Ok(PrefixCode {
+            table,
+            counts,
+            symbols,
+            single: None,
+        })
+    }
+
+    fn read(&self, br: &mut BitReader) -> Result<u16, &'static str> {
+        if let Some(symbol) = self.single {
+            return Ok(symbol);
+        }
+        let (symbol, len) = self.table[br.peek(8) as usize];
+        if len > 0 {
+            br.consume(len as u32);
+            return Ok(symbol);
+        }
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= br.read(1) as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("Invalid prefix code")
+    }
+}
+
+const CODE_LENGTH_CODE_ORDER: [usize; 19] = [
+    17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+fn read_prefix_code(br: &mut BitReader, alphabet_size: usize) -> Result<PrefixCode, &'static str> {
+    let mut lengths = vec![0u8; alphabet_size];
+    if br.read(1) == 1 {
+        /* A simple code of one or two symbols */
+        let num_symbols = br.read(1) + 1;
+        let first_bits = if br.read(1) == 1 { 8 } else { 1 };
+        let mut symbols = vec![br.read(first_bits) as usize];
+        if num_symbols == 2 {
+            symbols.push(br.read(8) as usize);
+        }
+        for s in symbols {
+            *lengths.get_mut(s).ok_or("Invalid prefix code")? = 1;
+        }
+        return PrefixCode::from_lengths(&lengths);
+    }
+    let mut code_length_lengths = [0u8; 19];
+    let num_codes = br.read(4) as usize + 4;
+    for &i in &CODE_LENGTH_CODE_ORDER[..num_codes] {
+        code_length_lengths[i] = br.read(3) as u8;
+    }
+    let code_length_code = PrefixCode::from_lengths(&code_length_lengths)?;
+    let mut max_symbol = if br.read(1) == 1 {
+        let nbits = 2 + 2 * br.read(3);
+        let max_symbol = 2 + br.read(nbits) as usize;
+        if max_symbol > alphabet_size {
+            return Err("Invalid prefix code");
+        }
+        max_symbol
+    } else {
+        alphabet_size
+    };
+    let mut prev_length = 8;
+    let mut symbol = 0;
+    while symbol < alphabet_size && max_symbol > 0 {
+        max_symbol -= 1;
+        let length = code_length_code.read(br)?;
+        if length < 16 {
+            lengths[symbol] = length as u8;
+            symbol += 1;
+            if length != 0 {
+                prev_length = length as u8;
+            }
+        } else {
+            let (extra_bits, offset, value) = match length {
+                16 => (2, 3, prev_length),
+                17 => (3, 3, 0),
+                _ => (7, 11, 0),
+            };
+            let repeat = br.read(extra_bits) as usize + offset;
+            if symbol + repeat > alphabet_size {
+                return Err("Invalid prefix code");
+            }
+            for l in &mut lengths[symbol..symbol + repeat] {
+                *l = value;
+            }
+            symbol += repeat;
+        }
+    }
+    PrefixCode::from_lengths(&lengths)
+}
+
+/// Reads a length or distance from its prefix symbol and extra bits.
+fn read_prefixed_value(symbol: u16, br: &mut BitReader) -> usize {
+    let symbol = symbol as usize;
+    if symbol < 4 {
+        return symbol + 1;
+    }
+    let extra_bits = (symbol - 2) >> 1;
+    let offset = (2 + (symbol & 1)) << extra_bits;
+    offset + br.read(extra_bits as u32) as usize + 1
+}
+
+#[rustfmt::skip]
+const DISTANCE_MAP: [(i8, i8); 120] = [
+    (0, 1),  (1, 0),  (1, 1),  (-1, 1), (0, 2),  (2, 0),  (1, 2),  (-1, 2),
+    (2, 1),  (-2, 1), (2, 2),  (-2, 2), (0, 3),  (3, 0),  (1, 3),  (-1, 3),
+    (3, 1),  (-3, 1), (2, 3),  (-2, 3), (3, 2),  (-3, 2), (0, 4),  (4, 0),
+    (1, 4),  (-1, 4), (4, 1),  (-4, 1), (3, 3),  (-3, 3), (2, 4),  (-2, 4),
+    (4, 2),  (-4, 2), (0, 5),  (3, 4),  (-3, 4), (4, 3),  (-4, 3), (5, 0),
+    (1, 5),  (-1, 5), (5, 1),  (-5, 1), (2, 5),  (-2, 5), (5, 2),  (-5, 2),
+    (4, 4),  (-4, 4), (3, 5),  (-3, 5), (5, 3),  (-5, 3), (0, 6),  (6, 0),
+    (1, 6),  (-1, 6), (6, 1),  (-6, 1), (2, 6),  (-2, 6), (6, 2),  (-6, 2),
+    (4, 5),  (-4, 5), (5, 4),  (-5, 4), (3, 6),  (-3, 6), (6, 3),  (-6, 3),
+    (0, 7),  (7, 0),  (1, 7),  (-1, 7), (5, 5),  (-5, 5), (7, 1),  (-7, 1),
+    (4, 6),  (-4, 6), (6, 4),  (-6, 4), (2, 7),  (-2, 7), (7, 2),  (-7, 2),
+    (3, 7),  (-3, 7), (7, 3),  (-7, 3), (5, 6),  (-5, 6), (6, 5),  (-6, 5),
+    (8, 0),  (4, 7),  (-4, 7), (7, 4),  (-7, 4), (8, 1),  (8, 2),  (6, 6),
+    (-6, 6), (8, 3),  (5, 7),  (-5, 7), (7, 5),  (-7, 5), (8, 4),  (6, 7),
+    (-6, 7), (7, 6),  (-7, 6), (8, 5),  (7, 7),  (-7, 7), (8, 6),  (8, 7),
+];
+
+fn plane_code_to_distance(xsize: usize, code: usize) -> usize {
+    if code > 120 {
+        return code - 120;
+    }
+    let (dx, dy) = DISTANCE_MAP[code - 1];
+    let dist = dx as isize + dy as isize * xsize as isize;
+    dist.max(1) as usize
+}
+
+fn subsample_size(size: usize, bits: u32) -> usize {
+    (size + (1 << bits) - 1) >> bits
+}
+
+/// Decodes the entropy-coded image of a VP8L image stream; only the main
+/// image (`is_main`) may use meta prefix codes.
+fn decode_entropy_image(
+    br: &mut BitReader,
+    xsize: usize,
+    ysize: usize,
+    is_main: bool,
+) -> Result<Vec<u32>, &'static str> {
+    let cache_bits = if br.read(1) == 1 {
+        let bits = br.read(4);
+        if !(1..=11).contains(&bits) {
+            return Err("Invalid color cache size");
+        }
+        bits
+    } else {
+        0
+    };
+    let (meta_bits, meta_image, meta_xsize) = if is_main && br.read(1) == 1 {
+        let bits = br.read(3) + 2;
+        let (w, h) = (subsample_size(xsize, bits), subsample_size(ysize, bits));
+        (bits, decode_entropy_image(br, w, h, false)?, w)
+    } else {
+        (0, Vec::new(), 0)
+    };
+    let num_groups = meta_image
+        .iter()
+        .map(|&p| (p >> 8 & 0xffff) as usize + 1)
+        .max()
+        .unwrap_or(1);
+    let cache_size = if cache_bits > 0 { 1 << cache_bits } else { 0 };
+    let alphabet_sizes = [256 + 24 + cache_size, 256, 256, 256, 40];
+    let mut groups = Vec::with_capacity(num_groups);
+    for _ in 0..num_groups {
+        let mut group = Vec::with_capacity(5);
+        for &size in &alphabet_sizes {
+            group.push(read_prefix_code(br, size)?);
+        }
+        groups.push(group);
+    }
+
+    let total = xsize * ysize;
+    let mut data: Vec<u32> = Vec::with_capacity(total);
+    let mut cache = vec![0u32; cache_size];
+    let mut cached = 0;
+    let (mut x, mut y) = (0, 0);
+    while data.len() < total {
+        let group = if meta_bits > 0 {
+            let p = meta_image[(y >> meta_bits) * meta_xsize + (x >> meta_bits)];
+            &groups[(p >> 8 & 0xffff) as usize]
+        } else {
+            &groups[0]
+        };
+        let green = group[0].read(br)? as usize;
+        let length = if green < 256 {
+            let red = group[1].read(br)? as u32;
+            let blue = group[2].read(br)? as u32;
+            let alpha = group[3].read(br)? as u32;
+            data.push(alpha << 24 | red << 16 | (green as u32) << 8 | blue);
+            1
+        } else if green < 256 + 24 {
+            let length = read_prefixed_value(green as u16 - 256, br);
+            let symbol = group[4].read(br)?;
+            let dist = plane_code_to_distance(xsize, read_prefixed_value(symbol, br));
+            if dist > data.len() || data.len() + length > total {
+                return Err("Invalid backward reference");
+            }
+            for _ in 0..length {
+                data.push(data[data.len() - dist]);
+            }
+            length
+        } else {
+            while cached < data.len() {
+                let p = data[cached];
+                cache[(0x1e35_a7bd_u32.wrapping_mul(p) >> (32 - cache_bits)) as usize] = p;
+                cached += 1;
+            }
+            data.push(cache[green - 280]);
+            1
+        };
+        if cache_bits == 0 {
+            cached = data.len();
+        }
+        x += length;
+        while x >= xsize {
+            x -= xsize;
+            y += 1;
+        }
+        if br.is_exhausted() {
+            return Err("Truncated image data");
+        }
+    }
+    Ok(data)
+}
+
+enum Transform {
+    Predictor(u32, Vec<u32>),
+    CrossColor(u32, Vec<u32>),
+    SubtractGreen,
+    ColorIndexing(u32, Vec<u32>),
+}
+
+fn average2(a: u32, b: u32) -> u32 {
+    (((a ^ b) & 0xfefe_fefe) >> 1) + (a & b)
+}
+
+fn channels(p: u32) -> [i32; 4] {
+    [
+        (p >> 24) as i32,
+        (p >> 16 & 0xff) as i32,
+        (p >> 8 & 0xff) as i32,
+        (p & 0xff) as i32,
+    ]
+}
+
+fn from_channels(c: [i32; 4]) -> u32 {
+    c.iter().fold(0, |p, &v| p << 8 | v.max(0).min(255) as u32)
+}
+
+fn predict(mode: u32, l: u32, t: u32, tr: u32, tl: u32) -> u32 {
+    match mode {
+        1 => l,
+        2 => t,
+        3 => tr,
+        4 => tl,
+        5 => average2(average2(l, tr), t),
+        6 => average2(l, tl),
+        7 => average2(l, t),
+        8 => average2(tl, t),
+        9 => average2(t, tr),
+        10 => average2(average2(l, tl), average2(t, tr)),
+        11 => {
+            let (cl, ct, ctl) = (channels(l), channels(t), channels(tl));
+            let (mut pl, mut pt) = (0, 0);
+            for i in 0..4 {
+                pl += (ct[i] - ctl[i]).abs();
+                pt += (cl[i] - ctl[i]).abs();
+            }
+            if pl < pt {
+                l
+            } else {
+                t
+            }
+        }
+        12 => {
+            let (cl, ct, ctl) = (channels(l), channels(t), channels(tl));
+            from_channels([
+                cl[0] + ct[0] - ctl[0],
+                cl[1] + ct[1] - ctl[1],
+                cl[2] + ct[2] - ctl[2],
+                cl[3] + ct[3] - ctl[3],
+            ])
+        }
+        13 => {
+            let (ca, ctl) = (channels(average2(l, t)), channels(tl));
+            from_channels([
+                ca[0] + (ca[0] - ctl[0]) / 2,
+                ca[1] + (ca[1] - ctl[1]) / 2,
+                ca[2] + (ca[2] - ctl[2]) / 2,
+                ca[3] + (ca[3] - ctl[3]) / 2,
+            ])
+        }
+        _ => 0xff00_0000,
+    }
+}
+
+fn add_pixels(a: u32, b: u32) -> u32 {
+    let ag = (a & 0xff00_ff00).wrapping_add(b & 0xff00_ff00) & 0xff00_ff00;
+    let rb = (a & 0x00ff_00ff).wrapping_add(b & 0x00ff_00ff) & 0x00ff_00ff;
+    ag | rb
+}
+
+fn color_transform_delta(t: u32, c: u32) -> u32 {
+    ((t as u8 as i8 as i32 * c as u8 as i8 as i32) >> 5) as u32
+}
+
+/// Decodes a VP8L image stream: transforms and the entropy-coded image.
+fn decode_vp8l_image(
+    br: &mut BitReader,
+    width: usize,
+    height: usize,
+) -> Result<Vec<u32>, &'static str> {
+    let mut transforms = Vec::new();
+    let mut seen = 0;
+    let mut xsize = width;
+    while br.read(1) == 1 {
+        let kind = br.read(2);
+        if seen & 1 << kind != 0 {
+            return Err("Repeated transform");
+        }
+        seen |= 1 << kind;
+        let transform = match kind {
+            0 | 1 => {
+                let bits = br.read(3) + 2;
+                let (w, h) = (subsample_size(xsize, bits), subsample_size(height, bits));
+                let data = decode_entropy_image(br, w, h, false)?;
+                if kind == 0 {
+                    Transform::Predictor(bits, data)
+                } else {
+                    Transform::CrossColor(bits, data)
+                }
+            }
+            2 => Transform::SubtractGreen,
+            _ => {
+                let size = br.read(8) as usize + 1;
+                let mut table = decode_entropy_image(br, size, 1, false)?;
+                for i in 1..size {
+                    table[i] = add_pixels(table[i], table[i - 1]);
+                }
+                let bits = match size {
+                    0..=2 => 3,
+                    3..=4 => 2,
+                    5..=16 => 1,
+                    _ => 0,
+                };
+                Transform::ColorIndexing(bits, table)
+            }
+        };
+        transforms.push((xsize, transform));
+        if let Some(&(_, Transform::ColorIndexing(bits, _))) = transforms.last() {
+            xsize = subsample_size(xsize, bits);
+        }
+    }
+    let mut data = decode_entropy_image(br, xsize, height, true)?;
+    for (xsize, transform) in transforms.iter().rev() {
+        let xsize = *xsize;
+        match transform {
+            Transform::Predictor(bits, modes) => {
+                let tiles_x = subsample_size(xsize, *bits);
+                for y in 0..height {
+                    for x in 0..xsize {
+                        let i = y * xsize + x;
+                        let pred = if y == 0 {
+                            if x == 0 {
+                                0xff00_0000
+                            } else {
+                                data[i - 1]
+                            }
+                        } else if x == 0 {
+                            data[i - xsize]
+                        } else {
+                            let mode = modes[(y >> bits) * tiles_x + (x >> bits)] >> 8 & 0xf;
+                            predict(
+                                mode,
+                                data[i - 1],
+                                data[i - xsize],
+                                data[i - xsize + 1],
+                                data[i - xsize - 1],
+                            )
+                        };
+                        data[i] = add_pixels(data[i], pred);
+                    }
+                }
+            }
+            Transform::CrossColor(bits, elements) => {
+                let tiles_x = subsample_size(xsize, *bits);
+                for y in 0..height {
+                    for x in 0..xsize {
+                        let cte = elements[(y >> bits) * tiles_x + (x >> bits)];
+                        let p = &mut data[y * xsize + x];
+                        let green = *p >> 8;
+                        let red = (*p >> 16).wrapping_add(color_transform_delta(cte, green)) & 0xff;
+                        let blue = (*p)
+                            .wrapping_add(color_transform_delta(cte >> 8, green))
+                            .wrapping_add(color_transform_delta(cte >> 16, red))
+                            & 0xff;
+                        *p = *p & 0xff00_ff00 | red << 16 | blue;
+                    }
+                }
+            }
+            Transform::SubtractGreen => {
+                for p in data.iter_mut() {
+                    let green = *p >> 8 & 0xff;
+                    *p = add_pixels(*p, green << 16 | green);
+                }
+            }
+            Transform::ColorIndexing(bits, table) => {
+                let packed_xsize = subsample_size(xsize, *bits);
+                let bits_per_pixel = 8 >> bits;
+                let mask = (1 << bits_per_pixel) - 1;
+                let mut out = Vec::with_capacity(xsize * height);
+                for y in 0..height {
+                    for x in 0..xsize {
+                        let packed = data[y * packed_xsize + (x >> bits)] >> 8 & 0xff;
+                        let shift = (x & ((1 << bits) - 1)) as u32 * bits_per_pixel;
+                        let index = (packed >> shift & mask) as usize;
+                        out.push(table.get(index).cloned().unwrap_or(0));
+                    }
+                }
+                data = out;
+            }
+        }
+    }
+    Ok(data)
+}
+
+fn decode_vp8l(data: &[u8]) -> Result<Picture, &'static str> {
+    if data.len() < 5 || data[0] != 0x2f {
+        return Err("Invalid lossless image header");
+    }
+    let mut br = BitReader::new(&data[1..]);
+    let width = br.read(14) as usize + 1;
+    let height = br.read(14) as usize + 1;
+    let _alpha_is_used = br.read(1);
+    if br.read(3) != 0 {
+        return Err("Unknown lossless image version");
+    }
+    let argb = decode_vp8l_image(&mut br, width, height)?;
+    let mut rgb = Vec::with_capacity(3 * argb.len());
+    let mut alpha = Vec::with_capacity(argb.len());
+    for p in argb {
+        rgb.extend_from_slice(&[(p >> 16) as u8, (p >> 8) as u8, p as u8]);
+        alpha.push((p >> 24) as u8);
+    }
+    Ok(Picture {
+        width,
+        height,
+        rgb,
+        alpha: Some(alpha),
+    })
+}
+
+/* Lossy images (VP8 key frames) */
+
+/// The boolean entropy decoder of RFC 6386, section 7.
+struct BoolDecoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    value: u32,
+    range: u32,
+    bit_count: u32,
+}
+
+impl<'a> BoolDecoder<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        let mut bd = BoolDecoder {
+            data,
+            pos: 0,
+            value: 0,
+            range: 255,
+            bit_count: 0,
+        };
+        bd.value = (bd.next_byte() as u32) << 8 | bd.next_byte() as u32;
+        bd
+    }
+
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.data.get(self.pos).cloned().unwrap_or(0);
+        self.pos += 1;
+        byte
+    }
+
+    fn read_bool(&mut self, prob: u8) -> bool {
+        let split = 1 + (((self.range - 1) * prob as u32) >> 8);
+        let big_split = split << 8;
+        let bit = if self.value >= big_split {
+            self.range -= split;
+            self.value -= big_split;
+            true
+        } else {
+            self.range = split;
+            false
+        };
+        while self.range < 128 {
+            self.value <<= 1;
+            self.range <<= 1;
+            self.bit_count += 1;
+            if self.bit_count == 8 {
+                self.bit_count = 0;
+                self.value |= self.next_byte() as u32;
+            }
+        }
+        bit
+    }
+
+    fn read_flag(&mut self) -> bool {
+        self.read_bool(128)
+    }
+
+    fn read_literal(&mut self, n: u32) -> i32 {
+        (0..n).fold(0, |v, _| v << 1 | self.read_flag() as i32)
+    }
+
+    fn read_signed(&mut self, n: u32) -> i32 {
+        let v = self.read_literal(n);
+        if self.read_flag() {
+            -v
+        } else {
+            v
+        }
+    }
+
+    fn read_optional_signed(&mut self, n: u32) -> i32 {
+        if self.read_flag() {
+            self.read_signed(n)
+        } else {
+            0
+        }
+    }
+
+    fn read_tree(&mut self, tree: &[i8], probs: &[u8]) -> usize {
+        let mut i = 0;
+        loop {
+            i = tree[i + self.read_bool(probs[i >> 1]) as usize] as isize as usize;
+            if (i as isize) <= 0 {
+                return (-(i as isize)) as usize;
+            }
+        }
+    }
+}
+
+const DC_PRED: usize = 0;
+const V_PRED: usize = 1;
+const H_PRED: usize = 2;
+const TM_PRED: usize = 3;
+const B_PRED: usize = 4;
+
+const B_DC_PRED: usize = 0;
+const B_TM_PRED: usize = 1;
+const B_VE_PRED: usize = 2;
+const B_HE_PRED: usize = 3;
+const B_LD_PRED: usize = 4;
+const B_RD_PRED: usize = 5;
+const B_VR_PRED: usize = 6;
+const B_VL_PRED: usize = 7;
+const B_HD_PRED: usize = 8;
+const B_HU_PRED: usize = 9;
+
+const SEGMENT_TREE: [i8; 6] = [2, 4, -0, -1, -2, -3];
+const YMODE_TREE: [i8; 8] = [
+    -(B_PRED as i8),
+    2,
+    4,
+    6,
+    -(DC_PRED as i8),
+    -(V_PRED as i8),
+    -(H_PRED as i8),
+    -(TM_PRED as i8),
+];
+const YMODE_PROBS: [u8; 4] = [145, 156, 163, 128];
+const UV_MODE_TREE: [i8; 6] = [
+    -(DC_PRED as i8),
+    2,
+    -(V_PRED as i8),
+    4,
+    -(H_PRED as i8),
+    -(TM_PRED as i8),
+];
+const UV_MODE_PROBS: [u8; 3] = [142, 114, 183];
+#[rustfmt::skip]
+const BMODE_TREE: [i8; 18] = [
+    -(B_DC_PRED as i8), 2,
+    -(B_TM_PRED as i8), 4,
+    -(B_VE_PRED as i8), 6,
+    8, 12,
+    -(B_HE_PRED as i8), 10,
+    -(B_RD_PRED as i8), -(B_VR_PRED as i8),
+    -(B_LD_PRED as i8), 14,
+    -(B_VL_PRED as i8), 16,
+    -(B_HD_PRED as i8), -(B_HU_PRED as i8),
+];
+
+/// Coefficient bands by position; the extra entry is never used for reading.
+const COEFF_BANDS: [usize; 17] = [0, 1, 2, 3, 6, 4, 5, 6, 6, 6, 6, 6, 6, 6, 6, 7, 0];
+const ZIGZAG: [usize; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
+const DCT_CAT_PROBS: [&[u8]; 4] = [
+    &[173, 148, 140],
+    &[176, 155, 140, 135],
+    &[180, 157, 141, 134, 130],
+    &[254, 254, 243, 230, 196, 177, 153, 140, 133, 130, 129],
+];
+
+type TokenProbs = [[[[u8; 11]; 3]; 8]; 4];
+
+/// Dequantization factors (DC, AC) of a segment.
+#[derive(Clone, Copy, Default)]
+struct QuantMatrix {
+    y1: (i32, i32),
+    y2: (i32, i32),
+    uv: (i32, i32),
+}
+
+/// Loop filter parameters of a macroblock.
+#[derive(Clone, Copy, Default)]
+struct FilterInfo {
+    limit: i32,
+    ilevel: i32,
+    hev_thresh: i32,
+    inner: bool,
+}
+
+/// Reads the tokens of a block of coefficients, and returns the position
+/// after the last one read (see libwebp's `GetCoeffs()`).
+fn read_coefficients(
+    bd: &mut BoolDecoder,
+    probs: &[[[u8; 11]; 3]; 8],
+    ctx: usize,
+    dq: (i32, i32),
+    mut n: usize,
+    out: &mut [i32; 16],
+) -> usize {
+    let mut p = &probs[COEFF_BANDS[n]][ctx];
+    while n < 16 {
+        if !bd.read_bool(p[0]) {
+            return n;
+        }
+        while !bd.read_bool(p[1]) {
+            n += 1;
+            if n == 16 {
+                return 16;
+            }
+            p = &probs[COEFF_BANDS[n]][0];
+        }
+        let (v, next_ctx) = if !bd.read_bool(p[2]) {
+            (1, 1)
+        } else if !bd.read_bool(p[3]) {
+            if !bd.read_bool(p[4]) {
+                (2, 2)
+            } else {
+                (3 + bd.read_bool(p[5]) as i32, 2)
+            }
+        } else if !bd.read_bool(p[6]) {
+            if !bd.read_bool(p[7]) {
+                (5 + bd.read_bool(159) as i32, 2)
+            } else {
+                let v = 7 + 2 * bd.read_bool(165) as i32;
+                (v + bd.read_bool(145) as i32, 2)
+            }
+        } else {
+            let bit1 = bd.read_bool(p[8]) as usize;
+            let bit0 = bd.read_bool(p[9 + bit1]) as usize;
+            let cat = 2 * bit1 + bit0;
+            let extra = DCT_CAT_PROBS[cat]
+                .iter()
+                .fold(0, |v, &prob| 2 * v + bd.read_bool(prob) as i32);
+            (3 + (8 << cat) + extra, 2)
+        };
+        let v = if bd.read_flag() { -v } else { v };
+        out[ZIGZAG[n]] = v * if n > 0 { dq.1 } else { dq.0 };
+        n += 1;
+        p = &probs[COEFF_BANDS[n]][next_ctx];
+    }
+    16
+}
+
+/// Inverse Walsh-Hadamard transform of the second-order luma block, giving
+/// the DC coefficients of the 16 luma blocks.
+fn inverse_wht(input: &[i32; 16], blocks: &mut [[i32; 16]; 16]) {
+    let mut tmp = [0i32; 16];
+    for i in 0..4 {
+        let a0 = input[i] + input[12 + i];
+        let a1 = input[4 + i] + input[8 + i];
+        let a2 = input[4 + i] - input[8 + i];
+        let a3 = input[i] - input[12 + i];
+        tmp[i] = a0 + a1;
+        tmp[8 + i] = a0 - a1;
+        tmp[4 + i] = a3 + a2;
+        tmp[12 + i] = a3 - a2;
+    }
+    for i in 0..4 {
+        let dc = tmp[4 * i] + 3;
+        let a0 = dc + tmp[4 * i + 3];
+        let a1 = tmp[4 * i + 1] + tmp[4 * i + 2];
+        let a2 = tmp[4 * i + 1] - tmp[4 * i + 2];
+        let a3 = dc - tmp[4 * i + 3];
+        blocks[4 * i][0] = (a0 + a1) >> 3;
+        blocks[4 * i + 1][0] = (a3 + a2) >> 3;
+        blocks[4 * i + 2][0] = (a0 - a1) >> 3;
+        blocks[4 * i + 3][0] = (a3 - a2) >> 3;
+    }
+}
+
+fn clip8(v: i32) -> u8 {
+    v.max(0).min(255) as u8
+}
+
+/// Adds the inverse DCT of a block to the prediction at `off`.
+fn add_inverse_dct(coeffs: &[i32; 16], buf: &mut [u8], off: usize, stride: usize) {
+    fn mul1(a: i32) -> i32 {
+        ((a * 20091) >> 16) + a
+    }
+    fn mul2(a: i32) -> i32 {
+        (a * 35468) >> 16
+    }
+    let mut tmp = [0i32; 16];
+    for i in 0..4 {
+        let a = coeffs[i] + coeffs[8 + i];
+        let b = coeffs[i] - coeffs[8 + i];
+        let c = mul2(coeffs[4 + i]) - mul1(coeffs[12 + i]);
+        let d = mul1(coeffs[4 + i]) + mul2(coeffs[12 + i]);
+        tmp[4 * i] = a + d;
+        tmp[4 * i + 1] = b + c;
+        tmp[4 * i + 2] = b - c;
+        tmp[4 * i + 3] = a - d;
+    }
+    for i in 0..4 {
+        let dc = tmp[i] + 4;
+        let a = dc + tmp[8 + i];
+        let b = dc - tmp[8 + i];
+        let c = mul2(tmp[4 + i]) - mul1(tmp[12 + i]);
+        let d = mul1(tmp[4 + i]) + mul2(tmp[12 + i]);
+        let row = off + i * stride;
+        for (x, v) in [a + d, b + c, b - c, a - d].iter().enumerate() {
+            buf[row + x] = clip8(buf[row + x] as i32 + (v >> 3));
+        }
+    }
+}
+
+fn avg2(a: u8, b: u8) -> u8 {
+    ((a as u32 + b as u32 + 1) >> 1) as u8
+}
+
+fn avg3(a: u8, b: u8, c: u8) -> u8 {
+    ((a as u32 + 2 * b as u32 + c as u32 + 2) >> 2) as u8
+}
+
+/// Predicts a 16x16 or 8x8 block at `off` from its bordering pixels.
+fn predict_block(
+    buf: &mut [u8],
+    off: usize,
+    stride: usize,
+    size: usize,
+    mode: usize,
+    edges: (bool, bool),
+) {
+    let (has_top, has_left) = edges;
+    let top: Vec<u8> = buf[off - stride..off - stride + size].to_vec();
+    let left: Vec<u8> = (0..size).map(|y| buf[off + y * stride - 1]).collect();
+    let top_left = buf[off - stride - 1] as i32;
+    let shift = if size == 16 { 4 } else { 3 };
+    for y in 0..size {
+        for x in 0..size {
+            buf[off + y * stride + x] = match mode {
+                V_PRED => top[x],
+                H_PRED => left[y],
+                TM_PRED => clip8(top[x] as i32 + left[y] as i32 - top_left),
+                _ => {
+                    let sum = |v: &[u8]| v.iter().map(|&p| p as u32).sum::<u32>();
+                    match (has_top, has_left) {
+                        (true, true) => {
+                            ((sum(&top) + sum(&left) + size as u32) >> (shift + 1)) as u8
+                        }
+                        (true, false) => ((sum(&top) + (size as u32 >> 1)) >> shift) as u8,
+                        (false, true) => ((sum(&left) + (size as u32 >> 1)) >> shift) as u8,
+                        (false, false) => 128,
+                    }
+                }
+            };
+        }
+    }
+}
+
+/// Predicts a 4x4 luma subblock at `off` (see libwebp's `dsp/dec.c`).
+fn predict_subblock(buf: &mut [u8], off: usize, stride: usize, mode: usize) {
+    let top: Vec<u8> = buf[off - stride..off - stride + 8].to_vec();
+    let (a, b, c, d, e, f, g, h) = (
+        top[0], top[1], top[2], top[3], top[4], top[5], top[6], top[7],
+    );
+    let x = buf[off - stride - 1];
+    let i = buf[off - 1];
+    let j = buf[off + stride - 1];
+    let k = buf[off + 2 * stride - 1];
+    let l = buf[off + 3 * stride - 1];
+    let mut out = [[0u8; 4]; 4];
+    {
+        let mut set = |px: usize, py: usize, v: u8| out[py][px] = v;
+        match mode {
+            B_DC_PRED => {
+                let sum = top[..4]
+                    .iter()
+                    .chain([i, j, k, l].iter())
+                    .map(|&p| p as u32)
+                    .sum::<u32>();
+                out = [[((sum + 4) >> 3) as u8; 4]; 4];
+            }
+            B_TM_PRED => {
+                for (row, &left) in out.iter_mut().zip([i, j, k, l].iter()) {
+                    for (p, &above) in row.iter_mut().zip(top.iter()) {
+                        *p = clip8(above as i32 + left as i32 - x as i32);
+                    }
+                }
+            }
+            B_VE_PRED => {
+                out = [[avg3(x, a, b), avg3(a, b, c), avg3(b, c, d), avg3(c, d, e)]; 4];
+            }
+            B_HE_PRED => {
+                let vals = [avg3(x, i, j), avg3(i, j, k), avg3(j, k, l), avg3(k, l, l)];
+                for (row, &v) in out.iter_mut().zip(vals.iter()) {
+                    *row = [v; 4];
+                }
+            }
+            B_RD_PRED => {
+                let edge = [l, k, j, i, x, a, b, c, d];
+                for py in 0..4 {
+                    for px in 0..4 {
+                        let n = 3 + px - py;
+                        set(px, py, avg3(edge[n], edge[n + 1], edge[n + 2]));
+                    }
+                }
+            }
+            B_LD_PRED => {
+                for py in 0..4 {
+                    for px in 0..4 {
+                        let n = px + py;
+                        let v = if n == 6 {
+                            avg3(g, h, h)
+                        } else {
+                            avg3(top[n], top[n + 1], top[n + 2])
+                        };
+                        set(px, py, v);
+                    }
+                }
+            }
+            B_VR_PRED => {
+                set(0, 0, avg2(x, a));
+                set(1, 2, avg2(x, a));
+                set(1, 0, avg2(a, b));
+                set(2, 2, avg2(a, b));
+                set(2, 0, avg2(b, c));
+                set(3, 2, avg2(b, c));
+                set(3, 0, avg2(c, d));
+                set(0, 3, avg3(k, j, i));
+                set(0, 2, avg3(j, i, x));
+                set(0, 1, avg3(i, x, a));
+                set(1, 3, avg3(i, x, a));
+                set(1, 1, avg3(x, a, b));
+                set(2, 3, avg3(x, a, b));
+                set(2, 1, avg3(a, b, c));
+                set(3, 3, avg3(a, b, c));
+                set(3, 1, avg3(b, c, d));
+            }
+            B_VL_PRED => {
+                set(0, 0, avg2(a, b));
+                set(1, 0, avg2(b, c));
+                set(0, 2, avg2(b, c));
+                set(2, 0, avg2(c, d));
+                set(1, 2, avg2(c, d));
+                set(3, 0, avg2(d, e));
+                set(2, 2, avg2(d, e));
+                set(0, 1, avg3(a, b, c));
+                set(1, 1, avg3(b, c, d));
+                set(0, 3, avg3(b, c, d));
+                set(2, 1, avg3(c, d, e));
+                set(1, 3, avg3(c, d, e));
+                set(3, 1, avg3(d, e, f));
+                set(2, 3, avg3(d, e, f));
+                set(3, 2, avg3(e, f, g));
+                set(3, 3, avg3(f, g, h));
+            }
+            B_HD_PRED => {
+                set(0, 0, avg2(i, x));
+                set(2, 1, avg2(i, x));
+                set(0, 1, avg2(j, i));
+                set(2, 2, avg2(j, i));
+                set(0, 2, avg2(k, j));
+                set(2, 3, avg2(k, j));
+                set(0, 3, avg2(l, k));
+                set(3, 0, avg3(a, b, c));
+                set(2, 0, avg3(x, a, b));
+                set(1, 0, avg3(i, x, a));
+                set(3, 1, avg3(i, x, a));
+                set(1, 1, avg3(j, i, x));
+                set(3, 2, avg3(j, i, x));
+                set(1, 2, avg3(k, j, i));
+                set(3, 3, avg3(k, j, i));
+                set(1, 3, avg3(l, k, j));
+            }
+            _ => {
+                set(0, 0, avg2(i, j));
+                set(2, 0, avg2(j, k));
+                set(0, 1, avg2(j, k));
+                set(2, 1, avg2(k, l));
+                set(0, 2, avg2(k, l));
+                set(1, 0, avg3(i, j, k));
+                set(3, 0, avg3(j, k, l));
+                set(1, 1, avg3(j, k, l));
+                set(3, 1, avg3(k, l, l));
+                set(1, 2, avg3(k, l, l));
+                for &(px, py) in &[(3, 2), (2, 2), (0, 3), (1, 3), (2, 3), (3, 3)] {
+                    set(px, py, l);
+                }
+            }
+        }
+    }
+    for (py, row) in out.iter().enumerate() {
+        buf[off + py * stride..off + py * stride + 4].copy_from_slice(row);
+    }
+}
+
+/* Loop filters (see libwebp's `dsp/dec.c`) */
+
+fn sclip1(v: i32) -> i32 {
+    v.max(-128).min(127)
+}
+
+fn sclip2(v: i32) -> i32 {
+    v.max(-16).min(15)
+}
+
+fn needs_filter(p: &[u8], i: usize, step: usize, t: i32) -> bool {
+    let (p1, p0, q0, q1) = (p[i - 2 * step], p[i - step], p[i], p[i + step]);
+    4 * (p0 as i32 - q0 as i32).abs() + (p1 as i32 - q1 as i32).abs() <= t
+}
+
+fn needs_filter2(p: &[u8], i: usize, step: usize, t: i32, it: i32) -> bool {
+    let v = |k: isize| p[(i as isize + k * step as isize) as usize] as i32;
+    let (p3, p2, p1, p0, q0, q1, q2, q3) = (v(-4), v(-3), v(-2), v(-1), v(0), v(1), v(2), v(3));
+    if 4 * (p0 - q0).abs() + (p1 - q1).abs() > t {
+        return false;
+    }
+    (p3 - p2).abs() <= it
+        && (p2 - p1).abs() <= it
+        && (p1 - p0).abs() <= it
+        && (q3 - q2).abs() <= it
+        && (q2 - q1).abs() <= it
+        && (q1 - q0).abs() <= it
+}
+
+fn high_edge_variance(p: &[u8], i: usize, step: usize, thresh: i32) -> bool {
+    let (p1, p0, q0, q1) = (p[i - 2 * step], p[i - step], p[i], p[i + step]);
+    (p1 as i32 - p0 as i32).abs() > thresh || (q1 as i32 - q0 as i32).abs() > thresh
+}
+
+fn do_filter2(p: &mut [u8], i: usize, step: usize) {
+    let (p1, p0, q0, q1) = (
+        p[i - 2 * step] as i32,
+        p[i - step] as i32,
+        p[i] as i32,
+        p[i + step] as i32,
+    );
+    let a = 3 * (q0 - p0) + sclip1(p1 - q1);
+    let a1 = sclip2((a + 4) >> 3);
+    let a2 = sclip2((a + 3) >> 3);
+    p[i - step] = clip8(p0 + a2);
+    p[i] = clip8(q0 - a1);
+}
+
+fn do_filter4(p: &mut [u8], i: usize, step: usize) {
+    let (p1, p0, q0, q1) = (
+        p[i - 2 * step] as i32,
+        p[i - step] as i32,
+        p[i] as i32,
+        p[i + step] as i32,
+    );
+    let a = 3 * (q0 - p0);
+    let a1 = sclip2((a + 4) >> 3);
+    let a2 = sclip2((a + 3) >> 3);
+    let a3 = (a1 + 1) >> 1;
+    p[i - 2 * step] = clip8(p1 + a3);
+    p[i - step] = clip8(p0 + a2);
+    p[i] = clip8(q0 - a1);
+    p[i + step] = clip8(q1 - a3);
+}
+
+fn do_filter6(p: &mut [u8], i: usize, step: usize) {
+    let (p2, p1, p0) = (
+        p[i - 3 * step] as i32,
+        p[i - 2 * step] as i32,
+        p[i - step] as i32,
+    );
+    let (q0, q1, q2) = (p[i] as i32, p[i + step] as i32, p[i + 2 * step] as i32);
+    let a = sclip1(3 * (q0 - p0) + sclip1(p1 - q1));
+    let a1 = (27 * a + 63) >> 7;
+    let a2 = (18 * a + 63) >> 7;
+    let a3 = (9 * a + 63) >> 7;
+    p[i - 3 * step] = clip8(p2 + a3);
+    p[i - 2 * step] = clip8(p1 + a2);
+    p[i - step] = clip8(p0 + a1);
+    p[i] = clip8(q0 - a1);
+    p[i + step] = clip8(q1 - a2);
+    p[i + 2 * step] = clip8(q2 - a3);
+}
+
+/// Filters `size` pixels across an edge: `step` crosses the edge and `along`
+/// runs along it.
+fn simple_filter(p: &mut [u8], i: usize, step: usize, along: usize, size: usize, thresh: i32) {
+    let thresh2 = 2 * thresh + 1;
+    for n in 0..size {
+        let i = i + n * along;
+        if needs_filter(p, i, step, thresh2) {
+            do_filter2(p, i, step);
+        }
+    }
+}
+
+fn normal_filter(
+    p: &mut [u8],
+    i: usize,
+    (step, along, size): (usize, usize, usize),
+    (thresh, ithresh, hev_thresh): (i32, i32, i32),
+    is_mb_edge: bool,
+) {
+    let thresh2 = 2 * thresh + 1;
+    for n in 0..size {
+        let i = i + n * along;
+        if needs_filter2(p, i, step, thresh2, ithresh) {
+            if high_edge_variance(p, i, step, hev_thresh) {
+                do_filter2(p, i, step);
+            } else if is_mb_edge {
+                do_filter6(p, i, step);
+            } else {
+                do_filter4(p, i, step);
+            }
+        }
+    }
+}
+
+struct Vp8Frame {
+    mb_w: usize,
+    mb_h: usize,
+    y: Vec<u8>,
+    u: Vec<u8>,
+    v: Vec<u8>,
+}
+
+impl Vp8Frame {
+    fn filter(&mut self, infos: &[FilterInfo], simple: bool) {
+        let (y_stride, uv_stride) = (16 * self.mb_w, 8 * self.mb_w);
+        for mb_y in 0..self.mb_h {
+            for mb_x in 0..self.mb_w {
+                let f = infos[mb_y * self.mb_w + mb_x];
+                if f.limit == 0 {
+                    continue;
+                }
+                let y_off = 16 * mb_y * y_stride + 16 * mb_x;
+                let uv_off = 8 * mb_y * uv_stride + 8 * mb_x;
+                if simple {
+                    let y = &mut self.y;
+                    if mb_x > 0 {
+                        simple_filter(y, y_off, 1, y_stride, 16, f.limit + 4);
+                    }
+                    if f.inner {
+                        for k in 1..4 {
+                            simple_filter(y, y_off + 4 * k, 1, y_stride, 16, f.limit);
+                        }
+                    }
+                    if mb_y > 0 {
+                        simple_filter(y, y_off, y_stride, 1, 16, f.limit + 4);
+                    }
+                    if f.inner {
+                        for k in 1..4 {
+                            simple_filter(y, y_off + 4 * k * y_stride, y_stride, 1, 16, f.limit);
+                        }
+                    }
+                    continue;
+                }
+                let edge = (f.limit + 4, f.ilevel, f.hev_thresh);
+                let inner = (f.limit, f.ilevel, f.hev_thresh);
+                if mb_x > 0 {
+                    normal_filter(&mut self.y, y_off, (1, y_stride, 16), edge, true);
+                    normal_filter(&mut self.u, uv_off, (1, uv_stride, 8), edge, true);
+                    normal_filter(&mut self.v, uv_off, (1, uv_stride, 8), edge, true);
+                }
+                if f.inner {
+                    for k in 1..4 {
+                        normal_filter(&mut self.y, y_off + 4 * k, (1, y_stride, 16), inner, false);
+                    }
+                    normal_filter(&mut self.u, uv_off + 4, (1, uv_stride, 8), inner, false);
+                    normal_filter(&mut self.v, uv_off + 4, (1, uv_stride, 8), inner, false);
+                }
+                if mb_y > 0 {
+                    normal_filter(&mut self.y, y_off, (y_stride, 1, 16), edge, true);
+                    normal_filter(&mut self.u, uv_off, (uv_stride, 1, 8), edge, true);
+                    normal_filter(&mut self.v, uv_off, (uv_stride, 1, 8), edge, true);
+                }
+                if f.inner {
+                    for k in 1..4 {
+                        let off = y_off + 4 * k * y_stride;
+                        normal_filter(&mut self.y, off, (y_stride, 1, 16), inner, false);
+                    }
+                    let off = uv_off + 4 * uv_stride;
+                    normal_filter(&mut self.u, off, (uv_stride, 1, 8), inner, false);
+                    normal_filter(&mut self.v, off, (uv_stride, 1, 8), inner, false);
+                }
+            }
+        }
+    }
+
+    /// Converts to RGB with libwebp's "fancy" upsampling of the chroma.
+    fn to_rgb(&self, width: usize, height: usize) -> Vec<u8> {
+        fn mul_hi(v: u8, coeff: i32) -> i32 {
+            (v as i32 * coeff) >> 8
+        }
+        fn clip(v: i32) -> u8 {
+            (v >> 6).max(0).min(255) as u8
+        }
+        let (y_stride, uv_stride) = (16 * self.mb_w, 8 * self.mb_w);
+        let (uv_w, uv_h) = ((width + 1) / 2, (height + 1) / 2);
+        let near = |v: usize, n: usize| {
+            let main = v / 2;
+            let other = if v & 1 == 1 {
+                main + 1
+            } else {
+                main.wrapping_sub(1)
+            };
+            (main, if other < n { other } else { main })
+        };
+        let mut rgb = Vec::with_capacity(3 * width * height);
+        for y in 0..height {
+            let (row, vrow) = near(y, uv_h);
+            for x in 0..width {
+                let (col, hcol) = near(x, uv_w);
+                let chroma = |plane: &[u8]| {
+                    let s = |r: usize, c: usize| plane[r * uv_stride + c] as u32;
+                    let v = 9 * s(row, col) + 3 * s(row, hcol) + 3 * s(vrow, col) + s(vrow, hcol);
+                    ((v + 8) / 16) as u8
+                };
+                let (luma, u, v) = (self.y[y * y_stride + x], chroma(&self.u), chroma(&self.v));
+                rgb.push(clip(mul_hi(luma, 19077) + mul_hi(v, 26149) - 14234));
+                rgb.push(clip(
+                    mul_hi(luma, 19077) - mul_hi(u, 6419) - mul_hi(v, 13320) + 8708,
+                ));
+                rgb.push(clip(mul_hi(luma, 19077) + mul_hi(u, 33050) - 17685));
+            }
+        }
+        rgb
+    }
+}
+
+fn decode_vp8(data: &[u8]) -> Result<Picture, &'static str> {
+    if data.len() < 10 {
+        return Err("Truncated lossy image");
+    }
+    let tag = le24(data);
+    if tag & 1 != 0 {
+        return Err("Not a key frame");
+    }
+    if &data[3..6] != b"\x9d\x01\x2a" {
+        return Err("Invalid lossy image header");
+    }
+    let width = le24(&data[6..]) & 0x3fff;
+    let height = le24(&data[8..]) & 0x3fff;
+    if width == 0 || height == 0 {
+        return Err("Invalid image size");
+    }
+    let first_size = tag >> 5;
+    let first = data
+        .get(10..10 + first_size)
+        .ok_or("Truncated lossy image")?;
+    let mut bd = BoolDecoder::new(first);
+    let _color_space = bd.read_flag();
+    let _clamping = bd.read_flag();
+
+    /* Segmentation */
+    let use_segment = bd.read_flag();
+    let mut update_map = false;
+    let mut absolute_delta = false;
+    let mut segment_quant = [0i32; 4];
+    let mut segment_filter = [0i32; 4];
+    let mut segment_probs = [255u8; 3];
+    if use_segment {
+        update_map = bd.read_flag();
+        if bd.read_flag() {
+            absolute_delta = bd.read_flag();
+            for q in segment_quant.iter_mut() {
+                *q = bd.read_optional_signed(7);
+            }
+            for f in segment_filter.iter_mut() {
+                *f = bd.read_optional_signed(6);
+            }
+        }
+        if update_map {
+            for p in segment_probs.iter_mut() {
+                *p = if bd.read_flag() {
+                    bd.read_literal(8) as u8
+                } else {
+                    255
+                };
+            }
+        }
+    }
+
+    /* Loop filter */
+    let simple_filter = bd.read_flag();
+    let filter_level = bd.read_literal(6);
+    let sharpness = bd.read_literal(3);
+    let use_lf_delta = bd.read_flag();
+    let (mut ref_lf_delta, mut mode_lf_delta) = ([0i32; 4], [0i32; 4]);
+    if use_lf_delta && bd.read_flag() {
+        for d in ref_lf_delta.iter_mut().chain(mode_lf_delta.iter_mut()) {
+            if bd.read_flag() {
+                *d = bd.read_signed(6);
+            }
+        }
+    }
+
+    /* Token partitions */
+    let num_partitions = 1 << bd.read_literal(2);
+    let rest = &data[10 + first_size..];
+    let sizes_len = 3 * (num_partitions - 1);
+    if rest.len() < sizes_len {
+        return Err("Truncated lossy image");
+    }
+    let mut partitions = Vec::with_capacity(num_partitions);
+    let mut start = sizes_len;
+    for p in 0..num_partitions {
+        let end = if p + 1 < num_partitions {
+            (start + le24(&rest[3 * p..])).min(rest.len())
+        } else {
+            rest.len()
+        };
+        partitions.push(BoolDecoder::new(&rest[start.min(end)..end]));
+        start = end;
+    }
+
+    /* Quantizers */
+    let base_q = bd.read_literal(7);
+    let dq_y1_dc = bd.read_optional_signed(4);
+    let dq_y2_dc = bd.read_optional_signed(4);
+    let dq_y2_ac = bd.read_optional_signed(4);
+    let dq_uv_dc = bd.read_optional_signed(4);
+    let dq_uv_ac = bd.read_optional_signed(4);
+    let mut quant = [QuantMatrix::default(); 4];
+    for (s, m) in quant.iter_mut().enumerate() {
+        let q = if use_segment {
+            segment_quant[s] + if absolute_delta { 0 } else { base_q }
+        } else {
+            base_q
+        };
+        let dc = |d: i32, max: i32| DC_QUANT[(q + d).max(0).min(max) as usize] as i32;
+        let ac = |d: i32| AC_QUANT[(q + d).max(0).min(127) as usize] as i32;
+        m.y1 = (dc(dq_y1_dc, 127), ac(0));
+        m.y2 = (
+            2 * dc(dq_y2_dc, 127),
+            ((ac(dq_y2_ac) * 101_581) >> 16).max(8),
+        );
+        m.uv = (dc(dq_uv_dc, 117), ac(dq_uv_ac));
+    }
+
+    let _refresh_entropy_probs = bd.read_flag();
+    let mut probs: TokenProbs = COEFF_PROBS;
+    for (t, plane) in probs.iter_mut().enumerate() {
+        for (b, band) in plane.iter_mut().enumerate() {
+            for (c, ctx) in band.iter_mut().enumerate() {
+                for (p, prob) in ctx.iter_mut().enumerate() {
+                    if bd.read_bool(COEFF_UPDATE_PROBS[t][b][c][p]) {
+                        *prob = bd.read_literal(8) as u8;
+                    }
+                }
+            }
+        }
+    }
+    let skip_prob = if bd.read_flag() {
+        Some(bd.read_literal(8) as u8)
+    } else {
+        None
+    };
+
+    /* Loop filter strengths by segment and by use of subblock prediction */
+    let mut strengths = [[FilterInfo::default(); 2]; 4];
+    for (s, strength) in strengths.iter_mut().enumerate() {
+        let base_level = if use_segment {
+            segment_filter[s] + if absolute_delta { 0 } else { filter_level }
+        } else {
+            filter_level
+        };
+        for (i4x4, info) in strength.iter_mut().enumerate() {
+            let mut level = base_level;
+            if use_lf_delta {
+                level += ref_lf_delta[0];
+                if i4x4 == 1 {
+                    level += mode_lf_delta[0];
+                }
+            }
+            let level = level.max(0).min(63);
+            if level > 0 {
+                let mut ilevel = level;
+                if sharpness > 0 {
+                    ilevel >>= if sharpness > 4 { 2 } else { 1 };
+                    ilevel = ilevel.min(9 - sharpness);
+                }
+                let ilevel = ilevel.max(1);
+                *info = FilterInfo {
+                    limit: 2 * level + ilevel,
+                    ilevel,
+                    hev_thresh: if level >= 40 {
+                        2
+                    } else if level >= 15 {
+                        1
+                    } else {
+                        0
+                    },
+                    inner: i4x4 == 1,
+                };
+            }
+        }
+    }
+
+    let (mb_w, mb_h) = ((width + 15) / 16, (height + 15) / 16);
+    let mut frame = Vp8Frame {
+        mb_w,
+        mb_h,
+        y: vec![0; 256 * mb_w * mb_h],
+        u: vec![0; 64 * mb_w * mb_h],
+        v: vec![0; 64 * mb_w * mb_h],
+    };
+    let mut filter_infos = vec![FilterInfo::default(); mb_w * mb_h];
+    /* Contexts: subblock modes and non-zero flags (Y, U, V, Y2) */
+    let mut top_modes = vec![[B_DC_PRED; 4]; mb_w];
+    let mut top_nz = vec![[false; 9]; mb_w];
+    for mb_y in 0..mb_h {
+        let mut left_modes = [B_DC_PRED; 4];
+        let mut left_nz = [false; 9];
+        let tokens = &mut partitions[mb_y & (num_partitions - 1)];
+        for mb_x in 0..mb_w {
+            let segment = if update_map {
+                bd.read_tree(&SEGMENT_TREE, &segment_probs)
+            } else {
+                0
+            };
+            let mut skip = skip_prob.is_some() && bd.read_bool(skip_prob.unwrap());
+            let ymode = bd.read_tree(&YMODE_TREE, &YMODE_PROBS);
+            let mut modes = [B_DC_PRED; 16];
+            if ymode == B_PRED {
+                for i in 0..16 {
+                    let (x, y) = (i & 3, i >> 2);
+                    let top = if y == 0 {
+                        top_modes[mb_x][x]
+                    } else {
+                        modes[i - 4]
+                    };
+                    let left = if x == 0 { left_modes[y] } else { modes[i - 1] };
+                    modes[i] = bd.read_tree(&BMODE_TREE, &BMODE_PROBS[top][left]);
+                }
+            } else {
+                let mode = match ymode {
+                    V_PRED => B_VE_PRED,
+                    H_PRED => B_HE_PRED,
+                    TM_PRED => B_TM_PRED,
+                    _ => B_DC_PRED,
+                };
+                modes = [mode; 16];
+            }
+            for i in 0..4 {
+                top_modes[mb_x][i] = modes[12 + i];
+                left_modes[i] = modes[4 * i + 3];
+            }
+            let uv_mode = bd.read_tree(&UV_MODE_TREE, &UV_MODE_PROBS);
+
+            /* Residuals */
+            let q = quant[segment];
+            let mut y_coeffs = [[0i32; 16]; 16];
+            let mut uv_coeffs = [[0i32; 16]; 8];
+            let tnz = &mut top_nz[mb_x];
+            if !skip {
+                let mut non_zero = false;
+                let (mut first, mut plane) = (0, 3);
+                if ymode != B_PRED {
+                    let mut y2 = [0i32; 16];
+                    let ctx = tnz[8] as usize + left_nz[8] as usize;
+                    let nz = read_coefficients(tokens, &probs[1], ctx, q.y2, 0, &mut y2);
+                    tnz[8] = nz > 0;
+                    left_nz[8] = nz > 0;
+                    inverse_wht(&y2, &mut y_coeffs);
+                    first = 1;
+                    plane = 0;
+                }
+                for (i, block) in y_coeffs.iter_mut().enumerate() {
+                    let (x, y) = (i & 3, i >> 2);
+                    let ctx = tnz[x] as usize + left_nz[y] as usize;
+                    let nz = read_coefficients(tokens, &probs[plane], ctx, q.y1, first, block);
+                    tnz[x] = nz > first;
+                    left_nz[y] = nz > first;
+                    non_zero |= nz > 1 || block[0] != 0;
+                }
+                for (i, block) in uv_coeffs.iter_mut().enumerate() {
+                    let (ch, x, y) = (i >> 2, i & 1, i >> 1 & 1);
+                    let (t, l) = (4 + 2 * ch + x, 4 + 2 * ch + y);
+                    let ctx = tnz[t] as usize + left_nz[l] as usize;
+                    let nz = read_coefficients(tokens, &probs[2], ctx, q.uv, 0, block);
+                    tnz[t] = nz > 0;
+                    left_nz[l] = nz > 0;
+                    non_zero |= nz > 1 || block[0] != 0;
+                }
+                skip = !non_zero;
+            } else {
+                let keep = if ymode == B_PRED { 8 } else { 9 };
+                for i in 0..keep {
+                    tnz[i] = false;
+                    left_nz[i] = false;
+                }
+            }
+            let mut info = strengths[segment][(ymode == B_PRED) as usize];
+            info.inner |= !skip;
+            filter_infos[mb_y * mb_w + mb_x] = info;
+
+            frame.reconstruct_mb(mb_x, mb_y, ymode, &modes, uv_mode, &y_coeffs, &uv_coeffs);
+        }
+    }
+    if filter_level > 0 {
+        frame.filter(&filter_infos, simple_filter);
+    }
+    Ok(Picture {
+        width,
+        height,
+        rgb: frame.to_rgb(width, height),
+        alpha: None,
+    })
+}
+
+impl Vp8Frame {
+    /// Predicts a macroblock from its (unfiltered) neighbours and adds the
+    /// residuals, in a work area with a one-pixel border and the four pixels
+    /// above-right of the luma block.
+    #[allow(clippy::too_many_arguments)]
+    fn reconstruct_mb(
+        &mut self,
+        mb_x: usize,
+        mb_y: usize,
+        ymode: usize,
+        modes: &[usize; 16],
+        uv_mode: usize,
+        y_coeffs: &[[i32; 16]; 16],
+        uv_coeffs: &[[i32; 16]; 8],
+    ) {
+        const YS: usize = 21;
+        const US: usize = 9;
+        let (y_stride, uv_stride) = (16 * self.mb_w, 8 * self.mb_w);
+        let mut ybuf = [0u8; YS * 17];
+        let x0 = 16 * mb_x;
+        let y0 = 16 * mb_y;
+        if mb_y == 0 {
+            for p in ybuf[..YS].iter_mut() {
+                *p = 127;
+            }
+        } else {
+            let above = (y0 - 1) * y_stride;
+            ybuf[0] = if mb_x == 0 {
+                129
+            } else {
+                self.y[above + x0 - 1]
+            };
+            ybuf[1..17].copy_from_slice(&self.y[above + x0..above + x0 + 16]);
+            for k in 0..4 {
+                ybuf[17 + k] = if mb_x + 1 < self.mb_w {
+                    self.y[above + x0 + 16 + k]
+                } else {
+                    self.y[above + x0 + 15]
+                };
+            }
+        }
+        for r in 0..16 {
+            ybuf[(r + 1) * YS] = if mb_x == 0 {
+                129
+            } else {
+                self.y[(y0 + r) * y_stride + x0 - 1]
+            };
+        }
+        /* Subblocks on the right edge take the pixels above-right of the
+         * macroblock as their above-right pixels. */
+        for r in &[4, 8, 12] {
+            let (src, dst) = (17, r * YS + 17);
+            ybuf.copy_within(src..src + 4, dst);
+        }
+        let edges = (mb_y > 0, mb_x > 0);
+        if ymode == B_PRED {
+            for (i, block) in y_coeffs.iter().enumerate() {
+                let off = (1 + 4 * (i >> 2)) * YS + 1 + 4 * (i & 3);
+                predict_subblock(&mut ybuf, off, YS, modes[i]);
+                add_inverse_dct(block, &mut ybuf, off, YS);
+            }
+        } else {
+            predict_block(&mut ybuf, YS + 1, YS, 16, ymode, edges);
+            for (i, block) in y_coeffs.iter().enumerate() {
+                let off = (1 + 4 * (i >> 2)) * YS + 1 + 4 * (i & 3);
+                add_inverse_dct(block, &mut ybuf, off, YS);
+            }
+        }
+        for r in 0..16 {
+            let dst = (y0 + r) * y_stride + x0;
+            self.y[dst..dst + 16].copy_from_slice(&ybuf[(r + 1) * YS + 1..(r + 1) * YS + 17]);
+        }
+
+        let (cx0, cy0) = (8 * mb_x, 8 * mb_y);
+        for (ch, plane) in [&mut self.u, &mut self.v].iter_mut().enumerate() {
+            let mut buf = [0u8; US * 9];
+            if mb_y == 0 {
+                for p in buf[..US].iter_mut() {
+                    *p = 127;
+                }
+            } else {
+                let above = (cy0 - 1) * uv_stride;
+                buf[0] = if mb_x == 0 {
+                    129
+                } else {
+                    plane[above + cx0 - 1]
+                };
+                buf[1..9].copy_from_slice(&plane[above + cx0..above + cx0 + 8]);
+            }
+            for r in 0..8 {
+                buf[(r + 1) * US] = if mb_x == 0 {
+                    129
+                } else {
+                    plane[(cy0 + r) * uv_stride + cx0 - 1]
+                };
+            }
+            predict_block(&mut buf, US + 1, US, 8, uv_mode, edges);
+            for i in 0..4 {
+                let off = (1 + 4 * (i >> 1)) * US + 1 + 4 * (i & 1);
+                add_inverse_dct(&uv_coeffs[4 * ch + i], &mut buf, off, US);
+            }
+            for r in 0..8 {
+                let dst = (cy0 + r) * uv_stride + cx0;
+                plane[dst..dst + 8].copy_from_slice(&buf[(r + 1) * US + 1..(r + 1) * US + 9]);
+            }
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn check_for_webp(handle: &mut InputHandleWrapper) -> i32 {
+    let mut sigbytes = [0u8; 12];
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    if handle.read_exact(&mut sigbytes).is_err()
+        || &sigbytes[0..4] != b"RIFF"
+        || &sigbytes[8..12] != b"WEBP"
+    {
+        return 0;
+    }
+    1
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn webp_get_bbox(
+    handle: &mut InputHandleWrapper,
+    width: *mut u32,
+    height: *mut u32,
+    xdensity: *mut f64,
+    ydensity: *mut f64,
+) -> i32 {
+    let mut data = Vec::new();
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    if handle.read_to_end(&mut data).is_err() {
+        warn!("WebP: Reading file failed.");
+        return -1;
+    }
+    match read_size(&data) {
+        Ok((w, h)) => {
+            *width = w as u32;
+            *height = h as u32;
+            *xdensity = 1.;
+            *ydensity = 1.;
+            0
+        }
+        Err(e) => {
+            warn!("WebP: {}.", e);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn webp_include_image(
+    ximage: *mut pdf_ximage,
+    handle: &mut InputHandleWrapper,
+) -> i32 {
+    let mut info = ximage_info::default();
+    pdf_ximage_init_image_info(&mut info);
+    let mut data = Vec::new();
+    handle.seek(SeekFrom::Start(0)).unwrap();
+    if handle.read_to_end(&mut data).is_err() {
+        warn!("WebP: Reading file failed.");
+        return -1;
+    }
+    let (mut picture, icc_profile) = match read_webp(&data) {
+        Ok(image) => image,
+        Err(e) => {
+            warn!("WebP: {}.", e);
+            return -1;
+        }
+    };
+    info.width = picture.width as i32;
+    info.height = picture.height as i32;
+    info.bits_per_component = 8;
+    info.num_components = 3;
+
+    let mut colorspace = 0 as *mut pdf_obj;
+    if let Some(profile) = icc_profile {
+        let (ptr, len) = (
+            profile.as_ptr() as *const libc::c_void,
+            profile.len() as i32,
+        );
+        if iccp_check_colorspace(-3, ptr, len) >= 0 {
+            let cspc_id = iccp_load_profile(0 as *const i8, ptr, len);
+            if cspc_id >= 0 {
+                colorspace = pdf_get_colorspace_reference(cspc_id);
+            }
+        }
+    }
+    if colorspace.is_null() {
+        colorspace = pdf_new_name("DeviceRGB");
+    }
+    let alpha = picture
+        .alpha
+        .take()
+        .filter(|alpha| !pdf_ximage_is_opaque(alpha, 8));
+    let stream = pdf_new_stream(STREAM_COMPRESS);
+    let stream_dict = (*stream).as_stream_mut().get_dict_mut();
+    pdf_add_dict(stream_dict, "ColorSpace", colorspace);
+    if let Some(alpha) = alpha {
+        if pdf_get_version() < 4 {
+            warn!("WebP: Transparency will be ignored. (no support in PDF ver. < 1.4)");
+            warn!("WebP: Please use -V 4 option to enable full alpha channel support.");
+            pdf_ximage_blend_white(&mut picture.rgb, &alpha, 3, false, 255);
+        } else {
+            let smask = pdf_ximage_soft_mask(&alpha, info.width, info.height, 8, None);
+            pdf_add_dict(stream_dict, "SMask", pdf_ref_obj(smask));
+            pdf_release_obj(smask);
+        }
+    }
+    pdf_add_stream(
+        &mut *stream,
+        picture.rgb.as_ptr() as *const libc::c_void,
+        picture.rgb.len() as i32,
+    );
+    if info.height > 64 {
+        pdf_stream_set_predictor(stream, 15, info.width, 8, 3);
+    }
+    pdf_ximage_set_image(ximage, &mut info, stream);
+    0
+}
+
+/* Tables from RFC 6386 */
+#[rustfmt::skip]
+const BMODE_PROBS: [[[u8; 9]; 10]; 10] = [
+    [
+        [231, 120, 48, 89, 115, 113, 120, 152, 112],
+        [152, 179, 64, 126, 170, 118, 46, 70, 95],
+        [175, 69, 143, 80, 85, 82, 72, 155, 103],
+        [56, 58, 10, 171, 218, 189, 17, 13, 152],
+        [144, 71, 10, 38, 171, 213, 144, 34, 26],
+        [114, 26, 17, 163, 44, 195, 21, 10, 173],
+        [121, 24, 80, 195, 26, 62, 44, 64, 85],
+        [170, 46, 55, 19, 136, 160, 33, 206, 71],
+        [63, 20, 8, 114, 114, 208, 12, 9, 226],
+        [81, 40, 11, 96, 182, 84, 29, 16, 36],
+    ],
+    [
+        [134, 183, 89, 137, 98, 101, 106, 165, 148],
+        [72, 187, 100, 130, 157, 111, 32, 75, 80],
+        [66, 102, 167, 99, 74, 62, 40, 234, 128],
+        [41, 53, 9, 178, 241, 141, 26, 8, 107],
+        [104, 79, 12, 27, 217, 255, 87, 17, 7],
+        [74, 43, 26, 146, 73, 166, 49, 23, 157],
+        [65, 38, 105, 160, 51, 52, 31, 115, 128],
+        [87, 68, 71, 44, 114, 51, 15, 186, 23],
+        [47, 41, 14, 110, 182, 183, 21, 17, 194],
+        [66, 45, 25, 102, 197, 189, 23, 18, 22],
+    ],
+    [
+        [88, 88, 147, 150, 42, 46, 45, 196, 205],
+        [43, 97, 183, 117, 85, 38, 35, 179, 61],
+        [39, 53, 200, 87, 26, 21, 43, 232, 171],
+        [56, 34, 51, 104, 114, 102, 29, 93, 77],
+        [107, 54, 32, 26, 51, 1, 81, 43, 31],
+        [39, 28, 85, 171, 58, 165, 90, 98, 64],
+        [34, 22, 116, 206, 23, 34, 43, 166, 73],
+        [68, 25, 106, 22, 64, 171, 36, 225, 114],
+        [34, 19, 21, 102, 132, 188, 16, 76, 124],
+        [62, 18, 78, 95, 85, 57, 50, 48, 51],
+    ],
+    [
+        [193, 101, 35, 159, 215, 111, 89, 46, 111],
+        [60, 148, 31, 172, 219, 228, 21, 18, 111],
+        [112, 113, 77, 85, 179, 255, 38, 120, 114],
+        [40, 42, 1, 196, 245, 209, 10, 25, 109],
+        [100, 80, 8, 43, 154, 1, 51, 26, 71],
+        [88, 43, 29, 140, 166, 213, 37, 43, 154],
+        [61, 63, 30, 155, 67, 45, 68, 1, 209],
+        [142, 78, 78, 16, 255, 128, 34, 197, 171],
+        [41, 40, 5, 102, 211, 183, 4, 1, 221],
+        [51, 50, 17, 168, 209, 192, 23, 25, 82],
+    ],
+    [
+        [125, 98, 42, 88, 104, 85, 117, 175, 82],
+        [95, 84, 53, 89, 128, 100, 113, 101, 45],
+        [75, 79, 123, 47, 51, 128, 81, 171, 1],
+        [57, 17, 5, 71, 102, 57, 53, 41, 49],
+        [115, 21, 2, 10, 102, 255, 166, 23, 6],
+        [38, 33, 13, 121, 57, 73, 26, 1, 85],
+        [41, 10, 67, 138, 77, 110, 90, 47, 114],
+        [101, 29, 16, 10, 85, 128, 101, 196, 26],
+        [57, 18, 10, 102, 102, 213, 34, 20, 43],
+        [117, 20, 15, 36, 163, 128, 68, 1, 26],
+    ],
+    [
+        [138, 31, 36, 171, 27, 166, 38, 44, 229],
+        [67, 87, 58, 169, 82, 115, 26, 59, 179],
+        [63, 59, 90, 180, 59, 166, 93, 73, 154],
+        [40, 40, 21, 116, 143, 209, 34, 39, 175],
+        [57, 46, 22, 24, 128, 1, 54, 17, 37],
+        [47, 15, 16, 183, 34, 223, 49, 45, 183],
+        [46, 17, 33, 183, 6, 98, 15, 32, 183],
+        [65, 32, 73, 115, 28, 128, 23, 128, 205],
+        [40, 3, 9, 115, 51, 192, 18, 6, 223],
+        [87, 37, 9, 115, 59, 77, 64, 21, 47],
+    ],
+    [
+        [104, 55, 44, 218, 9, 54, 53, 130, 226],
+        [64, 90, 70, 205, 40, 41, 23, 26, 57],
+        [54, 57, 112, 184, 5, 41, 38, 166, 213],
+        [30, 34, 26, 133, 152, 116, 10, 32, 134],
+        [75, 32, 12, 51, 192, 255, 160, 43, 51],
+        [39, 19, 53, 221, 26, 114, 32, 73, 255],
+        [31, 9, 65, 234, 2, 15, 1, 118, 73],
+        [88, 31, 35, 67, 102, 85, 55, 186, 85],
+        [56, 21, 23, 111, 59, 205, 45, 37, 192],
+        [55, 38, 70, 124, 73, 102, 1, 34, 98],
+    ],
+    [
+        [102, 61, 71, 37, 34, 53, 31, 243, 192],
+        [69, 60, 71, 38, 73, 119, 28, 222, 37],
+        [68, 45, 128, 34, 1, 47, 11, 245, 171],
+        [62, 17, 19, 70, 146, 85, 55, 62, 70],
+        [75, 15, 9, 9, 64, 255, 184, 119, 16],
+        [37, 43, 37, 154, 100, 163, 85, 160, 1],
+        [63, 9, 92, 136, 28, 64, 32, 201, 85],
+        [86, 6, 28, 5, 64, 255, 25, 248, 1],
+        [56, 8, 17, 132, 137, 255, 55, 116, 128],
+        [58, 15, 20, 82, 135, 57, 26, 121, 40],
+    ],
+    [
+        [164, 50, 31, 137, 154, 133, 25, 35, 218],
+        [51, 103, 44, 131, 131, 123, 31, 6, 158],
+        [86, 40, 64, 135, 148, 224, 45, 183, 128],
+        [22, 26, 17, 131, 240, 154, 14, 1, 209],
+        [83, 12, 13, 54, 192, 255, 68, 47, 28],
+        [45, 16, 21, 91, 64, 222, 7, 1, 197],
+        [56, 21, 39, 155, 60, 138, 23, 102, 213],
+        [85, 26, 85, 85, 128, 128, 32, 146, 171],
+        [18, 11, 7, 63, 144, 171, 4, 4, 246],
+        [35, 27, 10, 146, 174, 171, 12, 26, 128],
+    ],
+    [
+        [190, 80, 35, 99, 180, 80, 126, 54, 45],
+        [85, 126, 47, 87, 176, 51, 41, 20, 32],
+        [101, 75, 128, 139, 118, 146, 116, 128, 85],
+        [56, 41, 15, 176, 236, 85, 37, 9, 62],
+        [146, 36, 19, 30, 171, 255, 97, 27, 20],
+        [71, 30, 17, 119, 118, 255, 17, 18, 138],
+        [101, 38, 60, 138, 55, 70, 43, 26, 142],
+        [138, 45, 61, 62, 219, 1, 81, 188, 64],
+        [32, 41, 20, 117, 151, 142, 20, 21, 163],
+        [112, 19, 12, 61, 195, 128, 48, 4, 24],
+    ],
+];
+
+#[rustfmt::skip]
+const COEFF_UPDATE_PROBS: TokenProbs = [
+    [
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [176, 246, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [223, 241, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 244, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [234, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 246, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [239, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 248, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 253, 255, 254, 255, 255, 255, 255, 255, 255],
+            [250, 255, 254, 255, 254, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [217, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [225, 252, 241, 253, 255, 255, 254, 255, 255, 255, 255],
+            [234, 250, 241, 250, 253, 255, 253, 254, 255, 255, 255],
+        ],
+        [
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [223, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [238, 253, 254, 254, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 248, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [247, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [186, 251, 250, 255, 255, 255, 255, 255, 255, 255, 255],
+            [234, 251, 244, 254, 255, 255, 255, 255, 255, 255, 255],
+            [251, 251, 243, 253, 254, 255, 254, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [236, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [251, 253, 253, 254, 254, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 254, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+    [
+        [
+            [248, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 254, 252, 254, 255, 255, 255, 255, 255, 255, 255],
+            [248, 254, 249, 253, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [246, 253, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 254, 251, 254, 254, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 254, 252, 255, 255, 255, 255, 255, 255, 255, 255],
+            [248, 254, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 255, 254, 254, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 251, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [245, 251, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [253, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 251, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [252, 253, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 254, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 252, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [249, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 254, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 253, 255, 255, 255, 255, 255, 255, 255, 255],
+            [250, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+        [
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [254, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+            [255, 255, 255, 255, 255, 255, 255, 255, 255, 255, 255],
+        ],
+    ],
+];
+
+#[rustfmt::skip]
+const COEFF_PROBS: TokenProbs = [
+    [
+        [
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [253, 136, 254, 255, 228, 219, 128, 128, 128, 128, 128],
+            [189, 129, 242, 255, 227, 213, 255, 219, 128, 128, 128],
+            [106, 126, 227, 252, 214, 209, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 98, 248, 255, 236, 226, 255, 255, 128, 128, 128],
+            [181, 133, 238, 254, 221, 234, 255, 154, 128, 128, 128],
+            [78, 134, 202, 247, 198, 180, 255, 219, 128, 128, 128],
+        ],
+        [
+            [1, 185, 249, 255, 243, 255, 128, 128, 128, 128, 128],
+            [184, 150, 247, 255, 236, 224, 128, 128, 128, 128, 128],
+            [77, 110, 216, 255, 236, 230, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 101, 251, 255, 241, 255, 128, 128, 128, 128, 128],
+            [170, 139, 241, 252, 236, 209, 255, 255, 128, 128, 128],
+            [37, 116, 196, 243, 228, 255, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 204, 254, 255, 245, 255, 128, 128, 128, 128, 128],
+            [207, 160, 250, 255, 238, 128, 128, 128, 128, 128, 128],
+            [102, 103, 231, 255, 211, 171, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 152, 252, 255, 240, 255, 128, 128, 128, 128, 128],
+            [177, 135, 243, 255, 234, 225, 128, 128, 128, 128, 128],
+            [80, 129, 211, 255, 194, 224, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [246, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [255, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [198, 35, 237, 223, 193, 187, 162, 160, 145, 155, 62],
+            [131, 45, 198, 221, 172, 176, 220, 157, 252, 221, 1],
+            [68, 47, 146, 208, 149, 167, 221, 162, 255, 223, 128],
+        ],
+        [
+            [1, 149, 241, 255, 221, 224, 255, 255, 128, 128, 128],
+            [184, 141, 234, 253, 222, 220, 255, 199, 128, 128, 128],
+            [81, 99, 181, 242, 176, 190, 249, 202, 255, 255, 128],
+        ],
+        [
+            [1, 129, 232, 253, 214, 197, 242, 196, 255, 255, 128],
+            [99, 121, 210, 250, 201, 198, 255, 202, 128, 128, 128],
+            [23, 91, 163, 242, 170, 187, 247, 210, 255, 255, 128],
+        ],
+        [
+            [1, 200, 246, 255, 234, 255, 128, 128, 128, 128, 128],
+            [109, 178, 241, 255, 231, 245, 255, 255, 128, 128, 128],
+            [44, 130, 201, 253, 205, 192, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 132, 239, 251, 219, 209, 255, 165, 128, 128, 128],
+            [94, 136, 225, 251, 218, 190, 255, 255, 128, 128, 128],
+            [22, 100, 174, 245, 186, 161, 255, 199, 128, 128, 128],
+        ],
+        [
+            [1, 182, 249, 255, 232, 235, 128, 128, 128, 128, 128],
+            [124, 143, 241, 255, 227, 234, 128, 128, 128, 128, 128],
+            [35, 77, 181, 251, 193, 211, 255, 205, 128, 128, 128],
+        ],
+        [
+            [1, 157, 247, 255, 236, 231, 255, 255, 128, 128, 128],
+            [121, 141, 235, 255, 225, 227, 255, 255, 128, 128, 128],
+            [45, 99, 188, 251, 195, 217, 255, 224, 128, 128, 128],
+        ],
+        [
+            [1, 1, 251, 255, 213, 255, 128, 128, 128, 128, 128],
+            [203, 1, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+            [137, 1, 177, 255, 224, 255, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [253, 9, 248, 251, 207, 208, 255, 192, 128, 128, 128],
+            [175, 13, 224, 243, 193, 185, 249, 198, 255, 255, 128],
+            [73, 17, 171, 221, 161, 179, 236, 167, 255, 234, 128],
+        ],
+        [
+            [1, 95, 247, 253, 212, 183, 255, 255, 128, 128, 128],
+            [239, 90, 244, 250, 211, 209, 255, 255, 128, 128, 128],
+            [155, 77, 195, 248, 188, 195, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 24, 239, 251, 218, 219, 255, 205, 128, 128, 128],
+            [201, 51, 219, 255, 196, 186, 128, 128, 128, 128, 128],
+            [69, 46, 190, 239, 201, 218, 255, 228, 128, 128, 128],
+        ],
+        [
+            [1, 191, 251, 255, 255, 128, 128, 128, 128, 128, 128],
+            [223, 165, 249, 255, 213, 255, 128, 128, 128, 128, 128],
+            [141, 124, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 16, 248, 255, 255, 128, 128, 128, 128, 128, 128],
+            [190, 36, 230, 255, 236, 255, 128, 128, 128, 128, 128],
+            [149, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 226, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [247, 192, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [240, 128, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [1, 134, 252, 255, 255, 128, 128, 128, 128, 128, 128],
+            [213, 62, 250, 255, 255, 128, 128, 128, 128, 128, 128],
+            [55, 93, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+        [
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+            [128, 128, 128, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+    [
+        [
+            [202, 24, 213, 235, 186, 191, 220, 160, 240, 175, 255],
+            [126, 38, 182, 232, 169, 184, 228, 174, 255, 187, 128],
+            [61, 46, 138, 219, 151, 178, 240, 170, 255, 216, 128],
+        ],
+        [
+            [1, 112, 230, 250, 199, 191, 247, 159, 255, 255, 128],
+            [166, 109, 228, 252, 211, 215, 255, 174, 128, 128, 128],
+            [39, 77, 162, 232, 172, 180, 245, 178, 255, 255, 128],
+        ],
+        [
+            [1, 52, 220, 246, 198, 199, 249, 220, 255, 255, 128],
+            [124, 74, 191, 243, 183, 193, 250, 221, 255, 255, 128],
+            [24, 71, 130, 219, 154, 170, 243, 182, 255, 255, 128],
+        ],
+        [
+            [1, 182, 225, 249, 219, 240, 255, 224, 128, 128, 128],
+            [149, 150, 226, 252, 216, 205, 255, 171, 128, 128, 128],
+            [28, 108, 170, 242, 183, 194, 254, 223, 255, 255, 128],
+        ],
+        [
+            [1, 81, 230, 252, 204, 203, 255, 192, 128, 128, 128],
+            [123, 102, 209, 247, 188, 196, 255, 233, 128, 128, 128],
+            [20, 95, 153, 243, 164, 173, 255, 203, 128, 128, 128],
+        ],
+        [
+            [1, 222, 248, 255, 216, 213, 128, 128, 128, 128, 128],
+            [168, 175, 246, 252, 235, 205, 255, 255, 128, 128, 128],
+            [47, 116, 215, 255, 211, 212, 255, 255, 128, 128, 128],
+        ],
+        [
+            [1, 121, 236, 253, 212, 214, 255, 255, 128, 128, 128],
+            [141, 84, 213, 252, 201, 202, 255, 219, 128, 128, 128],
+            [42, 80, 160, 240, 162, 185, 255, 205, 128, 128, 128],
+        ],
+        [
+            [1, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [244, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+            [238, 1, 255, 128, 128, 128, 128, 128, 128, 128, 128],
+        ],
+    ],
+];
+
+#[rustfmt::skip]
+const DC_QUANT: [i16; 128] = [
+    4, 5, 6, 7, 8, 9, 10, 10, 11, 12, 13, 14, 15, 16, 17, 17,
+    18, 19, 20, 20, 21, 21, 22, 22, 23, 23, 24, 25, 25, 26, 27, 28,
+    29, 30, 31, 32, 33, 34, 35, 36, 37, 37, 38, 39, 40, 41, 42, 43,
+    44, 45, 46, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55, 56, 57, 58,
+    59, 60, 61, 62, 63, 64, 65, 66, 67, 68, 69, 70, 71, 72, 73, 74,
+    75, 76, 76, 77, 78, 79, 80, 81, 82, 83, 84, 85, 86, 87, 88, 89,
+    91, 93, 95, 96, 98, 100, 101, 102, 104, 106, 108, 110, 112, 114, 116, 118,
+    122, 124, 126, 128, 130, 132, 134, 136, 138, 140, 143, 145, 148, 151, 154, 157,
+];
+
+#[rustfmt::skip]
+const AC_QUANT: [i16; 128] = [
+    4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19,
+    20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33, 34, 35,
+    36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51,
+    52, 53, 54, 55, 56, 57, 58, 60, 62, 64, 66, 68, 70, 72, 74, 76,
+    78, 80, 82, 84, 86, 88, 90, 92, 94, 96, 98, 100, 102, 104, 106, 108,
+    110, 112, 114, 116, 119, 122, 125, 128, 131, 134, 137, 140, 143, 146, 149, 152,
+    155, 158, 161, 164, 167, 170, 173, 177, 181, 185, 189, 193, 197, 201, 205, 209,
+    213, 217, 221, 225, 229, 234, 239, 245, 249, 254, 259, 264, 269, 274, 279, 284,
+];
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A 3x2 lossless image with alpha, and a flat 2x2 lossy one.
+    const LOSSLESS: &[u8] = b"RIFFB\x00\x00\x00WEBPVP8L6\x00\x00\x00\
+        \x2f\x02\x40\x00\x10\x2f\x20\x24\x20\x9c\x50\xfd\x7f\xe9\x06\x99\
+        \xb4\x4d\xfb\x4e\x44\xfd\xbb\xdc\x78\x10\x48\xda\xac\xf9\x58\xbc\
+        \xa7\x7a\x00\xb8\xaf\x5c\x2b\x75\x50\xc8\x36\x02\x1c\xba\x17\xe4\
+        \x58\x2e\xa2\xff\x31\xbc";
+    const LOSSY: &[u8] = b"RIFF>\x00\x00\x00WEBPVP8 2\x00\x00\x00\
+        \xd0\x01\x00\x9d\x01\x2a\x02\x00\x02\x00\x00\xc0\x12\x25\xa0\x02\
+        \x74\xba\x01\xf8\x00\x03\xb0\x00\xfe\xda\x26\xff\xee\xf3\x7e\xd3\
+        \xd7\xb4\xf5\xfd\x4c\xff\xf8\xca\x9f\x20\x3f\xe3\x2a\x7f\xc5\xcc\
+        \x00\x00";
+
+    #[test]
+    fn lossless() {
+        assert_eq!(read_size(LOSSLESS), Ok((3, 2)));
+        let (picture, icc_profile) = read_webp(LOSSLESS).unwrap();
+        assert!(icc_profile.is_none());
+        assert_eq!(picture.alpha, Some(vec![255, 128, 0, 255, 255, 200]));
+        assert_eq!(&picture.rgb[0..6], &[255, 0, 0, 0, 255, 0]);
+        assert_eq!(&picture.rgb[9..18], &[10, 20, 30, 40, 50, 60, 70, 80, 90]);
+    }
+
+    #[test]
+    fn lossy() {
+        assert_eq!(read_size(LOSSY), Ok((2, 2)));
+        let (picture, _) = read_webp(LOSSY).unwrap();
+        assert!(picture.alpha.is_none());
+        assert_eq!(picture.rgb, [201, 100, 50].repeat(4));
+    }
+
+    #[test]
+    fn alpha_filters() {
+        /* Raw alpha with the gradient filter */
+        let alph = [0x0c, 10, 5, 250, 3, 0xff, 7];
+        let alpha = decode_alpha(&alph, 3, 2).unwrap();
+        assert_eq!(alpha, vec![10, 15, 9, 13, 17, 18]);
+    }
+}
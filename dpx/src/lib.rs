@@ -196,15 +196,21 @@ pub mod dpx_dvi;
 pub mod dpx_dvicodes;
 pub mod dpx_dvipdfmx;
 pub mod dpx_epdf;
+pub mod dpx_epsimage;
 pub mod dpx_error;
 pub mod dpx_fontmap;
+pub mod dpx_gifimage;
+pub mod dpx_imagecache;
 pub mod dpx_jp2image;
+pub mod dpx_jpegcodec;
 pub mod dpx_jpegimage;
+pub mod dpx_linearize;
 pub mod dpx_mem;
 pub mod dpx_mfileio;
 pub mod dpx_mpost;
 pub mod dpx_numbers;
 pub mod dpx_otl_opt;
+pub mod dpx_pdfa;
 pub mod dpx_pdfcolor;
 pub mod dpx_pdfdev;
 pub mod dpx_pdfdoc;
@@ -216,30 +222,37 @@ pub mod dpx_pdfnames;
 pub mod dpx_pdfobj;
 pub mod dpx_pdfparse;
 pub mod dpx_pdfresource;
+pub mod dpx_pdfstruct;
 pub mod dpx_pdfximage;
 pub mod dpx_pkfont;
+pub mod dpx_pkgen;
 pub mod dpx_pngimage;
 pub mod dpx_pst;
 pub mod dpx_pst_obj;
 pub mod dpx_sfnt;
 pub mod dpx_subfont;
+pub mod dpx_svgimage;
 pub mod dpx_t1_char;
 pub mod dpx_t1_load;
 pub mod dpx_tfm;
+pub mod dpx_tiffimage;
 pub mod dpx_truetype;
 pub mod dpx_tt_aux;
 pub mod dpx_tt_cmap;
+pub mod dpx_tt_color;
 pub mod dpx_tt_glyf;
 pub mod dpx_tt_gsub;
 pub mod dpx_tt_post;
 pub mod dpx_tt_table;
+pub mod dpx_tt_var;
 pub mod dpx_type0;
 pub mod dpx_type1;
 pub mod dpx_type1c;
 pub mod dpx_unicode;
 pub mod dpx_vf;
+pub mod dpx_webpimage;
 mod shims;
 pub mod specials;
 
-pub use crate::dpx_dvipdfmx::dvipdfmx_main;
+pub use crate::dpx_dvipdfmx::{dvipdfmx_main, DvipdfmxOptions};
 
@@ -28,10 +28,10 @@ unused_mut
 use crate::DisplayExt;
 use std::ffi::{CStr, CString};
 
-use crate::dpx_pdfdraw::{pdf_dev_concat, pdf_dev_transform};
+use crate::dpx_pdfdraw::{pdf_dev_concat, pdf_dev_currentmatrix, pdf_dev_transform};
 use crate::dpx_pdfximage::{
     pdf_ximage_findresource, pdf_ximage_get_reference, pdf_ximage_get_resname,
-    pdf_ximage_scale_image,
+    pdf_ximage_record_placement, pdf_ximage_scale_image,
 };
 
 use super::{spc_begin_annot, spc_end_annot};
@@ -609,6 +609,7 @@ unsafe fn spc_html__img_empty(mut spe: *mut spc_env, attr: &pdf_obj) -> i32 {
         let M1 = pdf_ximage_scale_image(id, &mut r, &mut ti); /* op: */
         M = M1.post_transform(&M);
         pdf_dev_concat(&mut M);
+        pdf_ximage_record_placement(id, &pdf_dev_currentmatrix());
         r.clip();
         let res_name = pdf_ximage_get_resname(id);
         pdf_doc_add_page_content(b" /");
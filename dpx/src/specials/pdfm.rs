@@ -73,6 +73,10 @@ use crate::dpx_pdfobj::{
 use crate::dpx_pdfparse::{
     ParseIdent, ParsePdfObj, SkipWhite,
 };
+use crate::dpx_pdfstruct::{
+    pdf_struct_add_role_map, pdf_struct_begin_artifact, pdf_struct_begin_element,
+    pdf_struct_end_artifact, pdf_struct_end_element, pdf_struct_set_tagged,
+};
 use crate::dpx_pdfximage::{pdf_ximage_findresource, pdf_ximage_get_reference};
 use crate::dpx_unicode::{
     UC_UTF16BE_encode_char, UC_UTF16BE_is_valid_string, UC_UTF8_decode_char,
@@ -1307,6 +1311,110 @@ unsafe fn spc_handler_pdfm_econtent(mut _spe: *mut spc_env, mut _args: *mut spc_
     pdf_dev_reset_color(0i32);
     0i32
 }
+/* Tagged PDF */
+unsafe fn spc_handler_pdfm_bstruct(mut spe: *mut spc_env, mut args: *mut spc_arg) -> i32 {
+    let mut sd: *mut spc_pdf_ = &mut _PDF_STAT;
+    (*args).cur.skip_white();
+    let role = if let Some(role) = (*args).cur.parse_pdf_name() {
+        role
+    } else {
+        spc_warn!(spe, "Structure type expected, e.g. \"pdf:bstruct /P\".");
+        return -1i32;
+    };
+    (*args).cur.skip_white();
+    let mut attrs = None;
+    if !(*args).cur.is_empty() {
+        match (*args).cur.parse_pdf_dict_with_tounicode(&mut (*sd).cd) {
+            Some(dict) if (*dict).is_dict() => attrs = Some(dict),
+            dict => {
+                spc_warn!(spe, "Invalid attributes for structure element: not a dictionary.");
+                if let Some(dict) = dict {
+                    pdf_release_obj(dict);
+                }
+                pdf_release_obj(role);
+                return -1i32;
+            }
+        }
+    }
+    let error = match pdf_struct_begin_element(&*role, attrs.map(|a| &*a)) {
+        Ok(()) => 0i32,
+        Err(msg) => {
+            spc_warn!(spe, "{}", msg);
+            -1i32
+        }
+    };
+    pdf_release_obj(role);
+    if let Some(attrs) = attrs {
+        pdf_release_obj(attrs);
+    }
+    error
+}
+unsafe fn spc_handler_pdfm_estruct(mut spe: *mut spc_env, mut args: *mut spc_arg) -> i32 {
+    (*args).cur.skip_white();
+    let role = if (*args).cur.starts_with(b"/") {
+        (*args).cur.parse_pdf_name()
+    } else {
+        None
+    };
+    let result = pdf_struct_end_element(role.map(|r| pdf_name_value(&*r).to_bytes()));
+    if let Some(role) = role {
+        pdf_release_obj(role);
+    }
+    match result {
+        Ok(()) => 0i32,
+        Err(msg) => {
+            spc_warn!(spe, "{}", msg);
+            -1i32
+        }
+    }
+}
+unsafe fn spc_handler_pdfm_bartifact(mut spe: *mut spc_env, mut args: *mut spc_arg) -> i32 {
+    (*args).cur.skip_white();
+    if (*args).cur.is_empty() {
+        pdf_struct_begin_artifact(None);
+        return 0i32;
+    }
+    /* The property list goes into the content stream as written. */
+    let start = (*args).cur;
+    match (*args).cur.parse_pdf_dict(0 as *mut pdf_file) {
+        Some(dict) => {
+            pdf_release_obj(dict);
+            pdf_struct_begin_artifact(Some(&start[..start.len() - (*args).cur.len()]));
+            0i32
+        }
+        None => {
+            spc_warn!(spe, "Invalid artifact properties: not a dictionary.");
+            -1i32
+        }
+    }
+}
+unsafe fn spc_handler_pdfm_eartifact(mut spe: *mut spc_env, mut _args: *mut spc_arg) -> i32 {
+    match pdf_struct_end_artifact() {
+        Ok(()) => 0i32,
+        Err(msg) => {
+            spc_warn!(spe, "{}", msg);
+            -1i32
+        }
+    }
+}
+unsafe fn spc_handler_pdfm_rolemap(mut spe: *mut spc_env, mut args: *mut spc_arg) -> i32 {
+    (*args).cur.skip_white();
+    match (*args).cur.parse_pdf_dict(0 as *mut pdf_file) {
+        Some(dict) => {
+            pdf_struct_add_role_map(&*dict);
+            pdf_release_obj(dict);
+            0i32
+        }
+        None => {
+            spc_warn!(spe, "Dictionary object expected but not found.");
+            -1i32
+        }
+    }
+}
+unsafe fn spc_handler_pdfm_tagged(mut _spe: *mut spc_env, mut _args: *mut spc_arg) -> i32 {
+    pdf_struct_set_tagged();
+    0i32
+}
 unsafe fn spc_handler_pdfm_code(mut _spe: *mut spc_env, mut args: *mut spc_arg) -> i32 {
     (*args).cur.skip_white();
     if !(*args).cur.is_empty() {
@@ -1732,7 +1840,7 @@ unsafe fn spc_handler_pdfm_tounicode(mut spe: *mut spc_env, mut args: *mut spc_a
         -1
     }
 }
-const PDFM_HANDLERS: [SpcHandler; 80] = [
+const PDFM_HANDLERS: [SpcHandler; 90] = [
     SpcHandler {
         key: b"annotation",
         exec: Some(spc_handler_pdfm_annot),
@@ -2045,6 +2153,46 @@ const PDFM_HANDLERS: [SpcHandler; 80] = [
         key: b"code",
         exec: Some(spc_handler_pdfm_code),
     },
+    SpcHandler {
+        key: b"bstruct",
+        exec: Some(spc_handler_pdfm_bstruct),
+    },
+    SpcHandler {
+        key: b"beginstruct",
+        exec: Some(spc_handler_pdfm_bstruct),
+    },
+    SpcHandler {
+        key: b"estruct",
+        exec: Some(spc_handler_pdfm_estruct),
+    },
+    SpcHandler {
+        key: b"endstruct",
+        exec: Some(spc_handler_pdfm_estruct),
+    },
+    SpcHandler {
+        key: b"bartifact",
+        exec: Some(spc_handler_pdfm_bartifact),
+    },
+    SpcHandler {
+        key: b"beginartifact",
+        exec: Some(spc_handler_pdfm_bartifact),
+    },
+    SpcHandler {
+        key: b"eartifact",
+        exec: Some(spc_handler_pdfm_eartifact),
+    },
+    SpcHandler {
+        key: b"endartifact",
+        exec: Some(spc_handler_pdfm_eartifact),
+    },
+    SpcHandler {
+        key: b"rolemap",
+        exec: Some(spc_handler_pdfm_rolemap),
+    },
+    SpcHandler {
+        key: b"tagged",
+        exec: Some(spc_handler_pdfm_tagged),
+    },
     SpcHandler {
         key: b"minorversion",
         exec: Some(spc_handler_pdfm_do_nothing),
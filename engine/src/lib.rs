@@ -48,8 +48,7 @@ pub unsafe extern "C" fn dvipdfmx_simple_main(
     mut api: *const tt_bridge_api_t,
     mut dviname: *const i8,
     mut pdfname: *const i8,
-    mut compress: bool,
-    mut deterministic_tags: bool,
+    options: &DvipdfmxOptions,
 ) -> i32 {
     bridge::tt_with_bridge(api, || {
         dvipdfmx_main(
@@ -58,8 +57,7 @@ pub unsafe extern "C" fn dvipdfmx_simple_main(
             0 as *const i8,
             0i32,
             false,
-            compress,
-            deterministic_tags,
+            options,
             false,
             0_u32,
         ) as i32
@@ -174,7 +172,16 @@ mod stub_icu;
 mod stub_stdio;
 mod stub_teckit;
 
-pub use xetex_engine_interface::tt_xetex_set_int_variable;
+pub use xetex_engine_interface::{
+    tt_xetex_add_font_file, tt_xetex_add_synctex_source_name, tt_xetex_clear_font_files,
+    tt_xetex_clear_synctex_source_names, tt_xetex_set_int_variable, tt_xetex_take_font_resolutions,
+    tt_xetex_take_missing_chars, FontResolution, MissingChar,
+};
+
+pub use dpx::dpx_dpxconf::paperinfo;
+pub use dpx::DvipdfmxOptions;
+pub use dpx::dpx_fontmap::{pdf_fontmap_add_request, pdf_fontmap_clear_requests};
+pub use dpx::dpx_pdffont::{pdf_font_take_report, FontReportEntry, FontReportType};
 
 #[inline]
 pub(crate) unsafe extern "C" fn strstartswith(s: *const i8, prefix: *const i8) -> *const i8 {
@@ -227,6 +234,13 @@ pub(crate) mod freetype_sys_patch {
             length: *mut FT_ULong,
         ) -> FT_Error;
 
+        #[no_mangle]
+        pub fn FT_Set_Var_Design_Coordinates(
+            face: FT_Face,
+            num_coords: FT_UInt,
+            coords: *mut FT_Fixed,
+        ) -> FT_Error;
+
         #[no_mangle]
         pub fn FT_Get_Sfnt_Name_Count(face: FT_Face) -> FT_UInt;
 
@@ -9,9 +9,14 @@
 )]
 
 use crate::streq_ptr;
+use crate::xetex_font_manager::{
+    XeTeXFontMgr_sHostFontsAllowed, XeTeXFontMgr_sIoFontFiles, XeTeXFontMgr_sResolutions,
+};
 use crate::xetex_ini::{
-    halt_on_error_p, in_initex_mode, semantic_pagination_enabled, synctex_enabled,
+    halt_on_error_p, in_initex_mode, missing_chars, semantic_pagination_enabled, synctex_enabled,
 };
+use crate::xetex_synctex::{synctex_add_source_name, synctex_clear_source_names};
+use std::ffi::CStr;
 
 /* tectonic/core-strutils.h: miscellaneous C string utilities
    Copyright 2016-2018 the Tectonic Project
@@ -39,6 +44,11 @@ pub unsafe extern "C" fn tt_xetex_set_int_variable(mut var_name: *mut i8, mut va
         b"semantic_pagination_enabled\x00" as *const u8 as *const i8,
     ) {
         semantic_pagination_enabled = value != 0i32
+    } else if streq_ptr(
+        var_name,
+        b"host_fonts_allowed\x00" as *const u8 as *const i8,
+    ) {
+        XeTeXFontMgr_sHostFontsAllowed = value != 0i32
     } else {
         return 1i32;
     } /* Uh oh: unrecognized variable */
@@ -53,3 +63,70 @@ pub unsafe extern "C" fn tt_xetex_set_string_variable(
     /* Currently unused; see Git history for how we used to set output_comment */
     1
 }
+/* Tectonic: register a name to record in the SyncTeX output in place of the
+ * name TeX used to open an input. */
+#[no_mangle]
+pub unsafe extern "C" fn tt_xetex_add_synctex_source_name(
+    mut tex_name: *const i8,
+    mut real_name: *const i8,
+) {
+    synctex_add_source_name(
+        CStr::from_ptr(tex_name).to_bytes(),
+        CStr::from_ptr(real_name).to_bytes(),
+    );
+}
+#[no_mangle]
+pub unsafe extern "C" fn tt_xetex_clear_synctex_source_names() {
+    synctex_clear_source_names();
+}
+/* Tectonic: register a font file that the engine can open through the I/O
+ * stack, so that fonts can be found in it by name. `in_project` says whether
+ * it is one of the project's own files rather than a bundle file. */
+#[no_mangle]
+pub unsafe extern "C" fn tt_xetex_add_font_file(mut name: *const i8, mut in_project: i32) {
+    XeTeXFontMgr_sIoFontFiles.push((CStr::from_ptr(name).to_owned(), in_project != 0));
+}
+#[no_mangle]
+pub unsafe extern "C" fn tt_xetex_clear_font_files() {
+    XeTeXFontMgr_sIoFontFiles.clear();
+}
+/// How the engine found a font that was requested by name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontResolution {
+    /// The name as given in the document.
+    pub name: String,
+    /// Whether the font was found through the I/O stack, as opposed to among
+    /// the fonts installed on the host.
+    pub from_io: bool,
+    /// The file that provided the font.
+    pub file: String,
+}
+/* Tectonic: hand over the fonts resolved since the last call. */
+pub unsafe fn tt_xetex_take_font_resolutions() -> Vec<FontResolution> {
+    XeTeXFontMgr_sResolutions
+        .drain(..)
+        .map(|(name, from_io, file)| FontResolution {
+            name: name.to_string_lossy().into_owned(),
+            from_io,
+            file: file.to_string_lossy().into_owned(),
+        })
+        .collect()
+}
+/// A character that the engine could not find in a font.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MissingChar {
+    /// The font, as TeX names it.
+    pub font: String,
+    /// The Unicode scalar value (or, for TFM fonts, the character code).
+    pub code: u32,
+}
+/* Tectonic: hand over the missing characters reported since the last call. */
+pub unsafe fn tt_xetex_take_missing_chars() -> Vec<MissingChar> {
+    missing_chars
+        .drain(..)
+        .map(|(font, code)| MissingChar {
+            font,
+            code: code as u32,
+        })
+        .collect()
+}
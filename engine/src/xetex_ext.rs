@@ -665,6 +665,42 @@ unsafe extern "C" fn readFeatureNumber(
     }
     true
 }
+/* s...e is an "axis=value" setting of a variable font, such as "wght=650",
+ * optionally prefixed with "+" like a feature. */
+unsafe extern "C" fn readVariation(
+    mut font: XeTeXFont,
+    mut s: *const i8,
+    mut e: *const i8,
+    mut variation: *mut hb_variation_t,
+) -> bool {
+    if *s as i32 == '+' as i32 {
+        s = s.offset(1)
+    }
+    let mut cp: *const i8 = s;
+    while cp < e && *cp as i32 != '=' as i32 {
+        cp = cp.offset(1)
+    }
+    let len = cp.wrapping_offset_from(s) as i32;
+    if cp == e || len < 1i32 || len > 4i32 {
+        return false;
+    }
+    let tag = hb_tag_from_string(s, len);
+    cp = cp.offset(1);
+    let start = cp;
+    let value = read_double(&mut cp);
+    if cp == start || !(*cp.offset(-1) as u8).is_ascii_digit() {
+        return false;
+    }
+    while *cp as i32 == ' ' as i32 || *cp as i32 == '\t' as i32 {
+        cp = cp.offset(1)
+    }
+    if cp != e || !findVariationAxis(font, tag) {
+        return false;
+    }
+    (*variation).tag = tag;
+    (*variation).value = value as f32;
+    true
+}
 unsafe extern "C" fn loadOTfont(
     mut fontRef: PlatformFontRef,
     mut font: XeTeXFont,
@@ -679,8 +715,10 @@ unsafe extern "C" fn loadOTfont(
         | 0_u32 & 0xff_u32;
     let mut language: *mut i8 = 0 as *mut i8;
     let mut features: *mut hb_feature_t = 0 as *mut hb_feature_t;
+    let mut variations: *mut hb_variation_t = 0 as *mut hb_variation_t;
     let mut shapers: *mut *mut i8 = 0 as *mut *mut i8;
     let mut nFeatures: i32 = 0i32;
+    let mut nVariations: i32 = 0i32;
     let mut nShapers: i32 = 0i32;
     let mut cp2: *mut i8 = 0 as *mut i8;
     let mut cp3: *const i8 = 0 as *const i8;
@@ -719,6 +757,8 @@ unsafe extern "C" fn loadOTfont(
             language,
             features,
             nFeatures,
+            0 as *mut hb_variation_t,
+            0i32,
             tmpShapers.as_mut_ptr(),
             rgbValue,
             extend,
@@ -750,6 +790,18 @@ unsafe extern "C" fn loadOTfont(
             {
                 cp2 = cp2.offset(1)
             }
+            let mut variation = hb_variation_t { tag: 0, value: 0. };
+            if readVariation(font, cp1, cp2, &mut variation) {
+                variations = xrealloc(
+                    variations as *mut libc::c_void,
+                    ((nVariations + 1i32) as u64)
+                        .wrapping_mul(::std::mem::size_of::<hb_variation_t>() as u64),
+                ) as *mut hb_variation_t;
+                *variations.offset(nVariations as isize) = variation;
+                nVariations += 1;
+                cp1 = cp2;
+                continue;
+            }
             cp3 = strstartswith(cp1, b"script\x00" as *const u8 as *const i8);
             if !cp3.is_null() {
                 if *cp3 as i32 != '=' as i32 {
@@ -957,12 +1009,24 @@ unsafe extern "C" fn loadOTfont(
         setFontLayoutDir(font, 1i32);
     }
     engine = createLayoutEngine(
-        fontRef, font, script, language, features, nFeatures, shapers, rgbValue, extend, slant,
+        fontRef,
+        font,
+        script,
+        language,
+        features,
+        nFeatures,
+        variations,
+        nVariations,
+        shapers,
+        rgbValue,
+        extend,
+        slant,
         embolden,
     );
     if engine.is_null() {
         // only free these if creation failed, otherwise the engine now owns them
         free(features as *mut libc::c_void);
+        free(variations as *mut libc::c_void);
         free(shapers as *mut libc::c_void);
     } else {
         native_font_type_flag = 0xfffeu32 as i32
@@ -1506,6 +1570,8 @@ pub unsafe extern "C" fn make_font_def(mut f: i32) -> i32 {
     let mut extend: f32 = 1.0f64 as f32;
     let mut slant: f32 = 0.0f64 as f32;
     let mut embolden: f32 = 0.0f64 as f32;
+    let mut variations: *const hb_variation_t = 0 as *const hb_variation_t;
+    let mut nVariations: i32 = 0i32;
     match *font_area.offset(f as isize) as u32 {
         #[cfg(target_os = "macos")]
         0xffffu32 => {
@@ -1573,6 +1639,7 @@ pub unsafe extern "C" fn make_font_def(mut f: i32) -> i32 {
             extend = getExtendFactor(engine);
             slant = getSlantFactor(engine);
             embolden = getEmboldenFactor(engine);
+            nVariations = getFontVariations(engine, &mut variations);
             size = D2Fix(getPointSize(engine) as f64)
         }
         _ => {
@@ -1586,12 +1653,18 @@ pub unsafe extern "C" fn make_font_def(mut f: i32) -> i32 {
     //  l[1] n[l]
     //  if flags & COLORED:
     //      c[4]
+    //  if flags & VARIATIONS:
+    //      nv[2] a[4nv] v[4nv]
      */
     fontDefLength = 4i32 + 2i32 + 1i32 + filenameLen as i32 + 4i32; /* face index */
     if *font_flags.offset(f as isize) as i32 & 0x1i32 != 0i32 {
         fontDefLength += 4i32; /* 32-bit RGBA value */
         flags = (flags as i32 | 0x200i32) as u16
     }
+    if nVariations > 0i32 {
+        fontDefLength += 2i32 + 8i32 * nVariations;
+        flags = (flags as i32 | 0x800i32) as u16
+    }
     if extend as f64 != 1.0f64 {
         fontDefLength += 4i32;
         flags = (flags as i32 | 0x1000i32) as u16
@@ -1628,6 +1701,18 @@ pub unsafe extern "C" fn make_font_def(mut f: i32) -> i32 {
         *(cp as *mut u32) = SWAP32(rgba);
         cp = cp.offset(4)
     }
+    if flags as i32 & 0x800i32 != 0 {
+        *(cp as *mut u16) = SWAP16(nVariations as u16);
+        cp = cp.offset(2);
+        for i in 0..nVariations as isize {
+            *(cp as *mut u32) = SWAP32((*variations.offset(i)).tag);
+            cp = cp.offset(4)
+        }
+        for i in 0..nVariations as isize {
+            *(cp as *mut u32) = SWAP32(D2Fix((*variations.offset(i)).value as f64) as u32);
+            cp = cp.offset(4)
+        }
+    }
     if flags as i32 & 0x1000i32 != 0 {
         let mut f_0: Fixed = D2Fix(extend as f64);
         *(cp as *mut u32) = SWAP32(f_0 as u32);
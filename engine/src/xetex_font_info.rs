@@ -30,7 +30,10 @@ use freetype::freetype_sys::{
     FT_Get_Name_Index,
     FT_Done_Face, 
 };
-use crate::freetype_sys_patch::{FT_Face_GetCharVariantIndex, FT_Get_Advance, FT_Load_Sfnt_Table};
+use crate::freetype_sys_patch::{
+    FT_Face_GetCharVariantIndex, FT_Get_Advance, FT_Load_Sfnt_Table, FT_Set_Var_Design_Coordinates,
+};
+use crate::xetex_layout_engine::hb_variation_t;
 
 use crate::{
     ttstub_input_close, ttstub_input_get_size, ttstub_input_read, ttstub_input_getc, ttstub_input_open, 
@@ -91,6 +94,12 @@ extern "C" {
         destroy: hb_destroy_func_t,
     );
     #[no_mangle]
+    fn hb_font_set_variations(
+        font: *mut hb_font_t,
+        variations: *const hb_variation_t,
+        variations_length: libc::c_uint,
+    );
+    #[no_mangle]
     fn Fix2D(f: Fixed) -> libc::c_double;
 }
 pub type size_t = usize;
@@ -742,6 +751,83 @@ pub unsafe extern "C" fn XeTeXFontInst_getFontTableFT(
 ) -> *mut libc::c_void {
     return FT_Get_Sfnt_Table((*self_0).m_ftFace, tag);
 }
+/// Reads the axes of the font's `fvar` table, as (tag, minimum, default,
+/// maximum) in the units of the axis.
+unsafe fn XeTeXFontInst_getVariationAxes(
+    self_0: *const XeTeXFontInst,
+) -> Vec<(OTTag, f64, f64, f64)> {
+    const FVAR: FT_ULong = 0x66766172;
+    let mut axes = Vec::new();
+    let mut length: FT_ULong = 0;
+    if FT_Load_Sfnt_Table((*self_0).m_ftFace, FVAR, 0, 0 as *mut FT_Byte, &mut length) != 0 {
+        return axes;
+    }
+    let mut table = vec![0u8; length as usize];
+    if FT_Load_Sfnt_Table(
+        (*self_0).m_ftFace,
+        FVAR,
+        0,
+        table.as_mut_ptr(),
+        &mut length,
+    ) != 0
+        || table.len() < 16
+    {
+        return axes;
+    }
+    let u16_at = |p: usize| (table[p] as usize) << 8 | table[p + 1] as usize;
+    let u32_at = |p: usize| u32::from_be_bytes([table[p], table[p + 1], table[p + 2], table[p + 3]]);
+    let fixed_at = |p: usize| u32_at(p) as i32 as f64 / 65536.0;
+    let offset = u16_at(4);
+    let size = u16_at(10);
+    for i in 0..u16_at(8) {
+        let p = offset + i * size;
+        if size < 16 || p + 16 > table.len() {
+            break;
+        }
+        axes.push((u32_at(p), fixed_at(p + 4), fixed_at(p + 8), fixed_at(p + 12)));
+    }
+    axes
+}
+#[no_mangle]
+pub unsafe extern "C" fn XeTeXFontInst_hasVariationAxis(
+    self_0: *const XeTeXFontInst,
+    tag: OTTag,
+) -> bool {
+    XeTeXFontInst_getVariationAxes(self_0)
+        .iter()
+        .any(|axis| axis.0 == tag)
+}
+/// Selects an instance of a variable font, for both the FreeType metrics
+/// and the HarfBuzz shaping. Axes that aren't given keep their defaults.
+#[no_mangle]
+pub unsafe extern "C" fn XeTeXFontInst_setVariations(
+    self_0: *mut XeTeXFontInst,
+    variations: *const hb_variation_t,
+    count: libc::c_uint,
+) {
+    let axes = XeTeXFontInst_getVariationAxes(self_0);
+    if axes.is_empty() || count == 0 {
+        return;
+    }
+    let settings = std::slice::from_raw_parts(variations, count as usize);
+    let mut coords: Vec<FT_Fixed> = axes
+        .iter()
+        .map(|&(tag, min, default, max)| {
+            let value = settings
+                .iter()
+                .rev()
+                .find(|v| v.tag == tag)
+                .map_or(default, |v| v.value as f64);
+            (value.max(min).min(max) * 65536.0).round() as FT_Fixed
+        })
+        .collect();
+    FT_Set_Var_Design_Coordinates(
+        (*self_0).m_ftFace,
+        coords.len() as FT_UInt,
+        coords.as_mut_ptr(),
+    );
+    hb_font_set_variations((*self_0).m_hbFont, variations, count);
+}
 #[no_mangle]
 pub unsafe extern "C" fn XeTeXFontInst_getGlyphBounds(
     mut self_0: *mut XeTeXFontInst,
@@ -400,6 +400,17 @@ pub static mut XeTeXFontMgr_sFontManager: *mut XeTeXFontMgr =
     0 as *const XeTeXFontMgr as *mut XeTeXFontMgr;
 #[no_mangle]
 pub static mut XeTeXFontMgr_sReqEngine: libc::c_char = 0i32 as libc::c_char;
+/* Tectonic: whether fonts requested by name may be looked up among the host's
+ * fonts, and the font files that we can reach through the I/O stack (with
+ * whether each comes from the project rather than the bundle). On macOS only
+ * the first setting has an effect for now. These are set up by the driver
+ * before each run. */
+pub static mut XeTeXFontMgr_sHostFontsAllowed: bool = true;
+pub static mut XeTeXFontMgr_sIoFontFiles: Vec<(CString, bool)> = Vec::new();
+/* Tectonic: for each font requested by name, whether it was found through the
+ * I/O stack and the file that satisfied it, so that the driver can report
+ * where fonts came from. */
+pub static mut XeTeXFontMgr_sResolutions: Vec<(CString, bool, CString)> = Vec::new();
 /* use our own fmax function because it seems to be missing on certain platforms
 (solaris2.9, at least) */
 #[inline]
@@ -504,7 +515,7 @@ pub unsafe extern "C" fn XeTeXFontMgr_findFont(
     let mut font: *mut XeTeXFontMgrFont = 0 as *mut XeTeXFontMgrFont;
     let mut dsize: libc::c_int = 100i32;
     loaded_font_design_size = 655360i64 as Fixed;
-    for pass in 0..2i32 {
+    for pass in 0..3i32 {
         // try full name as given
         if let Some(name_font_ptr) = (*(*self_0).m_nameToFont).get(&nameStr).cloned() {
             font = name_font_ptr.as_ptr();
@@ -592,9 +603,15 @@ pub unsafe extern "C" fn XeTeXFontMgr_findFont(
             }
         }
         if pass == 0i32 {
+            // Tectonic: look through the font files that we can reach through
+            // the I/O stack first, so that documents get the same fonts on
+            // every machine whenever those have them
+            #[cfg(not(target_os = "macos"))]
+            imp::XeTeXFontMgr_FC_searchForIoFonts(self_0, nameStr.as_ptr());
+        } else if pass == 1i32 && XeTeXFontMgr_sHostFontsAllowed {
             // didn't find it in our caches, so do a platform search (may be relatively expensive);
             // this will update the caches with any fonts that seem to match the name given,
-            // so that the second pass might find it
+            // so that the next pass might find it
             XeTeXFontMgr_searchForHostPlatformFonts(self_0, nameStr.as_ptr());
         }
     }
@@ -915,8 +932,30 @@ pub unsafe extern "C" fn XeTeXFontMgr_findFont(
         free(font_desc as *mut libc::c_void);
         end_diagnostic(0i32);
     }
+    XeTeXFontMgr_recordResolution(self_0, name, (*font).fontRef);
     return (*font).fontRef;
 }
+unsafe fn XeTeXFontMgr_recordResolution(
+    mut self_0: *const XeTeXFontMgr,
+    mut name: *const libc::c_char,
+    mut fontRef: PlatformFontRef,
+) {
+    use std::ffi::CStr;
+    #[cfg(not(target_os = "macos"))]
+    let fromIo = imp::XeTeXFontMgr_FC_isIoFont(fontRef);
+    #[cfg(target_os = "macos")]
+    let fromIo = false;
+    let mut font_desc: *mut libc::c_char = XeTeXFontMgr_getPlatformFontDesc(self_0, fontRef);
+    let resolution = (
+        CStr::from_ptr(name).to_owned(),
+        fromIo,
+        CStr::from_ptr(font_desc).to_owned(),
+    );
+    free(font_desc as *mut libc::c_void);
+    if !XeTeXFontMgr_sResolutions.contains(&resolution) {
+        XeTeXFontMgr_sResolutions.push(resolution);
+    }
+}
 #[no_mangle]
 pub unsafe extern "C" fn XeTeXFontMgr_getFullName(
     mut self_0: *const XeTeXFontMgr,
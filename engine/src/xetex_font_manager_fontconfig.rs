@@ -15,8 +15,12 @@ use std::ptr::NonNull;
 
 use freetype::freetype_sys::{FT_Byte, FT_UInt, FT_Long, FT_ULong, FT_Int32, FT_Pointer, FT_Error, FT_Fixed, 
     FT_Library, FT_Face, FT_Glyph, FT_String, FT_Parameter};
-use freetype::freetype_sys::{FT_New_Face, FT_Done_Face, FT_Get_Postscript_Name, FT_Init_FreeType};
+use freetype::freetype_sys::{FT_New_Face, FT_New_Memory_Face, FT_Done_Face, FT_Get_Postscript_Name, FT_Init_FreeType};
 use crate::freetype_sys_patch::{FT_Get_Sfnt_Name, FT_Get_Sfnt_Name_Count};
+use std::ffi::CStr;
+
+use crate::{ttstub_input_close, ttstub_input_get_size, ttstub_input_open, ttstub_input_read};
+use bridge::TTInputFormat;
 
 extern "C" {
     pub type _FcPattern;
@@ -50,6 +54,24 @@ extern "C" {
     #[no_mangle]
     fn FcPatternDestroy(p: *mut FcPattern);
     #[no_mangle]
+    fn FcPatternCreate() -> *mut FcPattern;
+    #[no_mangle]
+    fn FcPatternAddInteger(
+        p: *mut FcPattern,
+        object: *const libc::c_char,
+        i: libc::c_int,
+    ) -> FcBool;
+    #[no_mangle]
+    fn FcPatternAddString(
+        p: *mut FcPattern,
+        object: *const libc::c_char,
+        s: *const FcChar8,
+    ) -> FcBool;
+    #[no_mangle]
+    fn FcFontSetCreate() -> *mut FcFontSet;
+    #[no_mangle]
+    fn FcFontSetAdd(s: *mut FcFontSet, font: *mut FcPattern) -> FcBool;
+    #[no_mangle]
     fn FcPatternGetInteger(
         p: *const FcPattern,
         object: *const libc::c_char,
@@ -162,7 +184,7 @@ authorization from the copyright holders.
 \****************************************************************************/
 use super::{
     XeTeXFontMgr, XeTeXFontMgrFamily, XeTeXFontMgrFont, XeTeXFontMgrNameCollection,
-    XeTeXFontMgrOpSizeRec,
+    XeTeXFontMgrOpSizeRec, XeTeXFontMgr_sHostFontsAllowed, XeTeXFontMgr_sIoFontFiles,
 };
 /* ***************************************************************************\
  Part of the XeTeX typesetting system
@@ -296,7 +318,6 @@ pub unsafe extern "C" fn XeTeXFontMgr_FC_readNames(
     mut self_0: *mut XeTeXFontMgr,
     mut pat: *mut FcPattern,
 ) -> *mut XeTeXFontMgrNameCollection {
-    use crate::freetype_sys_patch::FT_SfntName;
     let mut names: *mut XeTeXFontMgrNameCollection = XeTeXFontMgrNameCollection_create();
     let mut pathname: *mut libc::c_char = 0 as *mut libc::c_char;
     if FcPatternGetString(
@@ -324,9 +345,23 @@ pub unsafe extern "C" fn XeTeXFontMgr_FC_readNames(
     if FT_New_Face(gFreeTypeLibrary, pathname, index as FT_Long, &mut face) != 0i32 {
         return names;
     }
+    XeTeXFontMgr_FC_readFaceNames(self_0, pat, face, names);
+    FT_Done_Face(face);
+    return names;
+}
+/* Tectonic: the name-reading logic is split out so that it can also be used
+ * for faces that we load from memory rather than from a host path. */
+unsafe extern "C" fn XeTeXFontMgr_FC_readFaceNames(
+    mut self_0: *mut XeTeXFontMgr,
+    mut pat: *mut FcPattern,
+    mut face: FT_Face,
+    mut names: *mut XeTeXFontMgrNameCollection,
+) {
+    use crate::freetype_sys_patch::FT_SfntName;
+    let mut index: libc::c_int = 0;
     let mut name: *const libc::c_char = FT_Get_Postscript_Name(face);
     if name.is_null() {
-        return names;
+        return;
     }
     CppStdString_assign_from_const_char_ptr((*names).m_psName, name);
     /* this string is *not* null-terminated! */
@@ -465,8 +500,6 @@ pub unsafe extern "C" fn XeTeXFontMgr_FC_readNames(
             CppStdString_delete(fullName);
         }
     }
-    FT_Done_Face(face);
-    return names;
 }
 #[no_mangle]
 pub unsafe extern "C" fn XeTeXFontMgr_FC_getOpSizeRecAndStyleFlags(
@@ -687,10 +720,133 @@ pub unsafe extern "C" fn XeTeXFontMgr_FC_searchForHostPlatformFonts(
     }
     CppStdString_delete(famName);
 }
+/* Tectonic: fonts found through the I/O stack -- the project files and the
+ * bundle -- rather than through the host's Fontconfig setup. For each face
+ * of such a file we build a pattern holding just "file" and "index", which is
+ * all that createFont() needs to load it through the I/O stack again. */
+static mut ioFonts: *mut FcFontSet = 0 as *mut FcFontSet;
+static mut ioFontFilesIndexed: Vec<bool> = Vec::new();
+/* Lowercase the ASCII letters and digits of a name, dropping everything else,
+ * so that "TeX Gyre Termes" can be compared with "texgyretermes-regular.otf". */
+fn normalize_font_name(name: &[u8]) -> Vec<u8> {
+    name.iter()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+/* Reading every font in the bundle would be far too slow (and may mean
+ * downloading all of them), so bundle files are only opened when their names
+ * look like the name being searched for. */
+fn io_font_file_may_match(name: &[u8], file: &[u8]) -> bool {
+    let base = match file.iter().rposition(|&c| c == b'/') {
+        Some(i) => &file[i + 1..],
+        None => file,
+    };
+    let stem = match base.iter().rposition(|&c| c == b'.') {
+        Some(i) => &base[..i],
+        None => base,
+    };
+    let stem = normalize_font_name(stem);
+    if stem.len() < 3 {
+        return false;
+    }
+    let family = match name.iter().position(|&c| c == b'-') {
+        Some(i) => &name[..i],
+        None => name,
+    };
+    [name, family].iter().any(|query| {
+        let query = normalize_font_name(query);
+        query.len() >= 3 && (stem.starts_with(&query) || query.starts_with(&stem))
+    })
+}
+unsafe fn XeTeXFontMgr_FC_indexIoFontFile(mut self_0: *mut XeTeXFontMgr, mut file: &CStr) {
+    let mut handle = match ttstub_input_open(file.as_ptr(), TTInputFormat::OPENTYPE, 0)
+        .or_else(|| ttstub_input_open(file.as_ptr(), TTInputFormat::TRUETYPE, 0))
+    {
+        Some(h) => h,
+        None => return,
+    };
+    let mut sz = ttstub_input_get_size(&mut handle);
+    let mut data: Vec<u8> = vec![0; sz as usize];
+    let mut r = ttstub_input_read(
+        handle.0.as_ptr(),
+        data.as_mut_ptr() as *mut libc::c_char,
+        sz,
+    );
+    ttstub_input_close(handle);
+    if r < 0 || r as usize != sz as usize {
+        return;
+    }
+    let mut faceIndex: FT_Long = 0;
+    let mut numFaces: FT_Long = 1;
+    while faceIndex < numFaces {
+        let mut face: FT_Face = 0 as FT_Face;
+        if FT_New_Memory_Face(
+            gFreeTypeLibrary,
+            data.as_ptr(),
+            sz as FT_Long,
+            faceIndex,
+            &mut face,
+        ) != 0i32
+        {
+            break;
+        }
+        numFaces = (*face).num_faces;
+        // only sfnt containers have the name table that we index by
+        if (*face).face_flags & 1 << 3i32 != 0 {
+            let mut pat: *mut FcPattern = FcPatternCreate();
+            FcPatternAddString(
+                pat,
+                b"file\x00" as *const u8 as *const libc::c_char,
+                file.as_ptr() as *const FcChar8,
+            );
+            FcPatternAddInteger(
+                pat,
+                b"index\x00" as *const u8 as *const libc::c_char,
+                faceIndex as libc::c_int,
+            );
+            FcFontSetAdd(ioFonts, pat);
+            let mut names: *mut XeTeXFontMgrNameCollection = XeTeXFontMgrNameCollection_create();
+            XeTeXFontMgr_FC_readFaceNames(self_0, pat, face, names);
+            XeTeXFontMgr_addToMaps(self_0, pat, names);
+            XeTeXFontMgrNameCollection_delete(names);
+        }
+        FT_Done_Face(face);
+        faceIndex += 1
+    }
+}
+/* Tectonic: add the fonts that may be called `name` among the files reachable
+ * through the I/O stack to our maps. Project files are few, so they are all
+ * indexed on the first search; bundle files are picked by their names. */
+#[no_mangle]
+pub unsafe extern "C" fn XeTeXFontMgr_FC_searchForIoFonts(
+    mut self_0: *mut XeTeXFontMgr,
+    mut name: *const libc::c_char,
+) {
+    let name = CStr::from_ptr(name).to_bytes();
+    ioFontFilesIndexed.resize(XeTeXFontMgr_sIoFontFiles.len(), false);
+    for (i, (file, inProject)) in XeTeXFontMgr_sIoFontFiles.iter().enumerate() {
+        if ioFontFilesIndexed[i] || !(*inProject || io_font_file_may_match(name, file.to_bytes())) {
+            continue;
+        }
+        ioFontFilesIndexed[i] = true;
+        XeTeXFontMgr_FC_indexIoFontFile(self_0, file);
+    }
+}
+/* Tectonic: whether a font was found through the I/O stack. */
+pub unsafe fn XeTeXFontMgr_FC_isIoFont(mut font: PlatformFontRef) -> bool {
+    if ioFonts.is_null() {
+        return false;
+    }
+    (0..(*ioFonts).nfont).any(|i| *(*ioFonts).fonts.offset(i as isize) == font)
+}
 #[no_mangle]
 pub unsafe extern "C" fn XeTeXFontMgr_FC_initialize(mut self_0: *mut XeTeXFontMgr) {
     let mut real_self: *mut XeTeXFontMgr_FC = self_0 as *mut XeTeXFontMgr_FC;
-    if FcInit() == 0i32 {
+    ioFonts = FcFontSetCreate();
+    ioFontFilesIndexed.clear();
+    (*real_self).cachedAll = 0i32 != 0;
+    if XeTeXFontMgr_sHostFontsAllowed && FcInit() == 0i32 {
         _tt_abort(b"fontconfig initialization failed\x00" as *const u8 as *const libc::c_char);
     }
     if gFreeTypeLibrary.is_null() && FT_Init_FreeType(&mut gFreeTypeLibrary) != 0i32 {
@@ -706,6 +862,12 @@ pub unsafe extern "C" fn XeTeXFontMgr_FC_initialize(mut self_0: *mut XeTeXFontMg
     if err as u64 != 0 {
         _tt_abort(b"cannot read font names\x00" as *const u8 as *const libc::c_char);
     }
+    if !XeTeXFontMgr_sHostFontsAllowed {
+        // Tectonic: in hermetic mode we don't look at the host's Fontconfig
+        // setup at all, so the list of host fonts is just left empty.
+        (*real_self).allFonts = FcFontSetCreate();
+        return;
+    }
     let mut pat: *mut FcPattern =
         FcNameParse(b":outline=true\x00" as *const u8 as *const libc::c_char as *const FcChar8);
     let mut os: *mut FcObjectSet = FcObjectSetBuild(
@@ -723,13 +885,16 @@ pub unsafe extern "C" fn XeTeXFontMgr_FC_initialize(mut self_0: *mut XeTeXFontMg
     (*real_self).allFonts = FcFontList(FcConfigGetCurrent(), pat, os);
     FcObjectSetDestroy(os);
     FcPatternDestroy(pat);
-    (*real_self).cachedAll = 0i32 != 0;
 }
 #[no_mangle]
 pub unsafe extern "C" fn XeTeXFontMgr_FC_terminate(mut self_0: *mut XeTeXFontMgr) {
     let mut real_self: *mut XeTeXFontMgr_FC = self_0 as *mut XeTeXFontMgr_FC;
     FcFontSetDestroy((*real_self).allFonts);
     (*real_self).allFonts = 0 as *mut FcFontSet;
+    if !ioFonts.is_null() {
+        FcFontSetDestroy(ioFonts);
+        ioFonts = 0 as *mut FcFontSet
+    }
     if !macRomanConv.is_null() {
         icu::ucnv_close(macRomanConv);
         macRomanConv = 0 as *mut icu::UConverter
@@ -1132,6 +1132,8 @@ pub static mut used_tectonic_coda_tokens: bool = false;
 pub static mut semantic_pagination_enabled: bool = false;
 #[no_mangle]
 pub static mut gave_char_warning_help: bool = false;
+/* Tectonic: the (font name, character) pairs that char_warning() reported. */
+pub static mut missing_chars: Vec<(String, i32)> = Vec::new();
 /* These ought to live in xetex-pagebuilder.c but are shared a lot: */
 #[no_mangle]
 pub static mut page_tail: i32 = 0;
@@ -9309,6 +9311,7 @@ pub unsafe extern "C" fn tt_run_engine(
     max_param_stack = 0i32;
     used_tectonic_coda_tokens = false;
     gave_char_warning_help = false;
+    missing_chars.clear();
     memset(
         buffer as *mut libc::c_void,
         0i32,
@@ -15,6 +15,15 @@ pub struct GlyphAssembly {
     pub parts: *mut hb_ot_math_glyph_part_t,
 }
 
+// TODO: NOTE: this type isn't included in harfbuzz_sys
+/// An axis setting of a variable font, as in `hb_font_set_variations`.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct hb_variation_t {
+    pub tag: hb_tag_t,
+    pub value: f32,
+}
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 pub struct GlyphBBox {
@@ -153,6 +162,13 @@ extern "C" {
     #[no_mangle]
     pub fn getExtendFactor(engine: XeTeXLayoutEngine) -> f32;
     #[no_mangle]
+    pub fn getFontVariations(
+        engine: XeTeXLayoutEngine,
+        variations: *mut *const hb_variation_t,
+    ) -> i32;
+    #[no_mangle]
+    pub fn findVariationAxis(font: XeTeXFont, tag: hb_tag_t) -> bool;
+    #[no_mangle]
     pub fn getFontRef(engine: XeTeXLayoutEngine) -> PlatformFontRef;
     #[no_mangle]
     pub fn getFont(engine: XeTeXLayoutEngine) -> XeTeXFont;
@@ -166,6 +182,8 @@ extern "C" {
         language: *mut i8,
         features: *mut hb_feature_t,
         nFeatures: i32,
+        variations: *mut hb_variation_t,
+        nVariations: i32,
         shapers: *mut *mut i8,
         rgbValue: u32,
         extend: f32,
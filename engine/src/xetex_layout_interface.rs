@@ -7,6 +7,7 @@
          unused_mut)]
 use crate::core_memory::{xcalloc, xmalloc};
 use harfbuzz_sys::*;
+use crate::xetex_layout_engine::hb_variation_t;
 
 use freetype::freetype_sys;
 
@@ -205,6 +206,14 @@ extern "C" {
     #[no_mangle]
     fn XeTeXFontInst_getFontTable(self_0: *const XeTeXFontInst, tag: OTTag) -> *mut libc::c_void;
     #[no_mangle]
+    fn XeTeXFontInst_hasVariationAxis(self_0: *const XeTeXFontInst, tag: OTTag) -> bool;
+    #[no_mangle]
+    fn XeTeXFontInst_setVariations(
+        self_0: *mut XeTeXFontInst,
+        variations: *const hb_variation_t,
+        count: libc::c_uint,
+    );
+    #[no_mangle]
     fn XeTeXFontInst_mapCharToGlyph(self_0: *const XeTeXFontInst, ch: UChar32) -> GlyphID;
     #[no_mangle]
     fn XeTeXFontMgr_getDesignSize(self_0: *mut XeTeXFontMgr, font: XeTeXFont) -> libc::c_double;
@@ -423,9 +432,11 @@ pub struct XeTeXLayoutEngine_rec {
     pub script: hb_tag_t,
     pub language: hb_language_t,
     pub features: *mut hb_feature_t,
+    pub variations: *mut hb_variation_t,
     pub ShaperList: *mut *mut libc::c_char,
     pub shaper: *mut libc::c_char,
     pub nFeatures: libc::c_int,
+    pub nVariations: libc::c_int,
     pub rgbValue: uint32_t,
     pub extend: libc::c_float,
     pub slant: libc::c_float,
@@ -785,6 +796,11 @@ pub unsafe extern "C" fn getFontTablePtr(
 ) -> *mut libc::c_void {
     return XeTeXFontInst_getFontTable(font as *mut XeTeXFontInst, tableTag);
 }
+/// Tells whether `tag` names an axis of a variable font.
+#[no_mangle]
+pub unsafe extern "C" fn findVariationAxis(mut font: XeTeXFont, mut tag: hb_tag_t) -> bool {
+    return XeTeXFontInst_hasVariationAxis(font as *mut XeTeXFontInst, tag);
+}
 #[no_mangle]
 pub unsafe extern "C" fn getSlant(mut font: XeTeXFont) -> Fixed {
     let mut italAngle: libc::c_float = XeTeXFontInst_getItalicAngle(font as *mut XeTeXFontInst);
@@ -1373,6 +1389,14 @@ pub unsafe extern "C" fn getSlantFactor(mut engine: XeTeXLayoutEngine) -> libc::
     return (*engine).slant;
 }
 #[no_mangle]
+pub unsafe extern "C" fn getFontVariations(
+    mut engine: XeTeXLayoutEngine,
+    mut variations: *mut *const hb_variation_t,
+) -> libc::c_int {
+    *variations = (*engine).variations;
+    return (*engine).nVariations;
+}
+#[no_mangle]
 pub unsafe extern "C" fn getEmboldenFactor(mut engine: XeTeXLayoutEngine) -> libc::c_float {
     return (*engine).embolden;
 }
@@ -1393,6 +1417,8 @@ pub unsafe extern "C" fn createLayoutEngine(
     mut language: *mut libc::c_char,
     mut features: *mut hb_feature_t,
     mut nFeatures: libc::c_int,
+    mut variations: *mut hb_variation_t,
+    mut nVariations: libc::c_int,
     mut shapers: *mut *mut libc::c_char,
     mut rgbValue: uint32_t,
     mut extend: libc::c_float,
@@ -1407,6 +1433,15 @@ pub unsafe extern "C" fn createLayoutEngine(
     (*result).ShaperList = shapers;
     (*result).shaper = 0 as *mut libc::c_char;
     (*result).nFeatures = nFeatures;
+    (*result).variations = variations;
+    (*result).nVariations = nVariations;
+    if nVariations > 0 {
+        XeTeXFontInst_setVariations(
+            font as *mut XeTeXFontInst,
+            variations,
+            nVariations as libc::c_uint,
+        );
+    }
     (*result).rgbValue = rgbValue;
     (*result).extend = extend;
     (*result).slant = slant;
@@ -1428,6 +1463,7 @@ pub unsafe extern "C" fn deleteLayoutEngine(mut engine: XeTeXLayoutEngine) {
     hb_buffer_destroy((*engine).hbBuffer);
     XeTeXFontInst_delete((*engine).font);
     free((*engine).shaper as *mut libc::c_void);
+    free((*engine).variations as *mut libc::c_void);
     XeTeXLayoutEngine_delete(engine);
 }
 unsafe extern "C" fn _decompose_compat(
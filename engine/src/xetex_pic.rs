@@ -25,11 +25,17 @@ use crate::xetex_xetex0::{
 use crate::TTInputFormat;
 use crate::{ttstub_input_close, ttstub_input_open};
 use dpx::dpx_bmpimage::{bmp_get_bbox, check_for_bmp};
+use dpx::dpx_epsimage::{check_for_eps, eps_get_bbox};
+use dpx::dpx_gifimage::{check_for_gif, gif_get_bbox};
+use dpx::dpx_jp2image::{check_for_jp2, jp2_get_bbox};
 use dpx::dpx_jpegimage::{check_for_jpeg, jpeg_get_bbox};
 use dpx::dpx_pdfdoc::{pdf_doc_get_page, pdf_doc_get_page_count};
 use dpx::dpx_pdfdraw::pdf_dev_transform;
 use dpx::dpx_pdfobj::{pdf_close, pdf_file, pdf_obj, pdf_open, pdf_release_obj};
 use dpx::dpx_pngimage::{check_for_png, png_get_bbox};
+use dpx::dpx_svgimage::{check_for_svg, svg_get_bbox};
+use dpx::dpx_tiffimage::{check_for_tiff, tiff_get_bbox};
+use dpx::dpx_webpimage::{check_for_webp, webp_get_bbox};
 use libc::{free, memcpy, strlen};
 
 use bridge::InputHandleWrapper;
@@ -178,6 +184,7 @@ unsafe extern "C" fn pdf_get_rect(
 }
 unsafe extern "C" fn get_image_size_in_inches(
     handle: &mut InputHandleWrapper,
+    page: i32,
     mut width: *mut f32,
     mut height: *mut f32,
 ) -> i32 {
@@ -194,6 +201,14 @@ unsafe extern "C" fn get_image_size_in_inches(
             &mut xdensity,
             &mut ydensity,
         )
+    } else if check_for_jp2(handle) != 0 {
+        err = jp2_get_bbox(
+            handle,
+            &mut width_pix,
+            &mut height_pix,
+            &mut xdensity,
+            &mut ydensity,
+        )
     } else if check_for_bmp(handle) != 0 {
         err = bmp_get_bbox(
             handle,
@@ -210,6 +225,31 @@ unsafe extern "C" fn get_image_size_in_inches(
             &mut xdensity,
             &mut ydensity,
         )
+    } else if check_for_webp(handle) != 0 {
+        err = webp_get_bbox(
+            handle,
+            &mut width_pix,
+            &mut height_pix,
+            &mut xdensity,
+            &mut ydensity,
+        )
+    } else if check_for_gif(handle) != 0 {
+        err = gif_get_bbox(
+            handle,
+            &mut width_pix,
+            &mut height_pix,
+            &mut xdensity,
+            &mut ydensity,
+        )
+    } else if check_for_tiff(handle) != 0 {
+        err = tiff_get_bbox(
+            handle,
+            page,
+            &mut width_pix,
+            &mut height_pix,
+            &mut xdensity,
+            &mut ydensity,
+        )
     }
     if err != 0 {
         *width = -1i32 as f32;
@@ -247,8 +287,28 @@ unsafe extern "C" fn find_pic_file(
     if pdfBoxType != 0i32 {
         /* if cmd was \XeTeXpdffile, use xpdflib to read it */
         err = pdf_get_rect(name_of_file, handle, page, pdfBoxType, bounds)
+    } else if check_for_eps(&mut handle) != 0 {
+        /* EPS figures are placed by their bounding box, like PDF pages */
+        let mut bbox = Rect::zero();
+        err = eps_get_bbox(&mut handle, &mut bbox);
+        if err == 0i32 {
+            (*bounds).x = (72.27 / 72. * bbox.ll.x) as f32;
+            (*bounds).y = (72.27 / 72. * bbox.ll.y) as f32;
+            (*bounds).wd = (72.27 / 72. * (bbox.ur.x - bbox.ll.x)) as f32;
+            (*bounds).ht = (72.27 / 72. * (bbox.ur.y - bbox.ll.y)) as f32;
+        }
+        ttstub_input_close(handle);
+    } else if check_for_svg(&mut handle) != 0 {
+        /* SVG figures take the size given by their width and height */
+        let mut bbox = Rect::zero();
+        err = svg_get_bbox(&mut handle, &mut bbox);
+        if err == 0i32 {
+            (*bounds).wd = (72.27 / 72. * (bbox.ur.x - bbox.ll.x)) as f32;
+            (*bounds).ht = (72.27 / 72. * (bbox.ur.y - bbox.ll.y)) as f32;
+        }
+        ttstub_input_close(handle);
     } else {
-        err = get_image_size_in_inches(&mut handle, &mut (*bounds).wd, &mut (*bounds).ht);
+        err = get_image_size_in_inches(&mut handle, page, &mut (*bounds).wd, &mut (*bounds).ht);
         (*bounds).wd = ((*bounds).wd as f64 * 72.27f64) as f32;
         (*bounds).ht = ((*bounds).ht as f64 * 72.27f64) as f32;
         ttstub_input_close(handle);
@@ -355,11 +415,12 @@ pub unsafe extern "C" fn load_picture(mut is_pdf: bool) {
     pack_file_name(cur_name, cur_area, cur_ext);
     pdf_box_type = 0i32;
     page = 0i32;
+    /* `page` also selects a page of a multi-page TIFF image */
+    if scan_keyword(b"page\x00" as *const u8 as *const i8) {
+        scan_int();
+        page = cur_val
+    }
     if is_pdf {
-        if scan_keyword(b"page\x00" as *const u8 as *const i8) {
-            scan_int();
-            page = cur_val
-        }
         pdf_box_type = 6i32;
         if scan_keyword(b"crop\x00" as *const u8 as *const i8) {
             pdf_box_type = 1i32
@@ -101,6 +101,49 @@ const default_synctex_ctxt: Context = Context {
 };
 static mut synctex_ctxt: Context = default_synctex_ctxt;
 
+/* Tectonic: a mapping from the names that TeX uses to open its inputs to the
+ * names that should be recorded in the SyncTeX file. This lets the driver
+ * point inputs that don't come from the filesystem under their TeX names
+ * (e.g., a primary input read from stdin or a memory buffer) at real
+ * sources. It is managed from the Rust side and is not reset by
+ * synctex_init_command(). */
+static mut synctex_source_names: Vec<(Vec<u8>, Vec<u8>)> = Vec::new();
+
+pub(crate) unsafe fn synctex_add_source_name(tex_name: &[u8], real_name: &[u8]) {
+    synctex_source_names.push((tex_name.to_vec(), real_name.to_vec()));
+}
+
+pub(crate) unsafe fn synctex_clear_source_names() {
+    synctex_source_names.clear();
+}
+
+/* Look up the name to record for an input. An exact match wins; failing
+ * that, we also try the name without a ".tex" extension, since the primary
+ * input may be named either way. */
+unsafe fn synctex_mapped_name(name: &[u8]) -> Option<&'static [u8]> {
+    find_source_name(&synctex_source_names, name)
+}
+
+fn find_source_name<'a>(names: &'a [(Vec<u8>, Vec<u8>)], name: &[u8]) -> Option<&'a [u8]> {
+    fn strip_tex(name: &[u8]) -> &[u8] {
+        if name.ends_with(b".tex") {
+            &name[..name.len() - 4]
+        } else {
+            name
+        }
+    }
+
+    names
+        .iter()
+        .find(|(tex_name, _)| &tex_name[..] == name)
+        .or_else(|| {
+            names
+                .iter()
+                .find(|(tex_name, _)| strip_tex(tex_name) == strip_tex(name))
+        })
+        .map(|(_, real_name)| &real_name[..])
+}
+
 unsafe extern "C" fn get_current_name() -> *mut i8 {
     /* This used to always make the pathname absolute but I'm getting rid of
      * that since it ends up adding dependencies on a bunch of functions I
@@ -1183,7 +1226,9 @@ unsafe extern "C" fn synctex_record_preamble() -> i32 {
 }
 #[inline]
 unsafe extern "C" fn synctex_record_input(mut tag: i32, mut name: *mut i8) -> i32 {
-    let s = format!("Input:{}:{}\n", tag, CStr::from_ptr(name).to_string_lossy(),);
+    let name = CStr::from_ptr(name).to_bytes();
+    let name = synctex_mapped_name(name).unwrap_or(name);
+    let s = format!("Input:{}:{}\n", tag, String::from_utf8_lossy(name),);
     if let Ok(len) = synctex_ctxt.file.as_mut().unwrap().write(s.as_bytes()) {
         synctex_ctxt.total_length += len;
         return 0i32;
@@ -1743,3 +1788,28 @@ There is some conditional coding.
     synctex_record_node_char (AKA synctex_node_recorder),
     synctex_record_node_unknown (AKA synctex_node_recorder),
 */
+
+#[cfg(test)]
+mod tests {
+    use super::find_source_name;
+
+    #[test]
+    fn exact_source_name_wins() {
+        let names = vec![
+            (b"foo".to_vec(), b"/src/foo".to_vec()),
+            (b"foo.tex".to_vec(), b"/src/foo.tex".to_vec()),
+        ];
+        assert_eq!(find_source_name(&names, b"foo"), Some(&b"/src/foo"[..]));
+        assert_eq!(
+            find_source_name(&names, b"foo.tex"),
+            Some(&b"/src/foo.tex"[..])
+        );
+    }
+
+    #[test]
+    fn source_name_without_extension() {
+        let names = vec![(b"foo.tex".to_vec(), b"/src/foo.tex".to_vec())];
+        assert_eq!(find_source_name(&names, b"foo"), Some(&b"/src/foo.tex"[..]));
+        assert_eq!(find_source_name(&names, b"bar"), None);
+    }
+}
@@ -8,6 +8,7 @@
     unused_mut
 )]
 
+use std::ffi::CStr;
 use std::io::Write;
 
 use super::xetex_ini::Selector;
@@ -58,18 +59,19 @@ use crate::xetex_ini::{
     long_help_seen, long_state, mag_set, main_f, main_h, main_i, main_j, main_k, main_p, main_pp,
     main_ppp, main_s, mapped_text, max_buf_stack, max_in_open, max_in_stack, max_nest_stack,
     max_param_stack, max_print_line, max_reg_help_line, max_reg_num, max_save_stack, max_strings,
-    mem, mem_end, name_in_progress, name_length, name_length16, name_of_file, name_of_file16,
-    native_font_type_flag, native_len, native_text, native_text_size, nest, nest_ptr, nest_size,
-    no_new_control_sequence, old_setting, open_parens, output_active, pack_begin_line,
-    page_contents, page_so_far, page_tail, par_loc, par_token, param_base, param_ptr, param_size,
-    param_stack, pdf_last_x_pos, pdf_last_y_pos, pool_ptr, pool_size, pre_adjust_tail, prev_class,
-    prim, prim_eqtb, prim_used, pseudo_files, pstack, quoted_filename, radix, read_file, read_open,
-    rover, rt_hit, rust_stdout, sa_chain, sa_level, sa_null, sa_root, save_native_len, save_ptr,
-    save_size, save_stack, scanner_status, selector, set_box_allowed, shown_mode, skew_char,
-    skip_line, source_filename_stack, space_class, stack_size, stop_at_space, str_pool, str_ptr,
-    str_start, tally, temp_ptr, term_offset, tex_remainder, texmf_log_name, total_shrink,
-    total_stretch, trick_buf, trick_count, use_err_help, used_tectonic_coda_tokens, warning_index,
-    width_base, write_file, write_open, xtx_ligature_present, LR_problems, LR_ptr,
+    mem, mem_end, missing_chars, name_in_progress, name_length, name_length16, name_of_file,
+    name_of_file16, native_font_type_flag, native_len, native_text, native_text_size, nest,
+    nest_ptr, nest_size, no_new_control_sequence, old_setting, open_parens, output_active,
+    pack_begin_line, page_contents, page_so_far, page_tail, par_loc, par_token, param_base,
+    param_ptr, param_size, param_stack, pdf_last_x_pos, pdf_last_y_pos, pool_ptr, pool_size,
+    pre_adjust_tail, prev_class, prim, prim_eqtb, prim_used, pseudo_files, pstack, quoted_filename,
+    radix, read_file, read_open, rover, rt_hit, rust_stdout, sa_chain, sa_level, sa_null, sa_root,
+    save_native_len, save_ptr, save_size, save_stack, scanner_status, selector, set_box_allowed,
+    shown_mode, skew_char, skip_line, source_filename_stack, space_class, stack_size,
+    stop_at_space, str_pool, str_ptr, str_start, tally, temp_ptr, term_offset, tex_remainder,
+    texmf_log_name, total_shrink, total_stretch, trick_buf, trick_count, use_err_help,
+    used_tectonic_coda_tokens, warning_index, width_base, write_file, write_open,
+    xtx_ligature_present, LR_problems, LR_ptr,
 };
 use crate::xetex_ini::{b16x4, b32x2, memory_word, prefixed_command};
 use crate::xetex_io::{input_line, open_or_close_in, set_input_file_encoding, u_close};
@@ -15631,6 +15633,10 @@ pub unsafe extern "C" fn char_warning(mut f: internal_font_number, mut c: i32) {
         chr,
         fn_0,
     );
+    let missing = (CStr::from_ptr(fn_0).to_string_lossy().into_owned(), c);
+    if !missing_chars.contains(&missing) {
+        missing_chars.push(missing);
+    }
     free(fn_0 as *mut libc::c_void);
     free(chr as *mut libc::c_void);
     if !gave_char_warning_help {
@@ -6,17 +6,21 @@ use tectonic;
 
 use structopt::StructOpt;
 
-use std::fs::File;
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 use std::process;
 use std::str::FromStr;
 
-use tectonic::config::PersistentConfig;
-use tectonic::driver::{OutputFormat, PassSetting, ProcessingSessionBuilder};
+use tectonic::config::{PersistentConfig, ProjectConfig};
+use tectonic::driver::{FontResolutionMode, OutputFormat, PassSetting, ProcessingSessionBuilder};
+use tectonic::engines::xdvipdfmx::{
+    parse_length, FontMap, FontMapMode, PaperSize, PdfAConformance, PdfEncryption, PdfOutputOptions,
+};
 use tectonic::errors::{ErrorKind, Result};
 use tectonic::io::zipbundle::ZipBundle;
 use tectonic::status::termcolor::TermcolorStatusBackend;
 use tectonic::status::{ChatterLevel, StatusBackend};
+use tectonic::synctex::SynctexData;
 
 use tectonic::{ctry, errmsg, tt_error, tt_error_styled, tt_note};
 
@@ -69,6 +73,93 @@ struct CliOptions {
     /// Generate SyncTeX data
     #[structopt(long)]
     synctex: bool,
+    /// Instead of processing <input>, read it as SyncTeX data and print where this line of a source file ended up in the output
+    #[structopt(long, name = "line:source", conflicts_with = "page:x:y")]
+    synctex_view: Option<String>,
+    /// Instead of processing <input>, read it as SyncTeX data and print the source location of this point on an output page, in big points from the top left
+    #[structopt(long, name = "page:x:y")]
+    synctex_edit: Option<String>,
+    /// Downsample images painted at more than 1.5 times <dpi> to it in the PDF output
+    #[structopt(long, name = "dpi")]
+    image_dpi: Option<u32>,
+    /// The JPEG quality (1 to 100) used for images that are re-encoded [default: 85]
+    #[structopt(long, name = "quality")]
+    jpeg_quality: Option<u8>,
+    /// Encode PNG images without transparency as JPEG in the PDF output
+    #[structopt(long)]
+    png_to_jpeg: bool,
+    /// Render bitmap (PK) fonts that cannot be found from matching Type 1/OpenType fonts
+    #[structopt(long)]
+    pk_from_outlines: bool,
+    /// Cache converted images between runs, in the "images" directory of Tectonic's per-user cache
+    #[structopt(long)]
+    image_cache: bool,
+    /// Make the PDF output conform to PDF/A at this level
+    #[structopt(long, name = "conformance", possible_values(&["2b", "3b"]))]
+    pdfa: Option<String>,
+    /// Encrypt the PDF output, with the password in the first line of this file granting full access
+    #[structopt(long, name = "owner_pw_file", parse(from_os_str))]
+    owner_password_file: Option<PathBuf>,
+    /// Encrypt the PDF output, with the password in the first line of this file needed to open it
+    #[structopt(long, name = "user_pw_file", parse(from_os_str))]
+    user_password_file: Option<PathBuf>,
+    /// Encrypt the PDF output, granting these permission flags (e.g. 0x3c)
+    #[structopt(long, name = "flags")]
+    permissions: Option<String>,
+    /// Encrypt the PDF output with a key of this length (40 to 128, or 256) [default: 256]
+    #[structopt(long, name = "bits")]
+    key_bits: Option<u32>,
+    /// Write linearized PDF output, for fast display of the first page over the web
+    #[structopt(long)]
+    linearize: bool,
+    /// Keep the links and layers of included PDF pages
+    #[structopt(long)]
+    keep_pdf_annotations: bool,
+    /// Where fonts requested by name may come from: the bundle and project only (not yet on macOS), or also the host
+    #[structopt(long, name = "mode", default_value = "host", possible_values(&["host", "hermetic"]))]
+    fonts: String,
+    /// Print a report on the fonts in the PDF output and any missing characters
+    #[structopt(long)]
+    font_report: bool,
+    /// Apply this font map file to the PDF output's font map; prefix it with "=" to override existing records, or "-" to remove them
+    #[structopt(long, name = "map_file", number_of_values = 1)]
+    map_file: Option<Vec<String>>,
+    /// Apply this font map line after any map files, with the same prefixes as --map-file
+    #[structopt(long, name = "map_line", number_of_values = 1)]
+    map_line: Option<Vec<String>>,
+    /// Do not load the default font map files (pdftex.map, kanjix.map and ckx.map)
+    #[structopt(long)]
+    no_default_maps: bool,
+    /// Print how each TFM font name was resolved for the PDF output
+    #[structopt(long)]
+    font_map_diagnostics: bool,
+    /// The paper size of the PDF output: a name such as "a4", or "<width>,<height>" [default: letter]
+    #[structopt(long, name = "paper")]
+    paper: Option<String>,
+    /// Turn the paper of the PDF output sideways
+    #[structopt(long)]
+    landscape: bool,
+    /// The distance of the origin from the left edge of the page, such as "1in" [default: 1in]
+    #[structopt(long, name = "length_x")]
+    x_offset: Option<String>,
+    /// The distance of the origin from the top edge of the page [default: 1in]
+    #[structopt(long, name = "length_y")]
+    y_offset: Option<String>,
+    /// The version of the PDF output, 1.3 to 1.7 [default: 1.5]
+    #[structopt(long, name = "pdf_version")]
+    pdf_version: Option<String>,
+    /// The number of decimal digits of coordinates in the PDF output, up to 8 [default: 5]
+    #[structopt(long, name = "digits")]
+    pdf_decimal_digits: Option<u8>,
+    /// How many levels of bookmarks are initially open; negative values count from the deepest [default: 0]
+    #[structopt(long, name = "depth", allow_hyphen_values = true)]
+    bookmark_open_depth: Option<i32>,
+    /// How far link annotations extend beyond their text [default: 0pt]
+    #[structopt(long, name = "margin")]
+    annot_grow: Option<String>,
+    /// The resolution of bitmap (PK) fonts in the PDF output [default: 600]
+    #[structopt(long, name = "pk_dpi")]
+    pk_dpi: Option<u32>,
     /// Tell the engine that no file at <hide_path> exists, if it tries to read it
     #[structopt(long, name = "hide_path")]
     hide: Option<Vec<PathBuf>>,
@@ -79,6 +170,142 @@ struct CliOptions {
     #[structopt(name = "outdir", short, long, parse(from_os_str))]
     outdir: Option<PathBuf>,
 }
+
+/// A query of the SyncTeX data produced by a previous run, as given by the
+/// `--synctex-view` and `--synctex-edit` options.
+#[derive(Debug)]
+enum SynctexCommand {
+    /// Find the output locations produced by a line of a source file
+    View {
+        /// The SyncTeX file to read (usually ending in ".synctex.gz")
+        synctex: PathBuf,
+        /// The name of the source file
+        input: String,
+        /// The line number in the source file
+        line: i32,
+    },
+    /// Find the source location that produced a point on an output page
+    Edit {
+        /// The SyncTeX file to read (usually ending in ".synctex.gz")
+        synctex: PathBuf,
+        /// The page number, starting at 1
+        page: i32,
+        /// The horizontal position, in big points from the left edge of the page
+        x: f64,
+        /// The vertical position, in big points from the top edge of the page
+        y: f64,
+    },
+}
+
+impl SynctexCommand {
+    /// The query asked for on the command line, if any.
+    fn from_args(args: &CliOptions) -> Option<Result<SynctexCommand>> {
+        let synctex = PathBuf::from(&args.input);
+
+        if let Some(ref text) = args.synctex_view {
+            // The source name goes last, since it may contain colons itself.
+            let mut parts = text.splitn(2, ':');
+            let line = parts.next().and_then(|l| l.parse().ok());
+
+            return Some(match (line, parts.next()) {
+                (Some(line), Some(input)) if !input.is_empty() => Ok(SynctexCommand::View {
+                    synctex,
+                    input: input.to_owned(),
+                    line,
+                }),
+                _ => Err(errmsg!(
+                    "invalid --synctex-view value \"{}\"; expected <line>:<source>",
+                    text
+                )),
+            });
+        }
+
+        let text = args.synctex_edit.as_ref()?;
+        let parts: Vec<&str> = text.split(':').collect();
+        let query = match parts[..] {
+            [page, x, y] => match (page.parse(), x.parse(), y.parse()) {
+                (Ok(page), Ok(x), Ok(y)) => Some(SynctexCommand::Edit {
+                    synctex,
+                    page,
+                    x,
+                    y,
+                }),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        Some(query.ok_or_else(|| {
+            errmsg!(
+                "invalid --synctex-edit value \"{}\"; expected <page>:<x>:<y>",
+                text
+            )
+        }))
+    }
+}
+
+fn synctex_inner(cmd: SynctexCommand) -> Result<()> {
+    match cmd {
+        SynctexCommand::View {
+            synctex,
+            input,
+            line,
+        } => {
+            let data = ctry!(SynctexData::from_path(&synctex); "failed to read SyncTeX file \"{}\"", synctex.display());
+            let hits = data.forward(&input, line);
+
+            if hits.is_empty() {
+                return Err(errmsg!("no output found for {}:{}", input, line));
+            }
+
+            for hit in hits {
+                println!(
+                    "Page:{}\tx:{:.2}\ty:{:.2}\tW:{:.2}\tH:{:.2}",
+                    hit.page, hit.rect.x, hit.rect.y, hit.rect.width, hit.rect.height
+                );
+            }
+        }
+
+        SynctexCommand::Edit {
+            synctex,
+            page,
+            x,
+            y,
+        } => {
+            let data = ctry!(SynctexData::from_path(&synctex); "failed to read SyncTeX file \"{}\"", synctex.display());
+
+            match data.inverse(page, x, y) {
+                Some(loc) => println!("Input:{}\tLine:{}", loc.file, loc.line),
+                None => {
+                    return Err(errmsg!(
+                        "no source found for page {} at ({}, {})",
+                        page,
+                        x,
+                        y
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn length_arg(text: &str) -> Result<f64> {
+    match parse_length(text) {
+        Some(v) => Ok(v),
+        None => Err(errmsg!("invalid length \"{}\"", text)),
+    }
+}
+
+/// Read a password from the first line of a file, so that it does not show
+/// up in the process list.
+fn password_file_arg(path: &Path) -> Result<String> {
+    let text =
+        ctry!(fs::read_to_string(path); "couldn't read the password file \"{}\"", path.display());
+    Ok(text.lines().next().unwrap_or("").to_owned())
+}
+
 fn inner(
     args: CliOptions,
     config: PersistentConfig,
@@ -91,7 +318,126 @@ fn inner(
         .keep_logs(args.keep_logs)
         .keep_intermediates(args.keep_intermediates)
         .format_cache_path(config.format_cache_path()?)
-        .synctex(args.synctex);
+        .synctex(args.synctex)
+        .image_dpi(args.image_dpi)
+        .png_to_jpeg(args.png_to_jpeg)
+        .pk_outline_fallback(args.pk_from_outlines);
+
+    if args.image_cache {
+        if let Some(p) = config.image_cache_path()? {
+            sess_builder.image_cache_path(p);
+        }
+    }
+
+    if let Some(q) = args.jpeg_quality {
+        if !(1..=100).contains(&q) {
+            return Err(errmsg!("the JPEG quality must be between 1 and 100"));
+        }
+        sess_builder.jpeg_quality(q);
+    }
+
+    if let Some(ref level) = args.pdfa {
+        sess_builder.pdfa(Some(PdfAConformance::from_str(level).unwrap()));
+    }
+
+    if args.owner_password_file.is_some()
+        || args.user_password_file.is_some()
+        || args.permissions.is_some()
+        || args.key_bits.is_some()
+    {
+        let mut enc = PdfEncryption::default();
+        if let Some(ref path) = args.owner_password_file {
+            enc.owner_password = password_file_arg(path)?;
+        }
+        if let Some(ref path) = args.user_password_file {
+            enc.user_password = password_file_arg(path)?;
+        }
+        if let Some(ref flags) = args.permissions {
+            let parsed = match flags.get(..2) {
+                Some("0x") | Some("0X") => u32::from_str_radix(&flags[2..], 16),
+                _ => flags.parse(),
+            };
+            enc.permissions = ctry!(parsed; "invalid permission flags \"{}\"", flags);
+        }
+        if let Some(bits) = args.key_bits {
+            if !PdfEncryption::key_bits_supported(bits) {
+                return Err(errmsg!(
+                    "the encryption key length must be 40 to 128 bits in steps of 8, or 256 bits"
+                ));
+            }
+            enc.key_bits = bits;
+        }
+        sess_builder.encryption(Some(enc));
+    }
+
+    sess_builder.linearize(args.linearize);
+    sess_builder.keep_annotations(args.keep_pdf_annotations);
+    sess_builder.font_resolution(FontResolutionMode::from_str(&args.fonts).unwrap());
+
+    let mut font_maps = if args.no_default_maps {
+        Vec::new()
+    } else {
+        FontMap::defaults()
+    };
+    for spec in args.map_file.unwrap_or_default() {
+        let (mode, name) = FontMapMode::split_prefix(&spec);
+        font_maps.push(FontMap::File(mode, name.to_owned()));
+    }
+    for spec in args.map_line.unwrap_or_default() {
+        let (mode, line) = FontMapMode::split_prefix(&spec);
+        font_maps.push(FontMap::Line(mode, line.to_owned()));
+    }
+    sess_builder.font_maps(font_maps);
+    sess_builder.font_map_diagnostics(args.font_map_diagnostics);
+
+    // A project configuration file next to the input overrides the per-user
+    // PDF output defaults, and the command line overrides both.
+    let project_dir = if args.input == "-" {
+        Path::new("")
+    } else {
+        Path::new(&args.input)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+    };
+    let mut output = config.pdf_output_options()?;
+    ProjectConfig::open(project_dir)?.apply_pdf_output_options(&mut output)?;
+    if let Some(ref paper) = args.paper {
+        output.paper = match PaperSize::from_str(paper) {
+            Ok(p) => p,
+            Err(e) => return Err(errmsg!("{} \"{}\"", e, paper)),
+        };
+    }
+    if args.landscape {
+        output.landscape = true;
+    }
+    if let Some(ref text) = args.x_offset {
+        output.x_offset = length_arg(text)?;
+    }
+    if let Some(ref text) = args.y_offset {
+        output.y_offset = length_arg(text)?;
+    }
+    if let Some(ref text) = args.annot_grow {
+        output.annot_grow = length_arg(text)?;
+    }
+    if let Some(ref version) = args.pdf_version {
+        output.pdf_version = match PdfOutputOptions::parse_pdf_version(version) {
+            Ok(v) => Some(v),
+            Err(e) => return Err(errmsg!("{}", e)),
+        };
+    }
+    if let Some(digits) = args.pdf_decimal_digits {
+        if digits > 8 {
+            return Err(errmsg!("the number of decimal digits must be at most 8"));
+        }
+        output.decimal_digits = digits;
+    }
+    if let Some(depth) = args.bookmark_open_depth {
+        output.bookmark_open_depth = depth;
+    }
+    if let Some(dpi) = args.pk_dpi {
+        output.pk_dpi = dpi;
+    }
+    sess_builder.pdf_output_options(output);
 
     sess_builder.output_format(OutputFormat::from_str(&args.outfmt).unwrap());
 
@@ -181,6 +527,10 @@ fn inner(
     let mut sess = sess_builder.create(status)?;
     let result = sess.run(status);
 
+    if result.is_ok() && args.font_report {
+        print!("{}", sess.font_report());
+    }
+
     if let Err(e) = &result {
         if let ErrorKind::EngineError(engine) = e.kind() {
             if let Some(output) = sess.io.mem.files.borrow().get(sess.io.mem.stdout_key()) {
@@ -202,6 +552,21 @@ fn inner(
 fn main() {
     let args = CliOptions::from_args();
 
+    // SyncTeX queries only read the output of a previous run, so they need
+    // neither the configuration nor a bundle.
+
+    if let Some(query) = SynctexCommand::from_args(&args) {
+        let mut status =
+            TermcolorStatusBackend::new(ChatterLevel::from_str(&args.chatter_level).unwrap());
+
+        if let Err(ref e) = query.and_then(synctex_inner) {
+            status.bare_error(e);
+            process::exit(1)
+        }
+
+        return;
+    }
+
     // The Tectonic crate comes with a hidden internal "test mode" that forces
     // it to use a specified set of local files, rather than going to the
     // bundle -- this makes it so that we can run tests without having to go
@@ -15,9 +15,11 @@ use serde::{Deserialize, Serialize};
 use std::ffi::OsStr;
 use std::fs::File;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::app_dirs;
+use crate::engines::xdvipdfmx::{parse_length, PaperSize, PdfOutputOptions};
 use crate::errors::{ErrorKind, Result};
 use crate::io::cached_itarbundle::CachedITarBundle;
 use crate::io::zipbundle::ZipBundle;
@@ -40,6 +42,8 @@ pub fn activate_config_test_mode(forced: bool) {
 #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct PersistentConfig {
     default_bundles: Vec<BundleInfo>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pdf_output: PdfOutputConfig,
 }
 
 #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
@@ -47,6 +51,75 @@ pub struct BundleInfo {
     url: String,
 }
 
+/// The `[pdf_output]` section, overriding the defaults of the PDF output for
+/// documents that do not set them themselves. Lengths take TeX units, as in
+/// `x_offset = "1in"`.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[derive(Default)]
+pub struct PdfOutputConfig {
+    paper: Option<String>,
+    landscape: Option<bool>,
+    x_offset: Option<String>,
+    y_offset: Option<String>,
+    pdf_version: Option<String>,
+    decimal_digits: Option<u8>,
+    bookmark_open_depth: Option<i32>,
+    annot_grow: Option<String>,
+    pk_dpi: Option<u32>,
+}
+
+fn config_length(key: &str, value: &Option<String>, default: f64) -> Result<f64> {
+    match value {
+        Some(text) => parse_length(text).ok_or_else(|| {
+            ErrorKind::Msg(format!(
+                "invalid length \"{}\" for pdf_output.{}",
+                text, key
+            ))
+            .into()
+        }),
+        None => Ok(default),
+    }
+}
+
+impl PdfOutputConfig {
+    /// Override `options` with the settings given in this section.
+    fn apply(&self, options: &mut PdfOutputOptions) -> Result<()> {
+        if let Some(ref paper) = self.paper {
+            options.paper = PaperSize::from_str(paper)
+                .map_err(|e| ErrorKind::Msg(format!("{} \"{}\" for pdf_output.paper", e, paper)))?;
+        }
+        if let Some(landscape) = self.landscape {
+            options.landscape = landscape;
+        }
+        options.x_offset = config_length("x_offset", &self.x_offset, options.x_offset)?;
+        options.y_offset = config_length("y_offset", &self.y_offset, options.y_offset)?;
+        if let Some(ref version) = self.pdf_version {
+            options.pdf_version = Some(
+                PdfOutputOptions::parse_pdf_version(version)
+                    .map_err(|e| ErrorKind::Msg(format!("pdf_output.pdf_version: {}", e)))?,
+            );
+        }
+        if let Some(digits) = self.decimal_digits {
+            if digits > 8 {
+                return Err(ErrorKind::Msg(
+                    "pdf_output.decimal_digits must be at most 8".to_owned(),
+                )
+                .into());
+            }
+            options.decimal_digits = digits;
+        }
+        if let Some(depth) = self.bookmark_open_depth {
+            options.bookmark_open_depth = depth;
+        }
+        options.annot_grow = config_length("annot_grow", &self.annot_grow, options.annot_grow)?;
+        if let Some(dpi) = self.pk_dpi {
+            options.pk_dpi = dpi;
+        }
+
+        Ok(())
+    }
+}
+
 impl PersistentConfig {
     #[cfg(feature = "serialization")]
     /// Open the per-user configuration file.
@@ -169,6 +242,25 @@ impl PersistentConfig {
             Ok(app_dirs::user_cache_dir("formats")?)
         }
     }
+
+    /// The directory finished image XObjects are cached in, next to the
+    /// format files. There is none in test mode, so that test runs do not
+    /// write into the test asset tree.
+    pub fn image_cache_path(&self) -> Result<Option<PathBuf>> {
+        if CONFIG_TEST_MODE_ACTIVATED.load(Ordering::SeqCst) {
+            Ok(None)
+        } else {
+            Ok(Some(app_dirs::user_cache_dir("images")?))
+        }
+    }
+
+    /// The PDF output defaults, with the settings of the `[pdf_output]`
+    /// section applied.
+    pub fn pdf_output_options(&self) -> Result<PdfOutputOptions> {
+        let mut options = PdfOutputOptions::default();
+        self.pdf_output.apply(&mut options)?;
+        Ok(options)
+    }
 }
 
 impl Default for PersistentConfig {
@@ -177,6 +269,56 @@ impl Default for PersistentConfig {
             default_bundles: vec![BundleInfo {
                 url: String::from("https://archive.org/services/purl/net/pkgwpub/tectonic-default"),
             }],
+            pdf_output: PdfOutputConfig::default(),
+        }
+    }
+}
+
+/// The name of the project configuration file, which is looked for in the
+/// directory of the primary input file.
+pub const PROJECT_CONFIG_FILE_NAME: &str = "Tectonic.toml";
+
+/// Settings for the documents in one directory, read from its
+/// `Tectonic.toml`. The `[pdf_output]` section takes the same keys as the
+/// per-user one and overrides it.
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[derive(Default)]
+pub struct ProjectConfig {
+    #[cfg_attr(feature = "serde", serde(default))]
+    pdf_output: PdfOutputConfig,
+}
+
+impl ProjectConfig {
+    #[cfg(feature = "serialization")]
+    /// Open the project configuration file in `dir`.
+    ///
+    /// If there is no such file, the default configuration is returned,
+    /// which changes nothing.
+    pub fn open(dir: &Path) -> Result<ProjectConfig> {
+        use std::io::ErrorKind as IoErrorKind;
+        use std::io::Read;
+
+        match File::open(dir.join(PROJECT_CONFIG_FILE_NAME)) {
+            Ok(mut f) => {
+                let mut buf = Vec::<u8>::new();
+                f.read_to_end(&mut buf)?;
+                Ok(toml::from_slice(&buf)?)
+            }
+            Err(ref e) if e.kind() == IoErrorKind::NotFound => Ok(ProjectConfig::default()),
+            Err(e) => Err(e.into()),
         }
     }
+
+    #[cfg(not(feature = "serialization"))]
+    /// Return a default configuration structure, since this version of
+    /// Tectonic has been built without the `serde` feature and cannot
+    /// deserialize the file.
+    pub fn open(_dir: &Path) -> Result<ProjectConfig> {
+        Ok(ProjectConfig::default())
+    }
+
+    /// Override `options` with the settings of the `[pdf_output]` section.
+    pub fn apply_pdf_output_options(&self, options: &mut PdfOutputOptions) -> Result<()> {
+        self.pdf_output.apply(options)
+    }
 }
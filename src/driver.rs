@@ -13,6 +13,7 @@
 
 use std::collections::{HashMap, HashSet};
 use std::ffi::{OsStr, OsString};
+use std::fmt;
 use std::fs::File;
 use std::io::Write;
 use std::path::{Path, PathBuf};
@@ -20,12 +21,20 @@ use std::rc::Rc;
 use std::str::FromStr;
 
 use crate::digest::DigestData;
+use crate::engines::bibtex::{BibtexDiagnostic, BibtexSeverity};
+use crate::engines::tex::{FontBackend, FontResolution, MissingChar};
+use crate::engines::xdvipdfmx::{
+    FontMap, PdfAConformance, PdfEncryption, PdfFont, PdfOutputOptions,
+};
 use crate::engines::IoEventBackend;
 use crate::errors::{ErrorKind, Result, ResultExt};
 use crate::io::{Bundle, InputOrigin, IoProvider, IoSetup, IoSetupBuilder, OpenResult};
 use crate::status::StatusBackend;
 use crate::{ctry, errmsg, tt_error, tt_note, tt_warning};
-use crate::{BibtexEngine, Spx2HtmlEngine, TexEngine, TexResult, XdvipdfmxEngine};
+use crate::{
+    BiberEngine, BibtexEngine, MakeindexEngine, Spx2HtmlEngine, TexEngine, TexResult,
+    XdvipdfmxEngine,
+};
 use std::result::Result as StdResult;
 
 /// Different patterns with which files may have been accessed by the
@@ -264,6 +273,135 @@ impl FromStr for PassSetting {
     }
 }
 
+/// Where the TeX engine may find fonts that documents request by name, such as
+/// with `\setmainfont{TeX Gyre Termes}`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FontResolutionMode {
+    /// Only fonts in the bundle and the project's own files are used, so
+    /// that a document gets the same fonts on every machine. This is not
+    /// supported on macOS yet, since such fonts can only be looked up by
+    /// name through Fontconfig.
+    Hermetic,
+    /// Fonts in the bundle and the project are preferred, but fonts installed
+    /// on the host are used if no other font matches.
+    HostAllowed,
+}
+
+impl Default for FontResolutionMode {
+    fn default() -> FontResolutionMode {
+        FontResolutionMode::HostAllowed
+    }
+}
+
+impl FromStr for FontResolutionMode {
+    type Err = &'static str;
+
+    fn from_str(a_str: &str) -> StdResult<Self, Self::Err> {
+        match a_str {
+            "hermetic" => Ok(FontResolutionMode::Hermetic),
+            "host" => Ok(FontResolutionMode::HostAllowed),
+            _ => Err("unsupported or unknown font resolution mode"),
+        }
+    }
+}
+
+/// Where the file of a font in the PDF output came from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FontOrigin {
+    /// One of the project's own files.
+    Project,
+    /// A file from the bundle.
+    Bundle,
+    /// A font installed on the host, found when the document asked for a
+    /// font by name.
+    Host,
+    /// The file could not be traced back to any of the above.
+    Unknown,
+}
+
+impl fmt::Display for FontOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            FontOrigin::Project => "project",
+            FontOrigin::Bundle => "bundle",
+            FontOrigin::Host => "host",
+            FontOrigin::Unknown => "unknown",
+        })
+    }
+}
+
+/// A font of the PDF output, together with the origin of its file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReportedFont {
+    /// The font as xdvipdfmx wrote it.
+    pub font: PdfFont,
+    /// Where the font file came from.
+    pub origin: FontOrigin,
+}
+
+/// An inventory of the fonts that went into a document, as returned by
+/// [`ProcessingSession::font_report`]. Its `Display` implementation gives a
+/// human-readable summary.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FontReport {
+    /// The font resources of the PDF output, in the order xdvipdfmx wrote
+    /// them.
+    pub fonts: Vec<ReportedFont>,
+    /// The characters that the TeX engine could not find in their fonts.
+    pub missing_chars: Vec<MissingChar>,
+}
+
+impl fmt::Display for FontReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "fonts in the PDF output:")?;
+
+        for r in &self.fonts {
+            let font = &r.font;
+
+            match font.subset_tag {
+                Some(ref tag) => write!(f, "  {}+{}", tag, font.font_name)?,
+                None => write!(f, "  {}", font.font_name)?,
+            }
+
+            write!(f, " ({}", font.font_type)?;
+            if font.cid_keyed {
+                write!(f, ", CID-keyed")?;
+            }
+
+            let embedding = if !font.embedded {
+                "not embedded"
+            } else if font.subset_tag.is_some() {
+                "subset"
+            } else {
+                "embedded"
+            };
+
+            writeln!(
+                f,
+                ", {}, {} glyphs, encoding {})",
+                embedding,
+                font.glyph_count,
+                font.encoding
+                    .as_ref()
+                    .map(|e| e.as_str())
+                    .unwrap_or("built-in")
+            )?;
+            let file = font.opened_as.as_ref().map_or(&font.file, |(name, _)| name);
+            writeln!(f, "    file: {} ({})", file, r.origin)?;
+        }
+
+        if !self.missing_chars.is_empty() {
+            writeln!(f, "missing characters:")?;
+
+            for m in &self.missing_chars {
+                writeln!(f, "  U+{:04X} in font \"{}\"", m.code, m.font)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
 /// Different places from which the "primary input" might originate.
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum PrimaryInputMode {
@@ -323,6 +461,20 @@ pub struct ProcessingSessionBuilder {
     keep_intermediates: bool,
     keep_logs: bool,
     synctex: bool,
+    synctex_source_names: Vec<(String, String)>,
+    image_dpi: Option<u32>,
+    jpeg_quality: Option<u8>,
+    png_to_jpeg: bool,
+    pk_outline_fallback: bool,
+    image_cache_path: Option<PathBuf>,
+    pdfa: Option<PdfAConformance>,
+    encryption: Option<PdfEncryption>,
+    linearize: bool,
+    keep_annotations: bool,
+    font_resolution: FontResolutionMode,
+    font_maps: Option<Vec<FontMap>>,
+    font_map_diagnostics: bool,
+    pdf_output_options: PdfOutputOptions,
 }
 
 impl ProcessingSessionBuilder {
@@ -456,13 +608,145 @@ impl ProcessingSessionBuilder {
     }
 
     /// If set to `true`, tex files will be compiled using synctex information.
+    ///
+    /// The SyncTeX data land in a file named like the main input with a
+    /// `.synctex.gz` extension. Like other outputs, it is available from
+    /// [`ProcessingSession::into_file_data`] if output files are not written
+    /// to disk.
     pub fn synctex(&mut self, s: bool) -> &mut Self {
         self.synctex = s;
         self
     }
 
+    /// Record `real_name` in the SyncTeX data for the input that TeX knows as
+    /// `tex_name`.
+    ///
+    /// This is mainly useful when the primary input comes from standard
+    /// input or a memory buffer: TeX only knows it by the name given to
+    /// [`Self::tex_input_name`], which doesn't correspond to any real file.
+    /// Mapping that name to the path of the actual source lets editors and
+    /// viewers synchronize with it. Names of other inputs can be mapped too.
+    pub fn synctex_source_name<P: AsRef<Path>>(
+        &mut self,
+        tex_name: &str,
+        real_name: P,
+    ) -> &mut Self {
+        self.synctex_source_names.push((
+            tex_name.to_owned(),
+            real_name.as_ref().to_string_lossy().into_owned(),
+        ));
+        self
+    }
+
+    /// Downsample images in the PDF output that are painted at more than 1.5
+    /// times this resolution, in dots per inch, to it. Images are included
+    /// at their original resolution by default.
+    pub fn image_dpi(&mut self, dpi: Option<u32>) -> &mut Self {
+        self.image_dpi = dpi;
+        self
+    }
+
+    /// Sets the JPEG quality, from 1 to 100, used when images are re-encoded
+    /// as JPEG. The default is 85.
+    pub fn jpeg_quality(&mut self, quality: u8) -> &mut Self {
+        self.jpeg_quality = Some(quality);
+        self
+    }
+
+    /// If set to `true`, PNG images without transparency are encoded as
+    /// JPEG in the PDF output.
+    pub fn png_to_jpeg(&mut self, p: bool) -> &mut Self {
+        self.png_to_jpeg = p;
+        self
+    }
+
+    /// If set to `true`, glyphs of bitmap (PK) fonts that cannot be found
+    /// are rendered from matching Type 1 or OpenType fonts.
+    pub fn pk_outline_fallback(&mut self, p: bool) -> &mut Self {
+        self.pk_outline_fallback = p;
+        self
+    }
+
+    /// Sets the directory that finished images are cached in, so that image
+    /// files that did not change need not be converted again. Images are not
+    /// cached by default.
+    pub fn image_cache_path<P: AsRef<Path>>(&mut self, p: P) -> &mut Self {
+        self.image_cache_path = Some(p.as_ref().to_owned());
+        self
+    }
+
+    /// Makes the PDF output conform to the given PDF/A level. Anything in
+    /// the document that prevents this is reported as an error.
+    pub fn pdfa(&mut self, level: Option<PdfAConformance>) -> &mut Self {
+        self.pdfa = level;
+        self
+    }
+
+    /// Encrypts the PDF output with the given settings. The output is not
+    /// encrypted by default.
+    pub fn encryption(&mut self, encryption: Option<PdfEncryption>) -> &mut Self {
+        self.encryption = encryption;
+        self
+    }
+
+    /// Writes linearized ("fast web view") PDF output.
+    pub fn linearize(&mut self, linearize: bool) -> &mut Self {
+        self.linearize = linearize;
+        self
+    }
+
+    /// Keeps the links and layers (optional content) of included PDF pages.
+    pub fn keep_annotations(&mut self, keep: bool) -> &mut Self {
+        self.keep_annotations = keep;
+        self
+    }
+
+    /// Sets where the TeX engine may find fonts that documents request by
+    /// name. Host fonts are allowed by default. On macOS, asking for
+    /// [`FontResolutionMode::Hermetic`] makes [`Self::create`] fail.
+    pub fn font_resolution(&mut self, mode: FontResolutionMode) -> &mut Self {
+        self.font_resolution = mode;
+        self
+    }
+
+    /// Sets the font map files and lines that xdvipdfmx applies, in order,
+    /// in place of the default ones (see [`FontMap::defaults`]).
+    pub fn font_maps(&mut self, maps: Vec<FontMap>) -> &mut Self {
+        self.font_maps = Some(maps);
+        self
+    }
+
+    /// Adds a font map file or line after those configured so far.
+    pub fn font_map(&mut self, map: FontMap) -> &mut Self {
+        self.font_maps
+            .get_or_insert_with(FontMap::defaults)
+            .push(map);
+        self
+    }
+
+    /// If set to `true`, xdvipdfmx prints how it resolved each TFM name to a
+    /// font.
+    pub fn font_map_diagnostics(&mut self, p: bool) -> &mut Self {
+        self.font_map_diagnostics = p;
+        self
+    }
+
+    /// Sets the paper size, origin offsets, PDF version and the other PDF
+    /// output defaults that documents do not set themselves.
+    pub fn pdf_output_options(&mut self, options: PdfOutputOptions) -> &mut Self {
+        self.pdf_output_options = options;
+        self
+    }
+
     /// Creates a `ProcessingSession`.
     pub fn create(self, status: &mut dyn StatusBackend) -> Result<ProcessingSession> {
+        if cfg!(target_os = "macos") && self.font_resolution == FontResolutionMode::Hermetic {
+            return Err(errmsg!(
+                "hermetic font resolution is not supported on macOS yet: fonts in the bundle and \
+                 the project can only be loaded by file name there"
+            ));
+        }
+
         let mut io = IoSetupBuilder::default();
         io.bundle(self.bundle.expect("a bundle must be specified"))
             .use_genuine_stdout(self.print_stdout);
@@ -529,9 +813,14 @@ impl ProcessingSessionBuilder {
         });
         let mut pdf_path = aux_path.clone();
         pdf_path.set_extension("pdf");
+        let mut bcf_path = aux_path.clone();
+        bcf_path.set_extension("bcf");
+
+        let mut io = io.create(status)?;
+        let font_files = io.font_file_names();
 
         Ok(ProcessingSession {
-            io: io.create(status)?,
+            io,
             events: IoEvents::new(),
             pass: self.pass,
             primary_input_path,
@@ -540,6 +829,7 @@ impl ProcessingSessionBuilder {
             tex_aux_path: aux_path.into_os_string(),
             tex_xdv_path: xdv_path.into_os_string(),
             tex_pdf_path: pdf_path.into_os_string(),
+            tex_bcf_path: bcf_path.into_os_string(),
             output_format: self.output_format,
             makefile_output_path: self.makefile_output_path,
             output_path,
@@ -548,6 +838,27 @@ impl ProcessingSessionBuilder {
             keep_logs: self.keep_logs,
             noted_tex_warnings: false,
             synctex_enabled: self.synctex,
+            synctex_source_names: self.synctex_source_names,
+            image_dpi: self.image_dpi,
+            jpeg_quality: self.jpeg_quality,
+            png_to_jpeg: self.png_to_jpeg,
+            pk_outline_fallback: self.pk_outline_fallback,
+            image_cache_path: self.image_cache_path,
+            pdfa: self.pdfa,
+            encryption: self.encryption,
+            linearize: self.linearize,
+            keep_annotations: self.keep_annotations,
+            font_resolution: self.font_resolution,
+            font_files,
+            font_maps: self.font_maps,
+            font_map_diagnostics: self.font_map_diagnostics,
+            pdf_output_options: self.pdf_output_options,
+            font_resolutions: Vec::new(),
+            missing_chars: Vec::new(),
+            pdf_fonts: Vec::new(),
+            bibtex_diagnostics: Vec::new(),
+            biber_bcf_digest: None,
+            index_inputs: HashMap::new(),
         })
     }
 }
@@ -585,6 +896,7 @@ pub struct ProcessingSession {
     tex_aux_path: OsString,
     tex_xdv_path: OsString,
     tex_pdf_path: OsString,
+    tex_bcf_path: OsString,
 
     /// If we're writing out Makefile rules, this is where they go. The TeX
     /// engine doesn't know about this path at all.
@@ -604,6 +916,44 @@ pub struct ProcessingSession {
     keep_logs: bool,
     noted_tex_warnings: bool,
     synctex_enabled: bool,
+    synctex_source_names: Vec<(String, String)>,
+    image_dpi: Option<u32>,
+    jpeg_quality: Option<u8>,
+    png_to_jpeg: bool,
+    pk_outline_fallback: bool,
+    image_cache_path: Option<PathBuf>,
+    pdfa: Option<PdfAConformance>,
+    encryption: Option<PdfEncryption>,
+    linearize: bool,
+    keep_annotations: bool,
+    font_resolution: FontResolutionMode,
+
+    /// The font files that the TeX engine can look up fonts in by name,
+    /// listed once rather than on every TeX pass.
+    font_files: Vec<(String, bool)>,
+
+    font_maps: Option<Vec<FontMap>>,
+    font_map_diagnostics: bool,
+    pdf_output_options: PdfOutputOptions,
+
+    /// How the fonts requested by name were found in the most recent TeX
+    /// pass.
+    font_resolutions: Vec<FontResolution>,
+
+    /// The characters missing from their fonts in the most recent TeX pass.
+    missing_chars: Vec<MissingChar>,
+
+    /// The fonts written by the most recent xdvipdfmx pass.
+    pdf_fonts: Vec<PdfFont>,
+    bibtex_diagnostics: Vec<BibtexDiagnostic>,
+
+    /// The digest of the `.bcf` file that biber last processed, so that we
+    /// rerun it when TeX writes a different one.
+    biber_bcf_digest: Option<DigestData>,
+
+    /// The contents of the index-like files that we last ran makeindex on,
+    /// so that we only rerun it when they change.
+    index_inputs: HashMap<OsString, Vec<u8>>,
 }
 
 const DEFAULT_MAX_TEX_PASSES: usize = 6;
@@ -611,6 +961,13 @@ const ALWAYS_INTERMEDIATE_EXTENSIONS: &[&str] = &[
     ".snm", ".toc", // generated by Beamer
 ];
 
+/// The extensions of the raw input, formatted output, and transcript files
+/// of the index-like passes that we know how to run.
+const INDEX_EXTENSIONS: &[(&str, &str, &str)] = &[
+    ("idx", "ind", "ilg"), // \makeindex
+    ("glo", "gls", "glg"), // glossaries
+];
+
 impl ProcessingSession {
     /// Assess whether we need to rerun an engine. This is the case if there
     /// was a file that the engine read and then rewrote, and the rewritten
@@ -676,8 +1033,9 @@ impl ProcessingSession {
     ///
     /// - if a `.fmt` file does not yet exist, generate one and cache it
     /// - run the TeX engine once
-    /// - run BibTeX, if it seems to be required
-    /// - repeat the last two steps as often as needed
+    /// - run BibTeX or biber, if one seems to be required
+    /// - run makeindex on any index or glossary files that have changed
+    /// - repeat the last three steps as often as needed
     /// - write the output files to disk, including a Makefile if it was requested.
     pub fn run<S: StatusBackend>(&mut self, status: &mut S) -> Result<()> {
         // Do we need to generate the format file?
@@ -836,7 +1194,9 @@ impl ProcessingSession {
                 continue;
             }
 
-            let is_logfile = sname.ends_with(".log") || sname.ends_with(".blg");
+            let is_logfile = [".log", ".blg", ".ilg", ".glg"]
+                .iter()
+                .any(|ext| sname.ends_with(ext));
 
             if is_logfile && !self.keep_logs {
                 continue;
@@ -884,22 +1244,32 @@ impl ProcessingSession {
         bibtex_first: bool,
         status: &mut S,
     ) -> Result<i32> {
-        // If `bibtex_first` is true, we start by running bibtex, and run
-        // proceed with the standard rerun logic. Otherwise, we run TeX,
-        // auto-detect whether we need to run bibtex, possibly run it, and
-        // then go ahead.
+        // If `bibtex_first` is true, we start by running biber or bibtex on
+        // the files left by a previous run, and then proceed with the
+        // standard rerun logic. Otherwise, we run TeX, auto-detect whether we
+        // need to run biber or bibtex, possibly run it, and then go ahead.
+        // Index generation can happen after any TeX pass, since the index
+        // entries might move around, and biber is rerun whenever TeX writes
+        // a different `.bcf` file.
 
         let mut rerun_result = if bibtex_first {
-            self.bibtex_pass(status)?;
-            Some(String::new())
+            self.bibliography_pass(true, status)?
         } else {
             self.tex_pass(None, status)?;
+            let index_changed = self.index_passes(status)?;
 
-            if self.use_bibtex() {
-                self.bibtex_pass(status)?;
-                Some(String::new())
+            if let Some(s) = self.bibliography_pass(false, status)? {
+                Some(s)
             } else {
                 self.rerun_needed(status)
+                    .map(|s| format!("\"{}\" changed", s))
+                    .or_else(|| {
+                        if index_changed {
+                            Some("makeindex was run".to_owned())
+                        } else {
+                            None
+                        }
+                    })
             }
         };
 
@@ -915,13 +1285,7 @@ impl ProcessingSession {
                 "I was told to".to_owned()
             } else {
                 match rerun_result {
-                    Some(ref s) => {
-                        if s == "" {
-                            "bibtex was run".to_owned()
-                        } else {
-                            format!("\"{}\" changed", s)
-                        }
-                    }
+                    Some(ref s) => s.clone(),
                     None => {
                         break;
                     }
@@ -939,9 +1303,25 @@ impl ProcessingSession {
             }
 
             self.tex_pass(Some(&rerun_explanation), status)?;
+            let index_changed = self.index_passes(status)?;
+            let biber_rerun = self.biber_rerun_needed();
+
+            if biber_rerun {
+                self.biber_pass(status)?;
+            }
 
             if !reruns_fixed {
-                rerun_result = self.rerun_needed(status);
+                rerun_result = self
+                    .rerun_needed(status)
+                    .map(|s| format!("\"{}\" changed", s));
+
+                if rerun_result.is_none() && index_changed {
+                    rerun_result = Some("makeindex was run".to_owned());
+                }
+
+                if rerun_result.is_none() && biber_rerun {
+                    rerun_result = Some("biber was run".to_owned());
+                }
 
                 if rerun_result.is_some() && i == DEFAULT_MAX_TEX_PASSES - 1 {
                     tt_warning!(
@@ -981,6 +1361,109 @@ impl ProcessingSession {
             .unwrap_or(false)
     }
 
+    /// biblatex documents using the biber backend leave a `.bcf` control
+    /// file rather than a `\bibdata` line in the `.aux` file. Before TeX has
+    /// run, we look for one left by a previous run instead.
+    fn use_biber<S: StatusBackend>(&mut self, before_tex: bool, status: &mut S) -> bool {
+        if self.io.mem.files.borrow().contains_key(&self.tex_bcf_path) {
+            return true;
+        }
+
+        if !before_tex {
+            return false;
+        }
+
+        let mut stack = self.io.as_stack();
+        match stack.input_open_name(&self.tex_bcf_path, status) {
+            OpenResult::Ok(_) => true,
+            _ => false,
+        }
+    }
+
+    /// The digest of the `.bcf` file that TeX last wrote, if any.
+    fn bcf_write_digest(&self) -> Option<DigestData> {
+        self.events
+            .0
+            .get(&self.tex_bcf_path)
+            .and_then(|summ| summ.write_digest)
+    }
+
+    /// Whether TeX has written a `.bcf` file that biber hasn't processed.
+    fn biber_rerun_needed(&self) -> bool {
+        match self.bcf_write_digest() {
+            Some(digest) => self.biber_bcf_digest != Some(digest),
+            None => false,
+        }
+    }
+
+    /// Run biber or bibtex, whichever the document needs. With
+    /// `before_tex`, this runs ahead of the first TeX pass, and bibtex is run
+    /// unless there is a `.bcf` file for biber. Returns the reason to rerun
+    /// TeX, if either of them was run.
+    fn bibliography_pass<S: StatusBackend>(
+        &mut self,
+        before_tex: bool,
+        status: &mut S,
+    ) -> Result<Option<String>> {
+        if self.use_biber(before_tex, status) {
+            self.biber_pass(status)?;
+            Ok(Some("biber was run".to_owned()))
+        } else if before_tex || self.use_bibtex() {
+            self.bibtex_pass(status)?;
+            Ok(Some("bibtex was run".to_owned()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Run makeindex on each index-like file that TeX has written, unless
+    /// its contents are the same as the last time that we processed it.
+    /// Returns true if any of the formatted outputs changed, meaning that
+    /// TeX needs to be rerun.
+    fn index_passes<S: StatusBackend>(&mut self, status: &mut S) -> Result<bool> {
+        let mut changed = false;
+
+        for (input_ext, output_ext, transcript_ext) in INDEX_EXTENSIONS {
+            let path = PathBuf::from(&self.tex_aux_path);
+            let input = path.with_extension(input_ext).into_os_string();
+            let output = path.with_extension(output_ext).into_os_string();
+            let transcript = path.with_extension(transcript_ext).into_os_string();
+
+            let (data, before) = {
+                let files = self.io.mem.files.borrow();
+
+                match files.get(&input) {
+                    Some(d) => (d.clone(), files.get(&output).cloned()),
+                    None => continue,
+                }
+            };
+
+            if self.index_inputs.get(&input) == Some(&data) {
+                continue;
+            }
+
+            // Glossaries use a style file that the package writes out for
+            // us; plain indexes use the default style.
+            let style = if *input_ext == "idx" {
+                None
+            } else {
+                let ist = path.with_extension("ist").into_os_string();
+
+                if self.io.mem.files.borrow().contains_key(&ist) {
+                    Some(ist)
+                } else {
+                    None
+                }
+            };
+
+            self.makeindex_pass(&input, &output, &transcript, style.as_ref(), status)?;
+            self.index_inputs.insert(input, data);
+            changed |= self.io.mem.files.borrow().get(&output) != before.as_ref();
+        }
+
+        Ok(changed)
+    }
+
     /// Use the TeX engine to generate a format file.
     fn make_format_pass<S: StatusBackend>(&mut self, status: &mut S) -> Result<i32> {
         if self.io.bundle.is_none() {
@@ -1073,18 +1556,49 @@ impl ProcessingSession {
                 status.note_highlighted("Running ", "TeX", " ...");
             }
 
-            TexEngine::new()
+            let mut engine = TexEngine::new();
+            engine
                 .halt_on_error_mode(true)
                 .initex_mode(self.output_format == OutputFormat::Format)
                 .synctex(self.synctex_enabled)
-                .semantic_pagination(self.output_format == OutputFormat::Html)
-                .process(
-                    &mut stack,
-                    &mut self.events,
-                    status,
-                    &self.format_name,
-                    &self.primary_input_tex_path,
-                )
+                .semantic_pagination(self.output_format == OutputFormat::Html);
+
+            for (tex_name, real_name) in &self.synctex_source_names {
+                engine.synctex_source_name(tex_name, real_name);
+            }
+
+            engine.host_fonts(self.font_resolution == FontResolutionMode::HostAllowed);
+
+            for (name, in_project) in &self.font_files {
+                engine.font_file(name, *in_project);
+            }
+
+            let result = engine.process(
+                &mut stack,
+                &mut self.events,
+                status,
+                &self.format_name,
+                &self.primary_input_tex_path,
+            );
+
+            let resolutions = engine.font_resolutions().to_vec();
+
+            for r in &resolutions {
+                // Only mention each host font once, rather than on every pass.
+                if r.backend == FontBackend::Host && !self.font_resolutions.contains(r) {
+                    tt_note!(
+                        status,
+                        "font \"{}\" was taken from the host system ({}); \
+                         other machines may use a different font",
+                        r.name,
+                        r.file
+                    );
+                }
+            }
+
+            self.font_resolutions = resolutions;
+            self.missing_chars = engine.missing_chars().to_vec();
+            result
         };
 
         match result {
@@ -1120,14 +1634,23 @@ impl ProcessingSession {
             let mut stack = self.io.as_stack();
             let mut engine = BibtexEngine::new();
             status.note_highlighted("Running ", "BibTeX", " ...");
-            engine.process(
+            let result = engine.process(
                 &mut stack,
                 &mut self.events,
                 status,
                 &self.tex_aux_path.to_str().unwrap(),
-            )
+            );
+            self.bibtex_diagnostics = engine.diagnostics().to_vec();
+            result
         };
 
+        for diag in &self.bibtex_diagnostics {
+            match diag.severity {
+                BibtexSeverity::Warning => tt_warning!(status, "BibTeX: {}", diag),
+                BibtexSeverity::Error => tt_error!(status, "BibTeX: {}", diag),
+            }
+        }
+
         match result {
             Ok(TexResult::Spotless) => {}
             Ok(TexResult::Warnings) => {
@@ -1151,18 +1674,123 @@ impl ProcessingSession {
         Ok(0)
     }
 
+    fn biber_pass<S: StatusBackend>(&mut self, status: &mut S) -> Result<i32> {
+        self.biber_bcf_digest = self.bcf_write_digest();
+
+        let result = {
+            let mut stack = self.io.as_stack();
+            let mut engine = BiberEngine::new();
+            status.note_highlighted("Running ", "biber", " ...");
+            engine.process(
+                &mut stack,
+                &mut self.events,
+                status,
+                &self.tex_bcf_path.to_str().unwrap(),
+            )
+        };
+
+        match result {
+            Ok(TexResult::Spotless) | Ok(TexResult::Warnings) => {}
+            Ok(TexResult::Errors) => {
+                tt_warning!(
+                    status,
+                    "errors were issued by biber, but were ignored; \
+                     use --keep-logs for details."
+                );
+            }
+            Err(e) => {
+                return Err(e.chain_err(|| ErrorKind::EngineError("biber")));
+            }
+        }
+
+        Ok(0)
+    }
+
+    fn makeindex_pass<S: StatusBackend>(
+        &mut self,
+        input: &OsStr,
+        output: &OsStr,
+        transcript: &OsStr,
+        style: Option<&OsString>,
+        status: &mut S,
+    ) -> Result<i32> {
+        let result = {
+            let mut stack = self.io.as_stack();
+            let mut engine = MakeindexEngine::new();
+
+            if let Some(style) = style {
+                engine.style(style.to_str().unwrap());
+            }
+
+            status.note_highlighted(
+                "Running ",
+                "makeindex",
+                &format!(" on {} ...", input.to_string_lossy()),
+            );
+            engine.process(
+                &mut stack,
+                &mut self.events,
+                status,
+                input.to_str().unwrap(),
+                output.to_str().unwrap(),
+                transcript.to_str().unwrap(),
+            )
+        };
+
+        match result {
+            Ok(TexResult::Spotless) | Ok(TexResult::Warnings) => {}
+            Ok(TexResult::Errors) => {
+                tt_warning!(
+                    status,
+                    "errors were issued by makeindex, but were ignored; \
+                     use --keep-logs for details."
+                );
+            }
+            Err(e) => {
+                return Err(e.chain_err(|| ErrorKind::EngineError("makeindex")));
+            }
+        }
+
+        Ok(0)
+    }
+
     fn xdvipdfmx_pass<S: StatusBackend>(&mut self, status: &mut S) -> Result<i32> {
-        {
+        let result = {
             let mut stack = self.io.as_stack();
-            let mut engine = XdvipdfmxEngine::new();
+            let mut engine = XdvipdfmxEngine::new()
+                .with_image_dpi(self.image_dpi)
+                .with_png_to_jpeg(self.png_to_jpeg)
+                .with_pk_outline_fallback(self.pk_outline_fallback)
+                .with_image_cache_path(self.image_cache_path.clone())
+                .with_pdfa(self.pdfa)
+                .with_encryption(self.encryption.clone())
+                .with_linearize(self.linearize)
+                .with_keep_annotations(self.keep_annotations)
+                .with_font_map_diagnostics(self.font_map_diagnostics)
+                .with_output_options(self.pdf_output_options.clone());
+            if let Some(quality) = self.jpeg_quality {
+                engine = engine.with_jpeg_quality(quality);
+            }
+            if let Some(ref maps) = self.font_maps {
+                engine = engine.with_font_maps(maps.clone());
+            }
             status.note_highlighted("Running ", "xdvipdfmx", " ...");
-            engine.process(
+            let result = engine.process(
                 &mut stack,
                 &mut self.events,
                 status,
                 &self.tex_xdv_path.to_str().unwrap(),
                 &self.tex_pdf_path.to_str().unwrap(),
-            )?;
+            );
+            self.pdf_fonts = engine.fonts().to_vec();
+            result
+        };
+
+        if let Err(e) = result {
+            // Whatever was written is either cut short or, after PDF/A
+            // violations, a complete document that does not conform.
+            self.io.mem.files.borrow_mut().remove(&self.tex_pdf_path);
+            return Err(e);
         }
 
         self.io.mem.files.borrow_mut().remove(&self.tex_xdv_path);
@@ -1186,6 +1814,52 @@ impl ProcessingSession {
         Ok(0)
     }
 
+    /// Get how the fonts that the document requested by name were found in
+    /// the most recent TeX pass, and which backend satisfied each of them.
+    pub fn font_resolutions(&self) -> &[FontResolution] {
+        &self.font_resolutions
+    }
+
+    /// Get a report on the fonts of the PDF output, as written by the most
+    /// recent xdvipdfmx pass, along with the characters that the most recent
+    /// TeX pass could not find in their fonts.
+    pub fn font_report(&self) -> FontReport {
+        FontReport {
+            fonts: self
+                .pdf_fonts
+                .iter()
+                .map(|font| ReportedFont {
+                    font: font.clone(),
+                    origin: self.font_origin(font),
+                })
+                .collect(),
+            missing_chars: self.missing_chars.clone(),
+        }
+    }
+
+    fn font_origin(&self, font: &PdfFont) -> FontOrigin {
+        if self
+            .font_resolutions
+            .iter()
+            .any(|r| r.backend == FontBackend::Host && r.file == font.file)
+        {
+            return FontOrigin::Host;
+        }
+
+        match font.opened_as {
+            Some((_, InputOrigin::Filesystem)) => FontOrigin::Project,
+            Some((_, InputOrigin::Other)) => FontOrigin::Bundle,
+            _ => FontOrigin::Unknown,
+        }
+    }
+
+    /// Get the warnings and errors reported by the most recent BibTeX pass.
+    ///
+    /// This is empty if BibTeX hasn't been run, or if it ran cleanly.
+    pub fn bibtex_diagnostics(&self) -> &[BibtexDiagnostic] {
+        &self.bibtex_diagnostics
+    }
+
     /// Consume this session and return the current set of files in memory.
     ///
     /// This convenience function tries to help with the annoyances of getting
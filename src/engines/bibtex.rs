@@ -2,22 +2,267 @@
 // Copyright 2017 the Tectonic Project
 // Licensed under the MIT License.
 
-use std::ffi::{CStr, CString};
+use std::ffi::{CStr, CString, OsStr};
+use std::fmt;
+use std::io::Read;
 
 use super::tex::TexResult;
 use super::{ExecutionState, IoEventBackend, TectonicBridgeApi};
 use crate::errors::{ErrorKind, Result};
-use crate::io::IoStack;
+use crate::io::{IoProvider, IoStack, OpenResult};
 use crate::status::StatusBackend;
 
+/// How serious a BibTeX diagnostic is.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BibtexSeverity {
+    /// BibTeX counted this as a warning.
+    Warning,
+    /// BibTeX counted this as an error.
+    Error,
+}
+
+/// The different kinds of problems that BibTeX reports.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BibtexDiagnosticKind {
+    /// A citation refers to a key that isn't in any database file.
+    MissingEntry,
+    /// A database entry uses an `@string` abbreviation that isn't defined.
+    UndefinedString,
+    /// A database entry has a type that the style file doesn't know about.
+    UnknownEntryType,
+    /// A database entry specifies the same field twice.
+    DuplicateField,
+    /// A database entry appears more than once.
+    RepeatedEntry,
+    /// A database entry is syntactically malformed.
+    MalformedEntry,
+    /// A `crossref` field refers to an entry that doesn't exist.
+    BadCrossReference,
+    /// The style file issued a warning, for example about an empty field.
+    StyleWarning,
+    /// Anything else, such as problems with the `.aux` or `.bst` files.
+    Other,
+}
+
+/// A warning or error reported by BibTeX.
+///
+/// These are recovered from the `.blg` log file that BibTeX writes, so they
+/// describe exactly what a human would see there, in structured form.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BibtexDiagnostic {
+    /// Whether this is a warning or an error.
+    pub severity: BibtexSeverity,
+    /// What kind of problem this is.
+    pub kind: BibtexDiagnosticKind,
+    /// The message, without any location information.
+    pub message: String,
+    /// The citation key or string name that the problem concerns, if known.
+    pub key: Option<String>,
+    /// The file in which the problem occurred, if known.
+    pub file: Option<String>,
+    /// The line number at which the problem occurred, if known.
+    pub line: Option<u32>,
+}
+
+impl fmt::Display for BibtexDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.file, self.line) {
+            (Some(file), Some(line)) => write!(f, "{}:{}: {}", file, line, self.message),
+            (Some(file), None) => write!(f, "{}: {}", file, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+/// Parse "--line N of file NAME" location text. Tectonic's BibTeX writes no
+/// space between "file" and the name, so accept it with or without one.
+fn parse_location(text: &str) -> Option<(u32, String)> {
+    let rest = text.trim_start_matches('-');
+    let rest = if rest.len() < text.len() && rest.starts_with("line ") {
+        &rest[5..]
+    } else {
+        return None;
+    };
+
+    let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+    let line = rest[..digits].parse::<u32>().ok()?;
+    let rest = rest[digits..].trim_start();
+
+    if !rest.starts_with("of file") {
+        return None;
+    }
+
+    let file = rest[7..].trim();
+
+    if file.is_empty() {
+        None
+    } else {
+        Some((line, file.to_owned()))
+    }
+}
+
+/// Extract the first double-quoted string in some text.
+fn quoted(text: &str) -> Option<String> {
+    let start = text.find('"')? + 1;
+    let len = text[start..].find('"')?;
+    Some(text[start..start + len].to_owned())
+}
+
+/// Classify the text following a `Warning--` prefix.
+fn classify_warning(message: &str) -> (BibtexDiagnosticKind, Option<String>) {
+    if message.starts_with("I didn't find a database entry for") {
+        (BibtexDiagnosticKind::MissingEntry, quoted(message))
+    } else if message.starts_with("string name") && message.ends_with("is undefined") {
+        (BibtexDiagnosticKind::UndefinedString, quoted(message))
+    } else if message.starts_with("entry type for") {
+        (BibtexDiagnosticKind::UnknownEntryType, quoted(message))
+    } else if let Some(rest) = message.strip_prefix("I'm ignoring ") {
+        let key = rest.find("'s extra").map(|n| rest[..n].to_owned());
+        (BibtexDiagnosticKind::DuplicateField, key)
+    } else {
+        // Style-file warnings conventionally end with "in <key>".
+        let key = message
+            .rfind(" in ")
+            .map(|n| message[n + 4..].trim().to_owned())
+            .filter(|k| !k.is_empty() && !k.contains(' '));
+        (BibtexDiagnosticKind::StyleWarning, key)
+    }
+}
+
+/// Recover structured diagnostics from the text of a BibTeX `.blg` file.
+pub fn parse_blg(text: &str) -> Vec<BibtexDiagnostic> {
+    let lines: Vec<&str> = text.lines().collect();
+    let mut diagnostics = Vec::new();
+    let mut prev_text: Option<&str> = None;
+    let mut i = 0;
+
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+
+        if let Some(message) = line.strip_prefix("Warning--") {
+            let message = message.trim_end();
+            let (kind, key) = classify_warning(message);
+            let mut diag = BibtexDiagnostic {
+                severity: BibtexSeverity::Warning,
+                kind,
+                message: message.to_owned(),
+                key,
+                file: None,
+                line: None,
+            };
+
+            // Many warnings are followed by a line giving their location,
+            // possibly with a "while executing" prefix for style-file
+            // problems.
+            if i < lines.len() {
+                let next = lines[i].trim_start_matches("while executing");
+
+                if let Some((n, file)) = parse_location(next) {
+                    diag.line = Some(n);
+                    diag.file = Some(file);
+                    i += 1;
+                }
+            }
+
+            diagnostics.push(diag);
+            prev_text = None;
+            continue;
+        }
+
+        if line.starts_with("A bad cross reference-") {
+            let key = quoted(line);
+            let mut message = "bad cross reference".to_owned();
+
+            if i < lines.len() && lines[i].starts_with("refers to entry") {
+                message = format!("entry \"{}\" {}", key.clone().unwrap_or_default(), lines[i]);
+                i += 1;
+            }
+
+            diagnostics.push(BibtexDiagnostic {
+                severity: BibtexSeverity::Error,
+                kind: BibtexDiagnosticKind::BadCrossReference,
+                message,
+                key,
+                file: None,
+                line: None,
+            });
+            prev_text = None;
+            continue;
+        }
+
+        if let Some(pos) = line.find("---line ") {
+            let mut message = line[..pos].trim().to_owned();
+
+            if message.is_empty() {
+                if let Some(p) = prev_text {
+                    message = p.trim().to_owned();
+                }
+            }
+
+            if let Some((n, file)) = parse_location(&line[pos..]) {
+                let kind = if !file.ends_with(".bib") {
+                    BibtexDiagnosticKind::Other
+                } else if message.starts_with("Repeated entry") {
+                    BibtexDiagnosticKind::RepeatedEntry
+                } else {
+                    BibtexDiagnosticKind::MalformedEntry
+                };
+
+                diagnostics.push(BibtexDiagnostic {
+                    severity: BibtexSeverity::Error,
+                    kind,
+                    message,
+                    key: None,
+                    file: Some(file),
+                    line: Some(n),
+                });
+
+                // Skip the echo of the offending input line.
+                while i < lines.len() && lines[i].starts_with(" : ") {
+                    i += 1;
+                }
+
+                prev_text = None;
+                continue;
+            }
+        }
+
+        if let Some(pos) = line.find("---while reading file ") {
+            diagnostics.push(BibtexDiagnostic {
+                severity: BibtexSeverity::Error,
+                kind: BibtexDiagnosticKind::Other,
+                message: line[..pos].trim().to_owned(),
+                key: None,
+                file: Some(line[pos + 22..].trim().to_owned()),
+                line: None,
+            });
+            prev_text = None;
+            continue;
+        }
+
+        prev_text = Some(line);
+    }
+
+    diagnostics
+}
+
 #[derive(Default)]
-pub struct BibtexEngine {}
+pub struct BibtexEngine {
+    diagnostics: Vec<BibtexDiagnostic>,
+}
 
 impl BibtexEngine {
     pub fn new() -> BibtexEngine {
         Default::default()
     }
 
+    /// Get the warnings and errors reported by the most recent call to
+    /// [`BibtexEngine::process`].
+    pub fn diagnostics(&self) -> &[BibtexDiagnostic] {
+        &self.diagnostics
+    }
+
     pub fn process(
         &mut self,
         io: &mut IoStack,
@@ -25,30 +270,53 @@ impl BibtexEngine {
         status: &mut dyn StatusBackend,
         aux: &str,
     ) -> Result<TexResult> {
-        let _guard = super::ENGINE_LOCK.lock().unwrap(); // until we're thread-safe ...
-
-        let caux = CString::new(aux)?;
-
-        let /*mut*/ state = ExecutionState::new(io, events, status);
-        let bridge = TectonicBridgeApi::new(&state);
-
-        unsafe {
-            match super::bibtex_simple_main(&*bridge, caux.as_ptr()) {
-                0 => Ok(TexResult::Spotless),
-                1 => Ok(TexResult::Warnings),
-                2 => Ok(TexResult::Errors),
-                3 => Err(ErrorKind::Msg("unspecified fatal bibtex error".into()).into()),
-                99 => {
-                    let ptr = super::tt_get_error_message();
-                    let msg = CStr::from_ptr(ptr).to_string_lossy().into_owned();
-                    Err(ErrorKind::Msg(msg).into())
+        let result = {
+            let _guard = super::ENGINE_LOCK.lock().unwrap(); // until we're thread-safe ...
+
+            let caux = CString::new(aux)?;
+
+            let /*mut*/ state = ExecutionState::new(io, events, status);
+            let bridge = TectonicBridgeApi::new(&state);
+
+            unsafe {
+                match super::bibtex_simple_main(&*bridge, caux.as_ptr()) {
+                    0 => Ok(TexResult::Spotless),
+                    1 => Ok(TexResult::Warnings),
+                    2 => Ok(TexResult::Errors),
+                    3 => Err(ErrorKind::Msg("unspecified fatal bibtex error".into()).into()),
+                    99 => {
+                        let ptr = super::tt_get_error_message();
+                        let msg = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+                        Err(ErrorKind::Msg(msg).into())
+                    }
+                    x => Err(ErrorKind::Msg(format!(
+                        "internal error: unexpected 'history' value {}",
+                        x
+                    ))
+                    .into()),
                 }
-                x => Err(ErrorKind::Msg(format!(
-                    "internal error: unexpected 'history' value {}",
-                    x
-                ))
-                .into()),
+            }
+        };
+
+        // Now go back and read the diagnostics out of the log file. We don't
+        // report this to the event backend since it's not something the
+        // engine read.
+
+        self.diagnostics.clear();
+
+        let blg = match aux.rfind('.') {
+            Some(n) => format!("{}.blg", &aux[..n]),
+            None => format!("{}.blg", aux),
+        };
+
+        if let OpenResult::Ok(mut ih) = io.input_open_name(OsStr::new(&blg), status) {
+            let mut text = Vec::new();
+
+            if ih.read_to_end(&mut text).is_ok() {
+                self.diagnostics = parse_blg(&String::from_utf8_lossy(&text));
             }
         }
+
+        result
     }
 }
@@ -0,0 +1,953 @@
+// src/engines/makeindex.rs -- a makeindex-compatible index processor
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! A makeindex-compatible index processor.
+//!
+//! This reads the raw index entries that LaTeX writes to an `.idx` file (or a
+//! glossary `.glo` file), sorts and merges them, and writes the result using
+//! the formatting rules of a makeindex `.ist` style file. The default style
+//! produces the usual `theindex` environment. Letter ordering, German
+//! sorting, and the `-p` page-start options of the original are not
+//! implemented.
+
+use std::cmp::Ordering;
+
+use super::tex::TexResult;
+use super::{read_input, write_output, IoEventBackend};
+use crate::errors::Result;
+use crate::io::IoStack;
+use crate::status::StatusBackend;
+use crate::{errmsg, tt_warning};
+
+#[derive(Default)]
+pub struct MakeindexEngine {
+    style: Option<String>,
+}
+
+impl MakeindexEngine {
+    pub fn new() -> MakeindexEngine {
+        Default::default()
+    }
+
+    /// Use the named `.ist` style file instead of the default style.
+    pub fn style(&mut self, style: &str) -> &mut Self {
+        self.style = Some(style.to_owned());
+        self
+    }
+
+    /// Process the index entries in `input`, writing the formatted index to
+    /// `output` and a transcript to `transcript`.
+    pub fn process(
+        &mut self,
+        io: &mut IoStack,
+        events: &mut dyn IoEventBackend,
+        status: &mut dyn StatusBackend,
+        input: &str,
+        output: &str,
+        transcript: &str,
+    ) -> Result<TexResult> {
+        let mut log = String::from("This is Tectonic's makeindex-compatible index processor.\n");
+        let mut style = Style::default();
+        let mut n_warnings = 0;
+        let mut n_errors = 0;
+
+        if let Some(ref name) = self.style {
+            let text = read_input(io, events, status, name)?
+                .ok_or_else(|| errmsg!("cannot find index style file \"{}\"", name))?;
+            let (n_set, unknown) = style.parse(&text);
+            log.push_str(&format!(
+                "Scanning style file {}...done ({} attributes redefined, {} ignored).\n",
+                name,
+                n_set,
+                unknown.len()
+            ));
+
+            for key in unknown {
+                log.push_str(&format!("** Unknown specifier {}.\n", key));
+                n_warnings += 1;
+            }
+        }
+
+        let text = read_input(io, events, status, input)?
+            .ok_or_else(|| errmsg!("cannot find index file \"{}\"", input))?;
+        let (mut entries, errors) = parse_entries(&text, &style);
+
+        log.push_str(&format!(
+            "Scanning input file {}...done ({} entries accepted, {} rejected).\n",
+            input,
+            entries.len(),
+            errors.len()
+        ));
+
+        for (line, msg) in &errors {
+            log.push_str(&format!(
+                "!! Input index error (file = {}, line = {}):\n   -- {}\n",
+                input, line, msg
+            ));
+            tt_warning!(status, "makeindex: {}:{}: {}", input, line, msg);
+            n_errors += 1;
+        }
+
+        entries.sort_by(compare_entries);
+        log.push_str("Sorting entries...done.\n");
+
+        let mut warnings = Vec::new();
+        let ind = generate(&entries, &style, &mut warnings);
+        n_warnings += warnings.len();
+
+        for w in &warnings {
+            log.push_str(&format!(
+                "## Warning (output file = {}):\n   -- {}\n",
+                output, w
+            ));
+            tt_warning!(status, "makeindex: {}", w);
+        }
+
+        log.push_str(&format!(
+            "Generating output file {}...done ({} lines written, {} warnings).\n",
+            output,
+            ind.matches('\n').count(),
+            n_warnings
+        ));
+        log.push_str(&format!("Output written in {}.\n", output));
+        log.push_str(&format!("Transcript written in {}.\n", transcript));
+
+        write_output(io, events, output, ind.as_bytes())?;
+        write_output(io, events, transcript, log.as_bytes())?;
+
+        Ok(if n_errors > 0 {
+            TexResult::Errors
+        } else if n_warnings > 0 {
+            TexResult::Warnings
+        } else {
+            TexResult::Spotless
+        })
+    }
+}
+
+/// The settings of a makeindex style file. Field names match the style
+/// file keywords.
+#[derive(Clone, Debug)]
+struct Style {
+    keyword: String,
+    arg_open: char,
+    arg_close: char,
+    range_open: char,
+    range_close: char,
+    level: char,
+    actual: char,
+    encap: char,
+    quote: char,
+    escape: char,
+    page_compositor: String,
+    preamble: String,
+    postamble: String,
+    group_skip: String,
+    headings_flag: i32,
+    heading_prefix: String,
+    heading_suffix: String,
+    symhead_positive: String,
+    symhead_negative: String,
+    numhead_positive: String,
+    numhead_negative: String,
+    item_0: String,
+    item_1: String,
+    item_2: String,
+    item_01: String,
+    item_x1: String,
+    item_12: String,
+    item_x2: String,
+    delim_0: String,
+    delim_1: String,
+    delim_2: String,
+    delim_n: String,
+    delim_r: String,
+    delim_t: String,
+    encap_prefix: String,
+    encap_infix: String,
+    encap_suffix: String,
+    line_max: usize,
+    indent_space: String,
+    indent_length: usize,
+    suffix_2p: String,
+    suffix_3p: String,
+    suffix_mp: String,
+    page_precedence: String,
+}
+
+impl Default for Style {
+    fn default() -> Style {
+        Style {
+            keyword: "\\indexentry".to_owned(),
+            arg_open: '{',
+            arg_close: '}',
+            range_open: '(',
+            range_close: ')',
+            level: '!',
+            actual: '@',
+            encap: '|',
+            quote: '"',
+            escape: '\\',
+            page_compositor: "-".to_owned(),
+            preamble: "\\begin{theindex}\n".to_owned(),
+            postamble: "\n\n\\end{theindex}\n".to_owned(),
+            group_skip: "\n\n  \\indexspace\n".to_owned(),
+            headings_flag: 0,
+            heading_prefix: String::new(),
+            heading_suffix: String::new(),
+            symhead_positive: "Symbols".to_owned(),
+            symhead_negative: "symbols".to_owned(),
+            numhead_positive: "Numbers".to_owned(),
+            numhead_negative: "numbers".to_owned(),
+            item_0: "\n  \\item ".to_owned(),
+            item_1: "\n    \\subitem ".to_owned(),
+            item_2: "\n      \\subsubitem ".to_owned(),
+            item_01: "\n    \\subitem ".to_owned(),
+            item_x1: "\n    \\subitem ".to_owned(),
+            item_12: "\n      \\subsubitem ".to_owned(),
+            item_x2: "\n      \\subsubitem ".to_owned(),
+            delim_0: ", ".to_owned(),
+            delim_1: ", ".to_owned(),
+            delim_2: ", ".to_owned(),
+            delim_n: ", ".to_owned(),
+            delim_r: "--".to_owned(),
+            delim_t: String::new(),
+            encap_prefix: "\\".to_owned(),
+            encap_infix: "{".to_owned(),
+            encap_suffix: "}".to_owned(),
+            line_max: 72,
+            indent_space: "\t\t".to_owned(),
+            indent_length: 16,
+            suffix_2p: String::new(),
+            suffix_3p: String::new(),
+            suffix_mp: String::new(),
+            page_precedence: "rnaRA".to_owned(),
+        }
+    }
+}
+
+/// A value in a style file.
+enum StyleValue {
+    Str(String),
+    Char(char),
+    Int(i64),
+}
+
+impl Style {
+    /// Apply the settings in a style file. Returns the number of settings
+    /// applied and the names of any that weren't recognized.
+    fn parse(&mut self, text: &str) -> (usize, Vec<String>) {
+        let mut chars = text.chars().peekable();
+        let mut n_set = 0;
+        let mut unknown = Vec::new();
+
+        loop {
+            // Skip whitespace and comments.
+            while let Some(&c) = chars.peek() {
+                if c == '%' {
+                    for c in chars.by_ref() {
+                        if c == '\n' {
+                            break;
+                        }
+                    }
+                } else if c.is_whitespace() {
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+
+            let mut key = String::new();
+
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() {
+                    break;
+                }
+                key.push(c);
+                chars.next();
+            }
+
+            if key.is_empty() {
+                break;
+            }
+
+            while let Some(&c) = chars.peek() {
+                if c == '\n' || !c.is_whitespace() {
+                    break;
+                }
+                chars.next();
+            }
+
+            let value = match chars.peek() {
+                Some('"') | Some('\'') => {
+                    let delim = chars.next().unwrap();
+                    let mut s = String::new();
+
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '\\' => match chars.next() {
+                                Some('n') => s.push('\n'),
+                                Some('t') => s.push('\t'),
+                                Some(c) => s.push(c),
+                                None => {}
+                            },
+                            _ if c == delim => break,
+                            _ => s.push(c),
+                        }
+                    }
+
+                    if delim == '"' {
+                        StyleValue::Str(s)
+                    } else {
+                        StyleValue::Char(s.chars().next().unwrap_or(' '))
+                    }
+                }
+                Some(c) if c.is_ascii_digit() || *c == '-' => {
+                    let mut s = String::new();
+
+                    while let Some(&c) = chars.peek() {
+                        if !(c.is_ascii_digit() || c == '-') {
+                            break;
+                        }
+                        s.push(c);
+                        chars.next();
+                    }
+
+                    StyleValue::Int(s.parse().unwrap_or(0))
+                }
+                _ => {
+                    unknown.push(key);
+                    continue;
+                }
+            };
+
+            if self.set(&key, value) {
+                n_set += 1;
+            } else {
+                unknown.push(key);
+            }
+        }
+
+        (n_set, unknown)
+    }
+
+    fn set(&mut self, key: &str, value: StyleValue) -> bool {
+        match value {
+            StyleValue::Str(s) => {
+                let dest = match key {
+                    "keyword" => &mut self.keyword,
+                    "page_compositor" => &mut self.page_compositor,
+                    "preamble" => &mut self.preamble,
+                    "postamble" => &mut self.postamble,
+                    "group_skip" => &mut self.group_skip,
+                    "heading_prefix" | "lethead_prefix" => &mut self.heading_prefix,
+                    "heading_suffix" | "lethead_suffix" => &mut self.heading_suffix,
+                    "symhead_positive" => &mut self.symhead_positive,
+                    "symhead_negative" => &mut self.symhead_negative,
+                    "numhead_positive" => &mut self.numhead_positive,
+                    "numhead_negative" => &mut self.numhead_negative,
+                    "item_0" => &mut self.item_0,
+                    "item_1" => &mut self.item_1,
+                    "item_2" => &mut self.item_2,
+                    "item_01" => &mut self.item_01,
+                    "item_x1" => &mut self.item_x1,
+                    "item_12" => &mut self.item_12,
+                    "item_x2" => &mut self.item_x2,
+                    "delim_0" => &mut self.delim_0,
+                    "delim_1" => &mut self.delim_1,
+                    "delim_2" => &mut self.delim_2,
+                    "delim_n" => &mut self.delim_n,
+                    "delim_r" => &mut self.delim_r,
+                    "delim_t" => &mut self.delim_t,
+                    "encap_prefix" => &mut self.encap_prefix,
+                    "encap_infix" => &mut self.encap_infix,
+                    "encap_suffix" => &mut self.encap_suffix,
+                    "indent_space" => &mut self.indent_space,
+                    "suffix_2p" => &mut self.suffix_2p,
+                    "suffix_3p" => &mut self.suffix_3p,
+                    "suffix_mp" => &mut self.suffix_mp,
+                    "page_precedence" => &mut self.page_precedence,
+                    _ => return false,
+                };
+                *dest = s;
+            }
+            StyleValue::Char(c) => {
+                let dest = match key {
+                    "arg_open" => &mut self.arg_open,
+                    "arg_close" => &mut self.arg_close,
+                    "range_open" => &mut self.range_open,
+                    "range_close" => &mut self.range_close,
+                    "level" => &mut self.level,
+                    "actual" => &mut self.actual,
+                    "encap" => &mut self.encap,
+                    "quote" => &mut self.quote,
+                    "escape" => &mut self.escape,
+                    _ => return false,
+                };
+                *dest = c;
+            }
+            StyleValue::Int(n) => match key {
+                "headings_flag" | "lethead_flag" => self.headings_flag = n as i32,
+                "line_max" => self.line_max = n.max(1) as usize,
+                "indent_length" => self.indent_length = n.max(0) as usize,
+                _ => return false,
+            },
+        }
+
+        true
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum RangeMark {
+    None,
+    Open,
+    Close,
+}
+
+#[derive(Clone, Debug)]
+struct IndexEntry {
+    /// The sort key of each level.
+    keys: Vec<String>,
+    /// The text to print for each level.
+    texts: Vec<String>,
+    page: String,
+    page_key: Vec<(usize, i64)>,
+    encap: String,
+    range: RangeMark,
+    order: usize,
+}
+
+fn roman_value(s: &str) -> Option<i64> {
+    let mut total = 0;
+    let mut prev = 0;
+
+    for c in s.chars().rev() {
+        let v = match c.to_ascii_lowercase() {
+            'i' => 1,
+            'v' => 5,
+            'x' => 10,
+            'l' => 50,
+            'c' => 100,
+            'd' => 500,
+            'm' => 1000,
+            _ => return None,
+        };
+
+        if v < prev {
+            total -= v;
+        } else {
+            total += v;
+            prev = v;
+        }
+    }
+
+    if total > 0 {
+        Some(total)
+    } else {
+        None
+    }
+}
+
+/// Classify one component of a page number, returning its rank in the page
+/// precedence and its numeric value.
+fn page_part_key(s: &str, precedence: &str) -> Option<(usize, i64)> {
+    let rank = |kind: char| precedence.find(kind).unwrap_or(precedence.len());
+
+    if !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) {
+        return s.parse().ok().map(|v| (rank('n'), v));
+    }
+
+    if s.chars().all(|c| c.is_ascii_lowercase()) {
+        if let Some(v) = roman_value(s) {
+            return Some((rank('r'), v));
+        }
+    }
+
+    if s.chars().all(|c| c.is_ascii_uppercase()) {
+        if let Some(v) = roman_value(s) {
+            return Some((rank('R'), v));
+        }
+    }
+
+    let mut chars = s.chars();
+
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_lowercase() => Some((rank('a'), (c as u8 - b'a') as i64 + 1)),
+        (Some(c), None) if c.is_ascii_uppercase() => Some((rank('A'), (c as u8 - b'A') as i64 + 1)),
+        _ => None,
+    }
+}
+
+fn page_key(page: &str, style: &Style) -> Option<Vec<(usize, i64)>> {
+    page.split(style.page_compositor.as_str())
+        .map(|p| page_part_key(p, &style.page_precedence))
+        .collect()
+}
+
+/// Read an argument delimited by `arg_open` and `arg_close`, starting just
+/// after the opening delimiter. Returns the contents and the remaining text.
+fn read_arg<'a>(text: &'a str, style: &Style) -> Option<(&'a str, &'a str)> {
+    let mut depth = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices() {
+        if quoted {
+            quoted = false;
+        } else if escaped {
+            escaped = false;
+        } else if c == style.escape {
+            escaped = true;
+        } else if c == style.quote {
+            quoted = true;
+        } else if c == style.arg_open {
+            depth += 1;
+        } else if c == style.arg_close {
+            if depth == 0 {
+                return Some((&text[..i], &text[i + c.len_utf8()..]));
+            }
+            depth -= 1;
+        }
+    }
+
+    None
+}
+
+/// Split the key argument of an index entry into its levels, sort keys,
+/// display texts, and encapsulator.
+fn parse_key(
+    arg: &str,
+    style: &Style,
+) -> std::result::Result<(Vec<String>, Vec<String>, String, RangeMark), String> {
+    let mut levels = vec![(String::new(), None::<String>)];
+    let mut encap: Option<String> = None;
+    let mut chars = arg.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let in_encap = encap.is_some();
+        let dest = match encap {
+            Some(ref mut e) => e,
+            None => {
+                let cur = levels.last_mut().unwrap();
+                match cur.1 {
+                    Some(ref mut t) => t,
+                    None => &mut cur.0,
+                }
+            }
+        };
+
+        if c == style.quote {
+            // The next character is literal, unless this quote is itself
+            // escaped.
+            if dest.ends_with(style.escape) {
+                dest.push(c);
+            } else if let Some(n) = chars.next() {
+                dest.push(n);
+            }
+        } else if in_encap {
+            dest.push(c);
+        } else if c == style.level {
+            if levels.len() == 3 {
+                return Err("too many sub-levels in index entry".to_owned());
+            }
+            levels.push((String::new(), None));
+        } else if c == style.actual {
+            let cur = levels.last_mut().unwrap();
+            if cur.1.is_some() {
+                return Err(format!("extra '{}' in index entry", style.actual));
+            }
+            cur.1 = Some(String::new());
+        } else if c == style.encap {
+            encap = Some(String::new());
+        } else {
+            dest.push(c);
+        }
+    }
+
+    let mut keys = Vec::new();
+    let mut texts = Vec::new();
+
+    for (key, text) in levels {
+        let key = key.trim().to_owned();
+
+        if key.is_empty() {
+            return Err("empty index key".to_owned());
+        }
+
+        texts.push(
+            text.map(|t| t.trim().to_owned())
+                .unwrap_or_else(|| key.clone()),
+        );
+        keys.push(key);
+    }
+
+    let mut encap = encap.unwrap_or_default();
+    let mut range = RangeMark::None;
+
+    if encap.starts_with(style.range_open) {
+        range = RangeMark::Open;
+        encap.remove(0);
+    } else if encap.starts_with(style.range_close) {
+        range = RangeMark::Close;
+        encap.remove(0);
+    }
+
+    Ok((keys, texts, encap, range))
+}
+
+/// Parse the entries of an `.idx` file. Returns the valid entries and a
+/// list of (line number, message) pairs for rejected ones.
+fn parse_entries(text: &str, style: &Style) -> (Vec<IndexEntry>, Vec<(usize, String)>) {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    let mut rest = text;
+
+    while let Some(n) = rest.find(&style.keyword) {
+        let line = text[..text.len() - rest.len() + n].matches('\n').count() + 1;
+        rest = &rest[n + style.keyword.len()..];
+
+        let mut parse_one = || {
+            let r = rest.trim_start();
+            let r = r
+                .strip_prefix(style.arg_open)
+                .ok_or_else(|| "missing key argument".to_owned())?;
+            let (key, r) = read_arg(r, style).ok_or_else(|| "unterminated key".to_owned())?;
+            let r = r
+                .trim_start()
+                .strip_prefix(style.arg_open)
+                .ok_or_else(|| "missing page argument".to_owned())?;
+            let (page, r) = read_arg(r, style).ok_or_else(|| "unterminated page".to_owned())?;
+            rest = r;
+
+            let page = page.trim();
+            let pk =
+                page_key(page, style).ok_or_else(|| format!("Illegal page number {}.", page))?;
+            let (keys, texts, encap, range) = parse_key(key, style)?;
+
+            Ok(IndexEntry {
+                keys,
+                texts,
+                page: page.to_owned(),
+                page_key: pk,
+                encap,
+                range,
+                order: entries.len(),
+            })
+        };
+
+        match parse_one() {
+            Ok(e) => entries.push(e),
+            Err(msg) => errors.push((line, msg)),
+        }
+    }
+
+    (entries, errors)
+}
+
+/// The letter group of a level-0 key: 0 for symbols, 1 for numbers, or 2
+/// for letters.
+fn key_group(key: &str) -> (u8, char) {
+    match key.chars().find(|c| *c != '\\' && *c != '{') {
+        Some(c) if c.is_alphabetic() => (2, c.to_uppercase().next().unwrap_or(c)),
+        Some(c) if c.is_ascii_digit() => (1, '0'),
+        _ => (0, ' '),
+    }
+}
+
+fn compare_keys(a: &str, b: &str) -> Ordering {
+    let (ga, _) = key_group(a);
+    let (gb, _) = key_group(b);
+
+    ga.cmp(&gb)
+        .then_with(|| match (a.parse::<i64>(), b.parse::<i64>()) {
+            (Ok(x), Ok(y)) => x.cmp(&y),
+            _ => Ordering::Equal,
+        })
+        .then_with(|| a.to_lowercase().cmp(&b.to_lowercase()))
+        .then_with(|| a.cmp(b))
+}
+
+fn compare_paths(a: &IndexEntry, b: &IndexEntry) -> Ordering {
+    for i in 0..a.keys.len().min(b.keys.len()) {
+        let ord = compare_keys(&a.keys[i], &b.keys[i]).then_with(|| a.texts[i].cmp(&b.texts[i]));
+
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+
+    a.keys.len().cmp(&b.keys.len())
+}
+
+fn compare_entries(a: &IndexEntry, b: &IndexEntry) -> Ordering {
+    compare_paths(a, b)
+        .then_with(|| a.page_key.cmp(&b.page_key))
+        .then_with(|| a.order.cmp(&b.order))
+}
+
+/// Accumulates output text, wrapping long lines of page numbers.
+struct Output<'a> {
+    text: String,
+    col: usize,
+    style: &'a Style,
+}
+
+impl<'a> Output<'a> {
+    fn put(&mut self, s: &str) {
+        self.text.push_str(s);
+
+        match s.rfind('\n') {
+            Some(n) => self.col = s[n + 1..].chars().count(),
+            None => self.col += s.chars().count(),
+        }
+    }
+
+    fn put_wrapped(&mut self, s: &str) {
+        if self.col + s.chars().count() > self.style.line_max {
+            self.text.push('\n');
+            self.text.push_str(&self.style.indent_space);
+            self.col = self.style.indent_length;
+        }
+
+        self.put(s);
+    }
+}
+
+fn encapsulate(text: &str, encap: &str, style: &Style) -> String {
+    if encap.is_empty() {
+        text.to_owned()
+    } else {
+        format!(
+            "{}{}{}{}{}",
+            style.encap_prefix, encap, style.encap_infix, text, style.encap_suffix
+        )
+    }
+}
+
+/// Turn the page references of one index entry into formatted page items.
+fn page_items(refs: &[&IndexEntry], style: &Style, warnings: &mut Vec<String>) -> Vec<String> {
+    // First resolve explicit ranges and drop duplicates.
+    let mut singles: Vec<(&IndexEntry, Option<&IndexEntry>)> = Vec::new();
+    let mut open: Option<&IndexEntry> = None;
+
+    for r in refs {
+        match r.range {
+            RangeMark::Open => {
+                if open.is_some() {
+                    warnings.push(format!(
+                        "Extra range opening operator {} for \"{}\".",
+                        style.range_open,
+                        r.texts.join(" ")
+                    ));
+                } else {
+                    open = Some(r);
+                }
+            }
+            RangeMark::Close => match open.take() {
+                Some(start) => singles.push((start, Some(r))),
+                None => warnings.push(format!(
+                    "Unmatched range closing operator {} for \"{}\".",
+                    style.range_close,
+                    r.texts.join(" ")
+                )),
+            },
+            RangeMark::None => {
+                // Pages inside an open range are absorbed by it.
+                if let Some(start) = open {
+                    if start.encap == r.encap {
+                        continue;
+                    }
+                }
+
+                let dup = singles
+                    .iter()
+                    .any(|(s, e)| e.is_none() && s.page == r.page && s.encap == r.encap);
+
+                if !dup {
+                    singles.push((r, None));
+                }
+            }
+        }
+    }
+
+    if let Some(start) = open {
+        warnings.push(format!(
+            "Unmatched range opening operator {} for \"{}\".",
+            style.range_open,
+            start.texts.join(" ")
+        ));
+        singles.push((start, None));
+    }
+
+    // Now merge runs of consecutive pages into implicit ranges.
+    let consecutive = |a: &IndexEntry, b: &IndexEntry| {
+        let n = a.page_key.len();
+
+        n == b.page_key.len()
+            && a.encap == b.encap
+            && a.page_key[..n - 1] == b.page_key[..n - 1]
+            && a.page_key[n - 1].0 == b.page_key[n - 1].0
+            && a.page_key[n - 1].1 + 1 == b.page_key[n - 1].1
+    };
+
+    let mut items = Vec::new();
+    let mut i = 0;
+
+    while i < singles.len() {
+        let (start, end) = singles[i];
+
+        if let Some(end) = end {
+            let text = format!("{}{}{}", start.page, style.delim_r, end.page);
+            items.push(encapsulate(&text, &start.encap, style));
+            i += 1;
+            continue;
+        }
+
+        let mut j = i;
+
+        while j + 1 < singles.len()
+            && singles[j + 1].1.is_none()
+            && consecutive(singles[j].0, singles[j + 1].0)
+        {
+            j += 1;
+        }
+
+        let count = j - i + 1;
+        let last = singles[j].0;
+
+        let text = if count == 2 && !style.suffix_2p.is_empty() {
+            Some(format!("{}{}", start.page, style.suffix_2p))
+        } else if count == 3 && !style.suffix_3p.is_empty() {
+            Some(format!("{}{}", start.page, style.suffix_3p))
+        } else if count >= 3 && !style.suffix_mp.is_empty() {
+            Some(format!("{}{}", start.page, style.suffix_mp))
+        } else if count >= 3 {
+            Some(format!("{}{}{}", start.page, style.delim_r, last.page))
+        } else {
+            None
+        };
+
+        match text {
+            Some(t) => {
+                items.push(encapsulate(&t, &start.encap, style));
+                i = j + 1;
+            }
+            None => {
+                items.push(encapsulate(&start.page, &start.encap, style));
+                i += 1;
+            }
+        }
+    }
+
+    items
+}
+
+/// Generate the formatted index from sorted entries.
+fn generate(entries: &[IndexEntry], style: &Style, warnings: &mut Vec<String>) -> String {
+    let mut out = Output {
+        text: String::new(),
+        col: 0,
+        style,
+    };
+
+    out.put(&style.preamble);
+
+    let mut prev: Option<&IndexEntry> = None;
+    let mut prev_group = None;
+    let mut prev_level = 0;
+    let mut prev_had_pages = false;
+    let mut i = 0;
+
+    while i < entries.len() {
+        // Gather all references to this entry.
+        let mut j = i + 1;
+
+        while j < entries.len() && compare_paths(&entries[i], &entries[j]) == Ordering::Equal {
+            j += 1;
+        }
+
+        let e = &entries[i];
+        let refs: Vec<&IndexEntry> = entries[i..j].iter().collect();
+        i = j;
+
+        let group = key_group(&e.keys[0]);
+
+        if prev_group != Some(group) {
+            if prev_group.is_some() {
+                out.put(&style.group_skip);
+            }
+
+            if style.headings_flag != 0 {
+                let heading = match group.0 {
+                    0 if style.headings_flag > 0 => style.symhead_positive.clone(),
+                    0 => style.symhead_negative.clone(),
+                    1 if style.headings_flag > 0 => style.numhead_positive.clone(),
+                    1 => style.numhead_negative.clone(),
+                    _ if style.headings_flag > 0 => group.1.to_string(),
+                    _ => group.1.to_lowercase().to_string(),
+                };
+                out.put(&style.heading_prefix);
+                out.put(&heading);
+                out.put(&style.heading_suffix);
+            }
+
+            prev_group = Some(group);
+            prev = None;
+        }
+
+        // How many leading levels are shared with the previous entry?
+        let common = match prev {
+            Some(p) => (0..e.keys.len().min(p.keys.len()))
+                .take_while(|l| p.keys[*l] == e.keys[*l] && p.texts[*l] == e.texts[*l])
+                .count()
+                .min(e.keys.len() - 1),
+            None => 0,
+        };
+
+        for level in common..e.keys.len() {
+            let first_child = prev.is_some() && prev_level + 1 == level;
+
+            let item = match level {
+                0 => &style.item_0,
+                1 if first_child && prev_had_pages => &style.item_01,
+                1 if first_child => &style.item_x1,
+                1 => &style.item_1,
+                _ if first_child && prev_had_pages => &style.item_12,
+                _ if first_child => &style.item_x2,
+                _ => &style.item_2,
+            };
+            out.put(item);
+            out.put(&e.texts[level]);
+
+            let has_pages = level == e.keys.len() - 1;
+
+            if has_pages {
+                let items = page_items(&refs, style, warnings);
+
+                out.put(match level {
+                    0 => &style.delim_0,
+                    1 => &style.delim_1,
+                    _ => &style.delim_2,
+                });
+
+                for (k, item) in items.iter().enumerate() {
+                    if k > 0 {
+                        out.put(&style.delim_n);
+                    }
+                    out.put_wrapped(item);
+                }
+
+                out.put(&style.delim_t);
+            }
+
+            prev = Some(e);
+            prev_level = level;
+            prev_had_pages = has_pages;
+        }
+    }
+
+    out.put(&style.postamble);
+    out.text
+}
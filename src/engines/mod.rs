@@ -18,6 +18,7 @@ use lazy_static::lazy_static;
 use libc;
 use md5::{Digest, Md5};
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::ffi::{CStr, OsStr, OsString};
 use std::io::{Read, SeekFrom, Write};
 use std::path::Path;
@@ -26,18 +27,24 @@ use std::{io, ptr, slice};
 
 use crate::digest::DigestData;
 use crate::errors::{Error, ErrorKind, Result};
-use crate::io::{InputFeatures, InputHandle, InputOrigin, IoProvider, OpenResult, OutputHandle};
+use crate::io::{
+    woff, InputFeatures, InputHandle, InputOrigin, IoProvider, IoStack, OpenResult, OutputHandle,
+};
 use crate::status::StatusBackend;
-use crate::{tt_error, tt_warning};
+use crate::{errmsg, tt_error, tt_warning};
 
 // Public sub-modules and reexports.
 
+pub mod biber;
 pub mod bibtex;
+pub mod makeindex;
 pub mod spx2html;
 pub mod tex;
 pub mod xdvipdfmx;
 
+pub use self::biber::BiberEngine;
 pub use self::bibtex::BibtexEngine;
+pub use self::makeindex::MakeindexEngine;
 pub use self::spx2html::Spx2HtmlEngine;
 pub use self::tex::TexEngine;
 pub use self::xdvipdfmx::XdvipdfmxEngine;
@@ -106,6 +113,56 @@ impl NoopIoEventBackend {
 
 impl IoEventBackend for NoopIoEventBackend {}
 
+// Helpers for the engines implemented in Rust, which read and write whole
+// files through the I/O stack.
+
+/// Read an input file, reporting it to the event backend. Returns `None` if
+/// the file doesn't exist.
+fn read_input(
+    io: &mut IoStack,
+    events: &mut dyn IoEventBackend,
+    status: &mut dyn StatusBackend,
+    name: &str,
+) -> Result<Option<String>> {
+    let mut ih = match io.input_open_name(OsStr::new(name), status) {
+        OpenResult::Ok(ih) => ih,
+        OpenResult::NotAvailable => {
+            events.input_not_available(OsStr::new(name));
+            return Ok(None);
+        }
+        OpenResult::Err(e) => return Err(e),
+    };
+
+    events.input_opened(ih.name(), ih.origin());
+    let mut buf = Vec::new();
+    ih.read_to_end(&mut buf)?;
+    let (name, digest) = ih.into_name_digest();
+    events.input_closed(name, digest);
+    Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
+}
+
+/// Write an output file, reporting it to the event backend.
+fn write_output(
+    io: &mut IoStack,
+    events: &mut dyn IoEventBackend,
+    name: &str,
+    data: &[u8],
+) -> Result<()> {
+    let mut oh = match io.output_open_name(OsStr::new(name)) {
+        OpenResult::Ok(h) => h,
+        OpenResult::NotAvailable => {
+            return Err(errmsg!("no way to write output file \"{}\"", name));
+        }
+        OpenResult::Err(e) => return Err(e),
+    };
+
+    events.output_opened(oh.name());
+    oh.write_all(data)?;
+    let (name, digest) = oh.into_name_digest();
+    events.output_closed(name, digest);
+    Ok(())
+}
+
 // Now, the private interfaces for executing various engines implemented in C/C++.
 
 // The C/C++ engines currently maintain global state, which means that we can
@@ -131,6 +188,10 @@ struct ExecutionState<'a, I: 'a + IoProvider> {
     input_handles: Vec<Box<InputHandle>>,
     #[allow(clippy::vec_box)]
     output_handles: Vec<Box<OutputHandle>>,
+    /// The name that each input was found under, which may have an extension
+    /// that the engine did not ask for, and where it came from; by the name
+    /// that the engine asked for.
+    opened_inputs: HashMap<OsString, (OsString, InputOrigin)>,
 }
 
 impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
@@ -145,6 +206,7 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
             status,
             output_handles: Vec::new(),
             input_handles: Vec::new(),
+            opened_inputs: HashMap::new(),
         }
     }
 
@@ -204,6 +266,17 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
     ) -> OpenResult<InputHandle> {
         let base = self.input_open_name_format(name, format);
 
+        // Web fonts are decoded as they are opened, so that the engines only
+        // ever see plain TrueType and OpenType data.
+        let base = match (format, base) {
+            (FileFormat::OpenType, OpenResult::Ok(ih))
+            | (FileFormat::TrueType, OpenResult::Ok(ih)) => match woff::decode_input(ih) {
+                Ok(ih) => OpenResult::Ok(ih),
+                Err(e) => OpenResult::Err(e),
+            },
+            (_, base) => base,
+        };
+
         if !is_gz {
             return base;
         }
@@ -383,6 +456,8 @@ impl<'a, I: 'a + IoProvider> ExecutionState<'a, I> {
 
         // the file name may have had an extension added, so we use ih.name() here:
         self.events.input_opened(ih.name(), ih.origin());
+        self.opened_inputs
+            .insert(name.to_owned(), (ih.name().to_owned(), ih.origin()));
         self.input_handles.push(Box::new(ih));
         &*self.input_handles[self.input_handles.len() - 1]
     }
@@ -500,8 +575,11 @@ impl std::ops::Deref for TectonicBridgeApi {
 }
 
 use tectonic_engine::{
-    bibtex_simple_main, dvipdfmx_simple_main, tex_simple_main, tt_get_error_message,
-    tt_xetex_set_int_variable,
+    bibtex_simple_main, dvipdfmx_simple_main, paperinfo, pdf_font_take_report,
+    pdf_fontmap_add_request, pdf_fontmap_clear_requests, tex_simple_main, tt_get_error_message,
+    tt_xetex_add_font_file, tt_xetex_add_synctex_source_name, tt_xetex_clear_font_files,
+    tt_xetex_clear_synctex_source_names, tt_xetex_set_int_variable, tt_xetex_take_font_resolutions,
+    tt_xetex_take_missing_chars,
 };
 
 // Entry points for the C/C++ API functions.
@@ -871,7 +949,7 @@ fn format_to_extension(format: FileFormat) -> Vec<&'static str> {
         FileFormat::FontMap => vec!["map"],
         FileFormat::MiscFonts => vec!["miscfonts"], /* XXX: no kpathsea suffixes */
         FileFormat::Ofm => vec!["ofm"],
-        FileFormat::OpenType => vec!["otf", "OTF"],
+        FileFormat::OpenType => vec!["otf", "OTF", "woff", "woff2"],
         FileFormat::Ovf => vec!["ovf", "vf"],
         FileFormat::Pict => vec!["pdf", "jpg", "eps", "epsi"], /* XXX: also .eps, .epsi, ... */
         FileFormat::Pk => vec!["pk"],
@@ -880,7 +958,7 @@ fn format_to_extension(format: FileFormat) -> Vec<&'static str> {
         FileFormat::Tex => vec!["tex", "sty", "cls", "fd", "aux", "bbl", "def", "clo", "ldf"],
         FileFormat::TexPsHeader => vec!["pro"],
         FileFormat::TFM => vec!["tfm"],
-        FileFormat::TrueType => vec!["ttf", "ttc", "TTF", "TTC", "dfont"],
+        FileFormat::TrueType => vec!["ttf", "ttc", "TTF", "TTC", "dfont", "woff", "woff2"],
         FileFormat::Type1 => vec!["pfa", "pfb"],
         FileFormat::Vf => vec!["vf"],
     }
@@ -27,6 +27,40 @@ impl DefinitelySame for TexResult {
     }
 }
 
+/// Where a font that was requested by name came from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FontBackend {
+    /// A font file found through the I/O stack: the bundle or the project's
+    /// own files.
+    Io,
+    /// A font installed on the host system.
+    Host,
+}
+
+/// How the engine satisfied a request for a font by name, as in
+/// `\font\x="TeX Gyre Termes"`. Fonts requested by file name are not
+/// included, since those always come through the I/O stack.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FontResolution {
+    /// The name as given in the document.
+    pub name: String,
+    /// Which backend found the font.
+    pub backend: FontBackend,
+    /// The file that provided the font, as the backend names it.
+    pub file: String,
+}
+
+/// A character that the engine could not find in the font it was set in,
+/// as reported by a "Missing character" warning.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MissingChar {
+    /// The font, as TeX names it.
+    pub font: String,
+    /// The Unicode scalar value of the character (or, for TFM fonts, its
+    /// character code).
+    pub code: u32,
+}
+
 #[derive(Debug)]
 pub struct TexEngine {
     // One day, the engine will hold its own state. For the time being,
@@ -35,6 +69,11 @@ pub struct TexEngine {
     initex_mode: bool,
     synctex_enabled: bool,
     semantic_pagination_enabled: bool,
+    synctex_source_names: Vec<(String, String)>,
+    host_fonts_allowed: bool,
+    font_files: Vec<(String, bool)>,
+    font_resolutions: Vec<FontResolution>,
+    missing_chars: Vec<MissingChar>,
 }
 
 impl Default for TexEngine {
@@ -44,6 +83,11 @@ impl Default for TexEngine {
             initex_mode: false,
             synctex_enabled: false,
             semantic_pagination_enabled: false,
+            synctex_source_names: Vec::new(),
+            host_fonts_allowed: true,
+            font_files: Vec::new(),
+            font_resolutions: Vec::new(),
+            missing_chars: Vec::new(),
         }
     }
 }
@@ -72,6 +116,20 @@ impl TexEngine {
         self
     }
 
+    /// Record a different name for an input file in the SyncTeX data.
+    ///
+    /// SyncTeX records input files using the names that TeX used to open
+    /// them. Some inputs don't exist anywhere under those names — most
+    /// notably a primary input that comes from standard input or a memory
+    /// buffer — so this lets the caller say which path an editor should open
+    /// instead. A `tex_name` ending in `.tex` also matches the name without
+    /// the extension.
+    pub fn synctex_source_name(&mut self, tex_name: &str, real_name: &str) -> &mut Self {
+        self.synctex_source_names
+            .push((tex_name.to_owned(), real_name.to_owned()));
+        self
+    }
+
     /// Configure the engine to use “semantic pagination”.
     ///
     /// In this mode, the TeX page builder is not run, and top-level boxes are
@@ -87,6 +145,42 @@ impl TexEngine {
         self
     }
 
+    /// Configure whether fonts requested by name may be looked up among the
+    /// fonts installed on the host.
+    ///
+    /// Fonts found through the I/O stack (see [`TexEngine::font_file`]) are
+    /// always preferred. Disallowing host fonts makes the font lookup
+    /// hermetic, so that a document gets the same fonts on every machine.
+    /// Host fonts are allowed by default. On macOS, fonts in the I/O stack
+    /// can currently only be loaded by file name.
+    pub fn host_fonts(&mut self, allowed: bool) -> &mut Self {
+        self.host_fonts_allowed = allowed;
+        self
+    }
+
+    /// Register a font file that the engine can open through the I/O stack,
+    /// so that fonts can be found in it by their family, style, or full
+    /// names. `in_project` says whether the file is one of the project's own
+    /// files: those are all indexed as soon as a font is looked up by name,
+    /// while bundle files are only opened if their names look like the name
+    /// being searched for. Files registered first take precedence.
+    pub fn font_file(&mut self, name: &str, in_project: bool) -> &mut Self {
+        self.font_files.push((name.to_owned(), in_project));
+        self
+    }
+
+    /// Get how the fonts requested by name were found during the most recent
+    /// run of the engine.
+    pub fn font_resolutions(&self) -> &[FontResolution] {
+        &self.font_resolutions
+    }
+
+    /// Get the characters that were missing from their fonts during the most
+    /// recent run of the engine, each font and character listed once.
+    pub fn missing_chars(&self) -> &[MissingChar] {
+        &self.missing_chars
+    }
+
     // This function can't be generic across the IoProvider trait, for now,
     // since the global pointer that stashes the ExecutionState must have a
     // complete type.
@@ -130,6 +224,30 @@ impl TexEngine {
         }
 
         unsafe {
+            super::tt_xetex_clear_synctex_source_names();
+        }
+        for (tex_name, real_name) in &self.synctex_source_names {
+            let ctex = CString::new(tex_name.as_str())?;
+            let creal = CString::new(real_name.as_str())?;
+            unsafe {
+                super::tt_xetex_add_synctex_source_name(ctex.as_ptr(), creal.as_ptr());
+            }
+        }
+
+        let v = if self.host_fonts_allowed { 1 } else { 0 };
+        unsafe {
+            super::tt_xetex_set_int_variable(b"host_fonts_allowed\0".as_ptr() as _, v);
+            super::tt_xetex_clear_font_files();
+        }
+        for (name, in_project) in &self.font_files {
+            let cname = CString::new(name.as_str())?;
+            let v = if *in_project { 1 } else { 0 };
+            unsafe {
+                super::tt_xetex_add_font_file(cname.as_ptr(), v);
+            }
+        }
+
+        let result = unsafe {
             match super::tex_simple_main(&*bridge, cformat.as_ptr(), cinput.as_ptr()) {
                 0 => Ok(TexResult::Spotless),
                 1 => Ok(TexResult::Warnings),
@@ -145,6 +263,29 @@ impl TexEngine {
                 ))
                 .into()),
             }
-        }
+        };
+
+        self.font_resolutions = unsafe { super::tt_xetex_take_font_resolutions() }
+            .into_iter()
+            .map(|r| FontResolution {
+                name: r.name,
+                backend: if r.from_io {
+                    FontBackend::Io
+                } else {
+                    FontBackend::Host
+                },
+                file: r.file,
+            })
+            .collect();
+
+        self.missing_chars = unsafe { super::tt_xetex_take_missing_chars() }
+            .into_iter()
+            .map(|m| MissingChar {
+                font: m.font,
+                code: m.code,
+            })
+            .collect();
+
+        result
     }
 }
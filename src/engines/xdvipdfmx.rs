@@ -2,16 +2,355 @@
 // Copyright 2017 the Tectonic Project
 // Licensed under the MIT License.
 
-use std::ffi::{CStr, CString};
+use std::ffi::{CStr, CString, OsStr};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use tectonic_engine::{DvipdfmxOptions, FontReportType};
 
 use super::{ExecutionState, IoEventBackend, TectonicBridgeApi};
 use crate::errors::{ErrorKind, Result};
-use crate::io::IoStack;
+use crate::io::{InputOrigin, IoStack};
 use crate::status::StatusBackend;
 
+/// The PDF/A conformance levels that the PDF output can be made to meet.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PdfAConformance {
+    /// PDF/A-2b (ISO 19005-2, level B).
+    A2b,
+    /// PDF/A-3b (ISO 19005-3, level B), which also allows arbitrary
+    /// embedded files.
+    A3b,
+}
+
+impl PdfAConformance {
+    fn part(self) -> u32 {
+        match self {
+            PdfAConformance::A2b => 2,
+            PdfAConformance::A3b => 3,
+        }
+    }
+}
+
+impl FromStr for PdfAConformance {
+    type Err = &'static str;
+
+    fn from_str(a_str: &str) -> std::result::Result<Self, Self::Err> {
+        match a_str {
+            "2b" => Ok(PdfAConformance::A2b),
+            "3b" => Ok(PdfAConformance::A3b),
+            _ => Err("unsupported or unknown PDF/A conformance level"),
+        }
+    }
+}
+
+/// How the PDF output is encrypted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PdfEncryption {
+    /// The password that lifts all restrictions. If it is empty, a random
+    /// password is used, so that the permission flags cannot be bypassed.
+    pub owner_password: String,
+    /// The password needed to open the document; may be empty.
+    pub user_password: String,
+    /// What readers of the document are allowed to do, as a combination of
+    /// the `PERMIT_*` flags.
+    pub permissions: u32,
+    /// The length of the encryption key: RC4 is used below 128 bits (40 to
+    /// 120 bits in steps of 8), AES-128 (V4/R4) at 128 bits, and AES-256 at
+    /// 256 bits, with the PDF version bumped to 1.7.
+    pub key_bits: u32,
+}
+
+impl PdfEncryption {
+    pub const PERMIT_PRINT: u32 = 1 << 2;
+    pub const PERMIT_MODIFY: u32 = 1 << 3;
+    pub const PERMIT_COPY: u32 = 1 << 4;
+    pub const PERMIT_ANNOTATE: u32 = 1 << 5;
+    pub const PERMIT_FILL_FORMS: u32 = 1 << 8;
+    pub const PERMIT_EXTRACT_FOR_ACCESSIBILITY: u32 = 1 << 9;
+    pub const PERMIT_ASSEMBLE: u32 = 1 << 10;
+    pub const PERMIT_PRINT_HIGH_QUALITY: u32 = 1 << 11;
+
+    /// Whether encryption keys of this length are supported.
+    pub fn key_bits_supported(bits: u32) -> bool {
+        ((40..=128).contains(&bits) && bits % 8 == 0) || bits == 256
+    }
+}
+
+impl Default for PdfEncryption {
+    /// AES-256 encryption without passwords that allows printing, copying,
+    /// modifying and annotating; these are the permissions dvipdfmx grants
+    /// by default.
+    fn default() -> Self {
+        PdfEncryption {
+            owner_password: String::new(),
+            user_password: String::new(),
+            permissions: PdfEncryption::PERMIT_PRINT
+                | PdfEncryption::PERMIT_MODIFY
+                | PdfEncryption::PERMIT_COPY
+                | PdfEncryption::PERMIT_ANNOTATE,
+            key_bits: 256,
+        }
+    }
+}
+
+/// Parse a length with a TeX unit (`pt`, `bp`, `mm`, `cm`, `in`, `pc`, `dd`,
+/// `cc` or `sp`), such as "1in" or "25.4mm", into big points (1/72 inch). A
+/// bare number is taken to be in big points.
+pub fn parse_length(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| c.is_ascii_alphabetic())
+        .unwrap_or(text.len());
+    let value = f64::from_str(text[..split].trim()).ok()?;
+    let unit = match &text[split..] {
+        "" | "bp" => 1.,
+        "pt" => 72. / 72.27,
+        "in" => 72.,
+        "cm" => 72. / 2.54,
+        "mm" => 72. / 25.4,
+        "pc" => 12. * 72. / 72.27,
+        "dd" => 1238. / 1157. * 72. / 72.27,
+        "cc" => 12. * 1238. / 1157. * 72. / 72.27,
+        "sp" => 72. / (72.27 * 65536.),
+        _ => return None,
+    };
+    Some(value * unit)
+}
+
+/// The size of the pages of the PDF output.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PaperSize {
+    /// A paper size that xdvipdfmx knows by name, such as "letter", "a4" or
+    /// "jisb5".
+    Named(String),
+    /// A width and a height, in big points.
+    Dimensions(f64, f64),
+}
+
+impl PaperSize {
+    fn is_valid(&self) -> bool {
+        match self {
+            PaperSize::Named(name) => unsafe { super::paperinfo(name.as_bytes()) }.is_some(),
+            PaperSize::Dimensions(width, height) => *width > 0. && *height > 0.,
+        }
+    }
+
+    /// The paper specification in the form that xdvipdfmx parses.
+    fn spec(&self) -> String {
+        match self {
+            PaperSize::Named(name) => name.clone(),
+            PaperSize::Dimensions(width, height) => format!("{}bp,{}bp", width, height),
+        }
+    }
+}
+
+impl FromStr for PaperSize {
+    type Err = &'static str;
+
+    /// Parse a paper name, or a width and a height separated by a comma, such
+    /// as "210mm,297mm".
+    fn from_str(a_str: &str) -> std::result::Result<Self, Self::Err> {
+        let paper = match a_str.find(',') {
+            Some(comma) => match (
+                parse_length(&a_str[..comma]),
+                parse_length(&a_str[comma + 1..]),
+            ) {
+                (Some(width), Some(height)) => PaperSize::Dimensions(width, height),
+                _ => return Err("invalid paper dimensions"),
+            },
+            None => PaperSize::Named(a_str.trim().to_owned()),
+        };
+
+        if paper.is_valid() {
+            Ok(paper)
+        } else {
+            Err("unknown paper size or invalid paper dimensions")
+        }
+    }
+}
+
+/// The page layout and other settings of the PDF output that documents can
+/// still change with specials (`papersize`, `pdf:pagesize`, `pdf:majorversion`
+/// and the like).
+#[derive(Clone, Debug, PartialEq)]
+pub struct PdfOutputOptions {
+    pub paper: PaperSize,
+    /// Turn the paper sideways, swapping its width and height.
+    pub landscape: bool,
+    /// How far the origin of the DVI coordinates lies from the left edge of
+    /// the page, in big points.
+    pub x_offset: f64,
+    /// How far the origin of the DVI coordinates lies from the top edge of
+    /// the page, in big points.
+    pub y_offset: f64,
+    /// The minor version of the PDF output (PDF 1.x), from 3 to 7. If it is
+    /// not given, it is 5, or 7 when AES-256 encryption needs it.
+    pub pdf_version: Option<u32>,
+    /// The number of decimal digits that coordinates in page content are
+    /// written with, up to 8.
+    pub decimal_digits: u8,
+    /// How many levels of bookmarks are initially open. Negative values
+    /// count from the deepest level instead.
+    pub bookmark_open_depth: i32,
+    /// How far link annotations extend beyond the text that they cover, in
+    /// big points.
+    pub annot_grow: f64,
+    /// The resolution that bitmap (PK) fonts are used at.
+    pub pk_dpi: u32,
+}
+
+impl PdfOutputOptions {
+    /// Parse a PDF version such as "1.5".
+    pub fn parse_pdf_version(text: &str) -> std::result::Result<u32, &'static str> {
+        let minor = match text.trim().get(..2) {
+            Some("1.") => text.trim()[2..].parse::<u32>().ok(),
+            _ => None,
+        };
+
+        match minor {
+            Some(v) if v >= 3 && v <= 7 => Ok(v),
+            _ => Err("the PDF version must be 1.3 to 1.7"),
+        }
+    }
+}
+
+impl Default for PdfOutputOptions {
+    /// Letter paper with the origin one inch from the top left corner, PDF
+    /// 1.5 (unless encryption needs 1.7), five decimal digits and 600 dpi bitmap fonts, as the TeXLive
+    /// configuration of dvipdfmx has it.
+    fn default() -> Self {
+        PdfOutputOptions {
+            paper: PaperSize::Named("letter".to_owned()),
+            landscape: false,
+            x_offset: 72.,
+            y_offset: 72.,
+            pdf_version: None,
+            decimal_digits: 5,
+            bookmark_open_depth: 0,
+            annot_grow: 0.,
+            pk_dpi: 600,
+        }
+    }
+}
+
+/// How an entry of the font map configuration changes the font map. These
+/// correspond to the `+`, `=` and `-` prefixes of the `pdf:mapfile` and
+/// `pdf:mapline` specials.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FontMapMode {
+    /// Add records for TFM names that are not mapped yet.
+    Append,
+    /// Add records, replacing any records for the same TFM names.
+    Replace,
+    /// Remove the records for the TFM names.
+    Remove,
+}
+
+impl FontMapMode {
+    /// Split the mode prefix (`+`, `=` or `-`) off a map file name or map
+    /// line. Without a prefix, the mode is `Append`.
+    pub fn split_prefix(spec: &str) -> (FontMapMode, &str) {
+        match spec.chars().next() {
+            Some('+') => (FontMapMode::Append, &spec[1..]),
+            Some('=') => (FontMapMode::Replace, &spec[1..]),
+            Some('-') => (FontMapMode::Remove, &spec[1..]),
+            _ => (FontMapMode::Append, spec),
+        }
+    }
+
+    fn code(self) -> i32 {
+        match self {
+            FontMapMode::Append => '+' as i32,
+            FontMapMode::Replace => 0,
+            FontMapMode::Remove => '-' as i32,
+        }
+    }
+}
+
+/// A font map file, or a single map line, that is applied to the font map
+/// before the document is processed. Map lines can be in the dvipdfm or the
+/// dvips/pdfTeX format; to remove a record, its TFM name is enough.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FontMap {
+    File(FontMapMode, String),
+    Line(FontMapMode, String),
+}
+
+impl FontMap {
+    /// The map files that are loaded unless told otherwise.
+    pub fn defaults() -> Vec<FontMap> {
+        ["pdftex.map", "kanjix.map", "ckx.map"]
+            .iter()
+            .map(|name| FontMap::File(FontMapMode::Append, (*name).to_owned()))
+            .collect()
+    }
+}
+
+/// The kind of font program behind a font of the PDF output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PdfFontType {
+    Type1,
+    /// A CFF font program (Type1C, or CIDFontType0 for CID-keyed fonts).
+    Cff,
+    TrueType,
+    /// A Type3 font drawn from PK bitmaps.
+    Type3,
+}
+
+impl fmt::Display for PdfFontType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            PdfFontType::Type1 => "Type1",
+            PdfFontType::Cff => "CFF",
+            PdfFontType::TrueType => "TrueType",
+            PdfFontType::Type3 => "Type3/PK",
+        })
+    }
+}
+
+/// A font resource written into the PDF output.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PdfFont {
+    /// The font file, as xdvipdfmx asked for it.
+    pub file: String,
+    /// The name that the font file was found under, which may have an
+    /// extension that `file` lacks, and where it came from; `None` if it was
+    /// not read through the I/O stack, as for the standard 14 fonts.
+    pub opened_as: Option<(String, InputOrigin)>,
+    /// The PostScript name of the font, without its subset tag.
+    pub font_name: String,
+    pub font_type: PdfFontType,
+    /// Whether the font was written as a composite (Type0) font.
+    pub cid_keyed: bool,
+    /// Whether the font program was embedded, rather than left for the
+    /// reader to supply.
+    pub embedded: bool,
+    /// The tag that prefixes the name of an embedded subset.
+    pub subset_tag: Option<String>,
+    /// How many glyphs (or, for simple fonts, character codes) are used.
+    pub glyph_count: usize,
+    /// The encoding or CMap of the font; `None` for its built-in encoding.
+    pub encoding: Option<String>,
+}
+
 pub struct XdvipdfmxEngine {
     enable_compression: bool,
     deterministic_tags: bool,
+    image_dpi: Option<u32>,
+    jpeg_quality: u8,
+    png_to_jpeg: bool,
+    pk_outline_fallback: bool,
+    image_cache_path: Option<PathBuf>,
+    pdfa: Option<PdfAConformance>,
+    encryption: Option<PdfEncryption>,
+    linearize: bool,
+    keep_annotations: bool,
+    font_maps: Vec<FontMap>,
+    font_map_diagnostics: bool,
+    output_options: PdfOutputOptions,
+    fonts: Vec<PdfFont>,
 }
 
 impl XdvipdfmxEngine {
@@ -19,6 +358,19 @@ impl XdvipdfmxEngine {
         XdvipdfmxEngine {
             enable_compression: true,
             deterministic_tags: false,
+            image_dpi: None,
+            jpeg_quality: 85,
+            png_to_jpeg: false,
+            pk_outline_fallback: false,
+            image_cache_path: None,
+            pdfa: None,
+            encryption: None,
+            linearize: false,
+            keep_annotations: false,
+            font_maps: FontMap::defaults(),
+            font_map_diagnostics: false,
+            output_options: PdfOutputOptions::default(),
+            fonts: Vec::new(),
         }
     }
 
@@ -32,6 +384,137 @@ impl XdvipdfmxEngine {
         self
     }
 
+    /// Downsample images painted at more than 1.5 times this resolution (in
+    /// dots per inch) to it. Images are left alone by default.
+    pub fn with_image_dpi(mut self, dpi: Option<u32>) -> Self {
+        self.image_dpi = dpi;
+        self
+    }
+
+    /// The JPEG quality (1 to 100) that downsampled JPEG images, and PNG
+    /// images converted to JPEG, are encoded at. The default is 85.
+    pub fn with_jpeg_quality(mut self, quality: u8) -> Self {
+        self.jpeg_quality = quality.max(1).min(100);
+        self
+    }
+
+    /// Encode PNG images without transparency as JPEG images.
+    pub fn with_png_to_jpeg(mut self, flag: bool) -> Self {
+        self.png_to_jpeg = flag;
+        self
+    }
+
+    /// When a bitmap (PK) font cannot be found, render its glyphs from a
+    /// Type 1 or OpenType font of the same name instead of failing.
+    pub fn with_pk_outline_fallback(mut self, flag: bool) -> Self {
+        self.pk_outline_fallback = flag;
+        self
+    }
+
+    /// Keep finished image XObjects in this directory, keyed by the contents
+    /// of the image files, and reuse them in later runs.
+    pub fn with_image_cache_path(mut self, path: Option<PathBuf>) -> Self {
+        self.image_cache_path = path;
+        self
+    }
+
+    /// Make the output conform to PDF/A. Output that cannot be made to
+    /// conform (unembedded fonts, encryption, ...) is reported as errors and
+    /// fails the run.
+    pub fn with_pdfa(mut self, level: Option<PdfAConformance>) -> Self {
+        self.pdfa = level;
+        self
+    }
+
+    /// Encrypt the output with these settings, or not at all.
+    pub fn with_encryption(mut self, encryption: Option<PdfEncryption>) -> Self {
+        self.encryption = encryption;
+        self
+    }
+
+    /// Encrypt the output, with this owner password. This and the following
+    /// methods start from the default `PdfEncryption` settings.
+    pub fn with_owner_password(mut self, password: &str) -> Self {
+        self.encryption
+            .get_or_insert_with(PdfEncryption::default)
+            .owner_password = password.to_owned();
+        self
+    }
+
+    /// Encrypt the output, with this password needed to open it.
+    pub fn with_user_password(mut self, password: &str) -> Self {
+        self.encryption
+            .get_or_insert_with(PdfEncryption::default)
+            .user_password = password.to_owned();
+        self
+    }
+
+    /// Encrypt the output, allowing only what these `PdfEncryption::PERMIT_*`
+    /// flags grant.
+    pub fn with_permissions(mut self, permissions: u32) -> Self {
+        self.encryption
+            .get_or_insert_with(PdfEncryption::default)
+            .permissions = permissions;
+        self
+    }
+
+    /// Encrypt the output with a key of this length.
+    pub fn with_key_bits(mut self, bits: u32) -> Self {
+        self.encryption
+            .get_or_insert_with(PdfEncryption::default)
+            .key_bits = bits;
+        self
+    }
+
+    /// Write a linearized PDF file, whose first page can be displayed before
+    /// the rest of it has been downloaded. Together with encryption, this
+    /// needs 256-bit keys.
+    pub fn with_linearize(mut self, flag: bool) -> Self {
+        self.linearize = flag;
+        self
+    }
+
+    /// Carry the links of included PDF pages over onto the pages they are
+    /// placed on, and keep the layers (optional content) of included files.
+    pub fn with_keep_annotations(mut self, flag: bool) -> Self {
+        self.keep_annotations = flag;
+        self
+    }
+
+    /// Apply these font map files and lines, in order, in place of the
+    /// default ones (see `FontMap::defaults`).
+    pub fn with_font_maps(mut self, maps: Vec<FontMap>) -> Self {
+        self.font_maps = maps;
+        self
+    }
+
+    /// Apply this font map file or line after those configured so far.
+    pub fn with_font_map(mut self, map: FontMap) -> Self {
+        self.font_maps.push(map);
+        self
+    }
+
+    /// Print how each TFM name was resolved to a font: through which font
+    /// map record, and from which map file it came, or as a virtual or
+    /// bitmap font.
+    pub fn with_font_map_diagnostics(mut self, flag: bool) -> Self {
+        self.font_map_diagnostics = flag;
+        self
+    }
+
+    /// Lay out the pages of the output, and set its PDF version and
+    /// precision, like this unless the document says otherwise.
+    pub fn with_output_options(mut self, options: PdfOutputOptions) -> Self {
+        self.output_options = options;
+        self
+    }
+
+    /// Get the fonts that were written into the PDF output by the most
+    /// recent run.
+    pub fn fonts(&self) -> &[PdfFont] {
+        &self.fonts
+    }
+
     pub fn process(
         &mut self,
         io: &mut IoStack,
@@ -44,18 +527,109 @@ impl XdvipdfmxEngine {
 
         let cdvi = CString::new(dvi)?;
         let cpdf = CString::new(pdf)?;
+        let cimages = match self.image_cache_path {
+            Some(ref p) => Some(CString::new(p.to_string_lossy().as_bytes())?),
+            None => None,
+        };
+
+        let options = &self.output_options;
+        if !options.paper.is_valid() {
+            return Err(ErrorKind::Msg(format!(
+                "unknown paper size or invalid paper dimensions: {:?}",
+                options.paper
+            ))
+            .into());
+        }
+        if let Some(version) = options.pdf_version {
+            if version < 3 || version > 7 {
+                return Err(
+                    ErrorKind::Msg(format!("unsupported PDF version: 1.{}", version)).into(),
+                );
+            }
+        }
+        let cpaper = CString::new(options.paper.spec())?;
+
+        let (cowner, cuser) = match self.encryption {
+            Some(ref enc) => {
+                if !PdfEncryption::key_bits_supported(enc.key_bits) {
+                    return Err(ErrorKind::Msg(format!(
+                        "unsupported encryption key length: {} bits",
+                        enc.key_bits
+                    ))
+                    .into());
+                }
+                match options.pdf_version {
+                    Some(version) if enc.key_bits > 40 && version < 4 => {
+                        return Err(ErrorKind::Msg(format!(
+                            "encryption keys longer than 40 bits require at least PDF 1.4, not 1.{}",
+                            version
+                        ))
+                        .into());
+                    }
+                    Some(version) if enc.key_bits == 256 && version < 7 => {
+                        return Err(ErrorKind::Msg(format!(
+                            "256-bit encryption keys require PDF 1.7, not 1.{}",
+                            version
+                        ))
+                        .into());
+                    }
+                    _ => {}
+                }
+                (
+                    Some(CString::new(enc.owner_password.as_bytes())?),
+                    Some(CString::new(enc.user_password.as_bytes())?),
+                )
+            }
+            None => (None, None),
+        };
 
         let /*mut*/ state = ExecutionState::new(io, events, status);
         let bridge = TectonicBridgeApi::new(&state);
 
         unsafe {
-            match super::dvipdfmx_simple_main(
-                &*bridge,
-                cdvi.as_ptr(),
-                cpdf.as_ptr(),
-                self.enable_compression,
-                self.deterministic_tags,
-            ) {
+            super::pdf_fontmap_clear_requests();
+        }
+        for map in &self.font_maps {
+            let (mode, spec, is_line) = match map {
+                FontMap::File(mode, name) => (mode, name, 0),
+                FontMap::Line(mode, line) => (mode, line, 1),
+            };
+            let cspec = CString::new(spec.as_str())?;
+            unsafe {
+                super::pdf_fontmap_add_request(cspec.as_ptr(), is_line, mode.code());
+            }
+        }
+
+        let coptions = DvipdfmxOptions {
+            compress: self.enable_compression,
+            deterministic_tags: self.deterministic_tags,
+            image_dpi: self.image_dpi.unwrap_or(0),
+            jpeg_quality: self.jpeg_quality,
+            png_to_jpeg: self.png_to_jpeg,
+            pk_outline_fallback: self.pk_outline_fallback,
+            image_cache_dir: cimages.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            pdfa_part: self.pdfa.map_or(0, |l| l.part()),
+            encrypt: self.encryption.is_some(),
+            enc_key_bits: self.encryption.as_ref().map_or(0, |e| e.key_bits),
+            enc_permission: self.encryption.as_ref().map_or(0, |e| e.permissions),
+            enc_owner_pw: cowner.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            enc_user_pw: cuser.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+            linearize: self.linearize,
+            keep_annotations: self.keep_annotations,
+            font_map_diagnostics: self.font_map_diagnostics,
+            paperspec: cpaper.as_ptr(),
+            landscape: options.landscape,
+            origin_x: options.x_offset,
+            origin_y: options.y_offset,
+            pdf_minor_version: options.pdf_version.unwrap_or(0),
+            decimal_digits: options.decimal_digits as i32,
+            bookmark_depth: options.bookmark_open_depth,
+            annot_margin: options.annot_grow,
+            pk_dpi: options.pk_dpi as i32,
+        };
+
+        let result = unsafe {
+            match super::dvipdfmx_simple_main(&*bridge, cdvi.as_ptr(), cpdf.as_ptr(), &coptions) {
                 99 => {
                     let ptr = super::tt_get_error_message();
                     let msg = CStr::from_ptr(ptr).to_string_lossy().into_owned();
@@ -63,7 +637,32 @@ impl XdvipdfmxEngine {
                 }
                 x => Ok(x as i32),
             }
-        }
+        };
+
+        let opened_inputs = &state.opened_inputs;
+        self.fonts = unsafe { super::pdf_font_take_report() }
+            .into_iter()
+            .map(|e| PdfFont {
+                opened_as: opened_inputs
+                    .get(OsStr::new(&e.file))
+                    .map(|(name, origin)| (name.to_string_lossy().into_owned(), *origin)),
+                file: e.file,
+                font_name: e.font_name,
+                font_type: match e.font_type {
+                    FontReportType::Type1 => PdfFontType::Type1,
+                    FontReportType::Cff => PdfFontType::Cff,
+                    FontReportType::TrueType => PdfFontType::TrueType,
+                    FontReportType::Type3 => PdfFontType::Type3,
+                },
+                cid_keyed: e.cid_keyed,
+                embedded: e.embedded,
+                subset_tag: e.subset_tag,
+                glyph_count: e.glyph_count,
+                encoding: e.encoding,
+            })
+            .collect();
+
+        result
     }
 }
 
@@ -686,6 +686,12 @@ impl IoProvider for CachedITarBundle {
             InputOrigin::Other,
         ))
     }
+
+    fn input_names(&mut self) -> Vec<String> {
+        let mut names: Vec<String> = self.index.keys().cloned().collect();
+        names.sort();
+        names
+    }
 }
 
 impl Bundle for CachedITarBundle {
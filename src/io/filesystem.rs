@@ -5,7 +5,7 @@
 use libc;
 use std::collections::HashSet;
 use std::ffi::OsStr;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{self, BufReader, Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 
@@ -92,6 +92,52 @@ impl FilesystemIo {
         combined.push(path);
         Ok(combined)
     }
+
+    fn list_dir(&self, rel_dir: &Path, depth: usize, names: &mut Vec<String>) {
+        let mut dir = PathBuf::from(&self.root);
+        dir.push(rel_dir);
+
+        // An empty root means the current directory.
+        let dir = if dir.as_os_str().is_empty() {
+            PathBuf::from(".")
+        } else {
+            dir
+        };
+
+        let entries = match fs::read_dir(&dir) {
+            Ok(e) => e,
+            Err(_) => return,
+        };
+
+        for entry in entries.filter_map(|e| e.ok()) {
+            let file_name = entry.file_name();
+
+            let name = match file_name.to_str() {
+                Some(n) => n,
+                None => continue,
+            };
+
+            let rel_path = rel_dir.join(name);
+
+            if name.starts_with('.') || self.hidden_input_paths.contains(&self.root.join(&rel_path))
+            {
+                continue;
+            }
+
+            let file_type = match entry.file_type() {
+                Ok(t) => t,
+                Err(_) => continue,
+            };
+
+            if file_type.is_dir() {
+                if depth > 0 {
+                    self.list_dir(&rel_path, depth - 1, names);
+                }
+            } else if let Some(s) = rel_path.to_str() {
+                names.push(s.replace('\\', "/"));
+            }
+        }
+    }
 }
 
 impl IoProvider for FilesystemIo {
@@ -155,6 +201,17 @@ impl IoProvider for FilesystemIo {
             InputOrigin::Filesystem,
         ))
     }
+
+    /// Lists the files in the root directory and in its immediate
+    /// subdirectories, which is where projects usually keep things like font
+    /// files. Hidden paths and dot-files are skipped, and names use forward
+    /// slashes so that they can be opened again through this provider.
+    fn input_names(&mut self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.list_dir(Path::new(""), 1, &mut names);
+        names.sort();
+        names
+    }
 }
 
 impl InputFeatures for File {
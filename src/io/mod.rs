@@ -24,6 +24,7 @@ pub mod memory;
 pub mod setup;
 pub mod stack;
 pub mod stdstreams;
+pub mod woff;
 pub mod zipbundle;
 
 pub trait InputFeatures: Read {
@@ -399,6 +400,17 @@ pub trait IoProvider: AsIoProviderMut {
     ) -> Result<()> {
         Err(ErrorKind::Msg("this I/O layer cannot save format files".to_owned()).into())
     }
+
+    /// List the names of the files that this provider can open for input.
+    ///
+    /// Most providers can only answer whether a particular name exists, so
+    /// the default is to list nothing. Providers that can enumerate their
+    /// contents cheaply, like bundles, report names that can be passed to
+    /// `input_open_name()`. This is used to discover font files that the
+    /// engine may look up by their family or full names.
+    fn input_names(&mut self) -> Vec<String> {
+        Vec::new()
+    }
 }
 
 impl<P: IoProvider + ?Sized> IoProvider for Box<P> {
@@ -438,6 +450,10 @@ impl<P: IoProvider + ?Sized> IoProvider for Box<P> {
     ) -> Result<()> {
         (**self).write_format(name, data, status)
     }
+
+    fn input_names(&mut self) -> Vec<String> {
+        (**self).input_names()
+    }
 }
 
 /// A special IoProvider that can make TeX format files.
@@ -91,6 +91,38 @@ impl IoSetup {
 
         IoStack::new(providers)
     }
+
+    /// Lists the OpenType and TrueType font files that the TeX engine can
+    /// look up fonts in by name, along with whether each file belongs to the
+    /// project (the filesystem layer) rather than the bundle. Project files
+    /// come first, so that they take precedence.
+    pub fn font_file_names(&mut self) -> Vec<(String, bool)> {
+        let mut names: Vec<(String, bool)> = self
+            .filesystem
+            .input_names()
+            .into_iter()
+            .filter(|n| is_font_file_name(n))
+            .map(|n| (n, true))
+            .collect();
+
+        if let Some(ref mut b) = self.bundle {
+            names.extend(
+                b.input_names()
+                    .into_iter()
+                    .filter(|n| is_font_file_name(n))
+                    .map(|n| (n, false)),
+            );
+        }
+
+        names
+    }
+}
+
+fn is_font_file_name(name: &str) -> bool {
+    let lower = name.to_lowercase();
+    [".otf", ".ttf", ".otc", ".ttc", ".woff", ".woff2"]
+        .iter()
+        .any(|ext| lower.ends_with(ext))
 }
 
 /// Where does the "primary input" stream come from?
@@ -254,3 +286,18 @@ impl IoSetupBuilder {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn font_file_names() {
+        assert!(is_font_file_name("texgyretermes-regular.otf"));
+        assert!(is_font_file_name("fonts/NotoSans-Bold.TTF"));
+        assert!(is_font_file_name("Collection.ttc"));
+        assert!(is_font_file_name("OpenSans-Regular.woff2"));
+        assert!(!is_font_file_name("cmr10.pfb"));
+        assert!(!is_font_file_name("otf.tex"));
+    }
+}
@@ -2,6 +2,7 @@
 // Copyright 2016-2017 the Tectonic Project
 // Licensed under the MIT License.
 
+use std::collections::HashSet;
 use std::ffi::OsStr;
 
 use super::{InputHandle, IoProvider, OpenResult, OutputHandle};
@@ -95,4 +96,19 @@ impl<'a> IoProvider for IoStack<'a> {
 
         OpenResult::NotAvailable
     }
+
+    fn input_names(&mut self) -> Vec<String> {
+        let mut seen = HashSet::new();
+        let mut names = Vec::new();
+
+        for item in &mut self.items {
+            for name in item.input_names() {
+                if seen.insert(name.clone()) {
+                    names.push(name);
+                }
+            }
+        }
+
+        names
+    }
 }
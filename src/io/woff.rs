@@ -0,0 +1,939 @@
+// src/io/woff.rs -- decoding of WOFF and WOFF2 web fonts
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Decoding of fonts packaged in the WOFF and WOFF2 web font formats.
+//!
+//! The engines only understand plain TrueType and OpenType ("sfnt") data. So
+//! that fonts distributed as web fonts can be used all the same, font files
+//! are checked as they are opened and decoded into memory if need be; see
+//! [`decode_input`].
+
+use brotli_decompressor::Decompressor;
+use flate2::read::ZlibDecoder;
+use std::io::{Cursor, Read, SeekFrom};
+
+use super::{InputFeatures, InputHandle};
+use crate::errmsg;
+use crate::errors::Result;
+
+const WOFF_SIGNATURE: u32 = 0x774f_4646; // "wOFF"
+const WOFF2_SIGNATURE: u32 = 0x774f_4632; // "wOF2"
+const TTC_TAG: u32 = 0x7474_6366; // "ttcf"
+const GLYF_TAG: u32 = 0x676c_7966;
+const LOCA_TAG: u32 = 0x6c6f_6361;
+const HMTX_TAG: u32 = 0x686d_7478;
+const HHEA_TAG: u32 = 0x6868_6561;
+const HEAD_TAG: u32 = 0x6865_6164;
+
+/// The tags that WOFF2 table directory entries can refer to by index.
+const WOFF2_KNOWN_TAGS: [&[u8; 4]; 63] = [
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post", b"cvt ", b"fpgm",
+    b"glyf", b"loca", b"prep", b"CFF ", b"VORG", b"EBDT", b"EBLC", b"gasp", b"hdmx", b"kern",
+    b"LTSH", b"PCLT", b"VDMX", b"vhea", b"vmtx", b"BASE", b"GDEF", b"GPOS", b"GSUB", b"EBSC",
+    b"JSTF", b"MATH", b"CBDT", b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar",
+    b"bdat", b"bloc", b"bsln", b"cvar", b"fdsc", b"feat", b"fmtx", b"fvar", b"gvar", b"hsty",
+    b"just", b"lcar", b"mort", b"morx", b"opbd", b"prop", b"trak", b"Zapf", b"Silf", b"Glat",
+    b"Gloc", b"Feat", b"Sill",
+];
+
+/// Whether `data` starts like a WOFF or WOFF2 file.
+pub fn is_woff(data: &[u8]) -> bool {
+    match Reader::new(data).u32() {
+        Ok(WOFF_SIGNATURE) | Ok(WOFF2_SIGNATURE) => true,
+        _ => false,
+    }
+}
+
+/// Decode a WOFF or WOFF2 file into the sfnt data that it packages: a
+/// TrueType or OpenType font, or a font collection.
+pub fn decode(data: &[u8]) -> Result<Vec<u8>> {
+    match Reader::new(data).u32()? {
+        WOFF_SIGNATURE => decode_woff(data),
+        WOFF2_SIGNATURE => decode_woff2(data),
+        _ => Err(errmsg!("not a WOFF or WOFF2 file")),
+    }
+}
+
+/// If `ih` refers to a WOFF or WOFF2 file, decode it and return a handle on
+/// the plain sfnt data instead. Other files are handed back rewound to their
+/// beginning.
+pub fn decode_input(mut ih: InputHandle) -> Result<InputHandle> {
+    let mut data = Vec::new();
+    (&mut ih).take(4).read_to_end(&mut data)?;
+
+    if !is_woff(&data) {
+        ih.try_seek(SeekFrom::Start(0))?;
+        return Ok(ih);
+    }
+
+    ih.read_to_end(&mut data)?;
+    let sfnt = decode(&data)?;
+    Ok(InputHandle::new(ih.name(), Cursor::new(sfnt), ih.origin()))
+}
+
+/// A cursor over big-endian binary data that fails cleanly at the end.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = match self.pos.checked_add(n) {
+            Some(end) if end <= self.data.len() => end,
+            _ => return Err(errmsg!("truncated font data")),
+        };
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<()> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let b = self.bytes(2)?;
+        Ok(u16::from(b[0]) << 8 | u16::from(b[1]))
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(self.u16()? as i16)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let b = self.bytes(4)?;
+        Ok(u32::from(b[0]) << 24 | u32::from(b[1]) << 16 | u32::from(b[2]) << 8 | u32::from(b[3]))
+    }
+
+    /// WOFF2's variable-length `UIntBase128`.
+    fn base128(&mut self) -> Result<u32> {
+        let mut value = 0u32;
+
+        for i in 0..5 {
+            let byte = self.u8()?;
+
+            if (i == 0 && byte == 0x80) || value & 0xfe00_0000 != 0 {
+                return Err(errmsg!("invalid UIntBase128 value in WOFF2 data"));
+            }
+
+            value = value << 7 | u32::from(byte & 0x7f);
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(errmsg!("overlong UIntBase128 value in WOFF2 data"))
+    }
+
+    /// WOFF2's variable-length `255UInt16`.
+    fn u255_16(&mut self) -> Result<u16> {
+        Ok(match self.u8()? {
+            253 => self.u16()?,
+            254 => u16::from(self.u8()?) + 506,
+            255 => u16::from(self.u8()?) + 253,
+            code => u16::from(code),
+        })
+    }
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.push((value >> 8) as u8);
+    out.push(value as u8);
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    put_u16(out, (value >> 16) as u16);
+    put_u16(out, value as u16);
+}
+
+fn pad4(out: &mut Vec<u8>) {
+    while out.len() % 4 != 0 {
+        out.push(0);
+    }
+}
+
+fn table_checksum(data: &[u8]) -> u32 {
+    data.chunks(4).fold(0u32, |sum, chunk| {
+        let mut word = [0u8; 4];
+        word[..chunk.len()].copy_from_slice(chunk);
+        sum.wrapping_add(Reader::new(&word).u32().unwrap())
+    })
+}
+
+/// A font of the sfnt being written: its flavor ("OTTO", 0x00010000, ...)
+/// and the indices of its tables.
+struct SfntFont {
+    flavor: u32,
+    tables: Vec<usize>,
+}
+
+/// Lay out an sfnt file holding `tables`, which are shared among `fonts`. If
+/// `collection_version` is given, the result is a TrueType collection.
+fn write_sfnt(
+    tables: &mut [(u32, Vec<u8>)],
+    fonts: &[SfntFont],
+    collection_version: Option<u32>,
+) -> Vec<u8> {
+    // The checksum adjustment is computed over the whole file, below, so it
+    // must not enter into the checksum of the head table.
+    for &mut (tag, ref mut data) in tables.iter_mut() {
+        if tag == HEAD_TAG && data.len() >= 12 {
+            data[8..12].copy_from_slice(&[0; 4]);
+        }
+    }
+
+    let mut header_size = match collection_version {
+        Some(version) if version >= 0x0002_0000 => 24 + 4 * fonts.len(),
+        Some(_) => 12 + 4 * fonts.len(),
+        None => 0,
+    };
+    let mut font_offsets = Vec::with_capacity(fonts.len());
+
+    for font in fonts {
+        font_offsets.push(header_size);
+        header_size += 12 + 16 * font.tables.len();
+    }
+
+    let mut table_offsets = Vec::with_capacity(tables.len());
+    let mut offset = header_size;
+
+    for (_, data) in tables.iter() {
+        table_offsets.push(offset);
+        offset += (data.len() + 3) & !3;
+    }
+
+    let mut out = Vec::with_capacity(offset);
+
+    if let Some(version) = collection_version {
+        put_u32(&mut out, TTC_TAG);
+        put_u32(&mut out, version);
+        put_u32(&mut out, fonts.len() as u32);
+
+        for &font_offset in &font_offsets {
+            put_u32(&mut out, font_offset as u32);
+        }
+
+        if version >= 0x0002_0000 {
+            // No DSIG table.
+            out.extend_from_slice(&[0; 12]);
+        }
+    }
+
+    for font in fonts {
+        let num_tables = font.tables.len() as u16;
+        let mut entry_selector = 0u16;
+
+        while 2u32 << entry_selector <= u32::from(num_tables) {
+            entry_selector += 1;
+        }
+
+        let search_range = 16u16 << entry_selector;
+        put_u32(&mut out, font.flavor);
+        put_u16(&mut out, num_tables);
+        put_u16(&mut out, search_range);
+        put_u16(&mut out, entry_selector);
+        put_u16(&mut out, (num_tables * 16).saturating_sub(search_range));
+
+        let mut sorted = font.tables.clone();
+        sorted.sort_by_key(|&i| tables[i].0);
+
+        for i in sorted {
+            let (tag, ref data) = tables[i];
+            put_u32(&mut out, tag);
+            put_u32(&mut out, table_checksum(data));
+            put_u32(&mut out, table_offsets[i] as u32);
+            put_u32(&mut out, data.len() as u32);
+        }
+    }
+
+    for (_, data) in tables.iter() {
+        out.extend_from_slice(data);
+        pad4(&mut out);
+    }
+
+    if collection_version.is_none() {
+        if let Some(i) = tables
+            .iter()
+            .position(|t| t.0 == HEAD_TAG && t.1.len() >= 12)
+        {
+            let adjustment = 0xb1b0_afbau32.wrapping_sub(table_checksum(&out));
+            let pos = table_offsets[i] + 8;
+            out[pos..pos + 4].copy_from_slice(&[
+                (adjustment >> 24) as u8,
+                (adjustment >> 16) as u8,
+                (adjustment >> 8) as u8,
+                adjustment as u8,
+            ]);
+        }
+    }
+
+    out
+}
+
+fn decode_woff(data: &[u8]) -> Result<Vec<u8>> {
+    let mut r = Reader::new(data);
+    r.skip(4)?; // signature
+    let flavor = r.u32()?;
+    r.skip(4)?; // length
+    let num_tables = r.u16()? as usize;
+    r.skip(30)?; // the rest of the header: sizes, versions, metadata, private data
+
+    let mut tables = Vec::with_capacity(num_tables);
+
+    for _ in 0..num_tables {
+        let tag = r.u32()?;
+        let offset = r.u32()? as usize;
+        let comp_length = r.u32()? as usize;
+        let orig_length = r.u32()? as usize;
+        r.skip(4)?; // checksum
+
+        let raw = Reader { data, pos: offset }.bytes(comp_length)?;
+
+        let table = if comp_length < orig_length {
+            let mut table = Vec::new();
+            ZlibDecoder::new(raw)
+                .take(orig_length as u64)
+                .read_to_end(&mut table)?;
+
+            if table.len() != orig_length {
+                return Err(errmsg!("bad compressed table in WOFF data"));
+            }
+
+            table
+        } else if comp_length == orig_length {
+            raw.to_vec()
+        } else {
+            return Err(errmsg!("bad table lengths in WOFF data"));
+        };
+
+        tables.push((tag, table));
+    }
+
+    let font = SfntFont {
+        flavor,
+        tables: (0..num_tables).collect(),
+    };
+    Ok(write_sfnt(&mut tables, &[font], None))
+}
+
+/// A table as listed in the WOFF2 table directory.
+struct Woff2Table {
+    tag: u32,
+    transformed: bool,
+    orig_length: usize,
+    /// Where its (possibly transformed) data lies in the decompressed stream.
+    start: usize,
+    end: usize,
+}
+
+fn decode_woff2(data: &[u8]) -> Result<Vec<u8>> {
+    let mut r = Reader::new(data);
+    r.skip(4)?; // signature
+    let flavor = r.u32()?;
+    r.skip(4)?; // length
+    let num_tables = r.u16()? as usize;
+    r.skip(6)?; // reserved, totalSfntSize
+    let compressed_size = r.u32()? as usize;
+    r.skip(24)?; // versions, metadata and private data
+
+    let mut directory = Vec::with_capacity(num_tables);
+    let mut stream_size = 0usize;
+
+    for _ in 0..num_tables {
+        let flags = r.u8()?;
+        let tag = match flags & 0x3f {
+            0x3f => r.u32()?,
+            i => Reader::new(WOFF2_KNOWN_TAGS[i as usize]).u32()?,
+        };
+
+        // For glyf and loca, transformation version 0 means the table is
+        // transformed, and version 3 that it isn't; for all other tables,
+        // version 0 is the null transform.
+        let version = flags >> 6;
+        let transformed = if tag == GLYF_TAG || tag == LOCA_TAG {
+            version == 0
+        } else {
+            version != 0
+        };
+
+        let orig_length = r.base128()? as usize;
+        let length = if transformed {
+            r.base128()? as usize
+        } else {
+            orig_length
+        };
+
+        let start = stream_size;
+        stream_size = stream_size
+            .checked_add(length)
+            .ok_or_else(|| errmsg!("bad table lengths in WOFF2 data"))?;
+
+        directory.push(Woff2Table {
+            tag,
+            transformed,
+            orig_length,
+            start,
+            end: stream_size,
+        });
+    }
+
+    let mut collection_version = None;
+    let mut fonts = Vec::new();
+
+    if flavor == TTC_TAG {
+        collection_version = Some(r.u32()?);
+        let num_fonts = r.u255_16()?;
+
+        for _ in 0..num_fonts {
+            let num_font_tables = r.u255_16()?;
+            let font_flavor = r.u32()?;
+            let mut tables = Vec::with_capacity(num_font_tables as usize);
+
+            for _ in 0..num_font_tables {
+                let index = r.u255_16()? as usize;
+
+                if index >= num_tables {
+                    return Err(errmsg!("bad table index in WOFF2 collection"));
+                }
+
+                tables.push(index);
+            }
+
+            fonts.push(SfntFont {
+                flavor: font_flavor,
+                tables,
+            });
+        }
+    } else {
+        fonts.push(SfntFont {
+            flavor,
+            tables: (0..num_tables).collect(),
+        });
+    }
+
+    let mut stream = Vec::new();
+    Decompressor::new(r.bytes(compressed_size)?, 4096)
+        .take(stream_size as u64)
+        .read_to_end(&mut stream)?;
+
+    if stream.len() != stream_size {
+        return Err(errmsg!("bad compressed data in WOFF2 data"));
+    }
+
+    let mut tables: Vec<Option<Vec<u8>>> = directory
+        .iter()
+        .map(|t| {
+            if t.transformed {
+                None
+            } else {
+                Some(stream[t.start..t.end].to_vec())
+            }
+        })
+        .collect();
+
+    // The glyf and loca tables, and then hmtx, whose transform refers to the
+    // glyphs, are rebuilt font by font; fonts of a collection may share them.
+    for font in &fonts {
+        let find = |tag| {
+            font.tables
+                .iter()
+                .cloned()
+                .find(|&i| directory[i].tag == tag)
+        };
+        let mut x_mins = None;
+
+        if let (Some(glyf), Some(loca)) = (find(GLYF_TAG), find(LOCA_TAG)) {
+            if directory[glyf].transformed != directory[loca].transformed {
+                return Err(errmsg!("mismatched glyf and loca transforms in WOFF2 data"));
+            }
+
+            if directory[glyf].transformed {
+                let glyphs = reconstruct_glyf(&stream[directory[glyf].start..directory[glyf].end])?;
+
+                if glyphs.loca.len() != directory[loca].orig_length {
+                    return Err(errmsg!("bad loca table length in WOFF2 data"));
+                }
+
+                tables[glyf] = Some(glyphs.glyf);
+                tables[loca] = Some(glyphs.loca);
+                x_mins = Some(glyphs.x_mins);
+            }
+        } else if find(GLYF_TAG).or_else(|| find(LOCA_TAG)).is_some() {
+            return Err(errmsg!("glyf table without loca table in WOFF2 data"));
+        }
+
+        if let Some(hmtx) = find(HMTX_TAG) {
+            if directory[hmtx].transformed && tables[hmtx].is_none() {
+                let x_mins =
+                    x_mins.ok_or_else(|| errmsg!("transformed hmtx without glyf in WOFF2 data"))?;
+                let num_h_metrics = match find(HHEA_TAG).and_then(|i| tables[i].as_ref()) {
+                    Some(hhea) => Reader {
+                        data: hhea,
+                        pos: 34,
+                    }
+                    .u16()?,
+                    None => return Err(errmsg!("hmtx table without hhea in WOFF2 data")),
+                };
+
+                tables[hmtx] = Some(reconstruct_hmtx(
+                    &stream[directory[hmtx].start..directory[hmtx].end],
+                    num_h_metrics as usize,
+                    &x_mins,
+                )?);
+            }
+        }
+    }
+
+    let mut tables = directory
+        .iter()
+        .zip(tables)
+        .map(|(t, data)| match data {
+            Some(data) => Ok((t.tag, data)),
+            None => Err(errmsg!("unsupported table transform in WOFF2 data")),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(write_sfnt(&mut tables, &fonts, collection_version))
+}
+
+/// The result of undoing the WOFF2 glyf transform.
+struct Glyphs {
+    glyf: Vec<u8>,
+    loca: Vec<u8>,
+    /// The left edge of each glyph's bounding box, for the hmtx transform.
+    x_mins: Vec<i16>,
+}
+
+fn reconstruct_glyf(data: &[u8]) -> Result<Glyphs> {
+    let mut header = Reader::new(data);
+    header.skip(2)?; // reserved
+    let option_flags = header.u16()?;
+    let num_glyphs = header.u16()? as usize;
+    let index_format = header.u16()?;
+
+    let mut streams = Vec::with_capacity(7);
+    let mut offset = 36;
+
+    for _ in 0..7 {
+        let size = header.u32()? as usize;
+        streams.push(Reader::new(Reader { data, pos: offset }.bytes(size)?));
+        offset += size;
+    }
+
+    let mut instructions = streams.pop().unwrap();
+    let mut bboxes = streams.pop().unwrap();
+    let mut composites = streams.pop().unwrap();
+    let mut glyph_stream = streams.pop().unwrap();
+    let mut flag_stream = streams.pop().unwrap();
+    let mut n_points_stream = streams.pop().unwrap();
+    let mut n_contours_stream = streams.pop().unwrap();
+
+    let bbox_bitmap = bboxes.bytes(((num_glyphs + 31) >> 5) << 2)?;
+    let overlap_bitmap = if option_flags & 1 != 0 {
+        Some(Reader { data, pos: offset }.bytes((num_glyphs + 7) >> 3)?)
+    } else {
+        None
+    };
+
+    let mut glyf = Vec::new();
+    let mut loca_offsets = Vec::with_capacity(num_glyphs + 1);
+    let mut x_mins = Vec::with_capacity(num_glyphs);
+
+    for i in 0..num_glyphs {
+        loca_offsets.push(glyf.len());
+
+        let n_contours = n_contours_stream.i16()?;
+        let has_bbox = bbox_bitmap[i >> 3] & (0x80 >> (i & 7)) != 0;
+        let read_bbox = |bboxes: &mut Reader| -> Result<[i16; 4]> {
+            Ok([bboxes.i16()?, bboxes.i16()?, bboxes.i16()?, bboxes.i16()?])
+        };
+
+        if n_contours == 0 {
+            if has_bbox {
+                return Err(errmsg!("bounding box for empty glyph in WOFF2 data"));
+            }
+
+            x_mins.push(0);
+            continue;
+        }
+
+        if n_contours < 0 {
+            if n_contours != -1 || !has_bbox {
+                return Err(errmsg!("bad composite glyph in WOFF2 data"));
+            }
+
+            let bbox = read_bbox(&mut bboxes)?;
+            let start = composites.pos;
+            let mut have_instructions = false;
+
+            loop {
+                let flags = composites.u16()?;
+                let mut size = 2; // glyph index
+
+                size += if flags & 0x0001 != 0 { 4 } else { 2 };
+
+                if flags & 0x0008 != 0 {
+                    size += 2;
+                } else if flags & 0x0040 != 0 {
+                    size += 4;
+                } else if flags & 0x0080 != 0 {
+                    size += 8;
+                }
+
+                composites.skip(size)?;
+                have_instructions |= flags & 0x0100 != 0;
+
+                if flags & 0x0020 == 0 {
+                    break;
+                }
+            }
+
+            put_u16(&mut glyf, n_contours as u16);
+
+            for &v in &bbox {
+                put_u16(&mut glyf, v as u16);
+            }
+
+            glyf.extend_from_slice(&composites.data[start..composites.pos]);
+
+            if have_instructions {
+                let length = glyph_stream.u255_16()?;
+                put_u16(&mut glyf, length);
+                glyf.extend_from_slice(instructions.bytes(length as usize)?);
+            }
+
+            x_mins.push(bbox[0]);
+            pad4(&mut glyf);
+            continue;
+        }
+
+        let mut end_points = Vec::with_capacity(n_contours as usize);
+        let mut n_points = 0usize;
+
+        for _ in 0..n_contours {
+            n_points += n_points_stream.u255_16()? as usize;
+
+            if n_points > 0xffff {
+                return Err(errmsg!("too many points in glyph in WOFF2 data"));
+            }
+
+            end_points.push((n_points as u16).wrapping_sub(1));
+        }
+
+        let mut points = Vec::with_capacity(n_points);
+        let (mut x, mut y) = (0i32, 0i32);
+
+        for &flag in flag_stream.bytes(n_points)? {
+            let (dx, dy) = decode_triplet(flag & 0x7f, &mut glyph_stream)?;
+            x += dx;
+            y += dy;
+            points.push((x, y, flag & 0x80 == 0));
+        }
+
+        let instruction_length = glyph_stream.u255_16()?;
+        let bbox = if has_bbox {
+            read_bbox(&mut bboxes)?
+        } else if points.is_empty() {
+            [0; 4]
+        } else {
+            let mut bbox = [
+                i16::max_value(),
+                i16::max_value(),
+                i16::min_value(),
+                i16::min_value(),
+            ];
+
+            for &(x, y, _) in &points {
+                bbox[0] = bbox[0].min(x as i16);
+                bbox[1] = bbox[1].min(y as i16);
+                bbox[2] = bbox[2].max(x as i16);
+                bbox[3] = bbox[3].max(y as i16);
+            }
+
+            bbox
+        };
+
+        put_u16(&mut glyf, n_contours as u16);
+
+        for &v in &bbox {
+            put_u16(&mut glyf, v as u16);
+        }
+
+        for &end in &end_points {
+            put_u16(&mut glyf, end);
+        }
+
+        put_u16(&mut glyf, instruction_length);
+        glyf.extend_from_slice(instructions.bytes(instruction_length as usize)?);
+
+        let overlap = overlap_bitmap.map_or(false, |b| b[i >> 3] & (0x80 >> (i & 7)) != 0);
+        store_points(&points, overlap, &mut glyf);
+        x_mins.push(bbox[0]);
+        pad4(&mut glyf);
+    }
+
+    loca_offsets.push(glyf.len());
+
+    let mut loca = Vec::with_capacity(loca_offsets.len() * 4);
+
+    for offset in loca_offsets {
+        if index_format == 0 {
+            if offset > 0x1fffe {
+                return Err(errmsg!("glyf table too large for short loca in WOFF2 data"));
+            }
+
+            put_u16(&mut loca, (offset >> 1) as u16);
+        } else {
+            put_u32(&mut loca, offset as u32);
+        }
+    }
+
+    Ok(Glyphs { glyf, loca, x_mins })
+}
+
+/// Decode the coordinate deltas of one point of a simple glyph, as described
+/// by its flag (with the on-curve bit masked off).
+fn decode_triplet(flag: u8, stream: &mut Reader) -> Result<(i32, i32)> {
+    fn with_sign(flag: u8, value: i32) -> i32 {
+        if flag & 1 != 0 {
+            value
+        } else {
+            -value
+        }
+    }
+
+    let f = i32::from(flag);
+
+    Ok(if flag < 10 {
+        let b = i32::from(stream.u8()?);
+        (0, with_sign(flag, ((f & 14) << 7) + b))
+    } else if flag < 20 {
+        let b = i32::from(stream.u8()?);
+        (with_sign(flag, (((f - 10) & 14) << 7) + b), 0)
+    } else if flag < 84 {
+        let b0 = f - 20;
+        let b1 = i32::from(stream.u8()?);
+        (
+            with_sign(flag, 1 + (b0 & 0x30) + (b1 >> 4)),
+            with_sign(flag >> 1, 1 + ((b0 & 0x0c) << 2) + (b1 & 0x0f)),
+        )
+    } else if flag < 120 {
+        let b0 = f - 84;
+        let b = stream.bytes(2)?;
+        (
+            with_sign(flag, 1 + ((b0 / 12) << 8) + i32::from(b[0])),
+            with_sign(flag >> 1, 1 + (((b0 % 12) >> 2) << 8) + i32::from(b[1])),
+        )
+    } else if flag < 124 {
+        let b = stream.bytes(3)?;
+        (
+            with_sign(flag, (i32::from(b[0]) << 4) + (i32::from(b[1]) >> 4)),
+            with_sign(flag >> 1, ((i32::from(b[1]) & 0x0f) << 8) + i32::from(b[2])),
+        )
+    } else {
+        let b = stream.bytes(4)?;
+        (
+            with_sign(flag, (i32::from(b[0]) << 8) + i32::from(b[1])),
+            with_sign(flag >> 1, (i32::from(b[2]) << 8) + i32::from(b[3])),
+        )
+    })
+}
+
+/// Write the flags and coordinates of the points of a simple glyph in the
+/// usual compact TrueType form.
+fn store_points(points: &[(i32, i32, bool)], overlap: bool, out: &mut Vec<u8>) {
+    let mut flags = Vec::with_capacity(points.len());
+    let mut xs = Vec::with_capacity(points.len());
+    let mut ys = Vec::with_capacity(points.len());
+    let (mut last_x, mut last_y) = (0, 0);
+    let mut last_flag = None;
+    let mut last_flag_index = 0;
+    let mut repeat = 0u8;
+
+    for (i, &(x, y, on_curve)) in points.iter().enumerate() {
+        let mut flag = if on_curve { 0x01 } else { 0x00 };
+
+        if overlap && i == 0 {
+            flag |= 0x40;
+        }
+
+        flag |= store_delta(x - last_x, &mut xs, 0x02, 0x10);
+        flag |= store_delta(y - last_y, &mut ys, 0x04, 0x20);
+
+        if last_flag == Some(flag) && repeat < 255 {
+            flags[last_flag_index] |= 0x08;
+
+            if repeat == 0 {
+                flags.push(1);
+            } else {
+                *flags.last_mut().unwrap() += 1;
+            }
+
+            repeat += 1;
+        } else {
+            last_flag = Some(flag);
+            last_flag_index = flags.len();
+            flags.push(flag);
+            repeat = 0;
+        }
+
+        last_x = x;
+        last_y = y;
+    }
+
+    out.extend_from_slice(&flags);
+    out.extend_from_slice(&xs);
+    out.extend_from_slice(&ys);
+}
+
+/// Store one coordinate delta of a glyph point, returning the bits it needs
+/// in the point's flag.
+fn store_delta(delta: i32, coords: &mut Vec<u8>, short_flag: u8, same_flag: u8) -> u8 {
+    if delta == 0 {
+        same_flag
+    } else if delta > -256 && delta < 256 {
+        coords.push(delta.abs() as u8);
+
+        if delta > 0 {
+            short_flag | same_flag
+        } else {
+            short_flag
+        }
+    } else {
+        put_u16(coords, delta as u16);
+        0
+    }
+}
+
+fn reconstruct_hmtx(data: &[u8], num_h_metrics: usize, x_mins: &[i16]) -> Result<Vec<u8>> {
+    let num_glyphs = x_mins.len();
+    let mut r = Reader::new(data);
+    let flags = r.u8()?;
+    let has_proportional_lsbs = flags & 1 == 0;
+    let has_monospace_lsbs = flags & 2 == 0;
+
+    if flags & 0xfc != 0
+        || (has_proportional_lsbs && has_monospace_lsbs)
+        || num_h_metrics == 0
+        || num_h_metrics > num_glyphs
+    {
+        return Err(errmsg!("bad hmtx transform in WOFF2 data"));
+    }
+
+    let mut advances = Vec::with_capacity(num_h_metrics);
+
+    for _ in 0..num_h_metrics {
+        advances.push(r.u16()?);
+    }
+
+    let mut out = Vec::with_capacity(2 * num_h_metrics + 2 * num_glyphs);
+
+    for (i, &x_min) in x_mins.iter().enumerate() {
+        let has_lsb = if i < num_h_metrics {
+            has_proportional_lsbs
+        } else {
+            has_monospace_lsbs
+        };
+        let lsb = if has_lsb { r.i16()? } else { x_min };
+
+        if i < num_h_metrics {
+            put_u16(&mut out, advances[i]);
+        }
+
+        put_u16(&mut out, lsb as u16);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variable_length_integers() {
+        assert_eq!(Reader::new(&[0x3f]).base128().unwrap(), 63);
+        assert_eq!(Reader::new(&[0x81, 0x00]).base128().unwrap(), 128);
+        assert!(Reader::new(&[0x80, 0x01]).base128().is_err());
+        assert!(Reader::new(&[0xff, 0xff, 0xff, 0xff, 0x7f])
+            .base128()
+            .is_err());
+
+        assert_eq!(Reader::new(&[252]).u255_16().unwrap(), 252);
+        assert_eq!(Reader::new(&[255, 0]).u255_16().unwrap(), 253);
+        assert_eq!(Reader::new(&[254, 0]).u255_16().unwrap(), 506);
+        assert_eq!(Reader::new(&[253, 0x01, 0x00]).u255_16().unwrap(), 256);
+    }
+
+    #[test]
+    fn triplets() {
+        let decode = |flag, bytes: &[u8]| decode_triplet(flag, &mut Reader::new(bytes)).unwrap();
+        assert_eq!(decode(0, &[5]), (0, -5));
+        assert_eq!(decode(11, &[5]), (5, 0));
+        assert_eq!(decode(23, &[0x12]), (2, 3));
+        assert_eq!(decode(124, &[0x01, 0x00, 0xff, 0xff]), (-256, -65535));
+    }
+
+    #[test]
+    fn woff_roundtrip() {
+        // A WOFF file with one stored and one compressed table.
+        let head = vec![0u8; 54];
+        let name = b"namenamenamenamenamenamenamenamename".to_vec();
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, &name).unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert!(compressed.len() < name.len());
+
+        let mut woff = Vec::new();
+        put_u32(&mut woff, WOFF_SIGNATURE);
+        put_u32(&mut woff, 0x0001_0000);
+        put_u32(&mut woff, 0); // length (unchecked)
+        put_u16(&mut woff, 2);
+        woff.extend_from_slice(&[0; 30]);
+        let data_start = woff.len() + 2 * 20;
+
+        put_u32(&mut woff, HEAD_TAG);
+        put_u32(&mut woff, data_start as u32);
+        put_u32(&mut woff, head.len() as u32);
+        put_u32(&mut woff, head.len() as u32);
+        put_u32(&mut woff, 0);
+        put_u32(&mut woff, 0x6e61_6d65); // "name"
+        put_u32(&mut woff, (data_start + 56) as u32);
+        put_u32(&mut woff, compressed.len() as u32);
+        put_u32(&mut woff, name.len() as u32);
+        put_u32(&mut woff, 0);
+        woff.extend_from_slice(&head);
+        pad4(&mut woff);
+        woff.extend_from_slice(&compressed);
+
+        assert!(is_woff(&woff));
+        let sfnt = decode(&woff).unwrap();
+
+        let mut r = Reader::new(&sfnt);
+        assert_eq!(r.u32().unwrap(), 0x0001_0000);
+        assert_eq!(r.u16().unwrap(), 2);
+        r.skip(6).unwrap();
+        assert_eq!(r.u32().unwrap(), HEAD_TAG);
+        r.skip(4).unwrap();
+        let head_offset = r.u32().unwrap() as usize;
+        assert_eq!(r.u32().unwrap(), 54);
+        assert_eq!(r.u32().unwrap(), 0x6e61_6d65);
+        r.skip(4).unwrap();
+        let name_offset = r.u32().unwrap() as usize;
+        assert_eq!(&sfnt[name_offset..name_offset + name.len()], &name[..]);
+        assert_eq!(&sfnt[head_offset + 12..head_offset + 54], &head[12..]);
+        assert_eq!(table_checksum(&sfnt), 0xb1b0_afba);
+    }
+}
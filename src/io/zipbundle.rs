@@ -72,6 +72,18 @@ impl<R: Read + Seek> IoProvider for ZipBundle<R> {
             InputOrigin::Other,
         ))
     }
+
+    fn input_names(&mut self) -> Vec<String> {
+        let mut names = Vec::with_capacity(self.zip.len());
+
+        for i in 0..self.zip.len() {
+            if let Ok(zipitem) = self.zip.by_index(i) {
+                names.push(zipitem.name().to_owned());
+            }
+        }
+
+        names
+    }
 }
 
 impl<R: Read + Seek> Bundle for ZipBundle<R> {}
@@ -56,13 +56,16 @@ pub mod engines;
 pub mod errors;
 pub mod io;
 pub mod status;
+pub mod synctex;
 
 // Note: this module is intentionally *not* gated by #[cfg(test)] -- see its
 // docstring for details.
 #[doc(hidden)]
 pub mod test_util;
 
+pub use crate::engines::biber::BiberEngine;
 pub use crate::engines::bibtex::BibtexEngine;
+pub use crate::engines::makeindex::MakeindexEngine;
 pub use crate::engines::spx2html::Spx2HtmlEngine;
 pub use crate::engines::tex::{TexEngine, TexResult};
 pub use crate::engines::xdvipdfmx::XdvipdfmxEngine;
@@ -0,0 +1,656 @@
+// src/synctex.rs -- reading and querying SyncTeX data
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Read SyncTeX files and answer source ↔ output queries.
+//!
+//! The TeX engine writes its synchronization data into a `.synctex.gz` file
+//! when SyncTeX support is enabled. This module parses that file into an
+//! in-memory tree and answers the two standard questions that editors and
+//! viewers ask:
+//!
+//! - *forward* (“view”) queries: given a source file and line, which page
+//!   and which rectangles on that page did it produce?
+//! - *inverse* (“edit”) queries: given a point on a page, which source file
+//!   and line produced the material there?
+//!
+//! All output-side coordinates are expressed in PostScript big points
+//! measured from the top-left corner of the page, the same convention used
+//! by the reference `synctex` tool.
+//!
+//! ```no_run
+//! use tectonic::synctex::SynctexData;
+//!
+//! let data = SynctexData::from_path("mydoc.synctex.gz").unwrap();
+//!
+//! for hit in data.forward("mydoc.tex", 12) {
+//!     println!("page {}: {:?}", hit.page, hit.rect);
+//! }
+//!
+//! if let Some(loc) = data.inverse(1, 72.0, 100.0) {
+//!     println!("{}:{}", loc.file, loc.line);
+//! }
+//! ```
+
+use flate2::read::GzDecoder;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::errmsg;
+use crate::errors::{Error, Result};
+
+/// The number of scaled points in one PostScript big point.
+const SP_PER_BP: f64 = 65536.0 * 72.27 / 72.0;
+
+/// The different kinds of records that can appear in a SyncTeX file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RecordKind {
+    /// A vertical list: `[...]`.
+    VBox,
+    /// A horizontal list: `(...)`.
+    HBox,
+    /// An empty vertical list: `v`.
+    VoidVBox,
+    /// An empty horizontal list: `h`.
+    VoidHBox,
+    /// A "current position" marker: `x`.
+    Current,
+    /// A kern: `k`.
+    Kern,
+    /// A glue item: `g`.
+    Glue,
+    /// A math node: `$`.
+    Math,
+    /// A rule: `r`.
+    Rule,
+    /// A reference to a form: `f`.
+    FormRef,
+}
+
+impl RecordKind {
+    fn is_box(self) -> bool {
+        match self {
+            RecordKind::VBox | RecordKind::HBox | RecordKind::VoidVBox | RecordKind::VoidHBox => {
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A rectangle on an output page, in big points, with the origin at the
+/// top-left corner of the page and *y* increasing downwards.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rect {
+    /// The horizontal position of the left edge.
+    pub x: f64,
+    /// The vertical position of the top edge.
+    pub y: f64,
+    /// The width of the rectangle.
+    pub width: f64,
+    /// The height of the rectangle.
+    pub height: f64,
+}
+
+impl Rect {
+    fn area(&self) -> f64 {
+        self.width * self.height
+    }
+
+    /// The distance from a point to this rectangle; zero if the point is inside.
+    fn distance_to(&self, x: f64, y: f64) -> f64 {
+        let dx = if x < self.x {
+            self.x - x
+        } else if x > self.x + self.width {
+            x - (self.x + self.width)
+        } else {
+            0.
+        };
+
+        let dy = if y < self.y {
+            self.y - y
+        } else if y > self.y + self.height {
+            y - (self.y + self.height)
+        } else {
+            0.
+        };
+
+        (dx * dx + dy * dy).sqrt()
+    }
+}
+
+/// One record from the content section of a SyncTeX file.
+///
+/// Dimensions are stored exactly as they appear in the file; use
+/// [`SynctexData::record_rect`] to convert them to page coordinates.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    /// What sort of record this is.
+    pub kind: RecordKind,
+    /// The tag identifying the input file that produced this record.
+    pub tag: i32,
+    /// The line number in the input file.
+    pub line: i32,
+    /// The horizontal position, in SyncTeX units.
+    pub h: i32,
+    /// The vertical position of the baseline, in SyncTeX units.
+    pub v: i32,
+    /// The width, in SyncTeX units. Zero for records that have none.
+    pub width: i32,
+    /// The height, in SyncTeX units. Zero for records that have none.
+    pub height: i32,
+    /// The depth, in SyncTeX units. Zero for records that have none.
+    pub depth: i32,
+    /// The (1-based) number of the page on which this record appears.
+    pub page: i32,
+    /// The index of the enclosing box record, if any.
+    pub parent: Option<usize>,
+}
+
+/// The answer to a forward query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForwardResult {
+    /// The (1-based) page number.
+    pub page: i32,
+    /// The rectangle on that page.
+    pub rect: Rect,
+}
+
+/// The answer to an inverse query.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InverseResult {
+    /// The name of the input file, as recorded by the engine.
+    pub file: String,
+    /// The line number in that file.
+    pub line: i32,
+}
+
+/// The parsed contents of a SyncTeX file.
+#[derive(Clone, Debug, Default)]
+pub struct SynctexData {
+    /// The input files, keyed by their tags.
+    pub inputs: HashMap<i32, String>,
+    /// The output format recorded by the engine (e.g. `"pdf"` or `"xdv"`).
+    pub output: String,
+    magnification: f64,
+    unit: f64,
+    x_offset: f64,
+    y_offset: f64,
+    records: Vec<Record>,
+}
+
+impl SynctexData {
+    /// Read SyncTeX data from a file on disk. If the file name ends in
+    /// `.gz`, it will be decompressed.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<SynctexData> {
+        let path = path.as_ref();
+        let f = File::open(path)?;
+
+        if path.extension().map(|e| e == "gz").unwrap_or(false) {
+            SynctexData::parse(BufReader::new(GzDecoder::new(f)))
+        } else {
+            SynctexData::parse(BufReader::new(f))
+        }
+    }
+
+    /// Read SyncTeX data from an in-memory buffer, such as the contents of a
+    /// `.synctex.gz` file produced by a processing session that was told not
+    /// to write its outputs to disk. Gzipped data are detected automatically.
+    pub fn from_bytes(data: &[u8]) -> Result<SynctexData> {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            SynctexData::parse(BufReader::new(GzDecoder::new(data)))
+        } else {
+            SynctexData::parse(data)
+        }
+    }
+
+    /// Parse uncompressed SyncTeX data from a stream.
+    pub fn parse<R: BufRead>(mut reader: R) -> Result<SynctexData> {
+        let mut data = SynctexData {
+            magnification: 1000.,
+            unit: 1.,
+            ..Default::default()
+        };
+
+        let mut stack: Vec<usize> = Vec::new();
+        let mut page = 0;
+        let mut in_content = false;
+        let mut line_no = 0;
+        let mut buf = Vec::new();
+
+        loop {
+            buf.clear();
+
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+
+            line_no += 1;
+            let text = String::from_utf8_lossy(&buf);
+            let text = text.trim_end_matches(|c| c == '\n' || c == '\r');
+
+            if line_no == 1 {
+                if !text.starts_with("SyncTeX Version:") {
+                    return Err(errmsg!("not a SyncTeX file (bad preamble)"));
+                }
+                continue;
+            }
+
+            if text.is_empty() {
+                continue;
+            }
+
+            // "Input:" lines may appear anywhere, since files can be opened
+            // after the content has started.
+            if text.starts_with("Input:") {
+                let rest = &text[6..];
+                let mut pieces = rest.splitn(2, ':');
+                let tag = pieces.next().unwrap_or("").parse::<i32>();
+                let name = pieces.next();
+
+                match (tag, name) {
+                    (Ok(tag), Some(name)) => {
+                        data.inputs.insert(tag, name.to_owned());
+                    }
+                    _ => {
+                        return Err(errmsg!(
+                            "malformed SyncTeX input record at line {}",
+                            line_no
+                        ));
+                    }
+                }
+                continue;
+            }
+
+            if !in_content {
+                if text == "Content:" {
+                    in_content = true;
+                } else if let Some(v) = header_value(text, "Output:") {
+                    data.output = v.to_owned();
+                } else if let Some(v) = header_value(text, "Magnification:") {
+                    data.magnification = parse_number(v, line_no)?;
+                } else if let Some(v) = header_value(text, "Unit:") {
+                    data.unit = parse_number(v, line_no)?;
+                } else if let Some(v) = header_value(text, "X Offset:") {
+                    data.x_offset = parse_number(v, line_no)?;
+                } else if let Some(v) = header_value(text, "Y Offset:") {
+                    data.y_offset = parse_number(v, line_no)?;
+                }
+                // Unrecognized preamble lines are ignored, as the format
+                // says they should be.
+                continue;
+            }
+
+            if text.starts_with("Postamble:") {
+                break;
+            }
+
+            let first_len = text.chars().next().map_or(1, |c| c.len_utf8());
+            let (first, rest) = text.split_at(first_len);
+
+            match first {
+                "!" => {} // byte-offset anchors; we don't need them
+                "{" => {
+                    page = parse_number(rest, line_no)? as i32;
+                    stack.clear();
+                }
+                "}" => {
+                    stack.clear();
+                }
+                "<" | ">" => {} // form definitions; not tied to a page
+                "]" | ")" => {
+                    stack.pop();
+                }
+                _ => {
+                    let kind = match first {
+                        "[" => RecordKind::VBox,
+                        "(" => RecordKind::HBox,
+                        "v" => RecordKind::VoidVBox,
+                        "h" => RecordKind::VoidHBox,
+                        "x" => RecordKind::Current,
+                        "k" => RecordKind::Kern,
+                        "g" => RecordKind::Glue,
+                        "$" => RecordKind::Math,
+                        "r" => RecordKind::Rule,
+                        "f" => RecordKind::FormRef,
+                        _ => {
+                            // Be liberal in what we accept: newer engines
+                            // may emit record types that we don't know.
+                            continue;
+                        }
+                    };
+
+                    let mut record = parse_record(kind, rest, line_no)?;
+                    record.page = page;
+                    record.parent = stack.last().cloned();
+                    data.records.push(record);
+
+                    if kind == RecordKind::VBox || kind == RecordKind::HBox {
+                        stack.push(data.records.len() - 1);
+                    }
+                }
+            }
+        }
+
+        if !in_content {
+            return Err(errmsg!("SyncTeX file has no content section"));
+        }
+
+        Ok(data)
+    }
+
+    /// Get all of the content records, in the order in which they appear in
+    /// the file.
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// Convert a record's position and dimensions to a rectangle on its
+    /// page.
+    pub fn record_rect(&self, r: &Record) -> Rect {
+        let x = self.to_bp(r.h) + self.x_offset_bp();
+        let baseline = self.to_bp(r.v) + self.y_offset_bp();
+        let height = self.to_bp(r.height);
+
+        Rect {
+            x,
+            y: baseline - height,
+            width: self.to_bp(r.width),
+            height: height + self.to_bp(r.depth),
+        }
+    }
+
+    fn to_bp(&self, value: i32) -> f64 {
+        f64::from(value) * self.unit * self.magnification / 1000. / SP_PER_BP
+    }
+
+    fn x_offset_bp(&self) -> f64 {
+        self.x_offset * self.unit / SP_PER_BP
+    }
+
+    fn y_offset_bp(&self) -> f64 {
+        self.y_offset * self.unit / SP_PER_BP
+    }
+
+    /// Find the tags that correspond to a given input file name.
+    ///
+    /// An exact match is preferred. Otherwise, names that end with the query
+    /// (as a path) match, and finally names with the same final path
+    /// component. This allows callers to use either the name that TeX saw or
+    /// the full path that an editor knows about.
+    pub fn tags_for_file(&self, file: &str) -> Vec<i32> {
+        let exact: Vec<i32> = self
+            .inputs
+            .iter()
+            .filter(|(_, name)| name.as_str() == file)
+            .map(|(tag, _)| *tag)
+            .collect();
+
+        if !exact.is_empty() {
+            return exact;
+        }
+
+        let query = Path::new(file);
+
+        let suffix: Vec<i32> = self
+            .inputs
+            .iter()
+            .filter(|(_, name)| {
+                let name = Path::new(name.as_str());
+                query.ends_with(name) || name.ends_with(query)
+            })
+            .map(|(tag, _)| *tag)
+            .collect();
+
+        if !suffix.is_empty() {
+            return suffix;
+        }
+
+        match query.file_name() {
+            Some(base) => self
+                .inputs
+                .iter()
+                .filter(|(_, name)| Path::new(name.as_str()).file_name() == Some(base))
+                .map(|(tag, _)| *tag)
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Perform a forward query: given an input file and a (1-based) line
+    /// number, find the output rectangles that it produced.
+    ///
+    /// If no material was produced by the exact line, the next line with
+    /// material is used instead, mirroring the behavior of the reference
+    /// implementation. The results are ordered by page and deduplicated.
+    pub fn forward(&self, file: &str, line: i32) -> Vec<ForwardResult> {
+        let tags = self.tags_for_file(file);
+
+        if tags.is_empty() {
+            return Vec::new();
+        }
+
+        let mut candidates: Vec<&Record> = self
+            .records
+            .iter()
+            .filter(|r| tags.contains(&r.tag) && r.line >= line)
+            .collect();
+
+        let best_line = match candidates.iter().map(|r| r.line).min() {
+            Some(l) => l,
+            None => {
+                // Past the last line that produced anything: fall back to the
+                // closest preceding line.
+                candidates = self
+                    .records
+                    .iter()
+                    .filter(|r| tags.contains(&r.tag) && r.line < line)
+                    .collect();
+
+                match candidates.iter().map(|r| r.line).max() {
+                    Some(l) => l,
+                    None => return Vec::new(),
+                }
+            }
+        };
+
+        let mut results: Vec<ForwardResult> = Vec::new();
+        let matches: Vec<&Record> = candidates
+            .into_iter()
+            .filter(|r| r.line == best_line)
+            .collect();
+
+        // Vertical boxes tend to enclose a lot of unrelated material, so only
+        // report them if nothing more specific is available.
+        let only_vboxes = matches.iter().all(|r| r.kind == RecordKind::VBox);
+
+        for r in matches {
+            if r.kind == RecordKind::VBox && !only_vboxes {
+                continue;
+            }
+
+            // Non-box records are reported using the box that contains them,
+            // since they don't have useful extents of their own.
+            let boxrec = if r.kind.is_box() {
+                r
+            } else {
+                match r.parent {
+                    Some(idx) => &self.records[idx],
+                    None => continue,
+                }
+            };
+
+            let hit = ForwardResult {
+                page: r.page,
+                rect: self.record_rect(boxrec),
+            };
+
+            if !results.contains(&hit) {
+                results.push(hit);
+            }
+        }
+
+        results.sort_by(|a, b| {
+            a.page.cmp(&b.page).then(
+                a.rect
+                    .y
+                    .partial_cmp(&b.rect.y)
+                    .unwrap_or(std::cmp::Ordering::Equal),
+            )
+        });
+        results
+    }
+
+    /// Perform an inverse query: given a (1-based) page number and a point
+    /// on that page, in big points from the top-left corner, find the source
+    /// location that produced the material there.
+    ///
+    /// We look for the smallest horizontal box containing the point (or the
+    /// closest one, if none contains it), and then for the node within that
+    /// box that is closest to the point.
+    pub fn inverse(&self, page: i32, x: f64, y: f64) -> Option<InverseResult> {
+        let mut best_box: Option<(usize, f64, f64)> = None; // (index, distance, area)
+
+        for (i, r) in self.records.iter().enumerate() {
+            if r.page != page || !r.kind.is_box() {
+                continue;
+            }
+
+            let rect = self.record_rect(r);
+            let dist = rect.distance_to(x, y);
+            let area = rect.area();
+
+            let better = match best_box {
+                None => true,
+                Some((_, bdist, barea)) => dist < bdist || (dist == bdist && area < barea),
+            };
+
+            if better {
+                best_box = Some((i, dist, area));
+            }
+        }
+
+        let (box_idx, _, _) = best_box?;
+
+        // Now find the closest child of that box, if any. Nodes that aren't
+        // boxes only have a horizontal position that's meaningful.
+        let mut best_child: Option<(usize, f64)> = None;
+
+        for (i, r) in self.records.iter().enumerate() {
+            if r.parent != Some(box_idx) || r.tag == 0 {
+                continue;
+            }
+
+            let rect = self.record_rect(r);
+            let dist = if r.kind.is_box() || r.kind == RecordKind::Rule {
+                rect.distance_to(x, y)
+            } else {
+                (rect.x - x).abs()
+            };
+
+            if best_child.map(|(_, d)| dist < d).unwrap_or(true) {
+                best_child = Some((i, dist));
+            }
+        }
+
+        let rec = match best_child {
+            Some((i, _)) => &self.records[i],
+            None => &self.records[box_idx],
+        };
+
+        self.inputs.get(&rec.tag).map(|file| InverseResult {
+            file: file.clone(),
+            line: rec.line,
+        })
+    }
+
+    /// Get the numbers of all of the pages described in this file, in
+    /// ascending order.
+    pub fn pages(&self) -> Vec<i32> {
+        let pages: BTreeSet<i32> = self.records.iter().map(|r| r.page).collect();
+        pages.into_iter().collect()
+    }
+}
+
+fn header_value<'a>(text: &'a str, key: &str) -> Option<&'a str> {
+    if text.starts_with(key) {
+        Some(text[key.len()..].trim())
+    } else {
+        None
+    }
+}
+
+fn parse_number(text: &str, line_no: usize) -> Result<f64> {
+    text.trim().parse::<f64>().map_err::<Error, _>(|_| {
+        errmsg!(
+            "bad number \"{}\" in SyncTeX data at line {}",
+            text,
+            line_no
+        )
+    })
+}
+
+fn parse_int(text: &str, line_no: usize) -> Result<i32> {
+    text.trim().parse::<i32>().map_err::<Error, _>(|_| {
+        errmsg!(
+            "bad integer \"{}\" in SyncTeX data at line {}",
+            text,
+            line_no
+        )
+    })
+}
+
+/// Parse the body of a content record: `tag,line:h,v[:W[,H,D]]`.
+fn parse_record(kind: RecordKind, text: &str, line_no: usize) -> Result<Record> {
+    let mut sections = text.split(':');
+
+    let link = sections.next().unwrap_or("");
+    let pos = sections
+        .next()
+        .ok_or_else::<Error, _>(|| errmsg!("incomplete SyncTeX record at line {}", line_no))?;
+    let size = sections.next();
+
+    let mut link = link.split(',');
+    let tag = parse_int(link.next().unwrap_or(""), line_no)?;
+    let line = match link.next() {
+        Some(l) => parse_int(l, line_no)?,
+        None => 0,
+    };
+
+    let mut pos = pos.split(',');
+    let h = parse_int(pos.next().unwrap_or(""), line_no)?;
+    let v = match pos.next() {
+        Some(t) => parse_int(t, line_no)?,
+        None => 0,
+    };
+
+    let (mut width, mut height, mut depth) = (0, 0, 0);
+
+    if let Some(size) = size {
+        let mut size = size.split(',');
+        width = parse_int(size.next().unwrap_or(""), line_no)?;
+
+        if let Some(t) = size.next() {
+            height = parse_int(t, line_no)?;
+        }
+
+        if let Some(t) = size.next() {
+            depth = parse_int(t, line_no)?;
+        }
+    }
+
+    Ok(Record {
+        kind,
+        tag,
+        line,
+        h,
+        v,
+        width,
+        height,
+        depth,
+        page: 0,
+        parent: None,
+    })
+}
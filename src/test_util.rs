@@ -42,7 +42,7 @@ use std::path::PathBuf;
 
 use crate::digest::DigestData;
 use crate::errors::Result;
-use crate::io::{Bundle, FilesystemIo, InputHandle, IoProvider, OpenResult};
+use crate::io::{Bundle, FilesystemIo, InputHandle, InputOrigin, IoProvider, OpenResult};
 use crate::status::StatusBackend;
 
 /// The name of the environment variable that the test code will consult to
@@ -122,7 +122,19 @@ impl IoProvider for TestBundle {
         name: &OsStr,
         status: &mut dyn StatusBackend,
     ) -> OpenResult<InputHandle> {
-        self.0.input_open_name(name, status)
+        // The files live on the filesystem, but they stand in for those of a
+        // real bundle, which are not project files.
+        match self.0.input_open_name(name, status) {
+            OpenResult::Ok(ih) => {
+                let name = ih.name().to_owned();
+                OpenResult::Ok(InputHandle::new(&name, ih, InputOrigin::Other))
+            }
+            r => r,
+        }
+    }
+
+    fn input_names(&mut self) -> Vec<String> {
+        self.0.input_names()
     }
 }
 
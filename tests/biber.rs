@@ -0,0 +1,93 @@
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+use std::collections::HashSet;
+use std::ffi::OsStr;
+
+use tectonic::engines::NoopIoEventBackend;
+use tectonic::io::{FilesystemIo, IoProvider, IoStack, MemoryIo};
+use tectonic::status::NoopStatusBackend;
+use tectonic::{BiberEngine, TexResult};
+
+#[path = "util/mod.rs"]
+mod util;
+use crate::util::test_path;
+
+fn run(stem: &str) -> (TexResult, String, String) {
+    util::set_test_root();
+
+    let mut mem = MemoryIo::new(true);
+    let mut assets = FilesystemIo::new(&test_path(&["biber"]), false, false, HashSet::new());
+
+    let result = {
+        let io_list: Vec<&mut dyn IoProvider> = vec![&mut mem, &mut assets];
+        let mut io = IoStack::new(io_list);
+        let mut events = NoopIoEventBackend::new();
+        let mut status = NoopStatusBackend::new();
+
+        BiberEngine::new()
+            .process(&mut io, &mut events, &mut status, &format!("{}.bcf", stem))
+            .unwrap()
+    };
+
+    let files = mem.files.borrow();
+    let get = |ext: &str| {
+        let name = format!("{}.{}", stem, ext);
+        String::from_utf8(files.get(OsStr::new(&name)).unwrap().clone()).unwrap()
+    };
+
+    (result, get("bbl"), get("blg"))
+}
+
+#[test]
+fn basic() {
+    let (result, bbl, blg) = run("basic");
+
+    // One undefined macro and one missing citation.
+    assert_eq!(result, TexResult::Warnings);
+    assert!(blg.contains("\"undefinedmacro\" is undefined"));
+    assert!(blg.contains("\"nosuchkey\""));
+
+    assert!(bbl.contains("% $ biblatex bbl format version 3.1 $"));
+    assert!(bbl.contains("\\providecommand{\\noop}[1]{}%"));
+    assert!(bbl.contains("  \\datalist[entry]{nyt/global//global/global}\n"));
+    assert!(bbl.contains("  \\missing{nosuchkey}\n"));
+
+    // Sorted by name, then year, then title; the cross-referenced
+    // proceedings are included since two entries cite them.
+    let order: Vec<&str> = bbl
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("\\entry{"))
+        .map(|l| &l[..l.find('}').unwrap()])
+        .collect();
+    assert_eq!(
+        order,
+        vec![
+            "chapter1",
+            "chapter2",
+            "knuth84b",
+            "knuth84",
+            "procs",
+            "vanderwaals"
+        ]
+    );
+
+    // Names, lists, dates, ranges, and legacy field mappings.
+    assert!(bbl.contains("           family={Waals},\n"));
+    assert!(bbl.contains("           prefix={van der},\n"));
+    assert!(bbl.contains("      \\true{moreauthor}\n"));
+    assert!(bbl.contains("        {TeX Users Group}%\n"));
+    assert!(bbl.contains("      \\field{journaltitle}{The Computer Journal}\n"));
+    assert!(bbl.contains("      \\field{month}{5}\n"));
+    assert!(bbl.contains("      \\field{pages}{97\\bibrangedash 111}\n"));
+    assert!(bbl.contains("      \\range{pages}{15}\n"));
+    assert!(bbl.contains("      \\verb 10.1093/comjnl/27.2.97\n"));
+    assert!(bbl.contains("      \\keyw{tex,typesetting}\n"));
+
+    // Crossref inheritance.
+    assert!(bbl.contains("      \\strng{crossref}{procs}\n"));
+    assert!(bbl.contains("      \\field{booktitle}{Proceedings of Something}\n"));
+
+    // Both Knuth 1984 entries get an extradate.
+    assert!(bbl.contains("      \\field{extradate}{2}\n"));
+}
@@ -3,6 +3,7 @@
 
 use std::collections::HashSet;
 
+use tectonic::engines::bibtex::{parse_blg, BibtexDiagnosticKind, BibtexSeverity};
 use tectonic::engines::NoopIoEventBackend;
 use tectonic::io::stdstreams::GenuineStdoutIo;
 use tectonic::io::{FilesystemIo, IoProvider, IoStack, MemoryIo};
@@ -48,9 +49,11 @@ impl TestCase {
         let mut events = NoopIoEventBackend::new();
         let mut status = NoopStatusBackend::new();
 
-        BibtexEngine::new()
+        let mut engine = BibtexEngine::new();
+        engine
             .process(&mut io, &mut events, &mut status, &auxname)
             .unwrap();
+        assert!(engine.diagnostics().is_empty());
 
         // Check that outputs match expectations.
 
@@ -68,3 +71,49 @@ impl TestCase {
 fn single_entry() {
     TestCase::new("single_entry").go()
 }
+
+#[test]
+fn blg_diagnostics() {
+    let blg = "\
+This is BibTeX, Version 0.99d
+Database file #1: refs.bib
+Warning--string name \"jgr\" is undefined
+--line 12 of filerefs.bib
+I was expecting a `,' or a `}'---line 20 of filerefs.bib
+ : @article{smith,
+ :                 title = {Oops}
+I'm skipping whatever remains of this entry
+Warning--I didn't find a database entry for \"nobody2020\"
+Warning--empty journal in jones
+(There were 3 warnings)
+";
+
+    let diags = parse_blg(blg);
+    assert_eq!(diags.len(), 4);
+
+    assert_eq!(diags[0].kind, BibtexDiagnosticKind::UndefinedString);
+    assert_eq!(diags[0].key.as_deref(), Some("jgr"));
+    assert_eq!(diags[0].file.as_deref(), Some("refs.bib"));
+    assert_eq!(diags[0].line, Some(12));
+
+    assert_eq!(diags[1].severity, BibtexSeverity::Error);
+    assert_eq!(diags[1].kind, BibtexDiagnosticKind::MalformedEntry);
+    assert_eq!(diags[1].line, Some(20));
+    assert_eq!(
+        diags[1].to_string(),
+        "refs.bib:20: I was expecting a `,' or a `}'"
+    );
+
+    assert_eq!(diags[2].kind, BibtexDiagnosticKind::MissingEntry);
+    assert_eq!(diags[2].key.as_deref(), Some("nobody2020"));
+    assert_eq!(diags[2].file, None);
+
+    assert_eq!(diags[3].kind, BibtexDiagnosticKind::StyleWarning);
+    assert_eq!(diags[3].key.as_deref(), Some("jones"));
+
+    // Other BibTeX implementations put a space before the file name.
+    let diags =
+        parse_blg("Warning--string name \"jgr\" is undefined\n--line 12 of file refs.bib\n");
+    assert_eq!(diags[0].file.as_deref(), Some("refs.bib"));
+    assert_eq!(diags[0].line, Some(12));
+}
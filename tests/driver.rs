@@ -8,15 +8,239 @@
 //! ProcessingSessionBuilder will need to learn how to tell `xdvipdfmx` to
 //! enable the reproducibility options used in the `tex-outputs` test rig.
 
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+
 use tectonic::config::PersistentConfig;
-use tectonic::driver::ProcessingSessionBuilder;
+use tectonic::driver::{
+    FontOrigin, FontResolutionMode, OutputFormat, PassSetting, ProcessingSession,
+    ProcessingSessionBuilder,
+};
+use tectonic::engines::xdvipdfmx::{PdfAConformance, PdfEncryption};
 use tectonic::status::termcolor::TermcolorStatusBackend;
 use tectonic::status::ChatterLevel;
+use tectonic::synctex::SynctexData;
 
 mod util;
 
 // Keep these alphabetized.
 
+/// Run the default passes on a document in `tests/biber`, keeping all of the
+/// outputs in memory.
+fn run_biber_document(stem: &str, pass: PassSetting) -> HashMap<OsString, Vec<u8>> {
+    util::set_test_root();
+
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+    let tex_name = format!("{}.tex", stem);
+
+    let mut pbuilder = ProcessingSessionBuilder::default();
+    pbuilder
+        .primary_input_path(util::test_path(&["biber", tex_name.as_str()]))
+        .tex_input_name(&tex_name)
+        .format_name("plain")
+        .format_cache_path(util::test_path(&[]))
+        .output_format(OutputFormat::Xdv)
+        .pass(pass)
+        .do_not_write_output_files()
+        .bundle(Box::new(util::TestBundle::default()));
+
+    let mut session = pbuilder
+        .create(&mut status)
+        .expect("couldn't create processing session");
+
+    session
+        .run(&mut status)
+        .expect("failed to execute processing session");
+
+    session.into_file_data()
+}
+
+/// Run the default passes on `source`, in a project directory that has a
+/// copy of Latin Modern Roman named `project-font.otf`, keeping all of the
+/// outputs in memory.
+fn run_with_project_font(source: &str, mode: FontResolutionMode) -> ProcessingSession {
+    util::set_test_root();
+
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+
+    let tempdir = tempfile::Builder::new()
+        .prefix("tectonic_driver_test")
+        .tempdir()
+        .unwrap();
+    fs::copy(
+        util::test_path(&["assets", "lmroman12-regular.otf"]),
+        tempdir.path().join("project-font.otf"),
+    )
+    .unwrap();
+    let tex_path = tempdir.path().join("fonts.tex");
+    fs::write(&tex_path, source).unwrap();
+
+    let mut pbuilder = ProcessingSessionBuilder::default();
+    pbuilder
+        .primary_input_path(&tex_path)
+        .tex_input_name("fonts.tex")
+        .format_name("plain")
+        .format_cache_path(util::test_path(&[]))
+        .font_resolution(mode)
+        .do_not_write_output_files()
+        .bundle(Box::new(util::TestBundle::default()));
+
+    let mut session = pbuilder
+        .create(&mut status)
+        .expect("couldn't create processing session");
+
+    session
+        .run(&mut status)
+        .expect("failed to execute processing session");
+
+    session
+}
+
+#[test]
+fn biber_bibtex_first() {
+    // With a .bcf file left by a previous run, biber should be chosen even
+    // though the bibliography tool runs before TeX.
+    let files = run_biber_document("basic", PassSetting::BibtexFirst);
+    let bbl = String::from_utf8(files[OsStr::new("basic.bbl")].clone()).unwrap();
+    assert!(bbl.contains("% $ biblatex bbl format version"));
+    assert!(bbl.contains("\\entry{knuth84}"));
+}
+
+#[test]
+fn biber_rerun_on_bcf_change() {
+    // The .bcf file of the second TeX pass cites a different entry, so biber
+    // must run again.
+    let files = run_biber_document("bcf_change", PassSetting::Default);
+    let bbl = String::from_utf8(files[OsStr::new("bcf_change.bbl")].clone()).unwrap();
+    assert!(bbl.contains("\\entry{vanderwaals}"));
+    assert!(!bbl.contains("\\entry{knuth84}"));
+}
+
+#[cfg(not(target_os = "macos"))]
+#[test]
+fn font_by_name_hermetic() {
+    // Without host fonts, the font can only come from the project file,
+    // whose name has nothing to do with the name of the font.
+    let session = run_with_project_font(
+        "\\font\\x=\"LM Roman 12\" at 10pt \\x A\\bye\n",
+        FontResolutionMode::Hermetic,
+    );
+
+    let resolution = session
+        .font_resolutions()
+        .iter()
+        .find(|r| r.name == "LM Roman 12")
+        .expect("the font was not looked up by name");
+    assert_eq!(resolution.backend, tectonic::engines::tex::FontBackend::Io);
+    assert!(resolution.file.contains("project-font.otf"));
+
+    let report = session.font_report();
+    assert!(report
+        .fonts
+        .iter()
+        .any(|r| r.font.font_name == "LMRoman12-Regular" && r.origin == FontOrigin::Project));
+}
+
+#[cfg(target_os = "macos")]
+#[test]
+fn font_by_name_hermetic() {
+    util::set_test_root();
+
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+
+    let mut pbuilder = ProcessingSessionBuilder::default();
+    pbuilder
+        .primary_input_path(util::test_path(&["tex-outputs", "the_letter_a.tex"]))
+        .tex_input_name("the_letter_a.tex")
+        .format_name("plain")
+        .format_cache_path(util::test_path(&[]))
+        .font_resolution(FontResolutionMode::Hermetic)
+        .do_not_write_output_files()
+        .bundle(Box::new(util::TestBundle::default()));
+
+    let err = pbuilder
+        .create(&mut status)
+        .err()
+        .expect("hermetic font resolution was accepted on macOS");
+    assert!(err.to_string().contains("macOS"));
+}
+
+#[test]
+fn font_report() {
+    // An OpenType font that ships with the project, next to the bundle's
+    // Computer Modern; the former has no CJK glyphs.
+    let session = run_with_project_font(
+        "\\font\\project=\"[project-font.otf]\" at 10pt \\project A\\char\"4E00\\relax \\rm B\\bye\n",
+        FontResolutionMode::HostAllowed,
+    );
+
+    let report = session.font_report();
+
+    assert!(report
+        .missing_chars
+        .iter()
+        .any(|m| m.code == 0x4E00 && m.font.contains("project-font")));
+
+    let project = report
+        .fonts
+        .iter()
+        .find(|r| r.font.font_name == "LMRoman12-Regular")
+        .expect("the project font is not in the report");
+    assert_eq!(project.origin, FontOrigin::Project);
+    assert!(project.font.embedded);
+    assert!(project.font.subset_tag.is_some());
+
+    let bundled = report
+        .fonts
+        .iter()
+        .find(|r| r.font.font_name == "CMR10")
+        .expect("the bundle font is not in the report");
+    assert_eq!(bundled.origin, FontOrigin::Bundle);
+    assert!(bundled.font.embedded);
+    assert!(bundled.font.subset_tag.is_some());
+    assert_eq!(
+        bundled
+            .font
+            .opened_as
+            .as_ref()
+            .map(|(name, _)| name.as_str()),
+        Some("cmr10.pfb")
+    );
+}
+
+#[test]
+fn pdfa_violation_drops_pdf() {
+    util::set_test_root();
+
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+
+    // PDF/A does not allow encryption, so xdvipdfmx fails after writing the
+    // whole document.
+    let mut pbuilder = ProcessingSessionBuilder::default();
+    pbuilder
+        .primary_input_path(util::test_path(&["tex-outputs", "the_letter_a.tex"]))
+        .tex_input_name("the_letter_a.tex")
+        .format_name("plain")
+        .format_cache_path(util::test_path(&[]))
+        .pdfa(Some(PdfAConformance::A2b))
+        .encryption(Some(PdfEncryption::default()))
+        .do_not_write_output_files()
+        .bundle(Box::new(util::TestBundle::default()));
+
+    let mut session = pbuilder
+        .create(&mut status)
+        .expect("couldn't create processing session");
+
+    let err = session
+        .run(&mut status)
+        .expect_err("PDF/A violations were not reported");
+    assert!(err.to_string().contains("PDF/A"));
+
+    let files = session.into_file_data();
+    assert!(!files.contains_key(OsStr::new("the_letter_a.pdf")));
+}
+
 #[test]
 fn the_letter_a() {
     util::set_test_root();
@@ -50,3 +274,43 @@ fn the_letter_a() {
         .run(&mut status)
         .expect("failed to execute processing session");
 }
+
+#[test]
+fn synctex_from_buffer() {
+    util::set_test_root();
+
+    let mut status = TermcolorStatusBackend::new(ChatterLevel::Minimal);
+
+    let mut pbuilder = ProcessingSessionBuilder::default();
+    pbuilder
+        .primary_input_buffer(b"a\\bye\n")
+        .tex_input_name("texput.tex")
+        .format_name("plain")
+        .format_cache_path(util::test_path(&[]))
+        .output_format(OutputFormat::Xdv)
+        .pass(PassSetting::Tex)
+        .synctex(true)
+        .synctex_source_name("texput.tex", "/home/user/doc.tex")
+        .do_not_write_output_files()
+        .bundle(Box::new(util::TestBundle::default()));
+
+    let mut session = pbuilder
+        .create(&mut status)
+        .expect("couldn't create processing session");
+
+    session
+        .run(&mut status)
+        .expect("failed to execute processing session");
+
+    let files = session.into_file_data();
+    let synctex = files
+        .get(OsStr::new("texput.synctex.gz"))
+        .expect("no SyncTeX data in memory");
+    let data = SynctexData::from_bytes(synctex).expect("couldn't parse SyncTeX data");
+
+    assert!(data
+        .inputs
+        .values()
+        .any(|name| name == "/home/user/doc.tex"));
+    assert!(!data.forward("/home/user/doc.tex", 1).is_empty());
+}
@@ -173,6 +173,60 @@ fn bad_input_path_2() {
     error_or_panic(output);
 }
 
+#[test]
+fn bad_jpeg_quality_1() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let output = run_tectonic(&PathBuf::from("."), &["-", "--jpeg-quality=0"]);
+    error_or_panic(output);
+}
+
+#[test]
+fn bad_key_bits_1() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let output = run_tectonic(&PathBuf::from("."), &["-", "--key-bits=100"]);
+    error_or_panic(output);
+}
+
+#[test]
+fn bad_key_bits_2() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    // AES-256 is not available before PDF 1.7.
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "--key-bits=256", "--pdf-version=1.6", "-"],
+        "Standard input content.\\bye",
+    );
+    error_or_panic(output);
+}
+
+#[test]
+fn bad_key_bits_3() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    // Neither is a key longer than 40 bits before PDF 1.4.
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "--key-bits=128", "--pdf-version=1.3", "-"],
+        "Standard input content.\\bye",
+    );
+    error_or_panic(output);
+}
+
 #[test]
 fn bad_outfmt_1() {
     if env::var("RUNNING_COVERAGE").is_ok() {
@@ -183,6 +237,126 @@ fn bad_outfmt_1() {
     error_or_panic(output);
 }
 
+#[test]
+fn bad_pdfa_1() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let output = run_tectonic(&PathBuf::from("."), &["-", "--pdfa=1b"]);
+    error_or_panic(output);
+}
+
+#[test]
+fn bad_permissions_1() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let output = run_tectonic(&PathBuf::from("."), &["-", "--permissions=0xzz"]);
+    error_or_panic(output);
+}
+
+#[test]
+fn bad_project_paper_1() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let tempdir = setup_and_copy_files(&[]);
+    let mut config = File::create(tempdir.path().join("Tectonic.toml")).unwrap();
+    writeln!(config, "[pdf_output]\npaper = \"nosuchpaper\"").unwrap();
+
+    let output = run_tectonic_with_stdin(tempdir.path(), &["-"], "Hello\\bye");
+    let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+    error_or_panic(output);
+    assert!(stderr.contains("nosuchpaper"));
+}
+
+#[test]
+fn encrypt_with_password_files() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(tempdir.path().join("owner.txt"), "owner secret\n").unwrap();
+    fs::write(tempdir.path().join("user.txt"), "user\n").unwrap();
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[
+            &fmt_arg,
+            "--owner-password-file=owner.txt",
+            "--user-password-file=user.txt",
+            "-",
+        ],
+        "Standard input content.\\bye",
+    );
+    success_or_panic(output);
+
+    let pdf = fs::read(tempdir.path().join("texput.pdf")).unwrap();
+    assert!(String::from_utf8_lossy(&pdf).contains("/Encrypt"));
+}
+
+#[test]
+fn font_map_options() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    // The map files come before the map lines. A value with a "=" prefix
+    // has to be a separate argument, since "--map-file==name" loses it.
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(tempdir.path().join("cmr10.map"), "cmr10 CMR10 <cmr10.pfb\n").unwrap();
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[
+            &fmt_arg,
+            "--print",
+            "--font-map-diagnostics",
+            "--no-default-maps",
+            "--map-line=cmr10 CMR10 <cmr10.pfb",
+            "--map-line=-cmbx10",
+            "--map-file",
+            "=cmr10.map",
+            "-",
+        ],
+        "Standard input content.\\bye",
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+    check_file(&tempdir, "texput.pdf");
+    assert!(stdout.contains("Font \"cmr10\": font map entry \"cmr10\" from cmr10.map line"));
+    assert!(stdout.contains("Duplicate font map entry \"cmr10\" in a map line ignored"));
+    assert!(stdout.contains("No font map entry \"cmbx10\" to remove"));
+}
+
+#[test]
+fn font_map_unmapped_font() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[
+            &fmt_arg,
+            "--print",
+            "--font-map-diagnostics",
+            "--no-default-maps",
+            "-",
+        ],
+        "Standard input content.\\bye",
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+    assert!(stdout.contains("Font \"cmr10\": no font map entry"));
+}
+
 #[test]
 fn help_flag() {
     if env::var("RUNNING_COVERAGE").is_ok() {
@@ -190,7 +364,75 @@ fn help_flag() {
     }
 
     let output = run_tectonic(&PathBuf::from("."), &["-h"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
     success_or_panic(output);
+    assert!(stdout.contains("--synctex-view"));
+    assert!(stdout.contains("--synctex-edit"));
+}
+
+/// The per-user config.toml is looked for under `$XDG_CONFIG_HOME`, which
+/// other platforms do not use.
+#[cfg(all(unix, not(target_os = "macos")))]
+#[test]
+fn pdf_output_config_precedence() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let user_dir = tempdir.path().join("config").join("Tectonic");
+    fs::create_dir_all(&user_dir).unwrap();
+    fs::write(
+        user_dir.join("config.toml"),
+        "default_bundles = []\n[pdf_output]\npdf_version = \"1.4\"\n",
+    )
+    .unwrap();
+    fs::write(tempdir.path().join("doc.tex"), "Hello\\bye\n").unwrap();
+
+    let pdf_version = |extra_args: &[&str]| {
+        let mut args = vec![fmt_arg.as_str()];
+        args.extend_from_slice(extra_args);
+        args.push("doc.tex");
+        let output = prep_tectonic(tempdir.path(), &args)
+            .env("XDG_CONFIG_HOME", tempdir.path().join("config"))
+            .output()
+            .expect("tectonic failed to start");
+        success_or_panic(output);
+        let pdf = fs::read(tempdir.path().join("doc.pdf")).unwrap();
+        String::from_utf8_lossy(&pdf[..8]).into_owned()
+    };
+
+    assert_eq!(pdf_version(&[]), "%PDF-1.4");
+
+    // The project configuration overrides the per-user one ...
+    fs::write(
+        tempdir.path().join("Tectonic.toml"),
+        "[pdf_output]\npdf_version = \"1.6\"\n",
+    )
+    .unwrap();
+    assert_eq!(pdf_version(&[]), "%PDF-1.6");
+
+    // ... and the command line overrides both.
+    assert_eq!(pdf_version(&["--pdf-version=1.3"]), "%PDF-1.3");
+}
+
+#[test]
+fn pk_and_image_dpi() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    // The two resolutions are separate arguments.
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    let output = run_tectonic_with_stdin(
+        tempdir.path(),
+        &[&fmt_arg, "--pk-dpi=300", "--image-dpi=150", "-"],
+        "Standard input content.\\bye",
+    );
+    success_or_panic(output);
+    check_file(&tempdir, "texput.pdf");
 }
 
 #[test] // GitHub #31
@@ -231,6 +473,59 @@ fn stdin_content() {
 }
 
 // Regression #36
+/// A document named like the SyncTeX options is still just a document.
+#[test]
+fn synctex_input_named_synctex() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let fmt_arg = get_plain_format_arg();
+    let tempdir = setup_and_copy_files(&[]);
+    fs::write(tempdir.path().join("synctex"), "Hello.\\bye\n").unwrap();
+
+    let output = run_tectonic(tempdir.path(), &[&fmt_arg, "synctex"]);
+    success_or_panic(output);
+    check_file(&tempdir, "synctex.pdf");
+}
+
+#[test]
+fn synctex_queries() {
+    if env::var("RUNNING_COVERAGE").is_ok() {
+        return;
+    }
+
+    let tempdir = setup_and_copy_files(&[]);
+    fs::copy(
+        PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("tests/tex-outputs/synctex.synctex.gz"),
+        tempdir.path().join("synctex.synctex.gz"),
+    )
+    .unwrap();
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &["--synctex-view", "2:synctex.tex", "synctex.synctex.gz"],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+    assert!(stdout.starts_with("Page:1\t"));
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &["--synctex-edit", "1:100:80", "synctex.synctex.gz"],
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    success_or_panic(output);
+    assert_eq!(stdout, "Input:synctex.tex\tLine:2\n");
+
+    let output = run_tectonic(
+        tempdir.path(),
+        &["--synctex-edit", "1:100", "synctex.synctex.gz"],
+    );
+    error_or_panic(output);
+}
+
 #[test]
 fn test_space() {
     if env::var("RUNNING_COVERAGE").is_ok() {
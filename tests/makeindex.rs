@@ -0,0 +1,96 @@
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+use std::collections::HashSet;
+use std::ffi::OsStr;
+
+use tectonic::engines::NoopIoEventBackend;
+use tectonic::io::{FilesystemIo, IoProvider, IoStack, MemoryIo};
+use tectonic::status::NoopStatusBackend;
+use tectonic::{MakeindexEngine, TexResult};
+
+#[path = "util/mod.rs"]
+mod util;
+use crate::util::test_path;
+
+fn run(style: Option<&str>) -> (TexResult, String, String) {
+    util::set_test_root();
+
+    let mut mem = MemoryIo::new(true);
+    let mut assets = FilesystemIo::new(&test_path(&["makeindex"]), false, false, HashSet::new());
+
+    let result = {
+        let io_list: Vec<&mut dyn IoProvider> = vec![&mut mem, &mut assets];
+        let mut io = IoStack::new(io_list);
+        let mut events = NoopIoEventBackend::new();
+        let mut status = NoopStatusBackend::new();
+        let mut engine = MakeindexEngine::new();
+
+        if let Some(style) = style {
+            engine.style(style);
+        }
+
+        engine
+            .process(
+                &mut io,
+                &mut events,
+                &mut status,
+                "basic.idx",
+                "basic.ind",
+                "basic.ilg",
+            )
+            .unwrap()
+    };
+
+    let files = mem.files.borrow();
+    let get = |name: &str| String::from_utf8(files.get(OsStr::new(name)).unwrap().clone()).unwrap();
+
+    (result, get("basic.ind"), get("basic.ilg"))
+}
+
+#[test]
+fn default_style() {
+    let (result, ind, ilg) = run(None);
+
+    // One entry has an invalid page number.
+    assert_eq!(result, TexResult::Errors);
+    assert!(ilg.contains("(16 entries accepted, 1 rejected)"));
+    assert!(ilg.contains("Illegal page number x?."));
+
+    assert_eq!(
+        ind,
+        "\\begin{theindex}\n\
+         \n  \\item *star, 8\n\
+         \n  \\indexspace\n\
+         \n  \\item 10, 9\n\
+         \n  \\indexspace\n\
+         \n  \\item alpha, 1--3\n\
+         \n  \\indexspace\n\
+         \n  \\item beta, 4\n    \\subitem gamma, 5\n\
+         \n  \\indexspace\n\
+         \n  \\item delta, \\textbf{7}\n\
+         \n  \\indexspace\n\
+         \n  \\item epsilon, 4, 5\n\
+         \n  \\indexspace\n\
+         \n  \\item quote!mark, 6\n\
+         \n  \\indexspace\n\
+         \n  \\item range, 10--14\n\
+         \n  \\indexspace\n\
+         \n  \\item \\textit{Zeta}, ii\n\
+         \n\\end{theindex}\n"
+    );
+}
+
+#[test]
+fn custom_style() {
+    let (_, ind, ilg) = run(Some("custom.ist"));
+
+    assert!(ilg.contains("(5 attributes redefined, 1 ignored)"));
+    assert!(ilg.contains("** Unknown specifier nonsense."));
+
+    assert!(ind.contains("\n  \\indexheading{Symbols}\n  \\item *star\\dotfill 8\n"));
+    assert!(
+        ind.contains("\n  \\indexheading{B}\n  \\item beta\\dotfill 4\n    \\subitem gamma, 5\n")
+    );
+    assert!(ind.contains("\n  \\item epsilon\\dotfill 4f.\n"));
+}
@@ -0,0 +1,62 @@
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+use tectonic::synctex::{RecordKind, SynctexData};
+
+#[path = "util/mod.rs"]
+mod util;
+use crate::util::test_path;
+
+fn load() -> SynctexData {
+    util::set_test_root();
+    SynctexData::from_path(test_path(&["tex-outputs", "synctex.synctex.gz"]))
+        .expect("couldn't parse SyncTeX file")
+}
+
+#[test]
+fn parse_preamble() {
+    let data = load();
+    assert_eq!(data.output, "pdf");
+    assert_eq!(data.inputs.get(&1).map(|s| s.as_str()), Some("synctex.tex"));
+    assert_eq!(data.pages(), vec![1]);
+    assert_eq!(data.records()[0].kind, RecordKind::VBox);
+}
+
+#[test]
+fn forward_query() {
+    let data = load();
+    let hits = data.forward("synctex.tex", 2);
+    assert!(!hits.is_empty());
+    assert!(hits.iter().all(|h| h.page == 1));
+
+    // A full path to the same file should work too.
+    assert_eq!(data.forward("/some/where/synctex.tex", 2), hits);
+
+    assert!(data.forward("nonexistent.tex", 2).is_empty());
+}
+
+#[test]
+fn inverse_query() {
+    let data = load();
+    let loc = data.inverse(1, 100.0, 80.0).expect("no inverse match");
+    assert_eq!(loc.file, "synctex.tex");
+    assert_eq!(loc.line, 2);
+
+    assert!(data.inverse(7, 100.0, 80.0).is_none());
+}
+
+#[test]
+fn reject_garbage() {
+    assert!(SynctexData::from_bytes(b"this is not synctex\n").is_err());
+}
+
+#[test]
+fn non_ascii_lines_and_repeated_pages() {
+    let data = SynctexData::from_bytes(
+        "SyncTeX Version:1\nInput:1:a.tex\nContent:\n{2\n[1,1:0,0:10,10,0\n]\n}2\n\
+         ¶ unknown record\n{1\nh1,2:0,0:10,10,0\n}1\n{2\nh1,3:0,0:10,10,0\n}2\nPostamble:\n"
+            .as_bytes(),
+    )
+    .expect("couldn't parse SyncTeX data");
+    assert_eq!(data.pages(), vec![1, 2]);
+}
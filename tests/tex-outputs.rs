@@ -3,9 +3,15 @@
 
 use std::collections::HashSet;
 use std::env;
+use std::ffi::OsStr;
 use std::path::Path;
 
+use md5::{Digest, Md5};
+
 use tectonic::engines::tex::TexResult;
+use tectonic::engines::xdvipdfmx::{
+    FontMap, FontMapMode, PaperSize, PdfEncryption, PdfOutputOptions,
+};
 use tectonic::engines::NoopIoEventBackend;
 use tectonic::errors::{DefinitelySame, ErrorKind, Result};
 use tectonic::io::testing::SingleInputFileIo;
@@ -15,6 +21,7 @@ use tectonic::{TexEngine, XdvipdfmxEngine};
 
 #[path = "util/mod.rs"]
 mod util;
+use crate::util::pdf::{numbers, raw_entry, reference, string, Pdf};
 use crate::util::{ensure_plain_format, test_path, ExpectedInfo};
 
 struct TestCase {
@@ -22,6 +29,13 @@ struct TestCase {
     expected_result: Result<TexResult>,
     check_synctex: bool,
     check_pdf: bool,
+    linearize: bool,
+    keep_annotations: bool,
+    xobjects: Option<Vec<XObject>>,
+    encryption: Option<PdfEncryption>,
+    output_options: Option<PdfOutputOptions>,
+    font_maps: Option<Vec<FontMap>>,
+    pdf_check: Option<fn(&Pdf)>,
     extra_io: Vec<Box<dyn IoProvider>>,
 }
 
@@ -32,6 +46,13 @@ impl TestCase {
             expected_result: Ok(TexResult::Spotless),
             check_synctex: false,
             check_pdf: false,
+            linearize: false,
+            keep_annotations: false,
+            xobjects: None,
+            encryption: None,
+            output_options: None,
+            font_maps: None,
+            pdf_check: None,
             extra_io: Vec::new(),
         }
     }
@@ -46,6 +67,49 @@ impl TestCase {
         self
     }
 
+    /// Write a linearized PDF and check its linearization dictionary,
+    /// instead of comparing the PDF to the expected one.
+    fn linearize(&mut self, linearize: bool) -> &mut Self {
+        self.linearize = linearize;
+        self
+    }
+
+    /// Carry the links and layers of included PDF pages over into the output.
+    fn keep_annotations(&mut self, keep: bool) -> &mut Self {
+        self.keep_annotations = keep;
+        self
+    }
+
+    /// Convert the output to PDF and check its XObjects, instead of
+    /// comparing the PDF to the expected one.
+    fn check_xobjects(&mut self, xobjects: &[XObject]) -> &mut Self {
+        self.xobjects = Some(xobjects.to_vec());
+        self
+    }
+
+    fn encrypt(&mut self, encryption: PdfEncryption) -> &mut Self {
+        self.encryption = Some(encryption);
+        self
+    }
+
+    fn output_options(&mut self, options: PdfOutputOptions) -> &mut Self {
+        self.output_options = Some(options);
+        self
+    }
+
+    fn font_maps(&mut self, maps: Vec<FontMap>) -> &mut Self {
+        self.font_maps = Some(maps);
+        self
+    }
+
+    /// Convert the output to PDF and run a check on it, after the XObjects
+    /// are checked if that is asked for too, instead of comparing the PDF to
+    /// the expected one.
+    fn check_pdf_with(&mut self, check: fn(&Pdf)) -> &mut Self {
+        self.pdf_check = Some(check);
+        self
+    }
+
     fn with_fs(&mut self, path: &Path) -> &mut Self {
         self.extra_io.push(Box::new(FilesystemIo::new(
             path,
@@ -115,14 +179,29 @@ impl TestCase {
             let tex_res =
                 TexEngine::new().process(&mut io, &mut events, &mut status, "plain.fmt", &texname);
 
-            if self.check_pdf && tex_res.definitely_same(&Ok(TexResult::Spotless)) {
+            if (self.check_pdf
+                || self.linearize
+                || self.xobjects.is_some()
+                || self.pdf_check.is_some())
+                && tex_res.definitely_same(&Ok(TexResult::Spotless))
+            {
                 // While the xdv and log output is deterministic without setting
                 // SOURCE_DATE_EPOCH, xdvipdfmx uses the current date in various places.
                 env::set_var("SOURCE_DATE_EPOCH", "1456304492"); // TODO: default to deterministic behaviour
 
-                XdvipdfmxEngine::new()
+                let mut engine = XdvipdfmxEngine::new()
                     .with_compression(false)
                     .with_deterministic_tags(true)
+                    .with_linearize(self.linearize)
+                    .with_keep_annotations(self.keep_annotations)
+                    .with_encryption(self.encryption.clone());
+                if let Some(ref options) = self.output_options {
+                    engine = engine.with_output_options(options.clone());
+                }
+                if let Some(ref maps) = self.font_maps {
+                    engine = engine.with_font_maps(maps.clone());
+                }
+                engine
                     .process(&mut io, &mut events, &mut status, &xdvname, &pdfname)
                     .unwrap();
             }
@@ -151,14 +230,842 @@ impl TestCase {
             ExpectedInfo::read_with_extension_gz(&mut p, "synctex.gz").test_from_collection(&files);
         }
 
-        if self.check_pdf {
+        if self.linearize {
+            check_linearized(&files[OsStr::new(&pdfname)]);
+        } else if let Some(ref xobjects) = self.xobjects {
+            check_xobjects(&files[OsStr::new(&pdfname)], xobjects);
+        } else if self.check_pdf {
             ExpectedInfo::read_with_extension(&mut p, "pdf").test_from_collection(&files);
         }
+
+        if let Some(check) = self.pdf_check {
+            check(&Pdf::new(&files[OsStr::new(&pdfname)]));
+        }
+    }
+}
+
+/// Checks the offsets given in the linearization parameter dictionary of a
+/// PDF file against the file.
+fn check_linearized(pdf: &[u8]) {
+    // Keeps the byte offsets, unlike String::from_utf8_lossy().
+    let text: String = pdf
+        .iter()
+        .map(|&b| if b < 0x80 { b as char } else { '.' })
+        .collect();
+    let number_at = |at: usize| -> usize {
+        text[at..]
+            .trim_start()
+            .split(|c: char| !c.is_ascii_digit())
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap()
+    };
+    let lin_at = text
+        .find(" 0 obj\n<< /Linearized 1 ")
+        .expect("no linearization dictionary");
+    assert!(lin_at < 1024, "linearization dictionary not at the start");
+    let lin_end = lin_at + text[lin_at..].find("endobj\n").unwrap() + 7;
+    let entry =
+        |key: &str| number_at(lin_at + text[lin_at..lin_end].find(key).unwrap() + key.len());
+
+    assert_eq!(entry("/L "), pdf.len());
+
+    // The first-page cross-reference table follows the dictionary, and is
+    // the one startxref points to.
+    assert!(text[lin_end..].starts_with("xref\n"));
+    let startxref = text.rfind("startxref\n").unwrap();
+    assert_eq!(number_at(startxref + 10), lin_end);
+    let mut lines = text[lin_end + 5..].lines();
+    let mut head = lines.next().unwrap().split(' ');
+    let first: usize = head.next().unwrap().parse().unwrap();
+    let count: usize = head.next().unwrap().parse().unwrap();
+    let offsets: Vec<usize> = lines
+        .take(count)
+        .map(|l| l[..10].parse().unwrap())
+        .collect();
+    for (i, &offset) in offsets.iter().enumerate() {
+        assert!(text[offset..].starts_with(&format!("{} 0 obj\n", first + i)));
+    }
+
+    // /O is the first page, somewhere in that table.
+    let o = entry("/O ");
+    assert!(o >= first && o < first + count);
+    assert!(text[offsets[o - first]..].contains("/Type/Page"));
+
+    // /H is the primary hint stream, /E where the first page ends.
+    let h = entry("/H [ ");
+    assert!(offsets.contains(&h));
+    assert!(text[h..].contains("stream\n"));
+    let e = entry("/E ");
+    assert!(text[..e].ends_with("endobj\n"));
+    assert!(offsets[o - first] < e);
+
+    // /T is just before the first entry of the main cross-reference table.
+    let t = entry("/T ");
+    assert_eq!(text[..t].rsplit('\n').nth(1), Some("xref"));
+    assert!(text[t + 1..].starts_with("0000000000 65535 f \n"));
+}
+
+/// How an image XObject is masked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mask {
+    None,
+    /// A `/SMask` image of the same size.
+    Soft,
+    /// A `/Mask` array of color ranges.
+    ColorKey,
+}
+
+/// The parts of an XObject in the PDF output that a picture's converter is
+/// responsible for.
+#[derive(Clone, Debug, PartialEq)]
+enum XObject {
+    Image {
+        width: u32,
+        height: u32,
+        /// The family of the `/ColorSpace`, if there is one.
+        color_space: Option<&'static str>,
+        bits_per_component: Option<u32>,
+        mask: Mask,
+    },
+    Form {
+        bbox: [f64; 4],
+        /// Whether the form's resources include a shading pattern.
+        shading: bool,
+    },
+}
+
+/// The color space families that the converters use.
+const COLOR_SPACE_FAMILIES: &[&str] = &[
+    "CalGray",
+    "CalRGB",
+    "DeviceCMYK",
+    "DeviceGray",
+    "DeviceRGB",
+    "ICCBased",
+    "Indexed",
+    "Lab",
+];
+
+/// Checks the image and form XObjects of a PDF file against the expected
+/// ones, in any order. Soft masks are checked along with the images that
+/// use them.
+fn check_xobjects(pdf: &[u8], expected: &[XObject]) {
+    let pdf = Pdf::new(pdf);
+    let number =
+        |dict: &str, key: &str| -> Option<u32> { pdf.entry(dict, key)?.trim().parse().ok() };
+
+    let soft_masks: HashSet<u32> = pdf
+        .objects()
+        .filter_map(|(_, dict)| reference(raw_entry(dict, "SMask")?))
+        .collect();
+    let mut found = Vec::new();
+
+    for (num, dict) in pdf.objects() {
+        match raw_entry(dict, "Subtype") {
+            Some("/Image") if !soft_masks.contains(&num) => {
+                let width = number(dict, "Width").expect("image without /Width");
+                let height = number(dict, "Height").expect("image without /Height");
+                let mask = if let Some(smask) = pdf.entry(dict, "SMask") {
+                    assert_eq!(number(&smask, "Width"), Some(width));
+                    assert_eq!(number(&smask, "Height"), Some(height));
+                    assert_eq!(pdf.entry(&smask, "ColorSpace").unwrap(), "/DeviceGray");
+                    Mask::Soft
+                } else if pdf
+                    .entry(dict, "Mask")
+                    .map_or(false, |m| m.starts_with('['))
+                {
+                    Mask::ColorKey
+                } else {
+                    Mask::None
+                };
+                let color_space = pdf.entry(dict, "ColorSpace").map(|cs| {
+                    let family = cs
+                        .trim_start_matches('[')
+                        .trim_start()
+                        .trim_start_matches('/')
+                        .split(|c: char| !c.is_ascii_alphanumeric())
+                        .next()
+                        .unwrap();
+                    *COLOR_SPACE_FAMILIES
+                        .iter()
+                        .find(|&&f| f == family)
+                        .unwrap_or_else(|| panic!("unexpected color space {}", cs))
+                });
+                found.push(XObject::Image {
+                    width,
+                    height,
+                    color_space,
+                    bits_per_component: number(dict, "BitsPerComponent"),
+                    mask,
+                });
+            }
+            Some("/Form") => {
+                let bbox = numbers(&pdf.entry(dict, "BBox").expect("form without /BBox"));
+                let shading = pdf
+                    .entry(dict, "Resources")
+                    .and_then(|res| pdf.entry(&res, "Pattern"))
+                    .is_some();
+                found.push(XObject::Form {
+                    bbox: [bbox[0], bbox[1], bbox[2], bbox[3]],
+                    shading,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let mut unmatched = found.clone();
+    for xobj in expected {
+        match unmatched.iter().position(|f| f == xobj) {
+            Some(i) => {
+                unmatched.remove(i);
+            }
+            None => panic!("expected {:?} among the XObjects {:?}", xobj, found),
+        }
+    }
+    assert!(
+        unmatched.is_empty(),
+        "unexpected XObjects {:?} among {:?}",
+        unmatched,
+        found
+    );
+}
+
+/// The bytes that PDF passwords are padded to 32 bytes with.
+const PASSWORD_PADDING: [u8; 32] = [
+    0x28, 0xbf, 0x4e, 0x5e, 0x4e, 0x75, 0x8a, 0x41, 0x64, 0x00, 0x4e, 0x56, 0xff, 0xfa, 0x01, 0x08,
+    0x2e, 0x2e, 0x00, 0xb6, 0xd0, 0x68, 0x3e, 0x80, 0x2f, 0x0c, 0xa9, 0xfe, 0x64, 0x53, 0x69, 0x7a,
+];
+
+const OWNER_PASSWORD: &str = "owner";
+const USER_PASSWORD: &str = "user";
+
+fn padded_password(password: &str) -> Vec<u8> {
+    password
+        .bytes()
+        .chain(PASSWORD_PADDING.iter().cloned())
+        .take(32)
+        .collect()
+}
+
+/// RC4, which encrypts and decrypts alike.
+fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut s: Vec<u8> = (0..=255).collect();
+    let mut j = 0u8;
+    for i in 0..256 {
+        j = j.wrapping_add(s[i]).wrapping_add(key[i % key.len()]);
+        s.swap(i, j as usize);
+    }
+
+    let (mut i, mut j) = (0u8, 0u8);
+    data.iter()
+        .map(|b| {
+            i = i.wrapping_add(1);
+            j = j.wrapping_add(s[i as usize]);
+            s.swap(i as usize, j as usize);
+            b ^ s[s[i as usize].wrapping_add(s[j as usize]) as usize]
+        })
+        .collect()
+}
+
+/// The MD5 hash of `data`, rehashed 50 times at revision 3 of the standard
+/// security handler, truncated to the key length.
+fn rc4_key_hash(data: &[u8], revision: u32, key_len: usize) -> Vec<u8> {
+    let mut hash = Md5::digest(data).to_vec();
+    if revision >= 3 {
+        for _ in 0..50 {
+            hash = Md5::digest(&hash[..key_len]).to_vec();
+        }
+    }
+    hash.truncate(key_len);
+    hash
+}
+
+/// Runs RC4 once with `key` at revision 2 of the standard security handler,
+/// and 20 times with `key` XORed with each step at revision 3.
+fn rc4_rounds(key: &[u8], data: &[u8], revision: u32, steps: impl Iterator<Item = u8>) -> Vec<u8> {
+    if revision < 3 {
+        return rc4(key, data);
+    }
+    steps.fold(data.to_vec(), |data, step| {
+        let key: Vec<u8> = key.iter().map(|b| b ^ step).collect();
+        rc4(&key, &data)
+    })
+}
+
+/// Checks that a PDF file is encrypted with RC4 keys of `key_bits` bits
+/// using the standard security handler, that both passwords open it, and
+/// that its streams decrypt with the key that the user password gives.
+fn check_rc4_encryption(pdf: &Pdf, key_bits: u32) {
+    let trailer = pdf.trailer();
+    let encrypt = pdf.entry(&trailer, "Encrypt").expect("no /Encrypt");
+    let number = |key: &str| -> i64 { raw_entry(&encrypt, key).unwrap().parse().unwrap() };
+    let (version, revision) = if key_bits == 40 { (1, 2) } else { (2, 3) };
+
+    assert_eq!(raw_entry(&encrypt, "Filter"), Some("/Standard"));
+    assert_eq!(number("V"), version);
+    assert_eq!(number("R"), revision as i64);
+    assert_eq!(number("Length"), key_bits as i64);
+
+    let owner_hash = string(raw_entry(&encrypt, "O").unwrap());
+    let user_hash = string(raw_entry(&encrypt, "U").unwrap());
+    let permissions = number("P") as i32;
+    let ids = raw_entry(&trailer, "ID").unwrap();
+    let first_id = string(&ids[1..=ids.find('>').unwrap()]);
+    let key_len = key_bits as usize / 8;
+
+    // The file key, from the user password.
+    let mut input = padded_password(USER_PASSWORD);
+    input.extend(&owner_hash);
+    input.extend(&permissions.to_le_bytes());
+    input.extend(&first_id);
+    let key = rc4_key_hash(&input, revision, key_len);
+
+    let user_check = if revision < 3 {
+        PASSWORD_PADDING.to_vec()
+    } else {
+        let mut input = PASSWORD_PADDING.to_vec();
+        input.extend(&first_id);
+        Md5::digest(&input).to_vec()
+    };
+    let user_check = rc4_rounds(&key, &user_check, revision, 0..20);
+    assert_eq!(user_check[..], user_hash[..user_check.len()]);
+
+    // The owner password decrypts the padded user password from /O.
+    let owner_key = rc4_key_hash(&padded_password(OWNER_PASSWORD), revision, key_len);
+    let user_padded = rc4_rounds(&owner_key, &owner_hash, revision, (0..20).rev());
+    assert_eq!(user_padded, padded_password(USER_PASSWORD));
+
+    let mut plain_streams = Vec::new();
+    for (num, dict) in pdf.objects() {
+        let data = match pdf.stream_data(num) {
+            Some(data) if raw_entry(dict, "Type") != Some("/XRef") => data,
+            _ => continue,
+        };
+        let mut input = key.clone();
+        input.extend(&num.to_le_bytes()[..3]);
+        input.extend(&[0, 0]);
+        let object_key = &Md5::digest(&input)[..(key_len + 5).min(16)];
+        plain_streams.push(String::from_utf8_lossy(&rc4(object_key, data)).into_owned());
+    }
+
+    assert!(!pdf.text.contains("/Type/Catalog"));
+    assert!(plain_streams.iter().any(|s| s.contains("/Type/Catalog")));
+    assert!(plain_streams
+        .iter()
+        .any(|s| s.contains("BT") && s.contains("ET")));
+}
+
+/// A map line for cmr10 that slants it, as tests/assets/cmr10-slanted.map
+/// has it.
+const SLANTED_CMR10: &str = "cmr10 CMR10 \" .167 SlantFont \" <cmr10.pfb";
+
+/// The default font maps, followed by `extra`.
+fn font_maps_after_defaults(extra: &[FontMap]) -> Vec<FontMap> {
+    let mut maps = FontMap::defaults();
+    maps.extend_from_slice(extra);
+    maps
+}
+
+/// Whether the text is set with cmr10 slanted, which takes a text matrix.
+fn is_slanted(pdf: &Pdf) -> bool {
+    pdf.text.contains(".167 1 ") && pdf.text.contains(" Tm")
+}
+
+fn check_slanted(pdf: &Pdf) {
+    assert!(is_slanted(pdf), "cmr10 is not slanted");
+}
+
+fn check_not_slanted(pdf: &Pdf) {
+    assert!(!is_slanted(pdf), "cmr10 is slanted");
+    assert!(pdf.text.contains("/BaseFont/000001+CMR10"));
+}
+
+/// The size of the pages, from the page tree.
+fn media_box(pdf: &Pdf) -> Vec<f64> {
+    let pages = pdf
+        .objects()
+        .find(|(_, obj)| raw_entry(obj, "Type") == Some("/Pages"))
+        .expect("no page tree")
+        .1;
+    numbers(raw_entry(pages, "MediaBox").expect("no MediaBox"))
+}
+
+fn check_media_box(pdf: &Pdf, width: f64, height: f64) {
+    let media_box = media_box(pdf);
+    assert_eq!(media_box.len(), 4);
+    assert!(
+        (media_box[2] - width).abs() < 0.01 && (media_box[3] - height).abs() < 0.01,
+        "MediaBox {:?} is not {} by {}",
+        media_box,
+        width,
+        height
+    );
+}
+
+/// The content stream of the only page.
+fn page_content(pdf: &Pdf) -> String {
+    let page = pdf
+        .objects()
+        .find(|(_, obj)| raw_entry(obj, "Type") == Some("/Page"))
+        .expect("no page")
+        .1;
+    let contents = raw_entry(page, "Contents").unwrap();
+    let num = reference(contents.trim_matches(|c| c == '[' || c == ']')).unwrap();
+    String::from_utf8_lossy(pdf.stream_data(num).unwrap()).into_owned()
+}
+
+/// The numbers of decimal digits of the operands of the `Td` operators.
+fn text_position_digits(pdf: &Pdf) -> Vec<usize> {
+    let content = page_content(pdf);
+    let tokens: Vec<&str> = content.split_whitespace().collect();
+    let mut digits = Vec::new();
+
+    for (i, token) in tokens.iter().enumerate() {
+        if token.starts_with("Td") {
+            for operand in &tokens[i - 2..i] {
+                digits.push(operand.split('.').nth(1).map_or(0, str::len));
+            }
+        }
+    }
+
+    assert!(!digits.is_empty(), "no text positions in {:?}", content);
+    digits
+}
+
+/// The /Count of the top-level bookmark of the outlines fixture, which is
+/// negative when the bookmark is closed, and that of the outline root,
+/// which only counts the bookmarks that are visible.
+fn bookmark_counts(pdf: &Pdf) -> (f64, f64) {
+    let catalog = pdf
+        .objects()
+        .find(|(_, obj)| raw_entry(obj, "Type") == Some("/Catalog"))
+        .expect("no catalog")
+        .1;
+    let root = pdf.entry(catalog, "Outlines").expect("no outlines");
+    let one = pdf
+        .objects()
+        .find(|(_, obj)| raw_entry(obj, "Title").map(string) == Some(b"One".to_vec()))
+        .expect("no bookmark \"One\"")
+        .1;
+
+    let count = |dict: &str| raw_entry(dict, "Count").unwrap().parse().unwrap();
+    (count(one), count(&root))
+}
+
+/// The objects that the indirect references in an array or a dictionary
+/// refer to, in order.
+fn references(value: &str) -> Vec<u32> {
+    let tokens: Vec<&str> = value
+        .split(|c: char| c.is_whitespace() || "[]<>/".contains(c))
+        .filter(|t| !t.is_empty())
+        .collect();
+    tokens
+        .windows(3)
+        .filter(|w| w[1] == "0" && w[2] == "R")
+        .filter_map(|w| w[0].parse().ok())
+        .collect()
+}
+
+/// The transformation in effect at the first `Do` operator of a content
+/// stream, as `[a b c d e f]`.
+fn matrix_at_do(content: &str) -> [f64; 6] {
+    let mut stack = vec![[1., 0., 0., 1., 0., 0.]];
+    let tokens: Vec<&str> = content.split_whitespace().collect();
+
+    for (i, &token) in tokens.iter().enumerate() {
+        match token {
+            "q" => stack.push(*stack.last().unwrap()),
+            "Q" => {
+                stack.pop();
+            }
+            "cm" => {
+                let m: Vec<f64> = tokens[i - 6..i]
+                    .iter()
+                    .map(|n| n.parse().unwrap())
+                    .collect();
+                let t = stack.last_mut().unwrap();
+                *t = [
+                    m[0] * t[0] + m[1] * t[2],
+                    m[0] * t[1] + m[1] * t[3],
+                    m[2] * t[0] + m[3] * t[2],
+                    m[2] * t[1] + m[3] * t[3],
+                    m[4] * t[0] + m[5] * t[2] + t[4],
+                    m[4] * t[1] + m[5] * t[3] + t[5],
+                ];
+            }
+            "Do" => return *stack.last().unwrap(),
+            _ => {}
+        }
     }
+    panic!("no XObject painted in {:?}", content);
+}
+
+/// Checks the output of `pdf_links_layers`: of the two links on the included
+/// page, the one to a web page is on the output page, where the page was
+/// painted, and both layers are in the output catalog.
+fn check_links_layers(pdf: &Pdf) {
+    let page = pdf
+        .objects()
+        .find(|(_, obj)| raw_entry(obj, "Type") == Some("/Page"))
+        .expect("no page")
+        .1;
+    let annots = references(&pdf.entry(page, "Annots").expect("no /Annots"));
+    assert_eq!(annots.len(), 1, "the link within the included file is kept");
+    let link = pdf.object(annots[0]).unwrap();
+    assert_eq!(raw_entry(link, "Subtype"), Some("/Link"));
+    let action = pdf.entry(link, "A").expect("no /A");
+    assert_eq!(raw_entry(&action, "S"), Some("/URI"));
+    assert!(action.contains("(https://tectonic-typesetting.github.io/)"));
+
+    // The included page is 100 by 50 and painted 200 wide; its link is at
+    // [10 10 40 20].
+    let m = matrix_at_do(&page_content(pdf));
+    let rect = numbers(raw_entry(link, "Rect").unwrap());
+    let expected = [
+        m[4] + 10. * m[0],
+        m[5] + 10. * m[3],
+        m[4] + 40. * m[0],
+        m[5] + 20. * m[3],
+    ];
+    assert!((m[0] - 2.).abs() < 0.001 && (m[3] - 2.).abs() < 0.001);
+    assert!(
+        rect.iter()
+            .zip(&expected)
+            .all(|(a, b)| (a - b).abs() < 0.01),
+        "link at {:?} instead of {:?}",
+        rect,
+        expected
+    );
+
+    // The layers used by the form XObject are the ones in the catalog.
+    let catalog = pdf
+        .objects()
+        .find(|(_, obj)| raw_entry(obj, "Type") == Some("/Catalog"))
+        .expect("no catalog")
+        .1;
+    let props = pdf
+        .entry(catalog, "OCProperties")
+        .expect("no /OCProperties");
+    let ocgs = references(raw_entry(&props, "OCGs").unwrap());
+    let names: Vec<Vec<u8>> = ocgs
+        .iter()
+        .map(|&num| string(raw_entry(pdf.object(num).unwrap(), "Name").unwrap()))
+        .collect();
+    assert_eq!(names, [b"Outline".to_vec(), b"Diagonal".to_vec()]);
+    assert_eq!(references(raw_entry(&props, "Order").unwrap()), ocgs);
+    assert_eq!(references(raw_entry(&props, "OFF").unwrap()), [ocgs[1]]);
+
+    let form = pdf
+        .objects()
+        .find(|(_, obj)| raw_entry(obj, "Subtype") == Some("/Form"))
+        .expect("no form XObject")
+        .1;
+    let resources = pdf.entry(form, "Resources").unwrap();
+    let properties = pdf.entry(&resources, "Properties").expect("no /Properties");
+    assert_eq!(references(&properties), ocgs);
+}
+
+/// Checks the structure tree of the `tagged` fixture: a single paragraph,
+/// whose marked content is the letter, while the page number is an
+/// artifact.
+fn check_tagged(pdf: &Pdf) {
+    let catalog = pdf
+        .objects()
+        .find(|(_, obj)| raw_entry(obj, "Type") == Some("/Catalog"))
+        .expect("no catalog")
+        .1;
+    let mark_info = pdf.entry(catalog, "MarkInfo").expect("no /MarkInfo");
+    assert_eq!(raw_entry(&mark_info, "Marked"), Some("true"));
+
+    let root = pdf
+        .entry(catalog, "StructTreeRoot")
+        .expect("no /StructTreeRoot");
+    assert_eq!(raw_entry(&root, "Type"), Some("/StructTreeRoot"));
+    let kids = raw_entry(&root, "K").unwrap();
+    let p_num = reference(kids.trim_matches(|c| c == '[' || c == ']')).expect("no element");
+    let p = pdf.object(p_num).unwrap();
+    assert_eq!(raw_entry(p, "S"), Some("/P"));
+    assert_eq!(raw_entry(p, "Lang").map(string), Some(b"en".to_vec()));
+    assert_eq!(numbers(raw_entry(p, "K").unwrap()), [0.]);
+
+    // The page's marked content is owned by the paragraph.
+    let page = pdf
+        .objects()
+        .find(|(_, obj)| raw_entry(obj, "Type") == Some("/Page"))
+        .expect("no page")
+        .1;
+    assert_eq!(raw_entry(page, "StructParents"), Some("0"));
+    let parent_tree = pdf.entry(&root, "ParentTree").expect("no /ParentTree");
+    let nums = raw_entry(&parent_tree, "Nums")
+        .unwrap()
+        .replace(|c| c == '[' || c == ']', " ");
+    assert_eq!(
+        nums.split_whitespace().collect::<Vec<_>>(),
+        ["0", &p_num.to_string(), "0", "R"]
+    );
+
+    let content = page_content(pdf);
+    let operators: Vec<&str> = content
+        .split_whitespace()
+        .filter(|t| *t == "BDC" || *t == "BMC" || *t == "EMC")
+        .collect();
+    assert_eq!(
+        operators.iter().filter(|&&t| t == "EMC").count() * 2,
+        operators.len(),
+        "unbalanced marked content in {:?}",
+        content
+    );
+    assert_eq!(content.matches("/MCID").count(), 1);
+    let start = content
+        .find("/P <</MCID 0>> BDC")
+        .expect("no marked content for the paragraph");
+    let end = start + content[start..].find("EMC").unwrap();
+    assert!(content[start..end].contains("(a)"));
+    assert!(!content[start..end].contains("(1)"));
+    let page_number = content.find("(1)").unwrap();
+    assert!(content[..page_number].rfind("/Artifact BMC") > content[..page_number].rfind("EMC"));
+}
+
+// Each picture is 12 by 12 big points, read from the file's own headers, so
+// the XDV files of the picture tests only differ in the file names.
+
+const GRAY12_IMAGE: XObject = XObject::Image {
+    width: 12,
+    height: 12,
+    color_space: Some("DeviceGray"),
+    bits_per_component: Some(8),
+    mask: Mask::None,
+};
+
+/// JPEG 2000 images carry their color space and depth in the codestream.
+const GRAY12_JPX: XObject = XObject::Image {
+    width: 12,
+    height: 12,
+    color_space: None,
+    bits_per_component: None,
+    mask: Mask::None,
+};
+
+const GRAY12_FORM: XObject = XObject::Form {
+    bbox: [0., 0., 12., 12.],
+    shading: false,
+};
+
+/// Checks that the page of an included PDF file, which fills its 12 by 12
+/// box with gray, is in a form XObject as plain content, so it was decrypted
+/// or found as it should have been.
+fn check_gray12_page(pdf: &Pdf) {
+    let content = b"0 0 12 12 re f";
+    let found = pdf.objects().any(|(num, dict)| {
+        raw_entry(dict, "Subtype") == Some("/Form")
+            && pdf.stream_data(num).map_or(false, |data| {
+                data.windows(content.len()).any(|w| w == content)
+            })
+    });
+    assert!(found, "no form XObject with the content of the page");
 }
 
 // Keep these alphabetized.
 
+#[test]
+fn alpha_webp() {
+    TestCase::new("alpha_webp")
+        .check_xobjects(&[XObject::Image {
+            width: 12,
+            height: 12,
+            color_space: Some("DeviceRGB"),
+            bits_per_component: Some(8),
+            mask: Mask::Soft,
+        }])
+        .go()
+}
+
+#[test]
+fn bookmarks_closed() {
+    TestCase::new("outlines")
+        .check_pdf_with(|pdf| assert_eq!(bookmark_counts(pdf), (-1., 1.)))
+        .go()
+}
+
+#[test]
+fn bookmarks_open() {
+    TestCase::new("outlines")
+        .output_options(PdfOutputOptions {
+            bookmark_open_depth: 1,
+            ..PdfOutputOptions::default()
+        })
+        .check_pdf_with(|pdf| assert_eq!(bookmark_counts(pdf), (1., 2.)))
+        .go()
+}
+
+#[test]
+fn encrypted_rc4_40() {
+    TestCase::new("md5_of_hello")
+        .encrypt(PdfEncryption {
+            owner_password: OWNER_PASSWORD.to_owned(),
+            user_password: USER_PASSWORD.to_owned(),
+            key_bits: 40,
+            ..PdfEncryption::default()
+        })
+        .check_pdf_with(|pdf| check_rc4_encryption(pdf, 40))
+        .go()
+}
+
+#[test]
+fn encrypted_rc4_96() {
+    TestCase::new("md5_of_hello")
+        .encrypt(PdfEncryption {
+            owner_password: OWNER_PASSWORD.to_owned(),
+            user_password: USER_PASSWORD.to_owned(),
+            key_bits: 96,
+            ..PdfEncryption::default()
+        })
+        .check_pdf_with(|pdf| check_rc4_encryption(pdf, 96))
+        .go()
+}
+
+/// Without a record, cmr10.pfb is found by the TFM name, and used as it is.
+#[test]
+fn font_map_file_remove() {
+    TestCase::new("md5_of_hello")
+        .font_maps(font_maps_after_defaults(&[
+            FontMap::Line(FontMapMode::Replace, SLANTED_CMR10.to_owned()),
+            FontMap::File(FontMapMode::Remove, "cmr10-slanted.map".to_owned()),
+        ]))
+        .check_pdf_with(check_not_slanted)
+        .go()
+}
+
+#[test]
+fn font_map_file_replace() {
+    TestCase::new("md5_of_hello")
+        .font_maps(font_maps_after_defaults(&[FontMap::File(
+            FontMapMode::Replace,
+            "cmr10-slanted.map".to_owned(),
+        )]))
+        .check_pdf_with(check_slanted)
+        .go()
+}
+
+/// Appending does not override the record from pdftex.map.
+#[test]
+fn font_map_line_append() {
+    TestCase::new("md5_of_hello")
+        .font_maps(font_maps_after_defaults(&[FontMap::Line(
+            FontMapMode::Append,
+            SLANTED_CMR10.to_owned(),
+        )]))
+        .check_pdf_with(check_not_slanted)
+        .go()
+}
+
+#[test]
+fn font_map_line_append_without_defaults() {
+    TestCase::new("md5_of_hello")
+        .font_maps(vec![FontMap::Line(
+            FontMapMode::Append,
+            SLANTED_CMR10.to_owned(),
+        )])
+        .check_pdf_with(check_slanted)
+        .go()
+}
+
+#[test]
+fn font_map_line_remove() {
+    TestCase::new("md5_of_hello")
+        .font_maps(font_maps_after_defaults(&[
+            FontMap::File(FontMapMode::Replace, "cmr10-slanted.map".to_owned()),
+            FontMap::Line(FontMapMode::Remove, "cmr10".to_owned()),
+        ]))
+        .check_pdf_with(check_not_slanted)
+        .go()
+}
+
+#[test]
+fn font_map_line_replace() {
+    TestCase::new("md5_of_hello")
+        .font_maps(font_maps_after_defaults(&[FontMap::Line(
+            FontMapMode::Replace,
+            SLANTED_CMR10.to_owned(),
+        )]))
+        .check_pdf_with(check_slanted)
+        .go()
+}
+
+#[test]
+fn gray12_eps() {
+    TestCase::new("gray12_eps")
+        .check_xobjects(&[GRAY12_FORM, GRAY12_IMAGE])
+        .go()
+}
+
+#[test]
+fn gray12_j2k() {
+    TestCase::new("gray12_j2k")
+        .check_xobjects(&[GRAY12_JPX])
+        .go()
+}
+
+#[test]
+fn gray12_jp2() {
+    TestCase::new("gray12_jp2")
+        .check_xobjects(&[GRAY12_JPX])
+        .go()
+}
+
+/// The image is only read by xdvipdfmx, which has to leave it out.
+#[test]
+fn gray12_jp2_truncated() {
+    TestCase::new("gray12_jp2_truncated")
+        .check_xobjects(&[])
+        .go()
+}
+
+#[test]
+fn gray12_pdf_aes() {
+    TestCase::new("gray12_pdf_aes")
+        .check_xobjects(&[GRAY12_FORM])
+        .check_pdf_with(check_gray12_page)
+        .go()
+}
+
+#[test]
+fn gray12_pdf_broken_xref() {
+    TestCase::new("gray12_pdf_broken_xref")
+        .check_xobjects(&[GRAY12_FORM])
+        .check_pdf_with(check_gray12_page)
+        .go()
+}
+
+#[test]
+fn gray12_pdf_rc4() {
+    TestCase::new("gray12_pdf_rc4")
+        .check_xobjects(&[GRAY12_FORM])
+        .check_pdf_with(check_gray12_page)
+        .go()
+}
+
+#[test]
+fn gray12_svg() {
+    TestCase::new("gray12_svg")
+        .check_xobjects(&[XObject::Form {
+            bbox: [0., 0., 12., 12.],
+            shading: true,
+        }])
+        .go()
+}
+
+/// The second page of the TIFF file.
+#[test]
+fn gray12_tif() {
+    TestCase::new("gray12_tif")
+        .check_xobjects(&[GRAY12_IMAGE])
+        .go()
+}
+
 /// An issue triggered by a bug in how the I/O subsystem reported file offsets
 /// after an ungetc() call.
 #[test]
@@ -168,6 +1075,24 @@ fn issue393_ungetc() {
         .go()
 }
 
+#[test]
+fn linearized() {
+    TestCase::new("png_formats").linearize(true).go()
+}
+
+#[test]
+fn mask12_gif() {
+    TestCase::new("mask12_gif")
+        .check_xobjects(&[XObject::Image {
+            width: 12,
+            height: 12,
+            color_space: Some("Indexed"),
+            bits_per_component: Some(8),
+            mask: Mask::ColorKey,
+        }])
+        .go()
+}
+
 #[test]
 fn md5_of_hello() {
     TestCase::new("md5_of_hello").check_pdf(true).go()
@@ -185,6 +1110,74 @@ fn otf_basic() {
         .go()
 }
 
+#[test]
+fn output_decimal_digits() {
+    // By default, the text is positioned with five decimal digits.
+    TestCase::new("the_letter_a")
+        .output_options(PdfOutputOptions {
+            decimal_digits: 2,
+            ..PdfOutputOptions::default()
+        })
+        .check_pdf_with(|pdf| assert_eq!(text_position_digits(pdf).iter().max(), Some(&2)))
+        .go()
+}
+
+#[test]
+fn output_offsets() {
+    // The origin is 36bp from the left and 144bp from the top of the letter
+    // paper, which is 792bp high.
+    TestCase::new("the_letter_a")
+        .output_options(PdfOutputOptions {
+            x_offset: 36.,
+            y_offset: 144.,
+            ..PdfOutputOptions::default()
+        })
+        .check_pdf_with(|pdf| assert!(page_content(pdf).contains("1 0 0 1 36 648 cm")))
+        .go()
+}
+
+#[test]
+fn paper_a4() {
+    TestCase::new("the_letter_a")
+        .output_options(PdfOutputOptions {
+            paper: PaperSize::Named("a4".to_owned()),
+            ..PdfOutputOptions::default()
+        })
+        .check_pdf_with(|pdf| check_media_box(pdf, 595.28, 841.89))
+        .go()
+}
+
+#[test]
+fn paper_a4_landscape() {
+    TestCase::new("the_letter_a")
+        .output_options(PdfOutputOptions {
+            paper: PaperSize::Named("a4".to_owned()),
+            landscape: true,
+            ..PdfOutputOptions::default()
+        })
+        .check_pdf_with(|pdf| check_media_box(pdf, 841.89, 595.28))
+        .go()
+}
+
+#[test]
+fn pdf_links_layers() {
+    TestCase::new("pdf_links_layers")
+        .keep_annotations(true)
+        .check_pdf_with(check_links_layers)
+        .go()
+}
+
+#[test]
+fn pdf_version_1_4() {
+    TestCase::new("the_letter_a")
+        .output_options(PdfOutputOptions {
+            pdf_version: Some(4),
+            ..PdfOutputOptions::default()
+        })
+        .check_pdf_with(|pdf| assert_eq!(pdf.version(), "1.4"))
+        .go()
+}
+
 #[test]
 fn tex_logo() {
     TestCase::new("tex_logo").go()
@@ -210,6 +1203,11 @@ fn synctex() {
     TestCase::new("synctex").check_synctex(true).go()
 }
 
+#[test]
+fn tagged() {
+    TestCase::new("tagged").check_pdf_with(check_tagged).go()
+}
+
 #[test]
 fn unicode_file_name() {
     TestCase::new("hallöchen 🐨 welt 🌍.tex")
@@ -22,6 +22,8 @@ use std::path::{Path, PathBuf};
 use ::tectonic::errors::Result;
 pub use ::tectonic::test_util::{test_path, TestBundle};
 
+pub mod pdf;
+
 /// Set the magic environment variable that enables the testing infrastructure
 /// embedded in the main Tectonic crate. This function is separated out from
 /// the main crate because it embeds `CARGO_MANIFEST_DIR`, which is not
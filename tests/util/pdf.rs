@@ -0,0 +1,249 @@
+// Copyright 2020 the Tectonic Project
+// Licensed under the MIT License.
+
+//! Just enough of a PDF reader to check what xdvipdfmx writes when
+//! compression is turned off: the objects, including those in object
+//! streams, and the entries of their dictionaries.
+
+use std::collections::HashMap;
+
+/// A PDF file written without compression.
+pub struct Pdf {
+    /// The file with every byte above 0x7f replaced by a dot, which keeps the
+    /// byte offsets.
+    pub text: String,
+    bytes: Vec<u8>,
+    /// The text of each object, with line breaks replaced by spaces; for
+    /// stream objects, the dictionary and the byte range of the data.
+    objects: HashMap<u32, (String, Option<(usize, usize)>)>,
+}
+
+impl Pdf {
+    pub fn new(bytes: &[u8]) -> Pdf {
+        let text: String = bytes
+            .iter()
+            .map(|&b| if b < 0x80 { b as char } else { '.' })
+            .collect();
+        let mut objects = HashMap::new();
+        let mut pos = 0;
+
+        while let Some(at) = text[pos..].find(" 0 obj") {
+            let head = pos + at;
+            let start = head + 6;
+            let num_start = text[..head]
+                .rfind(|c: char| !c.is_ascii_digit())
+                .map_or(0, |i| i + 1);
+            let mut end = start + text[start..].find("endobj").unwrap();
+
+            let (obj, data) = match text[start..end].find("stream\n") {
+                Some(s) => {
+                    let data_start = start + s + 7;
+                    let mut data_end = data_start + text[data_start..].find("endstream").unwrap();
+                    if text[..data_end].ends_with('\n') {
+                        data_end -= 1;
+                    }
+                    end = data_end + text[data_end..].find("endobj").unwrap();
+                    (&text[start..start + s], Some((data_start, data_end)))
+                }
+                None => (&text[start..end], None),
+            };
+            if let Ok(num) = text[num_start..head].parse() {
+                objects.insert(num, (obj.replace('\n', " "), data));
+            }
+            pos = end + 6;
+        }
+
+        let mut pdf = Pdf {
+            text,
+            bytes: bytes.to_vec(),
+            objects,
+        };
+        // The object streams of an encrypted file are encrypted too.
+        if raw_entry(&pdf.trailer(), "Encrypt").is_none() {
+            pdf.read_object_streams();
+        }
+        pdf
+    }
+
+    /// Add the objects of the uncompressed object streams.
+    fn read_object_streams(&mut self) {
+        let mut found = Vec::new();
+
+        for (dict, data) in self.objects.values() {
+            if raw_entry(dict, "Type") != Some("/ObjStm") || raw_entry(dict, "Filter").is_some() {
+                continue;
+            }
+            let (start, end) = data.unwrap();
+            let first: usize = raw_entry(dict, "First").unwrap().parse().unwrap();
+            let body = &self.text[start..end];
+            let header: Vec<usize> = body[..first]
+                .split_whitespace()
+                .map(|n| n.parse().unwrap())
+                .collect();
+            for (i, pair) in header.chunks(2).enumerate() {
+                let obj_end = header.get(2 * i + 3).map_or(body.len(), |&o| first + o);
+                let obj = body[first + pair[1]..obj_end].trim().replace('\n', " ");
+                found.push((pair[0] as u32, obj));
+            }
+        }
+
+        for (num, obj) in found {
+            self.objects.insert(num, (obj, None));
+        }
+    }
+
+    /// The objects, by number. Stream objects are given by their
+    /// dictionaries.
+    pub fn objects(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.objects
+            .iter()
+            .map(|(&num, (obj, _))| (num, obj.as_str()))
+    }
+
+    pub fn object(&self, num: u32) -> Option<&str> {
+        self.objects.get(&num).map(|(obj, _)| obj.as_str())
+    }
+
+    /// The data of a stream object, as it is in the file.
+    pub fn stream_data(&self, num: u32) -> Option<&[u8]> {
+        let (start, end) = self.objects.get(&num)?.1?;
+        Some(&self.bytes[start..end])
+    }
+
+    /// The value of an entry in a dictionary, with an indirect reference
+    /// replaced by the object it refers to.
+    pub fn entry(&self, dict: &str, key: &str) -> Option<String> {
+        let value = raw_entry(dict, key)?;
+        match reference(value) {
+            Some(num) => self.object(num).map(str::to_owned),
+            None => Some(value.to_owned()),
+        }
+    }
+
+    /// The trailer dictionary, which is the dictionary of the cross-reference
+    /// stream from PDF 1.5 on.
+    pub fn trailer(&self) -> String {
+        match self.text.rfind("trailer") {
+            Some(at) => self.text[at + 7..].replace('\n', " "),
+            None => self
+                .objects()
+                .find(|(_, obj)| raw_entry(obj, "Type") == Some("/XRef"))
+                .expect("no trailer")
+                .1
+                .to_owned(),
+        }
+    }
+
+    /// The version in the header, such as "1.5".
+    pub fn version(&self) -> &str {
+        assert!(self.text.starts_with("%PDF-"));
+        self.text[5..].split_whitespace().next().unwrap()
+    }
+}
+
+/// The text of the value of an entry in a dictionary. Nested dictionaries
+/// are searched too, so this is only meant for keys that occur once.
+pub fn raw_entry<'a>(dict: &'a str, key: &str) -> Option<&'a str> {
+    let key = format!("/{}", key);
+    let at = dict
+        .match_indices(&key)
+        .map(|(i, _)| i + key.len())
+        .find(|&i| !dict[i..].starts_with(|c: char| c.is_ascii_alphanumeric()))?;
+    let rest = dict[at..].trim_start();
+    let is_delim = |c: char| c.is_whitespace() || "/[]<>()".contains(c);
+
+    let len = if rest.starts_with('/') {
+        1 + rest[1..].find(is_delim).unwrap_or(rest.len() - 1)
+    } else if rest.starts_with('[') || rest.starts_with("<<") || rest.starts_with('(') {
+        let (open, close) = match &rest[..1] {
+            "[" => ("[", "]"),
+            "(" => ("(", ")"),
+            _ => ("<<", ">>"),
+        };
+        let mut depth = 0;
+        let mut i = 0;
+        loop {
+            if rest[i..].starts_with('\\') {
+                i += 2;
+            } else if rest[i..].starts_with(open) {
+                depth += 1;
+                i += open.len();
+            } else if rest[i..].starts_with(close) {
+                depth -= 1;
+                i += close.len();
+                if depth == 0 {
+                    break i;
+                }
+            } else {
+                i += 1;
+            }
+        }
+    } else if rest.starts_with('<') {
+        rest.find('>').unwrap() + 1
+    } else {
+        let number = rest.find(is_delim).unwrap_or(rest.len());
+        if rest[number..].trim_start().starts_with("0 R") {
+            number + rest[number..].find('R').unwrap() + 1
+        } else {
+            number
+        }
+    };
+
+    Some(&rest[..len])
+}
+
+/// The number of the object an indirect reference refers to.
+pub fn reference(value: &str) -> Option<u32> {
+    if value.ends_with(" 0 R") {
+        value.split(' ').next()?.parse().ok()
+    } else {
+        None
+    }
+}
+
+/// The bytes of a literal or hexadecimal string.
+pub fn string(value: &str) -> Vec<u8> {
+    if value.starts_with('<') {
+        let digits: Vec<u8> = value
+            .trim_matches(|c| c == '<' || c == '>')
+            .bytes()
+            .filter(|b| b.is_ascii_hexdigit())
+            .collect();
+        return digits
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect();
+    }
+
+    let mut bytes = Vec::new();
+    let mut chars = value[1..value.len() - 1].bytes();
+    while let Some(b) = chars.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match chars.next().unwrap() {
+            b'n' => bytes.push(b'\n'),
+            b'r' => bytes.push(b'\r'),
+            b't' => bytes.push(b'\t'),
+            b'b' => bytes.push(8),
+            b'f' => bytes.push(12),
+            d @ b'0'..=b'7' => {
+                // dvipdfmx always writes three octal digits.
+                let octal = [d, chars.next().unwrap(), chars.next().unwrap()];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&octal).unwrap(), 8).unwrap());
+            }
+            other => bytes.push(other),
+        }
+    }
+    bytes
+}
+
+/// The numbers in an array such as `[0 0 612 792]`.
+pub fn numbers(value: &str) -> Vec<f64> {
+    value
+        .trim_matches(|c| c == '[' || c == ']')
+        .split_whitespace()
+        .map(|n| n.parse().unwrap())
+        .collect()
+}
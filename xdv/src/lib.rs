@@ -490,6 +490,12 @@ impl<T: XdvEvents> XdvParser<T> {
             None
         };
 
+        if flags & NativeFontFlags::Variations as u16 != 0 {
+            let num_axes = cursor.get_u16()? as usize;
+            let _axis_tags = cursor.get_slice(4 * num_axes)?;
+            let _axis_values = cursor.get_slice(4 * num_axes)?; // fixed-point
+        }
+
         let _extend = if flags & NativeFontFlags::Extend as u16 != 0 {
             Some(cursor.get_u32()?) // fixed-point
         } else {
@@ -1147,6 +1153,7 @@ enum IdByte {
 enum NativeFontFlags {
     Vertical = 0x0100,
     Colored = 0x0200,
+    Variations = 0x0800,
     Extend = 0x1000,
     Slant = 0x2000,
     Embolden = 0x4000,